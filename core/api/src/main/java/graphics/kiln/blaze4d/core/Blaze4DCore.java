@@ -6,10 +6,15 @@ import graphics.kiln.blaze4d.core.types.B4DImageData;
 import graphics.kiln.blaze4d.core.types.B4DMeshData;
 import graphics.kiln.blaze4d.core.types.B4DVertexFormat;
 import jdk.incubator.foreign.MemoryAddress;
+import jdk.incubator.foreign.MemorySegment;
+import jdk.incubator.foreign.ResourceScope;
 import org.apache.logging.log4j.LogManager;
 import org.apache.logging.log4j.Logger;
 import org.apache.logging.log4j.message.StringFormatterMessageFactory;
 
+import static jdk.incubator.foreign.ValueLayout.ADDRESS;
+import static jdk.incubator.foreign.ValueLayout.JAVA_FLOAT;
+
 public class Blaze4DCore implements AutoCloseable {
     public static final Logger LOGGER = LogManager.getLogger("Blaze4DCore", new StringFormatterMessageFactory());
 
@@ -17,15 +22,64 @@ public class Blaze4DCore implements AutoCloseable {
 
     public Blaze4DCore(long glfwWindow) {
         boolean enableValidation = System.getProperty("b4d.enable_validation") != null;
+        boolean enableShaderPrintf = System.getProperty("b4d.enable_shader_printf") != null;
 
         MemoryAddress surfaceProvider = Natives.b4dCreateGlfwSurfaceProvider(glfwWindow);
-        this.handle = Natives.b4dInit(surfaceProvider, enableValidation);
+        this.handle = Natives.b4dInit(surfaceProvider, enableValidation, enableShaderPrintf);
     }
 
     public void setDebugMode(DebugMode mode) {
         Natives.b4dSetDebugMode(this.handle, mode.raw);
     }
 
+    /**
+     * Triggers a RenderDoc capture of the next nFrames frames. Only works if the natives were built
+     * with RenderDoc support and RenderDoc is attached.
+     */
+    public void triggerCapture(int nFrames) {
+        Natives.b4dTriggerCapture(this.handle, nFrames);
+    }
+
+    /**
+     * Enables or disables the debug overlay showing frame times, draw counts, memory usage and
+     * upload throughput.
+     */
+    public void setDebugOverlay(boolean enable) {
+        Natives.b4dSetDebugOverlay(this.handle, enable);
+    }
+
+    /**
+     * Enables or disables reversed depth. Projection matrices are still passed in the OpenGL
+     * convention and converted by the natives. Reduces z-fighting at long render distances.
+     */
+    public void setReverseZ(boolean enable) {
+        Natives.b4dSetReverseZ(this.handle, enable);
+    }
+
+    /**
+     * Enables or disables rendering object ids so that frames can be picked using {@link Frame#pick(int, int)}.
+     */
+    public void setObjectIds(boolean enable) {
+        Natives.b4dSetObjectIds(this.handle, enable);
+    }
+
+    /**
+     * Configures the multisample count of the render targets. Must be 1, 2, 4 or 8. The render targets are rebuilt
+     * before the next frame and keep the sample count when the window is resized.
+     */
+    public void setMsaa(int samples) {
+        Natives.b4dSetMsaa(this.handle, samples);
+    }
+
+    /**
+     * Runs the callback once the gpu has finished executing the frame with the specified {@link Frame#getId()}. Can be
+     * used to recycle per frame resources like buffers the frame data has been read from. The callback is run on the
+     * native worker thread, or immediately if the frame has already completed, and must not block.
+     */
+    public void onFrameComplete(long frameId, Runnable callback) {
+        Natives.b4dOnFrameComplete(this.handle, frameId, callback);
+    }
+
     public long createShader(B4DVertexFormat vertexFormat, long usedUniforms) {
         return Natives.b4dCreateShader(this.handle, vertexFormat.getAddress(), usedUniforms);
     }
@@ -38,10 +92,44 @@ public class Blaze4DCore implements AutoCloseable {
         return new GlobalMesh(Natives.b4dCreateGlobalMesh(this.handle, meshData.getAddress()));
     }
 
+    /**
+     * Creates a lod mesh from global meshes with decreasing detail. Each mesh is used up to the max distance at the
+     * same index. The lod mesh keeps its own references to the meshes so they may be closed afterwards.
+     */
+    public LodMesh createLodMesh(GlobalMesh[] meshes, float[] maxDistances) {
+        if (meshes.length != maxDistances.length) {
+            throw new IllegalArgumentException("Lod mesh requires one max distance per mesh");
+        }
+
+        try (ResourceScope scope = ResourceScope.newConfinedScope()) {
+            MemorySegment meshesSegment = MemorySegment.allocateNative(ADDRESS.byteSize() * meshes.length, scope);
+            MemorySegment distancesSegment = MemorySegment.allocateNative(JAVA_FLOAT.byteSize() * maxDistances.length, scope);
+            for (int i = 0; i < meshes.length; i++) {
+                meshesSegment.setAtIndex(ADDRESS, i, meshes[i].getHandle());
+                distancesSegment.setAtIndex(JAVA_FLOAT, i, maxDistances[i]);
+            }
+
+            return new LodMesh(Natives.b4dCreateLodMesh(meshesSegment.address(), distancesSegment.address(), meshes.length));
+        }
+    }
+
     public GlobalImage createGlobalImage(int width, int height, B4DFormat format) {
         return new GlobalImage(Natives.b4dCreateGlobalImage(this.handle, width, height, format.getValue()));
     }
 
+    /**
+     * Creates a global image from the contents of a KTX2 container including all mip levels. Returns null if the
+     * container is invalid or uses a format which cannot be loaded, for example BasisU payloads.
+     */
+    public GlobalImage createGlobalImageKtx2(MemorySegment data) {
+        MemoryAddress image = Natives.b4dCreateGlobalImageKtx2(this.handle, data.address(), data.byteSize());
+        if(image.toRawLongValue() == 0L) {
+            return null;
+        } else {
+            return new GlobalImage(image);
+        }
+    }
+
     public Frame startFrame(int windowWidth, int windowHeight) {
         MemoryAddress frame = Natives.b4dStartFrame(this.handle, windowWidth, windowHeight);
         if(frame.toRawLongValue() == 0L) {
@@ -13,6 +13,13 @@ public class Frame implements AutoCloseable {
         this.handle = handle;
     }
 
+    /**
+     * Returns the id of this frame which can be passed to {@link Blaze4DCore#onFrameComplete(long, Runnable)}.
+     */
+    public long getId() {
+        return Natives.b4dPassGetId(this.handle);
+    }
+
     public void updateUniform(long shaderId, B4DUniformData data) {
         Natives.b4dPassUpdateUniform(this.handle, data.getAddress(), shaderId);
     }
@@ -21,6 +28,13 @@ public class Frame implements AutoCloseable {
         Natives.b4dPassDrawGlobal(this.handle, mesh.getHandle(), shaderId, depthWrite);
     }
 
+    /**
+     * Draws the level of the lod mesh used at the specified distance from the camera.
+     */
+    public void drawGlobalLod(LodMesh mesh, float distance, long shaderId, boolean depthWrite) {
+        Natives.b4dPassDrawGlobalLod(this.handle, mesh.getHandle(), distance, shaderId, depthWrite);
+    }
+
     public int uploadImmediate(B4DMeshData data) {
         return Natives.b4dPassUploadImmediate(this.handle, data.getAddress());
     }
@@ -29,6 +43,60 @@ public class Frame implements AutoCloseable {
         Natives.b4dPassDrawImmediate(this.handle, meshId, shaderId, depthWrite);
     }
 
+    /**
+     * Submits all commands recorded so far so that the gpu can start executing them while the rest of the frame is
+     * still being recorded. Does not change the result of the frame.
+     */
+    public void flush() {
+        Natives.b4dPassFlush(this.handle);
+    }
+
+    /**
+     * Sets the object id written by all following draws of this frame. Requires object ids to be enabled with
+     * {@link Blaze4DCore#setObjectIds(boolean)}.
+     */
+    public void setObjectId(int objectId) {
+        Natives.b4dPassSetObjectId(this.handle, objectId);
+    }
+
+    /**
+     * Sets the color the frame is cleared to and whether the depth buffer is cleared. Must be called before the first
+     * draw of the frame.
+     */
+    public void setClearConfig(float r, float g, float b, float a, boolean clearDepth) {
+        Natives.b4dPassSetClearConfig(this.handle, true, r, g, b, a, clearDepth);
+    }
+
+    /**
+     * Disables clearing the color attachment, for example if the sky covers the full frame. Must be called before the
+     * first draw of the frame.
+     */
+    public void setNoColorClear(boolean clearDepth) {
+        Natives.b4dPassSetClearConfig(this.handle, false, 0f, 0f, 0f, 0f, clearDepth);
+    }
+
+    /**
+     * Restricts all following draws of this frame to the specified framebuffer rectangle.
+     */
+    public void setScissor(int x, int y, int width, int height) {
+        Natives.b4dPassSetScissor(this.handle, true, x, y, width, height);
+    }
+
+    /**
+     * Disables the scissor so that following draws of this frame are no longer clipped.
+     */
+    public void clearScissor() {
+        Natives.b4dPassSetScissor(this.handle, false, 0, 0, 0, 0);
+    }
+
+    /**
+     * Requests the object id of the pixel at the specified framebuffer position. The result becomes available once
+     * the frame has been executed on the gpu.
+     */
+    public Pick pick(int x, int y) {
+        return new Pick(Natives.b4dPassPick(this.handle, x, y));
+    }
+
     @Override
     public void close() throws Exception {
         Natives.b4dEndFrame(this.handle);
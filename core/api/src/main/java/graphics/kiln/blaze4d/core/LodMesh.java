@@ -0,0 +1,22 @@
+package graphics.kiln.blaze4d.core;
+
+import graphics.kiln.blaze4d.core.natives.Natives;
+import jdk.incubator.foreign.MemoryAddress;
+
+public class LodMesh implements AutoCloseable {
+
+    private final MemoryAddress handle;
+
+    LodMesh(MemoryAddress handle) {
+        this.handle = handle;
+    }
+
+    MemoryAddress getHandle() {
+        return this.handle;
+    }
+
+    @Override
+    public void close() throws Exception {
+        Natives.b4dDestroyLodMesh(this.handle);
+    }
+}
@@ -0,0 +1,60 @@
+package graphics.kiln.blaze4d.core;
+
+import graphics.kiln.blaze4d.core.natives.Natives;
+import jdk.incubator.foreign.MemoryAddress;
+import jdk.incubator.foreign.MemorySegment;
+import jdk.incubator.foreign.ResourceScope;
+
+import java.util.OptionalInt;
+
+import static jdk.incubator.foreign.ValueLayout.JAVA_INT;
+
+/**
+ * A pending request for the object id of a pixel. See {@link Frame#pick(int, int)}.
+ */
+public class Pick implements AutoCloseable {
+
+    private final MemoryAddress handle;
+    private boolean done = false;
+    private OptionalInt objectId = OptionalInt.empty();
+
+    Pick(MemoryAddress handle) {
+        this.handle = handle;
+    }
+
+    /**
+     * Returns true if the pick has completed. After this returns true the result is available through
+     * {@link #getObjectId()}.
+     */
+    public boolean poll() {
+        if (this.done) {
+            return true;
+        }
+
+        try (ResourceScope scope = ResourceScope.newConfinedScope()) {
+            MemorySegment result = MemorySegment.allocateNative(JAVA_INT, scope);
+            int status = Natives.b4dPickPoll(this.handle, result.address());
+            if (status == 0) {
+                return false;
+            }
+
+            this.done = true;
+            if (status == 1) {
+                this.objectId = OptionalInt.of(result.get(JAVA_INT, 0));
+            }
+            return true;
+        }
+    }
+
+    /**
+     * Returns the picked object id. Empty if the pick has not completed yet or did not produce a result.
+     */
+    public OptionalInt getObjectId() {
+        return this.objectId;
+    }
+
+    @Override
+    public void close() throws Exception {
+        Natives.b4dDestroyPick(this.handle);
+    }
+}
@@ -0,0 +1,63 @@
+package graphics.kiln.blaze4d.core;
+
+import graphics.kiln.blaze4d.core.natives.Natives;
+import jdk.incubator.foreign.MemoryAddress;
+import jdk.incubator.foreign.MemorySegment;
+import jdk.incubator.foreign.ResourceScope;
+
+import static jdk.incubator.foreign.ValueLayout.JAVA_FLOAT;
+import static jdk.incubator.foreign.ValueLayout.JAVA_INT;
+
+/**
+ * The face connectivity of all loaded chunk sections used to cull sections hidden by caves and terrain.
+ * Sections are identified by their section coordinates.
+ */
+public class SectionVisibilityGraph implements AutoCloseable {
+
+    private final MemoryAddress handle;
+
+    public SectionVisibilityGraph() {
+        this.handle = Natives.b4dCreateVisibilityGraph();
+    }
+
+    /**
+     * Sets the face connectivity of a section. The connectivity uses the bit layout of vanilla's VisibilitySet
+     * where the connection from face a to face b is stored in bit {@code a + b * 6}.
+     */
+    public void setSection(int x, int y, int z, long connectivity) {
+        Natives.b4dVisibilityGraphSetSection(this.handle, x, y, z, connectivity);
+    }
+
+    public void removeSection(int x, int y, int z) {
+        Natives.b4dVisibilityGraphRemoveSection(this.handle, x, y, z);
+    }
+
+    /**
+     * Returns the sections visible from the camera ordered front to back as x, y, z triples.
+     *
+     * @param viewProjection The column major matrix transforming positions relative to the camera into clip space.
+     * @param maxDistance The horizontal render distance in sections.
+     */
+    public int[] traverse(float cameraX, float cameraY, float cameraZ, float[] viewProjection, int maxDistance) {
+        if (viewProjection.length != 16) {
+            throw new IllegalArgumentException("View projection matrix must have 16 elements");
+        }
+
+        int capacity = Natives.b4dVisibilityGraphGetSectionCount(this.handle);
+        try (ResourceScope scope = ResourceScope.newConfinedScope()) {
+            MemorySegment matrixSegment = MemorySegment.allocateNative(JAVA_FLOAT.byteSize() * 16, scope);
+            matrixSegment.copyFrom(MemorySegment.ofArray(viewProjection));
+            MemorySegment sectionsSegment = MemorySegment.allocateNative(JAVA_INT.byteSize() * 3 * Math.max(capacity, 1), scope);
+
+            int count = Natives.b4dVisibilityGraphTraverse(this.handle, cameraX, cameraY, cameraZ, matrixSegment.address(), maxDistance, sectionsSegment.address(), capacity);
+            count = Math.min(count, capacity);
+
+            return sectionsSegment.asSlice(0, JAVA_INT.byteSize() * 3 * count).toArray(JAVA_INT);
+        }
+    }
+
+    @Override
+    public void close() throws Exception {
+        Natives.b4dDestroyVisibilityGraph(this.handle);
+    }
+}
@@ -15,7 +15,10 @@ import java.lang.invoke.MethodHandle;
 import java.lang.invoke.MethodHandles;
 import java.lang.invoke.MethodType;
 import java.nio.charset.StandardCharsets;
+import java.util.Map;
 import java.util.Optional;
+import java.util.concurrent.ConcurrentHashMap;
+import java.util.concurrent.atomic.AtomicLong;
 
 import static jdk.incubator.foreign.ValueLayout.*;
 
@@ -30,20 +33,48 @@ public class Natives {
     public static final MethodHandle B4D_INIT_HANDLE;
     public static final MethodHandle B4D_DESTROY_HANDLE;
     public static final MethodHandle B4D_SET_DEBUG_MODE_HANDLE;
+    public static final MethodHandle B4D_TRIGGER_CAPTURE_HANDLE;
+    public static final MethodHandle B4D_SET_DEBUG_OVERLAY_HANDLE;
+    public static final MethodHandle B4D_SET_REVERSE_Z_HANDLE;
+    public static final MethodHandle B4D_SET_OBJECT_IDS_HANDLE;
+    public static final MethodHandle B4D_SET_MSAA_HANDLE;
     public static final MethodHandle B4D_CREATE_GLOBAL_MESH_HANDLE;
     public static final MethodHandle B4D_DESTROY_GLOBAL_MESH_HANDLE;
+    public static final MethodHandle B4D_CREATE_LOD_MESH_HANDLE;
+    public static final MethodHandle B4D_DESTROY_LOD_MESH_HANDLE;
+    public static final MethodHandle B4D_CREATE_VISIBILITY_GRAPH_HANDLE;
+    public static final MethodHandle B4D_DESTROY_VISIBILITY_GRAPH_HANDLE;
+    public static final MethodHandle B4D_VISIBILITY_GRAPH_SET_SECTION_HANDLE;
+    public static final MethodHandle B4D_VISIBILITY_GRAPH_REMOVE_SECTION_HANDLE;
+    public static final MethodHandle B4D_VISIBILITY_GRAPH_GET_SECTION_COUNT_HANDLE;
+    public static final MethodHandle B4D_VISIBILITY_GRAPH_TRAVERSE_HANDLE;
     public static final MethodHandle B4D_CREATE_GLOBAL_IMAGE_HANDLE;
+    public static final MethodHandle B4D_CREATE_GLOBAL_IMAGE_KTX2_HANDLE;
     public static final MethodHandle B4D_UPDATE_GLOBAL_IMAGE_HANDLE;
     public static final MethodHandle B4D_DESTROY_GLOBAL_IMAGE_HANDLE;
     public static final MethodHandle B4D_CREATE_SHADER_HANDLE;
     public static final MethodHandle B4D_DESTROY_SHADER_HANDLE;
     public static final MethodHandle B4D_START_FRAME_HANDLE;
+    public static final MethodHandle B4D_PASS_GET_ID_HANDLE;
+    public static final MethodHandle B4D_ON_FRAME_COMPLETE_HANDLE;
     public static final MethodHandle B4D_PASS_UPDATE_UNIFORM_HANDLE;
     public static final MethodHandle B4D_PASS_DRAW_GLOBAL_HANDLE;
+    public static final MethodHandle B4D_PASS_DRAW_GLOBAL_LOD_HANDLE;
     public static final MethodHandle B4D_PASS_UPLOAD_IMMEDIATE_HANDLE;
     public static final MethodHandle B4D_PASS_DRAW_IMMEDIATE_HANDLE;
+    public static final MethodHandle B4D_PASS_FLUSH_HANDLE;
+    public static final MethodHandle B4D_PASS_SET_OBJECT_ID_HANDLE;
+    public static final MethodHandle B4D_PASS_SET_CLEAR_CONFIG_HANDLE;
+    public static final MethodHandle B4D_PASS_SET_SCISSOR_HANDLE;
+    public static final MethodHandle B4D_PASS_PICK_HANDLE;
+    public static final MethodHandle B4D_PICK_POLL_HANDLE;
+    public static final MethodHandle B4D_DESTROY_PICK_HANDLE;
     public static final MethodHandle B4D_END_FRAME_HANDLE;
 
+    private static final NativeSymbol frameCompleteHandler;
+    private static final Map<Long, Runnable> frameCompleteCallbacks = new ConcurrentHashMap<>();
+    private static final AtomicLong nextFrameCompleteCallback = new AtomicLong();
+
     static {
         Lib.loadNatives();
 
@@ -51,13 +82,14 @@ public class Natives {
         nativeMetadata = loadMetadata();
         initNativeLogger();
         preInitGlfw();
+        frameCompleteHandler = createFrameCompleteHandler();
 
         B4D_CREATE_GLFW_SURFACE_PROVIDER_HANDLE = lookupFunction("b4d_create_glfw_surface_provider",
                 FunctionDescriptor.of(ADDRESS, ADDRESS, ADDRESS, ADDRESS)
         );
 
         B4D_INIT_HANDLE = lookupFunction("b4d_init",
-                FunctionDescriptor.of(ADDRESS, ADDRESS, JAVA_INT)
+                FunctionDescriptor.of(ADDRESS, ADDRESS, JAVA_INT, JAVA_INT)
         );
 
         B4D_DESTROY_HANDLE = lookupFunction("b4d_destroy",
@@ -68,6 +100,26 @@ public class Natives {
                 FunctionDescriptor.ofVoid(ADDRESS, JAVA_INT)
         );
 
+        B4D_TRIGGER_CAPTURE_HANDLE = lookupFunction("b4d_trigger_capture",
+                FunctionDescriptor.ofVoid(ADDRESS, JAVA_INT)
+        );
+
+        B4D_SET_DEBUG_OVERLAY_HANDLE = lookupFunction("b4d_set_debug_overlay",
+                FunctionDescriptor.ofVoid(ADDRESS, JAVA_INT)
+        );
+
+        B4D_SET_REVERSE_Z_HANDLE = lookupFunction("b4d_set_reverse_z",
+                FunctionDescriptor.ofVoid(ADDRESS, JAVA_INT)
+        );
+
+        B4D_SET_OBJECT_IDS_HANDLE = lookupFunction("b4d_set_object_ids",
+                FunctionDescriptor.ofVoid(ADDRESS, JAVA_INT)
+        );
+
+        B4D_SET_MSAA_HANDLE = lookupFunction("b4d_set_msaa",
+                FunctionDescriptor.ofVoid(ADDRESS, JAVA_INT)
+        );
+
         B4D_CREATE_GLOBAL_MESH_HANDLE = lookupFunction("b4d_create_global_mesh",
                 FunctionDescriptor.of(ADDRESS, ADDRESS, ADDRESS)
         );
@@ -76,10 +128,46 @@ public class Natives {
                 FunctionDescriptor.ofVoid(ADDRESS)
         );
 
+        B4D_CREATE_LOD_MESH_HANDLE = lookupFunction("b4d_create_lod_mesh",
+                FunctionDescriptor.of(ADDRESS, ADDRESS, ADDRESS, JAVA_INT)
+        );
+
+        B4D_DESTROY_LOD_MESH_HANDLE = lookupFunction("b4d_destroy_lod_mesh",
+                FunctionDescriptor.ofVoid(ADDRESS)
+        );
+
+        B4D_CREATE_VISIBILITY_GRAPH_HANDLE = lookupFunction("b4d_create_visibility_graph",
+                FunctionDescriptor.of(ADDRESS)
+        );
+
+        B4D_DESTROY_VISIBILITY_GRAPH_HANDLE = lookupFunction("b4d_destroy_visibility_graph",
+                FunctionDescriptor.ofVoid(ADDRESS)
+        );
+
+        B4D_VISIBILITY_GRAPH_SET_SECTION_HANDLE = lookupFunction("b4d_visibility_graph_set_section",
+                FunctionDescriptor.ofVoid(ADDRESS, JAVA_INT, JAVA_INT, JAVA_INT, JAVA_LONG)
+        );
+
+        B4D_VISIBILITY_GRAPH_REMOVE_SECTION_HANDLE = lookupFunction("b4d_visibility_graph_remove_section",
+                FunctionDescriptor.ofVoid(ADDRESS, JAVA_INT, JAVA_INT, JAVA_INT)
+        );
+
+        B4D_VISIBILITY_GRAPH_GET_SECTION_COUNT_HANDLE = lookupFunction("b4d_visibility_graph_get_section_count",
+                FunctionDescriptor.of(JAVA_INT, ADDRESS)
+        );
+
+        B4D_VISIBILITY_GRAPH_TRAVERSE_HANDLE = lookupFunction("b4d_visibility_graph_traverse",
+                FunctionDescriptor.of(JAVA_INT, ADDRESS, JAVA_FLOAT, JAVA_FLOAT, JAVA_FLOAT, ADDRESS, JAVA_INT, ADDRESS, JAVA_INT)
+        );
+
         B4D_CREATE_GLOBAL_IMAGE_HANDLE = lookupFunction("b4d_create_global_image",
                 FunctionDescriptor.of(ADDRESS, JAVA_INT, JAVA_INT, JAVA_INT)
         );
 
+        B4D_CREATE_GLOBAL_IMAGE_KTX2_HANDLE = lookupFunction("b4d_create_global_image_ktx2",
+                FunctionDescriptor.of(ADDRESS, ADDRESS, ADDRESS, JAVA_LONG)
+        );
+
         B4D_UPDATE_GLOBAL_IMAGE_HANDLE = lookupFunction("b4d_update_global_image",
                 FunctionDescriptor.ofVoid(ADDRESS, ADDRESS, JAVA_INT)
         );
@@ -100,6 +188,14 @@ public class Natives {
                 FunctionDescriptor.of(ADDRESS, ADDRESS, JAVA_INT, JAVA_INT)
         );
 
+        B4D_PASS_GET_ID_HANDLE = lookupFunction("b4d_pass_get_id",
+                FunctionDescriptor.of(JAVA_LONG, ADDRESS)
+        );
+
+        B4D_ON_FRAME_COMPLETE_HANDLE = lookupFunction("b4d_on_frame_complete",
+                FunctionDescriptor.ofVoid(ADDRESS, JAVA_LONG, ADDRESS, JAVA_LONG)
+        );
+
         B4D_PASS_UPDATE_UNIFORM_HANDLE = lookupFunction("b4d_pass_update_uniform",
                 FunctionDescriptor.ofVoid(ADDRESS, ADDRESS, JAVA_LONG)
         );
@@ -108,6 +204,10 @@ public class Natives {
                 FunctionDescriptor.ofVoid(ADDRESS, ADDRESS, JAVA_LONG, JAVA_INT)
         );
 
+        B4D_PASS_DRAW_GLOBAL_LOD_HANDLE = lookupFunction("b4d_pass_draw_global_lod",
+                FunctionDescriptor.ofVoid(ADDRESS, ADDRESS, JAVA_FLOAT, JAVA_LONG, JAVA_INT)
+        );
+
         B4D_PASS_UPLOAD_IMMEDIATE_HANDLE = lookupFunction("b4d_pass_upload_immediate",
                 FunctionDescriptor.of(JAVA_INT, ADDRESS, ADDRESS)
         );
@@ -116,6 +216,34 @@ public class Natives {
                 FunctionDescriptor.ofVoid(ADDRESS, JAVA_INT, JAVA_LONG, JAVA_INT)
         );
 
+        B4D_PASS_FLUSH_HANDLE = lookupFunction("b4d_pass_flush",
+                FunctionDescriptor.ofVoid(ADDRESS)
+        );
+
+        B4D_PASS_SET_OBJECT_ID_HANDLE = lookupFunction("b4d_pass_set_object_id",
+                FunctionDescriptor.ofVoid(ADDRESS, JAVA_INT)
+        );
+
+        B4D_PASS_SET_CLEAR_CONFIG_HANDLE = lookupFunction("b4d_pass_set_clear_config",
+                FunctionDescriptor.ofVoid(ADDRESS, JAVA_INT, JAVA_FLOAT, JAVA_FLOAT, JAVA_FLOAT, JAVA_FLOAT, JAVA_INT)
+        );
+
+        B4D_PASS_SET_SCISSOR_HANDLE = lookupFunction("b4d_pass_set_scissor",
+                FunctionDescriptor.ofVoid(ADDRESS, JAVA_INT, JAVA_INT, JAVA_INT, JAVA_INT, JAVA_INT)
+        );
+
+        B4D_PASS_PICK_HANDLE = lookupFunction("b4d_pass_pick",
+                FunctionDescriptor.of(ADDRESS, ADDRESS, JAVA_INT, JAVA_INT)
+        );
+
+        B4D_PICK_POLL_HANDLE = lookupFunction("b4d_pick_poll",
+                FunctionDescriptor.of(JAVA_INT, ADDRESS, ADDRESS)
+        );
+
+        B4D_DESTROY_PICK_HANDLE = lookupFunction("b4d_destroy_pick",
+                FunctionDescriptor.ofVoid(ADDRESS)
+        );
+
         B4D_END_FRAME_HANDLE = lookupFunction("b4d_end_frame",
                 FunctionDescriptor.ofVoid(ADDRESS)
         );
@@ -131,10 +259,11 @@ public class Natives {
         }
     }
 
-    public static MemoryAddress b4dInit(MemoryAddress surface, boolean enableValidation) {
+    public static MemoryAddress b4dInit(MemoryAddress surface, boolean enableValidation, boolean enableShaderPrintf) {
         int enableValidationInt = enableValidation ? 1 : 0;
+        int enableShaderPrintfInt = enableShaderPrintf ? 1 : 0;
         try {
-            return (MemoryAddress) B4D_INIT_HANDLE.invoke(surface, enableValidationInt);
+            return (MemoryAddress) B4D_INIT_HANDLE.invoke(surface, enableValidationInt, enableShaderPrintfInt);
         } catch (Throwable e) {
             throw new RuntimeException("Failed to invoke b4d_init", e);
         }
@@ -156,6 +285,49 @@ public class Natives {
         }
     }
 
+    public static void b4dTriggerCapture(MemoryAddress b4d, int nFrames) {
+        try {
+            B4D_TRIGGER_CAPTURE_HANDLE.invoke(b4d, nFrames);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_trigger_capture", e);
+        }
+    }
+
+    public static void b4dSetDebugOverlay(MemoryAddress b4d, boolean enable) {
+        int enableInt = enable ? 1 : 0;
+        try {
+            B4D_SET_DEBUG_OVERLAY_HANDLE.invoke(b4d, enableInt);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_set_debug_overlay", e);
+        }
+    }
+
+    public static void b4dSetReverseZ(MemoryAddress b4d, boolean enable) {
+        int enableInt = enable ? 1 : 0;
+        try {
+            B4D_SET_REVERSE_Z_HANDLE.invoke(b4d, enableInt);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_set_reverse_z", e);
+        }
+    }
+
+    public static void b4dSetObjectIds(MemoryAddress b4d, boolean enable) {
+        int enableInt = enable ? 1 : 0;
+        try {
+            B4D_SET_OBJECT_IDS_HANDLE.invoke(b4d, enableInt);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_set_object_ids", e);
+        }
+    }
+
+    public static void b4dSetMsaa(MemoryAddress b4d, int samples) {
+        try {
+            B4D_SET_MSAA_HANDLE.invoke(b4d, samples);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_set_msaa", e);
+        }
+    }
+
     public static MemoryAddress b4dCreateGlobalMesh(MemoryAddress b4d, MemoryAddress meshData) {
         try {
             return (MemoryAddress) B4D_CREATE_GLOBAL_MESH_HANDLE.invoke(b4d, meshData);
@@ -172,6 +344,70 @@ public class Natives {
         }
     }
 
+    public static MemoryAddress b4dCreateLodMesh(MemoryAddress meshes, MemoryAddress maxDistances, int count) {
+        try {
+            return (MemoryAddress) B4D_CREATE_LOD_MESH_HANDLE.invoke(meshes, maxDistances, count);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_create_lod_mesh", e);
+        }
+    }
+
+    public static void b4dDestroyLodMesh(MemoryAddress mesh) {
+        try {
+            B4D_DESTROY_LOD_MESH_HANDLE.invoke(mesh);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_destroy_lod_mesh", e);
+        }
+    }
+
+    public static MemoryAddress b4dCreateVisibilityGraph() {
+        try {
+            return (MemoryAddress) B4D_CREATE_VISIBILITY_GRAPH_HANDLE.invoke();
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_create_visibility_graph", e);
+        }
+    }
+
+    public static void b4dDestroyVisibilityGraph(MemoryAddress graph) {
+        try {
+            B4D_DESTROY_VISIBILITY_GRAPH_HANDLE.invoke(graph);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_destroy_visibility_graph", e);
+        }
+    }
+
+    public static void b4dVisibilityGraphSetSection(MemoryAddress graph, int x, int y, int z, long connectivity) {
+        try {
+            B4D_VISIBILITY_GRAPH_SET_SECTION_HANDLE.invoke(graph, x, y, z, connectivity);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_visibility_graph_set_section", e);
+        }
+    }
+
+    public static void b4dVisibilityGraphRemoveSection(MemoryAddress graph, int x, int y, int z) {
+        try {
+            B4D_VISIBILITY_GRAPH_REMOVE_SECTION_HANDLE.invoke(graph, x, y, z);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_visibility_graph_remove_section", e);
+        }
+    }
+
+    public static int b4dVisibilityGraphGetSectionCount(MemoryAddress graph) {
+        try {
+            return (int) B4D_VISIBILITY_GRAPH_GET_SECTION_COUNT_HANDLE.invoke(graph);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_visibility_graph_get_section_count", e);
+        }
+    }
+
+    public static int b4dVisibilityGraphTraverse(MemoryAddress graph, float cameraX, float cameraY, float cameraZ, MemoryAddress viewProjection, int maxDistance, MemoryAddress sections, int capacity) {
+        try {
+            return (int) B4D_VISIBILITY_GRAPH_TRAVERSE_HANDLE.invoke(graph, cameraX, cameraY, cameraZ, viewProjection, maxDistance, sections, capacity);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_visibility_graph_traverse", e);
+        }
+    }
+
     public static MemoryAddress b4dCreateGlobalImage(MemoryAddress b4d, int width, int height, int format) {
         try {
             return (MemoryAddress) B4D_CREATE_GLOBAL_IMAGE_HANDLE.invoke(b4d, width, height, format);
@@ -180,6 +416,14 @@ public class Natives {
         }
     }
 
+    public static MemoryAddress b4dCreateGlobalImageKtx2(MemoryAddress b4d, MemoryAddress data, long dataLen) {
+        try {
+            return (MemoryAddress) B4D_CREATE_GLOBAL_IMAGE_KTX2_HANDLE.invoke(b4d, data, dataLen);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_create_global_image_ktx2", e);
+        }
+    }
+
     public static void b4DUpdateGlobalImage(MemoryAddress image, MemoryAddress data, int dataCount) {
         try {
             B4D_UPDATE_GLOBAL_IMAGE_HANDLE.invoke(image, data, dataCount);
@@ -220,6 +464,28 @@ public class Natives {
         }
     }
 
+    public static long b4dPassGetId(MemoryAddress frame) {
+        try {
+            return (long) B4D_PASS_GET_ID_HANDLE.invoke(frame);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_pass_get_id", e);
+        }
+    }
+
+    /**
+     * The callback is run on the native worker thread once the frame has completed execution on the gpu.
+     */
+    public static void b4dOnFrameComplete(MemoryAddress b4d, long frameId, Runnable callback) {
+        long userData = nextFrameCompleteCallback.getAndIncrement();
+        frameCompleteCallbacks.put(userData, callback);
+        try {
+            B4D_ON_FRAME_COMPLETE_HANDLE.invoke(b4d, frameId, frameCompleteHandler, userData);
+        } catch (Throwable e) {
+            frameCompleteCallbacks.remove(userData);
+            throw new RuntimeException("Failed to invoke b4d_on_frame_complete", e);
+        }
+    }
+
     public static void b4dPassUpdateUniform(MemoryAddress frame, MemoryAddress data, long shaderId) {
         try {
             B4D_PASS_UPDATE_UNIFORM_HANDLE.invoke(frame, data, shaderId);
@@ -242,6 +508,15 @@ public class Natives {
         }
     }
 
+    public static void b4dPassDrawGlobalLod(MemoryAddress frame, MemoryAddress mesh, float distance, long shaderId, boolean depthWrite) {
+        int depthWriteInt = depthWrite ? 1 : 0;
+        try {
+            B4D_PASS_DRAW_GLOBAL_LOD_HANDLE.invoke(frame, mesh, distance, shaderId, depthWriteInt);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_pass_draw_global_lod", e);
+        }
+    }
+
     public static int b4dPassUploadImmediate(MemoryAddress frame, MemoryAddress data) {
         try {
             return (int) B4D_PASS_UPLOAD_IMMEDIATE_HANDLE.invoke(frame, data);
@@ -264,6 +539,65 @@ public class Natives {
         }
     }
 
+    public static void b4dPassFlush(MemoryAddress frame) {
+        try {
+            B4D_PASS_FLUSH_HANDLE.invoke(frame);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_pass_flush", e);
+        }
+    }
+
+    public static void b4dPassSetObjectId(MemoryAddress frame, int objectId) {
+        try {
+            B4D_PASS_SET_OBJECT_ID_HANDLE.invoke(frame, objectId);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_pass_set_object_id", e);
+        }
+    }
+
+    public static void b4dPassSetClearConfig(MemoryAddress frame, boolean clearColor, float r, float g, float b, float a, boolean clearDepth) {
+        int clearColorInt = clearColor ? 1 : 0;
+        int clearDepthInt = clearDepth ? 1 : 0;
+        try {
+            B4D_PASS_SET_CLEAR_CONFIG_HANDLE.invoke(frame, clearColorInt, r, g, b, a, clearDepthInt);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_pass_set_clear_config", e);
+        }
+    }
+
+    public static void b4dPassSetScissor(MemoryAddress frame, boolean enable, int x, int y, int width, int height) {
+        int enableInt = enable ? 1 : 0;
+        try {
+            B4D_PASS_SET_SCISSOR_HANDLE.invoke(frame, enableInt, x, y, width, height);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_pass_set_scissor", e);
+        }
+    }
+
+    public static MemoryAddress b4dPassPick(MemoryAddress frame, int x, int y) {
+        try {
+            return (MemoryAddress) B4D_PASS_PICK_HANDLE.invoke(frame, x, y);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_pass_pick", e);
+        }
+    }
+
+    public static int b4dPickPoll(MemoryAddress pick, MemoryAddress objectId) {
+        try {
+            return (int) B4D_PICK_POLL_HANDLE.invoke(pick, objectId);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_pick_poll", e);
+        }
+    }
+
+    public static void b4dDestroyPick(MemoryAddress pick) {
+        try {
+            B4D_DESTROY_PICK_HANDLE.invoke(pick);
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to invoke b4d_destroy_pick", e);
+        }
+    }
+
     public static void b4dEndFrame(MemoryAddress frame) {
         try {
             B4D_END_FRAME_HANDLE.invoke(frame);
@@ -369,6 +703,32 @@ public class Natives {
         }
     }
 
+    private static NativeSymbol createFrameCompleteHandler() {
+        try {
+            MethodHandle handlerFn = MethodHandles.lookup().findStatic(Natives.class, "nativeFrameCompleteHandler",
+                    MethodType.methodType(Void.TYPE, Long.TYPE, Long.TYPE));
+            return linker.upcallStub(
+                    handlerFn,
+                    FunctionDescriptor.ofVoid(JAVA_LONG, JAVA_LONG),
+                    ResourceScope.globalScope()
+            );
+        } catch (Throwable e) {
+            throw new RuntimeException("Failed to create b4d frame complete handler", e);
+        }
+    }
+
+    private static void nativeFrameCompleteHandler(long frameId, long userData) {
+        // Exceptions must not propagate into native code
+        try {
+            Runnable callback = frameCompleteCallbacks.remove(userData);
+            if (callback != null) {
+                callback.run();
+            }
+        } catch (Throwable e) {
+            NATIVE_LOGGER.error("Frame complete callback of frame " + frameId + " failed", e);
+        }
+    }
+
     public static void verifyInit() {
     }
 }
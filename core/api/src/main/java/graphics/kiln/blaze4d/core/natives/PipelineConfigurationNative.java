@@ -18,6 +18,7 @@ public class PipelineConfigurationNative {
     public static final MemoryLayout.PathElement BLEND_ALPHA_OP_PATH;
     public static final MemoryLayout.PathElement BLEND_ALPHA_SRC_FACTOR_PATH;
     public static final MemoryLayout.PathElement BLEND_ALPHA_DST_FACTOR_PATH;
+    public static final MemoryLayout.PathElement COLOR_WRITE_MASK_PATH;
 
     public static final VarHandle DEPTH_TEST_ENABLE_HANDLE;
     public static final VarHandle DEPTH_COMPARE_OP_HANDLE;
@@ -29,6 +30,7 @@ public class PipelineConfigurationNative {
     public static final VarHandle BLEND_ALPHA_OP_HANDLE;
     public static final VarHandle BLEND_ALPHA_SRC_FACTOR_HANDLE;
     public static final VarHandle BLEND_ALPHA_DST_FACTOR_HANDLE;
+    public static final VarHandle COLOR_WRITE_MASK_HANDLE;
 
     static {
         LAYOUT = MemoryLayout.structLayout(
@@ -41,7 +43,8 @@ public class PipelineConfigurationNative {
                 ValueLayout.JAVA_INT.withName("blend_color_dst_factor"),
                 ValueLayout.JAVA_INT.withName("blend_alpha_op"),
                 ValueLayout.JAVA_INT.withName("blend_alpha_src_factor"),
-                ValueLayout.JAVA_INT.withName("blend_alpha_dst_factor")
+                ValueLayout.JAVA_INT.withName("blend_alpha_dst_factor"),
+                ValueLayout.JAVA_INT.withName("color_write_mask")
         );
 
         DEPTH_TEST_ENABLE_PATH = MemoryLayout.PathElement.groupElement("depth_test_enable");
@@ -54,6 +57,7 @@ public class PipelineConfigurationNative {
         BLEND_ALPHA_OP_PATH = MemoryLayout.PathElement.groupElement("blend_alpha_op");
         BLEND_ALPHA_SRC_FACTOR_PATH = MemoryLayout.PathElement.groupElement("blend_alpha_src_factor");
         BLEND_ALPHA_DST_FACTOR_PATH = MemoryLayout.PathElement.groupElement("blend_alpha_dst_factor");
+        COLOR_WRITE_MASK_PATH = MemoryLayout.PathElement.groupElement("color_write_mask");
 
         DEPTH_TEST_ENABLE_HANDLE = LAYOUT.varHandle(DEPTH_TEST_ENABLE_PATH);
         DEPTH_COMPARE_OP_HANDLE = LAYOUT.varHandle(DEPTH_COMPARE_OP_PATH);
@@ -65,5 +69,6 @@ public class PipelineConfigurationNative {
         BLEND_ALPHA_OP_HANDLE = LAYOUT.varHandle(BLEND_ALPHA_OP_PATH);
         BLEND_ALPHA_SRC_FACTOR_HANDLE = LAYOUT.varHandle(BLEND_ALPHA_SRC_FACTOR_PATH);
         BLEND_ALPHA_DST_FACTOR_HANDLE = LAYOUT.varHandle(BLEND_ALPHA_DST_FACTOR_PATH);
+        COLOR_WRITE_MASK_HANDLE = LAYOUT.varHandle(COLOR_WRITE_MASK_PATH);
     }
 }
\ No newline at end of file
@@ -10,7 +10,11 @@ public enum BlendFactor {
     SRC_ALPHA(6),
     ONE_MINUS_SRC_ALPHA(7),
     DST_ALPHA(8),
-    ONE_MINUS_DST_ALPHA(9);
+    ONE_MINUS_DST_ALPHA(9),
+    SRC1_COLOR(15),
+    ONE_MINUS_SRC1_COLOR(16),
+    SRC1_ALPHA(17),
+    ONE_MINUS_SRC1_ALPHA(18);
 
     private final int value;
 
@@ -34,6 +38,10 @@ public enum BlendFactor {
             case 7 -> ONE_MINUS_SRC_ALPHA;
             case 8 -> DST_ALPHA;
             case 9 -> ONE_MINUS_DST_ALPHA;
+            case 15 -> SRC1_COLOR;
+            case 16 -> ONE_MINUS_SRC1_COLOR;
+            case 17 -> SRC1_ALPHA;
+            case 18 -> ONE_MINUS_SRC1_ALPHA;
             default -> throw new IllegalArgumentException("Invalid blend factor value: " + value);
         };
     }
@@ -50,6 +58,10 @@ public enum BlendFactor {
             case 0x0305 -> ONE_MINUS_DST_ALPHA;
             case 0x0306 -> DST_COLOR;
             case 0x0307 -> ONE_MINUS_DST_COLOR;
+            case 0x88F9 -> SRC1_COLOR;
+            case 0x88FA -> ONE_MINUS_SRC1_COLOR;
+            case 0x8589 -> SRC1_ALPHA;
+            case 0x88FB -> ONE_MINUS_SRC1_ALPHA;
             default -> throw new IllegalArgumentException("Invalid blend func: " + factor);
         };
     }
@@ -95,6 +95,17 @@ public class PipelineConfiguration implements AutoCloseable {
         return BlendFactor.fromValue((int) PipelineConfigurationNative.BLEND_ALPHA_DST_FACTOR_HANDLE.get(this.memory));
     }
 
+    /**
+     * Sets the written color components as a bitmask of vulkan color component flags.
+     */
+    public void setColorWriteMask(int mask) {
+        PipelineConfigurationNative.COLOR_WRITE_MASK_HANDLE.set(this.memory, mask);
+    }
+
+    public int getColorWriteMask() {
+        return (int) PipelineConfigurationNative.COLOR_WRITE_MASK_HANDLE.get(this.memory);
+    }
+
     public MemoryAddress getAddress() {
         return this.memory.address();
     }
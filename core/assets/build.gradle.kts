@@ -11,10 +11,18 @@ configure<graphics.kiln.blaze4d.build.assets.AssetsPluginExtension> {
             addModule("debug/color.vert")
             addModule("debug/uv.vert")
             addModule("debug/null.vert")
+            addModule("debug/pulled.vert")
             addModule("debug/debug.frag")
             addModule("debug/textured.frag")
             addModule("debug/background.vert")
             addModule("debug/background.frag")
+            addModule("debug/background_ms.frag")
+            addModule("fallback/position.vert")
+            addModule("fallback/color.vert")
+            addModule("fallback/fallback.frag")
+            addModule("atlas/alpha_mipmap.comp")
+            addModule("lights/light_culling.comp")
+            addModule("culling/section_culling.comp")
         }
 
         addProject("Utils") {
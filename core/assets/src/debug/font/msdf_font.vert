@@ -17,6 +17,8 @@ layout(set=0, binding=0) uniform framebuffer_data {
     vec2 framebuffer_size;
 };
 
+layout(constant_id=0) const float em_size = 50.0;
+
 layout(location=0) out vec2 uv_cord;
 layout(location=1) out vec4 out_color;
 layout(location=2) flat out uint out_atlas_index;
@@ -26,7 +28,7 @@ void main() {
     out_color = vec4(color.rgb, 1.0);
     out_atlas_index = atlas_index;
 
-    vec2 position = (box_offset + (vertex_multiplier * box_size) + global_offset) * 50;
+    vec2 position = (box_offset + (vertex_multiplier * box_size) + global_offset) * em_size;
     position = ((position / framebuffer_size) - 0.5) * 2.0;
     gl_Position = vec4(position, 0.0, 1.0);
 }
\ No newline at end of file
@@ -1,8 +1,10 @@
 #version 450
 
+#include <screen_effects.glsl>
+
 layout(input_attachment_index=0, set=0, binding=0) uniform subpassInput rendered;
 
-layout(location=0) in vec2 in_pixel_coord;
+layout(location=0) in vec2 in_uv;
 
 layout(location=0) out vec4 out_color;
 
@@ -10,8 +12,8 @@ const float BASE_VALUE[2] = float[](0.2, 0.4);
 const float OFFSET_VALUE[2] = float[](0.0, -0.1);
 
 vec3 generate_bg() {
-    int x = int(round(in_pixel_coord.x));
-    int y = int(round(in_pixel_coord.y));
+    int x = int(gl_FragCoord.x);
+    int y = int(gl_FragCoord.y);
     float base = BASE_VALUE[((x / 200) + (y / 200)) % 2];
     float offset = OFFSET_VALUE[((x / 20) + (y / 20)) % 2];
 
@@ -23,5 +25,8 @@ void main() {
 
     float alpha = in_color.a;
 
-    out_color = vec4(((1.0 - alpha) * generate_bg()) + (alpha * in_color.rgb), 1.0);
+    // Subpass inputs can only be read at the current pixel so only the color of the effects is applied
+    vec3 color = screen_effects_apply_color(in_color.rgb, in_uv);
+
+    out_color = vec4(((1.0 - alpha) * generate_bg()) + (alpha * color), 1.0);
 }
\ No newline at end of file
@@ -0,0 +1,61 @@
+#version 450
+
+#include <screen_effects.glsl>
+
+layout(constant_id=0) const int SAMPLE_COUNT = 4;
+
+layout(input_attachment_index=0, set=0, binding=0) uniform subpassInputMS rendered;
+
+layout(location=0) in vec2 in_uv;
+
+layout(location=0) out vec4 out_color;
+
+const float BASE_VALUE[2] = float[](0.2, 0.4);
+const float OFFSET_VALUE[2] = float[](0.0, -0.1);
+
+vec3 generate_bg() {
+    int x = int(gl_FragCoord.x);
+    int y = int(gl_FragCoord.y);
+    float base = BASE_VALUE[((x / 200) + (y / 200)) % 2];
+    float offset = OFFSET_VALUE[((x / 20) + (y / 20)) % 2];
+
+    return vec3(base + offset);
+}
+
+float max3(vec3 v) {
+    return max(max(v.r, v.g), v.b);
+}
+
+vec3 tonemap(vec3 color) {
+    return color / (1.0 + max3(color));
+}
+
+vec3 inverse_tonemap(vec3 color) {
+    return color / max(1.0 - max3(color), 0.0001);
+}
+
+/**
+ * Averages all samples. Samples are tonemapped before averaging so that single very bright samples
+ * do not dominate the result.
+ */
+vec4 resolve() {
+    vec4 sum = vec4(0.0);
+    for (int i = 0; i < SAMPLE_COUNT; i++) {
+        vec4 color = subpassLoad(rendered, i);
+        sum += vec4(tonemap(color.rgb), color.a);
+    }
+    sum /= float(SAMPLE_COUNT);
+
+    return vec4(inverse_tonemap(sum.rgb), sum.a);
+}
+
+void main() {
+    vec4 in_color = resolve();
+
+    float alpha = in_color.a;
+
+    // Subpass inputs can only be read at the current pixel so only the color of the effects is applied
+    vec3 color = screen_effects_apply_color(in_color.rgb, in_uv);
+
+    out_color = vec4(((1.0 - alpha) * generate_bg()) + (alpha * color), 1.0);
+}
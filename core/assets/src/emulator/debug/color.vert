@@ -7,10 +7,11 @@
 
 layout(location=0) in vec3 in_position;
 layout(location=1) in vec4 in_color;
+layout(location=4) in vec3 in_instance_offset;
 
 layout(location=0) out vec4 out_color;
 
 void main() {
-    gl_Position = mc_transform_position(in_position);
+    gl_Position = mc_transform_position(in_position + in_instance_offset);
     out_color = in_color;
 }
\ No newline at end of file
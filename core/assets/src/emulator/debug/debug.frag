@@ -1,9 +1,13 @@
 #version 450
 
+#include <mc_uniforms.glsl>
+
 layout(location=0) in vec4 in_color;
 
 layout(location=0) out vec4 out_color;
+layout(location=1) out uint out_object_id;
 
 void main() {
     out_color = in_color;
+    out_object_id = mc_object_id();
 }
\ No newline at end of file
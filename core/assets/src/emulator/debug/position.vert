@@ -6,10 +6,11 @@
 #include <mc_uniforms.glsl>
 
 layout(location=0) in vec3 in_position;
+layout(location=4) in vec3 in_instance_offset;
 
 layout(location=0) out vec4 out_color;
 
 void main() {
-    gl_Position = mc_transform_position(in_position);
+    gl_Position = mc_transform_position(in_position + in_instance_offset);
     out_color = vec4(0.0, 0.0, 0.0, 1.0);
 }
\ No newline at end of file
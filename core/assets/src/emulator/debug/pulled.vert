@@ -0,0 +1,57 @@
+#version 450
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_buffer_reference_uvec2 : require
+/**
+ * A debug shader fetching the vertex data from the vertex buffer address instead of using vertex
+ * input attributes. Can replace any of the other debug vertex shaders.
+ */
+
+#define MC_VERTEX_PULLING
+#include <mc_uniforms.glsl>
+
+/**
+ * Selects which debug shader is replaced.
+ * 0: position.vert
+ * 1: color.vert
+ * 2: uv.vert
+ * 3: null.vert
+ */
+layout(constant_id=0) const uint OUTPUT_MODE = 0;
+
+layout(location=4) in vec3 in_instance_offset;
+
+layout(location=0) out vec4 out_color;
+layout(location=1) out vec2 out_uv;
+
+vec4 read_attribute(uint base) {
+    uint offset = base + mc_vertex_attribute_offset();
+    switch (mc_vertex_attribute_format()) {
+        case MC_VERTEX_FORMAT_R32G32_SFLOAT:
+            return vec4(uintBitsToFloat(mc_vertex_word(offset)), uintBitsToFloat(mc_vertex_word(offset + 4)), 0.0, 1.0);
+        case MC_VERTEX_FORMAT_R8G8B8A8_UNORM:
+            return unpackUnorm4x8(mc_vertex_word(offset));
+        case MC_VERTEX_FORMAT_R16G16_SINT: {
+            uint word = mc_vertex_word(offset);
+            return vec4(float(bitfieldExtract(int(word), 0, 16)), float(bitfieldExtract(int(word), 16, 16)), 0.0, 1.0);
+        }
+        default:
+            return vec4(0.0, 0.0, 0.0, 1.0);
+    }
+}
+
+void main() {
+    uint base = uint(gl_VertexIndex) * mc_vertex_stride();
+    gl_Position = mc_transform_position(mc_vertex_position(base) + in_instance_offset);
+
+    if (OUTPUT_MODE == 1) {
+        out_color = read_attribute(base);
+    } else if (OUTPUT_MODE == 2) {
+        vec2 uv = read_attribute(base).xy;
+        out_color = vec4(uv, 0.0, 1.0);
+        out_uv = uv;
+    } else if (OUTPUT_MODE == 3) {
+        out_color = vec4(0.0, 0.0, 0.0, 0.0);
+    } else {
+        out_color = vec4(0.0, 0.0, 0.0, 1.0);
+    }
+}
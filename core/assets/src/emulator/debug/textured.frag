@@ -5,9 +5,11 @@
 layout(location=1) in vec2 in_uv;
 
 layout(location=0) out vec4 out_color;
+layout(location=1) out uint out_object_id;
 
 layout(constant_id=0) const uint IMAGE_INDEX = 0;
 
 void main() {
     out_color = mc_image(IMAGE_INDEX, in_uv);
+    out_object_id = mc_object_id();
 }
\ No newline at end of file
@@ -7,12 +7,13 @@
 
 layout(location=0) in vec3 in_position;
 layout(location=1) in vec2 in_uv;
+layout(location=4) in vec3 in_instance_offset;
 
 layout(location=0) out vec4 out_color;
 layout(location=1) out vec2 out_uv;
 
 void main() {
-    gl_Position = mc_transform_position(in_position);
+    gl_Position = mc_transform_position(in_position + in_instance_offset);
     out_color = vec4(in_uv, 0.0, 1.0);
     out_uv = in_uv;
 }
\ No newline at end of file
@@ -0,0 +1,47 @@
+/**
+ * Helper functions to sample environment probes.
+ *
+ * Must match the atlas layout of EnvironmentProbe in probe.rs. The 6 faces are stored in a 3 by 2
+ * atlas ordered +X, -X, +Y, -Y, +Z, -Z using the orientation of vulkan cube maps.
+ */
+
+/**
+ * Returns the face index and the uv coordinates within the face sampled for a direction.
+ */
+vec3 environment_probe_face_uv(vec3 direction) {
+    vec3 abs_dir = abs(direction);
+
+    float face;
+    vec2 sc_tc;
+    float ma;
+    if (abs_dir.x >= abs_dir.y && abs_dir.x >= abs_dir.z) {
+        face = direction.x >= 0.0 ? 0.0 : 1.0;
+        sc_tc = vec2(direction.x >= 0.0 ? -direction.z : direction.z, -direction.y);
+        ma = abs_dir.x;
+    } else if (abs_dir.y >= abs_dir.z) {
+        face = direction.y >= 0.0 ? 2.0 : 3.0;
+        sc_tc = vec2(direction.x, direction.y >= 0.0 ? direction.z : -direction.z);
+        ma = abs_dir.y;
+    } else {
+        face = direction.z >= 0.0 ? 4.0 : 5.0;
+        sc_tc = vec2(direction.z >= 0.0 ? direction.x : -direction.x, -direction.y);
+        ma = abs_dir.z;
+    }
+
+    return vec3(face, (sc_tc / ma + 1.0) / 2.0);
+}
+
+/**
+ * Samples a environment probe atlas in a direction. The uv coordinates are clamped half a texel
+ * away from the face borders to avoid filtering across neighbouring faces.
+ */
+vec4 environment_probe_sample(sampler2D probe, vec3 direction) {
+    vec2 atlas_size = vec2(textureSize(probe, 0));
+    vec2 face_size = atlas_size / vec2(3.0, 2.0);
+
+    vec3 face_uv = environment_probe_face_uv(direction);
+    vec2 uv = clamp(face_uv.yz, 0.5 / face_size, 1.0 - 0.5 / face_size);
+
+    vec2 cell = vec2(mod(face_uv.x, 3.0), floor(face_uv.x / 3.0));
+    return texture(probe, (cell + uv) / vec2(3.0, 2.0));
+}
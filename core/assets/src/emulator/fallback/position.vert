@@ -0,0 +1,15 @@
+#version 450
+/**
+ * The fallback vertex shader used for vertex formats without a color channel.
+ */
+
+#include <fallback_transform.glsl>
+
+layout(location=0) in vec3 in_position;
+
+layout(location=0) out vec4 out_color;
+
+void main() {
+    gl_Position = fallback_transform_position(in_position);
+    out_color = vec4(1.0);
+}
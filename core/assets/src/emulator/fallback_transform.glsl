@@ -0,0 +1,19 @@
+/**
+ * The push constants of the fallback pipeline. All uniforms are passed as push constants since the
+ * fallback pipeline does not use any descriptors.
+ */
+layout(push_constant) uniform FallbackConstants {
+    mat4 model_view_projection;
+    vec4 chunk_offset;
+} fallback;
+
+/**
+ * Transforms a model space position into vulkan clip space. Equivalent to mc_transform_position
+ * with a standard depth range.
+ */
+vec4 fallback_transform_position(vec3 position) {
+    vec4 tmp = fallback.model_view_projection * vec4(position + fallback.chunk_offset.xyz, 1.0);
+    tmp.z = (tmp.z + tmp.w) / 2.0;
+    tmp.y *= -1.0;
+    return tmp;
+}
@@ -0,0 +1,46 @@
+/**
+ * Access to the point lights of the pass. Lights are culled per 16x16 pixel tile of the
+ * framebuffer before the pass so fragment shaders only have to iterate the lights affecting their
+ * tile. Must match lights.rs and light_culling.comp.
+ *
+ * All light positions are in view space.
+ */
+
+const uint _EMULATOR_LIGHT_TILE_SIZE = 16;
+const uint _EMULATOR_MAX_LIGHTS_PER_TILE = 63;
+
+struct _EmulatorPointLight {
+    vec4 position_radius;
+    vec4 color_intensity;
+};
+
+layout(set=0, binding=2, std430) readonly buffer _EmulatorLights {
+    _EmulatorPointLight lights[];
+} _emulator_lights;
+
+layout(set=0, binding=3, std430) readonly buffer _EmulatorLightTiles {
+    uint data[];
+} _emulator_light_tiles;
+
+/**
+ * Returns the diffuse light received from all point lights of the tile of the fragment. The
+ * intensity falls off quadratically to 0 at the radius of the light.
+ */
+vec3 point_lights_diffuse(vec3 view_position, vec3 view_normal) {
+    uvec2 tile = uvec2(gl_FragCoord.xy) / _EMULATOR_LIGHT_TILE_SIZE;
+    uint base = 1 + (tile.y * _emulator_light_tiles.data[0] + tile.x) * (_EMULATOR_MAX_LIGHTS_PER_TILE + 1);
+    uint count = _emulator_light_tiles.data[base];
+
+    vec3 result = vec3(0.0);
+    for (uint i = 0; i < count; i++) {
+        _EmulatorPointLight light = _emulator_lights.lights[_emulator_light_tiles.data[base + 1 + i]];
+
+        vec3 to_light = light.position_radius.xyz - view_position;
+        float distance = length(to_light);
+        float falloff = clamp(1.0 - distance / light.position_radius.w, 0.0, 1.0);
+        float n_dot_l = max(dot(view_normal, to_light / max(distance, 0.0001)), 0.0);
+
+        result += light.color_intensity.rgb * light.color_intensity.a * falloff * falloff * n_dot_l;
+    }
+    return result;
+}
@@ -2,15 +2,30 @@
  * Defines all inputs to support minecrafts uniforms.
  */
 
+#extension GL_EXT_multiview : require
+
+/**
+ * Set if the pipeline renders into multiple views. The projection matrix is then replaced by the
+ * per view matrices of the static uniforms.
+ */
+layout(constant_id=100) const bool _mc_multiview = false;
+
+/**
+ * Set if the pipeline uses reversed depth. The projection matrices then already map to the depth
+ * range used by vulkan.
+ */
+layout(constant_id=101) const bool _mc_reverse_z = false;
+
 layout(set=0, binding=1) uniform sampler2D[3] _mc_image;
 
-layout(set=0, binding=0, std140)
+layout(set=1, binding=0, std140)
 uniform _McStaticUniforms {
     mat4 projection_matrix;
     vec4 fog_color;
     vec3 fog_range_and_game_time;
     uint fog_shape;
     vec2 screen_size;
+    layout(offset=128) mat4 view_projection_matrices[2];
 } _mc_static_uniforms;
 
 /*
@@ -24,10 +39,34 @@ uniform McSet1Binding0 {
     float line_width;
 } mc_set_1_binding_0;*/
 
+#ifdef MC_VERTEX_PULLING
+/**
+ * The vertex buffer of the draw as an array of 4 byte words. Only defined if the shader fetches
+ * its vertex data using the vertex buffer address.
+ */
+layout(buffer_reference, std430, buffer_reference_align=4)
+readonly buffer _McVertexWords {
+    uint words[];
+};
+
+#define MC_VERTEX_FORMAT_NONE 0
+#define MC_VERTEX_FORMAT_R32G32_SFLOAT 1
+#define MC_VERTEX_FORMAT_R8G8B8A8_UNORM 2
+#define MC_VERTEX_FORMAT_R16G16_SINT 3
+#endif
+
 layout(push_constant)
 uniform _PushConstant {
     mat4 model_view_matrix;
     vec3 chunk_offset;
+    uint object_id;
+#ifdef MC_VERTEX_PULLING
+    uvec2 vertex_address;
+    uint vertex_stride;
+    uint position_offset;
+    uint attribute_offset;
+    uint attribute_format;
+#endif
 } _push_constant;
 
 mat4 mc_model_view_matrix() {
@@ -35,6 +74,9 @@ mat4 mc_model_view_matrix() {
 }
 
 mat4 mc_projection_matrix() {
+    if (_mc_multiview) {
+        return _mc_static_uniforms.view_projection_matrices[gl_ViewIndex];
+    }
     return _mc_static_uniforms.projection_matrix;
 }
 
@@ -91,9 +133,51 @@ vec3 mc_chunk_offset() {
     return _push_constant.chunk_offset;
 }
 
+/**
+ * The id of the object being drawn. Written into the object id attachment if the pipeline uses one.
+ */
+uint mc_object_id() {
+    return _push_constant.object_id;
+}
+
+#ifdef MC_VERTEX_PULLING
+uint mc_vertex_stride() {
+    return _push_constant.vertex_stride;
+}
+
+uint mc_vertex_attribute_offset() {
+    return _push_constant.attribute_offset;
+}
+
+uint mc_vertex_attribute_format() {
+    return _push_constant.attribute_format;
+}
+
+/**
+ * Reads the 4 byte word at a byte offset into the vertex buffer. The offset must be 4 byte aligned.
+ */
+uint mc_vertex_word(uint offset) {
+    return _McVertexWords(_push_constant.vertex_address).words[offset / 4];
+}
+
+/**
+ * Reads the R32G32B32_SFLOAT position of the vertex starting at a byte offset.
+ */
+vec3 mc_vertex_position(uint base) {
+    uint offset = base + _push_constant.position_offset;
+    return vec3(
+        uintBitsToFloat(mc_vertex_word(offset)),
+        uintBitsToFloat(mc_vertex_word(offset + 4)),
+        uintBitsToFloat(mc_vertex_word(offset + 8))
+    );
+}
+#endif
+
 vec4 mc_transform_position(vec3 position) {
     vec4 tmp = mc_projection_matrix() * (mc_model_view_matrix() * vec4(position + mc_chunk_offset(), 1.0));
-    tmp.z = (tmp.z + tmp.w) / 2.0;
+    if (!_mc_reverse_z) {
+        tmp.z = (tmp.z + tmp.w) / 2.0;
+    }
     tmp.y *= -1.0;
     return tmp;
 }
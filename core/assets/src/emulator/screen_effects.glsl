@@ -0,0 +1,61 @@
+/**
+ * Defines the inputs and helper functions for the vanilla fullscreen overlay effects.
+ *
+ * Must match ScreenEffects in pipeline.rs. All intensities are in the range 0 to 1 where 0
+ * disables the effect.
+ */
+
+layout(push_constant)
+uniform _ScreenEffects {
+    float portal;
+    float underwater;
+    float powder_snow;
+    float pumpkin_blur;
+    float time;
+} _screen_effects;
+
+/**
+ * Returns the distorted uv coordinates for pipelines which can sample the rendered image.
+ */
+vec2 screen_effects_distort_uv(vec2 uv) {
+    float time = _screen_effects.time;
+
+    vec2 centered = uv - vec2(0.5);
+    float portal_scale = 1.0 + sin(time * 2.0) * 0.05 * _screen_effects.portal;
+    centered *= portal_scale;
+
+    vec2 wave = vec2(sin(uv.y * 20.0 + time * 3.0), cos(uv.x * 20.0 + time * 3.0));
+    centered += wave * 0.004 * _screen_effects.underwater;
+
+    return centered + vec2(0.5);
+}
+
+/**
+ * Applies the color part of all effects. uv is in the range 0 to 1.
+ */
+vec3 screen_effects_apply_color(vec3 color, vec2 uv) {
+    float time = _screen_effects.time;
+    float edge = clamp(length(uv - vec2(0.5)) * 2.0, 0.0, 1.0);
+
+    if (_screen_effects.underwater > 0.0) {
+        vec3 tint = color * vec3(0.6, 0.75, 1.0);
+        color = mix(color, tint, _screen_effects.underwater);
+    }
+
+    if (_screen_effects.portal > 0.0) {
+        float pulse = 0.8 + 0.2 * sin(time * 4.0 + uv.x * 6.0 + uv.y * 6.0);
+        color = mix(color, vec3(0.5, 0.2, 0.8), _screen_effects.portal * 0.6 * pulse);
+    }
+
+    if (_screen_effects.powder_snow > 0.0) {
+        float frost = smoothstep(0.4, 1.0, edge);
+        color = mix(color, vec3(0.85, 0.92, 1.0), frost * _screen_effects.powder_snow);
+    }
+
+    if (_screen_effects.pumpkin_blur > 0.0) {
+        float dark = smoothstep(0.2, 0.7, edge);
+        color = mix(color, vec3(0.0), dark * _screen_effects.pumpkin_blur);
+    }
+
+    return color;
+}
@@ -6,6 +6,35 @@ layout(location=0) out vec4 out_color;
 
 layout(set=0,binding=0) uniform sampler2D image;
 
+// 0 = No conversion, 1 = Linear sRGB to linear Display-P3
+layout(constant_id=0) const uint GAMUT_CONVERSION = 0;
+layout(constant_id=1) const bool PREMULTIPLY_ALPHA = false;
+layout(constant_id=2) const bool NEAREST = false;
+// Replicates the red channel into all color channels. Used to visualize depth images.
+layout(constant_id=3) const bool GRAYSCALE = false;
+
+const mat3 SRGB_TO_DISPLAY_P3 = mat3(
+    0.8224621, 0.0331941, 0.0170827,
+    0.1775380, 0.9668058, 0.0723974,
+    0.0000000, 0.0000000, 0.9105199
+);
+
 void main() {
-    out_color = texture(image, uv);
-}
\ No newline at end of file
+    vec4 color;
+    if (NEAREST) {
+        ivec2 size = textureSize(image, 0);
+        color = texelFetch(image, clamp(ivec2(uv * vec2(size)), ivec2(0), size - 1), 0);
+    } else {
+        color = texture(image, uv);
+    }
+    if (GRAYSCALE) {
+        color = vec4(color.rrr, 1.0);
+    }
+    if (GAMUT_CONVERSION == 1) {
+        color.rgb = SRGB_TO_DISPLAY_P3 * color.rgb;
+    }
+    if (PREMULTIPLY_ALPHA) {
+        color.rgb *= color.a;
+    }
+    out_color = color;
+}
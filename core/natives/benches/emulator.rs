@@ -0,0 +1,173 @@
+//! Benchmarks of the emulator renderer using a headless device.
+//!
+//! Run with `cargo bench`. Requires a vulkan capable device.
+
+#[path = "../tests/test_common/mod.rs"]
+mod test_common;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use b4d_core::prelude::*;
+use b4d_core::renderer::emulator::debug_pipeline::{DebugPipeline, DebugPipelineMode};
+use b4d_core::renderer::emulator::mc_shaders::{McUniform, McUniformData};
+use b4d_core::renderer::emulator::pipeline::EmulatorPipeline;
+use b4d_core::renderer::emulator::replay::FrameReplayer;
+
+const OUTPUT_SIZE: (u32, u32) = (256, 256);
+
+/// The number of draws recorded per pass in the recording benchmarks.
+const DRAWS_PER_PASS: u64 = 4096;
+
+fn get_output_size() -> Vec2u32 {
+    Vec2u32::new(OUTPUT_SIZE.0, OUTPUT_SIZE.1)
+}
+
+fn make_replayer() -> FrameReplayer {
+    FrameReplayer::new_headless(false)
+}
+
+/// Blocks until all previously submitted passes have completed.
+fn wait_idle(replayer: &FrameReplayer) {
+    replayer.replay(&test_common::make_empty_scene(Vec2u32::new(16, 16)), DebugPipelineMode::Color).unwrap();
+}
+
+fn bench_immediate_upload(c: &mut Criterion) {
+    let replayer = make_replayer();
+    let emulator = replayer.get_emulator();
+    let pipeline: Arc<dyn EmulatorPipeline> = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, get_output_size()).unwrap();
+
+    let mesh = test_common::make_cube_mesh();
+    let data = mesh.as_mesh_data();
+    let mesh_bytes = (data.vertex_data.len() + data.index_data.len()) as u64;
+
+    let mut group = c.benchmark_group("immediate_upload");
+    group.throughput(Throughput::Bytes(mesh_bytes * DRAWS_PER_PASS));
+    group.bench_function("cube", |b| {
+        b.iter_custom(|iters| {
+            let mut total = Duration::ZERO;
+            for _ in 0..iters {
+                let start = Instant::now();
+                let mut recorder = emulator.start_pass(pipeline.clone());
+                for _ in 0..DRAWS_PER_PASS {
+                    recorder.upload_immediate(&data);
+                }
+                drop(recorder);
+                total += start.elapsed();
+
+                wait_idle(&replayer);
+            }
+            total
+        })
+    });
+    group.finish();
+}
+
+fn bench_draw_recording(c: &mut Criterion) {
+    let replayer = make_replayer();
+    let emulator = replayer.get_emulator();
+    let pipeline: Arc<dyn EmulatorPipeline> = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, get_output_size()).unwrap();
+    let shader = emulator.create_shader(&test_common::Vertex::make_b4d_vertex_format(), McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+
+    let mesh = test_common::make_cube_mesh();
+    let data = mesh.as_mesh_data();
+    let global_mesh = emulator.create_global_mesh(&data);
+
+    let mut group = c.benchmark_group("draw_recording");
+    group.throughput(Throughput::Elements(DRAWS_PER_PASS));
+    group.bench_function("immediate", |b| {
+        b.iter_custom(|iters| {
+            let mut total = Duration::ZERO;
+            for _ in 0..iters {
+                let start = Instant::now();
+                let mut recorder = emulator.start_pass(pipeline.clone());
+                recorder.update_uniform(&McUniformData::ProjectionMatrix(test_common::make_projection_matrix(get_output_size(), 90f32)), shader);
+                let id = recorder.upload_immediate(&data);
+                for index in 0..DRAWS_PER_PASS {
+                    recorder.update_uniform(&McUniformData::ModelViewMatrix(Mat4f32::new_translation(&Vec3f32::new(0f32, 0f32, 5f32 + index as f32))), shader);
+                    recorder.draw_immediate(id, shader, true);
+                }
+                drop(recorder);
+                total += start.elapsed();
+
+                wait_idle(&replayer);
+            }
+            total
+        })
+    });
+    group.bench_function("global", |b| {
+        b.iter_custom(|iters| {
+            let mut total = Duration::ZERO;
+            for _ in 0..iters {
+                let start = Instant::now();
+                let mut recorder = emulator.start_pass(pipeline.clone());
+                recorder.update_uniform(&McUniformData::ProjectionMatrix(test_common::make_projection_matrix(get_output_size(), 90f32)), shader);
+                for index in 0..DRAWS_PER_PASS {
+                    recorder.update_uniform(&McUniformData::ModelViewMatrix(Mat4f32::new_translation(&Vec3f32::new(0f32, 0f32, 5f32 + index as f32))), shader);
+                    recorder.draw_global(global_mesh.clone(), shader, true);
+                }
+                drop(recorder);
+                total += start.elapsed();
+
+                wait_idle(&replayer);
+            }
+            total
+        })
+    });
+    group.finish();
+
+    drop(global_mesh);
+    emulator.drop_shader(shader);
+}
+
+fn bench_pipeline_creation(c: &mut Criterion) {
+    let replayer = make_replayer();
+    let emulator = replayer.get_emulator();
+
+    c.bench_function("debug_pipeline_creation", |b| {
+        b.iter_batched(
+            || (),
+            |_| DebugPipeline::new(emulator.clone(), DebugPipelineMode::Textured0, get_output_size()).unwrap(),
+            BatchSize::PerIteration
+        )
+    });
+}
+
+fn bench_transfer_bandwidth(c: &mut Criterion) {
+    let replayer = make_replayer();
+    let emulator = replayer.get_emulator();
+
+    let mut group = c.benchmark_group("transfer_bandwidth");
+    for size in [64 * 1024usize, 1024 * 1024, 16 * 1024 * 1024] {
+        let vertex_data = vec![0u8; size];
+        let index_data: Vec<u8> = (0..(size / 16) as u32).flat_map(|index| index.to_ne_bytes()).collect();
+        let data = b4d_core::renderer::emulator::MeshData {
+            vertex_data: &vertex_data,
+            index_data: &index_data,
+            vertex_stride: 16,
+            index_count: (size / 16) as u32,
+            index_type: ash::vk::IndexType::UINT32,
+            primitive_topology: ash::vk::PrimitiveTopology::POINT_LIST,
+        };
+
+        group.throughput(Throughput::Bytes((vertex_data.len() + index_data.len()) as u64));
+        group.bench_function(format!("global_mesh_{}k", size / 1024), |b| {
+            b.iter_custom(|iters| {
+                let start = Instant::now();
+                for _ in 0..iters {
+                    let mesh = emulator.create_global_mesh(&data);
+                    // Waiting on a pass guarantees the upload has completed
+                    wait_idle(&replayer);
+                    drop(mesh);
+                }
+                start.elapsed()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_immediate_upload, bench_draw_recording, bench_pipeline_creation, bench_transfer_bandwidth);
+criterion_main!(benches);
@@ -7,7 +7,7 @@ use winit::event_loop::{ControlFlow, EventLoop};
 
 use b4d_core::prelude::*;
 use b4d_core::renderer::emulator::debug_pipeline::DebugPipelineMode;
-use b4d_core::renderer::emulator::mc_shaders::{McUniform, McUniformData, VertexFormat, VertexFormatEntry};
+use b4d_core::renderer::emulator::mc_shaders::{InstanceFormat, McUniform, McUniformData, VertexFormat, VertexFormatEntry};
 use b4d_core::renderer::emulator::MeshData;
 
 use b4d_core::window::WinitWindow;
@@ -17,11 +17,16 @@ fn main() {
 
     let event_loop = EventLoop::new();
     let window = Box::new(WinitWindow::new("ImmediateCube", 800.0, 600.0, &event_loop));
+    let mut framebuffer = window.create_framebuffer_tracker();
 
-    let b4d = b4d_core::b4d::Blaze4D::new(window, true);
+    let b4d = b4d_core::b4d::Blaze4D::new(window, true, false);
     b4d.set_debug_mode(Some(DebugPipelineMode::Textured0));
     let vertex_format = Vertex::make_b4d_vertex_format();
-    let mut shader = b4d.create_shader(&vertex_format, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+    let instance_format = InstanceFormat {
+        stride: std::mem::size_of::<[f32; 3]>() as u32,
+        offset: VertexFormatEntry { offset: 0, format: vk::Format::R32G32B32_SFLOAT },
+    };
+    let mut shader = b4d.create_instanced_shader(&vertex_format, &instance_format, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
 
     let data = MeshData {
         vertex_data: cast_slice(&CUBE_VERTICES),
@@ -32,13 +37,20 @@ fn main() {
         primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
     };
 
-    let mut mesh = b4d.create_global_mesh(&data);
+    let mesh = b4d.create_global_mesh(&data);
+
+    let mut offsets = Vec::with_capacity(11 * 11 * 11);
+    for x in -5i32..=5i32 {
+        for y in -5i32..=5i32 {
+            for z in -5i32..=5i32 {
+                offsets.push([x as f32, y as f32, z as f32]);
+            }
+        }
+    }
 
     let mut draw_times = Vec::with_capacity(1000);
     let mut last_update = std::time::Instant::now();
 
-    let mut current_size = Vec2u32::new(800, 600);
-
     let start = std::time::Instant::now();
 
     event_loop.run(move |event, _, control_flow| {
@@ -52,16 +64,16 @@ fn main() {
                 *control_flow = ControlFlow::Exit
             },
             Event::WindowEvent {
-                event: WindowEvent::Resized(new_size),
-                ..
+                event,
+                window_id
             } => {
-                current_size[0] = new_size.width;
-                current_size[1] = new_size.height;
+                if let Some(framebuffer_event) = framebuffer.handle_event(window_id, &event) {
+                    log::info!("Framebuffer changed: {:?}", framebuffer_event);
+                }
             }
             Event::MainEventsCleared => {
                 let now = std::time::Instant::now();
-
-                mesh = b4d.create_global_mesh(&data);
+                let current_size = framebuffer.get_framebuffer_size();
 
                 if let Some(mut recorder) = b4d.try_start_frame(current_size) {
 
@@ -69,29 +81,16 @@ fn main() {
 
                     let elapsed = start.elapsed().as_secs_f32();
                     let rotation = Mat4f32::new_rotation(Vec3f32::new(elapsed / 2.34f32, elapsed / 2.783f32, elapsed / 2.593f32));
+                    let translation = Mat4f32::new_translation(&Vec3f32::new(0f32, 0f32, 11f32));
+                    recorder.update_uniform(&McUniformData::ModelViewMatrix(translation * rotation), shader);
 
-                    for x in -5i32..=5i32 {
-                        for y in -5i32..=5i32 {
-                            for z in 1i32..=11i32 {
-                                let translation = Mat4f32::new_translation(&Vec3f32::new(
-                                    0f32 + ((x as f32) / 1f32),
-                                    0f32 + ((y as f32) / 1f32),
-                                    5f32 + ((z as f32) / 1f32)
-                                ));
-                                recorder.update_uniform(&McUniformData::ModelViewMatrix(translation * rotation), shader);
-
-                                let id = recorder.upload_immediate(&data);
-                                recorder.draw_immediate(id, shader, true);
-                                // recorder.draw_global(mesh.clone(), shader, true);
-                            }
-                        }
-                    }
+                    recorder.draw_static_instanced(mesh.clone(), cast_slice(&offsets), offsets.len() as u32, 0, shader, true);
 
                     drop(recorder);
 
                     // Stress test the shader stuff
                     b4d.drop_shader(shader);
-                    shader = b4d.create_shader(&vertex_format, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+                    shader = b4d.create_instanced_shader(&vertex_format, &instance_format, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
                 }
                 draw_times.push(now.elapsed());
 
@@ -183,7 +182,9 @@ impl Vertex {
             color: Some(VertexFormatEntry { offset: std::mem::size_of::<Vec3f32>() as u32, format: vk::Format::R32G32B32A32_SFLOAT }),
             uv0: Some(VertexFormatEntry { offset: std::mem::size_of::<Vec3f32>() as u32 + std::mem::size_of::<Vec4f32>() as u32, format: vk::Format::R32G32_SFLOAT }),
             uv1: None,
-            uv2: None
+            uv2: None,
+            joint_indices: None,
+            joint_weights: None,
         }
     }
 }
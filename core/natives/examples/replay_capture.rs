@@ -0,0 +1,63 @@
+extern crate b4d_core;
+
+use std::path::PathBuf;
+
+use b4d_core::renderer::emulator::capture::FrameCapture;
+use b4d_core::renderer::emulator::debug_pipeline::DebugPipelineMode;
+use b4d_core::renderer::emulator::replay::FrameReplayer;
+
+/// Replays a frame capture on a headless device and writes the result to a png file.
+///
+/// Usage: replay_capture <capture file> <output png> [debug mode]
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!("Usage: {} <capture file> <output png> [debug mode]", args[0]);
+        std::process::exit(1);
+    }
+
+    let capture_path = PathBuf::from(&args[1]);
+    let output_path = PathBuf::from(&args[2]);
+    let mode = args.get(3).map(|mode| parse_mode(mode)).unwrap_or(DebugPipelineMode::Textured0);
+
+    let capture = FrameCapture::read_from_file(&capture_path).unwrap_or_else(|err| {
+        log::error!("Failed to read capture {:?}: {:?}", capture_path, err);
+        std::process::exit(1);
+    });
+    log::info!("Loaded capture with {} commands and output size {:?}", capture.commands.len(), capture.output_size);
+
+    let replayer = FrameReplayer::new_headless(true);
+    let image = replayer.replay(&capture, mode).unwrap_or_else(|err| {
+        log::error!("Failed to replay capture: {:?}", err);
+        std::process::exit(1);
+    });
+
+    let file = std::fs::File::create(&output_path).unwrap();
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), image.size[0], image.size[1]);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(&image.data).unwrap();
+
+    log::info!("Wrote replayed frame to {:?}", output_path);
+}
+
+fn parse_mode(mode: &str) -> DebugPipelineMode {
+    match mode {
+        "depth" => DebugPipelineMode::Depth,
+        "position" => DebugPipelineMode::Position,
+        "color" => DebugPipelineMode::Color,
+        "normal" => DebugPipelineMode::Normal,
+        "uv0" => DebugPipelineMode::UV0,
+        "uv1" => DebugPipelineMode::UV1,
+        "uv2" => DebugPipelineMode::UV2,
+        "textured0" => DebugPipelineMode::Textured0,
+        "textured1" => DebugPipelineMode::Textured1,
+        "textured2" => DebugPipelineMode::Textured2,
+        _ => {
+            eprintln!("Unknown debug mode {}", mode);
+            std::process::exit(1);
+        }
+    }
+}
@@ -11,6 +11,7 @@ mod vma;
 
 pub struct Allocator {
     vma_allocator: vma::Allocator,
+    heap_count: usize,
 
     debug: bool,
     functions: Arc<DeviceFunctions>,
@@ -18,15 +19,33 @@ pub struct Allocator {
 
 impl Allocator {
     pub fn new(functions: Arc<DeviceFunctions>) -> Result<Self, vk::Result> {
-        let vma_allocator = vma::Allocator::new(&functions, vma::AllocatorCreateFlags::empty())?;
+        let mut flags = vma::AllocatorCreateFlags::empty();
+        if functions.buffer_device_address_khr.is_some() {
+            flags |= vma::AllocatorCreateFlags::BUFFER_DEVICE_ADDRESS;
+        }
+        let vma_allocator = vma::Allocator::new(&functions, flags)?;
+        let heap_count = unsafe {
+            functions.instance.vk().get_physical_device_memory_properties(functions.physical_device)
+        }.memory_heap_count as usize;
 
         Ok(Self {
             vma_allocator,
+            heap_count,
             debug: true,
             functions
         })
     }
 
+    /// Returns the current memory usage summed over all memory heaps.
+    pub fn get_memory_usage(&self) -> MemoryUsage {
+        let budgets = self.vma_allocator.get_heap_budgets();
+        budgets[0..self.heap_count].iter().fold(MemoryUsage::default(), |usage, budget| MemoryUsage {
+            allocated_bytes: usage.allocated_bytes + budget.statistics.allocation_bytes,
+            block_bytes: usage.block_bytes + budget.statistics.block_bytes,
+            budget_bytes: usage.budget_bytes + budget.budget,
+        })
+    }
+
     /// Allocates vulkan memory for some requirements.
     ///
     /// Returns the allocation and a [`AllocationBindingInfo`] containing information necessary to
@@ -198,6 +217,27 @@ impl Allocator {
         }
     }
 
+    /// Makes host writes to a range of mapped memory available to the device. Does nothing if the
+    /// memory is host coherent. `offset` is relative to the start of the allocation and `size` may
+    /// be [`vk::WHOLE_SIZE`]. The range is expanded to the non coherent atom size as necessary.
+    ///
+    /// # Safety
+    ///
+    /// `allocation` must have been previously allocated from this allocator and not yet freed.
+    pub unsafe fn flush_allocation(&self, allocation: Allocation, offset: vk::DeviceSize, size: vk::DeviceSize) -> Result<(), vk::Result> {
+        self.vma_allocator.flush_allocation(allocation.vma_allocation, offset, size)
+    }
+
+    /// Makes device writes to a range of mapped memory visible to the host. Does nothing if the
+    /// memory is host coherent. See [`Allocator::flush_allocation`].
+    ///
+    /// # Safety
+    ///
+    /// `allocation` must have been previously allocated from this allocator and not yet freed.
+    pub unsafe fn invalidate_allocation(&self, allocation: Allocation, offset: vk::DeviceSize, size: vk::DeviceSize) -> Result<(), vk::Result> {
+        self.vma_allocator.invalidate_allocation(allocation.vma_allocation, offset, size)
+    }
+
     /// Destroys a previously created buffer and allocation
     ///
     /// # Safety
@@ -243,7 +283,7 @@ impl Allocator {
 ///
 /// It is possible copy and clone handles. In that case the using code must ensure only one copy
 /// is freed.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub struct Allocation {
     vma_allocation: vma::Allocation,
 }
@@ -276,6 +316,17 @@ impl AllocationBindingInfo {
     }
 }
 
+/// Memory usage of a [`Allocator`].
+#[derive(Copy, Clone, Default, Debug)]
+pub struct MemoryUsage {
+    /// The number of bytes used by allocations.
+    pub allocated_bytes: vk::DeviceSize,
+    /// The number of bytes of vulkan memory allocated by the allocator.
+    pub block_bytes: vk::DeviceSize,
+    /// The estimated number of bytes available to the application.
+    pub budget_bytes: vk::DeviceSize,
+}
+
 /// Describes how the host will access some vulkan memory.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub enum HostAccess {
@@ -266,6 +266,23 @@ struct AllocatorCreateInfo {
     p_type_external_memory_handle_types: *const u8,
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Statistics {
+    pub block_count: u32,
+    pub allocation_count: u32,
+    pub block_bytes: vk::DeviceSize,
+    pub allocation_bytes: vk::DeviceSize,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Budget {
+    pub statistics: Statistics,
+    pub usage: vk::DeviceSize,
+    pub budget: vk::DeviceSize,
+}
+
 #[repr(transparent)]
 #[derive(Copy, Clone)]
 struct AllocatorHandle(*const u8);
@@ -303,6 +320,15 @@ impl Allocator {
         }
     }
 
+    /// Returns the budget of every memory heap. Only the first `memoryHeapCount` entries are valid.
+    pub fn get_heap_budgets(&self) -> [Budget; vk::MAX_MEMORY_HEAPS] {
+        let mut budgets = [Budget::default(); vk::MAX_MEMORY_HEAPS];
+        unsafe {
+            sys::vmaGetHeapBudgets(self.handle, budgets.as_mut_ptr())
+        };
+        budgets
+    }
+
     pub unsafe fn allocate_memory(&self, memory_requirements: &vk::MemoryRequirements, create_info: &AllocationCreateInfo, allocation_info: Option<&mut AllocationInfo>) -> Result<Allocation, vk::Result> {
         let mut handle = Allocation::null();
         let allocation_info = allocation_info.map(|i| i as *mut AllocationInfo).unwrap_or(std::ptr::null_mut());
@@ -344,6 +370,24 @@ impl Allocator {
         sys::vmaGetAllocationInfo(self.handle, allocation, info)
     }
 
+    pub unsafe fn flush_allocation(&self, allocation: Allocation, offset: vk::DeviceSize, size: vk::DeviceSize) -> Result<(), vk::Result> {
+        let result = sys::vmaFlushAllocation(self.handle, allocation, offset, size);
+        if result == vk::Result::SUCCESS {
+            Ok(())
+        } else {
+            Err(result)
+        }
+    }
+
+    pub unsafe fn invalidate_allocation(&self, allocation: Allocation, offset: vk::DeviceSize, size: vk::DeviceSize) -> Result<(), vk::Result> {
+        let result = sys::vmaInvalidateAllocation(self.handle, allocation, offset, size);
+        if result == vk::Result::SUCCESS {
+            Ok(())
+        } else {
+            Err(result)
+        }
+    }
+
     pub unsafe fn set_allocation_name(&self, allocation: Allocation, name: &CStr) {
         sys::vmaSetAllocationName(self.handle, allocation, name.as_ptr())
     }
@@ -429,6 +473,11 @@ mod sys {
             p_allocator: AllocatorHandle
         );
 
+        pub(super) fn vmaGetHeapBudgets(
+            allocator: AllocatorHandle,
+            p_budgets: *mut Budget,
+        );
+
         pub(super) fn vmaAllocateMemory(
             allocator: AllocatorHandle,
             p_vk_memory_requirements: *const vk::MemoryRequirements,
@@ -463,6 +512,20 @@ mod sys {
             p_allocation_info: *mut AllocationInfo,
         );
 
+        pub(super) fn vmaFlushAllocation(
+            allocator: AllocatorHandle,
+            allocation: Allocation,
+            offset: vk::DeviceSize,
+            size: vk::DeviceSize,
+        ) -> vk::Result;
+
+        pub(super) fn vmaInvalidateAllocation(
+            allocator: AllocatorHandle,
+            allocation: Allocation,
+            offset: vk::DeviceSize,
+            size: vk::DeviceSize,
+        ) -> vk::Result;
+
         pub(super) fn vmaSetAllocationName(
             allocator: AllocatorHandle,
             allocation: Allocation,
@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::ffi::CString;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
@@ -7,17 +9,57 @@ use crate::BUILD_INFO;
 
 use crate::instance::debug_messenger::RustLogDebugMessenger;
 use crate::device::init::{create_device, DeviceCreateConfig};
-use crate::device::surface::{DeviceSurface, SurfaceSwapchain, SwapchainConfig};
+use crate::device::surface::{DeviceSurface, PresentTimingStatistics, SurfaceSwapchain, SwapchainConfig};
 use crate::instance::init::{create_instance, InstanceCreateConfig};
 use crate::vk::objects::surface::SurfaceProvider;
 
 use crate::prelude::*;
-use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, GlobalMesh, MeshData};
-use crate::renderer::emulator::debug_pipeline::{DebugPipeline, DebugPipelineMode};
-use crate::renderer::emulator::mc_shaders::{McUniform, ShaderId, VertexFormat};
-use crate::renderer::emulator::PassRecorder;
-use crate::renderer::emulator::pipeline::{EmulatorPipeline, SwapchainOutput};
+use crate::renderer::debug::overlay::DebugOverlay;
+use crate::renderer::debug::statistics::StatisticsTracker;
+use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, GlobalMesh, MeshData, SamplerInfo};
+use crate::renderer::emulator::debug_pipeline::{DebugPipeline, DebugPipelineMode, DebugPipelineOptions, MsaaConfig};
+use crate::renderer::emulator::fallback_pipeline::FallbackPipeline;
+use crate::renderer::emulator::frame_stream::FrameStream;
+use crate::renderer::emulator::mc_shaders::{InstanceFormat, McUniform, ShaderId, VertexFormat};
+use crate::renderer::emulator::{PassId, PassRecorder};
+use crate::renderer::emulator::pipeline::{EmulatorPipeline, PipelineConfigHint, PresentConfig, SwapchainOutput};
+use crate::renderer::emulator::projection::DepthMode;
+use crate::renderer::emulator::resource_set::{OwnedShader, ResourceSet};
+use crate::renderer::emulator::streaming::StreamingManager;
+use crate::renderer::emulator::texture_loader::{Ktx2Texture, TextureLoadError};
 use crate::util::format::Format;
+use crate::util::trace::b4d_span;
+
+/// The printf buffer size used if shader printf is enabled. The validation layer default of 1024
+/// bytes is too small for most emulator shaders.
+const SHADER_PRINTF_BUFFER_SIZE: u32 = 1024 * 1024;
+
+/// The maximum time [`BackpressurePolicy::Block`] waits for the gpu before starting a frame anyway.
+const BACKPRESSURE_BLOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Configures how [`Blaze4D::try_start_frame`] behaves if the gpu falls behind the host.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BackpressurePolicy {
+    /// Frames are always started. Work queues up without limit if the gpu is too slow.
+    Unbounded,
+
+    /// Blocks until less than `max_pending_passes` passes are queued or executing on the gpu.
+    Block { max_pending_passes: u32 },
+
+    /// Returns [`None`] instead of starting a frame if `max_pending_passes` or more passes are
+    /// queued or executing on the gpu. The host should skip rendering the frame.
+    DropFrames { max_pending_passes: u32 },
+}
+
+impl BackpressurePolicy {
+    pub const DEFAULT: BackpressurePolicy = BackpressurePolicy::Block { max_pending_passes: 2 };
+}
+
+impl Default for BackpressurePolicy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
 
 pub struct Blaze4D {
     instance: Arc<InstanceContext>,
@@ -25,15 +67,42 @@ pub struct Blaze4D {
     emulator: Arc<EmulatorRenderer>,
 
     render_config: Mutex<RenderConfig>,
+    resources: Mutex<Arc<ResourceSet>>,
+
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<Mutex<renderdoc::RenderDoc<renderdoc::V141>>>,
 }
 
 impl Blaze4D {
     /// Creates a new Blaze4D instance and starts all engine modules.
     ///
     /// The supported vertex formats for the [`EmulatorRenderer`] must be provided here.
-    pub fn new(mut main_window: Box<dyn SurfaceProvider>, enable_validation: bool) -> Self {
+    ///
+    /// If `enable_shader_printf` is true the validation layers are enabled with debugPrintfEXT
+    /// support and any shader printf output is logged to the
+    /// [`crate::instance::debug_messenger::SHADER_PRINTF_LOG_TARGET`] target.
+    pub fn new(main_window: Box<dyn SurfaceProvider>, enable_validation: bool, enable_shader_printf: bool) -> Self {
+        Self::new_with_msaa(main_window, enable_validation, enable_shader_printf, MsaaConfig::NONE)
+    }
+
+    /// Creates a new Blaze4D instance rendering with multisampling. See [`Blaze4D::new`] and
+    /// [`Blaze4D::set_msaa`].
+    pub fn new_with_msaa(mut main_window: Box<dyn SurfaceProvider>, enable_validation: bool, enable_shader_printf: bool, msaa: MsaaConfig) -> Self {
         log::info!("Creating Blaze4D instance {:?}", BUILD_INFO);
 
+        // Only succeeds if renderdoc has already been injected into the process
+        #[cfg(feature = "renderdoc")]
+        let renderdoc = match renderdoc::RenderDoc::<renderdoc::V141>::new() {
+            Ok(renderdoc) => {
+                log::info!("Found RenderDoc in-application api");
+                Some(Mutex::new(renderdoc))
+            },
+            Err(err) => {
+                log::info!("RenderDoc in-application api not available: {:?}", err);
+                None
+            }
+        };
+
         let mut instance_config = InstanceCreateConfig::new(
             CString::new("Minecraft").unwrap(),
             vk::make_api_version(0, 0, 1, 0)
@@ -41,7 +110,11 @@ impl Blaze4D {
         if enable_validation {
             instance_config.enable_validation();
         }
+        if enable_shader_printf {
+            instance_config.enable_debug_printf(SHADER_PRINTF_BUFFER_SIZE);
+        }
         instance_config.add_debug_messenger(Box::new(RustLogDebugMessenger::new()));
+        instance_config.add_optional_extension(vk::ExtSwapchainColorspaceFn::name());
         for ext in main_window.get_required_instance_extensions() {
             instance_config.add_required_extension(&ext);
         }
@@ -63,7 +136,9 @@ impl Blaze4D {
 
         let emulator = Arc::new(EmulatorRenderer::new(device.clone()));
 
-        let render_config = Mutex::new(RenderConfig::new(device.clone(), emulator.clone(), main_surface));
+        let mut render_config = RenderConfig::new(device.clone(), emulator.clone(), main_surface);
+        render_config.set_msaa(msaa);
+        let render_config = Mutex::new(render_config);
 
         Self {
             instance,
@@ -71,6 +146,10 @@ impl Blaze4D {
             emulator,
 
             render_config,
+            resources: Mutex::new(Arc::new(ResourceSet::new())),
+
+            #[cfg(feature = "renderdoc")]
+            renderdoc,
         }
     }
 
@@ -82,6 +161,133 @@ impl Blaze4D {
         self.render_config.lock().unwrap().set_debug_mode(mode);
     }
 
+    /// Configures the color spaces which should be used for the main window in order of preference.
+    /// The first color space supported by the surface will be used. If none are supported
+    /// [`vk::ColorSpaceKHR::SRGB_NONLINEAR`] is used.
+    ///
+    /// Only sRGB, extended sRGB and Display-P3 color spaces are currently supported. Any other
+    /// color space will be ignored.
+    pub fn set_preferred_color_spaces(&self, color_spaces: &[vk::ColorSpaceKHR]) {
+        self.render_config.lock().unwrap().set_preferred_color_spaces(color_spaces);
+    }
+
+    /// Configures if the main window output should be composited with transparency. The window
+    /// itself must have been created with transparency support for this to have any effect.
+    ///
+    /// If enabled the alpha channel of the pipeline output will be used by the compositor. If the
+    /// surface does not support a non opaque composite alpha mode this has no effect.
+    pub fn set_transparent_output(&self, transparent: bool) {
+        self.render_config.lock().unwrap().set_transparent_output(transparent);
+    }
+
+    /// Enables or disables the debug overlay. The overlay displays frame timings, draw counts,
+    /// memory usage and upload throughput on top of the main window output.
+    pub fn set_debug_overlay(&self, enabled: bool) {
+        self.render_config.lock().unwrap().set_debug_overlay(enabled);
+    }
+
+    /// Configures the depth convention of the pipelines. Projection matrices are always passed in
+    /// the OpenGL convention and converted if [`DepthMode::Reversed`] is used, which greatly
+    /// reduces z-fighting at long render distances. Initially [`DepthMode::Standard`] is used.
+    pub fn set_depth_mode(&self, depth_mode: DepthMode) {
+        self.render_config.lock().unwrap().set_depth_mode(depth_mode);
+    }
+
+    /// Configures which attachment of the frame is presented to the main window and how it is
+    /// scaled. Intended for debugging. Initially [`PresentConfig::DEFAULT`] is used.
+    pub fn set_present_config(&self, config: PresentConfig) {
+        self.render_config.lock().unwrap().set_present_config(config);
+    }
+
+    /// Configures how frames are started if the gpu falls behind. Defaults to
+    /// [`BackpressurePolicy::DEFAULT`].
+    pub fn set_backpressure_policy(&self, policy: BackpressurePolicy) {
+        match policy {
+            BackpressurePolicy::Block { max_pending_passes: 0 } | BackpressurePolicy::DropFrames { max_pending_passes: 0 } => {
+                log::error!("Backpressure policy {:?} would never start a frame", policy);
+                panic!()
+            }
+            _ => {}
+        }
+        self.render_config.lock().unwrap().backpressure = policy;
+    }
+
+    /// Enables or disables rendering object ids so that frames can be picked using
+    /// [`PassRecorder::pick`]. Disabled by default.
+    pub fn set_object_ids(&self, enabled: bool) {
+        self.render_config.lock().unwrap().set_object_ids(enabled);
+    }
+
+    /// Configures multisampling of the render targets. The render targets are rebuilt with the new
+    /// configuration before the next frame and keep it when the window is resized. Unsupported
+    /// configurations disable multisampling. Initially [`MsaaConfig::NONE`] is used.
+    pub fn set_msaa(&self, msaa: MsaaConfig) {
+        self.render_config.lock().unwrap().set_msaa(msaa);
+    }
+
+    /// Captures all commands recorded into the next frame and writes them to the specified file.
+    ///
+    /// Global meshes are only included if they were created after enabling
+    /// [`Blaze4D::set_retain_capture_data`].
+    pub fn capture_next_frame(&self, path: PathBuf) {
+        self.render_config.lock().unwrap().capture_next_frame(path);
+    }
+
+    /// Configures if global mesh data should be retained on the host so it can be included in frame
+    /// captures. This increases memory usage and only affects meshes created after this call.
+    pub fn set_retain_capture_data(&self, retain: bool) {
+        self.emulator.set_retain_capture_data(retain);
+    }
+
+    /// Configures a stream receiving the output of frames at a fixed rate, for example to feed a
+    /// video encoder. Any previous stream is flushed before being replaced.
+    ///
+    /// If [`None`] is passed streaming is disabled.
+    pub fn set_frame_stream(&self, stream: Option<FrameStream>) {
+        self.render_config.lock().unwrap().frame_stream = stream;
+    }
+
+    /// Returns the present timing statistics of the main window.
+    ///
+    /// Returns [`None`] if VK_GOOGLE_display_timing is not supported or no swapchain currently exists.
+    pub fn get_present_timing_statistics(&self) -> Option<PresentTimingStatistics> {
+        self.render_config.lock().unwrap().get_present_timing_statistics()
+    }
+
+    /// Triggers a RenderDoc capture of the next `n_frames` frames.
+    ///
+    /// Requires the `renderdoc` feature and RenderDoc to be attached to the process. Otherwise a
+    /// warning is logged and nothing happens.
+    pub fn trigger_capture(&self, n_frames: u32) {
+        #[cfg(feature = "renderdoc")]
+        if let Some(renderdoc) = &self.renderdoc {
+            log::info!("Triggering RenderDoc capture of {} frames", n_frames);
+            renderdoc.lock().unwrap().trigger_multi_frame_capture(n_frames);
+            return;
+        }
+
+        log::warn!("Unable to trigger capture of {} frames. RenderDoc is not available", n_frames);
+    }
+
+    /// Calls the callback once the gpu has finished executing the frame. The frame is identified by
+    /// the [`DrawRecorder::get_pass_id`](crate::renderer::emulator::DrawRecorder::get_pass_id) of
+    /// the recorder returned by [`Blaze4D::try_start_frame`].
+    ///
+    /// Hosts can use this to recycle their own per frame resources, for example buffers the frame
+    /// data has been read from. The callback is called on the emulator worker thread, or
+    /// immediately if the frame has already completed, and must not block.
+    pub fn on_frame_complete<F: FnOnce() + Send + 'static>(&self, frame: PassId, callback: F) {
+        self.emulator.on_pass_complete(frame, callback);
+    }
+
+    /// Waits until the gpu has finished all submitted work. The emulator worker is paused while
+    /// waiting so no new work is submitted.
+    pub fn wait_idle(&self) {
+        if let Err(err) = self.device.wait_all_idle() {
+            log::error!("Failed to wait for device idle: {:?}", err);
+        }
+    }
+
     pub fn create_global_mesh(&self, data: &MeshData) -> Arc<GlobalMesh> {
         self.emulator.create_global_mesh(data)
     }
@@ -90,15 +296,62 @@ impl Blaze4D {
         self.emulator.create_global_image(size, format)
     }
 
+    /// Creates a global image from a KTX2 container including all of its mip levels.
+    pub fn create_global_image_ktx2(&self, data: &[u8]) -> Result<Arc<GlobalImage>, TextureLoadError> {
+        Ktx2Texture::parse(data)?.create_image(&self.emulator)
+    }
+
+    /// Creates a streaming manager which keeps the uploaded meshes and images below `budget` bytes.
+    pub fn create_streaming_manager(&self, budget: u64) -> StreamingManager {
+        StreamingManager::new(self.emulator.clone(), budget)
+    }
+
     pub fn create_shader(&self, vertex_format: &VertexFormat, used_uniforms: McUniform) -> ShaderId {
         self.emulator.create_shader(vertex_format, used_uniforms)
     }
 
+    pub fn create_instanced_shader(&self, vertex_format: &VertexFormat, instance_format: &InstanceFormat, used_uniforms: McUniform) -> ShaderId {
+        self.emulator.create_instanced_shader(vertex_format, instance_format, used_uniforms)
+    }
+
     pub fn drop_shader(&self, id: ShaderId) {
+        self.render_config.lock().unwrap().prewarm_hints.remove(&id);
         self.emulator.drop_shader(id);
     }
 
+    /// Creates the pipelines of a shader for the provided draw states on background threads so that
+    /// the first draw using them does not stall the frame. Should be called right after the shader
+    /// has been created. The hints are kept and applied again if the pipeline is rebuilt.
+    pub fn prewarm_shader(&self, shader: ShaderId, hints: &[PipelineConfigHint]) {
+        self.render_config.lock().unwrap().prewarm_shader(shader, hints);
+    }
+
+    /// Returns the current resource set. Frames should fetch the set once when they start and use it
+    /// for all of their draws. See [`Blaze4D::begin_resource_reload`].
+    pub fn get_resources(&self) -> Arc<ResourceSet> {
+        self.resources.lock().unwrap().clone()
+    }
+
+    /// Starts replacing the current resource set. The new set initially contains all resources of
+    /// the current set. Frames keep using the current set until [`ResourceReload::commit`] is
+    /// called.
+    pub fn begin_resource_reload(&self) -> ResourceReload {
+        let base = self.get_resources();
+        ResourceReload {
+            b4d: self,
+            base_generation: base.get_generation(),
+            resources: ResourceSet::clone(&base),
+            new_shaders: Vec::new(),
+            committed: false,
+        }
+    }
+
+    /// Attempts to start a new frame for the main window.
+    ///
+    /// The window size must be the size of the window framebuffer in physical pixels. On HiDPI
+    /// setups this differs from the logical window size.
     pub fn try_start_frame(&self, window_size: Vec2u32) -> Option<PassRecorder> {
+        let _span = b4d_span!("try_start_frame", width = window_size[0], height = window_size[1]);
         if let Some(recorder) = self.render_config.lock().unwrap().try_start_frame(&self.emulator, window_size) {
             Some(recorder)
         } else {
@@ -107,6 +360,99 @@ impl Blaze4D {
     }
 }
 
+/// A transaction building a new [`ResourceSet`] while the current one keeps being used.
+///
+/// Shaders created by the transaction are prewarmed in the background. Dropping the transaction
+/// without committing it releases all resources which have been added to it.
+pub struct ResourceReload<'a> {
+    b4d: &'a Blaze4D,
+    base_generation: u64,
+    resources: ResourceSet,
+    new_shaders: Vec<ShaderId>,
+    committed: bool,
+}
+
+impl<'a> ResourceReload<'a> {
+    /// Creates a shader and prewarms its pipelines for the provided draw states. Replaces any
+    /// shader with the same name.
+    pub fn create_shader(&mut self, name: &str, vertex_format: &VertexFormat, used_uniforms: McUniform, hints: &[PipelineConfigHint]) -> ShaderId {
+        let id = self.b4d.emulator.create_shader(vertex_format, used_uniforms);
+        self.b4d.prewarm_shader(id, hints);
+        self.new_shaders.push(id);
+        self.resources.insert_shader(name.to_string(), Arc::new(OwnedShader::new(self.b4d.emulator.clone(), id)));
+        id
+    }
+
+    /// Adds a image, for example a atlas. Uploads to the image are executed before the first
+    /// frame using it. Replaces any image with the same name.
+    pub fn set_image(&mut self, name: &str, image: Arc<GlobalImage>) {
+        self.resources.insert_image(name.to_string(), image);
+    }
+
+    /// Replaces any sampler with the same name.
+    pub fn set_sampler(&mut self, name: &str, sampler: SamplerInfo) {
+        self.resources.insert_sampler(name.to_string(), sampler);
+    }
+
+    pub fn remove_shader(&mut self, name: &str) {
+        self.resources.remove_shader(name);
+    }
+
+    pub fn remove_image(&mut self, name: &str) {
+        self.resources.remove_image(name);
+    }
+
+    pub fn remove_sampler(&mut self, name: &str) {
+        self.resources.remove_sampler(name);
+    }
+
+    /// Removes all resources inherited from the current set. Used for full reloads.
+    pub fn clear(&mut self) {
+        self.resources.clear();
+    }
+
+    /// Returns true if the pipelines of all shaders created by this transaction are ready.
+    /// Committing before may cause draws to use fallback pipelines for a few frames.
+    pub fn is_ready(&self) -> bool {
+        let guard = self.b4d.render_config.lock().unwrap();
+        self.new_shaders.iter().all(|shader| guard.is_shader_prewarmed(*shader))
+    }
+
+    /// Replaces the current resource set. Frames started after this call use the new set. The old
+    /// resources are released once all frames using them have finished.
+    pub fn commit(mut self) -> Arc<ResourceSet> {
+        // Holding the config lock prevents frames from starting while the set is replaced
+        let mut config = self.b4d.render_config.lock().unwrap();
+        let mut current = self.b4d.resources.lock().unwrap();
+
+        if current.get_generation() != self.base_generation {
+            log::warn!("Resource set has been replaced while reloading. Overwriting generation {} with a set based on generation {}", current.get_generation(), self.base_generation);
+        }
+
+        let resources = Arc::new(std::mem::replace(&mut self.resources, ResourceSet::new()).with_generation(current.get_generation() + 1));
+        for shader in current.get_shader_ids() {
+            if resources.get_shader_ids().all(|id| id != shader) {
+                config.prewarm_hints.remove(&shader);
+            }
+        }
+
+        *current = resources.clone();
+        self.committed = true;
+        resources
+    }
+}
+
+impl<'a> Drop for ResourceReload<'a> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let mut config = self.b4d.render_config.lock().unwrap();
+            for shader in &self.new_shaders {
+                config.prewarm_hints.remove(shader);
+            }
+        }
+    }
+}
+
 struct RenderConfig {
     device: Arc<DeviceContext>,
     emulator: Arc<EmulatorRenderer>,
@@ -118,6 +464,24 @@ struct RenderConfig {
 
     debug_mode: Option<DebugPipelineMode>,
     debug_pipeline: Option<(Arc<dyn EmulatorPipeline>, Arc<SwapchainOutput>)>,
+
+    /// The last created debug pipeline. If only the output size changes the new debug pipeline is
+    /// created by resizing it which reuses all graphics pipelines.
+    resize_source: Option<Arc<DebugPipeline>>,
+
+    depth_mode: DepthMode,
+    object_ids: bool,
+    msaa: MsaaConfig,
+    present_config: PresentConfig,
+    backpressure: BackpressurePolicy,
+    prewarm_hints: HashMap<ShaderId, Vec<PipelineConfigHint>>,
+
+    preferred_color_spaces: Box<[vk::ColorSpaceKHR]>,
+    transparent_output: bool,
+
+    debug_overlay: Option<(Arc<DebugOverlay>, StatisticsTracker)>,
+    pending_capture: Option<PathBuf>,
+    frame_stream: Option<FrameStream>,
 }
 
 impl RenderConfig {
@@ -132,7 +496,84 @@ impl RenderConfig {
             current_pipeline: None,
 
             debug_mode: Some(DebugPipelineMode::Color),
-            debug_pipeline: None
+            debug_pipeline: None,
+            resize_source: None,
+            depth_mode: DepthMode::Standard,
+            object_ids: false,
+            msaa: MsaaConfig::NONE,
+            present_config: PresentConfig::DEFAULT,
+            backpressure: BackpressurePolicy::DEFAULT,
+            prewarm_hints: HashMap::new(),
+
+            preferred_color_spaces: Box::new([vk::ColorSpaceKHR::SRGB_NONLINEAR]),
+            transparent_output: false,
+
+            debug_overlay: None,
+            pending_capture: None,
+            frame_stream: None,
+        }
+    }
+
+    fn prewarm_shader(&mut self, shader: ShaderId, hints: &[PipelineConfigHint]) {
+        let stored = self.prewarm_hints.entry(shader).or_insert_with(Vec::new);
+        for hint in hints {
+            if !stored.contains(hint) {
+                stored.push(*hint);
+            }
+        }
+
+        for (pipeline, _) in self.current_pipeline.iter().chain(self.debug_pipeline.iter()) {
+            pipeline.prewarm_shader(shader, hints);
+        }
+    }
+
+    /// Returns true if all stored prewarm hints of the shader have been created by the current
+    /// pipelines. Pipelines created later prewarm the shader when they are built.
+    fn is_shader_prewarmed(&self, shader: ShaderId) -> bool {
+        let hints = match self.prewarm_hints.get(&shader) {
+            Some(hints) => hints,
+            None => return true,
+        };
+        self.current_pipeline.iter().chain(self.debug_pipeline.iter()).all(|(pipeline, _)| {
+            pipeline.is_shader_prewarmed(shader, hints)
+        })
+    }
+
+    fn capture_next_frame(&mut self, path: PathBuf) {
+        self.pending_capture = Some(path);
+    }
+
+    fn get_present_timing_statistics(&self) -> Option<PresentTimingStatistics> {
+        self.current_swapchain.as_ref()?.get_present_timing_statistics()
+    }
+
+    fn set_debug_overlay(&mut self, enabled: bool) {
+        if self.debug_overlay.is_some() != enabled {
+            self.debug_overlay = if enabled {
+                Some((DebugOverlay::new(&self.emulator), StatisticsTracker::new()))
+            } else {
+                None
+            };
+            self.current_pipeline = None;
+            self.debug_pipeline = None;
+        }
+    }
+
+    fn set_transparent_output(&mut self, transparent: bool) {
+        if self.transparent_output != transparent {
+            self.transparent_output = transparent;
+            self.current_pipeline = None;
+            self.debug_pipeline = None;
+            self.current_swapchain = None;
+        }
+    }
+
+    fn set_preferred_color_spaces(&mut self, color_spaces: &[vk::ColorSpaceKHR]) {
+        if self.preferred_color_spaces.as_ref() != color_spaces {
+            self.preferred_color_spaces = color_spaces.into();
+            self.current_pipeline = None;
+            self.debug_pipeline = None;
+            self.current_swapchain = None;
         }
     }
 
@@ -140,10 +581,59 @@ impl RenderConfig {
         if self.debug_mode != mode {
             self.debug_mode = mode;
             self.debug_pipeline = None;
+            self.resize_source = None;
+        }
+    }
+
+    fn set_depth_mode(&mut self, depth_mode: DepthMode) {
+        if self.depth_mode != depth_mode {
+            self.depth_mode = depth_mode;
+            self.debug_pipeline = None;
+            self.resize_source = None;
+        }
+    }
+
+    fn set_object_ids(&mut self, object_ids: bool) {
+        if self.object_ids != object_ids {
+            self.object_ids = object_ids;
+            self.debug_pipeline = None;
+            self.resize_source = None;
+        }
+    }
+
+    fn set_msaa(&mut self, msaa: MsaaConfig) {
+        if self.msaa != msaa {
+            self.msaa = msaa;
+            self.debug_pipeline = None;
+            self.resize_source = None;
+        }
+    }
+
+    fn set_present_config(&mut self, config: PresentConfig) {
+        if self.present_config != config {
+            self.present_config = config;
+            self.current_pipeline = None;
+            self.debug_pipeline = None;
         }
     }
 
     fn try_start_frame(&mut self, renderer: &EmulatorRenderer, size: Vec2u32) -> Option<PassRecorder> {
+        match self.backpressure {
+            BackpressurePolicy::Unbounded => {}
+            BackpressurePolicy::Block { max_pending_passes } => {
+                let _span = b4d_span!("backpressure_wait");
+                if !renderer.wait_pending_passes(max_pending_passes as u64, BACKPRESSURE_BLOCK_TIMEOUT) {
+                    log::warn!("Gpu did not catch up within {:?}. Starting frame with {} pending passes", BACKPRESSURE_BLOCK_TIMEOUT, renderer.get_pending_pass_count());
+                }
+            }
+            BackpressurePolicy::DropFrames { max_pending_passes } => {
+                if renderer.get_pending_pass_count() >= max_pending_passes as u64 {
+                    log::debug!("Dropping frame because {} passes are pending", renderer.get_pending_pass_count());
+                    return None;
+                }
+            }
+        }
+
         let mut force_rebuild = false;
 
         // This if block only exists because of wayland
@@ -161,6 +651,13 @@ impl RenderConfig {
             self.debug_pipeline = None;
         }
 
+        if let Some((overlay, tracker)) = &mut self.debug_overlay {
+            let present_timing = self.current_swapchain.as_ref().and_then(|swapchain| swapchain.get_present_timing_statistics());
+            if let Some(text) = tracker.on_frame(renderer, present_timing) {
+                overlay.set_text(text);
+            }
+        }
+
         let (pipeline, output) = self.prepare_pipeline(size);
 
         let (output, suboptimal) = match output.next_image() {
@@ -175,6 +672,12 @@ impl RenderConfig {
 
         let mut recorder = renderer.start_pass(pipeline.clone());
         recorder.use_output(output);
+        if let Some(path) = self.pending_capture.take() {
+            recorder.start_capture(path);
+        }
+        if let Some(stream) = &mut self.frame_stream {
+            stream.on_pass(&mut recorder);
+        }
 
         if suboptimal {
             self.current_pipeline = None;
@@ -190,8 +693,43 @@ impl RenderConfig {
             if self.debug_pipeline.is_none() {
                 log::info!("No debug pipeline present. Rebuilding for size {:?}", output_size);
 
-                let pipeline = DebugPipeline::new(self.emulator.clone(), *debug_mode, output_size).unwrap();
-                let swapchain_output = SwapchainOutput::new(&self.device, pipeline.clone(), self.current_swapchain.as_ref().cloned().unwrap());
+                let debug_pipeline = match self.resize_source.take() {
+                    Some(pipeline) if pipeline.get_output().0 == output_size => Some(pipeline),
+                    Some(pipeline) => pipeline.resize(output_size).map_err(|err| {
+                        log::error!("Failed to resize debug pipeline {:?}", err);
+                    }).ok(),
+                    None if !DebugPipeline::is_supported(&self.device) => None,
+                    None => {
+                        let options = DebugPipelineOptions { msaa: self.msaa, depth_mode: self.depth_mode, object_ids: self.object_ids, ..DebugPipelineOptions::DEFAULT };
+                        match DebugPipeline::new_with_options(self.emulator.clone(), *debug_mode, output_size, options) {
+                            Ok(pipeline) => {
+                                pipeline.set_async_pipeline_creation(true);
+                                Some(pipeline)
+                            }
+                            Err(err) => {
+                                log::error!("Failed to create debug pipeline {:?}", err);
+                                None
+                            }
+                        }
+                    }
+                };
+                self.resize_source = debug_pipeline.clone();
+
+                let pipeline: Arc<dyn EmulatorPipeline> = match debug_pipeline {
+                    Some(pipeline) => pipeline,
+                    None => {
+                        log::warn!("Debug pipeline is unavailable. Using fallback pipeline");
+                        FallbackPipeline::new(self.emulator.clone(), output_size).unwrap_or_else(|err| {
+                            log::error!("Failed to create fallback pipeline {:?}", err);
+                            panic!()
+                        })
+                    }
+                };
+                let overlay = self.debug_overlay.as_ref().map(|(overlay, _)| overlay);
+                let swapchain_output = SwapchainOutput::new_with_overlay(&self.device, pipeline.clone(), self.current_swapchain.as_ref().cloned().unwrap(), overlay, &self.present_config);
+                for (shader, hints) in &self.prewarm_hints {
+                    pipeline.prewarm_shader(*shader, hints);
+                }
 
                 self.debug_pipeline = Some((pipeline, swapchain_output));
             }
@@ -213,14 +751,25 @@ impl RenderConfig {
         }
         self.last_rebuild = Instant::now();
 
+        let formats = self.preferred_color_spaces.iter()
+            .chain(std::iter::once(&vk::ColorSpaceKHR::SRGB_NONLINEAR))
+            .flat_map(|color_space| Self::get_color_space_formats(*color_space).iter().map(|format| {
+                vk::SurfaceFormatKHR{ format: *format, color_space: *color_space }
+            }))
+            .collect();
+
+        let composite_alpha: Box<[_]> = if self.transparent_output {
+            Box::new([vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED, vk::CompositeAlphaFlagsKHR::POST_MULTIPLIED])
+        } else {
+            Box::new([vk::CompositeAlphaFlagsKHR::OPAQUE])
+        };
+
         let config = SwapchainConfig {
             allow_tearing: true, // We set this to true to unlock fps for testing
-            formats: Box::new([
-                vk::SurfaceFormatKHR{ format: vk::Format::R8G8B8A8_SRGB, color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR },
-                vk::SurfaceFormatKHR{ format: vk::Format::B8G8R8A8_SRGB, color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR },
-            ]),
+            formats,
             required_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
             optional_usage: vk::ImageUsageFlags::empty(),
+            composite_alpha,
             clipped: true
         };
 
@@ -236,6 +785,21 @@ impl RenderConfig {
             }
         }
     }
+
+    /// Returns the swapchain formats we can use for some color space.
+    ///
+    /// The nonlinear color spaces use the sRGB transfer function so we only need the SRGB formats
+    /// for those. Any gamut conversion is done by the blit pass.
+    fn get_color_space_formats(color_space: vk::ColorSpaceKHR) -> &'static [vk::Format] {
+        match color_space {
+            vk::ColorSpaceKHR::SRGB_NONLINEAR | vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT => &[vk::Format::R8G8B8A8_SRGB, vk::Format::B8G8R8A8_SRGB],
+            vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT => &[vk::Format::R16G16B16A16_SFLOAT],
+            _ => {
+                log::warn!("Unsupported swapchain color space {:?}", color_space);
+                &[]
+            }
+        }
+    }
 }
 
 pub struct B4DVertexFormat {
@@ -4,10 +4,15 @@ use std::sync::Arc;
 use ash::vk;
 use crate::b4d::Blaze4D;
 use crate::glfw_surface::GLFWSurfaceProvider;
-use crate::prelude::{Mat4f32, UUID, Vec2f32, Vec2u32, Vec3f32, Vec4f32};
-
-use crate::renderer::emulator::{MeshData, PassRecorder, ImmediateMeshId, GlobalMesh, ImageData, GlobalImage, SamplerInfo};
-use crate::renderer::emulator::debug_pipeline::DebugPipelineMode;
+use crate::prelude::{Mat4f32, UUID, Vec2f32, Vec2u32, Vec3f32, Vec3i32, Vec4f32};
+
+use crate::renderer::emulator::{MeshData, PassId, PassRecorder, ImmediateMeshId, GlobalMesh, ImageData, GlobalImage, SamplerInfo};
+use crate::renderer::emulator::debug_pipeline::{DebugPipelineMode, MsaaConfig};
+use crate::renderer::emulator::pipeline::{BlendState, ClearConfig, DepthTest};
+use crate::renderer::emulator::projection::DepthMode;
+use crate::renderer::emulator::readback::PickReadback;
+use crate::renderer::emulator::lod::{LodLevel, LodMesh};
+use crate::renderer::emulator::visibility::{FaceConnectivity, SectionVisibilityGraph};
 use crate::renderer::emulator::mc_shaders::{McUniform, McUniformData, ShaderId, VertexFormat, VertexFormatEntry};
 use crate::util::format::Format;
 use crate::vk::objects::surface::SurfaceProvider;
@@ -70,6 +75,34 @@ struct CPipelineConfiguration {
     blend_alpha_op: i32,
     blend_alpha_src_factor: i32,
     blend_alpha_dst_factor: i32,
+    color_write_mask: u32,
+}
+
+impl CPipelineConfiguration {
+    fn to_blend_state(&self) -> Option<BlendState> {
+        if self.blend_enable != 0 {
+            Some(BlendState {
+                color_op: vk::BlendOp::from_raw(self.blend_color_op),
+                color_src_factor: vk::BlendFactor::from_raw(self.blend_color_src_factor),
+                color_dst_factor: vk::BlendFactor::from_raw(self.blend_color_dst_factor),
+                alpha_op: vk::BlendOp::from_raw(self.blend_alpha_op),
+                alpha_src_factor: vk::BlendFactor::from_raw(self.blend_alpha_src_factor),
+                alpha_dst_factor: vk::BlendFactor::from_raw(self.blend_alpha_dst_factor),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Only the equal compare op used by the glint render types needs to be emulated. All other
+    /// compare ops use the depth mode of the pipeline.
+    fn to_depth_test(&self) -> DepthTest {
+        if self.depth_test_enable != 0 && self.depth_compare_op == vk::CompareOp::EQUAL.as_raw() {
+            DepthTest::Equal
+        } else {
+            DepthTest::Default
+        }
+    }
 }
 
 #[repr(C)]
@@ -91,14 +124,20 @@ impl CMeshData {
             log::error!("Vertex data pointer is null");
             panic!();
         }
-        if self.index_data_ptr.is_null() {
-            log::error!("Index data pointer is null");
-            panic!();
-        }
+        // A null index pointer with no index data selects the shared quad indices
+        let index_data: &[u8] = if self.index_data_ptr.is_null() {
+            if self.index_data_len != 0 {
+                log::error!("Index data pointer is null");
+                panic!();
+            }
+            &[]
+        } else {
+            std::slice::from_raw_parts(self.index_data_ptr, self.index_data_len as usize)
+        };
 
         MeshData {
             vertex_data: std::slice::from_raw_parts(self.vertex_data_ptr, self.vertex_data_len as usize),
-            index_data: std::slice::from_raw_parts(self.index_data_ptr, self.index_data_len as usize),
+            index_data,
             vertex_stride: self.vertex_stride,
             index_count: self.index_count,
             index_type: vk::IndexType::from_raw(self.index_type),
@@ -187,7 +226,9 @@ impl CVertexFormat {
             color,
             uv0,
             uv1,
-            uv2
+            uv2,
+            joint_indices: None,
+            joint_weights: None,
         }
     }
 }
@@ -323,7 +364,7 @@ unsafe extern "C" fn b4d_get_native_metadata() -> *const NativeMetadata {
 /// This function will take ownership of the provided surface and vertex format set builder. The
 /// pointers must not be used again afterwards.
 #[no_mangle]
-unsafe extern "C" fn b4d_init(surface: *mut GLFWSurfaceProvider, enable_validation: u32) -> *mut Blaze4D {
+unsafe extern "C" fn b4d_init(surface: *mut GLFWSurfaceProvider, enable_validation: u32, enable_shader_printf: u32) -> *mut Blaze4D {
     catch_unwind(|| {
         if surface.is_null() {
             log::error!("Passed null surface to b4d_init");
@@ -333,8 +374,9 @@ unsafe extern "C" fn b4d_init(surface: *mut GLFWSurfaceProvider, enable_validati
         let surface_provider: Box<dyn SurfaceProvider> = Box::from_raw(surface);
 
         let enable_validation = enable_validation != 0;
+        let enable_shader_printf = enable_shader_printf != 0;
 
-        Box::leak(Box::new(Blaze4D::new(surface_provider, enable_validation)))
+        Box::leak(Box::new(Blaze4D::new(surface_provider, enable_validation, enable_shader_printf)))
     }).unwrap_or_else(|_| {
         log::error!("panic in b4d_init");
         exit(1);
@@ -371,6 +413,94 @@ unsafe extern "C" fn b4d_set_debug_mode(b4d: *const Blaze4D, mode: CDebugMode) {
     })
 }
 
+#[no_mangle]
+unsafe extern "C" fn b4d_set_reverse_z(b4d: *const Blaze4D, enable: u32) {
+    catch_unwind(|| {
+        let b4d = b4d.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null b4d to b4d_set_reverse_z");
+            exit(1);
+        });
+
+        b4d.set_depth_mode(if enable != 0 { DepthMode::Reversed } else { DepthMode::Standard });
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_set_reverse_z");
+        exit(1);
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn b4d_set_object_ids(b4d: *const Blaze4D, enable: u32) {
+    catch_unwind(|| {
+        let b4d = b4d.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null b4d to b4d_set_object_ids");
+            exit(1);
+        });
+
+        b4d.set_object_ids(enable != 0);
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_set_object_ids");
+        exit(1);
+    })
+}
+
+/// Configures the multisample count of the render targets. Must be 1, 2, 4 or 8. Any other value
+/// disables multisampling.
+#[no_mangle]
+unsafe extern "C" fn b4d_set_msaa(b4d: *const Blaze4D, samples: u32) {
+    catch_unwind(|| {
+        let b4d = b4d.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null b4d to b4d_set_msaa");
+            exit(1);
+        });
+
+        let samples = match samples {
+            1 => vk::SampleCountFlags::TYPE_1,
+            2 => vk::SampleCountFlags::TYPE_2,
+            4 => vk::SampleCountFlags::TYPE_4,
+            8 => vk::SampleCountFlags::TYPE_8,
+            _ => {
+                log::warn!("Invalid sample count {} passed to b4d_set_msaa. Disabling multisampling", samples);
+                vk::SampleCountFlags::TYPE_1
+            }
+        };
+
+        b4d.set_msaa(MsaaConfig { samples, ..MsaaConfig::NONE });
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_set_msaa");
+        exit(1);
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn b4d_trigger_capture(b4d: *const Blaze4D, n_frames: u32) {
+    catch_unwind(|| {
+        let b4d = b4d.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null b4d to b4d_trigger_capture");
+            exit(1);
+        });
+
+        b4d.trigger_capture(n_frames);
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_trigger_capture");
+        exit(1);
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn b4d_set_debug_overlay(b4d: *const Blaze4D, enable: u32) {
+    catch_unwind(|| {
+        let b4d = b4d.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null b4d to b4d_set_debug_overlay");
+            exit(1);
+        });
+
+        b4d.set_debug_overlay(enable != 0);
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_set_debug_overlay");
+        exit(1);
+    })
+}
+
 #[no_mangle]
 unsafe extern "C" fn b4d_create_global_mesh(b4d: *const Blaze4D, data: *const CMeshData) -> *mut Arc<GlobalMesh> {
     catch_unwind(|| {
@@ -406,6 +536,157 @@ unsafe extern "C" fn b4d_destroy_global_mesh(mesh: *mut Arc<GlobalMesh>) {
     })
 }
 
+/// Creates a lod mesh from `count` global meshes and their max distances. The lod mesh keeps its
+/// own references to the meshes so they may be destroyed afterwards.
+#[no_mangle]
+unsafe extern "C" fn b4d_create_lod_mesh(meshes: *const *const Arc<GlobalMesh>, max_distances: *const f32, count: u32) -> *mut LodMesh {
+    catch_unwind(|| {
+        if meshes.is_null() || max_distances.is_null() {
+            log::error!("Passed null levels to b4d_create_lod_mesh");
+            exit(1);
+        }
+
+        let meshes = std::slice::from_raw_parts(meshes, count as usize);
+        let max_distances = std::slice::from_raw_parts(max_distances, count as usize);
+        let levels = meshes.iter().zip(max_distances).map(|(mesh, max_distance)| {
+            let mesh = mesh.as_ref().unwrap_or_else(|| {
+                log::error!("Passed null mesh to b4d_create_lod_mesh");
+                exit(1);
+            });
+            LodLevel {
+                mesh: mesh.clone(),
+                max_distance: *max_distance,
+            }
+        }).collect();
+
+        Box::leak(Box::new(LodMesh::new(levels)))
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_create_lod_mesh");
+        exit(1);
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn b4d_destroy_lod_mesh(mesh: *mut LodMesh) {
+    catch_unwind(|| {
+        if mesh.is_null() {
+            log::error!("Passed null mesh to b4d_destroy_lod_mesh");
+            exit(1);
+        }
+
+        drop(Box::from_raw(mesh));
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_destroy_lod_mesh");
+        exit(1);
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn b4d_create_visibility_graph() -> *mut SectionVisibilityGraph {
+    catch_unwind(|| {
+        Box::leak(Box::new(SectionVisibilityGraph::new())) as *mut SectionVisibilityGraph
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_create_visibility_graph");
+        exit(1);
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn b4d_destroy_visibility_graph(graph: *mut SectionVisibilityGraph) {
+    catch_unwind(|| {
+        if graph.is_null() {
+            log::error!("Passed null graph to b4d_destroy_visibility_graph");
+            exit(1);
+        }
+
+        drop(Box::from_raw(graph));
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_destroy_visibility_graph");
+        exit(1);
+    })
+}
+
+/// Sets the face connectivity of a section. The connectivity uses the bit layout of vanilla's
+/// `VisibilitySet`, see [`FaceConnectivity`].
+#[no_mangle]
+unsafe extern "C" fn b4d_visibility_graph_set_section(graph: *mut SectionVisibilityGraph, x: i32, y: i32, z: i32, connectivity: u64) {
+    catch_unwind(|| {
+        let graph = graph.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null graph to b4d_visibility_graph_set_section");
+            exit(1);
+        });
+
+        graph.set_section(Vec3i32::new(x, y, z), FaceConnectivity::from_raw(connectivity));
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_visibility_graph_set_section");
+        exit(1);
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn b4d_visibility_graph_remove_section(graph: *mut SectionVisibilityGraph, x: i32, y: i32, z: i32) {
+    catch_unwind(|| {
+        let graph = graph.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null graph to b4d_visibility_graph_remove_section");
+            exit(1);
+        });
+
+        graph.remove_section(&Vec3i32::new(x, y, z));
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_visibility_graph_remove_section");
+        exit(1);
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn b4d_visibility_graph_get_section_count(graph: *const SectionVisibilityGraph) -> u32 {
+    catch_unwind(|| {
+        let graph = graph.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null graph to b4d_visibility_graph_get_section_count");
+            exit(1);
+        });
+
+        graph.get_section_count() as u32
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_visibility_graph_get_section_count");
+        exit(1);
+    })
+}
+
+/// Calls [`SectionVisibilityGraph::traverse`]. The view projection matrix is passed as 16 floats
+/// in column major order. The coordinates of the visible sections are written as x, y, z triples
+/// into `sections` which must have space for `capacity` sections. Returns the number of visible
+/// sections which may exceed the capacity. Since every section is visible at most once the section
+/// count of the graph is always a sufficient capacity.
+#[no_mangle]
+unsafe extern "C" fn b4d_visibility_graph_traverse(graph: *const SectionVisibilityGraph, camera_x: f32, camera_y: f32, camera_z: f32, view_projection: *const f32, max_distance: u32, sections: *mut i32, capacity: u32) -> u32 {
+    catch_unwind(|| {
+        let graph = graph.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null graph to b4d_visibility_graph_traverse");
+            exit(1);
+        });
+        if view_projection.is_null() || (sections.is_null() && capacity != 0) {
+            log::error!("Passed null pointer to b4d_visibility_graph_traverse");
+            exit(1);
+        }
+
+        let view_projection = Mat4f32::from_column_slice(std::slice::from_raw_parts(view_projection, 16));
+        let visible = graph.traverse(&Vec3f32::new(camera_x, camera_y, camera_z), &view_projection, max_distance);
+
+        if capacity != 0 {
+            let sections = std::slice::from_raw_parts_mut(sections, (capacity as usize) * 3);
+            for (dst, section) in sections.chunks_exact_mut(3).zip(visible.iter()) {
+                dst.copy_from_slice(section.as_slice());
+            }
+        }
+
+        visible.len() as u32
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_visibility_graph_traverse");
+        exit(1);
+    })
+}
+
 #[no_mangle]
 unsafe extern "C" fn b4d_create_global_image(b4d: *const Blaze4D, width: u32, height: u32, format: i32) -> *mut Arc<GlobalImage> {
     catch_unwind(|| {
@@ -424,6 +705,32 @@ unsafe extern "C" fn b4d_create_global_image(b4d: *const Blaze4D, width: u32, he
     })
 }
 
+#[no_mangle]
+unsafe extern "C" fn b4d_create_global_image_ktx2(b4d: *const Blaze4D, data: *const u8, data_len: u64) -> *mut Arc<GlobalImage> {
+    catch_unwind(|| {
+        let b4d = b4d.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null b4d to b4d_create_global_image_ktx2");
+            exit(1);
+        });
+        if data.is_null() {
+            log::error!("Passed null data to b4d_create_global_image_ktx2");
+            exit(1);
+        }
+
+        let data = std::slice::from_raw_parts(data, data_len as usize);
+        match b4d.create_global_image_ktx2(data) {
+            Ok(image) => Box::leak(Box::new(image)),
+            Err(err) => {
+                log::warn!("Failed to load KTX2 texture {:?}", err);
+                std::ptr::null_mut()
+            }
+        }
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_create_global_image_ktx2");
+        exit(1);
+    })
+}
+
 #[no_mangle]
 unsafe extern "C" fn b4d_update_global_image(image: *mut Arc<GlobalImage>, writes: *const CImageData, count: u32) {
     catch_unwind(|| {
@@ -518,6 +825,41 @@ unsafe extern "C" fn b4d_start_frame(b4d: *mut Blaze4D, window_width: u32, windo
     })
 }
 
+// frame_id, user_data
+type PfnFrameComplete = unsafe extern "C" fn(u64, u64);
+
+#[no_mangle]
+unsafe extern "C" fn b4d_pass_get_id(pass: *const PassRecorder) -> u64 {
+    catch_unwind(|| {
+        let pass = pass.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null pass to b4d_pass_get_id");
+            exit(1);
+        });
+
+        pass.get_pass_id().get_raw()
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_pass_get_id");
+        exit(1);
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn b4d_on_frame_complete(b4d: *const Blaze4D, frame_id: u64, pfn: PfnFrameComplete, user_data: u64) {
+    catch_unwind(|| {
+        let b4d = b4d.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null b4d to b4d_on_frame_complete");
+            exit(1);
+        });
+
+        b4d.on_frame_complete(PassId::from_raw(frame_id), move || {
+            pfn(frame_id, user_data);
+        });
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_on_frame_complete");
+        exit(1);
+    })
+}
+
 #[no_mangle]
 unsafe extern "C" fn b4d_pass_update_uniform(pass: *mut PassRecorder, data: *const CMcUniformData, shader_id: u64) {
     catch_unwind(|| {
@@ -566,6 +908,27 @@ unsafe extern "C" fn b4d_pass_update_texture(pass: *mut PassRecorder, index: u32
     })
 }
 
+#[no_mangle]
+unsafe extern "C" fn b4d_pass_set_pipeline_configuration(pass: *mut PassRecorder, config: *const CPipelineConfiguration) {
+    catch_unwind(|| {
+        let pass = pass.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null pass to b4d_pass_set_pipeline_configuration");
+            exit(1);
+        });
+        let config = config.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null config to b4d_pass_set_pipeline_configuration");
+            exit(1);
+        });
+
+        pass.set_blend_state(config.to_blend_state());
+        pass.set_depth_test(config.to_depth_test());
+        pass.set_color_write_mask(vk::ColorComponentFlags::from_raw(config.color_write_mask));
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_pass_set_pipeline_configuration");
+        exit(1);
+    })
+}
+
 #[no_mangle]
 unsafe extern "C" fn b4d_pass_draw_global(pass: *mut PassRecorder, mesh: *const Arc<GlobalMesh>, shader_id: u64, depth_write_enable: u32) {
     catch_unwind(|| {
@@ -588,6 +951,28 @@ unsafe extern "C" fn b4d_pass_draw_global(pass: *mut PassRecorder, mesh: *const
     })
 }
 
+#[no_mangle]
+unsafe extern "C" fn b4d_pass_draw_global_lod(pass: *mut PassRecorder, mesh: *const LodMesh, distance: f32, shader_id: u64, depth_write_enable: u32) {
+    catch_unwind(|| {
+        let pass = pass.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null pass to b4d_pass_draw_global_lod");
+            exit(1);
+        });
+        let mesh = mesh.as_ref().unwrap_or_else(|| {
+            log::error!("Passed null mesh to b4d_pass_draw_global_lod");
+            exit(1);
+        });
+        let shader_id = ShaderId::from_uuid(UUID::from_raw(shader_id));
+
+        let depth_write_enable = if depth_write_enable == 1 { true } else { false };
+
+        pass.draw_global_lod(mesh, distance, shader_id, depth_write_enable);
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_pass_draw_global_lod");
+        exit(1);
+    })
+}
+
 #[no_mangle]
 unsafe extern "C" fn b4d_pass_upload_immediate(pass: *mut PassRecorder, data: *const CMeshData) -> u32 {
     catch_unwind(|| {
@@ -627,6 +1012,141 @@ unsafe extern "C" fn b4d_pass_draw_immediate(pass: *mut PassRecorder, id: u32, s
     })
 }
 
+#[no_mangle]
+unsafe extern "C" fn b4d_pass_flush(pass: *mut PassRecorder) {
+    catch_unwind(|| {
+        let pass = pass.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null pass to b4d_pass_flush");
+            exit(1);
+        });
+
+        pass.flush();
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_pass_flush");
+        exit(1);
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn b4d_pass_set_object_id(pass: *mut PassRecorder, object_id: u32) {
+    catch_unwind(|| {
+        let pass = pass.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null pass to b4d_pass_set_object_id");
+            exit(1);
+        });
+
+        pass.set_object_id(object_id);
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_pass_set_object_id");
+        exit(1);
+    })
+}
+
+/// If `enable` is 0 the scissor is disabled and the rectangle is ignored. See
+/// [`DrawRecorder::set_scissor`].
+#[no_mangle]
+unsafe extern "C" fn b4d_pass_set_scissor(pass: *mut PassRecorder, enable: u32, x: i32, y: i32, width: u32, height: u32) {
+    catch_unwind(|| {
+        let pass = pass.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null pass to b4d_pass_set_scissor");
+            exit(1);
+        });
+
+        let scissor = if enable != 0 {
+            Some(vk::Rect2D {
+                offset: vk::Offset2D { x, y },
+                extent: vk::Extent2D { width, height },
+            })
+        } else {
+            None
+        };
+        pass.set_scissor(scissor);
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_pass_set_scissor");
+        exit(1);
+    })
+}
+
+/// If `clear_color` is 0 the color attachment is not cleared and the color is ignored. See
+/// [`PassRecorder::set_clear_config`].
+#[no_mangle]
+unsafe extern "C" fn b4d_pass_set_clear_config(pass: *mut PassRecorder, clear_color: u32, r: f32, g: f32, b: f32, a: f32, clear_depth: u32) {
+    catch_unwind(|| {
+        let pass = pass.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null pass to b4d_pass_set_clear_config");
+            exit(1);
+        });
+
+        let color = if clear_color != 0 { Some([r, g, b, a]) } else { None };
+        pass.set_clear_config(ClearConfig::new(color, clear_depth != 0));
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_pass_set_clear_config");
+        exit(1);
+    })
+}
+
+/// The returned pick must be destroyed with [`b4d_destroy_pick`] even after it has completed.
+#[no_mangle]
+unsafe extern "C" fn b4d_pass_pick(pass: *mut PassRecorder, x: u32, y: u32) -> *mut Option<PickReadback> {
+    catch_unwind(|| {
+        let pass = pass.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null pass to b4d_pass_pick");
+            exit(1);
+        });
+
+        Box::leak(Box::new(Some(pass.pick(x, y))))
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_pass_pick");
+        exit(1);
+    })
+}
+
+/// Returns 0 if the pick has not completed yet, 1 if it completed and the object id has been
+/// written into `object_id` or 2 if it completed without a result. A pick must not be polled
+/// again after it has completed.
+#[no_mangle]
+unsafe extern "C" fn b4d_pick_poll(pick: *mut Option<PickReadback>, object_id: *mut u32) -> u32 {
+    catch_unwind(|| {
+        let pick = pick.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null pick to b4d_pick_poll");
+            exit(1);
+        });
+        let object_id = object_id.as_mut().unwrap_or_else(|| {
+            log::error!("Passed null object_id to b4d_pick_poll");
+            exit(1);
+        });
+
+        match pick.take().map(PickReadback::try_get) {
+            Some(Err(pending)) => {
+                *pick = Some(pending);
+                0
+            }
+            Some(Ok(Some(id))) => {
+                *object_id = id;
+                1
+            }
+            Some(Ok(None)) | None => 2,
+        }
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_pick_poll");
+        exit(1);
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn b4d_destroy_pick(pick: *mut Option<PickReadback>) {
+    catch_unwind(|| {
+        if pick.is_null() {
+            log::error!("Passed null to b4d_destroy_pick");
+            exit(1);
+        }
+        drop(Box::from_raw(pick));
+    }).unwrap_or_else(|_| {
+        log::error!("panic in b4d_destroy_pick");
+        exit(1);
+    })
+}
+
 #[no_mangle]
 unsafe extern "C" fn b4d_end_frame(recorder: *mut PassRecorder) {
     catch_unwind(|| {
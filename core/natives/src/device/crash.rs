@@ -0,0 +1,202 @@
+//! Crash reports generated when the device is lost.
+//!
+//! If VK_EXT_device_fault is enabled the fault information reported by the driver is included.
+//! If VK_NV_device_diagnostic_checkpoints is enabled the checkpoints last reached by the queue are
+//! included. Users of the device can attach additional context like the last completed pass.
+
+use std::ffi::CStr;
+use std::io::Write;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ash::vk;
+
+use crate::device::device::Queue;
+
+use crate::prelude::*;
+
+#[derive(Clone, Debug)]
+pub struct DeviceFaultAddress {
+    pub address_type: vk::DeviceFaultAddressTypeEXT,
+    pub reported_address: vk::DeviceAddress,
+    pub address_precision: vk::DeviceSize,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceFaultVendorInfo {
+    pub description: String,
+    pub vendor_fault_code: u64,
+    pub vendor_fault_data: u64,
+}
+
+/// The fault information reported by VK_EXT_device_fault.
+#[derive(Clone, Debug)]
+pub struct DeviceFault {
+    pub description: String,
+    pub addresses: Vec<DeviceFaultAddress>,
+    pub vendor_infos: Vec<DeviceFaultVendorInfo>,
+}
+
+impl DeviceFault {
+    /// Queries the fault information of a lost device.
+    ///
+    /// Returns [`None`] if VK_EXT_device_fault is not enabled or the query failed.
+    pub fn query(device: &DeviceContext) -> Option<Self> {
+        let device_fault = device.device_fault_ext()?;
+        let handle = device.vk().handle();
+
+        let mut counts = vk::DeviceFaultCountsEXT::default();
+        let result = unsafe {
+            (device_fault.get_device_fault_info_ext)(handle, &mut counts, std::ptr::null_mut())
+        };
+        if result != vk::Result::SUCCESS {
+            log::warn!("Failed to query device fault counts {:?}", result);
+            return None;
+        }
+
+        let mut addresses = vec![vk::DeviceFaultAddressInfoEXT::default(); counts.address_info_count as usize];
+        let mut vendor_infos = vec![vk::DeviceFaultVendorInfoEXT::default(); counts.vendor_info_count as usize];
+        counts.vendor_binary_size = 0;
+
+        let mut info = vk::DeviceFaultInfoEXT::default();
+        info.p_address_infos = addresses.as_mut_ptr();
+        info.p_vendor_infos = vendor_infos.as_mut_ptr();
+
+        let result = unsafe {
+            (device_fault.get_device_fault_info_ext)(handle, &mut counts, &mut info)
+        };
+        if result != vk::Result::SUCCESS && result != vk::Result::INCOMPLETE {
+            log::warn!("Failed to query device fault info {:?}", result);
+            return None;
+        }
+
+        addresses.truncate(counts.address_info_count as usize);
+        vendor_infos.truncate(counts.vendor_info_count as usize);
+
+        Some(Self {
+            description: c_str_to_string(&info.description),
+            addresses: addresses.iter().map(|address| DeviceFaultAddress {
+                address_type: address.address_type,
+                reported_address: address.reported_address,
+                address_precision: address.address_precision,
+            }).collect(),
+            vendor_infos: vendor_infos.iter().map(|vendor_info| DeviceFaultVendorInfo {
+                description: c_str_to_string(&vendor_info.description),
+                vendor_fault_code: vendor_info.vendor_fault_code,
+                vendor_fault_data: vendor_info.vendor_fault_data,
+            }).collect(),
+        })
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        let addresses: Vec<_> = self.addresses.iter().map(|address| json::object! {
+            "type": format!("{:?}", address.address_type),
+            "address": format!("{:#018x}", address.reported_address),
+            "precision": address.address_precision,
+        }).collect();
+
+        let vendor_infos: Vec<_> = self.vendor_infos.iter().map(|vendor_info| json::object! {
+            "description": vendor_info.description.as_str(),
+            "code": format!("{:#x}", vendor_info.vendor_fault_code),
+            "data": format!("{:#x}", vendor_info.vendor_fault_data),
+        }).collect();
+
+        json::object! {
+            "description": self.description.as_str(),
+            "addresses": addresses,
+            "vendor_infos": vendor_infos,
+        }
+    }
+}
+
+/// A checkpoint reached by a queue as reported by VK_NV_device_diagnostic_checkpoints.
+#[derive(Copy, Clone, Debug)]
+pub struct Checkpoint {
+    pub stage: vk::PipelineStageFlags,
+    pub marker: u64,
+}
+
+impl Checkpoint {
+    /// Queries the checkpoints last reached by the queue.
+    pub fn query(queue: &Queue) -> Vec<Self> {
+        queue.get_checkpoint_data().iter().map(|data| Checkpoint {
+            stage: data.stage,
+            marker: data.p_checkpoint_marker as usize as u64,
+        }).collect()
+    }
+}
+
+/// A structured report describing a device loss.
+pub struct CrashReport {
+    device_name: String,
+    message: String,
+    fault: Option<DeviceFault>,
+    checkpoints: Vec<Checkpoint>,
+    context: json::JsonValue,
+}
+
+impl CrashReport {
+    /// Collects all available diagnostics of a lost device.
+    pub fn collect(device: &DeviceContext, queue: &Queue, message: String) -> Self {
+        let properties = unsafe {
+            device.get_instance().vk().get_physical_device_properties(device.get_functions().physical_device)
+        };
+
+        Self {
+            device_name: c_str_to_string(&properties.device_name),
+            message,
+            fault: DeviceFault::query(device),
+            checkpoints: Checkpoint::query(queue),
+            context: json::JsonValue::new_object(),
+        }
+    }
+
+    pub fn get_fault(&self) -> Option<&DeviceFault> {
+        self.fault.as_ref()
+    }
+
+    pub fn get_checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Attaches additional context to the report.
+    pub fn add_context<T: Into<json::JsonValue>>(&mut self, key: &str, value: T) {
+        self.context[key] = value.into();
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        let checkpoints: Vec<_> = self.checkpoints.iter().map(|checkpoint| json::object! {
+            "stage": format!("{:?}", checkpoint.stage),
+            "marker": checkpoint.marker,
+        }).collect();
+
+        json::object! {
+            "device": self.device_name.as_str(),
+            "message": self.message.as_str(),
+            "fault": self.fault.as_ref().map(DeviceFault::to_json),
+            "checkpoints": checkpoints,
+            "context": self.context.clone(),
+        }
+    }
+
+    /// Writes the report as a json file into the specified directory.
+    ///
+    /// Returns the path of the written file.
+    pub fn write_to_dir(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+        let path = dir.join(format!("b4d-crash-{}.json", timestamp));
+
+        let mut file = std::fs::File::create(&path)?;
+        file.write_all(self.to_json().pretty(4).as_bytes())?;
+
+        Ok(path)
+    }
+}
+
+fn c_str_to_string(str: &[c_char]) -> String {
+    if !str.contains(&0) {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(str.as_ptr()) }.to_string_lossy().into_owned()
+}
@@ -1,7 +1,7 @@
 use core::panic::{UnwindSafe, RefUnwindSafe};
 
 use std::cmp::Ordering;
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use ash::prelude::VkResult;
 
 use ash::vk;
@@ -9,6 +9,8 @@ use ash::vk;
 use crate::allocator::Allocator;
 use crate::device::device_utils::DeviceUtils;
 use crate::instance::instance::InstanceContext;
+use crate::objects::image_layout::ImageLayoutTracker;
+use crate::objects::ownership::ResourceOwnershipTracker;
 
 use crate::prelude::*;
 
@@ -21,6 +23,12 @@ pub struct DeviceFunctions {
     pub push_descriptor_khr: ash::extensions::khr::PushDescriptor,
     pub swapchain_khr: Option<ash::extensions::khr::Swapchain>,
     pub maintenance_4_khr: Option<ash::extensions::khr::Maintenance4>,
+    pub device_fault_ext: Option<vk::ExtDeviceFaultFn>,
+    pub buffer_device_address_khr: Option<ash::extensions::khr::BufferDeviceAddress>,
+    pub diagnostic_checkpoints_nv: Option<ash::extensions::nv::DeviceDiagnosticCheckpoints>,
+    pub display_timing_google: Option<vk::GoogleDisplayTimingFn>,
+    pub memory_decompression_nv: Option<vk::NvMemoryDecompressionFn>,
+    pub draw_indirect_count_khr: Option<ash::extensions::khr::DrawIndirectCount>,
 }
 
 impl Drop for DeviceFunctions {
@@ -36,9 +44,13 @@ pub struct DeviceContext {
     functions: Arc<DeviceFunctions>,
     main_queue: Arc<Queue>,
     async_compute_queue: Option<Arc<Queue>>,
-    async_transfer_queue: Option<Arc<Queue>>,
+    async_transfer_queues: Box<[Arc<Queue>]>,
     allocator: Arc<Allocator>,
     utils: Arc<DeviceUtils>,
+    image_layouts: ImageLayoutTracker,
+    resource_ownership: ResourceOwnershipTracker,
+    enabled_features: vk::PhysicalDeviceFeatures,
+    submissions: RwLock<()>,
 }
 
 impl DeviceContext {
@@ -46,7 +58,8 @@ impl DeviceContext {
         functions: Arc<DeviceFunctions>,
         main_queue: Arc<Queue>,
         async_compute_queue: Option<Arc<Queue>>,
-        async_transfer_queue: Option<Arc<Queue>>,
+        async_transfer_queues: Box<[Arc<Queue>]>,
+        enabled_features: vk::PhysicalDeviceFeatures,
     ) -> Arc<Self> {
         let allocator = Arc::new(Allocator::new(functions.clone()).unwrap());
         let utils = DeviceUtils::new(functions.clone(), allocator.clone());
@@ -56,9 +69,13 @@ impl DeviceContext {
             functions,
             main_queue,
             async_compute_queue,
-            async_transfer_queue,
+            async_transfer_queues,
             allocator,
-            utils
+            utils,
+            image_layouts: ImageLayoutTracker::new(),
+            resource_ownership: ResourceOwnershipTracker::new(),
+            enabled_features,
+            submissions: RwLock::new(()),
         })
     }
 
@@ -102,6 +119,45 @@ impl DeviceContext {
         self.functions.maintenance_4_khr.as_ref()
     }
 
+    pub fn device_fault_ext(&self) -> Option<&vk::ExtDeviceFaultFn> {
+        self.functions.device_fault_ext.as_ref()
+    }
+
+    pub fn buffer_device_address_khr(&self) -> Option<&ash::extensions::khr::BufferDeviceAddress> {
+        self.functions.buffer_device_address_khr.as_ref()
+    }
+
+    /// Returns the device address of a buffer. The buffer must have been created with
+    /// [`vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS`]. Returns [`None`] if the buffer device
+    /// address feature is not enabled.
+    pub fn get_buffer_device_address(&self, buffer: vk::Buffer) -> Option<vk::DeviceAddress> {
+        let info = vk::BufferDeviceAddressInfo::builder().buffer(buffer);
+        self.buffer_device_address_khr().map(|bda| unsafe {
+            bda.get_buffer_device_address(&info)
+        })
+    }
+
+    pub fn diagnostic_checkpoints_nv(&self) -> Option<&ash::extensions::nv::DeviceDiagnosticCheckpoints> {
+        self.functions.diagnostic_checkpoints_nv.as_ref()
+    }
+
+    pub fn display_timing_google(&self) -> Option<&vk::GoogleDisplayTimingFn> {
+        self.functions.display_timing_google.as_ref()
+    }
+
+    pub fn memory_decompression_nv(&self) -> Option<&vk::NvMemoryDecompressionFn> {
+        self.functions.memory_decompression_nv.as_ref()
+    }
+
+    pub fn draw_indirect_count_khr(&self) -> Option<&ash::extensions::khr::DrawIndirectCount> {
+        self.functions.draw_indirect_count_khr.as_ref()
+    }
+
+    /// Returns the optional core features which have been enabled on this device.
+    pub fn get_enabled_features(&self) -> &vk::PhysicalDeviceFeatures {
+        &self.enabled_features
+    }
+
     pub fn get_main_queue(&self) -> &Arc<Queue> {
         &self.main_queue
     }
@@ -110,8 +166,16 @@ impl DeviceContext {
         self.async_compute_queue.as_ref()
     }
 
+    /// Returns the first async transfer queue. See [`DeviceContext::get_async_transfer_queues`].
     pub fn get_async_transfer_queue(&self) -> Option<&Arc<Queue>> {
-        self.async_transfer_queue.as_ref()
+        self.async_transfer_queues.first()
+    }
+
+    /// Returns all queues of the async transfer queue family. Empty if the device has no transfer
+    /// only queue family. Up to [`MAX_ASYNC_TRANSFER_QUEUES`](super::init::MAX_ASYNC_TRANSFER_QUEUES)
+    /// queues are created.
+    pub fn get_async_transfer_queues(&self) -> &[Arc<Queue>] {
+        &self.async_transfer_queues
     }
 
     pub fn get_allocator(&self) -> &Arc<Allocator> {
@@ -121,6 +185,48 @@ impl DeviceContext {
     pub fn get_utils(&self) -> &Arc<DeviceUtils> {
         &self.utils
     }
+
+    /// Returns the layout tracker of all images which are used by more than one recording path.
+    pub fn get_image_layouts(&self) -> &ImageLayoutTracker {
+        &self.image_layouts
+    }
+
+    /// Returns the queue family ownership tracker of exclusive resources shared with custom submits.
+    pub fn get_resource_ownership(&self) -> &ResourceOwnershipTracker {
+        &self.resource_ownership
+    }
+
+    /// Returns a guard which worker threads hold while processing a task which may submit work to
+    /// any queue of this device. While the guard is held [`DeviceContext::pause_submissions`]
+    /// blocks.
+    pub(crate) fn lock_submissions(&self) -> RwLockReadGuard<'_, ()> {
+        self.submissions.read().unwrap()
+    }
+
+    /// Blocks until all workers have finished their current task and prevents them from picking
+    /// up new tasks until the returned guard is dropped.
+    ///
+    /// Used together with [`SubmissionPause::wait_all_idle`] to perform operations which require
+    /// the device to be idle, for example destroying a swapchain. The calling thread must not wait
+    /// for any worker progress while the guard is held.
+    pub fn pause_submissions(&self) -> SubmissionPause<'_> {
+        SubmissionPause {
+            device: self,
+            _guard: self.submissions.write().unwrap(),
+        }
+    }
+
+    /// Waits until the queue has finished all submitted work. Workers are paused while waiting so
+    /// they cannot submit new work to the queue.
+    pub fn wait_queue_idle(&self, queue: &Queue) -> VkResult<()> {
+        self.pause_submissions().wait_queue_idle(queue)
+    }
+
+    /// Waits until all queues of the device have finished all submitted work. Workers are paused
+    /// while waiting so they cannot submit new work.
+    pub fn wait_all_idle(&self) -> VkResult<()> {
+        self.pause_submissions().wait_all_idle()
+    }
 }
 
 impl PartialEq for DeviceContext {
@@ -146,6 +252,34 @@ impl Ord for DeviceContext {
 
 assert_impl_all!(DeviceContext: Send, Sync, UnwindSafe, RefUnwindSafe);
 
+/// Prevents workers from submitting new work while held. Created by
+/// [`DeviceContext::pause_submissions`].
+pub struct SubmissionPause<'a> {
+    device: &'a DeviceContext,
+    _guard: RwLockWriteGuard<'a, ()>,
+}
+
+impl<'a> SubmissionPause<'a> {
+    /// Waits until the queue has finished all submitted work.
+    pub fn wait_queue_idle(&self, queue: &Queue) -> VkResult<()> {
+        unsafe {
+            queue.wait_idle()
+        }
+    }
+
+    /// Waits until all queues of the device have finished all submitted work.
+    pub fn wait_all_idle(&self) -> VkResult<()> {
+        self.wait_queue_idle(&self.device.main_queue)?;
+        if let Some(queue) = &self.device.async_compute_queue {
+            self.wait_queue_idle(queue)?;
+        }
+        for queue in self.device.async_transfer_queues.iter() {
+            self.wait_queue_idle(queue)?;
+        }
+        Ok(())
+    }
+}
+
 pub struct Queue {
     functions: Arc<DeviceFunctions>,
     queue: Mutex<vk::Queue>,
@@ -197,7 +331,23 @@ impl Queue {
         self.functions.swapchain_khr.as_ref().unwrap().queue_present(*queue, present_info)
     }
 
-    pub fn lock_queue(&self) -> MutexGuard<vk::Queue> {
+    /// Returns the diagnostic checkpoints most recently reached by the queue.
+    ///
+    /// Returns an empty vec if VK_NV_device_diagnostic_checkpoints is not enabled.
+    pub fn get_checkpoint_data(&self) -> Vec<vk::CheckpointDataNV> {
+        if let Some(checkpoints) = self.functions.diagnostic_checkpoints_nv.as_ref() {
+            let queue = self.queue.lock().unwrap();
+            unsafe {
+                let mut data = vec![vk::CheckpointDataNV::default(); checkpoints.get_queue_checkpoint_data_len(*queue)];
+                checkpoints.get_queue_checkpoint_data(*queue, &mut data);
+                data
+            }
+        } else {
+            Vec::new()
+        }
+    }
+
+    pub fn lock_queue(&self) -> MutexGuard<'_, vk::Queue> {
         self.queue.lock().unwrap()
     }
 
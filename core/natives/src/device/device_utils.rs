@@ -7,6 +7,7 @@ use ash::vk;
 use bytemuck::cast_slice;
 use include_bytes_aligned::include_bytes_aligned;
 use crate::allocator::Allocator;
+use crate::device::layout_cache::LayoutCache;
 
 use crate::prelude::*;
 
@@ -21,13 +22,15 @@ pub fn create_shader_from_bytes(device: &DeviceFunctions, code: &[u8]) -> VkResu
 
 pub struct DeviceUtils {
     blit_utils: BlitUtils,
+    layout_cache: LayoutCache,
 }
 
 impl DeviceUtils {
     pub fn new(device: Arc<DeviceFunctions>, _: Arc<Allocator>) -> Arc<Self> {
         Arc::new_cyclic(|weak| {
             Self {
-                blit_utils: BlitUtils::new(weak.clone(), device)
+                blit_utils: BlitUtils::new(weak.clone(), device.clone()),
+                layout_cache: LayoutCache::new(device),
             }
         })
     }
@@ -35,6 +38,10 @@ impl DeviceUtils {
     pub fn blit_utils(&self) -> &BlitUtils {
         &self.blit_utils
     }
+
+    pub fn layout_cache(&self) -> &LayoutCache {
+        &self.layout_cache
+    }
 }
 
 pub struct BlitUtils {
@@ -66,17 +73,85 @@ impl BlitUtils {
         }
     }
 
-    pub fn create_blit_pass(&self, dst_format: vk::Format, load_op: vk::AttachmentLoadOp, initial_layout: vk::ImageLayout, final_layout: vk::ImageLayout) -> BlitPass {
+    /// Creates a blit pass writing to images of the specified format.
+    ///
+    /// The source image is assumed to contain linear sRGB values. If the destination color space
+    /// uses different primaries the blit will apply the necessary gamut conversion.
+    ///
+    /// If `premultiply_alpha` is true the color channels are multiplied by the alpha channel during
+    /// the blit. This is necessary to present to surfaces using pre multiplied composite alpha.
+    pub fn create_blit_pass(&self, dst_format: vk::Format, dst_color_space: vk::ColorSpaceKHR, premultiply_alpha: bool, load_op: vk::AttachmentLoadOp, initial_layout: vk::ImageLayout, final_layout: vk::ImageLayout) -> BlitPass {
+        self.create_blit_pass_with_sampling(dst_format, dst_color_space, premultiply_alpha, BlitSampling::default(), load_op, initial_layout, final_layout)
+    }
+
+    /// Creates a blit pass which samples the source image as specified by `sampling`. See
+    /// [`BlitUtils::create_blit_pass`].
+    pub fn create_blit_pass_with_sampling(&self, dst_format: vk::Format, dst_color_space: vk::ColorSpaceKHR, premultiply_alpha: bool, sampling: BlitSampling, load_op: vk::AttachmentLoadOp, initial_layout: vk::ImageLayout, final_layout: vk::ImageLayout) -> BlitPass {
         let render_pass = self.create_render_pass(dst_format, load_op, initial_layout, final_layout);
-        let pipeline = self.create_pipeline(render_pass);
+        let pipeline = self.create_pipeline(render_pass, Self::get_gamut_conversion(dst_color_space), premultiply_alpha, sampling, false);
+
+        BlitPass {
+            utils: self.utils.upgrade().unwrap(),
+            render_pass,
+            pipeline,
+            blend: false,
+        }
+    }
+
+    /// Creates a blit pass which blends the source image over the existing content of the
+    /// destination image using a constant opacity. The destination image must be in `layout`
+    /// before the blit and is left in the same layout. See [`BlitPass::record_blend`].
+    pub fn create_blend_blit_pass(&self, dst_format: vk::Format, dst_color_space: vk::ColorSpaceKHR, premultiply_alpha: bool, layout: vk::ImageLayout) -> BlitPass {
+        let render_pass = self.create_render_pass(dst_format, vk::AttachmentLoadOp::LOAD, layout, layout);
+        let pipeline = self.create_pipeline(render_pass, Self::get_gamut_conversion(dst_color_space), premultiply_alpha, BlitSampling::default(), true);
 
         BlitPass {
             utils: self.utils.upgrade().unwrap(),
             render_pass,
             pipeline,
+            blend: true,
         }
     }
 
+    /// Allocates and writes descriptor sets for a collection of image views which are in the
+    /// specified layout while being sampled. The sets can be used with any blit pass.
+    pub fn create_descriptor_sets_with_layout(&self, pool: vk::DescriptorPool, image_views: &[vk::ImageView], image_layout: vk::ImageLayout) -> VkResult<Vec<vk::DescriptorSet>> {
+        let layouts: Box<[_]> = repeat(self.set_layout).take(image_views.len()).collect();
+
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(layouts.as_ref());
+
+        let sets = unsafe {
+            self.device.vk.allocate_descriptor_sets(&info)
+        }?;
+
+        let image_writes: Box<[_]> = image_views.iter().map(|view| {
+            vk::DescriptorImageInfo::builder()
+                .image_view(*view)
+                .image_layout(image_layout)
+        }).collect();
+
+        let writes: Box<[_]> = sets.iter().zip(image_writes.iter()).map(|(set, info)| {
+            vk::WriteDescriptorSet::builder()
+                .dst_set(*set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(std::slice::from_ref(info))
+                .build()
+        }).collect();
+
+        unsafe {
+            self.device.vk.update_descriptor_sets(writes.as_ref(), &[])
+        };
+
+        // We had to build so we need to make sure lifetimes are guaranteed
+        drop(image_writes);
+
+        Ok(sets)
+    }
+
     fn create_render_pass(&self, dst_format: vk::Format, load_op: vk::AttachmentLoadOp, initial_layout: vk::ImageLayout, final_layout: vk::ImageLayout) -> vk::RenderPass {
         let attachment = vk::AttachmentDescription::builder()
             .format(dst_format)
@@ -104,7 +179,48 @@ impl BlitUtils {
         }.unwrap()
     }
 
-    fn create_pipeline(&self, render_pass: vk::RenderPass) -> vk::Pipeline {
+    /// Returns the value of the gamut conversion specialization constant of the blit shader for a
+    /// destination color space.
+    fn get_gamut_conversion(color_space: vk::ColorSpaceKHR) -> u32 {
+        match color_space {
+            vk::ColorSpaceKHR::SRGB_NONLINEAR | vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT | vk::ColorSpaceKHR::EXTENDED_SRGB_NONLINEAR_EXT => 0,
+            vk::ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT | vk::ColorSpaceKHR::DISPLAY_P3_LINEAR_EXT => 1,
+            _ => {
+                log::warn!("Unsupported blit destination color space {:?}. No gamut conversion will be applied", color_space);
+                0
+            }
+        }
+    }
+
+    fn create_pipeline(&self, render_pass: vk::RenderPass, gamut_conversion: u32, premultiply_alpha: bool, sampling: BlitSampling, blend: bool) -> vk::Pipeline {
+        let specialization_data = [gamut_conversion, premultiply_alpha as u32, sampling.nearest as u32, sampling.grayscale as u32];
+        let specializations = [
+            vk::SpecializationMapEntry {
+                constant_id: 0,
+                offset: 0,
+                size: 4
+            },
+            vk::SpecializationMapEntry {
+                constant_id: 1,
+                offset: 4,
+                size: 4
+            },
+            vk::SpecializationMapEntry {
+                constant_id: 2,
+                offset: 8,
+                size: 4
+            },
+            vk::SpecializationMapEntry {
+                constant_id: 3,
+                offset: 12,
+                size: 4
+            }
+        ];
+
+        let specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(&specializations)
+            .data(cast_slice(&specialization_data));
+
         let shader_stages = [
             vk::PipelineShaderStageCreateInfo::builder()
                 .stage(vk::ShaderStageFlags::VERTEX)
@@ -115,6 +231,7 @@ impl BlitUtils {
                 .stage(vk::ShaderStageFlags::FRAGMENT)
                 .module(self.fragment_shader)
                 .name(CStr::from_bytes_with_nul(b"main\0").unwrap())
+                .specialization_info(&specialization_info)
                 .build()
         ];
 
@@ -144,21 +261,29 @@ impl BlitUtils {
             .depth_test_enable(false)
             .depth_write_enable(false);
 
+        // The opacity of blended blits is passed as the alpha blend constant
         let attachment = vk::PipelineColorBlendAttachmentState::builder()
-            .blend_enable(false)
+            .blend_enable(blend)
+            .src_color_blend_factor(vk::BlendFactor::CONSTANT_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_CONSTANT_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::CONSTANT_ALPHA)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_CONSTANT_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD)
             .color_write_mask(vk::ColorComponentFlags::RGBA);
 
         let color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
             .logic_op_enable(false)
             .attachments(std::slice::from_ref(&attachment));
 
-        let dynamic_states = [
-            vk::DynamicState::VIEWPORT,
-            vk::DynamicState::SCISSOR
-        ];
+        let dynamic_states: &[vk::DynamicState] = if blend {
+            &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR, vk::DynamicState::BLEND_CONSTANTS]
+        } else {
+            &[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]
+        };
 
         let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
-            .dynamic_states(&dynamic_states);
+            .dynamic_states(dynamic_states);
 
         let info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&shader_stages)
@@ -234,10 +359,22 @@ impl Drop for BlitUtils {
     }
 }
 
+/// Describes how the source image of a blit is sampled.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct BlitSampling {
+    /// If true the nearest texel is used instead of filtering linearly.
+    pub nearest: bool,
+
+    /// If true the red channel is replicated into all color channels and alpha is set to 1. Used
+    /// to visualize depth images.
+    pub grayscale: bool,
+}
+
 pub struct BlitPass {
     utils: Arc<DeviceUtils>,
     render_pass: vk::RenderPass,
     pipeline: vk::Pipeline,
+    blend: bool,
 }
 
 impl BlitPass {
@@ -245,40 +382,13 @@ impl BlitPass {
     ///
     /// The descriptor sets are fully owned by the calling code after this function returns.
     pub fn create_descriptor_sets(&self, pool: vk::DescriptorPool, image_views: &[vk::ImageView]) -> VkResult<Vec<vk::DescriptorSet>> {
-        let layouts: Box<[_]> = repeat(self.utils.blit_utils.set_layout).take(image_views.len()).collect();
-
-        let info = vk::DescriptorSetAllocateInfo::builder()
-            .descriptor_pool(pool)
-            .set_layouts(layouts.as_ref());
-
-        let sets = unsafe {
-            self.utils.blit_utils.device.vk.allocate_descriptor_sets(&info)
-        }?;
-
-        let image_writes: Box<[_]> = image_views.iter().map(|view| {
-            vk::DescriptorImageInfo::builder()
-                .image_view(*view)
-                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-        }).collect();
-
-        let writes: Box<[_]> = sets.iter().zip(image_writes.iter()).map(|(set, info)| {
-            vk::WriteDescriptorSet::builder()
-                .dst_set(*set)
-                .dst_binding(0)
-                .dst_array_element(0)
-                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-                .image_info(std::slice::from_ref(info))
-                .build()
-        }).collect();
-
-        unsafe {
-            self.utils.blit_utils.device.vk.update_descriptor_sets(writes.as_ref(), &[])
-        };
-
-        // We had to build so we need to make sure lifetimes are guaranteed
-        drop(image_writes);
+        self.create_descriptor_sets_with_layout(pool, image_views, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+    }
 
-        Ok(sets)
+    /// Allocates and writes descriptor sets for a collection of image views which are in the
+    /// specified layout while being sampled. See [`BlitPass::create_descriptor_sets`].
+    pub fn create_descriptor_sets_with_layout(&self, pool: vk::DescriptorPool, image_views: &[vk::ImageView], image_layout: vk::ImageLayout) -> VkResult<Vec<vk::DescriptorSet>> {
+        self.utils.blit_utils.create_descriptor_sets_with_layout(pool, image_views, image_layout)
     }
 
     /// Creates a framebuffer for a image view which can be used for this blit operation.
@@ -302,8 +412,31 @@ impl BlitPass {
     /// struct. No memory barriers are generated.
     ///
     /// The framebuffer image will be used in the COLOR_ATTACHMENT_OUTPUT stage and the sampled image
-    /// in the FRAGMENT_SHADER stage. The sampled image must be in the SHADER_READ_OPTIMAL layout.
-    pub fn record_blit(&self, command_buffer: vk::CommandBuffer, descriptor_set: vk::DescriptorSet, framebuffer: vk::Framebuffer, size: Vec2u32, clear_value: Option<&vk::ClearValue>) {
+    /// in the FRAGMENT_SHADER stage. The sampled image must be in the layout used to create the
+    /// descriptor set.
+    ///
+    /// The source image is drawn into `region` of the framebuffer. If [`None`] the full framebuffer
+    /// is used.
+    pub fn record_blit(&self, command_buffer: vk::CommandBuffer, descriptor_set: vk::DescriptorSet, framebuffer: vk::Framebuffer, size: Vec2u32, region: Option<vk::Rect2D>, clear_value: Option<&vk::ClearValue>) {
+        if self.blend {
+            log::error!("Called BlitPass::record_blit on a blend blit pass");
+            panic!()
+        }
+        self.record(command_buffer, descriptor_set, framebuffer, size, region, clear_value, None);
+    }
+
+    /// Records a blit of a pass created by [`BlitUtils::create_blend_blit_pass`] blending the
+    /// source image over the framebuffer with the specified opacity. The source image is stretched
+    /// over the full framebuffer. See [`BlitPass::record_blit`].
+    pub fn record_blend(&self, command_buffer: vk::CommandBuffer, descriptor_set: vk::DescriptorSet, framebuffer: vk::Framebuffer, size: Vec2u32, opacity: f32) {
+        if !self.blend {
+            log::error!("Called BlitPass::record_blend on a blit pass without blending");
+            panic!()
+        }
+        self.record(command_buffer, descriptor_set, framebuffer, size, None, None, Some(opacity.clamp(0f32, 1f32)));
+    }
+
+    fn record(&self, command_buffer: vk::CommandBuffer, descriptor_set: vk::DescriptorSet, framebuffer: vk::Framebuffer, size: Vec2u32, region: Option<vk::Rect2D>, clear_value: Option<&vk::ClearValue>, opacity: Option<f32>) {
         let device = &self.utils.blit_utils.device;
 
         let mut info = vk::RenderPassBeginInfo::builder()
@@ -318,19 +451,19 @@ impl BlitPass {
             info = info.clear_values(std::slice::from_ref(clear_value))
         }
 
+        let scissor = region.unwrap_or(vk::Rect2D {
+            offset: vk::Offset2D{ x: 0, y: 0 },
+            extent: vk::Extent2D{ width: size[0], height: size[1] }
+        });
+
         let viewport = vk::Viewport::builder()
-            .x(0f32)
-            .y(0f32)
-            .width(size[0] as f32)
-            .height(size[1] as f32)
+            .x(scissor.offset.x as f32)
+            .y(scissor.offset.y as f32)
+            .width(scissor.extent.width as f32)
+            .height(scissor.extent.height as f32)
             .min_depth(0.0)
             .max_depth(1.0);
 
-        let scissor = vk::Rect2D {
-            offset: vk::Offset2D{ x: 0, y: 0 },
-            extent: vk::Extent2D{ width: size[0], height: size[1] }
-        };
-
         unsafe {
             device.vk.cmd_set_viewport(command_buffer, 0, std::slice::from_ref(&viewport));
             device.vk.cmd_set_scissor(command_buffer, 0, std::slice::from_ref(&scissor));
@@ -338,6 +471,9 @@ impl BlitPass {
             device.vk.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
 
             device.vk.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            if let Some(opacity) = opacity {
+                device.vk.cmd_set_blend_constants(command_buffer, &[0f32, 0f32, 0f32, opacity]);
+            }
 
             device.vk.cmd_bind_descriptor_sets(
                 command_buffer,
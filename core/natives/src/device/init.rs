@@ -12,6 +12,9 @@ use crate::instance::instance::{InstanceContext, VulkanVersion};
 
 use crate::prelude::*;
 
+/// The maximum number of queues created in the async transfer queue family.
+pub const MAX_ASYNC_TRANSFER_QUEUES: u32 = 4;
+
 #[derive(Debug)]
 pub struct DeviceCreateConfig {
     used_surfaces: Vec<vk::SurfaceKHR>,
@@ -89,10 +92,11 @@ pub fn create_device(config: DeviceCreateConfig, instance: Arc<InstanceContext>)
             .build()
         );
     }
+    let transfer_priorities = vec![priority; device_config.async_transfer_queue_count as usize];
     if let Some(family) = &device_config.async_transfer_family {
         queue_create_infos.push(vk::DeviceQueueCreateInfo::builder()
             .queue_family_index(*family)
-            .queue_priorities(std::slice::from_ref(&priority))
+            .queue_priorities(transfer_priorities.as_slice())
             .build()
         );
     }
@@ -130,6 +134,48 @@ pub fn create_device(config: DeviceCreateConfig, instance: Arc<InstanceContext>)
         None
     };
 
+    let device_fault_ext = if device_config.has_device_fault {
+        Some(vk::ExtDeviceFaultFn::load(|name| unsafe {
+            std::mem::transmute(instance.vk().get_device_proc_addr(device.handle(), name.as_ptr()))
+        }))
+    } else {
+        None
+    };
+
+    let buffer_device_address_khr = if device_config.has_buffer_device_address {
+        Some(ash::extensions::khr::BufferDeviceAddress::new(instance.vk(), &device))
+    } else {
+        None
+    };
+
+    let diagnostic_checkpoints_nv = if device_config.has_diagnostic_checkpoints {
+        Some(ash::extensions::nv::DeviceDiagnosticCheckpoints::new(instance.vk(), &device))
+    } else {
+        None
+    };
+
+    let display_timing_google = if device_config.has_display_timing {
+        Some(vk::GoogleDisplayTimingFn::load(|name| unsafe {
+            std::mem::transmute(instance.vk().get_device_proc_addr(device.handle(), name.as_ptr()))
+        }))
+    } else {
+        None
+    };
+
+    let draw_indirect_count_khr = if device_config.has_draw_indirect_count {
+        Some(ash::extensions::khr::DrawIndirectCount::new(instance.vk(), &device))
+    } else {
+        None
+    };
+
+    let memory_decompression_nv = if device_config.has_memory_decompression {
+        Some(vk::NvMemoryDecompressionFn::load(|name| unsafe {
+            std::mem::transmute(instance.vk().get_device_proc_addr(device.handle(), name.as_ptr()))
+        }))
+    } else {
+        None
+    };
+
     let functions = Arc::new(DeviceFunctions {
         instance,
         physical_device,
@@ -138,22 +184,38 @@ pub fn create_device(config: DeviceCreateConfig, instance: Arc<InstanceContext>)
         timeline_semaphore_khr,
         push_descriptor_khr,
         swapchain_khr,
-        maintenance_4_khr
+        maintenance_4_khr,
+        device_fault_ext,
+        buffer_device_address_khr,
+        diagnostic_checkpoints_nv,
+        display_timing_google,
+        memory_decompression_nv,
+        draw_indirect_count_khr,
     });
 
     let main_queue = Arc::new(Queue::new(functions.clone(), device_config.main_queue_family, 0));
     let async_compute_queue = device_config.async_compute_family.map(|family| {
         Arc::new(Queue::new(functions.clone(), family, 0))
     });
-    let async_transfer_queue = device_config.async_transfer_family.map(|family| {
-        Arc::new(Queue::new(functions.clone(), family, 0))
-    });
+    let async_transfer_queues = match device_config.async_transfer_family {
+        Some(family) => (0..device_config.async_transfer_queue_count).map(|index| {
+            Arc::new(Queue::new(functions.clone(), family, index))
+        }).collect(),
+        None => Vec::new(),
+    };
+
+    let enabled_features = vk::PhysicalDeviceFeatures::builder()
+        .logic_op(device_config.has_logic_op)
+        .dual_src_blend(device_config.has_dual_src_blend)
+        .sample_rate_shading(device_config.has_sample_rate_shading)
+        .build();
 
     Ok(DeviceContext::new(
         functions,
         main_queue,
         async_compute_queue,
-        async_transfer_queue
+        async_transfer_queues.into_boxed_slice(),
+        enabled_features
     ))
 }
 
@@ -341,6 +403,15 @@ impl<'a, 'b> DeviceConfigurator<'a, 'b> {
 struct DeviceConfigInfo {
     rating: f32,
     has_maintenance4: bool,
+    has_device_fault: bool,
+    has_buffer_device_address: bool,
+    has_diagnostic_checkpoints: bool,
+    has_display_timing: bool,
+    has_memory_decompression: bool,
+    has_draw_indirect_count: bool,
+    has_logic_op: bool,
+    has_dual_src_blend: bool,
+    has_sample_rate_shading: bool,
 
     /// The main queue family. It is guaranteed to support presentation to all surfaces as well as
     /// graphics, compute and transfer operations.
@@ -353,6 +424,10 @@ struct DeviceConfigInfo {
     /// The queue family used for async transfer operations. It is guaranteed to support transfer
     /// operations and must be a different queue family than both the main and compute queue family.
     async_transfer_family: Option<u32>,
+
+    /// The number of queues created in the async transfer queue family. At least 1 if an async
+    /// transfer family is used.
+    async_transfer_queue_count: u32,
 }
 
 fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConfigInfo>, DeviceCreateError> {
@@ -388,6 +463,38 @@ fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConf
         maintenance4 = None;
     }
 
+    let device_fault_name = CString::new("VK_EXT_device_fault").unwrap();
+    let mut device_fault_features;
+    if device.is_extension_supported(&device_fault_name) {
+        device_fault_features = Some(vk::PhysicalDeviceFaultFeaturesEXT::builder());
+        features = features.push_next(device_fault_features.as_mut().unwrap());
+    } else {
+        device_fault_features = None;
+    }
+
+    let buffer_device_address_name = CString::new("VK_KHR_buffer_device_address").unwrap();
+    let mut buffer_device_address_features;
+    if device.is_extension_supported(&buffer_device_address_name) {
+        buffer_device_address_features = Some(vk::PhysicalDeviceBufferDeviceAddressFeatures::builder());
+        features = features.push_next(buffer_device_address_features.as_mut().unwrap());
+    } else {
+        buffer_device_address_features = None;
+    }
+
+    let memory_decompression_name = CString::new("VK_NV_memory_decompression").unwrap();
+    let mut memory_decompression;
+    if device.is_extension_supported(&memory_decompression_name) {
+        memory_decompression = Some((
+            vk::PhysicalDeviceMemoryDecompressionFeaturesNV::builder(),
+            vk::PhysicalDeviceMemoryDecompressionPropertiesNV::builder()
+        ));
+        let (f, p) = memory_decompression.as_mut().unwrap();
+        features = features.push_next(f);
+        properties = properties.push_next(p);
+    } else {
+        memory_decompression = None;
+    }
+
     let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::builder();
     features = features.push_next(&mut timeline_features);
 
@@ -397,17 +504,24 @@ fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConf
     let mut synchronization2_features = vk::PhysicalDeviceSynchronization2Features::builder();
     features = features.push_next(&mut synchronization2_features);
 
+    let mut multiview_features = vk::PhysicalDeviceMultiviewFeatures::builder();
+    features = features.push_next(&mut multiview_features);
+
     let mut push_descriptor_properties = vk::PhysicalDevicePushDescriptorPropertiesKHR::builder();
     properties = properties.push_next(&mut push_descriptor_properties);
 
     // Read supported features and properties
-    device.get_features(features);
+    let supported_features = device.get_features(features);
     device.get_properties(properties);
     let timeline_features = timeline_features.build();
     let timeline_properties = timeline_properties.build();
     let synchronization2_features = synchronization2_features.build();
+    let multiview_features = multiview_features.build();
     let push_descriptor_properties = push_descriptor_properties.build();
     let maintenance4 = maintenance4.map(|(f, p)| (f.build(), p.build()));
+    let device_fault_features = device_fault_features.map(|f| f.build());
+    let buffer_device_address_features = buffer_device_address_features.map(|f| f.build());
+    let memory_decompression = memory_decompression.map(|(f, p)| (f.build(), p.build()));
 
     // Process the supported features and properties
     if timeline_features.timeline_semaphore != vk::TRUE {
@@ -433,6 +547,16 @@ fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConf
         );
     }
 
+    // Required by the emulator shaders even if no stereo passes are used
+    if multiview_features.multiview != vk::TRUE {
+        log::info!("Physical device {:?} does not support the multiview feature", device.get_name());
+        return Ok(None);
+    } else {
+        device.push_next(vk::PhysicalDeviceMultiviewFeatures::builder()
+            .multiview(true)
+        );
+    }
+
     if push_descriptor_properties.max_push_descriptors < 8 {
         log::info!("Physical device {:?} max_push_descriptors is too low {:?}", device.get_name(), push_descriptor_properties.max_push_descriptors);
         return Ok(None);
@@ -453,6 +577,93 @@ fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConf
         has_maintenance4 = false;
     }
 
+    // Crash diagnostics are optional and only used to generate crash reports on device loss
+    let mut has_device_fault = false;
+    if let Some(f) = device_fault_features.as_ref() {
+        if f.device_fault == vk::TRUE {
+            has_device_fault = true;
+            device.add_extension(&device_fault_name);
+            device.push_next(vk::PhysicalDeviceFaultFeaturesEXT::builder()
+                .device_fault(true)
+                .device_fault_vendor_binary(f.device_fault_vendor_binary == vk::TRUE)
+            );
+        }
+    }
+
+    // Used to fetch vertex data in shaders. Meshes are bound as vertex buffers if unsupported
+    let mut has_buffer_device_address = false;
+    if let Some(f) = buffer_device_address_features.as_ref() {
+        if f.buffer_device_address == vk::TRUE {
+            has_buffer_device_address = true;
+            device.add_extension(&buffer_device_address_name);
+            device.push_next(vk::PhysicalDeviceBufferDeviceAddressFeatures::builder()
+                .buffer_device_address(true)
+            );
+        }
+    }
+
+    // Used to decompress GDeflate mesh payloads on the device. Decompression works on device
+    // addresses so buffer device address is required as well
+    let mut has_memory_decompression = false;
+    if let Some((f, p)) = memory_decompression.as_ref() {
+        if has_buffer_device_address && f.memory_decompression == vk::TRUE && p.decompression_methods.contains(vk::MemoryDecompressionMethodFlagsNV::GDEFLATE_1_0) {
+            has_memory_decompression = true;
+            device.add_extension(&memory_decompression_name);
+            device.push_next(vk::PhysicalDeviceMemoryDecompressionFeaturesNV::builder()
+                .memory_decompression(true)
+            );
+        }
+    }
+
+    // Used to draw the sections which passed gpu culling without reading back their count. All
+    // sections are drawn individually if unsupported
+    let draw_indirect_count_name = CString::new("VK_KHR_draw_indirect_count").unwrap();
+    let has_draw_indirect_count = device.is_extension_supported(&draw_indirect_count_name);
+    if has_draw_indirect_count {
+        device.add_extension(&draw_indirect_count_name);
+    }
+
+    let diagnostic_checkpoints_name = CString::new("VK_NV_device_diagnostic_checkpoints").unwrap();
+    let has_diagnostic_checkpoints = device.is_extension_supported(&diagnostic_checkpoints_name);
+    if has_diagnostic_checkpoints {
+        device.add_extension(&diagnostic_checkpoints_name);
+    }
+
+    // Only useful if we present to a swapchain
+    let display_timing_name = CString::new("VK_GOOGLE_display_timing").unwrap();
+    let has_display_timing = device.config.required_extensions.contains(&CString::new("VK_KHR_swapchain").unwrap())
+        && device.is_extension_supported(&display_timing_name);
+    if has_display_timing {
+        device.add_extension(&display_timing_name);
+    }
+
+    // Used to emulate glLogicOp. Pipelines fall back to blending if unsupported
+    let has_logic_op = supported_features.logic_op == vk::TRUE;
+
+    // Used by blend states reading the second fragment shader output. Pipelines fall back to
+    // single source blending if unsupported
+    let has_dual_src_blend = supported_features.dual_src_blend == vk::TRUE;
+
+    // Used for per sample shading of multisampled passes. Disabled if unsupported
+    let has_sample_rate_shading = supported_features.sample_rate_shading == vk::TRUE;
+
+    if has_logic_op || has_dual_src_blend || has_sample_rate_shading {
+        device.push_next(vk::PhysicalDeviceFeatures2::builder()
+            .features(vk::PhysicalDeviceFeatures::builder()
+                .logic_op(has_logic_op)
+                .dual_src_blend(has_dual_src_blend)
+                .sample_rate_shading(has_sample_rate_shading)
+                .build()
+            )
+        );
+    }
+
+    // Required by shaders using debugPrintfEXT
+    let non_semantic_info_name = CString::new("VK_KHR_shader_non_semantic_info").unwrap();
+    if device.is_extension_supported(&non_semantic_info_name) {
+        device.add_extension(&non_semantic_info_name);
+    }
+
     // Calculate queue family assignments
     let main_families = device.filter_sort_queues(|family, properties, surface_support| {
         Some(family)
@@ -465,11 +676,45 @@ fn configure_device(device: &mut DeviceConfigurator) -> Result<Option<DeviceConf
         return Ok(None);
     }
 
+    // A compute only family allows compute work to overlap with work on the main queue
+    let async_compute_family = device.filter_sort_queues(|family, properties, _| {
+        if family != main_queue_family && properties.queue_flags.contains(vk::QueueFlags::COMPUTE) && !properties.queue_flags.contains(vk::QueueFlags::GRAPHICS) {
+            Some(family)
+        } else {
+            None
+        }
+    }).first().copied();
+
+    // Transfer only families usually map to dedicated copy engines. Multiple queues allow large
+    // uploads to run next to small ones
+    let async_transfer_family = device.filter_sort_queues(|family, properties, _| {
+        if family != main_queue_family && Some(family) != async_compute_family && properties.queue_flags.contains(vk::QueueFlags::TRANSFER) && !properties.queue_flags.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE) {
+            Some(family)
+        } else {
+            None
+        }
+    }).first().copied();
+    let async_transfer_queue_count = async_transfer_family.map(|transfer_family| {
+        device.filter_sort_queues(|family, properties, _| {
+            (family == transfer_family).then(|| properties.queue_count)
+        }).first().copied().unwrap_or(1).clamp(1, MAX_ASYNC_TRANSFER_QUEUES)
+    }).unwrap_or(0);
+
     Ok(Some(DeviceConfigInfo {
         rating: 0.0,
         has_maintenance4,
+        has_device_fault,
+        has_buffer_device_address,
+        has_diagnostic_checkpoints,
+        has_display_timing,
+        has_memory_decompression,
+        has_draw_indirect_count,
+        has_logic_op,
+        has_dual_src_blend,
+        has_sample_rate_shading,
         main_queue_family,
-        async_compute_family: None,
-        async_transfer_family: None
+        async_compute_family,
+        async_transfer_family,
+        async_transfer_queue_count,
     }))
 }
\ No newline at end of file
@@ -0,0 +1,180 @@
+//! Deduplication of descriptor set layouts and pipeline layouts.
+//!
+//! Pipelines created with the same pipeline layout handle are trivially compatible so switching
+//! between them keeps all bound descriptor sets and push constants valid. The [`LayoutCache`]
+//! returns the same handle for every request with an identical definition. All layouts stay alive
+//! until the device is destroyed and must not be destroyed by their users.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use ash::prelude::VkResult;
+use ash::vk;
+
+use crate::prelude::*;
+
+pub struct LayoutCache {
+    device: Arc<DeviceFunctions>,
+    set_layouts: Mutex<HashMap<DescriptorSetLayoutKey, vk::DescriptorSetLayout>>,
+    pipeline_layouts: Mutex<HashMap<PipelineLayoutKey, vk::PipelineLayout>>,
+}
+
+impl LayoutCache {
+    pub fn new(device: Arc<DeviceFunctions>) -> Self {
+        Self {
+            device,
+            set_layouts: Mutex::new(HashMap::new()),
+            pipeline_layouts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns a descriptor set layout with the specified bindings. The returned layout is owned
+    /// by the cache.
+    pub fn get_descriptor_set_layout(&self, flags: vk::DescriptorSetLayoutCreateFlags, bindings: &[vk::DescriptorSetLayoutBinding]) -> VkResult<vk::DescriptorSetLayout> {
+        let key = DescriptorSetLayoutKey::new(flags, bindings);
+
+        let mut guard = self.set_layouts.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned set layout mutex in LayoutCache::get_descriptor_set_layout");
+            panic!()
+        });
+        if let Some(layout) = guard.get(&key) {
+            return Ok(*layout);
+        }
+
+        let info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .flags(flags)
+            .bindings(bindings);
+
+        let layout = unsafe {
+            self.device.vk.create_descriptor_set_layout(&info, None)
+        }?;
+        guard.insert(key, layout);
+
+        Ok(layout)
+    }
+
+    /// Returns a pipeline layout with the specified set layouts and push constant ranges. The
+    /// returned layout is owned by the cache.
+    pub fn get_pipeline_layout(&self, set_layouts: &[vk::DescriptorSetLayout], push_constant_ranges: &[vk::PushConstantRange]) -> VkResult<vk::PipelineLayout> {
+        let key = PipelineLayoutKey::new(set_layouts, push_constant_ranges);
+
+        let mut guard = self.pipeline_layouts.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned pipeline layout mutex in LayoutCache::get_pipeline_layout");
+            panic!()
+        });
+        if let Some(layout) = guard.get(&key) {
+            return Ok(*layout);
+        }
+
+        let info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(set_layouts)
+            .push_constant_ranges(push_constant_ranges);
+
+        let layout = unsafe {
+            self.device.vk.create_pipeline_layout(&info, None)
+        }?;
+        guard.insert(key, layout);
+
+        Ok(layout)
+    }
+}
+
+impl Drop for LayoutCache {
+    fn drop(&mut self) {
+        let pipeline_layouts = self.pipeline_layouts.get_mut().unwrap_or_else(|err| err.into_inner());
+        for (_, layout) in pipeline_layouts.drain() {
+            unsafe { self.device.vk.destroy_pipeline_layout(layout, None) };
+        }
+
+        let set_layouts = self.set_layouts.get_mut().unwrap_or_else(|err| err.into_inner());
+        for (_, layout) in set_layouts.drain() {
+            unsafe { self.device.vk.destroy_descriptor_set_layout(layout, None) };
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct BindingKey {
+    binding: u32,
+    descriptor_type: vk::DescriptorType,
+    descriptor_count: u32,
+    stage_flags: vk::ShaderStageFlags,
+    immutable_samplers: Box<[vk::Sampler]>,
+}
+
+/// Binding order does not affect the layout so the bindings are sorted by their index.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct DescriptorSetLayoutKey {
+    flags: vk::DescriptorSetLayoutCreateFlags,
+    bindings: Box<[BindingKey]>,
+}
+
+impl DescriptorSetLayoutKey {
+    fn new(flags: vk::DescriptorSetLayoutCreateFlags, bindings: &[vk::DescriptorSetLayoutBinding]) -> Self {
+        let mut bindings: Vec<_> = bindings.iter().map(|binding| {
+            let immutable_samplers = if binding.p_immutable_samplers.is_null() {
+                Box::default()
+            } else {
+                unsafe {
+                    std::slice::from_raw_parts(binding.p_immutable_samplers, binding.descriptor_count as usize)
+                }.into()
+            };
+
+            BindingKey {
+                binding: binding.binding,
+                descriptor_type: binding.descriptor_type,
+                descriptor_count: binding.descriptor_count,
+                stage_flags: binding.stage_flags,
+                immutable_samplers,
+            }
+        }).collect();
+        bindings.sort_by_key(|binding| binding.binding);
+
+        Self {
+            flags,
+            bindings: bindings.into_boxed_slice(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct PipelineLayoutKey {
+    set_layouts: Box<[vk::DescriptorSetLayout]>,
+    push_constant_ranges: Box<[(vk::ShaderStageFlags, u32, u32)]>,
+}
+
+impl PipelineLayoutKey {
+    fn new(set_layouts: &[vk::DescriptorSetLayout], push_constant_ranges: &[vk::PushConstantRange]) -> Self {
+        Self {
+            set_layouts: set_layouts.into(),
+            push_constant_ranges: push_constant_ranges.iter().map(|range| (range.stage_flags, range.offset, range.size)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(binding: u32, descriptor_type: vk::DescriptorType) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::ALL_GRAPHICS,
+            p_immutable_samplers: std::ptr::null(),
+        }
+    }
+
+    #[test]
+    fn set_layout_key() {
+        let a = [binding(0, vk::DescriptorType::UNIFORM_BUFFER), binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)];
+        let b = [binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER), binding(0, vk::DescriptorType::UNIFORM_BUFFER)];
+        let c = [binding(0, vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC), binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)];
+
+        let flags = vk::DescriptorSetLayoutCreateFlags::empty();
+        assert_eq!(DescriptorSetLayoutKey::new(flags, &a), DescriptorSetLayoutKey::new(flags, &b));
+        assert_ne!(DescriptorSetLayoutKey::new(flags, &a), DescriptorSetLayoutKey::new(flags, &c));
+        assert_ne!(DescriptorSetLayoutKey::new(flags, &a), DescriptorSetLayoutKey::new(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR, &a));
+    }
+}
@@ -1,4 +1,7 @@
 pub mod device;
 pub mod init;
 pub mod device_utils;
+pub mod layout_cache;
 pub mod surface;
+pub mod crash;
+pub mod transfer;
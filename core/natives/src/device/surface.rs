@@ -2,6 +2,7 @@ use std::fmt::{Debug, Formatter};
 use std::ops::{BitAnd, BitOr};
 use std::sync::{Arc, Mutex, MutexGuard, Weak};
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
 
 use ash::prelude::VkResult;
 use ash::vk;
@@ -130,7 +131,7 @@ impl DeviceSurface {
 
         let size = Vec2u32::new(info.image_extent.width, info.image_extent.height);
 
-        let new_swapchain = Arc::new(SurfaceSwapchain::new(self.weak.upgrade().unwrap(), new_swapchain, images.as_slice(), size, format, info.image_usage));
+        let new_swapchain = Arc::new(SurfaceSwapchain::new(self.weak.upgrade().unwrap(), new_swapchain, images.as_slice(), size, format, info.image_usage, info.composite_alpha));
         guard.set_current(&new_swapchain);
         drop(guard);
 
@@ -218,7 +219,13 @@ impl DeviceSurface {
         }
     }
 
-    fn find_best_composite_alpha(&self, capabilities: &vk::SurfaceCapabilitiesKHR, _: &SwapchainConfig) -> Result<vk::CompositeAlphaFlagsKHR, SwapchainCreateError> {
+    fn find_best_composite_alpha(&self, capabilities: &vk::SurfaceCapabilitiesKHR, config: &SwapchainConfig) -> Result<vk::CompositeAlphaFlagsKHR, SwapchainCreateError> {
+        for composite_alpha in config.composite_alpha.as_ref() {
+            if capabilities.supported_composite_alpha.contains(*composite_alpha) {
+                return Ok(*composite_alpha);
+            }
+        }
+
         if capabilities.supported_composite_alpha.contains(vk::CompositeAlphaFlagsKHR::OPAQUE) {
             Ok(vk::CompositeAlphaFlagsKHR::OPAQUE)
 
@@ -300,6 +307,9 @@ pub struct SwapchainConfig {
     pub formats: Box<[vk::SurfaceFormatKHR]>,
     pub required_usage: vk::ImageUsageFlags,
     pub optional_usage: vk::ImageUsageFlags,
+    /// The preferred composite alpha modes in order of preference. If none are supported the
+    /// default selection (OPAQUE first) is used.
+    pub composite_alpha: Box<[vk::CompositeAlphaFlagsKHR]>,
     pub clipped: bool,
 }
 
@@ -335,10 +345,13 @@ pub struct SurfaceSwapchain {
     size: Vec2u32,
     format: vk::SurfaceFormatKHR,
     usage: vk::ImageUsageFlags,
+    composite_alpha: vk::CompositeAlphaFlagsKHR,
+
+    present_timing: Option<Mutex<PresentTimingState>>,
 }
 
 impl SurfaceSwapchain {
-    fn new(surface: Arc<DeviceSurface>, swapchain: vk::SwapchainKHR, images: &[vk::Image], size: Vec2u32, format: vk::SurfaceFormatKHR, usage: vk::ImageUsageFlags) -> Self {
+    fn new(surface: Arc<DeviceSurface>, swapchain: vk::SwapchainKHR, images: &[vk::Image], size: Vec2u32, format: vk::SurfaceFormatKHR, usage: vk::ImageUsageFlags, composite_alpha: vk::CompositeAlphaFlagsKHR) -> Self {
         let device = &surface.device;
 
         let acquire_objects = images.iter().map(|_| AcquireObjects::new(device)).collect();
@@ -347,6 +360,21 @@ impl SurfaceSwapchain {
             ImageObjects::new(device, Image::new(*image), format.format)
         ).collect();
 
+        let present_timing = device.display_timing_google.as_ref().map(|display_timing| {
+            let mut refresh_cycle = vk::RefreshCycleDurationGOOGLE::default();
+            let result = unsafe {
+                (display_timing.get_refresh_cycle_duration_google)(device.vk.handle(), swapchain, &mut refresh_cycle)
+            };
+            let refresh_duration = if result == vk::Result::SUCCESS && refresh_cycle.refresh_duration != 0 {
+                Some(refresh_cycle.refresh_duration)
+            } else {
+                log::warn!("Failed to query swapchain refresh cycle duration {:?}", result);
+                None
+            };
+
+            Mutex::new(PresentTimingState::new(refresh_duration))
+        });
+
         Self {
             surface,
             set_id: UUID::new(),
@@ -357,7 +385,10 @@ impl SurfaceSwapchain {
 
             size,
             format,
-            usage
+            usage,
+            composite_alpha,
+
+            present_timing,
         }
     }
 
@@ -393,6 +424,11 @@ impl SurfaceSwapchain {
         self.usage
     }
 
+    /// Returns the composite alpha mode used by the swapchain
+    pub fn get_composite_alpha(&self) -> vk::CompositeAlphaFlagsKHR {
+        self.composite_alpha
+    }
+
     pub fn acquire_next_image(&self, timeout: u64, fence: Option<vk::Fence>) -> VkResult<(AcquiredImageInfo, bool)> {
         let acquire = self.acquire_objects.get(self.get_next_acquire()).unwrap();
         let (ready_op, acquire_semaphore) = match acquire.wait_and_get(&self.surface.device, timeout) {
@@ -421,6 +457,61 @@ impl SurfaceSwapchain {
         &self.surface.device
     }
 
+    /// Allocates a present id which should be passed to the next present operation of this
+    /// swapchain using [`vk::PresentTimesInfoGOOGLE`].
+    ///
+    /// Returns [`None`] if VK_GOOGLE_display_timing is not enabled.
+    pub fn next_present_id(&self) -> Option<u32> {
+        let mut state = self.present_timing.as_ref()?.lock().unwrap();
+        let id = state.next_present_id;
+        state.next_present_id = state.next_present_id.wrapping_add(1).max(1);
+        Some(id)
+    }
+
+    /// Queries any newly available presentation timings and updates the present timing statistics.
+    ///
+    /// Does nothing if VK_GOOGLE_display_timing is not enabled.
+    pub fn update_present_timing(&self) {
+        let (display_timing, state) = match (self.surface.device.display_timing_google.as_ref(), self.present_timing.as_ref()) {
+            (Some(display_timing), Some(state)) => (display_timing, state),
+            _ => return,
+        };
+
+        let device = self.surface.device.vk.handle();
+        let guard = self.swapchain.lock().unwrap();
+
+        let mut count = 0u32;
+        let result = unsafe {
+            (display_timing.get_past_presentation_timing_google)(device, *guard, &mut count, std::ptr::null_mut())
+        };
+        if result != vk::Result::SUCCESS || count == 0 {
+            return;
+        }
+
+        let mut timings = vec![vk::PastPresentationTimingGOOGLE::default(); count as usize];
+        let result = unsafe {
+            (display_timing.get_past_presentation_timing_google)(device, *guard, &mut count, timings.as_mut_ptr())
+        };
+        drop(guard);
+        if result != vk::Result::SUCCESS && result != vk::Result::INCOMPLETE {
+            log::warn!("Failed to query past presentation timing {:?}", result);
+            return;
+        }
+        timings.truncate(count as usize);
+
+        let mut state = state.lock().unwrap();
+        for timing in &timings {
+            state.push_timing(timing);
+        }
+    }
+
+    /// Returns the present timing statistics of this swapchain.
+    ///
+    /// Returns [`None`] if VK_GOOGLE_display_timing is not enabled.
+    pub fn get_present_timing_statistics(&self) -> Option<PresentTimingStatistics> {
+        Some(self.present_timing.as_ref()?.lock().unwrap().statistics)
+    }
+
     fn get_next_acquire(&self) -> usize {
         loop {
             let old = self.acquire_next_index.load(Ordering::SeqCst);
@@ -531,6 +622,67 @@ impl AcquireObjects {
     }
 }
 
+/// Present timing statistics of a swapchain as reported by VK_GOOGLE_display_timing.
+///
+/// Timings are reported by the driver with some delay so these values lag a few frames behind.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PresentTimingStatistics {
+    /// The duration of a single refresh cycle of the display.
+    pub refresh_duration: Option<Duration>,
+
+    /// How early the most recently reported frame was processed by the presentation engine
+    /// compared to the latest time it could have been processed and still be presented on time.
+    pub last_present_margin: Option<Duration>,
+
+    /// The number of vblanks missed by the most recently reported frame.
+    pub last_missed_vblanks: u32,
+
+    /// The total number of vblanks missed since the swapchain was created.
+    pub total_missed_vblanks: u64,
+
+    /// The total number of frames with reported timings.
+    pub reported_frames: u64,
+}
+
+struct PresentTimingState {
+    next_present_id: u32,
+    refresh_duration: Option<u64>,
+    last_actual_present_time: Option<u64>,
+    statistics: PresentTimingStatistics,
+}
+
+impl PresentTimingState {
+    fn new(refresh_duration: Option<u64>) -> Self {
+        Self {
+            // 0 is not a valid present id
+            next_present_id: 1,
+            refresh_duration,
+            last_actual_present_time: None,
+            statistics: PresentTimingStatistics {
+                refresh_duration: refresh_duration.map(Duration::from_nanos),
+                ..Default::default()
+            }
+        }
+    }
+
+    fn push_timing(&mut self, timing: &vk::PastPresentationTimingGOOGLE) {
+        let missed_vblanks = match (self.refresh_duration, self.last_actual_present_time) {
+            (Some(refresh_duration), Some(last)) if timing.actual_present_time > last => {
+                let elapsed = timing.actual_present_time - last;
+                let vblanks = (elapsed + refresh_duration / 2) / refresh_duration;
+                vblanks.saturating_sub(1) as u32
+            },
+            _ => 0,
+        };
+
+        self.last_actual_present_time = Some(timing.actual_present_time);
+        self.statistics.last_present_margin = Some(Duration::from_nanos(timing.present_margin));
+        self.statistics.last_missed_vblanks = missed_vblanks;
+        self.statistics.total_missed_vblanks += missed_vblanks as u64;
+        self.statistics.reported_frames += 1;
+    }
+}
+
 pub struct ImageObjects {
     image: Image,
     framebuffer_view: vk::ImageView,
@@ -0,0 +1,114 @@
+//! Distribution of uploads across the async transfer queues.
+//!
+//! Devices with a transfer only queue family usually back each of its queues with a separate copy
+//! engine. If every upload is submitted to the same queue a large texture upload delays all small
+//! per frame buffer updates submitted after it. The [`TransferScheduler`] keeps the first queue
+//! free for small uploads and spreads large uploads round robin across the remaining queues. If
+//! only one queue is available all uploads use it.
+//!
+//! The emulator worker copies the initial uploads of global meshes on these queues. Global mesh
+//! and staging buffers are created with concurrent sharing between the main and the async transfer
+//! queue family if these queues exist so no queue family ownership transfers are needed.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ash::vk;
+
+use crate::prelude::*;
+
+/// Selects the async transfer queue used for an upload based on its size.
+pub struct TransferScheduler {
+    queues: Box<[Arc<Queue>]>,
+    large_threshold: vk::DeviceSize,
+    next_large: AtomicUsize,
+}
+
+impl TransferScheduler {
+    /// Uploads of at least this size are considered large by [`TransferScheduler::new`].
+    pub const DEFAULT_LARGE_THRESHOLD: vk::DeviceSize = 1024 * 1024;
+
+    /// Creates a scheduler for all async transfer queues of the device. Returns [`None`] if the
+    /// device has no async transfer queue.
+    pub fn new(device: &DeviceContext) -> Option<Self> {
+        Self::new_with_threshold(device.get_async_transfer_queues(), Self::DEFAULT_LARGE_THRESHOLD)
+    }
+
+    /// Creates a scheduler for the provided queues. All queues must belong to the same queue
+    /// family. Returns [`None`] if no queue is provided.
+    pub fn new_with_threshold(queues: &[Arc<Queue>], large_threshold: vk::DeviceSize) -> Option<Self> {
+        if queues.is_empty() {
+            return None;
+        }
+        let family = queues[0].get_queue_family_index();
+        if queues.iter().any(|queue| queue.get_queue_family_index() != family) {
+            log::error!("Called TransferScheduler::new_with_threshold with queues of different families");
+            panic!();
+        }
+
+        Some(Self {
+            queues: queues.into(),
+            large_threshold,
+            next_large: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the queue family of all queues of the scheduler.
+    pub fn get_queue_family_index(&self) -> u32 {
+        self.queues[0].get_queue_family_index()
+    }
+
+    pub fn get_queue_count(&self) -> usize {
+        self.queues.len()
+    }
+
+    pub fn get_queue(&self, index: usize) -> &Arc<Queue> {
+        &self.queues[index]
+    }
+
+    /// Returns the queue an upload of `size` bytes should be submitted to.
+    pub fn select_queue(&self, size: vk::DeviceSize) -> &Arc<Queue> {
+        &self.queues[self.select_queue_index(size)]
+    }
+
+    /// Like [`TransferScheduler::select_queue`] but returns the index of the queue. Useful to
+    /// batch multiple uploads per queue.
+    pub fn select_queue_index(&self, size: vk::DeviceSize) -> usize {
+        if size < self.large_threshold {
+            return 0;
+        }
+        let counter = self.next_large.fetch_add(1, Ordering::Relaxed);
+        large_queue_index(self.queues.len(), counter)
+    }
+}
+
+/// Returns the index of the queue used by the `counter`-th large upload. Queue 0 is reserved for
+/// small uploads unless it is the only queue.
+fn large_queue_index(queue_count: usize, counter: usize) -> usize {
+    if queue_count <= 1 {
+        0
+    } else {
+        1 + (counter % (queue_count - 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_queue() {
+        for counter in 0..4 {
+            assert_eq!(large_queue_index(1, counter), 0);
+        }
+    }
+
+    #[test]
+    fn large_uploads_skip_first_queue() {
+        let indices: Vec<_> = (0..6).map(|counter| large_queue_index(4, counter)).collect();
+        assert_eq!(indices, vec![1, 2, 3, 1, 2, 3]);
+
+        let indices: Vec<_> = (0..3).map(|counter| large_queue_index(2, counter)).collect();
+        assert_eq!(indices, vec![1, 1, 1]);
+    }
+}
@@ -24,9 +24,22 @@ impl RustLogDebugMessenger {
     }
 }
 
+/// The log target used for shader debugPrintfEXT output.
+pub const SHADER_PRINTF_LOG_TARGET: &'static str = "b4d::shader_printf";
+
+fn is_debug_printf_message(data: &vk::DebugUtilsMessengerCallbackDataEXT) -> bool {
+    if data.p_message_id_name.is_null() {
+        return false;
+    }
+    let id_name = unsafe { CStr::from_ptr(data.p_message_id_name) };
+    id_name.to_bytes().windows(12).any(|window| window == b"DEBUG-PRINTF")
+}
+
 impl DebugMessengerCallback for RustLogDebugMessenger {
-    fn on_message(&self, message_severity: vk::DebugUtilsMessageSeverityFlagsEXT, _: vk::DebugUtilsMessageTypeFlagsEXT, message: &CStr, _: &vk::DebugUtilsMessengerCallbackDataEXT) {
-        if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+    fn on_message(&self, message_severity: vk::DebugUtilsMessageSeverityFlagsEXT, _: vk::DebugUtilsMessageTypeFlagsEXT, message: &CStr, data: &vk::DebugUtilsMessengerCallbackDataEXT) {
+        if is_debug_printf_message(data) {
+            log::info!(target: SHADER_PRINTF_LOG_TARGET, "{}", message.to_string_lossy());
+        } else if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
             log::error!("{:?}", message);
         } else if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
             log::warn!("{:?}", message);
@@ -20,7 +20,9 @@ pub struct InstanceCreateConfig {
     application_version: u32,
     debug_messengers: Vec<DebugUtilsMessengerWrapper>,
     enable_validation: bool,
+    debug_printf_buffer_size: Option<u32>,
     required_extensions: HashSet<CString>,
+    optional_extensions: HashSet<CString>,
     require_surface_khr: bool,
 }
 
@@ -31,7 +33,9 @@ impl InstanceCreateConfig {
             application_version,
             debug_messengers: Vec::new(),
             enable_validation: false,
+            debug_printf_buffer_size: None,
             required_extensions: HashSet::new(),
+            optional_extensions: HashSet::new(),
             require_surface_khr: false,
         }
     }
@@ -44,10 +48,25 @@ impl InstanceCreateConfig {
         self.enable_validation = true;
     }
 
+    /// Enables the debugPrintfEXT validation layer feature. Printf output of shaders is reported
+    /// through the debug messengers. Implies [`InstanceCreateConfig::enable_validation`].
+    ///
+    /// The buffer size is the number of bytes available for printf output per draw or dispatch.
+    pub fn enable_debug_printf(&mut self, buffer_size: u32) {
+        self.enable_validation = true;
+        self.debug_printf_buffer_size = Some(buffer_size);
+    }
+
     pub fn add_required_extension(&mut self, extension: &CStr) {
         self.required_extensions.insert(CString::from(extension));
     }
 
+    /// Adds an extension which will be enabled if it is supported. Instance creation will not
+    /// fail if it is unavailable.
+    pub fn add_optional_extension(&mut self, extension: &CStr) {
+        self.optional_extensions.insert(CString::from(extension));
+    }
+
     pub fn require_surface_khr(&mut self) {
         self.require_surface_khr = true;
     }
@@ -116,15 +135,52 @@ pub fn create_instance(config: InstanceCreateConfig) -> Result<Arc<InstanceConte
             return Err(InstanceCreateError::MissingExtension(name.clone()));
         }
     }
+    for name in &config.optional_extensions {
+        if required_extensions.contains(name) {
+            continue;
+        }
+        if available_extensions.contains(name) {
+            required_extensions_str.push(name.as_c_str().as_ptr())
+        } else {
+            log::info!("Optional instance extension {:?} is not available", name);
+        }
+    }
 
+    let validation_layer_name = CStr::from_bytes_with_nul(b"VK_LAYER_KHRONOS_validation\0").unwrap();
     let required_layers = if config.enable_validation {
         log::info!("Validation layers enabled");
-        vec![CStr::from_bytes_with_nul(b"VK_LAYER_KHRONOS_validation\0").unwrap().as_ptr()]
+        vec![validation_layer_name.as_ptr()]
     } else {
         log::info!("Validation layers disabled");
         Vec::new()
     };
 
+    // Validation features are provided by the validation layer itself so we have to query the layer extensions
+    let validation_features_name = vk::ExtValidationFeaturesFn::name();
+    let enabled_validation_features = [vk::ValidationFeatureEnableEXT::DEBUG_PRINTF];
+    let mut validation_features = None;
+    if let Some(buffer_size) = config.debug_printf_buffer_size {
+        let layer_extensions = entry.enumerate_instance_extension_properties(Some(validation_layer_name))?;
+        let supported = layer_extensions.iter().any(|ext| {
+            let name = unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) };
+            name == validation_features_name
+        });
+
+        if supported {
+            log::info!("Shader debug printf enabled with buffer size {:?}", buffer_size);
+            // The buffer size can only be configured through the layer settings. Dont override the user if it is already set.
+            if std::env::var_os("VK_LAYER_PRINTF_BUFFER_SIZE").is_none() {
+                std::env::set_var("VK_LAYER_PRINTF_BUFFER_SIZE", buffer_size.to_string());
+            }
+            required_extensions_str.push(validation_features_name.as_ptr());
+            validation_features = Some(vk::ValidationFeaturesEXT::builder()
+                .enabled_validation_features(&enabled_validation_features)
+            );
+        } else {
+            log::warn!("Shader debug printf requested but the validation layer does not support {:?}", validation_features_name);
+        }
+    }
+
     let max_api_version = VulkanVersion::VK_1_1;
     let name = CString::new(CRATE_NAME).unwrap();
     let application_info = vk::ApplicationInfo::builder()
@@ -151,6 +207,9 @@ pub fn create_instance(config: InstanceCreateConfig) -> Result<Arc<InstanceConte
     for debug_messenger in debug_messenger_create_infos.iter_mut() {
         instance_create_info = instance_create_info.push_next(debug_messenger);
     }
+    if let Some(validation_features) = validation_features.as_mut() {
+        instance_create_info = instance_create_info.push_next(validation_features);
+    }
 
     let vp_instance_create_info = vp::InstanceCreateInfo::builder()
         .profile(&profile)
@@ -0,0 +1,325 @@
+//! Tracking of image layouts and queue family ownership.
+//!
+//! The [`ImageLayoutTracker`] stores the resting state of every registered image. This is the
+//! state the image is in between command buffers. Command buffers may be recorded in a different
+//! order than they are submitted so transitions within a command buffer are tracked by a separate
+//! [`ImageLayoutScope`] which returns all images it used back into its resting state when it is
+//! finished. The first use of a image in a scope updates the resting state immediately so that
+//! scopes recorded afterwards do not assume the image to still be uninitialized.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use ash::vk;
+
+use crate::util::vk::make_full_subresource_range;
+
+/// The access of a image by a set of commands.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ImageAccess {
+    pub stage_mask: vk::PipelineStageFlags2,
+    pub access_mask: vk::AccessFlags2,
+    pub layout: vk::ImageLayout,
+}
+
+impl ImageAccess {
+    /// The state of a image whose contents are undefined.
+    pub const UNDEFINED: Self = Self::new(vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE, vk::ImageLayout::UNDEFINED);
+
+    pub const fn new(stage_mask: vk::PipelineStageFlags2, access_mask: vk::AccessFlags2, layout: vk::ImageLayout) -> Self {
+        Self {
+            stage_mask,
+            access_mask,
+            layout,
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        let write = vk::AccessFlags2::SHADER_WRITE |
+            vk::AccessFlags2::SHADER_STORAGE_WRITE |
+            vk::AccessFlags2::COLOR_ATTACHMENT_WRITE |
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE |
+            vk::AccessFlags2::TRANSFER_WRITE |
+            vk::AccessFlags2::HOST_WRITE |
+            vk::AccessFlags2::MEMORY_WRITE;
+
+        !self.access_mask.intersects(write)
+    }
+}
+
+#[derive(Copy, Clone)]
+struct TrackedImage {
+    aspect_mask: vk::ImageAspectFlags,
+    mip_levels: u32,
+    resting: ImageAccess,
+
+    /// The queue family owning the image or [`vk::QUEUE_FAMILY_IGNORED`] if the image is shared
+    /// concurrently.
+    queue_family: u32,
+}
+
+/// Stores the resting state of images. See the module documentation.
+pub struct ImageLayoutTracker {
+    images: Mutex<HashMap<vk::Image, TrackedImage>>,
+}
+
+impl ImageLayoutTracker {
+    pub fn new() -> Self {
+        Self {
+            images: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers a newly created image. The image starts out in the [`ImageAccess::UNDEFINED`]
+    /// state. Images created with [`vk::SharingMode::CONCURRENT`] must pass
+    /// [`vk::QUEUE_FAMILY_IGNORED`] as queue family.
+    pub fn register(&self, image: vk::Image, aspect_mask: vk::ImageAspectFlags, mip_levels: u32, queue_family: u32) {
+        let old = self.lock().insert(image, TrackedImage {
+            aspect_mask,
+            mip_levels,
+            resting: ImageAccess::UNDEFINED,
+            queue_family,
+        });
+        if old.is_some() {
+            log::error!("Registered image {:?} with the layout tracker twice", image);
+            panic!();
+        }
+    }
+
+    /// Removes a image from the tracker. Must be called before the image is destroyed.
+    pub fn unregister(&self, image: vk::Image) {
+        if self.lock().remove(&image).is_none() {
+            log::warn!("Unregistered unknown image {:?} from the layout tracker", image);
+        }
+    }
+
+    /// Returns the layout of the image between command buffers or [`None`] if the image is not
+    /// registered.
+    pub fn get_resting_layout(&self, image: vk::Image) -> Option<vk::ImageLayout> {
+        self.lock().get(&image).map(|tracked| tracked.resting.layout)
+    }
+
+    /// Replaces the resting state of the image with the resting state of a scope and returns the
+    /// previous state.
+    fn begin_use(&self, image: vk::Image, resting: ImageAccess, queue_family: u32) -> TrackedImage {
+        let mut guard = self.lock();
+        let tracked = guard.get_mut(&image).unwrap_or_else(|| {
+            log::error!("Attempted to transition image {:?} which is not registered with the layout tracker", image);
+            panic!()
+        });
+
+        let old = *tracked;
+        tracked.resting = resting;
+        if tracked.queue_family != vk::QUEUE_FAMILY_IGNORED {
+            tracked.queue_family = queue_family;
+        }
+
+        old
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<vk::Image, TrackedImage>> {
+        self.images.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned image layout tracker mutex");
+            panic!()
+        })
+    }
+}
+
+impl Default for ImageLayoutTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct ScopeImage {
+    aspect_mask: vk::ImageAspectFlags,
+    levels: Box<[ImageAccess]>,
+}
+
+/// Tracks the state of images used by a single command buffer. See the module documentation.
+pub struct ImageLayoutScope {
+    resting: ImageAccess,
+    queue_family: u32,
+    images: HashMap<vk::Image, ScopeImage>,
+}
+
+impl ImageLayoutScope {
+    /// Creates a scope for a command buffer submitted to a queue of `queue_family` which returns
+    /// all images into the `resting` state.
+    pub fn new(resting: ImageAccess, queue_family: u32) -> Self {
+        Self {
+            resting,
+            queue_family,
+            images: HashMap::new(),
+        }
+    }
+
+    /// Returns the current layout of a mip level if the image has been used in this scope.
+    pub fn get_layout(&self, image: vk::Image, mip_level: u32) -> Option<vk::ImageLayout> {
+        self.images.get(&image).map(|scope_image| scope_image.levels[mip_level as usize].layout)
+    }
+
+    /// Transitions the mip levels to a new access and pushes the required barriers. Consecutive
+    /// levels in the same state share a single barrier. Read only accesses in the same layout do
+    /// not require a barrier and are merged into the current state instead.
+    ///
+    /// If the image is owned by a different queue family a barrier acquiring ownership of the
+    /// entire image is pushed first. The matching release barrier must be recorded on the previous
+    /// queue.
+    pub fn transition(&mut self, tracker: &ImageLayoutTracker, image: vk::Image, base_mip_level: u32, level_count: u32, access: ImageAccess, barriers: &mut Vec<vk::ImageMemoryBarrier2>) {
+        let resting = self.resting;
+        let queue_family = self.queue_family;
+        let scope_image = self.images.entry(image).or_insert_with(|| {
+            let old = tracker.begin_use(image, resting, queue_family);
+            if old.queue_family != vk::QUEUE_FAMILY_IGNORED && old.queue_family != queue_family {
+                barriers.push(vk::ImageMemoryBarrier2::builder()
+                    .dst_stage_mask(old.resting.stage_mask)
+                    .dst_access_mask(old.resting.access_mask)
+                    .old_layout(old.resting.layout)
+                    .new_layout(old.resting.layout)
+                    .src_queue_family_index(old.queue_family)
+                    .dst_queue_family_index(queue_family)
+                    .image(image)
+                    .subresource_range(make_full_subresource_range(old.aspect_mask))
+                    .build()
+                );
+            }
+
+            ScopeImage {
+                aspect_mask: old.aspect_mask,
+                levels: vec![old.resting; old.mip_levels as usize].into_boxed_slice(),
+            }
+        });
+
+        let end = base_mip_level + level_count;
+        if end as usize > scope_image.levels.len() {
+            log::error!("Attempted to transition mip levels {}..{} of image {:?} with {} mip levels", base_mip_level, end, image, scope_image.levels.len());
+            panic!();
+        }
+
+        let mut level = base_mip_level;
+        while level < end {
+            let old = scope_image.levels[level as usize];
+            let mut run_end = level + 1;
+            while run_end < end && scope_image.levels[run_end as usize] == old {
+                run_end += 1;
+            }
+
+            if old.layout == access.layout && old.is_read_only() && access.is_read_only() {
+                let merged = ImageAccess::new(old.stage_mask | access.stage_mask, old.access_mask | access.access_mask, old.layout);
+                scope_image.levels[level as usize..run_end as usize].fill(merged);
+            } else {
+                barriers.push(vk::ImageMemoryBarrier2::builder()
+                    .src_stage_mask(old.stage_mask)
+                    .src_access_mask(old.access_mask)
+                    .dst_stage_mask(access.stage_mask)
+                    .dst_access_mask(access.access_mask)
+                    .old_layout(old.layout)
+                    .new_layout(access.layout)
+                    .image(image)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: scope_image.aspect_mask,
+                        base_mip_level: level,
+                        level_count: run_end - level,
+                        base_array_layer: 0,
+                        layer_count: vk::REMAINING_ARRAY_LAYERS,
+                    })
+                    .build()
+                );
+                scope_image.levels[level as usize..run_end as usize].fill(access);
+            }
+
+            level = run_end;
+        }
+    }
+
+    /// Transitions all images used by this scope back into the resting state and resets the scope.
+    pub fn finish(&mut self, tracker: &ImageLayoutTracker, barriers: &mut Vec<vk::ImageMemoryBarrier2>) {
+        let resting = self.resting;
+        let images: Vec<_> = self.images.iter().map(|(image, scope_image)| (*image, scope_image.levels.len() as u32)).collect();
+        for (image, mip_levels) in images {
+            self.transition(tracker, image, 0, mip_levels, resting, barriers);
+        }
+        self.images.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    const READY: ImageAccess = ImageAccess::new(vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    const TRANSFER_WRITE: ImageAccess = ImageAccess::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+    const TRANSFER_READ: ImageAccess = ImageAccess::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+    #[test]
+    fn scope_returns_to_resting() {
+        let tracker = ImageLayoutTracker::new();
+        let image = vk::Image::from_raw(1);
+        tracker.register(image, vk::ImageAspectFlags::COLOR, 4, 0);
+
+        let mut barriers = Vec::new();
+        let mut scope = ImageLayoutScope::new(READY, 0);
+        scope.transition(&tracker, image, 0, 4, TRANSFER_WRITE, &mut barriers);
+        assert_eq!(barriers.len(), 1);
+        assert_eq!(barriers[0].old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(tracker.get_resting_layout(image), Some(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL));
+
+        // Only the first level changes so the remaining levels need a separate barrier
+        barriers.clear();
+        scope.transition(&tracker, image, 0, 1, TRANSFER_READ, &mut barriers);
+        scope.finish(&tracker, &mut barriers);
+        assert_eq!(barriers.len(), 3);
+        assert_eq!(barriers[1].subresource_range.level_count, 1);
+        assert_eq!(barriers[1].old_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        assert_eq!(barriers[2].subresource_range.base_mip_level, 1);
+        assert_eq!(barriers[2].subresource_range.level_count, 3);
+        assert_eq!(scope.get_layout(image, 0), None);
+    }
+
+    #[test]
+    fn read_after_read() {
+        let tracker = ImageLayoutTracker::new();
+        let image = vk::Image::from_raw(1);
+        tracker.register(image, vk::ImageAspectFlags::COLOR, 1, vk::QUEUE_FAMILY_IGNORED);
+
+        let mut barriers = Vec::new();
+        let mut scope = ImageLayoutScope::new(TRANSFER_READ, 0);
+        scope.transition(&tracker, image, 0, 1, TRANSFER_WRITE, &mut barriers);
+        scope.finish(&tracker, &mut barriers);
+        barriers.clear();
+
+        let compute_read = ImageAccess::new(vk::PipelineStageFlags2::COMPUTE_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        scope.transition(&tracker, image, 0, 1, compute_read, &mut barriers);
+        assert!(barriers.is_empty());
+
+        // A following write must wait for both reads
+        scope.transition(&tracker, image, 0, 1, TRANSFER_WRITE, &mut barriers);
+        assert_eq!(barriers.len(), 1);
+        assert_eq!(barriers[0].src_stage_mask, vk::PipelineStageFlags2::TRANSFER | vk::PipelineStageFlags2::COMPUTE_SHADER);
+    }
+
+    #[test]
+    fn queue_family_acquire() {
+        let tracker = ImageLayoutTracker::new();
+        let image = vk::Image::from_raw(1);
+        tracker.register(image, vk::ImageAspectFlags::COLOR, 1, 0);
+
+        let mut barriers = Vec::new();
+        let mut scope = ImageLayoutScope::new(READY, 1);
+        scope.transition(&tracker, image, 0, 1, TRANSFER_WRITE, &mut barriers);
+        assert_eq!(barriers.len(), 2);
+        assert_eq!((barriers[0].src_queue_family_index, barriers[0].dst_queue_family_index), (0, 1));
+        assert_eq!(barriers[1].src_queue_family_index, barriers[1].dst_queue_family_index);
+
+        // Ownership is only transferred once
+        barriers.clear();
+        scope.finish(&tracker, &mut barriers);
+        let mut scope = ImageLayoutScope::new(READY, 1);
+        scope.transition(&tracker, image, 0, 1, TRANSFER_WRITE, &mut barriers);
+        assert_eq!(barriers.len(), 2);
+        assert_eq!(barriers[1].src_queue_family_index, barriers[1].dst_queue_family_index);
+    }
+}
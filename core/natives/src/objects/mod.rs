@@ -1,4 +1,6 @@
 pub mod id;
+pub mod image_layout;
+pub mod ownership;
 pub mod sync;
 
 mod object_set;
@@ -0,0 +1,294 @@
+//! Tracking of queue family ownership of exclusive resources.
+//!
+//! Resources created with [`vk::SharingMode::EXCLUSIVE`] must be explicitly transferred between
+//! queue families by recording a release barrier on the source queue and a matching acquire barrier
+//! on the destination queue. The [`ResourceOwnershipTracker`] records which queue family currently
+//! owns every registered resource and generates both barriers, rejecting transfers which would be
+//! invalid.
+//!
+//! Global images of the emulator are tracked by the
+//! [`ImageLayoutTracker`](super::image_layout::ImageLayoutTracker) instead. This tracker is intended
+//! for resources shared between the application and custom submits.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use ash::vk;
+
+use crate::objects::image_layout::ImageAccess;
+use crate::util::vk::make_full_subresource_range;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum OwnershipError {
+    /// The resource is not registered with the tracker.
+    NotRegistered,
+
+    /// The resource is owned by a different queue family than the one releasing it. Contains the
+    /// current owner or [`None`] if the resource is currently being transferred.
+    NotOwned(Option<u32>),
+
+    /// The resource is already owned by the queue family attempting to acquire it.
+    AlreadyOwned,
+
+    /// The resource has not been released to the queue family attempting to acquire it.
+    NotReleased,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+enum TrackedResource {
+    Buffer(vk::Buffer),
+    Image(vk::Image),
+}
+
+#[derive(Copy, Clone, Debug)]
+enum OwnershipState {
+    Owned(u32),
+
+    /// Released by `src` but not yet acquired by `dst`. The layouts must be repeated by the
+    /// acquire barrier.
+    Released {
+        src: u32,
+        dst: u32,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    },
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Tracked {
+    aspect_mask: vk::ImageAspectFlags,
+    state: OwnershipState,
+}
+
+/// Stores the owning queue family of resources. See the module documentation.
+pub struct ResourceOwnershipTracker {
+    resources: Mutex<HashMap<TrackedResource, Tracked>>,
+}
+
+impl ResourceOwnershipTracker {
+    pub fn new() -> Self {
+        Self {
+            resources: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers a buffer which is initially owned by `queue_family`.
+    pub fn register_buffer(&self, buffer: vk::Buffer, queue_family: u32) {
+        self.register(TrackedResource::Buffer(buffer), vk::ImageAspectFlags::empty(), queue_family);
+    }
+
+    /// Registers a image which is initially owned by `queue_family`.
+    pub fn register_image(&self, image: vk::Image, aspect_mask: vk::ImageAspectFlags, queue_family: u32) {
+        self.register(TrackedResource::Image(image), aspect_mask, queue_family);
+    }
+
+    /// Removes a buffer from the tracker. Must be called before the buffer is destroyed.
+    pub fn unregister_buffer(&self, buffer: vk::Buffer) {
+        self.unregister(TrackedResource::Buffer(buffer));
+    }
+
+    /// Removes a image from the tracker. Must be called before the image is destroyed.
+    pub fn unregister_image(&self, image: vk::Image) {
+        self.unregister(TrackedResource::Image(image));
+    }
+
+    /// Returns the queue family currently owning the buffer. Returns [`None`] if the buffer is
+    /// not registered or is currently being transferred.
+    pub fn get_buffer_owner(&self, buffer: vk::Buffer) -> Option<u32> {
+        self.get_owner(TrackedResource::Buffer(buffer))
+    }
+
+    /// Returns the queue family currently owning the image. Returns [`None`] if the image is not
+    /// registered or is currently being transferred.
+    pub fn get_image_owner(&self, image: vk::Image) -> Option<u32> {
+        self.get_owner(TrackedResource::Image(image))
+    }
+
+    /// Releases ownership of the buffer from `src_queue_family` to `dst_queue_family`. The returned
+    /// barrier must be recorded on a queue of `src_queue_family` after all accesses described by
+    /// `src_stage_mask` and `src_access_mask`.
+    pub fn release_buffer(&self, buffer: vk::Buffer, src_queue_family: u32, dst_queue_family: u32, src_stage_mask: vk::PipelineStageFlags2, src_access_mask: vk::AccessFlags2) -> Result<vk::BufferMemoryBarrier2, OwnershipError> {
+        self.release(TrackedResource::Buffer(buffer), src_queue_family, dst_queue_family, vk::ImageLayout::UNDEFINED, vk::ImageLayout::UNDEFINED)?;
+
+        Ok(vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(src_stage_mask)
+            .src_access_mask(src_access_mask)
+            .src_queue_family_index(src_queue_family)
+            .dst_queue_family_index(dst_queue_family)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build()
+        )
+    }
+
+    /// Acquires ownership of a buffer previously released to `dst_queue_family`. The returned
+    /// barrier must be recorded on a queue of `dst_queue_family` before all accesses described by
+    /// `dst_stage_mask` and `dst_access_mask` and the submission must wait for the release.
+    pub fn acquire_buffer(&self, buffer: vk::Buffer, dst_queue_family: u32, dst_stage_mask: vk::PipelineStageFlags2, dst_access_mask: vk::AccessFlags2) -> Result<vk::BufferMemoryBarrier2, OwnershipError> {
+        let (src_queue_family, _, _, _) = self.acquire(TrackedResource::Buffer(buffer), dst_queue_family)?;
+
+        Ok(vk::BufferMemoryBarrier2::builder()
+            .dst_stage_mask(dst_stage_mask)
+            .dst_access_mask(dst_access_mask)
+            .src_queue_family_index(src_queue_family)
+            .dst_queue_family_index(dst_queue_family)
+            .buffer(buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE)
+            .build()
+        )
+    }
+
+    /// Releases ownership of the image from `src_queue_family` to `dst_queue_family` and
+    /// transitions it from the layout of `src` into `new_layout`. See
+    /// [`ResourceOwnershipTracker::release_buffer`].
+    pub fn release_image(&self, image: vk::Image, src_queue_family: u32, dst_queue_family: u32, src: ImageAccess, new_layout: vk::ImageLayout) -> Result<vk::ImageMemoryBarrier2, OwnershipError> {
+        let aspect_mask = self.release(TrackedResource::Image(image), src_queue_family, dst_queue_family, src.layout, new_layout)?;
+
+        Ok(vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(src.stage_mask)
+            .src_access_mask(src.access_mask)
+            .old_layout(src.layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(src_queue_family)
+            .dst_queue_family_index(dst_queue_family)
+            .image(image)
+            .subresource_range(make_full_subresource_range(aspect_mask))
+            .build()
+        )
+    }
+
+    /// Acquires ownership of a image previously released to `dst_queue_family`. The layout
+    /// transition of the release is repeated and `dst.layout` is ignored since the image ends up in
+    /// the layout specified by the release. See [`ResourceOwnershipTracker::acquire_buffer`].
+    pub fn acquire_image(&self, image: vk::Image, dst_queue_family: u32, dst: ImageAccess) -> Result<vk::ImageMemoryBarrier2, OwnershipError> {
+        let (src_queue_family, aspect_mask, old_layout, new_layout) = self.acquire(TrackedResource::Image(image), dst_queue_family)?;
+
+        Ok(vk::ImageMemoryBarrier2::builder()
+            .dst_stage_mask(dst.stage_mask)
+            .dst_access_mask(dst.access_mask)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(src_queue_family)
+            .dst_queue_family_index(dst_queue_family)
+            .image(image)
+            .subresource_range(make_full_subresource_range(aspect_mask))
+            .build()
+        )
+    }
+
+    fn register(&self, resource: TrackedResource, aspect_mask: vk::ImageAspectFlags, queue_family: u32) {
+        let old = self.lock().insert(resource, Tracked {
+            aspect_mask,
+            state: OwnershipState::Owned(queue_family),
+        });
+        if old.is_some() {
+            log::error!("Registered resource {:?} with the ownership tracker twice", resource);
+            panic!();
+        }
+    }
+
+    fn unregister(&self, resource: TrackedResource) {
+        if self.lock().remove(&resource).is_none() {
+            log::warn!("Unregistered unknown resource {:?} from the ownership tracker", resource);
+        }
+    }
+
+    fn get_owner(&self, resource: TrackedResource) -> Option<u32> {
+        match self.lock().get(&resource)?.state {
+            OwnershipState::Owned(owner) => Some(owner),
+            OwnershipState::Released { .. } => None,
+        }
+    }
+
+    fn release(&self, resource: TrackedResource, src: u32, dst: u32, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) -> Result<vk::ImageAspectFlags, OwnershipError> {
+        let mut guard = self.lock();
+        let tracked = guard.get_mut(&resource).ok_or(OwnershipError::NotRegistered)?;
+
+        match tracked.state {
+            OwnershipState::Owned(owner) if owner == src => {
+                if src == dst {
+                    return Err(OwnershipError::AlreadyOwned);
+                }
+                tracked.state = OwnershipState::Released { src, dst, old_layout, new_layout };
+                Ok(tracked.aspect_mask)
+            }
+            OwnershipState::Owned(owner) => Err(OwnershipError::NotOwned(Some(owner))),
+            OwnershipState::Released { .. } => Err(OwnershipError::NotOwned(None)),
+        }
+    }
+
+    fn acquire(&self, resource: TrackedResource, dst_queue_family: u32) -> Result<(u32, vk::ImageAspectFlags, vk::ImageLayout, vk::ImageLayout), OwnershipError> {
+        let mut guard = self.lock();
+        let tracked = guard.get_mut(&resource).ok_or(OwnershipError::NotRegistered)?;
+
+        match tracked.state {
+            OwnershipState::Released { src, dst, old_layout, new_layout } if dst == dst_queue_family => {
+                tracked.state = OwnershipState::Owned(dst);
+                Ok((src, tracked.aspect_mask, old_layout, new_layout))
+            }
+            OwnershipState::Owned(owner) if owner == dst_queue_family => Err(OwnershipError::AlreadyOwned),
+            _ => Err(OwnershipError::NotReleased),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<TrackedResource, Tracked>> {
+        self.resources.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned resource ownership tracker mutex");
+            panic!()
+        })
+    }
+}
+
+impl Default for ResourceOwnershipTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    #[test]
+    fn buffer_transfer() {
+        let tracker = ResourceOwnershipTracker::new();
+        let buffer = vk::Buffer::from_raw(1);
+        tracker.register_buffer(buffer, 0);
+
+        assert_eq!(tracker.release_buffer(buffer, 1, 2, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE).err(), Some(OwnershipError::NotOwned(Some(0))));
+        assert_eq!(tracker.acquire_buffer(buffer, 0, vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE).err(), Some(OwnershipError::AlreadyOwned));
+
+        let release = tracker.release_buffer(buffer, 0, 1, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE).unwrap();
+        assert_eq!((release.src_queue_family_index, release.dst_queue_family_index), (0, 1));
+        assert_eq!(tracker.get_buffer_owner(buffer), None);
+        assert_eq!(tracker.acquire_buffer(buffer, 2, vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE).err(), Some(OwnershipError::NotReleased));
+
+        let acquire = tracker.acquire_buffer(buffer, 1, vk::PipelineStageFlags2::VERTEX_INPUT, vk::AccessFlags2::VERTEX_ATTRIBUTE_READ).unwrap();
+        assert_eq!((acquire.src_queue_family_index, acquire.dst_queue_family_index), (0, 1));
+        assert_eq!(tracker.get_buffer_owner(buffer), Some(1));
+
+        // Double acquire
+        assert_eq!(tracker.acquire_buffer(buffer, 1, vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE).err(), Some(OwnershipError::AlreadyOwned));
+    }
+
+    #[test]
+    fn image_layouts_repeated() {
+        let tracker = ResourceOwnershipTracker::new();
+        let image = vk::Image::from_raw(1);
+        tracker.register_image(image, vk::ImageAspectFlags::COLOR, 0);
+
+        let src = ImageAccess::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        let release = tracker.release_image(image, 0, 1, src, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL).unwrap();
+
+        let dst = ImageAccess::new(vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let acquire = tracker.acquire_image(image, 1, dst).unwrap();
+        assert_eq!((acquire.old_layout, acquire.new_layout), (release.old_layout, release.new_layout));
+        assert_eq!(acquire.subresource_range.aspect_mask, vk::ImageAspectFlags::COLOR);
+
+        assert_eq!(tracker.release_image(vk::Image::from_raw(2), 0, 1, src, vk::ImageLayout::GENERAL).err(), Some(OwnershipError::NotRegistered));
+    }
+}
@@ -0,0 +1,5 @@
+//! Debug utilities rendered on top of the emulator output.
+
+pub mod text;
+pub mod overlay;
+pub mod statistics;
@@ -0,0 +1,569 @@
+//! A text overlay drawn on top of the emulator output.
+
+use std::ffi::CStr;
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ash::vk;
+use bytemuck::cast_slice;
+use include_bytes_aligned::include_bytes_aligned;
+
+use crate::allocator::{Allocation, HostAccess};
+use crate::device::device_utils::create_shader_from_bytes;
+use crate::renderer::debug::text::{GlyphInstance, MsdfFont};
+use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, ImageData};
+use crate::util::format::Format;
+
+use crate::prelude::*;
+
+/// The size of one em in pixels.
+const EM_SIZE: f32 = 16.0;
+
+/// The offset of the text from the top left corner in em units.
+const TEXT_OFFSET: [f32; 2] = [0.5, 0.5];
+
+/// The maximum number of glyphs which can be drawn in one frame.
+const MAX_GLYPHS: usize = 4096;
+
+/// The number of glyph buffer slots. Each frame uses the next slot.
+const SLOT_COUNT: usize = 8;
+
+const UNIFORM_OFFSET: usize = 0;
+const MULTIPLIER_OFFSET: usize = 256;
+const SLOTS_OFFSET: usize = 512;
+const SLOT_SIZE: usize = MAX_GLYPHS * std::mem::size_of::<GlyphInstance>();
+
+/// Resources of the debug overlay which are shared between all outputs.
+///
+/// The overlay displays a block of text in the top left corner of the output which can be updated
+/// at any time using [`DebugOverlay::set_text`].
+pub struct DebugOverlay {
+    device: Arc<DeviceContext>,
+    font: MsdfFont,
+    atlas: Arc<GlobalImage>,
+    vertex_shader: vk::ShaderModule,
+    fragment_shader: vk::ShaderModule,
+    sampler: vk::Sampler,
+    set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    text: Mutex<String>,
+}
+
+impl DebugOverlay {
+    pub fn new(emulator: &EmulatorRenderer) -> Arc<Self> {
+        let device = emulator.get_device().clone();
+
+        let font = MsdfFont::debug_font();
+        let atlas = emulator.create_global_image(font.get_atlas_size(), &Format::R8G8B8A8_UNORM);
+        atlas.update_regions(&[ImageData::new_full(font.get_atlas_data(), font.get_atlas_size())]);
+
+        let vertex_shader = create_shader_from_bytes(device.get_functions(), MSDF_FONT_VERTEX_SHADER).unwrap();
+        let fragment_shader = create_shader_from_bytes(device.get_functions(), MSDF_FONT_FRAGMENT_SHADER).unwrap();
+        let sampler = Self::create_sampler(&device);
+        let set_layout = Self::create_descriptor_set_layout(&device, sampler);
+        let pipeline_layout = Self::create_pipeline_layout(&device, set_layout);
+
+        Arc::new(Self {
+            device,
+            font,
+            atlas,
+            vertex_shader,
+            fragment_shader,
+            sampler,
+            set_layout,
+            pipeline_layout,
+            text: Mutex::new(String::new()),
+        })
+    }
+
+    /// Sets the text displayed by the overlay. Any frame started after this call will display the
+    /// new text.
+    pub fn set_text(&self, text: String) {
+        *self.text.lock().unwrap() = text;
+    }
+
+    /// Creates a renderer drawing the overlay into images of the specified format and size.
+    pub fn create_renderer(self: &Arc<Self>, format: vk::Format, size: Vec2u32) -> OverlayRenderer {
+        OverlayRenderer::new(self.clone(), format, size)
+    }
+
+    fn create_sampler(device: &DeviceContext) -> vk::Sampler {
+        let info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .anisotropy_enable(false)
+            .compare_enable(false)
+            .unnormalized_coordinates(false);
+
+        unsafe {
+            device.vk().create_sampler(&info, None)
+        }.unwrap()
+    }
+
+    fn create_descriptor_set_layout(device: &DeviceContext, sampler: vk::Sampler) -> vk::DescriptorSetLayout {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::VERTEX)
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(1)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .immutable_samplers(std::slice::from_ref(&sampler))
+                .build(),
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(2)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .descriptor_count(1)
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .build(),
+        ];
+
+        let info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&bindings);
+
+        unsafe {
+            device.vk().create_descriptor_set_layout(&info, None)
+        }.unwrap()
+    }
+
+    fn create_pipeline_layout(device: &DeviceContext, set_layout: vk::DescriptorSetLayout) -> vk::PipelineLayout {
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: 0,
+            size: std::mem::size_of::<Vec2f32>() as u32,
+        };
+
+        let info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(std::slice::from_ref(&set_layout))
+            .push_constant_ranges(std::slice::from_ref(&push_constant_range));
+
+        unsafe {
+            device.vk().create_pipeline_layout(&info, None)
+        }.unwrap()
+    }
+}
+
+impl Drop for DebugOverlay {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.vk().destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device.vk().destroy_descriptor_set_layout(self.set_layout, None);
+            self.device.vk().destroy_sampler(self.sampler, None);
+            self.device.vk().destroy_shader_module(self.fragment_shader, None);
+            self.device.vk().destroy_shader_module(self.vertex_shader, None);
+        }
+    }
+}
+
+/// The glyphs written by [`OverlayRenderer::prepare`] for one frame.
+#[derive(Copy, Clone, Debug)]
+pub struct OverlayDraw {
+    slot: usize,
+    glyph_count: u32,
+}
+
+/// Draws the [`DebugOverlay`] into images of a specific format and size.
+///
+/// The render pass is compatible with the blit pass of the same format so the same framebuffers
+/// can be used for both.
+pub struct OverlayRenderer {
+    overlay: Arc<DebugOverlay>,
+    size: Vec2u32,
+    render_pass: vk::RenderPass,
+    pipeline: vk::Pipeline,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    buffer: vk::Buffer,
+    allocation: Allocation,
+    mapped_ptr: NonNull<u8>,
+    next_slot: AtomicUsize,
+}
+
+impl OverlayRenderer {
+    fn new(overlay: Arc<DebugOverlay>, format: vk::Format, size: Vec2u32) -> Self {
+        let device = overlay.device.clone();
+
+        let render_pass = Self::create_render_pass(&device, format);
+        let pipeline = Self::create_pipeline(&overlay, render_pass);
+
+        let info = vk::BufferCreateInfo::builder()
+            .size((SLOTS_OFFSET + SLOT_SIZE * SLOT_COUNT) as vk::DeviceSize)
+            .usage(vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let (buffer, allocation, mapped_ptr) = unsafe {
+            device.get_allocator().create_buffer(&info, HostAccess::Random, &format_args!("DebugOverlayBuffer"))
+        }.unwrap();
+        let mapped_ptr = mapped_ptr.unwrap();
+
+        let framebuffer_size = [size[0] as f32, size[1] as f32];
+        let multipliers = [0f32, 0f32, 1f32, 0f32, 0f32, 1f32, 1f32, 1f32];
+        unsafe {
+            Self::write(mapped_ptr, UNIFORM_OFFSET, cast_slice(&framebuffer_size));
+            Self::write(mapped_ptr, MULTIPLIER_OFFSET, cast_slice(&multipliers));
+        }
+
+        let descriptor_pool = Self::create_descriptor_pool(&device);
+        let descriptor_set = Self::create_descriptor_set(&overlay, descriptor_pool, buffer);
+
+        Self {
+            overlay,
+            size,
+            render_pass,
+            pipeline,
+            descriptor_pool,
+            descriptor_set,
+            buffer,
+            allocation,
+            mapped_ptr,
+            next_slot: AtomicUsize::new(0),
+        }
+    }
+
+    /// Lays out the current overlay text and writes the glyphs into the next buffer slot.
+    pub fn prepare(&self) -> OverlayDraw {
+        let mut glyphs = Vec::new();
+        self.overlay.font.layout_text(&self.overlay.text.lock().unwrap(), Vec2f32::zeros(), Vec4f32::new(1.0, 1.0, 1.0, 1.0), &mut glyphs);
+        if glyphs.len() > MAX_GLYPHS {
+            log::warn!("Debug overlay text has too many glyphs ({}). Only the first {} will be drawn", glyphs.len(), MAX_GLYPHS);
+            glyphs.truncate(MAX_GLYPHS);
+        }
+
+        // Same as the uniform buffer pool we just hope that the slot isn't in use anymore. With 8
+        // slots this is fine for now since we never have that many frames in flight.
+        let slot = self.next_slot.fetch_add(1, Ordering::Relaxed) % SLOT_COUNT;
+        unsafe {
+            Self::write(self.mapped_ptr, SLOTS_OFFSET + slot * SLOT_SIZE, cast_slice(glyphs.as_slice()));
+        }
+
+        OverlayDraw {
+            slot,
+            glyph_count: glyphs.len() as u32,
+        }
+    }
+
+    /// Records the overlay render pass drawing into a framebuffer. No memory barriers are generated.
+    ///
+    /// The framebuffer image must be in the PRESENT_SRC layout and will be left in it.
+    pub fn record(&self, command_buffer: vk::CommandBuffer, framebuffer: vk::Framebuffer, draw: &OverlayDraw) {
+        if draw.glyph_count == 0 {
+            return;
+        }
+
+        let device = &self.overlay.device;
+
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(framebuffer)
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D { width: self.size[0], height: self.size[1] }
+            });
+
+        let viewport = vk::Viewport::builder()
+            .x(0f32)
+            .y(0f32)
+            .width(self.size[0] as f32)
+            .height(self.size[1] as f32)
+            .min_depth(0.0)
+            .max_depth(1.0);
+
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D{ x: 0, y: 0 },
+            extent: vk::Extent2D{ width: self.size[0], height: self.size[1] }
+        };
+
+        let buffers = [self.buffer, self.buffer];
+        let offsets = [(SLOTS_OFFSET + draw.slot * SLOT_SIZE) as vk::DeviceSize, MULTIPLIER_OFFSET as vk::DeviceSize];
+
+        unsafe {
+            device.vk().cmd_set_viewport(command_buffer, 0, std::slice::from_ref(&viewport));
+            device.vk().cmd_set_scissor(command_buffer, 0, std::slice::from_ref(&scissor));
+
+            device.vk().cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+
+            device.vk().cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            device.vk().cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.overlay.pipeline_layout,
+                0,
+                std::slice::from_ref(&self.descriptor_set),
+                &[]
+            );
+            device.vk().cmd_bind_vertex_buffers(command_buffer, 0, &buffers, &offsets);
+            device.vk().cmd_push_constants(command_buffer, self.overlay.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, cast_slice(&TEXT_OFFSET));
+
+            device.vk().cmd_draw(command_buffer, 4, draw.glyph_count, 0, 0);
+
+            device.vk().cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    unsafe fn write(mapped_ptr: NonNull<u8>, offset: usize, data: &[u8]) {
+        let dst = std::slice::from_raw_parts_mut(mapped_ptr.as_ptr().add(offset), data.len());
+        dst.copy_from_slice(data);
+    }
+
+    fn create_render_pass(device: &DeviceContext, format: vk::Format) -> vk::RenderPass {
+        let attachment = vk::AttachmentDescription::builder()
+            .format(format)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::LOAD)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .initial_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR);
+
+        let attachment_reference = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        };
+
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(std::slice::from_ref(&attachment_reference));
+
+        // We draw on top of the blit pass output
+        let dependency = vk::SubpassDependency::builder()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(std::slice::from_ref(&attachment))
+            .subpasses(std::slice::from_ref(&subpass))
+            .dependencies(std::slice::from_ref(&dependency));
+
+        unsafe {
+            device.vk().create_render_pass(&info, None)
+        }.unwrap()
+    }
+
+    fn create_pipeline(overlay: &DebugOverlay, render_pass: vk::RenderPass) -> vk::Pipeline {
+        let vertex_specialization_data = [EM_SIZE];
+        let fragment_specialization_data = [overlay.font.get_px_range(EM_SIZE)];
+        let specialization_entry = vk::SpecializationMapEntry {
+            constant_id: 0,
+            offset: 0,
+            size: 4
+        };
+
+        let vertex_specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(std::slice::from_ref(&specialization_entry))
+            .data(cast_slice(&vertex_specialization_data));
+
+        let fragment_specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(std::slice::from_ref(&specialization_entry))
+            .data(cast_slice(&fragment_specialization_data));
+
+        let shader_stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(overlay.vertex_shader)
+                .name(CStr::from_bytes_with_nul(b"main\0").unwrap())
+                .specialization_info(&vertex_specialization_info)
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(overlay.fragment_shader)
+                .name(CStr::from_bytes_with_nul(b"main\0").unwrap())
+                .specialization_info(&fragment_specialization_info)
+                .build()
+        ];
+
+        let bindings = [
+            vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: std::mem::size_of::<GlyphInstance>() as u32,
+                input_rate: vk::VertexInputRate::INSTANCE
+            },
+            vk::VertexInputBindingDescription {
+                binding: 1,
+                stride: std::mem::size_of::<Vec2f32>() as u32,
+                input_rate: vk::VertexInputRate::VERTEX
+            },
+        ];
+
+        let attributes = [
+            vk::VertexInputAttributeDescription { location: 0, binding: 0, format: vk::Format::R32G32_SFLOAT, offset: 0 },
+            vk::VertexInputAttributeDescription { location: 1, binding: 0, format: vk::Format::R32G32_SFLOAT, offset: 8 },
+            vk::VertexInputAttributeDescription { location: 2, binding: 0, format: vk::Format::R32G32_SFLOAT, offset: 16 },
+            vk::VertexInputAttributeDescription { location: 3, binding: 0, format: vk::Format::R32G32_SFLOAT, offset: 24 },
+            vk::VertexInputAttributeDescription { location: 4, binding: 0, format: vk::Format::R32G32B32A32_SFLOAT, offset: 32 },
+            vk::VertexInputAttributeDescription { location: 5, binding: 0, format: vk::Format::R32_UINT, offset: 48 },
+            vk::VertexInputAttributeDescription { location: 7, binding: 1, format: vk::Format::R32G32_SFLOAT, offset: 0 },
+        ];
+
+        let input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&bindings)
+            .vertex_attribute_descriptions(&attributes);
+
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(vk::PrimitiveTopology::TRIANGLE_STRIP);
+
+        let viewport = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+            .depth_clamp_enable(false)
+            .rasterizer_discard_enable(false)
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(vk::CullModeFlags::NONE)
+            .front_face(vk::FrontFace::CLOCKWISE)
+            .depth_bias_enable(false)
+            .line_width(1.0);
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+            .sample_shading_enable(false);
+
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(false)
+            .depth_write_enable(false);
+
+        // The alpha blend factors keep the output valid for pre multiplied composite alpha
+        let attachment = vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .color_write_mask(vk::ColorComponentFlags::RGBA);
+
+        let color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .attachments(std::slice::from_ref(&attachment));
+
+        let dynamic_states = [
+            vk::DynamicState::VIEWPORT,
+            vk::DynamicState::SCISSOR
+        ];
+
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&dynamic_states);
+
+        let info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&shader_stages)
+            .vertex_input_state(&input_state)
+            .input_assembly_state(&input_assembly)
+            .viewport_state(&viewport)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .color_blend_state(&color_blend)
+            .dynamic_state(&dynamic_state)
+            .layout(overlay.pipeline_layout)
+            .render_pass(render_pass);
+
+        let pipeline = * unsafe {
+            overlay.device.vk().create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), None)
+        }.unwrap().get(0).unwrap();
+
+        pipeline
+    }
+
+    fn create_descriptor_pool(device: &DeviceContext) -> vk::DescriptorPool {
+        let sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_count: 1,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLER,
+                descriptor_count: 1,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::SAMPLED_IMAGE,
+                descriptor_count: 1,
+            },
+        ];
+
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(1)
+            .pool_sizes(&sizes);
+
+        unsafe {
+            device.vk().create_descriptor_pool(&info, None)
+        }.unwrap()
+    }
+
+    fn create_descriptor_set(overlay: &DebugOverlay, pool: vk::DescriptorPool, buffer: vk::Buffer) -> vk::DescriptorSet {
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(pool)
+            .set_layouts(std::slice::from_ref(&overlay.set_layout));
+
+        let set = *unsafe {
+            overlay.device.vk().allocate_descriptor_sets(&info)
+        }.unwrap().get(0).unwrap();
+
+        let buffer_info = vk::DescriptorBufferInfo {
+            buffer,
+            offset: UNIFORM_OFFSET as vk::DeviceSize,
+            range: std::mem::size_of::<Vec2f32>() as vk::DeviceSize,
+        };
+
+        let image_info = vk::DescriptorImageInfo {
+            sampler: vk::Sampler::null(),
+            image_view: overlay.atlas.get_sampler_view(),
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+
+        let writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                .buffer_info(std::slice::from_ref(&buffer_info))
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_set(set)
+                .dst_binding(2)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .image_info(std::slice::from_ref(&image_info))
+                .build(),
+        ];
+
+        unsafe {
+            overlay.device.vk().update_descriptor_sets(&writes, &[])
+        };
+
+        set
+    }
+}
+
+impl Drop for OverlayRenderer {
+    fn drop(&mut self) {
+        let device = &self.overlay.device;
+        unsafe {
+            device.vk().destroy_descriptor_pool(self.descriptor_pool, None);
+            device.get_allocator().destroy_buffer(self.buffer, self.allocation);
+            device.vk().destroy_pipeline(self.pipeline, None);
+            device.vk().destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
+
+// The mapped pointer is only written to through &self in prepare which uses a separate slot for each call
+unsafe impl Send for OverlayRenderer {
+}
+unsafe impl Sync for OverlayRenderer {
+}
+
+static MSDF_FONT_VERTEX_SHADER: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "debug/font/msdf_font_vert.spv"));
+static MSDF_FONT_FRAGMENT_SHADER: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "debug/font/msdf_font_frag.spv"));
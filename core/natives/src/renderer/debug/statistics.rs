@@ -0,0 +1,85 @@
+//! Collection of frame statistics displayed by the debug overlay.
+
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::device::surface::PresentTimingStatistics;
+use crate::renderer::emulator::EmulatorRenderer;
+
+/// The interval in which the statistics text is updated. Frame times are averaged over this interval.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Tracks frame timings and formats them together with the emulator and allocator statistics.
+pub struct StatisticsTracker {
+    last_update: Instant,
+    frame_count: u32,
+    last_uploaded_bytes: u64,
+}
+
+impl StatisticsTracker {
+    pub fn new() -> Self {
+        Self {
+            last_update: Instant::now(),
+            frame_count: 0,
+            last_uploaded_bytes: 0,
+        }
+    }
+
+    /// Must be called once for every started frame.
+    ///
+    /// Returns a new statistics text if the update interval has elapsed since the last update.
+    ///
+    /// Present timings are only displayed if `present_timing` is not [`None`].
+    pub fn on_frame(&mut self, emulator: &EmulatorRenderer, present_timing: Option<PresentTimingStatistics>) -> Option<String> {
+        self.frame_count += 1;
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_update);
+        if elapsed < UPDATE_INTERVAL {
+            return None;
+        }
+
+        let statistics = emulator.get_statistics();
+        let memory = emulator.get_device().get_allocator().get_memory_usage();
+
+        let seconds = elapsed.as_secs_f64();
+        let fps = self.frame_count as f64 / seconds;
+        let upload_rate = (statistics.uploaded_bytes - self.last_uploaded_bytes) as f64 / seconds;
+
+        let mut text = String::new();
+        writeln!(text, "FPS: {:.1}", fps).unwrap();
+        // Wall clock time between frames. This includes waiting for the gpu and presentation
+        writeln!(text, "Frame: {:.2} ms", 1000.0 / fps).unwrap();
+        match statistics.gpu_pass_time {
+            Some(time) => writeln!(text, "GPU: {:.2} ms", time.as_secs_f64() * 1000.0).unwrap(),
+            None => writeln!(text, "GPU: -").unwrap(),
+        }
+        if let Some(present_timing) = present_timing {
+            match present_timing.last_present_margin {
+                Some(margin) => writeln!(text, "Present margin: {:.2} ms", margin.as_secs_f64() * 1000.0).unwrap(),
+                None => writeln!(text, "Present margin: -").unwrap(),
+            }
+            writeln!(text, "Missed vblanks: {} (total {})", present_timing.last_missed_vblanks, present_timing.total_missed_vblanks).unwrap();
+        }
+        writeln!(text, "Draws: {}", statistics.draw_count).unwrap();
+        writeln!(text, "Memory: {:.1} / {:.1} MiB (budget {:.1} MiB)", to_mib(memory.allocated_bytes as f64), to_mib(memory.block_bytes as f64), to_mib(memory.budget_bytes as f64)).unwrap();
+        writeln!(text, "Uniforms: {:.1} MiB peak frame / {:.1} MiB", to_mib(statistics.uniforms.peak_frame_bytes as f64), to_mib(statistics.uniforms.allocated_bytes as f64)).unwrap();
+        write!(text, "Upload: {:.2} MiB/s", to_mib(upload_rate)).unwrap();
+
+        self.last_update = now;
+        self.frame_count = 0;
+        self.last_uploaded_bytes = statistics.uploaded_bytes;
+
+        Some(text)
+    }
+}
+
+impl Default for StatisticsTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn to_mib(bytes: f64) -> f64 {
+    bytes / (1024.0 * 1024.0)
+}
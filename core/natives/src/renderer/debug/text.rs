@@ -0,0 +1,236 @@
+//! Text layout using multi-channel signed distance field fonts.
+//!
+//! Fonts are generated by msdf-atlas-gen and consist of a json file describing the glyphs and a
+//! png atlas image.
+
+use std::collections::HashMap;
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::prelude::*;
+
+/// The data of a single glyph instance as consumed by the msdf font vertex shader.
+///
+/// All box values are in em units relative to the text origin with the y axis pointing down.
+/// Atlas values are normalized texture coordinates.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct GlyphInstance {
+    pub box_offset: Vec2f32,
+    pub box_size: Vec2f32,
+    pub atlas_offset: Vec2f32,
+    pub atlas_size: Vec2f32,
+    pub color: Vec4f32,
+    pub atlas_index: u32,
+}
+
+unsafe impl Zeroable for GlyphInstance {}
+unsafe impl Pod for GlyphInstance {}
+
+#[derive(Copy, Clone, Debug)]
+struct Glyph {
+    advance: f32,
+    quad: Option<GlyphQuad>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct GlyphQuad {
+    box_offset: Vec2f32,
+    box_size: Vec2f32,
+    atlas_offset: Vec2f32,
+    atlas_size: Vec2f32,
+}
+
+#[derive(Debug)]
+pub enum FontLoadError {
+    Json(json::Error),
+    Png(png::DecodingError),
+    InvalidFormat(&'static str),
+}
+
+impl From<json::Error> for FontLoadError {
+    fn from(err: json::Error) -> Self {
+        FontLoadError::Json(err)
+    }
+}
+
+impl From<png::DecodingError> for FontLoadError {
+    fn from(err: png::DecodingError) -> Self {
+        FontLoadError::Png(err)
+    }
+}
+
+/// A msdf font and its atlas image.
+pub struct MsdfFont {
+    glyphs: HashMap<char, Glyph>,
+    fallback: Option<Glyph>,
+    line_height: f32,
+    ascender: f32,
+    distance_range: f32,
+    glyph_size: f32,
+    atlas_size: Vec2u32,
+    atlas_data: Box<[u8]>,
+}
+
+impl MsdfFont {
+    /// Loads a font from a msdf-atlas-gen json description and png atlas.
+    ///
+    /// The atlas data is converted to tightly packed R8G8B8A8 texels.
+    pub fn load(description: &str, atlas: &[u8]) -> Result<Self, FontLoadError> {
+        let description = json::parse(description)?;
+
+        let atlas_info = &description["atlas"];
+        let atlas_size = Vec2u32::new(
+            atlas_info["width"].as_u32().ok_or(FontLoadError::InvalidFormat("Missing atlas width"))?,
+            atlas_info["height"].as_u32().ok_or(FontLoadError::InvalidFormat("Missing atlas height"))?
+        );
+        let distance_range = atlas_info["distanceRange"].as_f32().ok_or(FontLoadError::InvalidFormat("Missing atlas distance range"))?;
+        let glyph_size = atlas_info["size"].as_f32().ok_or(FontLoadError::InvalidFormat("Missing atlas glyph size"))?;
+        let y_origin_bottom = atlas_info["yOrigin"].as_str() == Some("bottom");
+
+        let metrics = &description["metrics"];
+        let line_height = metrics["lineHeight"].as_f32().ok_or(FontLoadError::InvalidFormat("Missing line height"))?;
+        let ascender = metrics["ascender"].as_f32().ok_or(FontLoadError::InvalidFormat("Missing ascender"))?;
+
+        let mut glyphs = HashMap::new();
+        for glyph in description["glyphs"].members() {
+            let unicode = glyph["unicode"].as_u32().and_then(char::from_u32).ok_or(FontLoadError::InvalidFormat("Invalid glyph unicode"))?;
+            let advance = glyph["advance"].as_f32().ok_or(FontLoadError::InvalidFormat("Missing glyph advance"))?;
+
+            let plane = &glyph["planeBounds"];
+            let atlas = &glyph["atlasBounds"];
+            let quad = if plane.is_object() && atlas.is_object() {
+                let plane = Self::parse_bounds(plane)?;
+                let atlas = Self::parse_bounds(atlas)?;
+
+                // Plane bounds use a y up coordinate system while we lay out text y down
+                let box_offset = Vec2f32::new(plane[0], -plane[3]);
+                let box_size = Vec2f32::new(plane[2] - plane[0], plane[3] - plane[1]);
+
+                let atlas_top = if y_origin_bottom { atlas_size[1] as f32 - atlas[3] } else { atlas[1] };
+                let atlas_offset = Vec2f32::new(atlas[0] / atlas_size[0] as f32, atlas_top / atlas_size[1] as f32);
+                let atlas_extent = Vec2f32::new((atlas[2] - atlas[0]) / atlas_size[0] as f32, (atlas[3] - atlas[1]).abs() / atlas_size[1] as f32);
+
+                Some(GlyphQuad {
+                    box_offset,
+                    box_size,
+                    atlas_offset,
+                    atlas_size: atlas_extent,
+                })
+            } else {
+                None
+            };
+
+            glyphs.insert(unicode, Glyph { advance, quad });
+        }
+
+        let fallback = glyphs.get(&char::REPLACEMENT_CHARACTER).or_else(|| glyphs.get(&'?')).cloned();
+        let atlas_data = Self::decode_atlas(atlas, atlas_size)?;
+
+        Ok(Self {
+            glyphs,
+            fallback,
+            line_height,
+            ascender,
+            distance_range,
+            glyph_size,
+            atlas_size,
+            atlas_data,
+        })
+    }
+
+    /// Loads the built in JetBrains Mono font used for debug text.
+    pub fn debug_font() -> Self {
+        let description = std::str::from_utf8(DEBUG_FONT_DESCRIPTION).unwrap();
+        Self::load(description, DEBUG_FONT_ATLAS).unwrap_or_else(|err| {
+            log::error!("Failed to load debug font {:?}", err);
+            panic!()
+        })
+    }
+
+    /// Returns the size of the atlas image in texels.
+    pub fn get_atlas_size(&self) -> Vec2u32 {
+        self.atlas_size
+    }
+
+    /// Returns the atlas image as tightly packed R8G8B8A8 texels.
+    pub fn get_atlas_data(&self) -> &[u8] {
+        &self.atlas_data
+    }
+
+    /// Returns the screen space distance range in pixels when rendering with the specified em size
+    /// in pixels.
+    pub fn get_px_range(&self, em_size: f32) -> f32 {
+        self.distance_range * em_size / self.glyph_size
+    }
+
+    /// Returns the distance between 2 lines in em units.
+    pub fn get_line_height(&self) -> f32 {
+        self.line_height
+    }
+
+    /// Lays out some text and pushes the resulting glyph instances into `instances`.
+    ///
+    /// The origin is the top left corner of the first line in em units. Newlines start a new line.
+    /// Returns the size of the text bounding box in em units.
+    pub fn layout_text(&self, text: &str, origin: Vec2f32, color: Vec4f32, instances: &mut Vec<GlyphInstance>) -> Vec2f32 {
+        let mut cursor = Vec2f32::new(0.0, self.ascender);
+        let mut width = 0f32;
+
+        for c in text.chars() {
+            if c == '\n' {
+                width = width.max(cursor[0]);
+                cursor = Vec2f32::new(0.0, cursor[1] + self.line_height);
+                continue;
+            }
+
+            let glyph = match self.glyphs.get(&c).or(self.fallback.as_ref()) {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+
+            if let Some(quad) = &glyph.quad {
+                instances.push(GlyphInstance {
+                    box_offset: origin + cursor + quad.box_offset,
+                    box_size: quad.box_size,
+                    atlas_offset: quad.atlas_offset,
+                    atlas_size: quad.atlas_size,
+                    color,
+                    atlas_index: 0,
+                });
+            }
+
+            cursor[0] += glyph.advance;
+        }
+        width = width.max(cursor[0]);
+
+        Vec2f32::new(width, cursor[1] - self.ascender + self.line_height)
+    }
+
+    fn parse_bounds(bounds: &json::JsonValue) -> Result<[f32; 4], FontLoadError> {
+        let get = |name: &str| bounds[name].as_f32().ok_or(FontLoadError::InvalidFormat("Invalid glyph bounds"));
+        Ok([get("left")?, get("bottom")?, get("right")?, get("top")?])
+    }
+
+    fn decode_atlas(atlas: &[u8], size: Vec2u32) -> Result<Box<[u8]>, FontLoadError> {
+        let mut reader = png::Decoder::new(atlas).read_info()?;
+        let mut data = vec![0u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+
+        if info.width != size[0] || info.height != size[1] || info.bit_depth != png::BitDepth::Eight {
+            return Err(FontLoadError::InvalidFormat("Atlas image does not match description"));
+        }
+
+        let data = &data[0..info.buffer_size()];
+        let rgba: Box<[u8]> = match info.color_type {
+            png::ColorType::Rgba => data.into(),
+            png::ColorType::Rgb => data.chunks_exact(3).flat_map(|texel| [texel[0], texel[1], texel[2], 255u8]).collect(),
+            _ => return Err(FontLoadError::InvalidFormat("Unsupported atlas color type")),
+        };
+
+        Ok(rgba)
+    }
+}
+
+static DEBUG_FONT_DESCRIPTION: &'static [u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/src/debug/font/JetBrainsMono/tmp_built/regular.json"));
+static DEBUG_FONT_ATLAS: &'static [u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/src/debug/font/JetBrainsMono/tmp_built/regular.png"));
@@ -0,0 +1,636 @@
+//! Stitching of many small sprites into atlas images.
+//!
+//! Sprites are packed into rows (shelves) sorted by height. If a atlas page is full a new page is
+//! started. Every sprite is surrounded by a padding border filled with its own edge texels so that
+//! linear filtering does not bleed neighbouring sprites into each other. If mipmaps are requested
+//! sprite positions and padding are aligned such that every sprite still covers whole texels in all
+//! mip levels. Mip levels are generated using [`MipmapMode::AlphaWeighted`] so that cutout sprites
+//! keep their color at a distance.
+//!
+//! Built atlases can be modified at runtime, for example when resource packs are reloaded. See
+//! [`Atlas`].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, ImageCopyRegion, ImageData, MipmapMode};
+use crate::util::format::Format;
+
+use crate::prelude::*;
+
+#[derive(Debug)]
+pub enum AtlasError {
+    /// The sprite with the given id does not fit into a single page.
+    SpriteTooLarge(AtlasSpriteId, Vec2u32),
+    /// The sprite data does not match its size.
+    InvalidData(AtlasSpriteId),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct AtlasSpriteId(u32);
+
+impl AtlasSpriteId {
+    pub fn get_raw(&self) -> u32 {
+        self.0
+    }
+}
+
+/// The location of a sprite inside a [`Atlas`].
+#[derive(Copy, Clone, Debug)]
+pub struct AtlasSprite {
+    /// The index of the page containing the sprite.
+    pub page: u32,
+
+    /// The offset of the sprite in the page in texels excluding padding.
+    pub offset: Vec2u32,
+    pub size: Vec2u32,
+
+    pub uv_min: Vec2f32,
+    pub uv_max: Vec2f32,
+}
+
+pub struct AtlasBuilder {
+    max_size: u32,
+    padding: u32,
+    mip_levels: u32,
+    sprites: Vec<(Vec2u32, Box<[u8]>)>,
+}
+
+impl AtlasBuilder {
+    /// Creates a new builder.
+    ///
+    /// `max_size` is the maximum width and height of a single page. `padding` is the minimum number
+    /// of texels between a sprite and its neighbours.
+    pub fn new(max_size: u32, padding: u32, mip_levels: u32) -> Self {
+        let max_mip_levels = 32 - max_size.max(1).leading_zeros();
+
+        Self {
+            max_size,
+            padding,
+            mip_levels: mip_levels.clamp(1, max_mip_levels),
+            sprites: Vec::new(),
+        }
+    }
+
+    /// Adds a sprite with tightly packed R8G8B8A8 sRGB data.
+    pub fn add_sprite(&mut self, size: Vec2u32, data: Box<[u8]>) -> AtlasSpriteId {
+        let id = AtlasSpriteId(self.sprites.len() as u32);
+        self.sprites.push((size, data));
+        id
+    }
+
+    /// Packs all sprites and uploads the resulting pages.
+    pub fn build(self, renderer: &EmulatorRenderer) -> Result<Atlas, AtlasError> {
+        for (index, (size, data)) in self.sprites.iter().enumerate() {
+            if data.len() != (size[0] as usize) * (size[1] as usize) * 4 {
+                return Err(AtlasError::InvalidData(AtlasSpriteId(index as u32)));
+            }
+        }
+
+        let sizes: Vec<_> = self.sprites.iter().map(|(size, _)| *size).collect();
+        let layout = pack_sprites(&sizes, self.max_size, self.padding, self.mip_levels)?;
+
+        let mut pages = Vec::with_capacity(layout.page_sizes.len());
+        for (page_index, page_size) in layout.page_sizes.iter().enumerate() {
+            let mut data = vec![0u8; (page_size[0] as usize) * (page_size[1] as usize) * 4];
+            for (placement, (size, sprite_data)) in layout.placements.iter().zip(self.sprites.iter()) {
+                if placement.page as usize == page_index {
+                    write_sprite(&mut data, page_size[0], placement.offset, *size, layout.padding, sprite_data);
+                }
+            }
+
+            let image = Atlas::create_page(renderer, *page_size, self.mip_levels);
+            image.update_regions(std::slice::from_ref(&ImageData::new_full(&data, *page_size)));
+            image.generate_mipmaps();
+            pages.push(image);
+        }
+
+        let sprites = layout.placements.iter().zip(sizes.iter()).map(|(placement, size)| {
+            Some(make_sprite(*placement, *size, layout.page_sizes[placement.page as usize]))
+        }).collect();
+
+        Ok(Atlas {
+            mip_levels: self.mip_levels,
+            packer: layout.packer,
+            pages,
+            sprites,
+            remap_callback: None,
+        })
+    }
+}
+
+/// A sprite whose location changed because its page was resized or the atlas was defragmented.
+#[derive(Copy, Clone, Debug)]
+pub struct AtlasRemap {
+    pub id: AtlasSpriteId,
+    pub old: AtlasSprite,
+    pub new: AtlasSprite,
+}
+
+/// A set of atlas pages and the locations of all sprites in them.
+///
+/// Sprites can be added and removed after the atlas has been built. New sprites are placed into
+/// free space of the existing pages which grow up to the maximum page size if needed. The space of
+/// removed sprites is only reclaimed by [`Atlas::defragment`]. Whenever existing sprites move the
+/// remap callback is called with their old and new location so that the host can update any uv
+/// coordinates it has already handed out. All contents are moved on the gpu, the sprite data does
+/// not need to be retained.
+pub struct Atlas {
+    mip_levels: u32,
+    packer: Packer,
+    pages: Vec<Arc<GlobalImage>>,
+
+    /// Indexed by sprite id. [`None`] if the sprite has been removed.
+    sprites: Vec<Option<AtlasSprite>>,
+    remap_callback: Option<Box<dyn Fn(&[AtlasRemap]) + Send + Sync>>,
+}
+
+impl Atlas {
+    pub fn get_pages(&self) -> &[Arc<GlobalImage>] {
+        &self.pages
+    }
+
+    pub fn get_page(&self, index: u32) -> &Arc<GlobalImage> {
+        &self.pages[index as usize]
+    }
+
+    pub fn get_sprite(&self, id: AtlasSpriteId) -> &AtlasSprite {
+        self.sprites[id.0 as usize].as_ref().unwrap_or_else(|| {
+            log::error!("Attempted to get removed atlas sprite {:?}", id);
+            panic!()
+        })
+    }
+
+    /// Sets the callback called with all sprites which moved after sprites are added or the atlas
+    /// is defragmented.
+    pub fn set_remap_callback(&mut self, callback: Box<dyn Fn(&[AtlasRemap]) + Send + Sync>) {
+        self.remap_callback = Some(callback);
+    }
+
+    /// Adds a sprite with tightly packed R8G8B8A8 sRGB data. See [`Atlas::add_sprites`].
+    pub fn add_sprite(&mut self, renderer: &EmulatorRenderer, size: Vec2u32, data: &[u8]) -> Result<AtlasSpriteId, AtlasError> {
+        self.add_sprites(renderer, &[(size, data)]).map(|ids| ids[0])
+    }
+
+    /// Adds sprites with tightly packed R8G8B8A8 sRGB data. Prefer adding many sprites at once as
+    /// the mipmaps of every modified page are regenerated once per call.
+    ///
+    /// Pages which run out of space grow up to the maximum page size after which new pages are
+    /// created. Sprites of grown pages keep their texel offset but their uv coordinates change and
+    /// are passed to the remap callback. If any sprite is invalid no sprite is added.
+    pub fn add_sprites(&mut self, renderer: &EmulatorRenderer, sprites: &[(Vec2u32, &[u8])]) -> Result<Vec<AtlasSpriteId>, AtlasError> {
+        let first_id = self.sprites.len() as u32;
+        for (index, (size, data)) in sprites.iter().enumerate() {
+            let id = AtlasSpriteId(first_id + index as u32);
+            if data.len() != (size[0] as usize) * (size[1] as usize) * 4 {
+                return Err(AtlasError::InvalidData(id));
+            }
+            if !self.packer.fits(*size) {
+                return Err(AtlasError::SpriteTooLarge(id, *size));
+            }
+        }
+
+        let mut order: Vec<_> = (0..sprites.len()).collect();
+        order.sort_by(|a, b| sprites[*b].0[1].cmp(&sprites[*a].0[1]).then(sprites[*b].0[0].cmp(&sprites[*a].0[0])));
+
+        let mut placements = vec![Placement { page: 0, offset: Vec2u32::new(0, 0) }; sprites.len()];
+        for index in order {
+            placements[index] = self.packer.place(sprites[index].0).unwrap();
+        }
+
+        let mut remaps = Vec::new();
+        let mut modified_pages = Vec::new();
+        for page in 0..self.packer.pages.len() {
+            let required_size = self.packer.get_page_size(page as u32);
+            if page >= self.pages.len() {
+                self.pages.push(Self::create_page(renderer, required_size, self.mip_levels));
+            } else if self.pages[page].get_size() != required_size {
+                self.grow_page(renderer, page as u32, required_size, &mut remaps);
+            } else {
+                continue;
+            }
+            modified_pages.push(page as u32);
+        }
+
+        let padding = self.packer.padding;
+        for (placement, (size, data)) in placements.iter().zip(sprites.iter()) {
+            if size[0] == 0 || size[1] == 0 {
+                continue;
+            }
+
+            // Upload the sprite together with its padding border
+            let padded_size = Vec2u32::new(size[0] + padding * 2, size[1] + padding * 2);
+            let mut padded = vec![0u8; (padded_size[0] as usize) * (padded_size[1] as usize) * 4];
+            write_sprite(&mut padded, padded_size[0], Vec2u32::new(padding, padding), *size, padding, data);
+
+            let offset = placement.offset - Vec2u32::new(padding, padding);
+            self.pages[placement.page as usize].update_regions(std::slice::from_ref(&ImageData::new_extent(&padded, offset, padded_size)));
+            if !modified_pages.contains(&placement.page) {
+                modified_pages.push(placement.page);
+            }
+        }
+
+        for page in modified_pages {
+            self.pages[page as usize].generate_mipmaps();
+        }
+
+        let ids = placements.iter().zip(sprites.iter()).enumerate().map(|(index, (placement, (size, _)))| {
+            let page_size = self.pages[placement.page as usize].get_size();
+            self.sprites.push(Some(make_sprite(*placement, *size, page_size)));
+            AtlasSpriteId(first_id + index as u32)
+        }).collect();
+
+        self.notify_remaps(&remaps);
+
+        Ok(ids)
+    }
+
+    /// Removes a sprite. Its space is only reused after [`Atlas::defragment`].
+    pub fn remove_sprite(&mut self, id: AtlasSpriteId) {
+        if self.sprites.get_mut(id.0 as usize).and_then(Option::take).is_none() {
+            log::warn!("Attempted to remove unknown atlas sprite {:?}", id);
+        }
+    }
+
+    /// Returns the fraction of the allocated page space which is not used by any sprite because
+    /// the sprite was removed. Can be used to decide when to call [`Atlas::defragment`].
+    pub fn get_fragmentation(&self) -> f32 {
+        let allocated = self.packer.get_allocated_area();
+        if allocated == 0 {
+            return 0.0;
+        }
+
+        let used: u64 = self.sprites.iter().flatten().map(|sprite| {
+            let cell = self.packer.get_cell_size(sprite.size);
+            (cell[0] as u64) * (cell[1] as u64)
+        }).sum();
+
+        1.0 - (used as f32 / allocated as f32)
+    }
+
+    /// Repacks all remaining sprites into new pages removing the space of removed sprites. The
+    /// sprites are copied on the gpu and all sprites are passed to the remap callback. Sprite ids
+    /// stay valid.
+    pub fn defragment(&mut self, renderer: &EmulatorRenderer) {
+        let live: Vec<_> = self.sprites.iter().enumerate().filter_map(|(index, sprite)| sprite.map(|sprite| (AtlasSpriteId(index as u32), sprite))).collect();
+
+        let sizes: Vec<_> = live.iter().map(|(_, sprite)| sprite.size).collect();
+        let layout = pack_sprites(&sizes, self.packer.max_size, self.packer.padding, self.mip_levels).unwrap_or_else(|err| {
+            log::error!("Failed to repack atlas sprites which have already been packed {:?}", err);
+            panic!()
+        });
+
+        let pages: Vec<_> = layout.page_sizes.iter().map(|size| Self::create_page(renderer, *size, self.mip_levels)).collect();
+
+        let padding = layout.padding;
+        let mut copies: HashMap<(u32, u32), Vec<ImageCopyRegion>> = HashMap::new();
+        let mut remaps = Vec::with_capacity(live.len());
+        for ((id, old), placement) in live.iter().zip(layout.placements.iter()) {
+            if old.size[0] != 0 && old.size[1] != 0 {
+                copies.entry((old.page, placement.page)).or_default().push(ImageCopyRegion {
+                    mip_level: 0,
+                    src_offset: old.offset - Vec2u32::new(padding, padding),
+                    dst_offset: placement.offset - Vec2u32::new(padding, padding),
+                    extent: Vec2u32::new(old.size[0] + padding * 2, old.size[1] + padding * 2),
+                });
+            }
+
+            let new = make_sprite(*placement, old.size, layout.page_sizes[placement.page as usize]);
+            self.sprites[id.0 as usize] = Some(new);
+            remaps.push(AtlasRemap {
+                id: *id,
+                old: *old,
+                new,
+            });
+        }
+
+        for ((src, dst), regions) in copies {
+            pages[dst as usize].copy_regions_from(&self.pages[src as usize], &regions);
+        }
+        for page in &pages {
+            page.generate_mipmaps();
+        }
+
+        self.pages = pages;
+        self.packer = layout.packer;
+
+        self.notify_remaps(&remaps);
+    }
+
+    /// Replaces a page with a larger page and copies the old contents to the same texel offset.
+    fn grow_page(&mut self, renderer: &EmulatorRenderer, page: u32, size: Vec2u32, remaps: &mut Vec<AtlasRemap>) {
+        let old_page = &self.pages[page as usize];
+        let new_page = Self::create_page(renderer, size, self.mip_levels);
+        new_page.copy_regions_from(old_page, &[ImageCopyRegion {
+            mip_level: 0,
+            src_offset: Vec2u32::new(0, 0),
+            dst_offset: Vec2u32::new(0, 0),
+            extent: old_page.get_size(),
+        }]);
+        self.pages[page as usize] = new_page;
+
+        for (index, sprite) in self.sprites.iter_mut().enumerate() {
+            if let Some(sprite) = sprite.as_mut().filter(|sprite| sprite.page == page) {
+                let old = *sprite;
+                *sprite = make_sprite(Placement { page, offset: old.offset }, old.size, size);
+                remaps.push(AtlasRemap {
+                    id: AtlasSpriteId(index as u32),
+                    old,
+                    new: *sprite,
+                });
+            }
+        }
+    }
+
+    fn notify_remaps(&self, remaps: &[AtlasRemap]) {
+        if remaps.is_empty() {
+            return;
+        }
+        if let Some(callback) = &self.remap_callback {
+            callback(remaps);
+        }
+    }
+
+    fn create_page(renderer: &EmulatorRenderer, size: Vec2u32, mip_levels: u32) -> Arc<GlobalImage> {
+        renderer.create_global_image_mips_with_mode(size, mip_levels, MipmapMode::AlphaWeighted, &Format::R8G8B8A8_SRGB)
+    }
+}
+
+fn make_sprite(placement: Placement, size: Vec2u32, page_size: Vec2u32) -> AtlasSprite {
+    let page_size = Vec2f32::new(page_size[0] as f32, page_size[1] as f32);
+
+    AtlasSprite {
+        page: placement.page,
+        offset: placement.offset,
+        size,
+        uv_min: Vec2f32::new(placement.offset[0] as f32 / page_size[0], placement.offset[1] as f32 / page_size[1]),
+        uv_max: Vec2f32::new((placement.offset[0] + size[0]) as f32 / page_size[0], (placement.offset[1] + size[1]) as f32 / page_size[1]),
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Placement {
+    page: u32,
+    offset: Vec2u32,
+}
+
+struct Layout {
+    padding: u32,
+    page_sizes: Vec<Vec2u32>,
+    placements: Vec<Placement>,
+    packer: Packer,
+}
+
+/// A row of cells in a page.
+#[derive(Copy, Clone, Debug)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    cursor_x: u32,
+}
+
+#[derive(Clone, Debug)]
+struct PageAllocator {
+    shelves: Vec<Shelf>,
+
+    /// The extent of all allocated cells.
+    used: Vec2u32,
+
+    /// The total area of all allocated cells in texels.
+    allocated_area: u64,
+}
+
+impl PageAllocator {
+    fn new() -> Self {
+        Self {
+            shelves: Vec::new(),
+            used: Vec2u32::new(0, 0),
+            allocated_area: 0,
+        }
+    }
+
+    /// Allocates a cell in the first shelf with enough space or starts a new shelf. Returns the
+    /// offset of the cell.
+    fn allocate(&mut self, cell: Vec2u32, max_size: u32) -> Option<Vec2u32> {
+        let offset = if let Some(shelf) = self.shelves.iter_mut().find(|shelf| cell[1] <= shelf.height && shelf.cursor_x + cell[0] <= max_size) {
+            let offset = Vec2u32::new(shelf.cursor_x, shelf.y);
+            shelf.cursor_x += cell[0];
+            offset
+        } else {
+            let y = self.shelves.last().map_or(0, |shelf| shelf.y + shelf.height);
+            if y + cell[1] > max_size {
+                return None;
+            }
+            self.shelves.push(Shelf {
+                y,
+                height: cell[1],
+                cursor_x: cell[0],
+            });
+            Vec2u32::new(0, y)
+        };
+
+        self.used = Vec2u32::new(self.used[0].max(offset[0] + cell[0]), self.used[1].max(offset[1] + cell[1]));
+        self.allocated_area += (cell[0] as u64) * (cell[1] as u64);
+        Some(offset)
+    }
+}
+
+/// Packs sprites into shelves of pages. Keeps the state of all pages so that sprites can be added
+/// after the initial packing.
+#[derive(Clone, Debug)]
+struct Packer {
+    max_size: u32,
+    padding: u32,
+    alignment: u32,
+    pages: Vec<PageAllocator>,
+}
+
+impl Packer {
+    fn new(max_size: u32, padding: u32, mip_levels: u32) -> Self {
+        let alignment = 1u32 << (mip_levels - 1);
+
+        Self {
+            max_size,
+            padding: align_up(padding, alignment),
+            alignment,
+            pages: Vec::new(),
+        }
+    }
+
+    fn get_cell_size(&self, size: Vec2u32) -> Vec2u32 {
+        Vec2u32::new(align_up(size[0] + self.padding * 2, self.alignment), align_up(size[1] + self.padding * 2, self.alignment))
+    }
+
+    fn fits(&self, size: Vec2u32) -> bool {
+        let cell = self.get_cell_size(size);
+        cell[0] <= self.max_size && cell[1] <= self.max_size
+    }
+
+    /// Places a sprite into the first page with enough space. Returns [`None`] if the sprite does
+    /// not fit into a single page. The returned offset excludes the padding.
+    fn place(&mut self, size: Vec2u32) -> Option<Placement> {
+        if !self.fits(size) {
+            return None;
+        }
+        let cell = self.get_cell_size(size);
+
+        let existing = self.pages.iter_mut().enumerate().find_map(|(page, allocator)| {
+            allocator.allocate(cell, self.max_size).map(|offset| (page, offset))
+        });
+        let (page, offset) = existing.unwrap_or_else(|| {
+            let mut allocator = PageAllocator::new();
+            let offset = allocator.allocate(cell, self.max_size).unwrap();
+            self.pages.push(allocator);
+            (self.pages.len() - 1, offset)
+        });
+
+        Some(Placement {
+            page: page as u32,
+            offset: offset + Vec2u32::new(self.padding, self.padding),
+        })
+    }
+
+    /// Returns the size of the image required to contain all cells of the page.
+    fn get_page_size(&self, page: u32) -> Vec2u32 {
+        page_size(self.pages[page as usize].used)
+    }
+
+    fn get_allocated_area(&self) -> u64 {
+        self.pages.iter().map(|page| page.allocated_area).sum()
+    }
+}
+
+/// Packs the sprites into shelves sorted by height. The returned offsets exclude the padding.
+fn pack_sprites(sizes: &[Vec2u32], max_size: u32, padding: u32, mip_levels: u32) -> Result<Layout, AtlasError> {
+    let mut packer = Packer::new(max_size, padding, mip_levels);
+
+    let mut order: Vec<_> = (0..sizes.len()).collect();
+    order.sort_by(|a, b| sizes[*b][1].cmp(&sizes[*a][1]).then(sizes[*b][0].cmp(&sizes[*a][0])));
+
+    let mut placements = vec![Placement { page: 0, offset: Vec2u32::new(0, 0) }; sizes.len()];
+    for index in order {
+        placements[index] = packer.place(sizes[index]).ok_or(AtlasError::SpriteTooLarge(AtlasSpriteId(index as u32), sizes[index]))?;
+    }
+
+    let page_sizes = (0..packer.pages.len()).map(|page| packer.get_page_size(page as u32)).collect();
+
+    Ok(Layout {
+        padding: packer.padding,
+        page_sizes,
+        placements,
+        packer,
+    })
+}
+
+/// Pages are rounded up to a power of 2 so that every mip level halves cleanly.
+fn page_size(used: Vec2u32) -> Vec2u32 {
+    Vec2u32::new(used[0].max(1).next_power_of_two(), used[1].max(1).next_power_of_two())
+}
+
+fn align_up(value: u32, alignment: u32) -> u32 {
+    ((value + alignment - 1) / alignment) * alignment
+}
+
+/// Writes a sprite and its padding border filled with the nearest edge texels.
+fn write_sprite(page: &mut [u8], page_width: u32, offset: Vec2u32, size: Vec2u32, padding: u32, data: &[u8]) {
+    if size[0] == 0 || size[1] == 0 {
+        return;
+    }
+
+    let padded_width = size[0] + padding * 2;
+    let padded_height = size[1] + padding * 2;
+    for y in 0..padded_height {
+        let src_y = y.saturating_sub(padding).min(size[1] - 1);
+        let dst_y = offset[1] - padding + y;
+        for x in 0..padded_width {
+            let src_x = x.saturating_sub(padding).min(size[0] - 1);
+            let dst_x = offset[0] - padding + x;
+
+            let src = ((src_y * size[0] + src_x) * 4) as usize;
+            let dst = ((dst_y * page_width + dst_x) * 4) as usize;
+            page[dst..dst + 4].copy_from_slice(&data[src..src + 4]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: (Placement, Vec2u32), b: (Placement, Vec2u32)) -> bool {
+        a.0.page == b.0.page &&
+            a.0.offset[0] < b.0.offset[0] + b.1[0] && b.0.offset[0] < a.0.offset[0] + a.1[0] &&
+            a.0.offset[1] < b.0.offset[1] + b.1[1] && b.0.offset[1] < a.0.offset[1] + a.1[1]
+    }
+
+    #[test]
+    fn packed_sprites_do_not_overlap() {
+        let sizes: Vec<_> = (1..40u32).map(|i| Vec2u32::new(i % 7 + 4, i % 5 + 3)).collect();
+        let layout = pack_sprites(&sizes, 64, 1, 1).unwrap();
+
+        for (i, a) in layout.placements.iter().enumerate() {
+            let page_size = layout.page_sizes[a.page as usize];
+            assert!(a.offset[0] + sizes[i][0] + 1 <= page_size[0]);
+            assert!(a.offset[1] + sizes[i][1] + 1 <= page_size[1]);
+
+            for (j, b) in layout.placements.iter().enumerate().skip(i + 1) {
+                let padded = |p: &Placement, s: Vec2u32| (Placement { page: p.page, offset: p.offset - Vec2u32::new(1, 1) }, s + Vec2u32::new(2, 2));
+                assert!(!overlaps(padded(a, sizes[i]), padded(b, sizes[j])));
+            }
+        }
+    }
+
+    #[test]
+    fn overflow_creates_new_pages() {
+        let sizes = vec![Vec2u32::new(16, 16); 5];
+        let layout = pack_sprites(&sizes, 32, 0, 1).unwrap();
+        assert_eq!(layout.page_sizes.len(), 2);
+        assert_eq!(layout.page_sizes[1], Vec2u32::new(16, 16));
+    }
+
+    #[test]
+    fn mip_aligned_offsets() {
+        let sizes = vec![Vec2u32::new(5, 3), Vec2u32::new(16, 16), Vec2u32::new(7, 9)];
+        let layout = pack_sprites(&sizes, 256, 1, 4).unwrap();
+        assert_eq!(layout.padding, 8);
+        for placement in &layout.placements {
+            assert_eq!(placement.offset[0] % 8, 0);
+            assert_eq!(placement.offset[1] % 8, 0);
+        }
+    }
+
+    #[test]
+    fn sprite_too_large() {
+        let sizes = vec![Vec2u32::new(8, 8), Vec2u32::new(64, 8)];
+        assert!(matches!(pack_sprites(&sizes, 64, 1, 1), Err(AtlasError::SpriteTooLarge(AtlasSpriteId(1), _))));
+    }
+
+    #[test]
+    fn incremental_placement_grows_pages() {
+        let mut layout = pack_sprites(&[Vec2u32::new(16, 16)], 32, 0, 1).unwrap();
+        assert_eq!(layout.page_sizes, vec![Vec2u32::new(16, 16)]);
+
+        // Smaller sprites reuse the existing shelf and grow the page
+        let placement = layout.packer.place(Vec2u32::new(8, 8)).unwrap();
+        assert_eq!(placement, Placement { page: 0, offset: Vec2u32::new(16, 0) });
+        assert_eq!(layout.packer.get_page_size(0), Vec2u32::new(32, 16));
+
+        let placement = layout.packer.place(Vec2u32::new(16, 16)).unwrap();
+        assert_eq!(placement, Placement { page: 0, offset: Vec2u32::new(0, 16) });
+        assert_eq!(layout.packer.get_page_size(0), Vec2u32::new(32, 32));
+
+        // The page is full so a new page is started
+        let placement = layout.packer.place(Vec2u32::new(32, 8)).unwrap();
+        assert_eq!(placement.page, 1);
+        assert_eq!(layout.packer.get_allocated_area(), 16 * 16 * 2 + 8 * 8 + 32 * 8);
+    }
+
+    #[test]
+    fn padding_extends_edges() {
+        let data = [1u8, 1, 1, 1, 2, 2, 2, 2];
+        let mut page = vec![0u8; 4 * 3 * 4];
+        write_sprite(&mut page, 4, Vec2u32::new(1, 1), Vec2u32::new(2, 1), 1, &data);
+        let row: Vec<_> = page.chunks_exact(4).map(|texel| texel[0]).collect();
+        assert_eq!(row, vec![1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2]);
+    }
+}
@@ -0,0 +1,292 @@
+//! Batching of pipeline barriers.
+//!
+//! Recording every barrier with its own `vkCmdPipelineBarrier2` call produces a large number of
+//! tiny commands. The [`BarrierBatcher`] collects barriers until the next command depending on
+//! them is recorded and emits them with as few calls as possible. Barriers for the same resource
+//! range are merged into a single barrier and barriers which do not synchronize anything are
+//! dropped.
+
+use ash::vk;
+
+use crate::prelude::*;
+
+pub(super) struct BarrierBatcher {
+    buffer_barriers: Vec<vk::BufferMemoryBarrier2>,
+    image_barriers: Vec<vk::ImageMemoryBarrier2>,
+
+    /// The start indices into the buffer and image barrier lists of every batch after the first.
+    /// Barriers within a batch never overlap. If a barrier overlaps a previous barrier of the
+    /// current batch and cannot be merged with it a new batch is started which is emitted by a
+    /// separate call.
+    batch_starts: Vec<(usize, usize)>,
+}
+
+impl BarrierBatcher {
+    /// If too many barriers are passed in a single command the driver may fail to record (Yes this
+    /// limit has been hit at 4000 barriers during testing in minecraft)
+    const CHUNK_SIZE: usize = 256;
+
+    pub(super) fn new() -> Self {
+        Self {
+            buffer_barriers: Vec::new(),
+            image_barriers: Vec::new(),
+            batch_starts: Vec::new(),
+        }
+    }
+
+    pub(super) fn push_buffer_barrier(&mut self, barrier: vk::BufferMemoryBarrier2) {
+        if Self::is_redundant(barrier.src_stage_mask, barrier.dst_stage_mask, None) {
+            return;
+        }
+
+        let batch_start = self.batch_starts.last().map(|(start, _)| *start).unwrap_or(0);
+        let end = get_buffer_end(&barrier);
+
+        for pending in &mut self.buffer_barriers[batch_start..] {
+            if pending.buffer != barrier.buffer || pending.src_queue_family_index != barrier.src_queue_family_index || pending.dst_queue_family_index != barrier.dst_queue_family_index {
+                continue;
+            }
+
+            let pending_end = get_buffer_end(pending);
+            if pending.offset < end && barrier.offset < pending_end {
+                if pending.offset == barrier.offset && pending_end == end {
+                    merge_masks_buffer(pending, &barrier);
+                } else {
+                    self.start_batch();
+                    self.buffer_barriers.push(barrier);
+                }
+                return;
+            }
+        }
+
+        // Staging ranges are usually allocated back to back so adjacent barriers with the same
+        // access can be combined
+        for pending in &mut self.buffer_barriers[batch_start..] {
+            if pending.buffer == barrier.buffer && has_same_masks_buffer(pending, &barrier) && pending.size != vk::WHOLE_SIZE && barrier.size != vk::WHOLE_SIZE {
+                if pending.offset + pending.size == barrier.offset {
+                    pending.size += barrier.size;
+                    return;
+                }
+                if barrier.offset + barrier.size == pending.offset {
+                    pending.offset = barrier.offset;
+                    pending.size += barrier.size;
+                    return;
+                }
+            }
+        }
+
+        self.buffer_barriers.push(barrier);
+    }
+
+    pub(super) fn push_image_barrier(&mut self, barrier: vk::ImageMemoryBarrier2) {
+        if Self::is_redundant(barrier.src_stage_mask, barrier.dst_stage_mask, Some((barrier.old_layout, barrier.new_layout))) {
+            return;
+        }
+
+        let batch_start = self.batch_starts.last().map(|(_, start)| *start).unwrap_or(0);
+
+        for pending in &mut self.image_barriers[batch_start..] {
+            if pending.image != barrier.image || !ranges_overlap(&pending.subresource_range, &barrier.subresource_range) {
+                continue;
+            }
+
+            if ranges_equal(&pending.subresource_range, &barrier.subresource_range) && pending.new_layout == barrier.old_layout && pending.src_queue_family_index == barrier.src_queue_family_index && pending.dst_queue_family_index == barrier.dst_queue_family_index {
+                merge_masks_image(pending, &barrier);
+                pending.new_layout = barrier.new_layout;
+            } else {
+                self.start_batch();
+                self.image_barriers.push(barrier);
+            }
+            return;
+        }
+
+        self.image_barriers.push(barrier);
+    }
+
+    /// Records all pending barriers into the command buffer.
+    pub(super) fn flush(&mut self, device: &DeviceContext, cmd: vk::CommandBuffer) {
+        let mut batch_starts = std::mem::take(&mut self.batch_starts);
+        batch_starts.push((self.buffer_barriers.len(), self.image_barriers.len()));
+
+        let mut start = (0, 0);
+        for end in batch_starts {
+            self.record_batch(device, cmd, &self.buffer_barriers[start.0..end.0], &self.image_barriers[start.1..end.1]);
+            start = end;
+        }
+
+        self.buffer_barriers.clear();
+        self.image_barriers.clear();
+    }
+
+    fn record_batch(&self, device: &DeviceContext, cmd: vk::CommandBuffer, buffer_barriers: &[vk::BufferMemoryBarrier2], image_barriers: &[vk::ImageMemoryBarrier2]) {
+        let chunk_count = std::cmp::max(
+            (buffer_barriers.len() + Self::CHUNK_SIZE - 1) / Self::CHUNK_SIZE,
+            (image_barriers.len() + Self::CHUNK_SIZE - 1) / Self::CHUNK_SIZE
+        );
+
+        for chunk in 0..chunk_count {
+            let min = chunk * Self::CHUNK_SIZE;
+            let max = min + Self::CHUNK_SIZE;
+            let mut info = vk::DependencyInfo::builder();
+            if min < buffer_barriers.len() {
+                let max = std::cmp::min(max, buffer_barriers.len());
+                info = info.buffer_memory_barriers(&buffer_barriers[min..max]);
+            }
+            if min < image_barriers.len() {
+                let max = std::cmp::min(max, image_barriers.len());
+                info = info.image_memory_barriers(&image_barriers[min..max]);
+            }
+
+            unsafe {
+                device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &info);
+            }
+        }
+    }
+
+    fn start_batch(&mut self) {
+        self.batch_starts.push((self.buffer_barriers.len(), self.image_barriers.len()));
+    }
+
+    /// A barrier without a layout transition whose source or destination scope is empty does not
+    /// synchronize anything.
+    fn is_redundant(src_stage_mask: vk::PipelineStageFlags2, dst_stage_mask: vk::PipelineStageFlags2, layouts: Option<(vk::ImageLayout, vk::ImageLayout)>) -> bool {
+        let has_transition = layouts.map(|(old, new)| old != new).unwrap_or(false);
+        !has_transition && (src_stage_mask.is_empty() || dst_stage_mask.is_empty())
+    }
+}
+
+fn get_buffer_end(barrier: &vk::BufferMemoryBarrier2) -> vk::DeviceSize {
+    if barrier.size == vk::WHOLE_SIZE {
+        vk::DeviceSize::MAX
+    } else {
+        barrier.offset + barrier.size
+    }
+}
+
+fn has_same_masks_buffer(a: &vk::BufferMemoryBarrier2, b: &vk::BufferMemoryBarrier2) -> bool {
+    a.src_stage_mask == b.src_stage_mask && a.src_access_mask == b.src_access_mask &&
+        a.dst_stage_mask == b.dst_stage_mask && a.dst_access_mask == b.dst_access_mask &&
+        a.src_queue_family_index == b.src_queue_family_index && a.dst_queue_family_index == b.dst_queue_family_index
+}
+
+/// Combines 2 barriers with no commands recorded between them. All commands before the second
+/// barrier are also before the first and all commands after the first are also after the second so
+/// the union of both scopes is used.
+fn merge_masks_buffer(dst: &mut vk::BufferMemoryBarrier2, src: &vk::BufferMemoryBarrier2) {
+    dst.src_stage_mask |= src.src_stage_mask;
+    dst.src_access_mask |= src.src_access_mask;
+    dst.dst_stage_mask |= src.dst_stage_mask;
+    dst.dst_access_mask |= src.dst_access_mask;
+}
+
+fn merge_masks_image(dst: &mut vk::ImageMemoryBarrier2, src: &vk::ImageMemoryBarrier2) {
+    dst.src_stage_mask |= src.src_stage_mask;
+    dst.src_access_mask |= src.src_access_mask;
+    dst.dst_stage_mask |= src.dst_stage_mask;
+    dst.dst_access_mask |= src.dst_access_mask;
+}
+
+fn ranges_equal(a: &vk::ImageSubresourceRange, b: &vk::ImageSubresourceRange) -> bool {
+    a.aspect_mask == b.aspect_mask &&
+        a.base_mip_level == b.base_mip_level && a.level_count == b.level_count &&
+        a.base_array_layer == b.base_array_layer && a.layer_count == b.layer_count
+}
+
+fn ranges_overlap(a: &vk::ImageSubresourceRange, b: &vk::ImageSubresourceRange) -> bool {
+    fn overlap(a_base: u32, a_count: u32, b_base: u32, b_count: u32) -> bool {
+        let a_end = if a_count == vk::REMAINING_MIP_LEVELS { u32::MAX } else { a_base + a_count };
+        let b_end = if b_count == vk::REMAINING_MIP_LEVELS { u32::MAX } else { b_base + b_count };
+        a_base < b_end && b_base < a_end
+    }
+
+    a.aspect_mask.intersects(b.aspect_mask) &&
+        overlap(a.base_mip_level, a.level_count, b.base_mip_level, b.level_count) &&
+        overlap(a.base_array_layer, a.layer_count, b.base_array_layer, b.layer_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer_barrier(offset: vk::DeviceSize, size: vk::DeviceSize, dst_access: vk::AccessFlags2) -> vk::BufferMemoryBarrier2 {
+        vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::VERTEX_INPUT)
+            .dst_access_mask(dst_access)
+            .buffer(vk::Handle::from_raw(1))
+            .offset(offset)
+            .size(size)
+            .build()
+    }
+
+    fn image_barrier(level: u32, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) -> vk::ImageMemoryBarrier2 {
+        vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .image(vk::Handle::from_raw(1))
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: level,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1
+            })
+            .build()
+    }
+
+    #[test]
+    fn merge_buffer_barriers() {
+        let mut batcher = BarrierBatcher::new();
+
+        batcher.push_buffer_barrier(buffer_barrier(0, 64, vk::AccessFlags2::VERTEX_ATTRIBUTE_READ));
+        batcher.push_buffer_barrier(buffer_barrier(64, 64, vk::AccessFlags2::VERTEX_ATTRIBUTE_READ));
+        batcher.push_buffer_barrier(buffer_barrier(0, 128, vk::AccessFlags2::INDEX_READ));
+        assert_eq!(batcher.buffer_barriers.len(), 1);
+        assert_eq!(batcher.buffer_barriers[0].size, 128);
+        assert_eq!(batcher.buffer_barriers[0].dst_access_mask, vk::AccessFlags2::VERTEX_ATTRIBUTE_READ | vk::AccessFlags2::INDEX_READ);
+
+        // Adjacent but different access
+        batcher.push_buffer_barrier(buffer_barrier(128, 64, vk::AccessFlags2::INDEX_READ));
+        assert_eq!(batcher.buffer_barriers.len(), 2);
+        assert!(batcher.batch_starts.is_empty());
+
+        // Partially overlapping barriers must be emitted separately
+        batcher.push_buffer_barrier(buffer_barrier(32, 64, vk::AccessFlags2::INDEX_READ));
+        assert_eq!(batcher.buffer_barriers.len(), 3);
+        assert_eq!(batcher.batch_starts, vec![(2, 0)]);
+
+        // Nothing to synchronize
+        let mut empty = buffer_barrier(0, vk::WHOLE_SIZE, vk::AccessFlags2::NONE);
+        empty.dst_stage_mask = vk::PipelineStageFlags2::NONE;
+        batcher.push_buffer_barrier(empty);
+        assert_eq!(batcher.buffer_barriers.len(), 3);
+    }
+
+    #[test]
+    fn merge_image_barriers() {
+        let mut batcher = BarrierBatcher::new();
+
+        batcher.push_image_barrier(image_barrier(0, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL));
+        batcher.push_image_barrier(image_barrier(0, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL));
+        batcher.push_image_barrier(image_barrier(1, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL));
+        assert_eq!(batcher.image_barriers.len(), 2);
+        assert_eq!(batcher.image_barriers[0].old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(batcher.image_barriers[0].new_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+        // Layouts do not chain
+        batcher.push_image_barrier(image_barrier(1, vk::ImageLayout::GENERAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL));
+        assert_eq!(batcher.image_barriers.len(), 3);
+        assert_eq!(batcher.batch_starts, vec![(0, 2)]);
+
+        // Same layout with an empty scope
+        let mut empty = image_barrier(2, vk::ImageLayout::GENERAL, vk::ImageLayout::GENERAL);
+        empty.src_stage_mask = vk::PipelineStageFlags2::NONE;
+        batcher.push_image_barrier(empty);
+        assert_eq!(batcher.image_barriers.len(), 3);
+    }
+}
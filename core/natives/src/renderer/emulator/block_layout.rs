@@ -0,0 +1,256 @@
+//! Layout of image data for texel block formats.
+//!
+//! Copies between buffers and images are specified in texels even for block compressed formats. The
+//! offset and extent of every copy must be aligned to the texel blocks of the format unless the
+//! region touches the edge of the mip level and the buffer row length must be a whole number of
+//! blocks. [`BlockLayout`] computes these values for BCn, ETC2, EAC and ASTC formats as well as
+//! uncompressed formats (which use 1x1 blocks) and validates upload regions before they are recorded.
+
+use ash::vk;
+
+use crate::prelude::*;
+use crate::renderer::emulator::readback::get_texel_size;
+use crate::renderer::emulator::ImageData;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RegionError {
+    /// The format has no known block layout.
+    UnsupportedFormat(vk::Format),
+
+    /// The region targets a mip level which does not exist.
+    MipLevelOutOfRange(u32),
+
+    /// The region has a width or height of 0.
+    EmptyRegion,
+
+    /// The region extends past the edge of the mip level.
+    OutOfBounds,
+
+    /// The offset of the region is not a multiple of the block extent.
+    UnalignedOffset,
+
+    /// The extent of the region is not a multiple of the block extent and does not reach the edge
+    /// of the mip level.
+    UnalignedExtent,
+
+    /// The row stride is not a multiple of the block width.
+    UnalignedRowStride,
+
+    /// The row stride is smaller than the width of the region.
+    RowStrideTooSmall,
+
+    /// The region data is smaller than the number of bytes the copy reads.
+    DataTooSmall { required: u64, provided: u64 },
+}
+
+/// The size of the texel blocks of a format.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BlockLayout {
+    width: u32,
+    height: u32,
+    bytes: u32,
+}
+
+impl BlockLayout {
+    pub const fn new(width: u32, height: u32, bytes: u32) -> Self {
+        Self { width, height, bytes }
+    }
+
+    /// Returns the block layout of the color aspect of a format or [`None`] if the format is not
+    /// supported.
+    pub fn for_format(format: vk::Format) -> Option<Self> {
+        match format {
+            vk::Format::BC1_RGB_UNORM_BLOCK |
+            vk::Format::BC1_RGB_SRGB_BLOCK |
+            vk::Format::BC1_RGBA_UNORM_BLOCK |
+            vk::Format::BC1_RGBA_SRGB_BLOCK |
+            vk::Format::BC4_UNORM_BLOCK |
+            vk::Format::BC4_SNORM_BLOCK => Some(Self::new(4, 4, 8)),
+            vk::Format::BC2_UNORM_BLOCK |
+            vk::Format::BC2_SRGB_BLOCK |
+            vk::Format::BC3_UNORM_BLOCK |
+            vk::Format::BC3_SRGB_BLOCK |
+            vk::Format::BC5_UNORM_BLOCK |
+            vk::Format::BC5_SNORM_BLOCK |
+            vk::Format::BC6H_UFLOAT_BLOCK |
+            vk::Format::BC6H_SFLOAT_BLOCK |
+            vk::Format::BC7_UNORM_BLOCK |
+            vk::Format::BC7_SRGB_BLOCK => Some(Self::new(4, 4, 16)),
+            vk::Format::ETC2_R8G8B8_UNORM_BLOCK |
+            vk::Format::ETC2_R8G8B8_SRGB_BLOCK |
+            vk::Format::ETC2_R8G8B8A1_UNORM_BLOCK |
+            vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK |
+            vk::Format::EAC_R11_UNORM_BLOCK |
+            vk::Format::EAC_R11_SNORM_BLOCK => Some(Self::new(4, 4, 8)),
+            vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK |
+            vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK |
+            vk::Format::EAC_R11G11_UNORM_BLOCK |
+            vk::Format::EAC_R11G11_SNORM_BLOCK => Some(Self::new(4, 4, 16)),
+            vk::Format::ASTC_4X4_UNORM_BLOCK | vk::Format::ASTC_4X4_SRGB_BLOCK => Some(Self::new(4, 4, 16)),
+            vk::Format::ASTC_5X4_UNORM_BLOCK | vk::Format::ASTC_5X4_SRGB_BLOCK => Some(Self::new(5, 4, 16)),
+            vk::Format::ASTC_5X5_UNORM_BLOCK | vk::Format::ASTC_5X5_SRGB_BLOCK => Some(Self::new(5, 5, 16)),
+            vk::Format::ASTC_6X5_UNORM_BLOCK | vk::Format::ASTC_6X5_SRGB_BLOCK => Some(Self::new(6, 5, 16)),
+            vk::Format::ASTC_6X6_UNORM_BLOCK | vk::Format::ASTC_6X6_SRGB_BLOCK => Some(Self::new(6, 6, 16)),
+            vk::Format::ASTC_8X5_UNORM_BLOCK | vk::Format::ASTC_8X5_SRGB_BLOCK => Some(Self::new(8, 5, 16)),
+            vk::Format::ASTC_8X6_UNORM_BLOCK | vk::Format::ASTC_8X6_SRGB_BLOCK => Some(Self::new(8, 6, 16)),
+            vk::Format::ASTC_8X8_UNORM_BLOCK | vk::Format::ASTC_8X8_SRGB_BLOCK => Some(Self::new(8, 8, 16)),
+            vk::Format::ASTC_10X5_UNORM_BLOCK | vk::Format::ASTC_10X5_SRGB_BLOCK => Some(Self::new(10, 5, 16)),
+            vk::Format::ASTC_10X6_UNORM_BLOCK | vk::Format::ASTC_10X6_SRGB_BLOCK => Some(Self::new(10, 6, 16)),
+            vk::Format::ASTC_10X8_UNORM_BLOCK | vk::Format::ASTC_10X8_SRGB_BLOCK => Some(Self::new(10, 8, 16)),
+            vk::Format::ASTC_10X10_UNORM_BLOCK | vk::Format::ASTC_10X10_SRGB_BLOCK => Some(Self::new(10, 10, 16)),
+            vk::Format::ASTC_12X10_UNORM_BLOCK | vk::Format::ASTC_12X10_SRGB_BLOCK => Some(Self::new(12, 10, 16)),
+            vk::Format::ASTC_12X12_UNORM_BLOCK | vk::Format::ASTC_12X12_SRGB_BLOCK => Some(Self::new(12, 12, 16)),
+            _ => get_texel_size(format, vk::ImageAspectFlags::COLOR).map(|size| Self::new(1, 1, size)),
+        }
+    }
+
+    /// Returns the size of a block in texels.
+    pub fn get_block_extent(&self) -> Vec2u32 {
+        Vec2u32::new(self.width, self.height)
+    }
+
+    /// Returns the size of a block in bytes.
+    pub fn get_block_byte_size(&self) -> u32 {
+        self.bytes
+    }
+
+    /// Returns true if blocks contain more than one texel.
+    pub fn is_compressed(&self) -> bool {
+        self.width != 1 || self.height != 1
+    }
+
+    /// Returns the number of blocks needed to store a region of the specified size in texels.
+    pub fn get_block_count(&self, size: Vec2u32) -> Vec2u32 {
+        Vec2u32::new((size[0] + self.width - 1) / self.width, (size[1] + self.height - 1) / self.height)
+    }
+
+    /// Rounds a size in texels up to a whole number of blocks.
+    pub fn get_block_aligned_extent(&self, size: Vec2u32) -> Vec2u32 {
+        let blocks = self.get_block_count(size);
+        Vec2u32::new(blocks[0] * self.width, blocks[1] * self.height)
+    }
+
+    /// Returns the buffer row length in texels of tightly packed data with the specified width.
+    pub fn get_row_length(&self, width: u32) -> u32 {
+        self.get_block_count(Vec2u32::new(width, 1))[0] * self.width
+    }
+
+    /// Returns the number of bytes of tightly packed data of the specified size in texels.
+    pub fn get_level_byte_size(&self, size: Vec2u32) -> u64 {
+        let blocks = self.get_block_count(size);
+        (blocks[0] as u64) * (blocks[1] as u64) * (self.bytes as u64)
+    }
+
+    /// Returns the byte offset of every mip level of a tightly packed mip chain starting with the
+    /// base level.
+    pub fn get_mip_offsets(&self, size: Vec2u32, level_count: u32) -> Box<[u64]> {
+        let mut offset = 0;
+        (0..level_count).map(|level| {
+            let current = offset;
+            offset += self.get_level_byte_size(get_mip_size(size, level));
+            current
+        }).collect()
+    }
+
+    /// Returns the number of bytes a copy of the region reads from its data.
+    pub fn get_region_byte_size(&self, region: &ImageData<'_>) -> u64 {
+        let row_length = if region.row_stride == 0 { region.extent[0] } else { region.row_stride };
+        let row_blocks = self.get_block_count(Vec2u32::new(row_length, 1))[0] as u64;
+        let blocks = self.get_block_count(region.extent);
+        if blocks[1] == 0 {
+            return 0;
+        }
+
+        ((blocks[1] as u64 - 1) * row_blocks + (blocks[0] as u64)) * (self.bytes as u64)
+    }
+
+    /// Validates that a region can be copied into a mip level of the specified size.
+    pub fn validate_region(&self, level_size: Vec2u32, region: &ImageData<'_>) -> Result<(), RegionError> {
+        if region.extent[0] == 0 || region.extent[1] == 0 {
+            return Err(RegionError::EmptyRegion);
+        }
+
+        let end_x = region.offset[0].checked_add(region.extent[0]).ok_or(RegionError::OutOfBounds)?;
+        let end_y = region.offset[1].checked_add(region.extent[1]).ok_or(RegionError::OutOfBounds)?;
+        if end_x > level_size[0] || end_y > level_size[1] {
+            return Err(RegionError::OutOfBounds);
+        }
+
+        if region.offset[0] % self.width != 0 || region.offset[1] % self.height != 0 {
+            return Err(RegionError::UnalignedOffset);
+        }
+        if (region.extent[0] % self.width != 0 && end_x != level_size[0]) || (region.extent[1] % self.height != 0 && end_y != level_size[1]) {
+            return Err(RegionError::UnalignedExtent);
+        }
+
+        if region.row_stride != 0 {
+            if region.row_stride % self.width != 0 {
+                return Err(RegionError::UnalignedRowStride);
+            }
+            if region.row_stride < region.extent[0] {
+                return Err(RegionError::RowStrideTooSmall);
+            }
+        }
+
+        let required = self.get_region_byte_size(region);
+        let provided = region.data.len() as u64;
+        if provided < required {
+            return Err(RegionError::DataTooSmall { required, provided });
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the size of a mip level of a image with the specified base size.
+pub fn get_mip_size(size: Vec2u32, level: u32) -> Vec2u32 {
+    Vec2u32::new(std::cmp::max(size[0] >> level, 1), std::cmp::max(size[1] >> level, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_sizes() {
+        let bc1 = BlockLayout::for_format(vk::Format::BC1_RGBA_UNORM_BLOCK).unwrap();
+        assert_eq!(bc1.get_level_byte_size(Vec2u32::new(10, 6)), 3 * 2 * 8);
+        assert_eq!(bc1.get_row_length(10), 12);
+        assert_eq!(bc1.get_block_aligned_extent(Vec2u32::new(1, 1)), Vec2u32::new(4, 4));
+
+        let astc = BlockLayout::for_format(vk::Format::ASTC_10X6_SRGB_BLOCK).unwrap();
+        assert_eq!(astc.get_block_count(Vec2u32::new(21, 6)), Vec2u32::new(3, 1));
+
+        let etc2 = BlockLayout::for_format(vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK).unwrap();
+        assert_eq!(etc2.get_block_byte_size(), 16);
+
+        assert!(!BlockLayout::for_format(vk::Format::R8G8B8A8_UNORM).unwrap().is_compressed());
+        assert_eq!(BlockLayout::for_format(vk::Format::R8G8B8_SSCALED), None);
+    }
+
+    #[test]
+    fn mip_offsets() {
+        let bc3 = BlockLayout::for_format(vk::Format::BC3_UNORM_BLOCK).unwrap();
+        // 16x8 -> 8x4 -> 4x2 -> 2x1, the last two levels use a single block each
+        assert_eq!(&*bc3.get_mip_offsets(Vec2u32::new(16, 8), 4), &[0, 128, 160, 176]);
+    }
+
+    #[test]
+    fn region_validation() {
+        let bc1 = BlockLayout::for_format(vk::Format::BC1_RGB_UNORM_BLOCK).unwrap();
+        let data = [0u8; 256];
+        let level_size = Vec2u32::new(18, 8);
+
+        assert_eq!(bc1.validate_region(level_size, &ImageData::new_extent(&data, Vec2u32::new(4, 4), Vec2u32::new(8, 4))), Ok(()));
+        assert_eq!(bc1.validate_region(level_size, &ImageData::new_extent(&data, Vec2u32::new(2, 0), Vec2u32::new(8, 4))), Err(RegionError::UnalignedOffset));
+        assert_eq!(bc1.validate_region(level_size, &ImageData::new_extent(&data, Vec2u32::new(0, 0), Vec2u32::new(6, 4))), Err(RegionError::UnalignedExtent));
+        assert_eq!(bc1.validate_region(level_size, &ImageData::new_extent(&data, Vec2u32::new(16, 0), Vec2u32::new(4, 4))), Err(RegionError::OutOfBounds));
+
+        // Partial blocks are allowed at the edge of the mip level
+        assert_eq!(bc1.validate_region(level_size, &ImageData::new_extent(&data, Vec2u32::new(16, 4), Vec2u32::new(2, 4))), Ok(()));
+
+        assert_eq!(bc1.validate_region(level_size, &ImageData::new_extent_with_stride(&data, 6, Vec2u32::new(0, 0), Vec2u32::new(4, 4))), Err(RegionError::UnalignedRowStride));
+        assert_eq!(bc1.validate_region(level_size, &ImageData::new_extent_with_stride(&data, 4, Vec2u32::new(0, 0), Vec2u32::new(8, 4))), Err(RegionError::RowStrideTooSmall));
+        assert_eq!(bc1.validate_region(level_size, &ImageData::new_full(&data[0..32], level_size)), Err(RegionError::DataTooSmall { required: 5 * 2 * 8, provided: 32 }));
+    }
+}
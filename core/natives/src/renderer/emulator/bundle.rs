@@ -0,0 +1,305 @@
+//! Reusable draw bundles.
+//!
+//! A [`DrawBundle`] contains draws and uniform updates recorded once by a [`DrawBundleRecorder`]
+//! which can be replayed in any number of later passes using
+//! [`PassRecorder::draw_bundle`](super::PassRecorder::draw_bundle). This is useful for content
+//! which does not change between frames, for example gui panels or static entities, since
+//! replaying a bundle only has to copy its prepared tasks.
+//!
+//! Immediate meshes uploaded to a bundle recorder are stored in global meshes since immediate
+//! buffers only live for a single pass. Model view matrices set by the bundle are relative to the
+//! matrix stack of the pass recorder the bundle is drawn in.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::renderer::emulator::{GlobalImage, GlobalMesh, ImmediateMeshId, MeshData};
+use crate::renderer::emulator::global_objects::{GlobalImageId, SamplerInfo};
+use crate::renderer::emulator::matrix_stack::MatrixStack;
+use crate::renderer::emulator::mc_shaders::{McUniformData, ShaderId, VertexFormat};
+use crate::renderer::emulator::pass::{DrawState, get_render_layer, make_global_draw_task, uses_model_view};
+use crate::renderer::emulator::pipeline::{BlendState, CullState, DepthBias, DepthTest, DrawTask, PipelineTask};
+use crate::renderer::emulator::quad_indices::QuadIndexBuffer;
+use crate::renderer::emulator::recorder_state::PendingUniforms;
+use crate::renderer::emulator::render_layer::{RenderLayer, RenderLayerId};
+use crate::renderer::emulator::share::Share;
+use crate::renderer::emulator::tessellator::{Tessellator, TessellatorMode};
+
+/// Records the draws of a [`DrawBundle`].
+///
+/// Created by [`EmulatorRenderer::create_bundle_recorder`](super::EmulatorRenderer::create_bundle_recorder).
+/// The fixed function state, matrix stack and render layer of the pass recorder are not inherited,
+/// all draws use the state set on this recorder. Immediate mesh ids are only valid inside the
+/// recorder that created them.
+pub struct DrawBundleRecorder {
+    share: Arc<Share>,
+    quad_indices: Arc<QuadIndexBuffer>,
+
+    tasks: Vec<PipelineTask>,
+    used_shaders: HashSet<ShaderId>,
+    used_global_images: HashMap<GlobalImageId, Arc<GlobalImage>>,
+    textures: Vec<(ShaderId, u32, Arc<GlobalImage>, SamplerInfo)>,
+    meshes: Vec<Arc<GlobalMesh>>,
+    immediate_meshes: Vec<Arc<GlobalMesh>>,
+    draw_count: u32,
+    draw_state: DrawState,
+    current_layer: Option<RenderLayerId>,
+    matrix_stack: MatrixStack,
+    model_view_versions: HashMap<ShaderId, u64>,
+    pending_uniforms: PendingUniforms,
+}
+
+impl DrawBundleRecorder {
+    pub(super) fn new(share: Arc<Share>, quad_indices: Arc<QuadIndexBuffer>) -> Self {
+        Self {
+            share,
+            quad_indices,
+
+            tasks: Vec::new(),
+            used_shaders: HashSet::new(),
+            used_global_images: HashMap::new(),
+            textures: Vec::new(),
+            meshes: Vec::new(),
+            immediate_meshes: Vec::new(),
+            draw_count: 0,
+            draw_state: DrawState::new(),
+            current_layer: None,
+            matrix_stack: MatrixStack::new(),
+            model_view_versions: HashMap::new(),
+            pending_uniforms: PendingUniforms::new(),
+        }
+    }
+
+    /// Sets a uniform of the shader. Model view matrices are multiplied with the matrix stack of
+    /// the pass recorder when the bundle is drawn.
+    pub fn update_uniform(&mut self, data: &McUniformData, shader: ShaderId) {
+        self.used_shaders.insert(shader);
+        if let McUniformData::ModelViewMatrix(_) = data {
+            self.model_view_versions.remove(&shader);
+        }
+        self.pending_uniforms.set_uniform(shader, data);
+    }
+
+    pub fn update_texture(&mut self, index: u32, image: &Arc<GlobalImage>, sampler_info: &SamplerInfo, shader: ShaderId) {
+        self.used_shaders.insert(shader);
+        let view = image.get_sampler_view();
+        let sampler = image.get_sampler(sampler_info);
+
+        self.used_global_images.entry(image.get_id()).or_insert_with(|| image.clone());
+        self.textures.push((shader, index, image.clone(), *sampler_info));
+        self.current_layer = None;
+        self.tasks.push(PipelineTask::UpdateTexture(shader, index, view, sampler));
+    }
+
+    /// Returns the model view matrix stack of this recorder. See
+    /// [`DrawRecorder::get_matrix_stack`](super::DrawRecorder::get_matrix_stack).
+    pub fn get_matrix_stack(&mut self) -> &mut MatrixStack {
+        &mut self.matrix_stack
+    }
+
+    /// See [`DrawRecorder::push_matrix`](super::DrawRecorder::push_matrix).
+    pub fn push_matrix(&mut self) {
+        self.matrix_stack.push();
+    }
+
+    /// See [`DrawRecorder::pop_matrix`](super::DrawRecorder::pop_matrix).
+    pub fn pop_matrix(&mut self) {
+        self.matrix_stack.pop();
+    }
+
+    /// Sets the scissor rectangle used by all following draws of this bundle.
+    pub fn set_scissor(&mut self, scissor: Option<vk::Rect2D>) {
+        self.draw_state.scissor = scissor;
+    }
+
+    /// Sets the viewport used by all following draws of this bundle. See
+    /// [`DrawRecorder::set_viewport`](super::DrawRecorder::set_viewport).
+    pub fn set_viewport(&mut self, viewport: Option<vk::Rect2D>) {
+        self.draw_state.viewport = viewport;
+    }
+
+    pub fn set_blend_state(&mut self, blend_state: Option<BlendState>) {
+        self.draw_state.blend_state = blend_state;
+    }
+
+    pub fn set_logic_op(&mut self, logic_op: Option<vk::LogicOp>) {
+        self.draw_state.logic_op = logic_op;
+    }
+
+    pub fn set_color_write_mask(&mut self, color_write_mask: vk::ColorComponentFlags) {
+        self.draw_state.color_write_mask = color_write_mask;
+    }
+
+    pub fn set_depth_bias(&mut self, depth_bias: Option<DepthBias>) {
+        self.draw_state.depth_bias = depth_bias;
+    }
+
+    pub fn set_depth_test(&mut self, depth_test: DepthTest) {
+        self.draw_state.depth_test = depth_test;
+    }
+
+    pub fn set_cull_state(&mut self, cull_state: CullState) {
+        self.draw_state.cull_state = cull_state;
+    }
+
+    pub fn set_cull_enable(&mut self, cull_enable: bool) {
+        self.set_cull_state(CullState::from_enable(cull_enable));
+    }
+
+    /// See [`DrawRecorder::set_object_id`](super::DrawRecorder::set_object_id).
+    pub fn set_object_id(&mut self, object_id: u32) {
+        self.draw_state.object_id = object_id;
+    }
+
+    /// Uploads the mesh into a global mesh owned by the bundle.
+    pub fn upload_immediate(&mut self, data: &MeshData<'_>) -> ImmediateMeshId {
+        let mesh = GlobalMesh::new(self.share.clone(), data, &self.quad_indices).unwrap();
+
+        let id = self.immediate_meshes.len() as u32;
+        self.immediate_meshes.push(mesh);
+
+        ImmediateMeshId::form_raw(id)
+    }
+
+    /// See [`DrawRecorder::begin`](super::DrawRecorder::begin).
+    pub fn begin(&mut self, format: &VertexFormat, mode: TessellatorMode) -> Tessellator<'_, Self> {
+        Tessellator::new(self, format, mode)
+    }
+
+    pub fn draw_immediate(&mut self, id: ImmediateMeshId, shader: ShaderId, depth_write_enable: bool) {
+        let mesh = self.immediate_meshes.get(id.get_raw() as usize).unwrap().clone();
+        self.draw_global(mesh, shader, depth_write_enable);
+    }
+
+    pub fn draw_global(&mut self, mesh: Arc<GlobalMesh>, shader: ShaderId, depth_write_enable: bool) {
+        self.used_shaders.insert(shader);
+        self.apply_matrix_stack(shader);
+
+        let draw_task = make_global_draw_task(&mesh, shader, depth_write_enable, &self.draw_state);
+
+        self.draw_count += 1;
+        self.meshes.push(mesh);
+        self.push_draw(draw_task);
+    }
+
+    /// See [`DrawRecorder::draw_immediate_layer`](super::DrawRecorder::draw_immediate_layer).
+    pub fn draw_immediate_layer(&mut self, id: ImmediateMeshId, layer: RenderLayerId) {
+        let mesh = self.immediate_meshes.get(id.get_raw() as usize).unwrap().clone();
+        self.draw_global_layer(mesh, layer);
+    }
+
+    /// See [`DrawRecorder::draw_global_layer`](super::DrawRecorder::draw_global_layer).
+    pub fn draw_global_layer(&mut self, mesh: Arc<GlobalMesh>, layer: RenderLayerId) {
+        let layer = self.use_render_layer(layer);
+        let info = layer.get_info();
+        self.used_shaders.insert(info.shader);
+        self.apply_matrix_stack(info.shader);
+        layer.validate_topology(mesh.get_draw_info().primitive_topology);
+
+        let draw_task = make_global_draw_task(&mesh, info.shader, info.depth_write_enable, &self.draw_state.with_layer(info));
+
+        self.draw_count += 1;
+        self.meshes.push(mesh);
+        self.push_draw(draw_task);
+    }
+
+    /// Finishes recording. Uniforms which have been set after the last draw using their shader
+    /// remain set after the bundle has been drawn.
+    pub fn finish(mut self) -> DrawBundle {
+        for (shader, data) in self.pending_uniforms.take_all() {
+            self.tasks.push(PipelineTask::UpdateUniform(shader, data));
+        }
+        self.meshes.sort_by_key(|mesh| mesh.get_id());
+        self.meshes.dedup();
+
+        DrawBundle {
+            tasks: self.tasks,
+            used_shaders: self.used_shaders,
+            used_global_images: self.used_global_images.into_values().collect(),
+            textures: self.textures,
+            meshes: self.meshes,
+            draw_count: self.draw_count,
+        }
+    }
+
+    /// See [`DrawRecorder::apply_matrix_stack`](super::DrawRecorder::apply_matrix_stack). Unlike
+    /// pass recorders the uploaded matrix is relative to the matrix stack of the pass the bundle is
+    /// drawn in.
+    fn apply_matrix_stack(&mut self, shader: ShaderId) {
+        let version = self.matrix_stack.get_version();
+        if version == 0 || self.model_view_versions.get(&shader) == Some(&version) {
+            return;
+        }
+
+        if uses_model_view(&self.share, shader) {
+            let model_view = self.matrix_stack.get_model_view();
+            self.update_uniform(&McUniformData::ModelViewMatrix(model_view), shader);
+        }
+        self.model_view_versions.insert(shader, version);
+    }
+
+    /// See [`DrawRecorder::push_draw`](super::DrawRecorder::push_draw).
+    fn push_draw(&mut self, draw_task: DrawTask) {
+        for data in self.pending_uniforms.take_shader(draw_task.shader) {
+            self.tasks.push(PipelineTask::UpdateUniform(draw_task.shader, data));
+        }
+        self.tasks.push(PipelineTask::Draw(draw_task));
+    }
+
+    /// See [`DrawRecorder::use_render_layer`](super::DrawRecorder::use_render_layer).
+    fn use_render_layer(&mut self, id: RenderLayerId) -> Arc<RenderLayer> {
+        let layer = get_render_layer(&self.share, id);
+        if self.current_layer != Some(id) {
+            let info = layer.get_info();
+            for texture in &info.textures {
+                self.update_texture(texture.index, &texture.image, &texture.sampler_info, info.shader);
+            }
+            self.current_layer = Some(id);
+        }
+        layer
+    }
+}
+
+/// Draws and uniform updates recorded by a [`DrawBundleRecorder`]. Keeps all meshes and images
+/// used by the bundle alive. See [`PassRecorder::draw_bundle`](super::PassRecorder::draw_bundle).
+pub struct DrawBundle {
+    tasks: Vec<PipelineTask>,
+    used_shaders: HashSet<ShaderId>,
+    used_global_images: Vec<Arc<GlobalImage>>,
+    textures: Vec<(ShaderId, u32, Arc<GlobalImage>, SamplerInfo)>,
+    meshes: Vec<Arc<GlobalMesh>>,
+    draw_count: u32,
+}
+
+impl DrawBundle {
+    pub fn get_draw_count(&self) -> u32 {
+        self.draw_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    pub(super) fn get_tasks(&self) -> &[PipelineTask] {
+        &self.tasks
+    }
+
+    pub(super) fn get_used_shaders(&self) -> &HashSet<ShaderId> {
+        &self.used_shaders
+    }
+
+    pub(super) fn get_used_global_images(&self) -> &[Arc<GlobalImage>] {
+        &self.used_global_images
+    }
+
+    /// Returns the textures bound by the bundle in the order they have been bound.
+    pub(super) fn get_textures(&self) -> &[(ShaderId, u32, Arc<GlobalImage>, SamplerInfo)] {
+        &self.textures
+    }
+
+    pub(super) fn get_meshes(&self) -> &[Arc<GlobalMesh>] {
+        &self.meshes
+    }
+}
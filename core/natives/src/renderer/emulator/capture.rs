@@ -0,0 +1,1184 @@
+//! Capturing of the commands recorded into a single pass.
+//!
+//! A [`FrameCapture`] contains all shaders, uniform updates, texture bindings and mesh data used by
+//! a pass such that it can be written to a file and replayed later. Global image contents are not
+//! captured, only their size and format.
+//!
+//! Global mesh data is only available if it has been retained when the mesh was created. See
+//! [`EmulatorRenderer::set_retain_capture_data`](super::EmulatorRenderer::set_retain_capture_data).
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use ash::vk;
+
+use crate::prelude::*;
+use crate::renderer::emulator::global_objects::{GlobalImageId, GlobalMeshId};
+use crate::renderer::emulator::gpu_culling::CulledSection;
+use crate::renderer::emulator::{GlobalImage, GlobalMesh, MeshData, SamplerInfo};
+use crate::renderer::emulator::pipeline::{BlendState, ClearConfig, CullState, DepthBias, DepthTest, ScreenEffects};
+use crate::renderer::emulator::mc_shaders::{InstanceFormat, McUniform, McUniformData, Shader, ShaderId, VertexFormat, VertexFormatEntry};
+use crate::util::format::Format;
+
+const CAPTURE_MAGIC: &[u8; 8] = b"B4DFCAP\0";
+const CAPTURE_VERSION: u32 = 11;
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(std::io::Error),
+    InvalidFormat(&'static str),
+}
+
+impl From<std::io::Error> for CaptureError {
+    fn from(err: std::io::Error) -> Self {
+        CaptureError::Io(err)
+    }
+}
+
+/// Owned copy of [`MeshData`].
+#[derive(Clone, Debug)]
+pub struct CapturedMesh {
+    pub vertex_data: Box<[u8]>,
+    pub index_data: Box<[u8]>,
+    pub vertex_stride: u32,
+    pub index_count: u32,
+    pub index_type: vk::IndexType,
+    pub primitive_topology: vk::PrimitiveTopology,
+}
+
+impl CapturedMesh {
+    pub fn from_mesh_data(data: &MeshData<'_>) -> Self {
+        Self {
+            vertex_data: data.vertex_data.into(),
+            index_data: data.index_data.into(),
+            vertex_stride: data.vertex_stride,
+            index_count: data.index_count,
+            index_type: data.index_type,
+            primitive_topology: data.primitive_topology,
+        }
+    }
+
+    pub fn as_mesh_data(&self) -> MeshData<'_> {
+        MeshData {
+            vertex_data: &self.vertex_data,
+            index_data: &self.index_data,
+            vertex_stride: self.vertex_stride,
+            index_count: self.index_count,
+            index_type: self.index_type,
+            primitive_topology: self.primitive_topology,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct CapturedShader {
+    pub vertex_format: VertexFormat,
+    pub instance_format: Option<InstanceFormat>,
+    pub used_uniforms: McUniform,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct CapturedImage {
+    pub size: Vec2u32,
+    pub format: vk::Format,
+}
+
+/// A single command recorded into a pass. Shaders, images and meshes are referenced by their index
+/// in the corresponding list of the [`FrameCapture`].
+///
+/// Immediate meshes are referenced by the order in which they have been uploaded.
+#[derive(Clone, Debug)]
+pub enum CaptureCommand {
+    UpdateUniform { shader: u32, data: McUniformData },
+    UpdateTexture { shader: u32, index: u32, image: u32, sampler: SamplerInfo },
+    UploadImmediate(CapturedMesh),
+    DrawImmediate { mesh: u32, shader: u32, depth_write_enable: bool },
+    DrawGlobal { mesh: u32, shader: u32, depth_write_enable: bool },
+    DrawGlobalInstanced { mesh: u32, shader: u32, instance_data: Box<[u8]>, instance_count: u32, first_instance: u32, depth_write_enable: bool },
+    DrawGlobalCulled { mesh: u32, shader: u32, sections: Box<[CulledSection]>, view_projection: Mat4f32, depth_write_enable: bool },
+    UpdateBoneMatrices { shader: u32, matrices: Box<[Mat4f32]> },
+    SetScissor(Option<vk::Rect2D>),
+    SetBlendState(Option<BlendState>),
+    SetLogicOp(Option<vk::LogicOp>),
+    SetDepthBias(Option<DepthBias>),
+    SetCullState(CullState),
+    SetScreenEffects(ScreenEffects),
+    SetViewport(Option<vk::Rect2D>),
+    ClearDepth(Option<vk::Rect2D>),
+    SetViewProjections([Mat4f32; 2]),
+    SetObjectId(u32),
+    SetClearConfig(ClearConfig),
+    SetColorWriteMask(vk::ColorComponentFlags),
+    SetDepthTest(DepthTest),
+}
+
+/// All data necessary to replay a single pass.
+#[derive(Clone, Debug)]
+pub struct FrameCapture {
+    pub output_size: Vec2u32,
+    pub shaders: Vec<CapturedShader>,
+    pub images: Vec<CapturedImage>,
+
+    /// The data of global meshes. [`None`] if the mesh data was not retained.
+    pub global_meshes: Vec<Option<CapturedMesh>>,
+    pub commands: Vec<CaptureCommand>,
+}
+
+impl FrameCapture {
+    pub fn write_to_file(&self, path: &Path) -> Result<(), CaptureError> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn read_from_file(path: &Path) -> Result<Self, CaptureError> {
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        Self::read(&mut reader)
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<(), CaptureError> {
+        w.write_all(CAPTURE_MAGIC)?;
+        write_u32(w, CAPTURE_VERSION)?;
+        write_u32(w, self.output_size[0])?;
+        write_u32(w, self.output_size[1])?;
+
+        write_u32(w, self.shaders.len() as u32)?;
+        for shader in &self.shaders {
+            write_vertex_format(w, &shader.vertex_format)?;
+            match &shader.instance_format {
+                Some(format) => {
+                    write_u8(w, 1)?;
+                    write_u32(w, format.stride)?;
+                    write_u32(w, format.offset.offset)?;
+                    write_i32(w, format.offset.format.as_raw())?;
+                }
+                None => write_u8(w, 0)?,
+            }
+            write_u64(w, shader.used_uniforms.as_raw())?;
+        }
+
+        write_u32(w, self.images.len() as u32)?;
+        for image in &self.images {
+            write_u32(w, image.size[0])?;
+            write_u32(w, image.size[1])?;
+            write_i32(w, image.format.as_raw())?;
+        }
+
+        write_u32(w, self.global_meshes.len() as u32)?;
+        for mesh in &self.global_meshes {
+            match mesh {
+                Some(mesh) => {
+                    write_u8(w, 1)?;
+                    write_mesh(w, mesh)?;
+                }
+                None => write_u8(w, 0)?,
+            }
+        }
+
+        write_u32(w, self.commands.len() as u32)?;
+        for command in &self.commands {
+            match command {
+                CaptureCommand::UpdateUniform { shader, data } => {
+                    write_u8(w, 0)?;
+                    write_u32(w, *shader)?;
+                    write_uniform(w, data)?;
+                }
+                CaptureCommand::UpdateTexture { shader, index, image, sampler } => {
+                    write_u8(w, 1)?;
+                    write_u32(w, *shader)?;
+                    write_u32(w, *index)?;
+                    write_u32(w, *image)?;
+                    write_sampler(w, sampler)?;
+                }
+                CaptureCommand::UploadImmediate(mesh) => {
+                    write_u8(w, 2)?;
+                    write_mesh(w, mesh)?;
+                }
+                CaptureCommand::DrawImmediate { mesh, shader, depth_write_enable } => {
+                    write_u8(w, 3)?;
+                    write_u32(w, *mesh)?;
+                    write_u32(w, *shader)?;
+                    write_u8(w, *depth_write_enable as u8)?;
+                }
+                CaptureCommand::DrawGlobal { mesh, shader, depth_write_enable } => {
+                    write_u8(w, 4)?;
+                    write_u32(w, *mesh)?;
+                    write_u32(w, *shader)?;
+                    write_u8(w, *depth_write_enable as u8)?;
+                }
+                CaptureCommand::UpdateBoneMatrices { shader, matrices } => {
+                    write_u8(w, 5)?;
+                    write_u32(w, *shader)?;
+                    write_u32(w, matrices.len() as u32)?;
+                    for matrix in matrices.iter() {
+                        write_f32s(w, matrix.as_slice())?;
+                    }
+                }
+                CaptureCommand::SetScissor(scissor) => {
+                    write_u8(w, 6)?;
+                    write_rect(w, scissor)?;
+                }
+                CaptureCommand::SetBlendState(blend_state) => {
+                    write_u8(w, 7)?;
+                    match blend_state {
+                        Some(blend_state) => {
+                            write_u8(w, 1)?;
+                            for value in [
+                                blend_state.color_op.as_raw(), blend_state.color_src_factor.as_raw(), blend_state.color_dst_factor.as_raw(),
+                                blend_state.alpha_op.as_raw(), blend_state.alpha_src_factor.as_raw(), blend_state.alpha_dst_factor.as_raw(),
+                            ] {
+                                write_i32(w, value)?;
+                            }
+                        }
+                        None => write_u8(w, 0)?,
+                    }
+                }
+                CaptureCommand::SetLogicOp(logic_op) => {
+                    write_u8(w, 8)?;
+                    match logic_op {
+                        Some(logic_op) => {
+                            write_u8(w, 1)?;
+                            write_i32(w, logic_op.as_raw())?;
+                        }
+                        None => write_u8(w, 0)?,
+                    }
+                }
+                CaptureCommand::SetDepthBias(depth_bias) => {
+                    write_u8(w, 9)?;
+                    match depth_bias {
+                        Some(depth_bias) => {
+                            write_u8(w, 1)?;
+                            write_f32s(w, &[depth_bias.constant_factor, depth_bias.slope_factor])?;
+                        }
+                        None => write_u8(w, 0)?,
+                    }
+                }
+                CaptureCommand::SetCullState(cull_state) => {
+                    write_u8(w, 10)?;
+                    write_u32(w, cull_state.cull_mode.as_raw())?;
+                    write_i32(w, cull_state.front_face.as_raw())?;
+                }
+                CaptureCommand::SetScreenEffects(effects) => {
+                    write_u8(w, 11)?;
+                    write_f32s(w, &[effects.portal, effects.underwater, effects.powder_snow, effects.pumpkin_blur, effects.time])?;
+                }
+                CaptureCommand::SetViewport(viewport) => {
+                    write_u8(w, 12)?;
+                    write_rect(w, viewport)?;
+                }
+                CaptureCommand::ClearDepth(region) => {
+                    write_u8(w, 13)?;
+                    write_rect(w, region)?;
+                }
+                CaptureCommand::SetViewProjections([left, right]) => {
+                    write_u8(w, 14)?;
+                    write_f32s(w, left.as_slice())?;
+                    write_f32s(w, right.as_slice())?;
+                }
+                CaptureCommand::SetObjectId(object_id) => {
+                    write_u8(w, 15)?;
+                    write_u32(w, *object_id)?;
+                }
+                CaptureCommand::SetClearConfig(config) => {
+                    write_u8(w, 16)?;
+                    match config.color {
+                        Some(color) => {
+                            write_u8(w, 1)?;
+                            write_f32s(w, &color)?;
+                        }
+                        None => write_u8(w, 0)?,
+                    }
+                    write_u8(w, config.clear_depth as u8)?;
+                }
+                CaptureCommand::SetColorWriteMask(color_write_mask) => {
+                    write_u8(w, 17)?;
+                    write_u32(w, color_write_mask.as_raw())?;
+                }
+                CaptureCommand::SetDepthTest(depth_test) => {
+                    write_u8(w, 18)?;
+                    write_u8(w, match depth_test {
+                        DepthTest::Default => 0,
+                        DepthTest::Equal => 1,
+                    })?;
+                }
+                CaptureCommand::DrawGlobalInstanced { mesh, shader, instance_data, instance_count, first_instance, depth_write_enable } => {
+                    write_u8(w, 19)?;
+                    write_u32(w, *mesh)?;
+                    write_u32(w, *shader)?;
+                    write_bytes(w, instance_data)?;
+                    write_u32(w, *instance_count)?;
+                    write_u32(w, *first_instance)?;
+                    write_u8(w, *depth_write_enable as u8)?;
+                }
+                CaptureCommand::DrawGlobalCulled { mesh, shader, sections, view_projection, depth_write_enable } => {
+                    write_u8(w, 20)?;
+                    write_u32(w, *mesh)?;
+                    write_u32(w, *shader)?;
+                    write_u32(w, sections.len() as u32)?;
+                    for section in sections.iter() {
+                        write_u32(w, section.first_index)?;
+                        write_u32(w, section.index_count)?;
+                        write_i32(w, section.vertex_offset)?;
+                        write_f32s(w, section.bounds_min.as_slice())?;
+                        write_f32s(w, section.bounds_max.as_slice())?;
+                    }
+                    write_f32s(w, view_projection.as_slice())?;
+                    write_u8(w, *depth_write_enable as u8)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self, CaptureError> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(CaptureError::InvalidFormat("Not a frame capture file"));
+        }
+        if read_u32(r)? != CAPTURE_VERSION {
+            return Err(CaptureError::InvalidFormat("Unsupported frame capture version"));
+        }
+        let output_size = Vec2u32::new(read_u32(r)?, read_u32(r)?);
+
+        let shader_count = read_u32(r)?;
+        let mut shaders = Vec::new();
+        for _ in 0..shader_count {
+            shaders.push(CapturedShader {
+                vertex_format: read_vertex_format(r)?,
+                instance_format: match read_u8(r)? {
+                    0 => None,
+                    _ => Some(InstanceFormat {
+                        stride: read_u32(r)?,
+                        offset: VertexFormatEntry {
+                            offset: read_u32(r)?,
+                            format: read_format(r)?,
+                        },
+                    }),
+                },
+                used_uniforms: McUniform::from_raw(read_u64(r)?),
+            });
+        }
+
+        let image_count = read_u32(r)?;
+        let mut images = Vec::new();
+        for _ in 0..image_count {
+            images.push(CapturedImage {
+                size: Vec2u32::new(read_u32(r)?, read_u32(r)?),
+                format: read_format(r)?,
+            });
+        }
+
+        let mesh_count = read_u32(r)?;
+        let mut global_meshes = Vec::new();
+        for _ in 0..mesh_count {
+            global_meshes.push(match read_u8(r)? {
+                0 => None,
+                _ => Some(read_mesh(r)?),
+            });
+        }
+
+        let command_count = read_u32(r)?;
+        let mut commands = Vec::new();
+        for _ in 0..command_count {
+            commands.push(match read_u8(r)? {
+                0 => CaptureCommand::UpdateUniform {
+                    shader: read_u32(r)?,
+                    data: read_uniform(r)?,
+                },
+                1 => CaptureCommand::UpdateTexture {
+                    shader: read_u32(r)?,
+                    index: read_u32(r)?,
+                    image: read_u32(r)?,
+                    sampler: read_sampler(r)?,
+                },
+                2 => CaptureCommand::UploadImmediate(read_mesh(r)?),
+                3 => CaptureCommand::DrawImmediate {
+                    mesh: read_u32(r)?,
+                    shader: read_u32(r)?,
+                    depth_write_enable: read_u8(r)? != 0,
+                },
+                4 => CaptureCommand::DrawGlobal {
+                    mesh: read_u32(r)?,
+                    shader: read_u32(r)?,
+                    depth_write_enable: read_u8(r)? != 0,
+                },
+                5 => {
+                    let shader = read_u32(r)?;
+                    let count = read_u32(r)?;
+                    let mut matrices = Vec::new();
+                    for _ in 0..count {
+                        matrices.push(Mat4f32::from_column_slice(&read_f32s::<_, 16>(r)?));
+                    }
+                    CaptureCommand::UpdateBoneMatrices { shader, matrices: matrices.into_boxed_slice() }
+                }
+                6 => CaptureCommand::SetScissor(read_rect(r)?),
+                7 => {
+                    if read_u8(r)? != 0 {
+                        CaptureCommand::SetBlendState(Some(BlendState {
+                            color_op: read_blend_op(r)?,
+                            color_src_factor: read_blend_factor(r)?,
+                            color_dst_factor: read_blend_factor(r)?,
+                            alpha_op: read_blend_op(r)?,
+                            alpha_src_factor: read_blend_factor(r)?,
+                            alpha_dst_factor: read_blend_factor(r)?,
+                        }))
+                    } else {
+                        CaptureCommand::SetBlendState(None)
+                    }
+                }
+                8 => {
+                    if read_u8(r)? != 0 {
+                        CaptureCommand::SetLogicOp(Some(read_logic_op(r)?))
+                    } else {
+                        CaptureCommand::SetLogicOp(None)
+                    }
+                }
+                9 => {
+                    if read_u8(r)? != 0 {
+                        let [constant_factor, slope_factor] = read_f32s::<_, 2>(r)?;
+                        if !constant_factor.is_finite() || !slope_factor.is_finite() {
+                            return Err(CaptureError::InvalidFormat("Invalid depth bias"));
+                        }
+                        CaptureCommand::SetDepthBias(Some(DepthBias::new(constant_factor, slope_factor)))
+                    } else {
+                        CaptureCommand::SetDepthBias(None)
+                    }
+                }
+                10 => {
+                    let cull_mode = vk::CullModeFlags::from_raw(read_u32(r)?);
+                    if !vk::CullModeFlags::FRONT_AND_BACK.contains(cull_mode) {
+                        return Err(CaptureError::InvalidFormat("Unknown cull mode"));
+                    }
+                    let front_face = match vk::FrontFace::from_raw(read_i32(r)?) {
+                        vk::FrontFace::COUNTER_CLOCKWISE => vk::FrontFace::COUNTER_CLOCKWISE,
+                        vk::FrontFace::CLOCKWISE => vk::FrontFace::CLOCKWISE,
+                        _ => return Err(CaptureError::InvalidFormat("Unknown front face")),
+                    };
+                    CaptureCommand::SetCullState(CullState::new(cull_mode).with_front_face(front_face))
+                }
+                11 => {
+                    let [portal, underwater, powder_snow, pumpkin_blur, time] = read_f32s::<_, 5>(r)?;
+                    CaptureCommand::SetScreenEffects(ScreenEffects { portal, underwater, powder_snow, pumpkin_blur, time })
+                }
+                12 => CaptureCommand::SetViewport(read_rect(r)?),
+                13 => CaptureCommand::ClearDepth(read_rect(r)?),
+                14 => {
+                    let left = Mat4f32::from_column_slice(&read_f32s::<_, 16>(r)?);
+                    let right = Mat4f32::from_column_slice(&read_f32s::<_, 16>(r)?);
+                    CaptureCommand::SetViewProjections([left, right])
+                }
+                15 => CaptureCommand::SetObjectId(read_u32(r)?),
+                16 => {
+                    let color = if read_u8(r)? != 0 {
+                        Some(read_f32s::<_, 4>(r)?)
+                    } else {
+                        None
+                    };
+                    CaptureCommand::SetClearConfig(ClearConfig::new(color, read_u8(r)? != 0))
+                }
+                17 => {
+                    let mask = vk::ColorComponentFlags::from_raw(read_u32(r)?);
+                    if !vk::ColorComponentFlags::RGBA.contains(mask) {
+                        return Err(CaptureError::InvalidFormat("Unknown color write mask"));
+                    }
+                    CaptureCommand::SetColorWriteMask(mask)
+                }
+                18 => CaptureCommand::SetDepthTest(match read_u8(r)? {
+                    0 => DepthTest::Default,
+                    1 => DepthTest::Equal,
+                    _ => return Err(CaptureError::InvalidFormat("Unknown depth test")),
+                }),
+                19 => CaptureCommand::DrawGlobalInstanced {
+                    mesh: read_u32(r)?,
+                    shader: read_u32(r)?,
+                    instance_data: read_bytes(r)?,
+                    instance_count: read_u32(r)?,
+                    first_instance: read_u32(r)?,
+                    depth_write_enable: read_u8(r)? != 0,
+                },
+                20 => {
+                    let mesh = read_u32(r)?;
+                    let shader = read_u32(r)?;
+                    let count = read_u32(r)?;
+                    let mut sections = Vec::new();
+                    for _ in 0..count {
+                        sections.push(CulledSection {
+                            first_index: read_u32(r)?,
+                            index_count: read_u32(r)?,
+                            vertex_offset: read_i32(r)?,
+                            bounds_min: Vec3f32::from(read_f32s::<_, 3>(r)?),
+                            bounds_max: Vec3f32::from(read_f32s::<_, 3>(r)?),
+                        });
+                    }
+                    CaptureCommand::DrawGlobalCulled {
+                        mesh,
+                        shader,
+                        sections: sections.into_boxed_slice(),
+                        view_projection: Mat4f32::from_column_slice(&read_f32s::<_, 16>(r)?),
+                        depth_write_enable: read_u8(r)? != 0,
+                    }
+                }
+                _ => return Err(CaptureError::InvalidFormat("Unknown capture command")),
+            });
+        }
+
+        Ok(Self {
+            output_size,
+            shaders,
+            images,
+            global_meshes,
+            commands,
+        })
+    }
+}
+
+/// Builds a [`FrameCapture`] from the calls made to a [`PassRecorder`](super::PassRecorder).
+pub(super) struct CaptureRecorder {
+    capture: FrameCapture,
+    shaders: HashMap<ShaderId, u32>,
+    images: HashMap<GlobalImageId, u32>,
+    global_meshes: HashMap<GlobalMeshId, u32>,
+}
+
+impl CaptureRecorder {
+    pub(super) fn new(output_size: Vec2u32) -> Self {
+        Self {
+            capture: FrameCapture {
+                output_size,
+                shaders: Vec::new(),
+                images: Vec::new(),
+                global_meshes: Vec::new(),
+                commands: Vec::new(),
+            },
+            shaders: HashMap::new(),
+            images: HashMap::new(),
+            global_meshes: HashMap::new(),
+        }
+    }
+
+    pub(super) fn update_uniform(&mut self, shader: &Shader, data: &McUniformData) {
+        let shader = self.get_shader_index(shader);
+        self.capture.commands.push(CaptureCommand::UpdateUniform { shader, data: *data });
+    }
+
+    pub(super) fn update_texture(&mut self, shader: &Shader, index: u32, image: &GlobalImage, sampler: &SamplerInfo) {
+        let shader = self.get_shader_index(shader);
+        let image = *self.images.entry(image.get_id()).or_insert_with(|| {
+            self.capture.images.push(CapturedImage {
+                size: image.get_size(),
+                format: image.get_format().get_format(),
+            });
+            (self.capture.images.len() - 1) as u32
+        });
+        self.capture.commands.push(CaptureCommand::UpdateTexture { shader, index, image, sampler: *sampler });
+    }
+
+    pub(super) fn update_bone_matrices(&mut self, shader: &Shader, matrices: &[Mat4f32]) {
+        let shader = self.get_shader_index(shader);
+        self.capture.commands.push(CaptureCommand::UpdateBoneMatrices { shader, matrices: matrices.into() });
+    }
+
+    pub(super) fn set_scissor(&mut self, scissor: Option<vk::Rect2D>) {
+        self.capture.commands.push(CaptureCommand::SetScissor(scissor));
+    }
+
+    pub(super) fn set_blend_state(&mut self, blend_state: Option<BlendState>) {
+        self.capture.commands.push(CaptureCommand::SetBlendState(blend_state));
+    }
+
+    pub(super) fn set_logic_op(&mut self, logic_op: Option<vk::LogicOp>) {
+        self.capture.commands.push(CaptureCommand::SetLogicOp(logic_op));
+    }
+
+    pub(super) fn set_color_write_mask(&mut self, color_write_mask: vk::ColorComponentFlags) {
+        self.capture.commands.push(CaptureCommand::SetColorWriteMask(color_write_mask));
+    }
+
+    pub(super) fn set_depth_bias(&mut self, depth_bias: Option<DepthBias>) {
+        self.capture.commands.push(CaptureCommand::SetDepthBias(depth_bias));
+    }
+
+    pub(super) fn set_depth_test(&mut self, depth_test: DepthTest) {
+        self.capture.commands.push(CaptureCommand::SetDepthTest(depth_test));
+    }
+
+    pub(super) fn set_cull_state(&mut self, cull_state: CullState) {
+        self.capture.commands.push(CaptureCommand::SetCullState(cull_state));
+    }
+
+    pub(super) fn set_screen_effects(&mut self, effects: &ScreenEffects) {
+        self.capture.commands.push(CaptureCommand::SetScreenEffects(*effects));
+    }
+
+    pub(super) fn set_viewport(&mut self, viewport: Option<vk::Rect2D>) {
+        self.capture.commands.push(CaptureCommand::SetViewport(viewport));
+    }
+
+    pub(super) fn clear_depth(&mut self, region: Option<vk::Rect2D>) {
+        self.capture.commands.push(CaptureCommand::ClearDepth(region));
+    }
+
+    pub(super) fn set_view_projections(&mut self, left: &Mat4f32, right: &Mat4f32) {
+        self.capture.commands.push(CaptureCommand::SetViewProjections([*left, *right]));
+    }
+
+    pub(super) fn set_object_id(&mut self, object_id: u32) {
+        self.capture.commands.push(CaptureCommand::SetObjectId(object_id));
+    }
+
+    pub(super) fn set_clear_config(&mut self, config: &ClearConfig) {
+        self.capture.commands.push(CaptureCommand::SetClearConfig(*config));
+    }
+
+    pub(super) fn upload_immediate(&mut self, data: &MeshData<'_>) {
+        self.capture.commands.push(CaptureCommand::UploadImmediate(CapturedMesh::from_mesh_data(data)));
+    }
+
+    pub(super) fn draw_immediate(&mut self, mesh: u32, shader: &Shader, depth_write_enable: bool) {
+        let shader = self.get_shader_index(shader);
+        self.capture.commands.push(CaptureCommand::DrawImmediate { mesh, shader, depth_write_enable });
+    }
+
+    pub(super) fn draw_global(&mut self, mesh: &GlobalMesh, shader: &Shader, depth_write_enable: bool) {
+        let shader = self.get_shader_index(shader);
+        let mesh = self.get_global_mesh_index(mesh);
+        self.capture.commands.push(CaptureCommand::DrawGlobal { mesh, shader, depth_write_enable });
+    }
+
+    pub(super) fn draw_global_instanced(&mut self, mesh: &GlobalMesh, shader: &Shader, instance_data: &[u8], instance_count: u32, first_instance: u32, depth_write_enable: bool) {
+        let shader = self.get_shader_index(shader);
+        let mesh = self.get_global_mesh_index(mesh);
+        self.capture.commands.push(CaptureCommand::DrawGlobalInstanced {
+            mesh,
+            shader,
+            instance_data: instance_data.into(),
+            instance_count,
+            first_instance,
+            depth_write_enable
+        });
+    }
+
+    pub(super) fn draw_global_culled(&mut self, mesh: &GlobalMesh, shader: &Shader, sections: &[CulledSection], view_projection: &Mat4f32, depth_write_enable: bool) {
+        let shader = self.get_shader_index(shader);
+        let mesh = self.get_global_mesh_index(mesh);
+        self.capture.commands.push(CaptureCommand::DrawGlobalCulled {
+            mesh,
+            shader,
+            sections: sections.into(),
+            view_projection: *view_projection,
+            depth_write_enable
+        });
+    }
+
+    pub(super) fn finish(self) -> FrameCapture {
+        self.capture
+    }
+
+    fn get_global_mesh_index(&mut self, mesh: &GlobalMesh) -> u32 {
+        *self.global_meshes.entry(mesh.get_id()).or_insert_with(|| {
+            let data = mesh.get_capture_data().cloned();
+            if data.is_none() {
+                log::warn!("Captured global mesh {:?} without retained data", mesh.get_id());
+            }
+            self.capture.global_meshes.push(data);
+            (self.capture.global_meshes.len() - 1) as u32
+        })
+    }
+
+    fn get_shader_index(&mut self, shader: &Shader) -> u32 {
+        *self.shaders.entry(shader.get_id()).or_insert_with(|| {
+            self.capture.shaders.push(CapturedShader {
+                vertex_format: *shader.get_vertex_format(),
+                instance_format: shader.get_instance_format().copied(),
+                used_uniforms: shader.get_used_uniforms(),
+            });
+            (self.capture.shaders.len() - 1) as u32
+        })
+    }
+}
+
+fn write_u8<W: Write>(w: &mut W, value: u8) -> std::io::Result<()> {
+    w.write_all(&[value])
+}
+
+fn write_u32<W: Write>(w: &mut W, value: u32) -> std::io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+fn write_i32<W: Write>(w: &mut W, value: i32) -> std::io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+fn write_u64<W: Write>(w: &mut W, value: u64) -> std::io::Result<()> {
+    w.write_all(&value.to_le_bytes())
+}
+
+fn write_f32s<W: Write>(w: &mut W, values: &[f32]) -> std::io::Result<()> {
+    for value in values {
+        w.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_bytes<W: Write>(w: &mut W, data: &[u8]) -> std::io::Result<()> {
+    write_u32(w, data.len() as u32)?;
+    w.write_all(data)
+}
+
+fn write_vertex_format<W: Write>(w: &mut W, format: &VertexFormat) -> std::io::Result<()> {
+    write_u32(w, format.stride)?;
+    write_u32(w, format.position.offset)?;
+    write_i32(w, format.position.format.as_raw())?;
+    for entry in [&format.normal, &format.color, &format.uv0, &format.uv1, &format.uv2, &format.joint_indices, &format.joint_weights] {
+        match entry {
+            Some(entry) => {
+                write_u8(w, 1)?;
+                write_u32(w, entry.offset)?;
+                write_i32(w, entry.format.as_raw())?;
+            }
+            None => write_u8(w, 0)?,
+        }
+    }
+    Ok(())
+}
+
+fn write_mesh<W: Write>(w: &mut W, mesh: &CapturedMesh) -> std::io::Result<()> {
+    write_u32(w, mesh.vertex_stride)?;
+    write_u32(w, mesh.index_count)?;
+    write_i32(w, mesh.index_type.as_raw())?;
+    write_i32(w, mesh.primitive_topology.as_raw())?;
+    write_bytes(w, &mesh.vertex_data)?;
+    write_bytes(w, &mesh.index_data)
+}
+
+fn write_rect<W: Write>(w: &mut W, rect: &Option<vk::Rect2D>) -> std::io::Result<()> {
+    match rect {
+        Some(rect) => {
+            write_u8(w, 1)?;
+            write_i32(w, rect.offset.x)?;
+            write_i32(w, rect.offset.y)?;
+            write_u32(w, rect.extent.width)?;
+            write_u32(w, rect.extent.height)
+        }
+        None => write_u8(w, 0),
+    }
+}
+
+fn write_sampler<W: Write>(w: &mut W, sampler: &SamplerInfo) -> std::io::Result<()> {
+    write_i32(w, sampler.mag_filter.as_raw())?;
+    write_i32(w, sampler.min_filter.as_raw())?;
+    write_i32(w, sampler.mipmap_mode.as_raw())?;
+    write_i32(w, sampler.address_mode_u.as_raw())?;
+    write_i32(w, sampler.address_mode_v.as_raw())?;
+    write_u8(w, sampler.anisotropy_enable as u8)
+}
+
+fn write_uniform<W: Write>(w: &mut W, data: &McUniformData) -> std::io::Result<()> {
+    match data {
+        McUniformData::ModelViewMatrix(m) => { write_u8(w, 0)?; write_f32s(w, m.as_slice()) }
+        McUniformData::ProjectionMatrix(m) => { write_u8(w, 1)?; write_f32s(w, m.as_slice()) }
+        McUniformData::InverseViewRotationMatrix(m) => { write_u8(w, 2)?; write_f32s(w, m.as_slice()) }
+        McUniformData::TextureMatrix(m) => { write_u8(w, 3)?; write_f32s(w, m.as_slice()) }
+        McUniformData::ScreenSize(v) => { write_u8(w, 4)?; write_f32s(w, v.as_slice()) }
+        McUniformData::ColorModulator(v) => { write_u8(w, 5)?; write_f32s(w, v.as_slice()) }
+        McUniformData::Light0Direction(v) => { write_u8(w, 6)?; write_f32s(w, v.as_slice()) }
+        McUniformData::Light1Direction(v) => { write_u8(w, 7)?; write_f32s(w, v.as_slice()) }
+        McUniformData::FogStart(f) => { write_u8(w, 8)?; write_f32s(w, &[*f]) }
+        McUniformData::FogEnd(f) => { write_u8(w, 9)?; write_f32s(w, &[*f]) }
+        McUniformData::FogColor(v) => { write_u8(w, 10)?; write_f32s(w, v.as_slice()) }
+        McUniformData::FogShape(s) => { write_u8(w, 11)?; write_u32(w, *s) }
+        McUniformData::LineWidth(f) => { write_u8(w, 12)?; write_f32s(w, &[*f]) }
+        McUniformData::GameTime(f) => { write_u8(w, 13)?; write_f32s(w, &[*f]) }
+        McUniformData::ChunkOffset(v) => { write_u8(w, 14)?; write_f32s(w, v.as_slice()) }
+    }
+}
+
+fn read_u8<R: Read>(r: &mut R) -> std::io::Result<u8> {
+    let mut bytes = [0u8; 1];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32<R: Read>(r: &mut R) -> std::io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_i32<R: Read>(r: &mut R) -> std::io::Result<i32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> std::io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f32s<R: Read, const N: usize>(r: &mut R) -> std::io::Result<[f32; N]> {
+    let mut values = [0f32; N];
+    for value in values.iter_mut() {
+        let mut bytes = [0u8; 4];
+        r.read_exact(&mut bytes)?;
+        *value = f32::from_le_bytes(bytes);
+    }
+    Ok(values)
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> Result<Box<[u8]>, CaptureError> {
+    // The length is not trusted so the buffer only grows as data is actually read
+    let len = read_u32(r)? as u64;
+    let mut data = Vec::new();
+    r.by_ref().take(len).read_to_end(&mut data)?;
+    if data.len() as u64 != len {
+        return Err(CaptureError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(data.into_boxed_slice())
+}
+
+fn read_format<R: Read>(r: &mut R) -> Result<vk::Format, CaptureError> {
+    let format = vk::Format::from_raw(read_i32(r)?);
+    match Format::try_format_for(format) {
+        Some(_) => Ok(format),
+        None => Err(CaptureError::InvalidFormat("Unknown format")),
+    }
+}
+
+fn read_vertex_format<R: Read>(r: &mut R) -> Result<VertexFormat, CaptureError> {
+    let stride = read_u32(r)?;
+    let position = VertexFormatEntry {
+        offset: read_u32(r)?,
+        format: read_format(r)?,
+    };
+
+    let mut entries = [None; 7];
+    for entry in entries.iter_mut() {
+        if read_u8(r)? != 0 {
+            *entry = Some(VertexFormatEntry {
+                offset: read_u32(r)?,
+                format: read_format(r)?,
+            });
+        }
+    }
+    let [normal, color, uv0, uv1, uv2, joint_indices, joint_weights] = entries;
+
+    Ok(VertexFormat {
+        stride,
+        position,
+        normal,
+        color,
+        uv0,
+        uv1,
+        uv2,
+        joint_indices,
+        joint_weights,
+    })
+}
+
+fn read_mesh<R: Read>(r: &mut R) -> Result<CapturedMesh, CaptureError> {
+    Ok(CapturedMesh {
+        vertex_stride: read_u32(r)?,
+        index_count: read_u32(r)?,
+        index_type: match vk::IndexType::from_raw(read_i32(r)?) {
+            vk::IndexType::UINT8_EXT => vk::IndexType::UINT8_EXT,
+            vk::IndexType::UINT16 => vk::IndexType::UINT16,
+            vk::IndexType::UINT32 => vk::IndexType::UINT32,
+            _ => return Err(CaptureError::InvalidFormat("Unknown index type")),
+        },
+        primitive_topology: match read_i32(r)? {
+            // Everything up to but excluding patch lists
+            raw @ 0..=9 => vk::PrimitiveTopology::from_raw(raw),
+            _ => return Err(CaptureError::InvalidFormat("Unknown primitive topology")),
+        },
+        vertex_data: read_bytes(r)?,
+        index_data: read_bytes(r)?,
+    })
+}
+
+fn read_blend_op<R: Read>(r: &mut R) -> Result<vk::BlendOp, CaptureError> {
+    match vk::BlendOp::from_raw(read_i32(r)?) {
+        vk::BlendOp::ADD => Ok(vk::BlendOp::ADD),
+        vk::BlendOp::SUBTRACT => Ok(vk::BlendOp::SUBTRACT),
+        vk::BlendOp::REVERSE_SUBTRACT => Ok(vk::BlendOp::REVERSE_SUBTRACT),
+        vk::BlendOp::MIN => Ok(vk::BlendOp::MIN),
+        vk::BlendOp::MAX => Ok(vk::BlendOp::MAX),
+        _ => Err(CaptureError::InvalidFormat("Unknown blend op")),
+    }
+}
+
+fn read_blend_factor<R: Read>(r: &mut R) -> Result<vk::BlendFactor, CaptureError> {
+    match read_i32(r)? {
+        // Everything from ZERO to ONE_MINUS_SRC1_ALPHA
+        raw @ 0..=18 => Ok(vk::BlendFactor::from_raw(raw)),
+        _ => Err(CaptureError::InvalidFormat("Unknown blend factor")),
+    }
+}
+
+fn read_logic_op<R: Read>(r: &mut R) -> Result<vk::LogicOp, CaptureError> {
+    match read_i32(r)? {
+        // Everything from CLEAR to SET
+        raw @ 0..=15 => Ok(vk::LogicOp::from_raw(raw)),
+        _ => Err(CaptureError::InvalidFormat("Unknown logic op")),
+    }
+}
+
+fn read_rect<R: Read>(r: &mut R) -> std::io::Result<Option<vk::Rect2D>> {
+    if read_u8(r)? != 0 {
+        Ok(Some(vk::Rect2D {
+            offset: vk::Offset2D { x: read_i32(r)?, y: read_i32(r)? },
+            extent: vk::Extent2D { width: read_u32(r)?, height: read_u32(r)? },
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+fn read_sampler<R: Read>(r: &mut R) -> Result<SamplerInfo, CaptureError> {
+    Ok(SamplerInfo {
+        mag_filter: read_filter(r)?,
+        min_filter: read_filter(r)?,
+        mipmap_mode: match vk::SamplerMipmapMode::from_raw(read_i32(r)?) {
+            vk::SamplerMipmapMode::NEAREST => vk::SamplerMipmapMode::NEAREST,
+            vk::SamplerMipmapMode::LINEAR => vk::SamplerMipmapMode::LINEAR,
+            _ => return Err(CaptureError::InvalidFormat("Unknown sampler mipmap mode")),
+        },
+        address_mode_u: read_address_mode(r)?,
+        address_mode_v: read_address_mode(r)?,
+        anisotropy_enable: read_u8(r)? != 0,
+    })
+}
+
+fn read_filter<R: Read>(r: &mut R) -> Result<vk::Filter, CaptureError> {
+    match vk::Filter::from_raw(read_i32(r)?) {
+        vk::Filter::NEAREST => Ok(vk::Filter::NEAREST),
+        vk::Filter::LINEAR => Ok(vk::Filter::LINEAR),
+        _ => Err(CaptureError::InvalidFormat("Unknown sampler filter")),
+    }
+}
+
+fn read_address_mode<R: Read>(r: &mut R) -> Result<vk::SamplerAddressMode, CaptureError> {
+    match read_i32(r)? {
+        // Everything from REPEAT to CLAMP_TO_BORDER
+        raw @ 0..=3 => Ok(vk::SamplerAddressMode::from_raw(raw)),
+        _ => Err(CaptureError::InvalidFormat("Unknown sampler address mode")),
+    }
+}
+
+fn read_uniform<R: Read>(r: &mut R) -> Result<McUniformData, CaptureError> {
+    Ok(match read_u8(r)? {
+        0 => McUniformData::ModelViewMatrix(Mat4f32::from_column_slice(&read_f32s::<_, 16>(r)?)),
+        1 => McUniformData::ProjectionMatrix(Mat4f32::from_column_slice(&read_f32s::<_, 16>(r)?)),
+        2 => McUniformData::InverseViewRotationMatrix(Mat4f32::from_column_slice(&read_f32s::<_, 16>(r)?)),
+        3 => McUniformData::TextureMatrix(Mat4f32::from_column_slice(&read_f32s::<_, 16>(r)?)),
+        4 => McUniformData::ScreenSize(Vec2f32::from(read_f32s::<_, 2>(r)?)),
+        5 => McUniformData::ColorModulator(Vec4f32::from(read_f32s::<_, 4>(r)?)),
+        6 => McUniformData::Light0Direction(Vec3f32::from(read_f32s::<_, 3>(r)?)),
+        7 => McUniformData::Light1Direction(Vec3f32::from(read_f32s::<_, 3>(r)?)),
+        8 => McUniformData::FogStart(read_f32s::<_, 1>(r)?[0]),
+        9 => McUniformData::FogEnd(read_f32s::<_, 1>(r)?[0]),
+        10 => McUniformData::FogColor(Vec4f32::from(read_f32s::<_, 4>(r)?)),
+        11 => McUniformData::FogShape(read_u32(r)?),
+        12 => McUniformData::LineWidth(read_f32s::<_, 1>(r)?[0]),
+        13 => McUniformData::GameTime(read_f32s::<_, 1>(r)?[0]),
+        14 => McUniformData::ChunkOffset(Vec3f32::from(read_f32s::<_, 3>(r)?)),
+        _ => return Err(CaptureError::InvalidFormat("Unknown uniform type")),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instanced_round_trip() {
+        let entry = VertexFormatEntry { offset: 0, format: vk::Format::R32G32B32_SFLOAT };
+        let capture = FrameCapture {
+            output_size: Vec2u32::new(16, 16),
+            shaders: vec![CapturedShader {
+                vertex_format: VertexFormat {
+                    stride: 12,
+                    position: entry,
+                    normal: None,
+                    color: None,
+                    uv0: None,
+                    uv1: None,
+                    uv2: None,
+                    joint_indices: None,
+                    joint_weights: None,
+                },
+                instance_format: Some(InstanceFormat { stride: 16, offset: VertexFormatEntry { offset: 4, format: vk::Format::R32G32B32_SFLOAT } }),
+                used_uniforms: McUniform::MODEL_VIEW_MATRIX,
+            }],
+            images: Vec::new(),
+            global_meshes: vec![None],
+            commands: vec![CaptureCommand::DrawGlobalInstanced {
+                mesh: 0,
+                shader: 0,
+                instance_data: vec![7u8; 48].into_boxed_slice(),
+                instance_count: 2,
+                first_instance: 1,
+                depth_write_enable: true,
+            }],
+        };
+
+        let mut data = Vec::new();
+        capture.write(&mut data).unwrap();
+        let read = FrameCapture::read(&mut data.as_slice()).unwrap();
+
+        let instance_format = read.shaders[0].instance_format.unwrap();
+        assert_eq!(instance_format.stride, 16);
+        assert_eq!(instance_format.offset.offset, 4);
+        assert_eq!(instance_format.offset.format, vk::Format::R32G32B32_SFLOAT);
+        match &read.commands[0] {
+            CaptureCommand::DrawGlobalInstanced { mesh, shader, instance_data, instance_count, first_instance, depth_write_enable } => {
+                assert_eq!((*mesh, *shader), (0, 0));
+                assert_eq!(instance_data.as_ref(), &[7u8; 48]);
+                assert_eq!((*instance_count, *first_instance), (2, 1));
+                assert!(*depth_write_enable);
+            }
+            command => panic!("Unexpected command {:?}", command),
+        }
+    }
+
+    #[test]
+    fn culled_round_trip() {
+        let section = CulledSection {
+            first_index: 6,
+            index_count: 12,
+            vertex_offset: -4,
+            bounds_min: Vec3f32::new(0.0, 1.0, 2.0),
+            bounds_max: Vec3f32::new(16.0, 17.0, 18.0),
+        };
+        let view_projection = Mat4f32::new_translation(&Vec3f32::new(1.0, 2.0, 3.0));
+        let capture = FrameCapture {
+            output_size: Vec2u32::new(16, 16),
+            shaders: Vec::new(),
+            images: Vec::new(),
+            global_meshes: vec![None],
+            commands: vec![CaptureCommand::DrawGlobalCulled {
+                mesh: 0,
+                shader: 0,
+                sections: vec![section; 2].into_boxed_slice(),
+                view_projection,
+                depth_write_enable: false,
+            }],
+        };
+
+        let mut data = Vec::new();
+        capture.write(&mut data).unwrap();
+        let read = FrameCapture::read(&mut data.as_slice()).unwrap();
+
+        match &read.commands[0] {
+            CaptureCommand::DrawGlobalCulled { mesh, shader, sections, view_projection: read_view_projection, depth_write_enable } => {
+                assert_eq!((*mesh, *shader), (0, 0));
+                assert_eq!(sections.as_ref(), &[section; 2]);
+                assert_eq!(*read_view_projection, view_projection);
+                assert!(!*depth_write_enable);
+            }
+            command => panic!("Unexpected command {:?}", command),
+        }
+    }
+
+    fn read_cull_state(cull_mode: u32, front_face: i32) -> Result<FrameCapture, CaptureError> {
+        let mut data = Vec::new();
+        FrameCapture {
+            output_size: Vec2u32::new(16, 16),
+            shaders: Vec::new(),
+            images: Vec::new(),
+            global_meshes: Vec::new(),
+            commands: vec![CaptureCommand::SetCullState(CullState::BACK)],
+        }.write(&mut data).unwrap();
+
+        // The cull state is the last 8 bytes of the capture
+        let len = data.len();
+        data[(len - 8)..(len - 4)].copy_from_slice(&cull_mode.to_le_bytes());
+        data[(len - 4)..].copy_from_slice(&front_face.to_le_bytes());
+        FrameCapture::read(&mut data.as_slice())
+    }
+
+    #[test]
+    fn cull_state_validation() {
+        let capture = read_cull_state(vk::CullModeFlags::FRONT_AND_BACK.as_raw(), vk::FrontFace::CLOCKWISE.as_raw()).unwrap();
+        match capture.commands[0] {
+            CaptureCommand::SetCullState(state) => assert_eq!(state, CullState::new(vk::CullModeFlags::FRONT_AND_BACK).with_front_face(vk::FrontFace::CLOCKWISE)),
+            ref command => panic!("Unexpected command {:?}", command),
+        }
+
+        assert!(matches!(read_cull_state(8, vk::FrontFace::CLOCKWISE.as_raw()), Err(CaptureError::InvalidFormat(_))));
+        assert!(matches!(read_cull_state(vk::CullModeFlags::BACK.as_raw(), 2), Err(CaptureError::InvalidFormat(_))));
+    }
+
+    /// Writes a capture containing only `command` and replaces the 4 bytes starting `offset` bytes
+    /// before the end of the capture with `value`.
+    fn read_patched(command: CaptureCommand, offset: usize, value: i32) -> Result<FrameCapture, CaptureError> {
+        let mut data = Vec::new();
+        FrameCapture {
+            output_size: Vec2u32::new(16, 16),
+            shaders: Vec::new(),
+            images: Vec::new(),
+            global_meshes: Vec::new(),
+            commands: vec![command],
+        }.write(&mut data).unwrap();
+
+        let start = data.len() - offset;
+        data[start..(start + 4)].copy_from_slice(&value.to_le_bytes());
+        FrameCapture::read(&mut data.as_slice())
+    }
+
+    #[test]
+    fn blend_and_logic_op_validation() {
+        let blend = CaptureCommand::SetBlendState(Some(BlendState::TRANSLUCENT));
+        assert!(read_patched(blend.clone(), 4, vk::BlendFactor::ONE_MINUS_SRC1_ALPHA.as_raw()).is_ok());
+        assert!(matches!(read_patched(blend.clone(), 4, 19), Err(CaptureError::InvalidFormat(_))));
+        assert!(matches!(read_patched(blend, 12, 5), Err(CaptureError::InvalidFormat(_))));
+
+        let logic_op = CaptureCommand::SetLogicOp(Some(vk::LogicOp::XOR));
+        assert!(read_patched(logic_op.clone(), 4, vk::LogicOp::SET.as_raw()).is_ok());
+        assert!(matches!(read_patched(logic_op, 4, 16), Err(CaptureError::InvalidFormat(_))));
+    }
+
+    #[test]
+    fn mesh_validation() {
+        let mesh = CaptureCommand::UploadImmediate(CapturedMesh {
+            vertex_data: Box::new([]),
+            index_data: Box::new([]),
+            vertex_stride: 12,
+            index_count: 0,
+            index_type: vk::IndexType::UINT16,
+            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        });
+
+        // The index type and topology are followed by two empty byte arrays
+        assert!(read_patched(mesh.clone(), 16, vk::IndexType::UINT8_EXT.as_raw()).is_ok());
+        assert!(matches!(read_patched(mesh.clone(), 16, vk::IndexType::NONE_KHR.as_raw()), Err(CaptureError::InvalidFormat(_))));
+        assert!(matches!(read_patched(mesh.clone(), 12, vk::PrimitiveTopology::PATCH_LIST.as_raw()), Err(CaptureError::InvalidFormat(_))));
+
+        // A length prefix larger than the remaining data must fail without allocating it upfront
+        assert!(matches!(read_patched(mesh, 8, -1), Err(CaptureError::Io(_))));
+    }
+
+    #[test]
+    fn image_format_validation() {
+        let mut data = Vec::new();
+        FrameCapture {
+            output_size: Vec2u32::new(16, 16),
+            shaders: Vec::new(),
+            images: vec![CapturedImage { size: Vec2u32::new(4, 4), format: vk::Format::R8G8B8A8_SRGB }],
+            global_meshes: Vec::new(),
+            commands: Vec::new(),
+        }.write(&mut data).unwrap();
+        assert!(FrameCapture::read(&mut data.as_slice()).is_ok());
+
+        // The format is followed by the empty mesh and command lists
+        let start = data.len() - 12;
+        data[start..(start + 4)].copy_from_slice(&i32::MAX.to_le_bytes());
+        assert!(matches!(FrameCapture::read(&mut data.as_slice()), Err(CaptureError::InvalidFormat(_))));
+    }
+}
@@ -0,0 +1,87 @@
+//! Non blocking notification of pass completion.
+//!
+//! Global object updates and readbacks are executed together with passes so the completion of a
+//! pass is also used to schedule follow up work of asset streaming. Besides
+//! [`EmulatorRenderer::on_pass_complete`](super::EmulatorRenderer::on_pass_complete) a pass can be
+//! polled using [`EmulatorRenderer::is_pass_complete`](super::EmulatorRenderer::is_pass_complete)
+//! or awaited using a [`PassCompletion`] future.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::renderer::emulator::pass::PassId;
+use crate::renderer::emulator::share::Share;
+
+struct CompletionState {
+    completed: bool,
+    waker: Option<Waker>,
+}
+
+/// A future which completes once a pass has completed execution on the gpu. The future is woken
+/// on the emulator worker thread. Futures of passes which never complete, for example because the
+/// renderer is destroyed, never complete either.
+pub struct PassCompletion {
+    share: Arc<Share>,
+    pass: PassId,
+    state: Option<Arc<Mutex<CompletionState>>>,
+}
+
+impl PassCompletion {
+    pub(super) fn new(share: Arc<Share>, pass: PassId) -> Self {
+        Self {
+            share,
+            pass,
+            state: None,
+        }
+    }
+
+    pub fn get_pass(&self) -> PassId {
+        self.pass
+    }
+}
+
+impl Future for PassCompletion {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(state) = &self.state {
+            let mut guard = state.lock().unwrap();
+            if guard.completed {
+                return Poll::Ready(());
+            }
+            guard.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        if self.share.is_pass_completed(self.pass.get_raw()) {
+            return Poll::Ready(());
+        }
+
+        let state = Arc::new(Mutex::new(CompletionState {
+            completed: false,
+            waker: Some(cx.waker().clone()),
+        }));
+        self.state = Some(state.clone());
+
+        // The callback may be called immediately so no lock must be held while registering it
+        self.share.add_completion_callback(self.pass.get_raw(), Box::new(move || {
+            let waker = {
+                let mut guard = state.lock().unwrap();
+                guard.completed = true;
+                guard.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }));
+
+        let completed = self.state.as_ref().unwrap().lock().unwrap().completed;
+        if completed {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
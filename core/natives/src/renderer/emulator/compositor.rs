@@ -0,0 +1,155 @@
+//! Composition of the outputs of multiple [`EmulatorPipeline`]s.
+//!
+//! Intended for side by side debugging of rendering changes, for example to show the output of a
+//! debug pipeline on top of the world at 50% opacity. The additional pipeline renders its own pass
+//! using a [`CompositorSource`] as output which records the output image produced by the pass.
+//! The layers passed to [`SwapchainOutput::next_image_with_layers`](super::pipeline::SwapchainOutput::next_image_with_layers)
+//! are then blended over the presented image in order after the swapchain blit. The layers and
+//! their opacity can be changed every frame.
+//!
+//! The source pass must be submitted before the pass presenting the image. If a source has not
+//! produced any output yet its layer is skipped.
+
+use std::sync::{Arc, Mutex};
+
+use ash::vk;
+use bumpalo::Bump;
+
+use crate::device::device::Queue;
+use crate::device::device_utils::BlitPass;
+use crate::renderer::emulator::pipeline::{EmulatorOutput, EmulatorPipeline, EmulatorPipelinePass, PassAttachment, PooledObjectProvider, SubmitRecorder};
+
+use crate::prelude::*;
+
+/// A layer blended over the presented image.
+#[derive(Clone)]
+pub struct CompositorLayer {
+    pub source: Arc<CompositorSource>,
+
+    /// The opacity of the layer between 0 and 1.
+    pub opacity: f32,
+}
+
+impl CompositorLayer {
+    pub fn new(source: Arc<CompositorSource>, opacity: f32) -> Self {
+        Self {
+            source,
+            opacity,
+        }
+    }
+}
+
+/// Provides the latest output of a pipeline to [`CompositorLayer`]s.
+pub struct CompositorSource {
+    device: Arc<DeviceContext>,
+    #[allow(unused)] // We just need to keep the pipeline alive
+    pipeline: Arc<dyn EmulatorPipeline>,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Box<[vk::DescriptorSet]>,
+
+    /// The output index of the last submitted pass.
+    latest: Mutex<Option<usize>>,
+}
+
+impl CompositorSource {
+    pub fn new(device: Arc<DeviceContext>, pipeline: Arc<dyn EmulatorPipeline>) -> Arc<Self> {
+        let (views, layout) = pipeline.get_sampled_attachment(PassAttachment::Output).unwrap();
+
+        let sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: views.len() as u32,
+            }
+        ];
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(views.len() as u32)
+            .pool_sizes(&sizes);
+
+        let descriptor_pool = unsafe {
+            device.vk().create_descriptor_pool(&info, None)
+        }.unwrap();
+
+        let descriptor_sets = device.get_utils().blit_utils().create_descriptor_sets_with_layout(descriptor_pool, &views, layout).unwrap().into_boxed_slice();
+
+        Arc::new(Self {
+            device,
+            pipeline,
+            descriptor_pool,
+            descriptor_sets,
+            latest: Mutex::new(None),
+        })
+    }
+
+    /// Returns a output which must be used by a pass of the pipeline of this source. Once the pass
+    /// has been submitted its output is used by all following compositions.
+    pub fn create_output(self: &Arc<Self>) -> Box<dyn EmulatorOutput + Send> {
+        Box::new(CompositorSourceOutput {
+            source: self.clone(),
+            output_index: None,
+        })
+    }
+
+    /// Returns the descriptor set of the latest output or [`None`] if no pass has been submitted
+    /// yet.
+    pub(super) fn get_latest_descriptor_set(&self) -> Option<vk::DescriptorSet> {
+        self.latest.lock().unwrap().map(|index| self.descriptor_sets[index])
+    }
+}
+
+impl Drop for CompositorSource {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.vk().destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+    }
+}
+
+struct CompositorSourceOutput {
+    source: Arc<CompositorSource>,
+    output_index: Option<usize>,
+}
+
+impl EmulatorOutput for CompositorSourceOutput {
+    fn init(&mut self, pass: &dyn EmulatorPipelinePass, _: &mut PooledObjectProvider) {
+        self.output_index = Some(pass.get_output_index());
+    }
+
+    fn record<'a>(&mut self, _: &mut PooledObjectProvider, _: &mut SubmitRecorder<'a>, _: &'a Bump) {
+    }
+
+    fn on_post_submit(&mut self, _: &Queue) {
+        *self.source.latest.lock().unwrap() = self.output_index;
+    }
+}
+
+/// Records the blending of the layers over the framebuffer. Must be recorded outside of a render
+/// pass after the image has been written.
+pub(super) fn record_layers(cmd: vk::CommandBuffer, blend_pass: &BlitPass, framebuffer: vk::Framebuffer, size: Vec2u32, layers: &[CompositorLayer]) {
+    let sets: Vec<_> = layers.iter().filter_map(|layer| {
+        layer.source.get_latest_descriptor_set().map(|set| (set, layer.opacity))
+    }).collect();
+    if sets.is_empty() {
+        return;
+    }
+
+    // The outputs of other passes are only guaranteed to be visible to their own outputs
+    let barrier = vk::MemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ | vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE);
+
+    unsafe {
+        blend_pass.get_device().vk.cmd_pipeline_barrier(
+            cmd,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::DependencyFlags::empty(),
+            std::slice::from_ref(&barrier),
+            &[],
+            &[]
+        );
+    }
+
+    for (set, opacity) in sets {
+        blend_pass.record_blend(cmd, set, framebuffer, size, opacity);
+    }
+}
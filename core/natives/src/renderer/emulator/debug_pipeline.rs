@@ -2,21 +2,27 @@
 
 use std::collections::HashMap;
 use std::ffi::CStr;
+use std::ptr::NonNull;
 use std::sync::{Arc, Mutex, Weak};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::time::Instant;
 use ash::vk;
 use bumpalo::Bump;
-use bytemuck::{bytes_of, cast_slice, Pod, Zeroable};
+use bytemuck::{bytes_of, Pod, Zeroable};
 use include_bytes_aligned::include_bytes_aligned;
-use crate::allocator::Allocation;
+use crate::allocator::{Allocation, HostAccess};
 use crate::device::device::Queue;
 use crate::device::device_utils::create_shader_from_bytes;
 
 use crate::prelude::*;
-use crate::renderer::emulator::EmulatorRenderer;
-use crate::renderer::emulator::mc_shaders::{McUniform, McUniformData, ShaderDropListener, ShaderId, ShaderListener, VertexFormat, VertexFormatEntry};
-use crate::renderer::emulator::pipeline::{DrawTask, EmulatorPipeline, EmulatorPipelinePass, PipelineTask, PooledObjectProvider, SubmitRecorder};
+use crate::renderer::emulator::{EmulatorRenderer, pipeline_compiler};
+use crate::renderer::emulator::lights::{get_light_buffer_info, get_light_tile_buffer_size};
+use crate::renderer::emulator::gpu_culling::{DRAW_COMMAND_OFFSET, DRAW_COMMAND_STRIDE};
+use crate::renderer::emulator::mc_shaders::{InstanceFormat, McUniform, McUniformData, ShaderDropListener, ShaderId, ShaderListener, VertexFormat, VertexFormatEntry};
+use crate::renderer::emulator::vertex_compaction::VertexChannels;
+use crate::renderer::emulator::projection::{DepthMode, to_reverse_z};
+use crate::renderer::emulator::render_graph::{GraphPass, ImageUsage, RenderGraph};
+use crate::renderer::emulator::pipeline::{BlendMode, BlendState, CullState, DepthBias, DepthTest, CulledDrawTask, DrawTask, EmulatorPipeline, EmulatorPipelinePass, PipelineTask, PooledObjectProvider, ScreenEffects, ClearConfig, SubmitRecorder, PassAttachment, AttachmentInfo, PipelineConfigHint, LightsInfo};
 use crate::util::vk::{make_full_rect, make_full_viewport};
 
 pub struct DepthTypeInfo {
@@ -54,6 +60,79 @@ pub enum DebugPipelineMode {
     Textured2,
 }
 
+/// How the multisampled color attachment is resolved before the output subpass.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum MsaaResolve {
+    /// Resolves using a render pass resolve attachment. Always averages the samples.
+    RenderPass,
+
+    /// The output subpass loads every sample and resolves them in the shader. Samples are
+    /// tonemapped before averaging which avoids aliasing on edges of very bright hdr content.
+    Shader,
+}
+
+/// The multisampling configuration of a [`DebugPipeline`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MsaaConfig {
+    pub samples: vk::SampleCountFlags,
+    pub resolve: MsaaResolve,
+
+    /// If set enables sample shading with the minimum fraction of samples which are shaded
+    /// individually. Improves antialiasing of cutout geometry at a significant cost. Ignored if
+    /// the device does not support sample rate shading.
+    pub min_sample_shading: Option<f32>,
+}
+
+impl MsaaConfig {
+    pub const NONE: Self = Self {
+        samples: vk::SampleCountFlags::TYPE_1,
+        resolve: MsaaResolve::RenderPass,
+        min_sample_shading: None,
+    };
+
+    pub fn is_enabled(&self) -> bool {
+        self.samples != vk::SampleCountFlags::TYPE_1
+    }
+
+    /// Returns true if a render pass resolve attachment is needed.
+    fn uses_resolve_attachment(&self) -> bool {
+        self.is_enabled() && self.resolve == MsaaResolve::RenderPass
+    }
+}
+
+/// The options used to create a [`DebugPipeline`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DebugPipelineOptions {
+    pub msaa: MsaaConfig,
+
+    /// If set every pass renders a left and right view. See [`DebugPipeline::new_stereo`].
+    pub stereo: bool,
+
+    /// The depth convention of the pipeline. Projection matrices are converted if
+    /// [`DepthMode::Reversed`] is used so all passes can still use OpenGL style matrices.
+    pub depth_mode: DepthMode,
+
+    /// If set every pass renders the object id of its draws into a [`PassAttachment::ObjectId`]
+    /// attachment which can be used for picking. Not supported with multisampling or stereo
+    /// rendering.
+    pub object_ids: bool,
+
+    /// If set draws fetch their vertex data in the vertex shader using the device address of the
+    /// vertex buffer instead of vertex input attributes. Requires the `bufferDeviceAddress` device
+    /// feature. Shaders with vertex formats which cannot be fetched use vertex input attributes.
+    pub vertex_pulling: bool,
+}
+
+impl DebugPipelineOptions {
+    pub const DEFAULT: Self = Self {
+        msaa: MsaaConfig::NONE,
+        stereo: false,
+        depth_mode: DepthMode::Standard,
+        object_ids: false,
+        vertex_pulling: true,
+    };
+}
+
 /// A [`EmulatorPipeline`] which provides debug information.
 ///
 /// The following outputs are supported:
@@ -68,70 +147,205 @@ pub enum DebugPipelineMode {
 pub struct DebugPipeline {
     emulator: Arc<EmulatorRenderer>,
     weak: Weak<Self>,
+    shared: Arc<SharedObjects>,
 
     framebuffer_size: Vec2u32,
+    descriptor_pool: vk::DescriptorPool,
+    next_index: AtomicUsize,
+    pass_objects: Box<[PassObjects]>,
+
+    /// The sampled output of every view for each pass object. The first view is used as the
+    /// output of the pipeline.
+    view_outputs: Box<[Box<[vk::ImageView]>]>,
+}
+assert_impl_all!(DebugPipeline: Send, Sync);
+
+/// The objects of a [`DebugPipeline`] which do not depend on the framebuffer size. Shared by all
+/// pipelines created using [`DebugPipeline::resize`] so that resizing only recreates the render
+/// targets.
+struct SharedObjects {
+    emulator: Arc<EmulatorRenderer>,
+    weak: Weak<Self>,
+
+    depth_format: vk::Format,
+    msaa: MsaaConfig,
+    view_count: u32,
+    depth_mode: DepthMode,
+    object_ids: bool,
+    vertex_pulling: bool,
 
     shader_modules: ShaderModules,
     render_pass: vk::RenderPass,
+
+    /// A render pass compatible with `render_pass` which loads the attachments instead of clearing
+    /// them. Used to continue rendering after a segment of a pass has been submitted.
+    load_render_pass: vk::RenderPass,
+
+    /// Render passes compatible with `render_pass` which do not clear the color or depth
+    /// attachment. Keyed by whether the color and depth attachments are cleared and created when
+    /// first needed.
+    clear_render_passes: Mutex<HashMap<(bool, bool), vk::RenderPass>>,
     draw_pipeline: DrawPipeline,
     background_pipeline: BackgroundPipeline,
-    descriptor_pool: vk::DescriptorPool,
+
+    /// A zero offset bound as the instance data of draws without an instance buffer.
+    instance_placeholder: (vk::Buffer, Allocation),
 
     pipelines: Mutex<HashMap<ShaderId, ShaderPipelines>>,
-    next_index: AtomicUsize,
-    pass_objects: Box<[PassObjects]>,
-    output_views: Box<[vk::ImageView]>,
+    async_pipeline_creation: AtomicBool,
 }
-assert_impl_all!(DebugPipeline: Send, Sync);
+assert_impl_all!(SharedObjects: Send, Sync);
 
 impl DebugPipeline {
+    /// Returns true if the device supports the attachment formats used by the debug pipeline. If
+    /// not the [`FallbackPipeline`](super::fallback_pipeline::FallbackPipeline) should be used.
+    pub fn is_supported(device: &DeviceContext) -> bool {
+        let required = [
+            (vk::Format::D32_SFLOAT, vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE),
+            (vk::Format::R8G8B8A8_SRGB, vk::FormatFeatureFlags::COLOR_ATTACHMENT | vk::FormatFeatureFlags::SAMPLED_IMAGE),
+        ];
+
+        for (format, features) in required {
+            let properties = unsafe {
+                device.get_instance().vk().get_physical_device_format_properties(device.get_functions().physical_device, format)
+            };
+            if !properties.optimal_tiling_features.contains(features) {
+                log::info!("Debug pipeline is not supported since format {:?} does not support {:?}", format, features);
+                return false;
+            }
+        }
+        true
+    }
+
     pub fn new(emulator: Arc<EmulatorRenderer>, mode: DebugPipelineMode, framebuffer_size: Vec2u32) -> Result<Arc<Self>, ObjectCreateError> {
-        let concurrent_passes = 2usize;
+        Self::new_with_msaa(emulator, mode, framebuffer_size, MsaaConfig::NONE)
+    }
+
+    /// Creates a new debug pipeline rendering with multisampling. If the configuration is not
+    /// supported by the device multisampling is disabled.
+    pub fn new_with_msaa(emulator: Arc<EmulatorRenderer>, mode: DebugPipelineMode, framebuffer_size: Vec2u32, msaa: MsaaConfig) -> Result<Arc<Self>, ObjectCreateError> {
+        Self::new_with_options(emulator, mode, framebuffer_size, DebugPipelineOptions { msaa, ..DebugPipelineOptions::DEFAULT })
+    }
+
+    /// Creates a new debug pipeline rendering a left and right view in every pass using multiview.
+    /// All attachments are layered images with one layer per view.
+    ///
+    /// Passes must set the matrices of both views using
+    /// [`PassRecorder::set_view_projections`](super::PassRecorder::set_view_projections) which
+    /// replace the projection matrix of all shaders. The output of the pipeline is the left view,
+    /// both views can be accessed with [`DebugPipeline::get_view_output`].
+    pub fn new_stereo(emulator: Arc<EmulatorRenderer>, mode: DebugPipelineMode, framebuffer_size: Vec2u32, msaa: MsaaConfig) -> Result<Arc<Self>, ObjectCreateError> {
+        Self::new_with_options(emulator, mode, framebuffer_size, DebugPipelineOptions { msaa, stereo: true, ..DebugPipelineOptions::DEFAULT })
+    }
+
+    /// The number of views rendered by stereo pipelines.
+    pub const STEREO_VIEW_COUNT: u32 = 2;
+
+    /// Creates a new debug pipeline using all options. Unsupported multisampling configurations
+    /// are disabled.
+    pub fn new_with_options(emulator: Arc<EmulatorRenderer>, mode: DebugPipelineMode, framebuffer_size: Vec2u32, options: DebugPipelineOptions) -> Result<Arc<Self>, ObjectCreateError> {
         let depth_format = vk::Format::D32_SFLOAT;
+        let view_count = if options.stereo { Self::STEREO_VIEW_COUNT } else { 1 };
+        let depth_mode = options.depth_mode;
 
         let device = emulator.get_device();
+        let msaa = Self::validate_msaa(device, mode, options.msaa);
+        let object_ids = Self::validate_object_ids(options.object_ids, &msaa, view_count);
+        let vertex_pulling = Self::validate_vertex_pulling(device, options.vertex_pulling);
+
+        let mut shader_modules = ShaderModules::new(device, mode, view_count > 1, depth_mode == DepthMode::Reversed, vertex_pulling)?;
 
-        let mut shader_modules = ShaderModules::new(device, mode)?;
+        let render_pass = match Self::create_render_pass(&device, depth_format, &msaa, view_count, object_ids, false, &ClearConfig::DEFAULT) {
+            Ok(render_pass) => render_pass,
+            Err(err) => {
+                shader_modules.destroy(device);
+                return Err(err);
+            }
+        };
 
-        let render_pass = match Self::create_render_pass(&device, depth_format) {
+        let load_render_pass = match Self::create_render_pass(&device, depth_format, &msaa, view_count, object_ids, true, &ClearConfig::DEFAULT) {
             Ok(render_pass) => render_pass,
             Err(err) => {
+                unsafe { device.vk().destroy_render_pass(render_pass, None) };
                 shader_modules.destroy(device);
                 return Err(err);
             }
         };
 
-        let mut draw_pipeline = match DrawPipeline::new(device) {
+        let draw_pipeline = match DrawPipeline::new(device) {
             Ok(pipeline) => pipeline,
             Err(err) => {
+                unsafe { device.vk().destroy_render_pass(load_render_pass, None) };
                 unsafe { device.vk().destroy_render_pass(render_pass, None) };
                 shader_modules.destroy(device);
                 return Err(err);
             }
         };
 
-        let mut background_pipeline = match BackgroundPipeline::new(device, render_pass, 1, framebuffer_size) {
+        let mut background_pipeline = match BackgroundPipeline::new(device, render_pass, 1, &msaa) {
             Ok(pipeline) => pipeline,
             Err(err) => {
-                draw_pipeline.destroy(device);
+                unsafe { device.vk().destroy_render_pass(load_render_pass, None) };
                 unsafe { device.vk().destroy_render_pass(render_pass, None) };
                 shader_modules.destroy(device);
                 return Err(err);
             }
         };
 
-        let descriptor_pool = match Self::create_descriptor_pool(device, concurrent_passes) {
-            Ok(pool) => pool,
+        let instance_placeholder = match Self::create_instance_placeholder(device) {
+            Ok(placeholder) => placeholder,
             Err(err) => {
                 background_pipeline.destroy(device);
-                draw_pipeline.destroy(device);
+                unsafe { device.vk().destroy_render_pass(load_render_pass, None) };
                 unsafe { device.vk().destroy_render_pass(render_pass, None) };
                 shader_modules.destroy(device);
                 return Err(err);
             }
         };
 
-        let layouts: Box<[_]> = std::iter::repeat(background_pipeline.descriptor_set_layout).take(concurrent_passes).collect();
+        let shared = Arc::new_cyclic(|weak| {
+            SharedObjects {
+                emulator: emulator.clone(),
+                weak: weak.clone(),
+
+                depth_format,
+                msaa,
+                view_count,
+                depth_mode,
+                object_ids,
+                vertex_pulling,
+
+                shader_modules,
+                render_pass,
+                load_render_pass,
+                clear_render_passes: Mutex::new(HashMap::new()),
+                draw_pipeline,
+                background_pipeline,
+                instance_placeholder,
+
+                pipelines: Mutex::new(HashMap::new()),
+                async_pipeline_creation: AtomicBool::new(false),
+            }
+        });
+
+        Self::new_with_shared(emulator, shared, framebuffer_size)
+    }
+
+    /// Creates a new pipeline rendering with a different framebuffer size. Only the render targets
+    /// are created, all render passes and graphics pipelines are shared with this pipeline which
+    /// stays valid until it is dropped.
+    pub fn resize(&self, framebuffer_size: Vec2u32) -> Result<Arc<Self>, ObjectCreateError> {
+        Self::new_with_shared(self.emulator.clone(), self.shared.clone(), framebuffer_size)
+    }
+
+    /// Creates the render targets of a pipeline.
+    fn new_with_shared(emulator: Arc<EmulatorRenderer>, shared: Arc<SharedObjects>, framebuffer_size: Vec2u32) -> Result<Arc<Self>, ObjectCreateError> {
+        let concurrent_passes = 2usize;
+        let device = emulator.get_device();
+
+        let descriptor_pool = Self::create_descriptor_pool(device, concurrent_passes)?;
+
+        let layouts: Box<[_]> = std::iter::repeat(shared.background_pipeline.descriptor_set_layout).take(concurrent_passes).collect();
         let info = vk::DescriptorSetAllocateInfo::builder()
             .descriptor_pool(descriptor_pool)
             .set_layouts(&layouts);
@@ -142,27 +356,21 @@ impl DebugPipeline {
             Ok(layouts) => layouts,
             Err(err) => {
                 unsafe { device.vk().destroy_descriptor_pool(descriptor_pool, None) };
-                background_pipeline.destroy(device);
-                draw_pipeline.destroy(device);
-                unsafe { device.vk().destroy_render_pass(render_pass, None) };
-                shader_modules.destroy(device);
                 return Err(ObjectCreateError::Vulkan(err));
             }
         };
 
         let mut pass_objects: Vec<PassObjects> = Vec::with_capacity(layouts.len());
         for descriptor_set in descriptor_sets {
-            let objects = match PassObjects::new(device, framebuffer_size, depth_format, vk::Format::R8G8B8A8_SRGB, render_pass, descriptor_set) {
+            let objects = match UniformRing::new(device, shared.draw_pipeline.set1_layout).and_then(|uniform_ring| {
+                PassObjects::new(device, framebuffer_size, shared.depth_format, vk::Format::R8G8B8A8_SRGB, &shared.msaa, shared.view_count, shared.object_ids, shared.render_pass, descriptor_set, uniform_ring)
+            }) {
                 Ok(objects) => objects,
                 Err(err) => {
                     for mut pass_object in pass_objects {
                         pass_object.destroy(device);
                     }
                     unsafe { device.vk().destroy_descriptor_pool(descriptor_pool, None) };
-                    background_pipeline.destroy(device);
-                    draw_pipeline.destroy(device);
-                    unsafe { device.vk().destroy_render_pass(render_pass, None) };
-                    shader_modules.destroy(device);
                     return Err(err);
                 }
             };
@@ -170,33 +378,84 @@ impl DebugPipeline {
         }
         let pass_objects = pass_objects.into_boxed_slice();
 
-        let output_views: Box<_> = if mode == DebugPipelineMode::Depth {
-            pass_objects.iter().map(|obj| obj.depth_sampler_view).collect()
-        } else {
-            pass_objects.iter().map(|obj| obj.output_view).collect()
-        };
+        let view_outputs: Box<_> = (0..(shared.view_count as usize)).map(|view| {
+            if shared.shader_modules.mode == DebugPipelineMode::Depth {
+                pass_objects.iter().map(|obj| obj.depth_sampler_views[view]).collect()
+            } else {
+                pass_objects.iter().map(|obj| obj.get_output_sampler_view(view)).collect()
+            }
+        }).collect();
 
         Ok(Arc::new_cyclic(|weak| {
             Self {
                 emulator,
                 weak: weak.clone(),
+                shared,
 
                 framebuffer_size,
-
-                shader_modules,
-                render_pass,
-                draw_pipeline,
-                background_pipeline,
                 descriptor_pool,
-
-                pipelines: Mutex::new(HashMap::new()),
                 next_index: AtomicUsize::new(0),
                 pass_objects,
-                output_views
+                view_outputs
             }
         }))
     }
 
+    /// Disables the parts of the multisampling configuration which are not supported.
+    fn validate_msaa(device: &DeviceContext, mode: DebugPipelineMode, mut msaa: MsaaConfig) -> MsaaConfig {
+        if !msaa.is_enabled() {
+            return MsaaConfig::NONE;
+        }
+
+        if mode == DebugPipelineMode::Depth {
+            log::warn!("Multisampling is not supported in depth mode. Disabling multisampling");
+            return MsaaConfig::NONE;
+        }
+
+        let limits = unsafe {
+            device.get_instance().vk().get_physical_device_properties(device.get_functions().physical_device)
+        }.limits;
+        let supported = limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts & limits.sampled_image_color_sample_counts & limits.sampled_image_depth_sample_counts;
+        if !supported.contains(msaa.samples) {
+            log::warn!("Sample count {:?} is not supported (supported {:?}). Disabling multisampling", msaa.samples, supported);
+            return MsaaConfig::NONE;
+        }
+
+        if msaa.min_sample_shading.is_some() && device.get_enabled_features().sample_rate_shading != vk::TRUE {
+            log::warn!("Sample rate shading is not supported. Disabling sample shading");
+            msaa.min_sample_shading = None;
+        }
+
+        msaa
+    }
+
+    /// Disables object ids if they are not supported by the configuration of the pipeline.
+    fn validate_object_ids(object_ids: bool, msaa: &MsaaConfig, view_count: u32) -> bool {
+        if !object_ids {
+            return false;
+        }
+
+        if msaa.is_enabled() {
+            log::warn!("Object ids are not supported with multisampling. Disabling object ids");
+            return false;
+        }
+        if view_count > 1 {
+            log::warn!("Object ids are not supported with stereo rendering. Disabling object ids");
+            return false;
+        }
+
+        true
+    }
+
+    /// Disables vertex pulling if the device does not support buffer device addresses.
+    fn validate_vertex_pulling(device: &DeviceContext, vertex_pulling: bool) -> bool {
+        if vertex_pulling && device.buffer_device_address_khr().is_none() {
+            log::info!("Buffer device addresses are not supported. Disabling vertex pulling");
+            return false;
+        }
+        vertex_pulling
+    }
+
     /// Returns the next index to be used for a pass and increments the internal counter.
     fn next_index(&self) -> usize {
         loop {
@@ -208,107 +467,63 @@ impl DebugPipeline {
         }
     }
 
-    /// Returns the pipeline to be used for a specific configuration. If the pipeline doesnt exits
-    /// yet a new one is created.
-    fn get_pipeline(&self, shader: ShaderId, config: &PipelineConfig) -> vk::Pipeline {
-        let mut guard = self.pipelines.lock().unwrap();
-        let pipelines = guard.get_mut(&shader).unwrap_or_else(|| {
-            log::error!("Called get_pipeline for unregistered shader {:?}", shader);
-            panic!()
-        });
-
-        pipelines.get_or_create_pipeline(config, |format| self.create_pipeline(config, format))
+    /// Returns the number of views rendered by every pass.
+    pub fn get_view_count(&self) -> u32 {
+        self.shared.view_count
     }
 
-    fn create_pipeline(&self, config: &PipelineConfig, vertex_format: &VertexFormat) -> vk::Pipeline {
-        let alloc = Bump::new();
-        let (shader_stages, input_state) = self.shader_modules.configure_pipeline(vertex_format, &alloc);
-
-        let viewport = make_full_viewport(self.framebuffer_size);
-        let scissor = make_full_rect(self.framebuffer_size);
-
-        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
-            .viewports(std::slice::from_ref(&viewport))
-            .scissors(std::slice::from_ref(&scissor));
-
-        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
-            .polygon_mode(vk::PolygonMode::FILL)
-            .cull_mode(vk::CullModeFlags::BACK)
-            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-            .line_width(1f32);
-
-        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
-            .sample_shading_enable(false);
-
-        let attachment_blend_state = [
-            vk::PipelineColorBlendAttachmentState::builder()
-                .blend_enable(true)
-                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
-                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                .color_blend_op(vk::BlendOp::ADD)
-                .src_alpha_blend_factor(vk::BlendFactor::ONE)
-                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
-                .color_blend_op(vk::BlendOp::ADD)
-                .color_write_mask(vk::ColorComponentFlags::RGBA)
-                .build(),
-        ];
-
-        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
-            .logic_op_enable(false)
-            .attachments(&attachment_blend_state);
-
-        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder();
-
-        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(config.primitive_topology)
-            .primitive_restart_enable(false);
-
-        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
-            .depth_test_enable(config.depth_test_enable)
-            .depth_write_enable(config.depth_write_enable)
-            .depth_compare_op(vk::CompareOp::LESS);
-
-        let info = vk::GraphicsPipelineCreateInfo::builder()
-            .stages(shader_stages)
-            .vertex_input_state(input_state)
-            .input_assembly_state(&input_assembly_state)
-            .viewport_state(&viewport_state)
-            .rasterization_state(&rasterization_state)
-            .multisample_state(&multisample_state)
-            .depth_stencil_state(&depth_stencil_state)
-            .color_blend_state(&color_blend_state)
-            .dynamic_state(&dynamic_state)
-            .layout(self.draw_pipeline.pipeline_layout)
-            .render_pass(self.render_pass)
-            .subpass(0);
+    /// Returns true if draws fetch their vertex data using the vertex buffer address. See
+    /// [`DebugPipelineOptions::vertex_pulling`].
+    pub fn uses_vertex_pulling(&self) -> bool {
+        self.shared.vertex_pulling
+    }
 
-        let pipeline = *unsafe {
-            self.emulator.get_device().vk().create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), None)
-        }.unwrap_or_else(|(_, err)| {
-            log::error!("Failed to create graphics pipeline {:?}", err);
-            panic!();
-        }).get(0).unwrap();
+    /// Returns the output images of a view. Like [`EmulatorPipeline::get_output`] the image used
+    /// by a pass is selected by its output index. Returns [`None`] if the view does not exist.
+    pub fn get_view_output(&self, view: u32) -> Option<&[vk::ImageView]> {
+        self.view_outputs.get(view as usize).map(|views| views.as_ref())
+    }
 
-        pipeline
+    /// Configures if pipelines should be created on background threads. Disabled by default.
+    ///
+    /// If enabled draws using a pipeline which is still being created use a compatible pipeline of
+    /// the same shader instead. If no compatible pipeline exists the draw is skipped. This avoids
+    /// hitches when new shaders are first used at the cost of some draws being rendered
+    /// incorrectly or not at all for a few frames.
+    pub fn set_async_pipeline_creation(&self, enabled: bool) {
+        self.shared.async_pipeline_creation.store(enabled, Ordering::SeqCst);
     }
 
-    fn create_render_pass(device: &DeviceContext, depth_format: vk::Format) -> Result<vk::RenderPass, ObjectCreateError> {
-        let attachments = [
+    /// Creates the render pass of the pipeline. If `load` is set the depth and color attachments
+    /// are loaded from the end of a previous render pass instead of being cleared. Otherwise they
+    /// are cleared as specified by `clear`. Render passes created with different arguments are
+    /// compatible.
+    ///
+    /// If `object_ids` is set a object id attachment is added after all other attachments. Object
+    /// ids are never used together with the resolve attachment.
+    fn create_render_pass(device: &DeviceContext, depth_format: vk::Format, msaa: &MsaaConfig, view_count: u32, object_ids: bool, load: bool, clear: &ClearConfig) -> Result<vk::RenderPass, ObjectCreateError> {
+        let (load_op, depth_initial_layout, color_initial_layout) = if load {
+            (vk::AttachmentLoadOp::LOAD, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::GENERAL)
+        } else {
+            (vk::AttachmentLoadOp::CLEAR, vk::ImageLayout::UNDEFINED, vk::ImageLayout::UNDEFINED)
+        };
+        let clear_op = |cleared: bool| if load || cleared { load_op } else { vk::AttachmentLoadOp::DONT_CARE };
+
+        let mut attachments = vec![
             vk::AttachmentDescription::builder()
                 .format(depth_format)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .samples(msaa.samples)
+                .load_op(clear_op(clear.clear_depth))
                 .store_op(vk::AttachmentStoreOp::STORE)
-                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .initial_layout(depth_initial_layout)
                 .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
                 .build(),
             vk::AttachmentDescription::builder()
                 .format(vk::Format::R8G8B8A8_SRGB)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .load_op(vk::AttachmentLoadOp::CLEAR)
-                .store_op(vk::AttachmentStoreOp::DONT_CARE)
-                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .samples(msaa.samples)
+                .load_op(clear_op(clear.color.is_some()))
+                .store_op(vk::AttachmentStoreOp::STORE) // Needed for readback
+                .initial_layout(color_initial_layout)
                 .final_layout(vk::ImageLayout::GENERAL)
                 .build(),
             vk::AttachmentDescription::builder()
@@ -320,6 +535,28 @@ impl DebugPipeline {
                 .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
                 .build()
         ];
+        if msaa.uses_resolve_attachment() {
+            attachments.push(vk::AttachmentDescription::builder()
+                .format(vk::Format::R8G8B8A8_SRGB)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .store_op(vk::AttachmentStoreOp::STORE) // Needed for readback
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::GENERAL)
+                .build()
+            );
+        }
+        if object_ids {
+            attachments.push(vk::AttachmentDescription::builder()
+                .format(vk::Format::R32_UINT)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(load_op)
+                .store_op(vk::AttachmentStoreOp::STORE) // Needed for readback
+                .initial_layout(color_initial_layout)
+                .final_layout(vk::ImageLayout::GENERAL)
+                .build()
+            );
+        }
 
         let pass_0_depth = vk::AttachmentReference {
             attachment: 0,
@@ -331,11 +568,27 @@ impl DebugPipeline {
                 attachment: 1,
                 layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
             },
+            vk::AttachmentReference {
+                attachment: 3,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            },
+        ];
+        let pass_0_color_refs = if object_ids {
+            &pass_0_color[..]
+        } else {
+            &pass_0_color[0..1]
+        };
+
+        let pass_0_resolve = [
+            vk::AttachmentReference {
+                attachment: 3,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            },
         ];
 
         let pass_1_input = [
             vk::AttachmentReference {
-                attachment: 1,
+                attachment: if msaa.uses_resolve_attachment() { 3 } else { 1 },
                 layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
             },
         ];
@@ -347,12 +600,18 @@ impl DebugPipeline {
             },
         ];
 
+        let pass_0 = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(pass_0_color_refs)
+            .depth_stencil_attachment(&pass_0_depth);
+        let pass_0 = if msaa.uses_resolve_attachment() {
+            pass_0.resolve_attachments(&pass_0_resolve)
+        } else {
+            pass_0
+        };
+
         let subpasses = [
-            vk::SubpassDescription::builder()
-                .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-                .color_attachments(&pass_0_color)
-                .depth_stencil_attachment(&pass_0_depth)
-                .build(),
+            pass_0.build(),
             vk::SubpassDescription::builder()
                 .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
                 .input_attachments(&pass_1_input)
@@ -360,7 +619,30 @@ impl DebugPipeline {
                 .build(),
         ];
 
+        // Both render passes must have identical dependencies to stay compatible. The external
+        // dependencies are only needed when a render pass continues the attachments of a previous
+        // segment of the pass.
+        let external_src_stage_mask = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS | vk::PipelineStageFlags::FRAGMENT_SHADER;
+        let external_src_access_mask = vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
         let subpass_dependencies = [
+            vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: external_src_stage_mask,
+                dst_stage_mask: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                src_access_mask: external_src_access_mask,
+                dst_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE | vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dependency_flags: vk::DependencyFlags::empty()
+            },
+            vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 1,
+                src_stage_mask: external_src_stage_mask,
+                dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                src_access_mask: external_src_access_mask,
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dependency_flags: vk::DependencyFlags::empty()
+            },
             vk::SubpassDependency {
                 src_subpass: 0,
                 dst_subpass: 1,
@@ -368,14 +650,29 @@ impl DebugPipeline {
                 dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
                 src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
                 dst_access_mask: vk::AccessFlags::SHADER_READ,
-                dependency_flags: vk::DependencyFlags::empty()
+                // Each view of the output only reads the same view of the color attachment
+                dependency_flags: if view_count > 1 { vk::DependencyFlags::VIEW_LOCAL } else { vk::DependencyFlags::empty() }
             }
         ];
 
+        // Both subpasses render all views. The views are rendered from nearby positions so they are
+        // marked as correlated.
+        let view_mask = (1u32 << view_count) - 1;
+        let view_masks = [view_mask; 2];
+        let correlation_masks = [view_mask];
+        let mut multiview_info = vk::RenderPassMultiviewCreateInfo::builder()
+            .view_masks(&view_masks)
+            .correlation_masks(&correlation_masks);
+
         let info = vk::RenderPassCreateInfo::builder()
             .attachments(&attachments)
             .subpasses(&subpasses)
             .dependencies(&subpass_dependencies);
+        let info = if view_count > 1 {
+            info.push_next(&mut multiview_info)
+        } else {
+            info
+        };
 
         let render_pass = unsafe {
             device.vk().create_render_pass(&info, None)
@@ -384,14 +681,30 @@ impl DebugPipeline {
             err
         })?;
 
-        drop(pass_0_depth);
-        drop(pass_0_color);
-        drop(pass_1_input);
-        drop(pass_1_color);
-
         Ok(render_pass)
     }
 
+    fn create_instance_placeholder(device: &DeviceContext) -> Result<(vk::Buffer, Allocation), ObjectCreateError> {
+        let info = vk::BufferCreateInfo::builder()
+            .size(INSTANCE_PLACEHOLDER_SIZE as vk::DeviceSize)
+            .usage(vk::BufferUsageFlags::VERTEX_BUFFER)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let (buffer, allocation, mapped_ptr) = unsafe {
+            device.get_allocator().create_buffer(&info, HostAccess::Random, &format_args!("DebugPipelineInstancePlaceholder"))
+        }.ok_or(ObjectCreateError::Allocation)?;
+
+        let mapped_ptr = mapped_ptr.unwrap_or_else(|| {
+            log::error!("Instance placeholder buffer is not mapped");
+            panic!()
+        });
+        unsafe {
+            std::ptr::write_bytes(mapped_ptr.as_ptr(), 0, INSTANCE_PLACEHOLDER_SIZE as usize);
+        }
+
+        Ok((buffer, allocation))
+    }
+
     fn create_descriptor_pool(device: &DeviceContext, concurrent_passes: usize) -> Result<vk::DescriptorPool, ObjectCreateError> {
         let concurrent_passes = concurrent_passes as u32;
 
@@ -417,6 +730,206 @@ impl DebugPipeline {
     }
 }
 
+impl SharedObjects {
+    /// Returns the pipeline to be used for a specific configuration. If the pipeline doesnt exits
+    /// yet a new one is created.
+    ///
+    /// If asynchronous pipeline creation is enabled the returned pipeline may be a fallback which
+    /// is indicated by the returned bool being false. Returns [`None`] if the draw should be
+    /// skipped.
+    fn get_pipeline(&self, shader: ShaderId, config: &PipelineConfig) -> Option<(vk::Pipeline, bool)> {
+        let mut guard = self.pipelines.lock().unwrap();
+        let pipelines = guard.get_mut(&shader).unwrap_or_else(|| {
+            log::error!("Called get_pipeline for unregistered shader {:?}", shader);
+            panic!()
+        });
+
+        if !self.async_pipeline_creation.load(Ordering::SeqCst) {
+            return Some((pipelines.get_or_create_pipeline(config, |vertex_format, instance_format| self.create_pipeline(config, vertex_format, instance_format)), true));
+        }
+
+        match pipelines.pipelines.get(config) {
+            Some(PipelineState::Ready(pipeline)) => return Some((*pipeline, true)),
+            Some(PipelineState::Pending) => {},
+            None => self.submit_pipeline_creation(pipelines, shader, *config),
+        }
+
+        pipelines.find_fallback(config).map(|pipeline| (pipeline, false))
+    }
+
+    /// Returns the layout used to fetch the vertex data of a shader. [`None`] if the pipelines of
+    /// the shader use vertex input attributes.
+    fn get_vertex_pull_layout(&self, shader: ShaderId) -> Option<VertexPullLayout> {
+        self.pipelines.lock().unwrap().get(&shader).and_then(|pipelines| pipelines.vertex_pull_layout)
+    }
+
+    /// Queues the creation of a pipeline on the compiler threads.
+    fn submit_pipeline_creation(&self, pipelines: &mut ShaderPipelines, shader: ShaderId, config: PipelineConfig) {
+        pipelines.pipelines.insert(config, PipelineState::Pending);
+
+        let weak = self.weak.clone();
+        let vertex_format = pipelines.vertex_format.clone();
+        let instance_format = pipelines.instance_format.clone();
+        pipeline_compiler::submit(Box::new(move || {
+            if let Some(parent) = weak.upgrade() {
+                let pipeline = parent.create_pipeline(&config, &vertex_format, instance_format.as_ref());
+                parent.on_pipeline_created(shader, config, pipeline);
+            }
+        }));
+    }
+
+    /// Creates the pipeline tracking state of a shader which has not been used by this pipeline
+    /// yet. Returns [`None`] if the shader does not exist.
+    fn create_shader_pipelines(&self, shader: ShaderId) -> Option<ShaderPipelines> {
+        let shader_obj = self.emulator.get_shader(shader)?;
+        let listener = shader_obj.register_drop_listener(&(self.weak.upgrade().unwrap() as Arc<dyn ShaderDropListener + Send + Sync>));
+
+        let vertex_format = shader_obj.get_vertex_format().clone();
+        let instance_format = shader_obj.get_instance_format().copied();
+        let used_uniforms = shader_obj.get_used_uniforms();
+
+        let vertex_pull_layout = self.shader_modules.get_vertex_pull_layout(&vertex_format);
+
+        Some(ShaderPipelines::new(self.emulator.get_device().clone(), vertex_format, instance_format, used_uniforms, vertex_pull_layout, listener))
+    }
+
+    /// Called by the compiler threads when a asynchronously created pipeline is ready.
+    fn on_pipeline_created(&self, shader: ShaderId, config: PipelineConfig, pipeline: vk::Pipeline) {
+        let mut guard = self.pipelines.lock().unwrap();
+        match guard.get_mut(&shader) {
+            Some(pipelines) => pipelines.insert_created(config, pipeline),
+            // The shader has been dropped while the pipeline was being created
+            None => unsafe {
+                self.emulator.get_device().vk().destroy_pipeline(pipeline, None);
+            }
+        }
+    }
+
+    fn create_pipeline(&self, config: &PipelineConfig, vertex_format: &VertexFormat, instance_format: Option<&InstanceFormat>) -> vk::Pipeline {
+        let alloc = Bump::new();
+        let (shader_stages, input_state) = self.shader_modules.configure_pipeline(vertex_format, instance_format, &alloc);
+
+        // The viewport and scissor are dynamic so that pipelines do not depend on the framebuffer size
+        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(config.cull_state.cull_mode)
+            .front_face(config.cull_state.front_face)
+            .depth_bias_enable(config.depth_bias_enable)
+            .line_width(1f32);
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(self.msaa.samples)
+            .sample_shading_enable(self.msaa.min_sample_shading.is_some())
+            .min_sample_shading(self.msaa.min_sample_shading.unwrap_or(0f32));
+
+        let attachment_blend_state = [
+            match &config.blend_mode.to_blend_state() {
+                Some(blend_state) => vk::PipelineColorBlendAttachmentState::builder()
+                    .blend_enable(true)
+                    .src_color_blend_factor(blend_state.color_src_factor)
+                    .dst_color_blend_factor(blend_state.color_dst_factor)
+                    .color_blend_op(blend_state.color_op)
+                    .src_alpha_blend_factor(blend_state.alpha_src_factor)
+                    .dst_alpha_blend_factor(blend_state.alpha_dst_factor)
+                    .alpha_blend_op(blend_state.alpha_op)
+                    .color_write_mask(config.color_write_mask)
+                    .build(),
+                None => vk::PipelineColorBlendAttachmentState::builder()
+                    .blend_enable(false)
+                    .color_write_mask(config.color_write_mask)
+                    .build(),
+            }
+        ];
+
+        let object_id_blend_state = vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(false)
+            .color_write_mask(vk::ColorComponentFlags::R)
+            .build();
+        let attachment_blend_state = [attachment_blend_state[0], object_id_blend_state];
+        let attachment_blend_state = if self.object_ids {
+            &attachment_blend_state[..]
+        } else {
+            &attachment_blend_state[0..1]
+        };
+
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .attachments(attachment_blend_state);
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR, vk::DynamicState::DEPTH_BIAS];
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&dynamic_states);
+
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(config.primitive_topology)
+            .primitive_restart_enable(false);
+
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(config.depth_test_enable)
+            .depth_write_enable(config.depth_write_enable)
+            .depth_compare_op(match config.depth_test {
+                DepthTest::Default => self.depth_mode.get_compare_op(),
+                DepthTest::Equal => vk::CompareOp::EQUAL,
+            });
+
+        let info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(shader_stages)
+            .vertex_input_state(input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state)
+            .layout(self.draw_pipeline.pipeline_layout)
+            .render_pass(self.render_pass)
+            .subpass(0);
+
+        let pipeline = *unsafe {
+            self.emulator.get_device().vk().create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), None)
+        }.unwrap_or_else(|(_, err)| {
+            log::error!("Failed to create graphics pipeline {:?}", err);
+            panic!();
+        }).get(0).unwrap();
+
+        pipeline
+    }
+
+    /// Returns the render pass used to start a pass with the clear config.
+    fn get_clear_render_pass(&self, clear: &ClearConfig) -> vk::RenderPass {
+        let key = (clear.color.is_some(), clear.clear_depth);
+        if key == (true, true) {
+            return self.render_pass;
+        }
+
+        *self.clear_render_passes.lock().unwrap().entry(key).or_insert_with(|| {
+            DebugPipeline::create_render_pass(self.emulator.get_device(), self.depth_format, &self.msaa, self.view_count, self.object_ids, false, clear).unwrap_or_else(|err| {
+                log::error!("Failed to create render pass for clear config {:?}: {:?}", clear, err);
+                panic!()
+            })
+        })
+    }
+}
+
+impl ShaderDropListener for SharedObjects {
+    fn on_shader_drop(&self, id: ShaderId) {
+        let mut drop = false;
+        let mut guard = self.pipelines.lock().unwrap();
+        if let Some(pipeline) = guard.get_mut(&id) {
+            pipeline.mark();
+            drop = pipeline.can_drop();
+        }
+        if drop {
+            guard.remove(&id);
+        }
+    }
+}
+
 impl EmulatorPipeline for DebugPipeline {
     fn start_pass(&self) -> Box<dyn EmulatorPipelinePass + Send> {
         let index = self.next_index();
@@ -426,24 +939,69 @@ impl EmulatorPipeline for DebugPipeline {
     }
 
     fn get_output(&self) -> (Vec2u32, &[vk::ImageView]) {
-        (self.framebuffer_size, &self.output_views)
+        (self.framebuffer_size, &self.view_outputs[0])
+    }
+
+    fn get_attachment(&self, attachment: PassAttachment, index: usize) -> Option<AttachmentInfo> {
+        let objects = self.pass_objects.get(index)?;
+        let (image, format, aspect_mask, layout) = match attachment {
+            PassAttachment::Output => (objects.output_image, vk::Format::R8G8B8A8_SRGB, vk::ImageAspectFlags::COLOR, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            PassAttachment::ObjectId if self.shared.object_ids => (objects.object_id_image, vk::Format::R32_UINT, vk::ImageAspectFlags::COLOR, vk::ImageLayout::GENERAL),
+            PassAttachment::ObjectId => return None,
+            // Multisampled images cannot be copied into buffers so only resolved images are supported
+            PassAttachment::Color if self.shared.msaa.uses_resolve_attachment() => (objects.resolve_image, vk::Format::R8G8B8A8_SRGB, vk::ImageAspectFlags::COLOR, vk::ImageLayout::GENERAL),
+            _ if self.shared.msaa.is_enabled() => return None,
+            PassAttachment::Color => (objects.pass_image, vk::Format::R8G8B8A8_SRGB, vk::ImageAspectFlags::COLOR, vk::ImageLayout::GENERAL),
+            PassAttachment::Depth => (objects.depth_image, self.shared.depth_format, vk::ImageAspectFlags::DEPTH, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+        };
+
+        Some(AttachmentInfo {
+            image,
+            format,
+            aspect_mask,
+            layout,
+            size: self.framebuffer_size,
+        })
+    }
+
+    fn get_sampled_attachment(&self, attachment: PassAttachment) -> Option<(Vec<vk::ImageView>, vk::ImageLayout)> {
+        let views = |view: fn(&PassObjects) -> vk::ImageView| self.pass_objects.iter().map(view).collect();
+        match attachment {
+            PassAttachment::Output => Some((self.view_outputs[0].to_vec(), vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+            // Framebuffer views of stereo passes are array views which cannot be sampled as 2d images
+            PassAttachment::Color if self.shared.view_count > 1 => None,
+            PassAttachment::Color if self.shared.msaa.uses_resolve_attachment() => Some((views(|objects| objects.resolve_view), vk::ImageLayout::GENERAL)),
+            _ if self.shared.msaa.is_enabled() => None,
+            PassAttachment::Color => Some((views(|objects| objects.pass_view), vk::ImageLayout::GENERAL)),
+            PassAttachment::Depth => Some((views(|objects| objects.depth_sampler_views[0]), vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+            PassAttachment::ObjectId => None,
+        }
+    }
+
+    fn get_used_vertex_channels(&self) -> VertexChannels {
+        match self.shared.shader_modules.mode {
+            DebugPipelineMode::Depth |
+            DebugPipelineMode::Position => VertexChannels::empty(),
+            DebugPipelineMode::Color => VertexChannels::COLOR,
+            DebugPipelineMode::Normal => VertexChannels::NORMAL,
+            DebugPipelineMode::UV0 |
+            DebugPipelineMode::Textured0 => VertexChannels::UV0,
+            DebugPipelineMode::UV1 |
+            DebugPipelineMode::Textured1 => VertexChannels::UV1,
+            DebugPipelineMode::UV2 |
+            DebugPipelineMode::Textured2 => VertexChannels::UV2,
+        }
     }
 
     fn inc_shader_used(&self, shader: ShaderId) {
-        let mut guard = self.pipelines.lock().unwrap();
+        let mut guard = self.shared.pipelines.lock().unwrap();
         if let Some(pipelines) = guard.get_mut(&shader) {
             pipelines.inc_used();
         } else {
-            let listener = self.emulator.get_shader(shader).unwrap_or_else(|| {
+            let mut pipelines = self.shared.create_shader_pipelines(shader).unwrap_or_else(|| {
                 log::error!("Called inc_shader_used for nonexistent shader {:?}", shader);
                 panic!()
-            }).register_drop_listener(&(self.weak.upgrade().unwrap() as Arc<dyn ShaderDropListener + Send + Sync>));
-
-            let shader_obj = self.emulator.get_shader(shader).unwrap();
-            let vertex_format = shader_obj.get_vertex_format().clone();
-            let used_uniforms = shader_obj.get_used_uniforms();
-
-            let mut  pipelines = ShaderPipelines::new(self.emulator.get_device().clone(), vertex_format, used_uniforms, listener);
+            });
             pipelines.inc_used();
 
             guard.insert(shader, pipelines);
@@ -451,7 +1009,7 @@ impl EmulatorPipeline for DebugPipeline {
     }
 
     fn dec_shader_used(&self, shader: ShaderId) {
-        let mut guard = self.pipelines.lock().unwrap();
+        let mut guard = self.shared.pipelines.lock().unwrap();
         let pipelines = guard.get_mut(&shader).unwrap_or_else(|| {
             log::error!("Called dec_shader_used for shader which is not registered {:?}", shader);
             panic!();
@@ -462,20 +1020,39 @@ impl EmulatorPipeline for DebugPipeline {
             guard.remove(&shader);
         }
     }
-}
 
-impl ShaderDropListener for DebugPipeline {
-    fn on_shader_drop(&self, id: ShaderId) {
-        let mut drop = false;
-        let mut guard = self.pipelines.lock().unwrap();
-        if let Some(pipeline) = guard.get_mut(&id) {
-            pipeline.mark();
-            drop = pipeline.can_drop();
+    fn prewarm_shader(&self, shader: ShaderId, hints: &[PipelineConfigHint]) {
+        let mut guard = self.shared.pipelines.lock().unwrap();
+        if !guard.contains_key(&shader) {
+            match self.shared.create_shader_pipelines(shader) {
+                Some(pipelines) => guard.insert(shader, pipelines),
+                None => {
+                    log::warn!("Attempted to prewarm nonexistent shader {:?}", shader);
+                    return;
+                }
+            };
         }
-        if drop {
-            guard.remove(&id);
+        let pipelines = guard.get_mut(&shader).unwrap();
+
+        for hint in hints {
+            let config = PipelineConfig::from_hint(hint);
+            if !pipelines.pipelines.contains_key(&config) {
+                self.shared.submit_pipeline_creation(pipelines, shader, config);
+            }
         }
     }
+
+    fn is_shader_prewarmed(&self, shader: ShaderId, hints: &[PipelineConfigHint]) -> bool {
+        let guard = self.shared.pipelines.lock().unwrap();
+        let pipelines = match guard.get(&shader) {
+            Some(pipelines) => pipelines,
+            None => return hints.is_empty(),
+        };
+
+        hints.iter().all(|hint| {
+            matches!(pipelines.pipelines.get(&PipelineConfig::from_hint(hint)), Some(PipelineState::Ready(_)))
+        })
+    }
 }
 
 impl Drop for DebugPipeline {
@@ -484,13 +1061,23 @@ impl Drop for DebugPipeline {
         for objects in self.pass_objects.iter_mut() {
             objects.destroy(device);
         }
-        self.pipelines.get_mut().unwrap().clear();
         unsafe {
             device.vk().destroy_descriptor_pool(self.descriptor_pool, None);
         }
+    }
+}
+
+impl Drop for SharedObjects {
+    fn drop(&mut self) {
+        let device = self.emulator.get_device();
+        self.pipelines.get_mut().unwrap().clear();
         self.background_pipeline.destroy(device);
-        self.draw_pipeline.destroy(device);
         unsafe {
+            device.get_allocator().destroy_buffer(self.instance_placeholder.0, self.instance_placeholder.1);
+            for render_pass in self.clear_render_passes.get_mut().unwrap().values() {
+                device.vk().destroy_render_pass(*render_pass, None);
+            }
+            device.vk().destroy_render_pass(self.load_render_pass, None);
             device.vk().destroy_render_pass(self.render_pass, None);
         }
         self.shader_modules.destroy(device);
@@ -500,14 +1087,20 @@ impl Drop for DebugPipeline {
 /// The shader modules needed to create vulkan pipelines for the debug pipeline
 struct ShaderModules {
     mode: DebugPipelineMode,
+    multiview: bool,
+    reverse_z: bool,
     vertex_module: vk::ShaderModule,
     null_module: vk::ShaderModule,
     fragment_module: vk::ShaderModule,
     texture_module: Option<vk::ShaderModule>,
+
+    /// The vertex module fetching the vertex data from the vertex buffer address. Replaces all
+    /// other vertex modules. [`None`] if vertex pulling is disabled.
+    pulled_module: Option<vk::ShaderModule>,
 }
 
 impl ShaderModules {
-    fn new(device: &DeviceContext, mode: DebugPipelineMode) -> Result<Self, ObjectCreateError> {
+    fn new(device: &DeviceContext, mode: DebugPipelineMode, multiview: bool, reverse_z: bool, vertex_pulling: bool) -> Result<Self, ObjectCreateError> {
         let null_module = try_create_shader_module(device, DEBUG_NULL_VERTEX_BIN, "null_vertex")?;
 
         let fragment_module = try_create_shader_module(device, DEBUG_FRAGMENT_BIN, "fragment").map_err(|err| {
@@ -546,28 +1139,72 @@ impl ShaderModules {
             err
         })?;
 
+        let pulled_module = if vertex_pulling {
+            try_create_shader_module(device, DEBUG_PULLED_VERTEX_BIN, "pulled_vertex").map(|val| Some(val))
+        } else {
+            Ok(None)
+        }.map_err(|err| {
+            unsafe {
+                device.vk().destroy_shader_module(null_module, None);
+                device.vk().destroy_shader_module(fragment_module, None);
+                device.vk().destroy_shader_module(vertex_module, None);
+                if let Some(texture_module) = texture_module {
+                    device.vk().destroy_shader_module(texture_module, None);
+                }
+            }
+            err
+        })?;
+
         Ok(Self {
             mode,
+            multiview,
+            reverse_z,
             vertex_module,
             null_module,
             fragment_module,
             texture_module,
+            pulled_module,
         })
     }
 
-    fn configure_pipeline<'s, 'a: 's>(&'s self, vertex_format: &VertexFormat, alloc: &'a Bump) -> (&'a [vk::PipelineShaderStageCreateInfo], &'a vk::PipelineVertexInputStateCreateInfo) {
+    fn configure_pipeline<'s, 'a: 's>(&'s self, vertex_format: &VertexFormat, instance_format: Option<&InstanceFormat>, alloc: &'a Bump) -> (&'a [vk::PipelineShaderStageCreateInfo], &'a vk::PipelineVertexInputStateCreateInfo) {
+        // Shaders without instance format only draw a single instance reading the placeholder
+        let (instance_stride, instance_attribute) = match instance_format {
+            Some(format) => (format.stride, vk::VertexInputAttributeDescription {
+                location: INSTANCE_OFFSET_LOCATION,
+                binding: INSTANCE_BINDING,
+                format: format.offset.format,
+                offset: format.offset.offset,
+            }),
+            None => (0, vk::VertexInputAttributeDescription {
+                location: INSTANCE_OFFSET_LOCATION,
+                binding: INSTANCE_BINDING,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: 0,
+            }),
+        };
+
         let input_bindings: &[_] = alloc.alloc([
             vk::VertexInputBindingDescription {
                 binding: 0,
                 stride: vertex_format.stride,
                 input_rate: vk::VertexInputRate::VERTEX
+            },
+            vk::VertexInputBindingDescription {
+                binding: INSTANCE_BINDING,
+                stride: instance_stride,
+                input_rate: vk::VertexInputRate::INSTANCE
             }
         ]);
 
         let vertex_module;
         let input_attributes: &[_];
         let vertex_format_supported;
-        if let Some(entry) = self.process_vertex_format(vertex_format) {
+        if let (Some(pulled_module), Some(layout)) = (self.pulled_module, self.get_vertex_pull_layout(vertex_format)) {
+            vertex_format_supported = layout.output_mode != VertexPullLayout::OUTPUT_NULL;
+            vertex_module = pulled_module;
+            input_attributes = alloc.alloc([instance_attribute]);
+        } else if let Some(entry) = self.process_vertex_format(vertex_format) {
             vertex_format_supported = true;
             vertex_module = self.vertex_module;
 
@@ -583,7 +1220,8 @@ impl ShaderModules {
                     binding: 0,
                     format: entry.format,
                     offset: entry.offset
-                }
+                },
+                instance_attribute,
             ]);
         } else {
             vertex_format_supported = false;
@@ -596,6 +1234,7 @@ impl ShaderModules {
                     format: vertex_format.position.format,
                     offset: vertex_format.position.offset,
                 },
+                instance_attribute,
             ]);
         }
 
@@ -626,11 +1265,38 @@ impl ShaderModules {
             }
         };
 
+        // Selects the projection and depth mapping in mc_uniforms.glsl and the output of pulled.vert.
+        // Entries of constants not used by a module are ignored.
+        let output_mode = self.get_vertex_pull_layout(vertex_format).map_or(0, |layout| layout.output_mode);
+        let vertex_data = alloc.alloc([self.multiview as vk::Bool32, self.reverse_z as vk::Bool32, output_mode]);
+        let vertex_entries = alloc.alloc([
+            vk::SpecializationMapEntry {
+                constant_id: MC_MULTIVIEW_CONSTANT_ID,
+                offset: 0,
+                size: 4
+            },
+            vk::SpecializationMapEntry {
+                constant_id: MC_REVERSE_Z_CONSTANT_ID,
+                offset: 4,
+                size: 4
+            },
+            vk::SpecializationMapEntry {
+                constant_id: 0,
+                offset: 8,
+                size: 4
+            }
+        ]);
+        let vertex_specialization = alloc.alloc(vk::SpecializationInfo::builder()
+            .map_entries(vertex_entries)
+            .data(bytes_of(vertex_data))
+        );
+
         let shader_stages: &[_] = alloc.alloc([
             vk::PipelineShaderStageCreateInfo::builder()
                 .stage(vk::ShaderStageFlags::VERTEX)
                 .module(vertex_module)
                 .name(SHADER_ENTRY)
+                .specialization_info(vertex_specialization)
                 .build(),
             vk::PipelineShaderStageCreateInfo::builder()
                 .stage(vk::ShaderStageFlags::FRAGMENT)
@@ -640,6 +1306,8 @@ impl ShaderModules {
                 .build(),
         ]);
 
+        // Pulled vertices only use the instance binding
+        let input_bindings = if input_attributes.len() == 1 { &input_bindings[1..] } else { input_bindings };
         let input_state: &_ = alloc.alloc(vk::PipelineVertexInputStateCreateInfo::builder()
             .vertex_binding_descriptions(input_bindings)
             .vertex_attribute_descriptions(input_attributes)
@@ -664,6 +1332,48 @@ impl ShaderModules {
         }
     }
 
+    /// Returns the layout used to fetch vertex data of the format in pulled.vert. Returns [`None`]
+    /// if vertex pulling is disabled or the format cannot be fetched.
+    fn get_vertex_pull_layout(&self, vertex_format: &VertexFormat) -> Option<VertexPullLayout> {
+        self.pulled_module?;
+
+        if vertex_format.position.format != vk::Format::R32G32B32_SFLOAT || vertex_format.position.offset % 4 != 0 || vertex_format.stride % 4 != 0 {
+            return None;
+        }
+
+        let (output_mode, attribute) = match self.mode {
+            DebugPipelineMode::Depth |
+            DebugPipelineMode::Position => (VertexPullLayout::OUTPUT_POSITION, None),
+            DebugPipelineMode::Color => (VertexPullLayout::OUTPUT_COLOR, self.process_vertex_format(vertex_format)),
+            _ => (VertexPullLayout::OUTPUT_UV, self.process_vertex_format(vertex_format)),
+        };
+
+        let (output_mode, attribute_offset, attribute_format) = match (output_mode, attribute) {
+            (VertexPullLayout::OUTPUT_POSITION, _) => (output_mode, 0, VertexPullLayout::FORMAT_NONE),
+            (_, None) => (VertexPullLayout::OUTPUT_NULL, 0, VertexPullLayout::FORMAT_NONE),
+            (_, Some(entry)) => {
+                let format = match entry.format {
+                    vk::Format::R32G32_SFLOAT => VertexPullLayout::FORMAT_R32G32_SFLOAT,
+                    vk::Format::R8G8B8A8_UNORM => VertexPullLayout::FORMAT_R8G8B8A8_UNORM,
+                    vk::Format::R16G16_SINT => VertexPullLayout::FORMAT_R16G16_SINT,
+                    _ => return None,
+                };
+                if entry.offset % 4 != 0 {
+                    return None;
+                }
+                (output_mode, entry.offset, format)
+            }
+        };
+
+        Some(VertexPullLayout {
+            vertex_stride: vertex_format.stride,
+            position_offset: vertex_format.position.offset,
+            attribute_offset,
+            attribute_format,
+            output_mode,
+        })
+    }
+
     fn destroy(&mut self, device: &DeviceContext) {
         unsafe {
             device.vk().destroy_shader_module(self.vertex_module, None);
@@ -672,25 +1382,27 @@ impl ShaderModules {
             if let Some(texture_module) = self.texture_module.take() {
                 device.vk().destroy_shader_module(texture_module, None);
             }
+            if let Some(pulled_module) = self.pulled_module.take() {
+                device.vk().destroy_shader_module(pulled_module, None);
+            }
         }
     }
 }
 
+/// The layouts used by all draw pipelines. Owned by the [`LayoutCache`] of the device so they are
+/// shared with every other pipeline using the same bindings.
+///
+/// [`LayoutCache`]: crate::device::layout_cache::LayoutCache
 struct DrawPipeline {
-    set0_layout: vk::DescriptorSetLayout,
+    set1_layout: vk::DescriptorSetLayout,
     pipeline_layout: vk::PipelineLayout,
 }
 
 impl DrawPipeline {
     fn new(device: &DeviceContext) -> Result<Self, ObjectCreateError> {
+        let layout_cache = device.get_utils().layout_cache();
+
         let bindings = [
-            vk::DescriptorSetLayoutBinding {
-                binding: 0,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
-                descriptor_count: 1,
-                stage_flags: vk::ShaderStageFlags::ALL,
-                p_immutable_samplers: std::ptr::null(),
-            },
             vk::DescriptorSetLayoutBinding {
                 binding: 1,
                 descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
@@ -698,53 +1410,65 @@ impl DrawPipeline {
                 stage_flags: vk::ShaderStageFlags::ALL_GRAPHICS,
                 p_immutable_samplers: std::ptr::null(),
             },
+            // The point lights and per tile light lists. See lights.glsl
+            vk::DescriptorSetLayoutBinding {
+                binding: 2,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                p_immutable_samplers: std::ptr::null(),
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 3,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                p_immutable_samplers: std::ptr::null(),
+            },
         ];
 
-        let info = vk::DescriptorSetLayoutCreateInfo::builder()
-            .flags(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR)
-            .bindings(&bindings);
-
-        let set0_layout = unsafe {
-            device.vk().create_descriptor_set_layout(&info, None)
-        }.map_err(|err| {
+        let set0_layout = layout_cache.get_descriptor_set_layout(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR, &bindings).map_err(|err| {
             log::error!("vkCreateDescriptorSetLayout returned {:?} in DrawPipeline::new when creating set 0 layout", err);
             err
         })?;
 
+        // Push descriptors cannot contain dynamic buffers so the static uniforms use their own set
+        let bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::ALL,
+                p_immutable_samplers: std::ptr::null(),
+            },
+        ];
+
+        let set1_layout = layout_cache.get_descriptor_set_layout(vk::DescriptorSetLayoutCreateFlags::empty(), &bindings).map_err(|err| {
+            log::error!("vkCreateDescriptorSetLayout returned {:?} in DrawPipeline::new when creating set 1 layout", err);
+            err
+        })?;
+
         let push_constant_range = vk::PushConstantRange {
             stage_flags: vk::ShaderStageFlags::ALL_GRAPHICS,
             offset: 0,
-            size: std::mem::size_of::<PushConstants>() as u32,
+            size: (std::mem::size_of::<PushConstants>() + std::mem::size_of::<VertexPullConstants>()) as u32,
         };
 
         let layouts = [
-            set0_layout
+            set0_layout,
+            set1_layout,
         ];
 
-        let info = vk::PipelineLayoutCreateInfo::builder()
-            .push_constant_ranges(std::slice::from_ref(&push_constant_range))
-            .set_layouts(&layouts);
-
-        let pipeline_layout = unsafe {
-            device.vk().create_pipeline_layout(&info, None)
-        }.map_err(|err| {
+        let pipeline_layout = layout_cache.get_pipeline_layout(&layouts, std::slice::from_ref(&push_constant_range)).map_err(|err| {
             log::error!("vkCreatePipelineLayout returned {:?} in DrawPipeline::new", err);
-            unsafe { device.vk().destroy_descriptor_set_layout(set0_layout, None) };
             err
         })?;
 
         Ok(Self {
-            set0_layout,
+            set1_layout,
             pipeline_layout
         })
     }
-
-    fn destroy(&mut self, device: &DeviceContext) {
-        unsafe {
-            device.vk().destroy_pipeline_layout(self.pipeline_layout, None);
-            device.vk().destroy_descriptor_set_layout(self.set0_layout, None);
-        }
-    }
 }
 
 struct BackgroundPipeline {
@@ -754,7 +1478,9 @@ struct BackgroundPipeline {
 }
 
 impl BackgroundPipeline {
-    fn new(device: &DeviceContext, render_pass: vk::RenderPass, subpass: u32, framebuffer_size: Vec2u32) -> Result<Self, ObjectCreateError> {
+    fn new(device: &DeviceContext, render_pass: vk::RenderPass, subpass: u32, msaa: &MsaaConfig) -> Result<Self, ObjectCreateError> {
+        let layout_cache = device.get_utils().layout_cache();
+
         let bindings = [
             vk::DescriptorSetLayoutBinding {
                 binding: 0,
@@ -765,34 +1491,23 @@ impl BackgroundPipeline {
             },
         ];
 
-        let info = vk::DescriptorSetLayoutCreateInfo::builder()
-            .bindings(&bindings);
-
-        let descriptor_set_layout = unsafe {
-            device.vk().create_descriptor_set_layout(&info, None)
-        }.map_err(|err| {
+        let descriptor_set_layout = layout_cache.get_descriptor_set_layout(vk::DescriptorSetLayoutCreateFlags::empty(), &bindings).map_err(|err| {
             log::error!("vkCreateDescriptorSetLayout returned {:?} in BackgroundPipeline::new", err);
             err
         })?;
 
-        let info = vk::PipelineLayoutCreateInfo::builder()
-            .set_layouts(std::slice::from_ref(&descriptor_set_layout));
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 0,
+            size: std::mem::size_of::<ScreenEffects>() as u32
+        };
 
-        let pipeline_layout = unsafe {
-            device.vk().create_pipeline_layout(&info, None)
-        }.map_err(|err| {
+        let pipeline_layout = layout_cache.get_pipeline_layout(std::slice::from_ref(&descriptor_set_layout), std::slice::from_ref(&push_constant_range)).map_err(|err| {
             log::error!("vkCreatePipelineLayout returned {:?} in BackgroundPipeline::new", err);
-            unsafe { device.vk().destroy_descriptor_set_layout(descriptor_set_layout, None) };
             err
         })?;
 
-        let pipeline = Self::create_pipeline(device, pipeline_layout, render_pass, subpass, framebuffer_size).map_err(|err| {
-            unsafe {
-                device.vk().destroy_pipeline_layout(pipeline_layout, None);
-                device.vk().destroy_descriptor_set_layout(descriptor_set_layout, None);
-            }
-            err
-        })?;
+        let pipeline = Self::create_pipeline(device, pipeline_layout, render_pass, subpass, msaa)?;
 
         Ok(Self {
             descriptor_set_layout,
@@ -804,47 +1519,46 @@ impl BackgroundPipeline {
     fn destroy(&mut self, device: &DeviceContext) {
         unsafe {
             device.vk().destroy_pipeline(self.pipeline, None);
-            device.vk().destroy_pipeline_layout(self.pipeline_layout, None);
-            device.vk().destroy_descriptor_set_layout(self.descriptor_set_layout, None);
         }
     }
 
-    fn create_pipeline(device: &DeviceContext, layout: vk::PipelineLayout, render_pass: vk::RenderPass, subpass: u32, framebuffer_size: Vec2u32) -> Result<vk::Pipeline, ObjectCreateError> {
+    fn create_pipeline(device: &DeviceContext, layout: vk::PipelineLayout, render_pass: vk::RenderPass, subpass: u32, msaa: &MsaaConfig) -> Result<vk::Pipeline, ObjectCreateError> {
+        let shader_resolve = msaa.is_enabled() && msaa.resolve == MsaaResolve::Shader;
+
         let vertex_module = try_create_shader_module(device, BACKGROUND_VERTEX_BIN, "background_vert")?;
-        let fragment_module = try_create_shader_module(device, BACKGROUND_FRAGMENT_BIN, "background_frag").map_err(|err| {
+        let fragment_module = if shader_resolve {
+            try_create_shader_module(device, BACKGROUND_MS_FRAGMENT_BIN, "background_ms_frag")
+        } else {
+            try_create_shader_module(device, BACKGROUND_FRAGMENT_BIN, "background_frag")
+        }.map_err(|err| {
             unsafe { device.vk().destroy_shader_module(vertex_module, None) };
             err
         })?;
 
-        let specialization_data = Vec2f32::new(framebuffer_size[0] as f32, framebuffer_size[1] as f32);
-        let specializations = [
+        let sample_count = msaa.samples.as_raw();
+        let fragment_specializations = [
             vk::SpecializationMapEntry {
                 constant_id: 0,
                 offset: 0,
                 size: 4
-            },
-            vk::SpecializationMapEntry {
-                constant_id: 1,
-                offset: 4,
-                size: 4
             }
         ];
 
-        let specialization_info = vk::SpecializationInfo::builder()
-            .map_entries(&specializations)
-            .data(cast_slice(specialization_data.data.as_slice()));
+        let fragment_specialization_info = vk::SpecializationInfo::builder()
+            .map_entries(&fragment_specializations)
+            .data(bytes_of(&sample_count));
 
         let shader_stages = [
             vk::PipelineShaderStageCreateInfo::builder()
                 .stage(vk::ShaderStageFlags::VERTEX)
                 .module(vertex_module)
                 .name(SHADER_ENTRY)
-                .specialization_info(&specialization_info)
                 .build(),
             vk::PipelineShaderStageCreateInfo::builder()
                 .stage(vk::ShaderStageFlags::FRAGMENT)
                 .module(fragment_module)
                 .name(SHADER_ENTRY)
+                .specialization_info(&fragment_specialization_info)
                 .build()
         ];
 
@@ -860,12 +1574,9 @@ impl BackgroundPipeline {
             .depth_bounds_test_enable(false)
             .stencil_test_enable(false);
 
-        let viewport = make_full_viewport(framebuffer_size);
-        let scissor = make_full_rect(framebuffer_size);
-
         let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
-            .viewports(std::slice::from_ref(&viewport))
-            .scissors(std::slice::from_ref(&scissor));
+            .viewport_count(1)
+            .scissor_count(1);
 
         let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
             .polygon_mode(vk::PolygonMode::FILL)
@@ -888,7 +1599,9 @@ impl BackgroundPipeline {
             .logic_op_enable(false)
             .attachments(&attachment_blend_state);
 
-        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder();
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&dynamic_states);
 
         let info = vk::GraphicsPipelineCreateInfo::builder()
             .stages(&shader_stages)
@@ -919,7 +1632,7 @@ impl BackgroundPipeline {
             device.vk().destroy_shader_module(vertex_module, None);
             device.vk().destroy_shader_module(fragment_module, None);
         }
-        drop(specialization_info);
+        drop(fragment_specialization_info);
 
         Ok(pipeline)
     }
@@ -930,7 +1643,9 @@ struct PassObjects {
 
     depth_image: vk::Image,
     depth_framebuffer_view: vk::ImageView,
-    depth_sampler_view: vk::ImageView,
+
+    /// Single layer views of the depth image for every view.
+    depth_sampler_views: Vec<vk::ImageView>,
 
     pass_image: vk::Image,
     pass_view: vk::ImageView,
@@ -938,83 +1653,164 @@ struct PassObjects {
     output_image: vk::Image,
     output_view: vk::ImageView,
 
+    /// Single layer views of the output image for every view. Empty if only one view is rendered
+    /// in which case the framebuffer view is sampled directly.
+    output_layer_views: Vec<vk::ImageView>,
+
+    /// Target of the render pass resolve. Only used with [`MsaaResolve::RenderPass`].
+    resolve_image: vk::Image,
+    resolve_view: vk::ImageView,
+
+    /// The object id attachment. Only used if object ids are enabled.
+    object_id_image: vk::Image,
+    object_id_view: vk::ImageView,
+
+    /// The per tile light lists written by the light culling shader.
+    light_tile_buffer: vk::Buffer,
+
     bg_descriptor_set: vk::DescriptorSet,
     framebuffer: vk::Framebuffer,
+    uniform_ring: Mutex<UniformRing>,
 
     allocations: Vec<Allocation>,
 }
 
 impl PassObjects {
-    fn new(device: &DeviceContext, framebuffer_size: Vec2u32, depth_format: vk::Format, color_format: vk::Format, render_pass: vk::RenderPass, bg_descriptor_set: vk::DescriptorSet) -> Result<Self, ObjectCreateError> {
+    fn new(device: &DeviceContext, framebuffer_size: Vec2u32, depth_format: vk::Format, color_format: vk::Format, msaa: &MsaaConfig, view_count: u32, object_ids: bool, render_pass: vk::RenderPass, bg_descriptor_set: vk::DescriptorSet, uniform_ring: UniformRing) -> Result<Self, ObjectCreateError> {
         let mut result = PassObjects {
             ready: AtomicBool::new(true),
 
             depth_image: vk::Image::null(),
             depth_framebuffer_view: vk::ImageView::null(),
-            depth_sampler_view: vk::ImageView::null(),
+            depth_sampler_views: Vec::with_capacity(view_count as usize),
 
             pass_image: vk::Image::null(),
             pass_view: vk::ImageView::null(),
 
             output_image: vk::Image::null(),
             output_view: vk::ImageView::null(),
+            output_layer_views: Vec::new(),
+
+            resolve_image: vk::Image::null(),
+            resolve_view: vk::ImageView::null(),
+
+            object_id_image: vk::Image::null(),
+            object_id_view: vk::ImageView::null(),
+
+            light_tile_buffer: vk::Buffer::null(),
 
             bg_descriptor_set,
             framebuffer: vk::Framebuffer::null(),
+            uniform_ring: Mutex::new(uniform_ring),
 
-            allocations: Vec::with_capacity(3)
+            allocations: Vec::with_capacity(6)
         };
 
-        let (depth_image, allocation) = Self::create_image(device, framebuffer_size, depth_format, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)?;
+        let (depth_image, allocation) = Self::create_image(device, framebuffer_size, view_count, depth_format, msaa.samples, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC)?;
         result.depth_image = depth_image;
         result.allocations.push(allocation);
 
-        let depth_framebuffer_view = Self::create_image_view(device, depth_image, depth_format, vk::ImageAspectFlags::DEPTH, false).map_err(|err| {
+        let depth_framebuffer_view = Self::create_image_view(device, depth_image, depth_format, vk::ImageAspectFlags::DEPTH, false, 0, view_count).map_err(|err| {
             result.destroy(device);
             err
         })?;
         result.depth_framebuffer_view = depth_framebuffer_view;
 
-        let depth_sampler_view = Self::create_image_view(device, depth_image, depth_format, vk::ImageAspectFlags::DEPTH, true).map_err(|err| {
-            result.destroy(device);
-            err
-        })?;
-        result.depth_sampler_view = depth_sampler_view;
+        for view in 0..view_count {
+            let depth_sampler_view = Self::create_image_view(device, depth_image, depth_format, vk::ImageAspectFlags::DEPTH, true, view, 1).map_err(|err| {
+                result.destroy(device);
+                err
+            })?;
+            result.depth_sampler_views.push(depth_sampler_view);
+        }
 
-        let (pass_image, allocation) = Self::create_image(device, framebuffer_size, color_format, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT).map_err(|err| {
+        // Single sampled images can be presented directly for debugging
+        let pass_usage = if msaa.is_enabled() { vk::ImageUsageFlags::empty() } else { vk::ImageUsageFlags::SAMPLED };
+        let (pass_image, allocation) = Self::create_image(device, framebuffer_size, view_count, color_format, msaa.samples, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC | pass_usage).map_err(|err| {
             result.destroy(device);
             err
         })?;
         result.pass_image = pass_image;
         result.allocations.push(allocation);
 
-        let pass_view = Self::create_image_view(device, pass_image, color_format, vk::ImageAspectFlags::COLOR, false).map_err(|err| {
+        let pass_view = Self::create_image_view(device, pass_image, color_format, vk::ImageAspectFlags::COLOR, false, 0, view_count).map_err(|err| {
             result.destroy(device);
             err
         })?;
         result.pass_view = pass_view;
 
-        let (output_image, allocation) = Self::create_image(device, framebuffer_size, color_format, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED).map_err(|err| {
+        let (output_image, allocation) = Self::create_image(device, framebuffer_size, view_count, color_format, vk::SampleCountFlags::TYPE_1, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC).map_err(|err| {
             result.destroy(device);
             err
         })?;
         result.output_image = output_image;
         result.allocations.push(allocation);
 
-        let output_view = Self::create_image_view(device, output_image, color_format, vk::ImageAspectFlags::COLOR, false).map_err(|err| {
+        let output_view = Self::create_image_view(device, output_image, color_format, vk::ImageAspectFlags::COLOR, false, 0, view_count).map_err(|err| {
             result.destroy(device);
             err
         })?;
         result.output_view = output_view;
 
-        let framebuffer = Self::create_framebuffer(device, framebuffer_size, depth_framebuffer_view, pass_view, output_view, render_pass).map_err(|err| {
+        if view_count > 1 {
+            for view in 0..view_count {
+                let output_layer_view = Self::create_image_view(device, output_image, color_format, vk::ImageAspectFlags::COLOR, false, view, 1).map_err(|err| {
+                    result.destroy(device);
+                    err
+                })?;
+                result.output_layer_views.push(output_layer_view);
+            }
+        }
+
+        let input_view = if msaa.uses_resolve_attachment() {
+            let (resolve_image, allocation) = Self::create_image(device, framebuffer_size, view_count, color_format, vk::SampleCountFlags::TYPE_1, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::INPUT_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::SAMPLED).map_err(|err| {
+                result.destroy(device);
+                err
+            })?;
+            result.resolve_image = resolve_image;
+            result.allocations.push(allocation);
+
+            let resolve_view = Self::create_image_view(device, resolve_image, color_format, vk::ImageAspectFlags::COLOR, false, 0, view_count).map_err(|err| {
+                result.destroy(device);
+                err
+            })?;
+            result.resolve_view = resolve_view;
+
+            resolve_view
+        } else {
+            pass_view
+        };
+
+        if object_ids {
+            let (object_id_image, allocation) = Self::create_image(device, framebuffer_size, view_count, vk::Format::R32_UINT, vk::SampleCountFlags::TYPE_1, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC).map_err(|err| {
+                result.destroy(device);
+                err
+            })?;
+            result.object_id_image = object_id_image;
+            result.allocations.push(allocation);
+
+            let object_id_view = Self::create_image_view(device, object_id_image, vk::Format::R32_UINT, vk::ImageAspectFlags::COLOR, false, 0, view_count).map_err(|err| {
+                result.destroy(device);
+                err
+            })?;
+            result.object_id_view = object_id_view;
+        }
+
+        let framebuffer = Self::create_framebuffer(device, framebuffer_size, depth_framebuffer_view, pass_view, output_view, result.resolve_view, result.object_id_view, render_pass).map_err(|err| {
             result.destroy(device);
             err
         })?;
         result.framebuffer = framebuffer;
 
+        let (light_tile_buffer, allocation) = Self::create_light_tile_buffer(device, framebuffer_size).map_err(|err| {
+            result.destroy(device);
+            err
+        })?;
+        result.light_tile_buffer = light_tile_buffer;
+        result.allocations.push(allocation);
+
         let info = vk::DescriptorImageInfo::builder()
-            .image_view(pass_view)
+            .image_view(input_view)
             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
 
         let write = vk::WriteDescriptorSet::builder()
@@ -1031,6 +1827,15 @@ impl PassObjects {
         Ok(result)
     }
 
+    /// Returns a view of the output image which can be sampled to access a single view.
+    fn get_output_sampler_view(&self, view: usize) -> vk::ImageView {
+        if self.output_layer_views.is_empty() {
+            self.output_view
+        } else {
+            self.output_layer_views[view]
+        }
+    }
+
     fn wait_and_take(&self) {
         let mut start = Instant::now();
         loop {
@@ -1047,9 +1852,27 @@ impl PassObjects {
 
     fn destroy(&mut self, device: &DeviceContext) {
         unsafe {
+            if self.light_tile_buffer != vk::Buffer::null() {
+                device.vk().destroy_buffer(self.light_tile_buffer, None);
+            }
             if self.framebuffer != vk::Framebuffer::null() {
                 device.vk().destroy_framebuffer(self.framebuffer, None);
             }
+            if self.object_id_view != vk::ImageView::null() {
+                device.vk().destroy_image_view(self.object_id_view, None);
+            }
+            if self.object_id_image != vk::Image::null() {
+                device.vk().destroy_image(self.object_id_image, None);
+            }
+            if self.resolve_view != vk::ImageView::null() {
+                device.vk().destroy_image_view(self.resolve_view, None);
+            }
+            if self.resolve_image != vk::Image::null() {
+                device.vk().destroy_image(self.resolve_image, None);
+            }
+            for view in self.output_layer_views.drain(..) {
+                device.vk().destroy_image_view(view, None);
+            }
             if self.output_view != vk::ImageView::null() {
                 device.vk().destroy_image_view(self.output_view, None);
             }
@@ -1062,8 +1885,8 @@ impl PassObjects {
             if self.pass_image != vk::Image::null() {
                 device.vk().destroy_image(self.pass_image, None);
             }
-            if self.depth_sampler_view != vk::ImageView::null() {
-                device.vk().destroy_image_view(self.depth_sampler_view, None);
+            for view in self.depth_sampler_views.drain(..) {
+                device.vk().destroy_image_view(view, None);
             }
             if self.depth_framebuffer_view != vk::ImageView::null() {
                 device.vk().destroy_image_view(self.depth_framebuffer_view, None);
@@ -1073,9 +1896,10 @@ impl PassObjects {
             }
             device.get_allocator().free_memory_pages(&self.allocations);
         }
+        self.uniform_ring.get_mut().unwrap().destroy(device);
     }
 
-    fn create_image(device: &DeviceContext, size: Vec2u32, format: vk::Format, usage: vk::ImageUsageFlags) -> Result<(vk::Image, Allocation), ObjectCreateError> {
+    fn create_image(device: &DeviceContext, size: Vec2u32, layers: u32, format: vk::Format, samples: vk::SampleCountFlags, usage: vk::ImageUsageFlags) -> Result<(vk::Image, Allocation), ObjectCreateError> {
         let info = vk::ImageCreateInfo::builder()
             .image_type(vk::ImageType::TYPE_2D)
             .format(format)
@@ -1085,8 +1909,8 @@ impl PassObjects {
                 depth: 1
             })
             .mip_levels(1)
-            .array_layers(1)
-            .samples(vk::SampleCountFlags::TYPE_1)
+            .array_layers(layers)
+            .samples(samples)
             .tiling(vk::ImageTiling::OPTIMAL)
             .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
@@ -1097,10 +1921,22 @@ impl PassObjects {
         }.ok_or(ObjectCreateError::Allocation)
     }
 
-    fn create_image_view(device: &DeviceContext, image: vk::Image, format: vk::Format, aspect_mask: vk::ImageAspectFlags, swizzle_r: bool) -> Result<vk::ImageView, ObjectCreateError> {
+    fn create_light_tile_buffer(device: &DeviceContext, framebuffer_size: Vec2u32) -> Result<(vk::Buffer, Allocation), ObjectCreateError> {
+        let info = vk::BufferCreateInfo::builder()
+            .size(get_light_tile_buffer_size(framebuffer_size))
+            .usage(vk::BufferUsageFlags::STORAGE_BUFFER)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        unsafe {
+            device.get_allocator().create_gpu_buffer(&info, &format_args!("DebugPipelineLightTiles"))
+        }.ok_or(ObjectCreateError::Allocation)
+    }
+
+    /// Creates a view of a range of layers. Views of multiple layers are array views.
+    fn create_image_view(device: &DeviceContext, image: vk::Image, format: vk::Format, aspect_mask: vk::ImageAspectFlags, swizzle_r: bool, base_array_layer: u32, layer_count: u32) -> Result<vk::ImageView, ObjectCreateError> {
         let info = vk::ImageViewCreateInfo::builder()
             .image(image)
-            .view_type(vk::ImageViewType::TYPE_2D)
+            .view_type(if layer_count > 1 { vk::ImageViewType::TYPE_2D_ARRAY } else { vk::ImageViewType::TYPE_2D })
             .format(format);
 
         let info = if swizzle_r {
@@ -1124,8 +1960,8 @@ impl PassObjects {
                 aspect_mask,
                 base_mip_level: 0,
                 level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1
+                base_array_layer,
+                layer_count
             });
 
         let image_view = unsafe {
@@ -1138,10 +1974,12 @@ impl PassObjects {
         Ok(image_view)
     }
 
-    fn create_framebuffer(device: &DeviceContext, size: Vec2u32, depth_view: vk::ImageView, pass_view: vk::ImageView, output_view: vk::ImageView, render_pass: vk::RenderPass) -> Result<vk::Framebuffer, ObjectCreateError> {
-        let attachments = [
-            depth_view, pass_view, output_view
-        ];
+    /// Creates the framebuffer. If `resolve_view` or `object_id_view` are null the corresponding
+    /// attachment is not used.
+    fn create_framebuffer(device: &DeviceContext, size: Vec2u32, depth_view: vk::ImageView, pass_view: vk::ImageView, output_view: vk::ImageView, resolve_view: vk::ImageView, object_id_view: vk::ImageView, render_pass: vk::RenderPass) -> Result<vk::Framebuffer, ObjectCreateError> {
+        let attachments: Vec<_> = [depth_view, pass_view, output_view, resolve_view, object_id_view].into_iter()
+            .filter(|view| *view != vk::ImageView::null())
+            .collect();
 
         let info = vk::FramebufferCreateInfo::builder()
             .render_pass(render_pass)
@@ -1166,13 +2004,73 @@ struct PipelineConfig {
     primitive_topology: vk::PrimitiveTopology,
     depth_test_enable: bool,
     depth_write_enable: bool,
+    depth_bias_enable: bool,
+    depth_test: DepthTest,
+    cull_state: CullState,
+    blend_mode: BlendMode,
+    color_write_mask: vk::ColorComponentFlags,
+}
+
+impl PipelineConfig {
+    fn from_hint(hint: &PipelineConfigHint) -> Self {
+        Self {
+            primitive_topology: hint.primitive_topology,
+            depth_test_enable: true,
+            depth_write_enable: hint.depth_write_enable,
+            depth_bias_enable: hint.depth_bias_enable,
+            depth_test: hint.depth_test,
+            cull_state: hint.cull_state,
+            blend_mode: resolve_blend_mode(hint.blend_state, hint.logic_op),
+            color_write_mask: hint.color_write_mask,
+        }
+    }
+
+    /// Returns true if a pipeline created with this config can be used in place of a pipeline with
+    /// the other config while it is being created. The shaders of the debug pipeline only depend on
+    /// the vertex format so any pipeline of the same shader with the same topology would work.
+    /// However to avoid corrupting the depth buffer or making opaque geometry transparent the
+    /// depth and blend state must also roughly match.
+    fn is_fallback_for(&self, other: &PipelineConfig) -> bool {
+        self.primitive_topology == other.primitive_topology &&
+            self.depth_write_enable == other.depth_write_enable &&
+            self.depth_bias_enable == other.depth_bias_enable &&
+            self.blend_mode.is_opaque() == other.blend_mode.is_opaque() &&
+            self.color_write_mask == other.color_write_mask
+    }
+}
+
+/// Returns the canonical blend mode used for a draw.
+///
+/// Vulkan does not apply logic ops to the srgb output attachment so we always have to fall back
+/// to blending. Dual source blending is never used since the debug shaders only write a single
+/// color and the object id output would exceed `maxFragmentDualSrcAttachments` on most devices.
+fn resolve_blend_mode(blend_state: Option<BlendState>, logic_op: Option<vk::LogicOp>) -> BlendMode {
+    let blend_state = match logic_op {
+        Some(logic_op) => BlendState::approximate_logic_op(logic_op),
+        None => blend_state,
+    };
+    BlendMode::from_blend_state(blend_state.map(|blend_state| {
+        if blend_state.uses_dual_source() {
+            blend_state.approximate_dual_source()
+        } else {
+            blend_state
+        }
+    }))
+}
+
+enum PipelineState {
+    /// The pipeline is being created on a compiler thread.
+    Pending,
+    Ready(vk::Pipeline),
 }
 
 struct ShaderPipelines {
     device: Arc<DeviceContext>,
     vertex_format: VertexFormat,
+    instance_format: Option<InstanceFormat>,
     used_uniforms: McUniform,
-    pipelines: HashMap<PipelineConfig, vk::Pipeline>,
+    vertex_pull_layout: Option<VertexPullLayout>,
+    pipelines: HashMap<PipelineConfig, PipelineState>,
     #[allow(unused)]
     listener: ShaderListener,
     used_counter: u32,
@@ -1180,11 +2078,13 @@ struct ShaderPipelines {
 }
 
 impl ShaderPipelines {
-    fn new(device: Arc<DeviceContext>, vertex_format: VertexFormat, used_uniforms: McUniform, listener: ShaderListener) -> Self {
+    fn new(device: Arc<DeviceContext>, vertex_format: VertexFormat, instance_format: Option<InstanceFormat>, used_uniforms: McUniform, vertex_pull_layout: Option<VertexPullLayout>, listener: ShaderListener) -> Self {
         Self {
             device,
             vertex_format,
+            instance_format,
             used_uniforms,
+            vertex_pull_layout,
             pipelines: HashMap::new(),
             listener,
             used_counter: 0,
@@ -1192,16 +2092,37 @@ impl ShaderPipelines {
         }
     }
 
-    fn get_or_create_pipeline<T: FnOnce(&VertexFormat) -> vk::Pipeline>(&mut self, config: &PipelineConfig, create_fn: T) -> vk::Pipeline {
-        if let Some(pipeline) = self.pipelines.get(config) {
+    fn get_or_create_pipeline<T: FnOnce(&VertexFormat, Option<&InstanceFormat>) -> vk::Pipeline>(&mut self, config: &PipelineConfig, create_fn: T) -> vk::Pipeline {
+        if let Some(PipelineState::Ready(pipeline)) = self.pipelines.get(config) {
             *pipeline
         } else {
-            let pipeline = create_fn(&self.vertex_format);
-            self.pipelines.insert(*config, pipeline);
+            // If the pipeline is pending the asynchronously created one is destroyed once ready
+            let pipeline = create_fn(&self.vertex_format, self.instance_format.as_ref());
+            self.pipelines.insert(*config, PipelineState::Ready(pipeline));
             pipeline
         }
     }
 
+    /// Stores a asynchronously created pipeline. If a pipeline has been created synchronously in
+    /// the meantime the new pipeline is destroyed.
+    fn insert_created(&mut self, config: PipelineConfig, pipeline: vk::Pipeline) {
+        if let Some(PipelineState::Ready(_)) = self.pipelines.get(&config) {
+            unsafe {
+                self.device.vk().destroy_pipeline(pipeline, None);
+            }
+        } else {
+            self.pipelines.insert(config, PipelineState::Ready(pipeline));
+        }
+    }
+
+    /// Returns a ready pipeline which can be used in place of the pipeline for the config.
+    fn find_fallback(&self, config: &PipelineConfig) -> Option<vk::Pipeline> {
+        self.pipelines.iter().find_map(|(other, state)| match state {
+            PipelineState::Ready(pipeline) if other.is_fallback_for(config) => Some(*pipeline),
+            _ => None,
+        })
+    }
+
     fn inc_used(&mut self) {
         self.used_counter += 1;
     }
@@ -1217,35 +2138,244 @@ impl ShaderPipelines {
     fn can_drop(&self) -> bool {
         self.marked && self.used_counter == 0
     }
-}
+}
+
+impl Drop for ShaderPipelines {
+    fn drop(&mut self) {
+        for state in self.pipelines.values() {
+            if let PipelineState::Ready(pipeline) = state {
+                unsafe {
+                    self.device.vk().destroy_pipeline(*pipeline, None);
+                }
+            }
+        }
+    }
+}
+
+/// Persistently mapped buffer used to stream the static uniforms of a pass.
+///
+/// Every update is written into the next free slot which is then bound using a dynamic offset so
+/// no descriptor updates are needed. Each [`PassObjects`] owns a ring which is reset when a new
+/// pass starts using it. If a pass runs out of slots an additional block is allocated and kept for
+/// future passes.
+struct UniformRing {
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    blocks: Vec<UniformBlock>,
+    slot_size: vk::DeviceSize,
+    current_block: usize,
+    next_slot: u32,
+}
+
+struct UniformBlock {
+    buffer: vk::Buffer,
+    allocation: Allocation,
+    mapped_ptr: NonNull<u8>,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl UniformRing {
+    const SLOTS_PER_BLOCK: u32 = 4096;
+    const MAX_BLOCKS: u32 = 16;
+
+    fn new(device: &DeviceContext, set_layout: vk::DescriptorSetLayout) -> Result<Self, ObjectCreateError> {
+        let alignment = unsafe {
+            device.get_instance().vk().get_physical_device_properties(device.get_functions().physical_device)
+        }.limits.min_uniform_buffer_offset_alignment;
+        let size = std::mem::size_of::<StaticUniforms>() as vk::DeviceSize;
+        let slot_size = ((size + alignment - 1) / alignment) * alignment;
+
+        let sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                descriptor_count: Self::MAX_BLOCKS
+            },
+        ];
+
+        let info = vk::DescriptorPoolCreateInfo::builder()
+            .max_sets(Self::MAX_BLOCKS)
+            .pool_sizes(&sizes);
+
+        let descriptor_pool = unsafe {
+            device.vk().create_descriptor_pool(&info, None)
+        }.map_err(|err| {
+            log::error!("vkCreateDescriptorPool returned {:?} in UniformRing::new", err);
+            err
+        })?;
+
+        let mut result = Self {
+            set_layout,
+            descriptor_pool,
+            blocks: Vec::with_capacity(1),
+            slot_size,
+            current_block: 0,
+            next_slot: 0,
+        };
+
+        if let Err(err) = result.create_block(device) {
+            result.destroy(device);
+            return Err(err);
+        }
+
+        Ok(result)
+    }
+
+    /// Makes all slots available again. Must only be called once the previous pass using this ring
+    /// has completed execution.
+    fn reset(&mut self) {
+        self.current_block = 0;
+        self.next_slot = 0;
+    }
+
+    /// Writes the data into the next free slot and returns the descriptor set and dynamic offset
+    /// which must be used to access it.
+    fn write(&mut self, device: &DeviceContext, data: &[u8]) -> (vk::DescriptorSet, u32) {
+        if data.len() as vk::DeviceSize > self.slot_size {
+            log::error!("Attempted to write {} bytes into uniform ring with slot size {}", data.len(), self.slot_size);
+            panic!()
+        }
+
+        if self.next_slot == Self::SLOTS_PER_BLOCK {
+            self.current_block += 1;
+            self.next_slot = 0;
+        }
+        if self.current_block == self.blocks.len() {
+            if self.blocks.len() as u32 == Self::MAX_BLOCKS {
+                log::error!("Exceeded maximum number of uniform updates in a single pass");
+                panic!()
+            }
+            self.create_block(device).unwrap_or_else(|err| {
+                log::error!("Failed to create uniform ring block {:?}", err);
+                panic!()
+            });
+        }
+
+        let block = &self.blocks[self.current_block];
+        let offset = (self.next_slot as vk::DeviceSize) * self.slot_size;
+        self.next_slot += 1;
+
+        let dst = unsafe {
+            std::slice::from_raw_parts_mut(block.mapped_ptr.as_ptr().offset(offset as isize), data.len())
+        };
+        dst.copy_from_slice(data);
+
+        (block.descriptor_set, offset as u32)
+    }
+
+    fn create_block(&mut self, device: &DeviceContext) -> Result<(), ObjectCreateError> {
+        let size = self.slot_size * (Self::SLOTS_PER_BLOCK as vk::DeviceSize);
+        let info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let (buffer, allocation, mapped_ptr) = unsafe {
+            device.get_allocator().create_buffer(&info, HostAccess::Random, &format_args!("DebugPipelineUniformRing"))
+        }.ok_or(ObjectCreateError::Allocation)?;
+
+        let info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(std::slice::from_ref(&self.set_layout));
+
+        let descriptor_set = match unsafe {
+            device.vk().allocate_descriptor_sets(&info)
+        } {
+            Ok(sets) => sets[0],
+            Err(err) => {
+                log::error!("vkAllocateDescriptorSets returned {:?} in UniformRing::create_block", err);
+                unsafe { device.get_allocator().destroy_buffer(buffer, allocation) };
+                return Err(ObjectCreateError::Vulkan(err));
+            }
+        };
+
+        let buffer_info = vk::DescriptorBufferInfo {
+            buffer,
+            offset: 0,
+            range: std::mem::size_of::<StaticUniforms>() as vk::DeviceSize
+        };
+        let write = vk::WriteDescriptorSet::builder()
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .dst_array_element(0)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .buffer_info(std::slice::from_ref(&buffer_info));
+
+        unsafe {
+            device.vk().update_descriptor_sets(std::slice::from_ref(&write), &[])
+        };
+
+        self.blocks.push(UniformBlock {
+            buffer,
+            allocation,
+            mapped_ptr: mapped_ptr.unwrap(),
+            descriptor_set,
+        });
+
+        Ok(())
+    }
 
-impl Drop for ShaderPipelines {
-    fn drop(&mut self) {
-        for pipeline in self.pipelines.values() {
-            unsafe {
-                self.device.vk().destroy_pipeline(*pipeline, None);
+    fn destroy(&mut self, device: &DeviceContext) {
+        unsafe {
+            for block in self.blocks.drain(..) {
+                device.get_allocator().destroy_buffer(block.buffer, block.allocation);
             }
+            device.vk().destroy_descriptor_pool(self.descriptor_pool, None);
         }
     }
 }
 
+// The mapped pointer is only accessed while holding the lock of the pass objects
+unsafe impl Send for UniformRing {
+}
+
 struct DebugPipelinePass {
     parent: Arc<DebugPipeline>,
     index: usize,
 
+    /// The size of the render targets used by this pass.
+    framebuffer_size: Vec2u32,
+
     placeholder_texture: vk::ImageView,
     placeholder_sampler: vk::Sampler,
     shader_uniforms: HashMap<ShaderId, UniformStateTracker>,
 
     command_buffer: Option<vk::CommandBuffer>,
+
+    /// Records the gpu culling of the current segment. Submitted before the command buffer of the
+    /// segment so that the culling is outside of the render pass.
+    culling_command_buffer: Option<vk::CommandBuffer>,
     current_pipeline: Option<(ShaderId, PipelineConfig)>,
     current_vertex_buffer: Option<vk::Buffer>,
+    current_instance_buffer: Option<(vk::Buffer, vk::DeviceSize)>,
+    current_vertex_pull_layout: Option<VertexPullLayout>,
+    current_vertex_pull_constants: Option<VertexPullConstants>,
     current_index_buffer: Option<vk::Buffer>,
+    current_scissor: Option<vk::Rect2D>,
+    current_viewport: Option<vk::Rect2D>,
+    current_depth_bias: Option<DepthBias>,
+    current_object_id: Option<u32>,
+    current_static_uniforms: Option<(vk::DescriptorSet, u32)>,
+    screen_effects: ScreenEffects,
+
+    /// The view projection matrices of stereo passes. Copied into the trackers of new shaders.
+    view_projections: [Mat4f32; 2],
+
+    /// The render graph of the pass and the graph pass of the outputs reading the pass images.
+    render_graph: Option<(RenderGraph, GraphPass)>,
+
+    /// The render pass is only begun by the first task rendering into the attachments so that the
+    /// clear config can still be changed before.
+    clear_config: ClearConfig,
+    render_pass_begun: bool,
+
+    /// The lights are culled right before the render pass is begun.
+    lights: LightsInfo,
 }
 
 impl DebugPipelinePass {
     fn new(parent: Arc<DebugPipeline>, index: usize) -> Self {
         Self {
+            framebuffer_size: parent.framebuffer_size,
             parent,
             index,
 
@@ -1254,16 +2384,65 @@ impl DebugPipelinePass {
             shader_uniforms: HashMap::new(),
 
             command_buffer: None,
+            culling_command_buffer: None,
             current_pipeline: None,
             current_vertex_buffer: None,
-            current_index_buffer: None
+            current_instance_buffer: None,
+            current_vertex_pull_layout: None,
+            current_vertex_pull_constants: None,
+            current_index_buffer: None,
+            current_scissor: None,
+            current_viewport: None,
+            current_depth_bias: None,
+            current_object_id: None,
+            current_static_uniforms: None,
+            screen_effects: ScreenEffects::NONE,
+
+            view_projections: [Mat4f32::identity(); 2],
+
+            render_graph: None,
+
+            clear_config: ClearConfig::DEFAULT,
+            render_pass_begun: false,
+
+            lights: LightsInfo::none(),
         }
     }
 
+    /// Builds the render graph of the pass. Returns the graph, the render pass and the pass of the
+    /// outputs reading the images after the render pass.
+    fn build_render_graph(&self) -> (RenderGraph, GraphPass, GraphPass) {
+        let objects = &self.parent.pass_objects[self.index];
+        let subresource_range = |aspect_mask| vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: self.parent.shared.view_count
+        };
+
+        let mut graph = RenderGraph::new();
+
+        // The previous pass using these objects may still be read by outputs
+        let depth = graph.add_image(objects.depth_image, subresource_range(vk::ImageAspectFlags::DEPTH), ImageUsage::OUTPUT_READ);
+        let output = graph.add_image(objects.output_image, subresource_range(vk::ImageAspectFlags::COLOR), ImageUsage::OUTPUT_READ);
+
+        let render_pass = graph.add_pass(&[
+            (depth, ImageUsage::depth_attachment(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+            (output, ImageUsage::color_attachment(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+        ]);
+        let outputs = graph.add_pass(&[
+            (depth, ImageUsage::OUTPUT_READ),
+            (output, ImageUsage::OUTPUT_READ),
+        ]);
+
+        (graph, render_pass, outputs)
+    }
+
     fn update_uniform(&mut self, shader: ShaderId, data: &McUniformData) {
         if !self.shader_uniforms.contains_key(&shader) {
-            let uniforms = self.parent.pipelines.lock().unwrap().get(&shader).unwrap().used_uniforms;
-            self.shader_uniforms.insert(shader, UniformStateTracker::new(uniforms, self.placeholder_texture, self.placeholder_sampler));
+            let uniforms = self.parent.shared.pipelines.lock().unwrap().get(&shader).unwrap().used_uniforms;
+            self.shader_uniforms.insert(shader, UniformStateTracker::new(uniforms, self.parent.shared.depth_mode, self.placeholder_texture, self.placeholder_sampler, &self.view_projections));
         }
         let tracker = self.shader_uniforms.get_mut(&shader).unwrap();
         tracker.update_uniform(data);
@@ -1271,27 +2450,208 @@ impl DebugPipelinePass {
 
     fn update_texture(&mut self, shader: ShaderId, index: u32, view: vk::ImageView, sampler: vk::Sampler) {
         if !self.shader_uniforms.contains_key(&shader) {
-            let uniforms = self.parent.pipelines.lock().unwrap().get(&shader).unwrap().used_uniforms;
-            self.shader_uniforms.insert(shader, UniformStateTracker::new(uniforms, self.placeholder_texture, self.placeholder_sampler));
+            let uniforms = self.parent.shared.pipelines.lock().unwrap().get(&shader).unwrap().used_uniforms;
+            self.shader_uniforms.insert(shader, UniformStateTracker::new(uniforms, self.parent.shared.depth_mode, self.placeholder_texture, self.placeholder_sampler, &self.view_projections));
         }
         let tracker = self.shader_uniforms.get_mut(&shader).unwrap();
         tracker.update_texture(index, view, sampler);
     }
 
-    fn draw(&mut self, task: &DrawTask, obj: &mut PooledObjectProvider) {
+    /// Submits the command buffer of the current segment preceded by the culling command buffer if
+    /// any culled draws were recorded.
+    fn push_submit<'a>(&mut self, cmd: vk::CommandBuffer, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+        let command_buffer_infos: &[vk::CommandBufferSubmitInfo] = if let Some(culling_cmd) = self.culling_command_buffer.take() {
+            unsafe {
+                self.parent.emulator.get_device().vk().end_command_buffer(culling_cmd).unwrap();
+            }
+            alloc.alloc([
+                vk::CommandBufferSubmitInfo::builder().command_buffer(culling_cmd).build(),
+                vk::CommandBufferSubmitInfo::builder().command_buffer(cmd).build(),
+            ])
+        } else {
+            alloc.alloc([
+                vk::CommandBufferSubmitInfo::builder().command_buffer(cmd).build(),
+            ])
+        };
+
+        submits.push(vk::SubmitInfo2::builder()
+            .command_buffer_infos(command_buffer_infos)
+        );
+    }
+
+    fn set_view_projections(&mut self, matrices: &[Mat4f32; 2]) {
+        self.view_projections = if self.parent.shared.depth_mode == DepthMode::Reversed {
+            matrices.map(|matrix| to_reverse_z(&matrix))
+        } else {
+            *matrices
+        };
+        let matrices = &self.view_projections;
+        for tracker in self.shader_uniforms.values_mut() {
+            tracker.set_view_projections(matrices);
+        }
+    }
+
+    fn clear_depth(&mut self, region: Option<vk::Rect2D>) {
+        let rect = clamp_scissor(region, self.framebuffer_size);
+        if rect.extent.width == 0 || rect.extent.height == 0 {
+            return;
+        }
+
+        let attachment = vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            color_attachment: 0,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: self.parent.shared.depth_mode.get_clear_depth(),
+                    stencil: 0
+                }
+            }
+        };
+        let rect = vk::ClearRect {
+            rect,
+            base_array_layer: 0,
+            layer_count: 1
+        };
+
+        unsafe {
+            self.parent.emulator.get_device().vk().cmd_clear_attachments(*self.command_buffer.as_ref().unwrap(), std::slice::from_ref(&attachment), std::slice::from_ref(&rect));
+        }
+    }
+
+    /// Begins the render pass of the pipeline. `render_pass` must be either the render pass or the
+    /// load render pass of the parent.
+    fn begin_render_pass(&self, cmd: vk::CommandBuffer, render_pass: vk::RenderPass) {
+        let clear_values = [
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: self.parent.shared.depth_mode.get_clear_depth(),
+                    stencil: 0
+                }
+            },
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: self.clear_config.color.unwrap_or([0f32, 0f32, 0f32, 0f32]),
+                }
+            },
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0f32, 0f32, 0f32, 0f32],
+                }
+            },
+            // Either the resolve or object id attachment
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    uint32: [0u32, 0u32, 0u32, 0u32],
+                }
+            }
+        ];
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(render_pass)
+            .framebuffer(self.parent.pass_objects[self.index].framebuffer)
+            .render_area(make_full_rect(self.framebuffer_size))
+            .clear_values(&clear_values);
+
+        unsafe {
+            self.parent.emulator.get_device().vk().cmd_begin_render_pass(cmd, &info, vk::SubpassContents::INLINE);
+        }
+    }
+
+    /// Begins the render pass using the clear config if it has not been begun yet.
+    fn ensure_render_pass(&mut self) {
+        if !self.render_pass_begun {
+            self.render_pass_begun = true;
+            let cmd = *self.command_buffer.as_ref().unwrap();
+
+            // Also clears the tile lists of the previous pass if there are no lights
+            let tile_buffer = self.parent.pass_objects[self.index].light_tile_buffer;
+            self.parent.emulator.get_light_culling_pipeline().record(cmd, &self.lights, tile_buffer, self.framebuffer_size);
+
+            let render_pass = self.parent.shared.get_clear_render_pass(&self.clear_config);
+            self.begin_render_pass(cmd, render_pass);
+        }
+    }
+
+    /// Resets all state bound to the command buffer. Must be called when a new command buffer is
+    /// started.
+    fn reset_bound_state(&mut self) {
+        self.current_pipeline = None;
+        self.current_vertex_buffer = None;
+        self.current_instance_buffer = None;
+        self.current_vertex_pull_constants = None;
+        self.current_index_buffer = None;
+        self.current_scissor = None;
+        self.current_viewport = None;
+        self.current_depth_bias = None;
+        self.current_object_id = None;
+        self.current_static_uniforms = None;
+        for tracker in self.shader_uniforms.values_mut() {
+            tracker.invalidate_bindings();
+        }
+    }
+
+    fn draw(&mut self, task: &DrawTask) {
+        if !self.bind_draw_state(task) {
+            return;
+        }
+
+        unsafe {
+            self.parent.emulator.get_device().vk().cmd_draw_indexed(*self.command_buffer.as_ref().unwrap(), task.index_count, task.instance_count, task.first_index, task.vertex_offset, task.first_instance);
+        }
+    }
+
+    /// Culls the sections of the task and draws the visible sections using the draw commands
+    /// written by the culling shader.
+    fn draw_culled(&mut self, task: &CulledDrawTask, obj: &mut PooledObjectProvider) {
+        let compact = self.parent.emulator.get_device().draw_indirect_count_khr().is_some();
+        let culling_cmd = *self.culling_command_buffer.get_or_insert_with(|| obj.get_begin_command_buffer().unwrap());
+        self.parent.emulator.get_section_culling_pipeline().record(culling_cmd, task, compact);
+
+        if !self.bind_draw_state(&task.draw) {
+            return;
+        }
+
+        let device = self.parent.emulator.get_device();
+        let cmd = *self.command_buffer.as_ref().unwrap();
+        let commands_offset = task.indirect_offset + DRAW_COMMAND_OFFSET;
+        unsafe {
+            if let Some(draw_indirect_count) = device.draw_indirect_count_khr() {
+                draw_indirect_count.cmd_draw_indexed_indirect_count(cmd, task.indirect_buffer, commands_offset, task.indirect_buffer, task.indirect_offset, task.section_count, DRAW_COMMAND_STRIDE);
+            } else {
+                // multiDrawIndirect is not enabled so every command needs its own draw
+                for index in 0..task.section_count {
+                    let offset = commands_offset + (index as vk::DeviceSize) * (DRAW_COMMAND_STRIDE as vk::DeviceSize);
+                    device.vk().cmd_draw_indexed_indirect(cmd, task.indirect_buffer, offset, 1, DRAW_COMMAND_STRIDE);
+                }
+            }
+        }
+    }
+
+    /// Binds the pipeline and all state used by the draw. Returns false if the draw must be
+    /// skipped.
+    fn bind_draw_state(&mut self, task: &DrawTask) -> bool {
         let device = self.parent.emulator.get_device();
         let cmd = *self.command_buffer.as_ref().unwrap();
 
         let pipeline_config = PipelineConfig {
             primitive_topology: task.primitive_topology,
             depth_test_enable: true,
-            depth_write_enable: task.depth_write_enable
+            depth_write_enable: task.depth_write_enable,
+            depth_bias_enable: task.depth_bias.is_some(),
+            depth_test: task.depth_test,
+            cull_state: task.cull_state,
+            blend_mode: resolve_blend_mode(task.blend_state, task.logic_op),
+            color_write_mask: task.color_write_mask,
         };
 
         if self.current_pipeline != Some((task.shader, pipeline_config)) {
-            self.current_pipeline = Some((task.shader, pipeline_config));
+            let (new_pipeline, exact) = match self.parent.shared.get_pipeline(task.shader, &pipeline_config) {
+                Some(pipeline) => pipeline,
+                None => return false,
+            };
 
-            let new_pipeline = self.parent.get_pipeline(task.shader, &pipeline_config);
+            // Fallback pipelines are not cached so the real pipeline is used as soon as it is ready
+            self.current_pipeline = if exact { Some((task.shader, pipeline_config)) } else { None };
+            self.current_vertex_pull_layout = self.parent.shared.get_vertex_pull_layout(task.shader);
             unsafe {
                 device.vk().cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, new_pipeline);
             }
@@ -1299,43 +2659,43 @@ impl DebugPipelinePass {
 
         if !self.shader_uniforms.contains_key(&task.shader) {
             log::warn!("Called draw without any shader uniforms. Using default values!");
-            let uniforms = self.parent.pipelines.lock().unwrap().get(&task.shader).unwrap().used_uniforms;
-            self.shader_uniforms.insert(task.shader, UniformStateTracker::new(uniforms, self.placeholder_texture, self.placeholder_sampler));
+            let uniforms = self.parent.shared.pipelines.lock().unwrap().get(&task.shader).unwrap().used_uniforms;
+            self.shader_uniforms.insert(task.shader, UniformStateTracker::new(uniforms, self.parent.shared.depth_mode, self.placeholder_texture, self.placeholder_sampler, &self.view_projections));
         }
         if let Some(tracker) = self.shader_uniforms.get_mut(&task.shader) {
             if let Some(push_constants) = tracker.validate_push_constants() {
                 unsafe {
                     device.vk().cmd_push_constants(
                         self.command_buffer.unwrap(),
-                        self.parent.draw_pipeline.pipeline_layout,
+                        self.parent.shared.draw_pipeline.pipeline_layout,
                         vk::ShaderStageFlags::ALL_GRAPHICS,
                         0,
                         bytes_of(push_constants)
                     );
                 }
+                self.current_object_id = Some(push_constants.object_id);
             }
 
             if let Some(static_uniforms) = tracker.validate_static_uniforms() {
-                let (buffer, offset) = obj.allocate_uniform(bytes_of(static_uniforms));
-                let buffer_info = vk::DescriptorBufferInfo {
-                    buffer,
-                    offset,
-                    range: std::mem::size_of::<StaticUniforms>() as vk::DeviceSize
-                };
-                let write = vk::WriteDescriptorSet::builder()
-                    .dst_binding(0)
-                    .dst_array_element(0)
-                    .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-                    .buffer_info(std::slice::from_ref(&buffer_info));
+                let mut uniform_ring = self.parent.pass_objects[self.index].uniform_ring.lock().unwrap();
+                let binding = uniform_ring.write(device, bytes_of(static_uniforms));
+                tracker.static_uniform_binding = Some(binding);
+            }
 
-                unsafe {
-                    device.push_descriptor_khr().cmd_push_descriptor_set(
-                        self.command_buffer.unwrap(),
-                        vk::PipelineBindPoint::GRAPHICS,
-                        self.parent.draw_pipeline.pipeline_layout,
-                        0,
-                        std::slice::from_ref(&write)
-                    );
+            // Different shaders may use different slots so we need to check even if nothing changed
+            if let Some((descriptor_set, offset)) = tracker.static_uniform_binding {
+                if self.current_static_uniforms != Some((descriptor_set, offset)) {
+                    unsafe {
+                        device.vk().cmd_bind_descriptor_sets(
+                            cmd,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            self.parent.shared.draw_pipeline.pipeline_layout,
+                            1,
+                            std::slice::from_ref(&descriptor_set),
+                            std::slice::from_ref(&offset)
+                        );
+                    }
+                    self.current_static_uniforms = Some((descriptor_set, offset));
                 }
             }
 
@@ -1355,6 +2715,13 @@ impl DebugPipelinePass {
                     image_view: textures[2].0,
                     image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
                 };
+                let tile_buffer = self.parent.pass_objects[self.index].light_tile_buffer;
+                let light_info = get_light_buffer_info(&self.lights, tile_buffer);
+                let tile_info = vk::DescriptorBufferInfo {
+                    buffer: tile_buffer,
+                    offset: 0,
+                    range: vk::WHOLE_SIZE,
+                };
                 let writes = [
                     vk::WriteDescriptorSet::builder()
                         .dst_binding(1)
@@ -1374,13 +2741,23 @@ impl DebugPipelinePass {
                         .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
                         .image_info(std::slice::from_ref(&image_info2))
                         .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_binding(2)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .buffer_info(std::slice::from_ref(&light_info))
+                        .build(),
+                    vk::WriteDescriptorSet::builder()
+                        .dst_binding(3)
+                        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                        .buffer_info(std::slice::from_ref(&tile_info))
+                        .build(),
                 ];
 
                 unsafe {
                     device.push_descriptor_khr().cmd_push_descriptor_set(
                         self.command_buffer.unwrap(),
                         vk::PipelineBindPoint::GRAPHICS,
-                        self.parent.draw_pipeline.pipeline_layout,
+                        self.parent.shared.draw_pipeline.pipeline_layout,
                         0,
                         &writes
                     );
@@ -1388,7 +2765,86 @@ impl DebugPipelinePass {
             }
         }
 
-        if self.current_vertex_buffer != Some(task.vertex_buffer) {
+        let region = task.viewport.unwrap_or_else(|| make_full_rect(self.framebuffer_size));
+        if region.extent.width == 0 || region.extent.height == 0 {
+            // Vulkan does not allow empty viewports
+            return false;
+        }
+        if self.current_viewport != Some(region) {
+            let viewport = vk::Viewport {
+                x: region.offset.x as f32,
+                y: region.offset.y as f32,
+                width: region.extent.width as f32,
+                height: region.extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0
+            };
+            unsafe {
+                device.vk().cmd_set_viewport(cmd, 0, std::slice::from_ref(&viewport));
+            }
+            self.current_viewport = Some(region);
+        }
+
+        let scissor = match (task.scissor, task.viewport) {
+            (Some(scissor), Some(viewport)) => Some(intersect_rect(scissor, viewport)),
+            (scissor, viewport) => scissor.or(viewport),
+        };
+        let scissor = clamp_scissor(scissor, self.framebuffer_size);
+        if self.current_scissor != Some(scissor) {
+            unsafe {
+                device.vk().cmd_set_scissor(cmd, 0, std::slice::from_ref(&scissor));
+            }
+            self.current_scissor = Some(scissor);
+        }
+
+        if let Some(depth_bias) = task.depth_bias {
+            if self.current_depth_bias != Some(depth_bias) {
+                unsafe {
+                    let factor = self.parent.shared.depth_mode.get_depth_bias_factor();
+                    device.vk().cmd_set_depth_bias(cmd, depth_bias.constant_factor * factor, 0f32, depth_bias.slope_factor * factor);
+                }
+                self.current_depth_bias = Some(depth_bias);
+            }
+        }
+
+        if self.parent.shared.object_ids && self.current_object_id != Some(task.object_id) {
+            unsafe {
+                device.vk().cmd_push_constants(
+                    cmd,
+                    self.parent.shared.draw_pipeline.pipeline_layout,
+                    vk::ShaderStageFlags::ALL_GRAPHICS,
+                    PushConstants::OBJECT_ID_OFFSET,
+                    bytes_of(&task.object_id)
+                );
+            }
+            self.current_object_id = Some(task.object_id);
+        }
+
+        if let Some(layout) = self.current_vertex_pull_layout {
+            let vertex_address = task.vertex_address.unwrap_or_else(|| {
+                log::error!("Draw task without vertex address used with vertex pulling");
+                panic!()
+            });
+            let constants = VertexPullConstants {
+                vertex_address,
+                vertex_stride: layout.vertex_stride,
+                position_offset: layout.position_offset,
+                attribute_offset: layout.attribute_offset,
+                attribute_format: layout.attribute_format,
+            };
+            if self.current_vertex_pull_constants != Some(constants) {
+                unsafe {
+                    device.vk().cmd_push_constants(
+                        cmd,
+                        self.parent.shared.draw_pipeline.pipeline_layout,
+                        vk::ShaderStageFlags::ALL_GRAPHICS,
+                        std::mem::size_of::<PushConstants>() as u32,
+                        bytes_of(&constants)
+                    );
+                }
+                self.current_vertex_pull_constants = Some(constants);
+            }
+        } else if self.current_vertex_buffer != Some(task.vertex_buffer) {
             unsafe {
                 device.vk().cmd_bind_vertex_buffers(
                     cmd,
@@ -1400,6 +2856,19 @@ impl DebugPipelinePass {
             self.current_vertex_buffer = Some(task.vertex_buffer);
         }
 
+        let instance_buffer = task.instance_buffer.unwrap_or((self.parent.shared.instance_placeholder.0, 0));
+        if self.current_instance_buffer != Some(instance_buffer) {
+            unsafe {
+                device.vk().cmd_bind_vertex_buffers(
+                    cmd,
+                    INSTANCE_BINDING,
+                    std::slice::from_ref(&instance_buffer.0),
+                    std::slice::from_ref(&instance_buffer.1)
+                );
+            }
+            self.current_instance_buffer = Some(instance_buffer);
+        }
+
         if self.current_index_buffer != Some(task.index_buffer) {
             unsafe {
                 device.vk().cmd_bind_index_buffer(cmd, task.index_buffer, 0, task.index_type);
@@ -1407,9 +2876,7 @@ impl DebugPipelinePass {
             self.current_index_buffer = Some(task.index_buffer);
         }
 
-        unsafe {
-            device.vk().cmd_draw_indexed(cmd, task.index_count, 1, task.first_index, task.vertex_offset, 0);
-        }
+        true
     }
 }
 
@@ -1421,35 +2888,13 @@ impl EmulatorPipelinePass for DebugPipelinePass {
         let cmd = obj.get_begin_command_buffer().unwrap();
         self.command_buffer = Some(cmd);
 
-        let device = self.parent.emulator.get_device();
+        self.parent.pass_objects[self.index].uniform_ring.lock().unwrap().reset();
 
-        let clear_values = [
-            vk::ClearValue {
-                depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
-                    stencil: 0
-                }
-            },
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0f32, 0f32, 0f32, 0f32],
-                }
-            },
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0f32, 0f32, 0f32, 0f32],
-                }
-            }
-        ];
-        let info = vk::RenderPassBeginInfo::builder()
-            .render_pass(self.parent.render_pass)
-            .framebuffer(self.parent.pass_objects[self.index].framebuffer)
-            .render_area(make_full_rect(self.parent.framebuffer_size))
-            .clear_values(&clear_values);
+        let device = self.parent.emulator.get_device();
 
-        unsafe {
-            device.vk().cmd_begin_render_pass(cmd, &info, vk::SubpassContents::INLINE);
-        }
+        let (render_graph, render_pass, outputs) = self.build_render_graph();
+        render_graph.record_barriers(device, cmd, render_pass);
+        self.render_graph = Some((render_graph, outputs));
     }
 
     fn process_task(&mut self, task: &PipelineTask, obj: &mut PooledObjectProvider) {
@@ -1460,82 +2905,96 @@ impl EmulatorPipelinePass for DebugPipelinePass {
             PipelineTask::UpdateTexture(shader, index, view, sampler) => {
                 self.update_texture(*shader, *index, *view, *sampler);
             }
+            PipelineTask::UpdateBoneMatrices(_, _, _, _) => {
+                // The debug shaders only visualize the unskinned vertex attributes
+            }
             PipelineTask::Draw(task) => {
-                self.draw(task, obj);
+                self.ensure_render_pass();
+                self.draw(task);
+            }
+            PipelineTask::DrawCulled(task) => {
+                self.ensure_render_pass();
+                self.draw_culled(task, obj);
+            }
+            PipelineTask::SetScreenEffects(effects) => {
+                self.screen_effects = *effects;
+            }
+            PipelineTask::ClearDepth(region) => {
+                self.ensure_render_pass();
+                self.clear_depth(*region);
+            }
+            PipelineTask::SetViewProjections(matrices) => {
+                if self.parent.shared.view_count > 1 {
+                    self.set_view_projections(matrices);
+                }
+            }
+            PipelineTask::SetClearConfig(config) => {
+                if self.render_pass_begun {
+                    log::warn!("Clear config set after the first draw of a pass. Ignoring!");
+                } else {
+                    self.clear_config = *config;
+                }
+            }
+            PipelineTask::SetLights(lights) => {
+                if self.render_pass_begun {
+                    log::warn!("Lights set after the first draw of a pass. Ignoring!");
+                } else {
+                    self.lights = *lights;
+                }
             }
         }
     }
 
-    fn record<'a>(&mut self, _: &mut PooledObjectProvider, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+    fn record_segment<'a>(&mut self, obj: &mut PooledObjectProvider, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) -> bool {
+        self.ensure_render_pass();
         let device = self.parent.emulator.get_device();
         let cmd = self.command_buffer.take().unwrap();
 
-        let bg_descriptor_sets = [self.parent.pass_objects[self.index].bg_descriptor_set];
-
+        // The output subpass is only rendered by the last segment
         unsafe {
             device.vk().cmd_next_subpass(cmd, vk::SubpassContents::INLINE);
-            device.vk().cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.parent.background_pipeline.pipeline);
-            device.vk().cmd_bind_descriptor_sets(cmd, vk::PipelineBindPoint::GRAPHICS, self.parent.background_pipeline.pipeline_layout, 0, &bg_descriptor_sets, &[]);
-            device.vk().cmd_draw(cmd, 4, 1, 0, 0);
+            device.vk().cmd_end_render_pass(cmd);
+            device.vk().end_command_buffer(cmd).unwrap();
         }
 
-        let image_barrier = [
-            vk::ImageMemoryBarrier2::builder()
-                .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
-                .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
-                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-                .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
-                .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                .src_queue_family_index(0)
-                .dst_queue_family_index(0)
-                .image(self.parent.pass_objects[self.index].depth_image)
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::DEPTH,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1
-                })
-                .build(),
-            vk::ImageMemoryBarrier2::builder()
-                .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
-                .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
-                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
-                .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
-                .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-                .src_queue_family_index(0)
-                .dst_queue_family_index(0)
-                .image(self.parent.pass_objects[self.index].output_image)
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1
-                })
-                .build(),
-        ];
+        self.push_submit(cmd, submits, alloc);
+
+        let cmd = obj.get_begin_command_buffer().unwrap();
+        self.command_buffer = Some(cmd);
+        self.begin_render_pass(cmd, self.parent.shared.load_render_pass);
+        self.reset_bound_state();
+
+        true
+    }
+
+    fn record<'a>(&mut self, _: &mut PooledObjectProvider, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+        self.ensure_render_pass();
+        let device = self.parent.emulator.get_device();
+        let cmd = self.command_buffer.take().unwrap();
 
-        let info = vk::DependencyInfo::builder()
-            .image_memory_barriers(&image_barrier);
+        let bg_descriptor_sets = [self.parent.pass_objects[self.index].bg_descriptor_set];
+        let viewport = make_full_viewport(self.framebuffer_size);
+        let scissor = make_full_rect(self.framebuffer_size);
 
         unsafe {
+            device.vk().cmd_next_subpass(cmd, vk::SubpassContents::INLINE);
+            device.vk().cmd_set_viewport(cmd, 0, std::slice::from_ref(&viewport));
+            device.vk().cmd_set_scissor(cmd, 0, std::slice::from_ref(&scissor));
+            device.vk().cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, self.parent.shared.background_pipeline.pipeline);
+            device.vk().cmd_bind_descriptor_sets(cmd, vk::PipelineBindPoint::GRAPHICS, self.parent.shared.background_pipeline.pipeline_layout, 0, &bg_descriptor_sets, &[]);
+            device.vk().cmd_push_constants(cmd, self.parent.shared.background_pipeline.pipeline_layout, vk::ShaderStageFlags::FRAGMENT, 0, bytes_of(&self.screen_effects));
+            device.vk().cmd_draw(cmd, 4, 1, 0, 0);
             device.vk().cmd_end_render_pass(cmd);
+        }
 
-            device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &info);
+        let (render_graph, outputs) = self.render_graph.take().unwrap();
+        render_graph.record_barriers(device, cmd, outputs);
 
+        unsafe {
             device.vk().end_command_buffer(cmd).unwrap();
         }
 
-        let command_buffer_info = alloc.alloc(vk::CommandBufferSubmitInfo::builder()
-            .command_buffer(cmd)
-        );
-
-        submits.push(vk::SubmitInfo2::builder()
-            .command_buffer_infos(std::slice::from_ref(command_buffer_info))
-        );
+        self.push_submit(cmd, submits, alloc);
     }
 
     fn get_output_index(&self) -> usize {
@@ -1555,25 +3014,31 @@ impl Drop for DebugPipelinePass {
 
 struct UniformStateTracker {
     used_uniforms: McUniform,
+    depth_mode: DepthMode,
     push_constants_dirty: bool,
     static_uniforms_dirty: bool,
     textures_dirty: bool,
     push_constant_cache: PushConstants,
     static_uniform_cache: StaticUniforms,
     textures: [(vk::ImageView, vk::Sampler); 3],
+
+    /// The ring slot containing the current static uniforms.
+    static_uniform_binding: Option<(vk::DescriptorSet, u32)>,
 }
 
 impl UniformStateTracker {
-    fn new(used_uniforms: McUniform, initial_texture: vk::ImageView, initial_sampler: vk::Sampler) -> Self {
+    fn new(used_uniforms: McUniform, depth_mode: DepthMode, initial_texture: vk::ImageView, initial_sampler: vk::Sampler, view_projections: &[Mat4f32; 2]) -> Self {
         Self {
             used_uniforms,
+            depth_mode,
             push_constants_dirty: true,
             static_uniforms_dirty: true,
             textures_dirty: true,
+            static_uniform_binding: None,
             push_constant_cache: PushConstants {
                 model_view_matrix: Mat4f32::identity(),
                 chunk_offset: Vec3f32::zeros(),
-                _padding0: Default::default(),
+                object_id: 0,
             },
             static_uniform_cache: StaticUniforms {
                 projection_matrix: Mat4f32::identity(),
@@ -1584,6 +3049,7 @@ impl UniformStateTracker {
                 _padding1: Default::default(),
                 fog_shape: 0,
                 _padding2: Default::default(),
+                view_projection_matrices: *view_projections,
             },
             textures: [(initial_texture, initial_sampler); 3],
         }
@@ -1599,7 +3065,10 @@ impl UniformStateTracker {
             }
             McUniformData::ProjectionMatrix(mat) => {
                 if self.used_uniforms.contains(&McUniform::PROJECTION_MATRIX) {
-                    self.static_uniform_cache.projection_matrix = *mat;
+                    self.static_uniform_cache.projection_matrix = match self.depth_mode {
+                        DepthMode::Standard => *mat,
+                        DepthMode::Reversed => to_reverse_z(mat),
+                    };
                     self.static_uniforms_dirty = true;
                 }
             }
@@ -1654,6 +3123,11 @@ impl UniformStateTracker {
         }
     }
 
+    fn set_view_projections(&mut self, matrices: &[Mat4f32; 2]) {
+        self.static_uniform_cache.view_projection_matrices = *matrices;
+        self.static_uniforms_dirty = true;
+    }
+
     fn update_texture(&mut self, index: u32, view: vk::ImageView, sampler: vk::Sampler) {
         match index {
             0 => {
@@ -1672,6 +3146,13 @@ impl UniformStateTracker {
         }
     }
 
+    /// Marks all state which is pushed into the command buffer as dirty so that it is pushed again
+    /// into a new command buffer.
+    fn invalidate_bindings(&mut self) {
+        self.push_constants_dirty = true;
+        self.textures_dirty = true;
+    }
+
     fn validate_push_constants(&mut self) -> Option<&PushConstants> {
         if self.push_constants_dirty {
             self.push_constants_dirty = false;
@@ -1709,7 +3190,8 @@ struct PushConstants {
     #[allow(unused)]
     chunk_offset: Vec3f32,
 
-    _padding0: [u8; 4],
+    /// Always 0 in the cached push constants. The id of the current draw is pushed separately.
+    object_id: u32,
 }
 const_assert_eq!(std::mem::size_of::<PushConstants>(), 80);
 const_assert_eq!(std::mem::size_of::<PushConstants>() % 16, 0);
@@ -1717,6 +3199,50 @@ const_assert_eq!(std::mem::size_of::<PushConstants>() % 16, 0);
 unsafe impl Zeroable for PushConstants {}
 unsafe impl Pod for PushConstants {}
 
+impl PushConstants {
+    const OBJECT_ID_OFFSET: u32 = (std::mem::size_of::<Mat4f32>() + std::mem::size_of::<Vec3f32>()) as u32;
+}
+
+/// The push constants following [`PushConstants`] used by pulled.vert to fetch the vertex data.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Default)]
+struct VertexPullConstants {
+    /// Read as a uvec2 in the shader.
+    vertex_address: vk::DeviceAddress,
+    vertex_stride: u32,
+    position_offset: u32,
+    attribute_offset: u32,
+    attribute_format: u32,
+}
+const_assert_eq!(std::mem::size_of::<VertexPullConstants>(), 24);
+
+unsafe impl Zeroable for VertexPullConstants {}
+unsafe impl Pod for VertexPullConstants {}
+
+/// Describes how pulled.vert fetches the vertex data of a vertex format.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct VertexPullLayout {
+    vertex_stride: u32,
+    position_offset: u32,
+    attribute_offset: u32,
+    attribute_format: u32,
+
+    /// The value of the output mode specialization constant.
+    output_mode: u32,
+}
+
+impl VertexPullLayout {
+    const OUTPUT_POSITION: u32 = 0;
+    const OUTPUT_COLOR: u32 = 1;
+    const OUTPUT_UV: u32 = 2;
+    const OUTPUT_NULL: u32 = 3;
+
+    const FORMAT_NONE: u32 = 0;
+    const FORMAT_R32G32_SFLOAT: u32 = 1;
+    const FORMAT_R8G8B8A8_UNORM: u32 = 2;
+    const FORMAT_R16G16_SINT: u32 = 3;
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Default)]
 struct StaticUniforms {
@@ -1740,13 +3266,48 @@ struct StaticUniforms {
     fog_shape: u32,
 
     _padding2: [u8; 12],
+
+    /// Only used by stereo passes.
+    #[allow(unused)]
+    view_projection_matrices: [Mat4f32; 2],
 }
-const_assert_eq!(std::mem::size_of::<StaticUniforms>(), 128);
+const_assert_eq!(std::mem::size_of::<StaticUniforms>(), 256);
 const_assert_eq!(std::mem::size_of::<StaticUniforms>() % 16, 0);
 
 unsafe impl Zeroable for StaticUniforms {}
 unsafe impl Pod for StaticUniforms {}
 
+/// Returns the intersection of two rectangles. The result has a size of 0 if they do not overlap.
+pub(super) fn intersect_rect(a: vk::Rect2D, b: vk::Rect2D) -> vk::Rect2D {
+    let min_x = (a.offset.x as i64).max(b.offset.x as i64);
+    let min_y = (a.offset.y as i64).max(b.offset.y as i64);
+    let max_x = (a.offset.x as i64 + a.extent.width as i64).min(b.offset.x as i64 + b.extent.width as i64).max(min_x);
+    let max_y = (a.offset.y as i64 + a.extent.height as i64).min(b.offset.y as i64 + b.extent.height as i64).max(min_y);
+
+    vk::Rect2D {
+        offset: vk::Offset2D { x: min_x as i32, y: min_y as i32 },
+        extent: vk::Extent2D { width: (max_x - min_x) as u32, height: (max_y - min_y) as u32 },
+    }
+}
+
+/// Clamps the scissor of a draw to the framebuffer since vulkan does not allow negative offsets.
+pub(super) fn clamp_scissor(scissor: Option<vk::Rect2D>, framebuffer_size: Vec2u32) -> vk::Rect2D {
+    let scissor = match scissor {
+        Some(scissor) => scissor,
+        None => return make_full_rect(framebuffer_size),
+    };
+
+    let min_x = (scissor.offset.x as i64).clamp(0, framebuffer_size[0] as i64);
+    let min_y = (scissor.offset.y as i64).clamp(0, framebuffer_size[1] as i64);
+    let max_x = (scissor.offset.x as i64 + scissor.extent.width as i64).clamp(min_x, framebuffer_size[0] as i64);
+    let max_y = (scissor.offset.y as i64 + scissor.extent.height as i64).clamp(min_y, framebuffer_size[1] as i64);
+
+    vk::Rect2D {
+        offset: vk::Offset2D { x: min_x as i32, y: min_y as i32 },
+        extent: vk::Extent2D { width: (max_x - min_x) as u32, height: (max_y - min_y) as u32 },
+    }
+}
+
 fn try_create_shader_module(device: &DeviceContext, data: &[u8], name: &str) -> Result<vk::ShaderModule, vk::Result> {
     unsafe {
         create_shader_from_bytes(device.get_functions(), data)
@@ -1756,13 +3317,31 @@ fn try_create_shader_module(device: &DeviceContext, data: &[u8], name: &str) ->
     })
 }
 
+/// The vertex input binding of the per instance data of a draw. See [`DrawTask::instance_buffer`].
+const INSTANCE_BINDING: u32 = 1;
+
+/// The size of the zeroed buffer bound as instance data of draws without instance buffer. Covers
+/// the offset attribute of the first instance of any valid [`InstanceFormat`].
+const INSTANCE_PLACEHOLDER_SIZE: u32 = InstanceFormat::MAX_STRIDE + 32;
+
+/// The location of the `in_instance_offset` input of the debug vertex shaders.
+const INSTANCE_OFFSET_LOCATION: u32 = 4;
+
+/// The specialization constant id of `_mc_multiview` in mc_uniforms.glsl.
+const MC_MULTIVIEW_CONSTANT_ID: u32 = 100;
+
+/// The specialization constant id of `_mc_reverse_z` in mc_uniforms.glsl.
+const MC_REVERSE_Z_CONSTANT_ID: u32 = 101;
+
 const SHADER_ENTRY: &'static CStr = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") }; // GOD I LOVE RUSTS FFI API IT IS SO NICE AND DEFINITELY NOT STUPID WITH WHICH FUNCTIONS ARE CONST AND WHICH AREN'T
 static DEBUG_POSITION_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/position_vert.spv"));
 static DEBUG_COLOR_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/color_vert.spv"));
 static DEBUG_UV_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/uv_vert.spv"));
 static DEBUG_NULL_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/null_vert.spv"));
+static DEBUG_PULLED_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/pulled_vert.spv"));
 static DEBUG_FRAGMENT_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/debug_frag.spv"));
 static TEXTURED_FRAGMENT_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/textured_frag.spv"));
 
 static BACKGROUND_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/background_vert.spv"));
-static BACKGROUND_FRAGMENT_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/background_frag.spv"));
\ No newline at end of file
+static BACKGROUND_FRAGMENT_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/background_frag.spv"));
+static BACKGROUND_MS_FRAGMENT_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/debug/background_ms_frag.spv"));
\ No newline at end of file
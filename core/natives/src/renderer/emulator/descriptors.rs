@@ -20,8 +20,27 @@ impl DescriptorPool {
         }
     }
 
-    pub(super) fn allocate_uniform(&mut self, data: &[u8]) -> (vk::Buffer, vk::DeviceSize) {
-        self.uniform_buffer_pool.allocate_write(data)
+    /// Creates uniform blocks until the pool contains at least `count` blocks.
+    pub(super) fn preallocate_uniform_blocks(&mut self, count: u64) {
+        self.uniform_buffer_pool.preallocate(&self.device, count);
+    }
+
+    /// Returns a free uniform block. New blocks are only created if all existing blocks are in use.
+    pub(super) fn get_uniform_block(&mut self) -> UniformBlock {
+        self.uniform_buffer_pool.get_block(&self.device)
+    }
+
+    /// Returns the blocks of a [`UniformArena`] once all submissions using them have completed.
+    pub(super) fn return_uniform_blocks(&mut self, blocks: Vec<UniformBlock>, used_bytes: u64) {
+        self.uniform_buffer_pool.return_blocks(blocks, used_bytes);
+    }
+
+    pub(super) fn get_uniform_alignment(&self) -> vk::DeviceSize {
+        self.uniform_buffer_pool.alignment
+    }
+
+    pub(super) fn get_uniform_statistics(&self) -> UniformStatistics {
+        self.uniform_buffer_pool.statistics
     }
 }
 
@@ -31,68 +50,217 @@ impl Drop for DescriptorPool {
     }
 }
 
-struct UniformBufferPool {
-    buffer_allocation: Allocation,
+/// Statistics of the uniform buffer memory used by passes.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct UniformStatistics {
+    /// The total size of all uniform blocks.
+    pub allocated_bytes: u64,
+
+    /// The largest amount of uniform data written by a single frame.
+    pub peak_frame_bytes: u64,
+
+    /// The largest number of bytes of uniform blocks which have been in use at the same time.
+    pub peak_in_use_bytes: u64,
+}
+
+/// A host visible uniform buffer which is sub allocated by a single [`UniformArena`] at a time.
+pub(super) struct UniformBlock {
     buffer: vk::Buffer,
-    buffer_size: usize,
-    current_offset: usize,
+    allocation: Allocation,
     mapped_ptr: NonNull<u8>,
 }
 
+// The mapped memory is only accessed by the arena currently owning the block
+unsafe impl Send for UniformBlock {
+}
+
+struct UniformBufferPool {
+    alignment: vk::DeviceSize,
+    free_blocks: Vec<UniformBlock>,
+    block_count: u64,
+    in_use_count: u64,
+    statistics: UniformStatistics,
+}
+
 impl UniformBufferPool {
+    const BLOCK_SIZE: vk::DeviceSize = 2u64.pow(20); // 1MB
+
     fn new(device: &DeviceContext) -> Self {
-        let target_size = 2usize.pow(25); // ~32MB
+        let alignment = unsafe {
+            device.get_instance().vk().get_physical_device_properties(device.get_functions().physical_device)
+        }.limits.min_uniform_buffer_offset_alignment;
+
+        Self {
+            alignment,
+            free_blocks: Vec::new(),
+            block_count: 0,
+            in_use_count: 0,
+            statistics: UniformStatistics::default(),
+        }
+    }
+
+    fn get_block(&mut self, device: &DeviceContext) -> UniformBlock {
+        let block = self.free_blocks.pop().unwrap_or_else(|| {
+            self.block_count += 1;
+            self.statistics.allocated_bytes = self.block_count * Self::BLOCK_SIZE;
+            Self::create_block(device)
+        });
+
+        self.in_use_count += 1;
+        self.statistics.peak_in_use_bytes = self.statistics.peak_in_use_bytes.max(self.in_use_count * Self::BLOCK_SIZE);
+
+        block
+    }
+
+    fn preallocate(&mut self, device: &DeviceContext, count: u64) {
+        while self.block_count < count {
+            self.block_count += 1;
+            self.free_blocks.push(Self::create_block(device));
+        }
+        self.statistics.allocated_bytes = self.block_count * Self::BLOCK_SIZE;
+    }
+
+    fn return_blocks(&mut self, blocks: Vec<UniformBlock>, used_bytes: u64) {
+        self.in_use_count -= blocks.len() as u64;
+        self.statistics.peak_frame_bytes = self.statistics.peak_frame_bytes.max(used_bytes);
+        self.free_blocks.extend(blocks);
+    }
+
+    fn create_block(device: &DeviceContext) -> UniformBlock {
         let info = vk::BufferCreateInfo::builder()
-            .size(target_size as vk::DeviceSize)
+            .size(Self::BLOCK_SIZE)
             .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
-        let (buffer, buffer_allocation, ptr) = unsafe {
-            device.get_allocator().create_buffer(&info, HostAccess::Random, &format_args!("UniformBufferPool"))
-        }.unwrap();
+        let (buffer, allocation, ptr) = unsafe {
+            device.get_allocator().create_buffer(&info, HostAccess::Random, &format_args!("UniformBlock"))
+        }.unwrap_or_else(|| {
+            log::error!("Failed to allocate uniform block");
+            panic!()
+        });
 
-        Self {
-            buffer_allocation,
+        UniformBlock {
             buffer,
-            buffer_size: target_size,
-            current_offset: 0,
+            allocation,
             mapped_ptr: ptr.unwrap(),
         }
     }
 
-    fn allocate_write(&mut self, data: &[u8]) -> (vk::Buffer, vk::DeviceSize) {
-        // We just allocate a new slot hoping that it isn't in use anymore. This is not that dangerous right now since we have a 32MB buffer which equates to roughly 100k slots
-        // but it for sure can't become a permanent solution.
-        let src = data;
-        if src.len() > 1024 { // Just a sanity check all of our uniforms currently are < 256
-            panic!("Wtf are you doing???");
+    fn destroy(&mut self, device: &DeviceContext) {
+        if self.in_use_count != 0 {
+            log::warn!("Destroying uniform buffer pool while {} blocks are still in use", self.in_use_count);
+        }
+        for block in self.free_blocks.drain(..) {
+            unsafe {
+                device.get_allocator().destroy_buffer(block.buffer, block.allocation)
+            };
         }
+    }
+}
+
+/// Allocates uniform data for a single frame.
+///
+/// Space is sub allocated linearly from blocks of the [`DescriptorPool`]. The blocks are only
+/// returned to the pool once the frame has completed so the data is never overwritten while it
+/// may still be read by the gpu.
+pub(super) struct UniformArena {
+    blocks: Vec<UniformBlock>,
+    cursor: ArenaCursor,
+    used_bytes: u64,
+}
 
-        // We align to 256bytes because that was the highest in the gpuinfo database (Yes this is entire module is very much a TODO)
-        let add = 256 - (self.current_offset % 256);
-        let mut base_offset = self.current_offset + add;
+impl UniformArena {
+    pub(super) fn new() -> Self {
+        Self {
+            blocks: Vec::new(),
+            cursor: ArenaCursor::new(UniformBufferPool::BLOCK_SIZE),
+            used_bytes: 0,
+        }
+    }
 
-        if base_offset + src.len() > self.buffer_size {
-            base_offset = 0;
+    /// Writes the data into the arena and returns the buffer and offset it has been written to.
+    /// `get_block` is called if the current block is full.
+    pub(super) fn allocate_write<F: FnOnce() -> UniformBlock>(&mut self, data: &[u8], alignment: vk::DeviceSize, get_block: F) -> (vk::Buffer, vk::DeviceSize) {
+        let size = data.len() as vk::DeviceSize;
+        if size > UniformBufferPool::BLOCK_SIZE {
+            log::error!("Uniform data of size {} is larger than a uniform block", size);
+            panic!()
         }
-        let base_offset = base_offset;
 
-        self.current_offset = base_offset + src.len();
+        let offset = match self.cursor.allocate(size, alignment) {
+            Some(offset) if !self.blocks.is_empty() => offset,
+            _ => {
+                self.blocks.push(get_block());
+                self.cursor.reset();
+                self.cursor.allocate(size, alignment).unwrap()
+            }
+        };
+        self.used_bytes += size;
 
+        let block = self.blocks.last().unwrap();
         let dst = unsafe {
-            std::slice::from_raw_parts_mut(self.mapped_ptr.as_ptr().offset(base_offset as isize), src.len())
+            std::slice::from_raw_parts_mut(block.mapped_ptr.as_ptr().offset(offset as isize), data.len())
         };
-        dst.copy_from_slice(src);
+        dst.copy_from_slice(data);
 
-        (self.buffer, base_offset as vk::DeviceSize)
+        (block.buffer, offset)
     }
 
-    fn destroy(&mut self, device: &DeviceContext) {
-        unsafe {
-            device.get_allocator().destroy_buffer(self.buffer, self.buffer_allocation)
-        };
+    /// Returns all blocks and the number of bytes written into them. Must only be called once all
+    /// submissions using the data have completed.
+    pub(super) fn take_blocks(&mut self) -> (Vec<UniformBlock>, u64) {
+        self.cursor.reset();
+        (std::mem::take(&mut self.blocks), std::mem::replace(&mut self.used_bytes, 0))
+    }
+}
+
+/// Tracks the next free offset inside a block.
+struct ArenaCursor {
+    block_size: vk::DeviceSize,
+    offset: vk::DeviceSize,
+}
+
+impl ArenaCursor {
+    fn new(block_size: vk::DeviceSize) -> Self {
+        Self {
+            block_size,
+            offset: 0,
+        }
+    }
+
+    /// Returns the aligned offset of the allocation or [`None`] if the remaining space of the block
+    /// is too small.
+    fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        let offset = ((self.offset + alignment - 1) / alignment) * alignment;
+        if offset + size > self.block_size {
+            return None;
+        }
+
+        self.offset = offset + size;
+        Some(offset)
+    }
+
+    fn reset(&mut self) {
+        self.offset = 0;
     }
 }
 
-unsafe impl Send for UniformBufferPool {
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arena_cursor() {
+        let mut cursor = ArenaCursor::new(1024);
+
+        assert_eq!(cursor.allocate(100, 256), Some(0));
+        assert_eq!(cursor.allocate(100, 256), Some(256));
+        assert_eq!(cursor.allocate(256, 256), Some(512));
+        assert_eq!(cursor.allocate(256, 256), Some(768));
+        assert_eq!(cursor.allocate(1, 256), None);
+
+        cursor.reset();
+        assert_eq!(cursor.allocate(1024, 256), Some(0));
+        assert_eq!(cursor.allocate(0, 1), Some(1024));
+    }
+}
@@ -0,0 +1,239 @@
+//! Small textures which are fully rewritten very frequently, for example the 16x16 lightmap which
+//! is updated every tick.
+//!
+//! Updates through [`GlobalImage::update_regions`] allocate from the shared staging pool and lock
+//! it twice per update. A [`DynamicTexture`] instead owns a small persistently mapped ring of
+//! staging slots sized for the full texture, so an update is a single memcpy followed by a copy
+//! recorded by the worker.
+//!
+//! `VK_EXT_host_image_copy` would allow skipping the staging copy entirely, however the ash version
+//! used does not provide bindings for it so the staging ring is always used.
+
+use std::ptr::NonNull;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use ash::vk;
+
+use crate::allocator::{Allocation, HostAccess};
+use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, ImageData};
+use crate::renderer::emulator::readback::get_texel_size;
+use crate::renderer::emulator::worker::ImageWriteStaging;
+use crate::util::format::Format;
+
+use crate::prelude::*;
+
+/// A [`GlobalImage`] with a dedicated staging ring for cheap full updates.
+///
+/// The image returned by [`DynamicTexture::get_image`] can be used like any other global image.
+pub struct DynamicTexture {
+    image: Arc<GlobalImage>,
+    ring: Arc<StagingRing>,
+}
+
+impl DynamicTexture {
+    /// The number of updates which can be in flight at the same time before falling back to the
+    /// shared staging pool.
+    const SLOT_COUNT: usize = 4;
+
+    /// Slot offsets are aligned to this value which is a multiple of every supported texel size.
+    const SLOT_ALIGNMENT: usize = 256;
+
+    pub fn new(emulator: &EmulatorRenderer, size: Vec2u32, format: &'static Format) -> Self {
+        let texel_size = get_texel_size(format.get_format(), vk::ImageAspectFlags::COLOR).unwrap_or_else(|| {
+            log::error!("Format {:?} is not supported for dynamic textures", format);
+            panic!()
+        });
+
+        let image = emulator.create_global_image(size, format);
+        let data_size = (size[0] as usize) * (size[1] as usize) * (texel_size as usize);
+        let ring = Arc::new(StagingRing::new(emulator.get_device().clone(), data_size, Self::SLOT_COUNT));
+
+        Self {
+            image,
+            ring,
+        }
+    }
+
+    pub fn get_image(&self) -> &Arc<GlobalImage> {
+        &self.image
+    }
+
+    pub fn get_size(&self) -> Vec2u32 {
+        self.image.get_size()
+    }
+
+    /// Replaces the full contents of the texture. The data must be tightly packed.
+    ///
+    /// If all staging slots are still in use by previous updates the update is performed using the
+    /// shared staging pool instead.
+    pub fn update(&self, data: &[u8]) {
+        if data.len() != self.ring.data_size {
+            log::error!("Dynamic texture update has size {} but expected {}", data.len(), self.ring.data_size);
+            panic!()
+        }
+
+        let size = self.image.get_size();
+        let index = match self.ring.slots.try_acquire() {
+            Some(index) => index,
+            None => {
+                self.image.update_regions(std::slice::from_ref(&ImageData::new_full(data, size)));
+                return;
+            }
+        };
+
+        let offset = self.ring.get_slot_offset(index);
+        unsafe {
+            let mapped = std::slice::from_raw_parts_mut(self.ring.mapped_ptr.as_ptr().add(offset), data.len());
+            mapped.copy_from_slice(data);
+            self.ring.device.get_allocator().flush_allocation(self.ring.allocation, offset as vk::DeviceSize, data.len() as vk::DeviceSize)
+        }.unwrap_or_else(|err| {
+            log::error!("Failed to flush dynamic texture staging memory {:?}", err);
+            panic!()
+        });
+
+        let copy = vk::BufferImageCopy {
+            buffer_offset: offset as vk::DeviceSize,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D {
+                width: size[0],
+                height: size[1],
+                depth: 1
+            }
+        };
+
+        let slot = DynamicTextureSlot {
+            ring: self.ring.clone(),
+            index,
+        };
+        self.image.push_write(ImageWriteStaging::DynamicTexture(slot), self.ring.buffer, (offset as vk::DeviceSize, data.len() as vk::DeviceSize), Box::new([copy]));
+    }
+}
+
+/// A acquired slot of a [`DynamicTexture`] staging ring. The slot is released when dropped which
+/// must only happen after the copy reading from it has finished execution.
+pub(super) struct DynamicTextureSlot {
+    ring: Arc<StagingRing>,
+    index: usize,
+}
+
+impl Drop for DynamicTextureSlot {
+    fn drop(&mut self) {
+        self.ring.slots.release(self.index);
+    }
+}
+
+struct StagingRing {
+    device: Arc<DeviceContext>,
+    buffer: vk::Buffer,
+    allocation: Allocation,
+    mapped_ptr: NonNull<u8>,
+    data_size: usize,
+    slot_stride: usize,
+    slots: SlotTracker,
+}
+
+impl StagingRing {
+    fn new(device: Arc<DeviceContext>, data_size: usize, slot_count: usize) -> Self {
+        let slot_stride = (data_size + DynamicTexture::SLOT_ALIGNMENT - 1) / DynamicTexture::SLOT_ALIGNMENT * DynamicTexture::SLOT_ALIGNMENT;
+
+        let info = vk::BufferCreateInfo::builder()
+            .size((slot_stride * slot_count) as vk::DeviceSize)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let (buffer, allocation, mapped_ptr) = unsafe {
+            device.get_allocator().create_buffer(&info, HostAccess::SequentialWrite, &format_args!("DynamicTextureStaging"))
+        }.unwrap();
+
+        Self {
+            device,
+            buffer,
+            allocation,
+            mapped_ptr: mapped_ptr.unwrap(),
+            data_size,
+            slot_stride,
+            slots: SlotTracker::new(slot_count),
+        }
+    }
+
+    fn get_slot_offset(&self, index: usize) -> usize {
+        index * self.slot_stride
+    }
+}
+
+impl Drop for StagingRing {
+    fn drop(&mut self) {
+        // All slots hold a reference to the ring so no copy can be pending anymore
+        unsafe {
+            self.device.get_allocator().destroy_buffer(self.buffer, self.allocation);
+        }
+    }
+}
+
+// The mapped memory of a slot is only accessed by the thread which acquired it
+unsafe impl Send for StagingRing {
+}
+unsafe impl Sync for StagingRing {
+}
+
+/// Tracks which slots of a ring are currently in use. Slots are handed out in round robin order
+/// so that the most recently released slot is reused last.
+struct SlotTracker {
+    used: Box<[AtomicBool]>,
+    next: AtomicUsize,
+}
+
+impl SlotTracker {
+    fn new(slot_count: usize) -> Self {
+        Self {
+            used: (0..slot_count).map(|_| AtomicBool::new(false)).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Acquires a free slot. Returns [`None`] if all slots are in use.
+    fn try_acquire(&self) -> Option<usize> {
+        let start = self.next.load(Ordering::Relaxed);
+        for i in 0..self.used.len() {
+            let index = (start + i) % self.used.len();
+            if self.used[index].compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                self.next.store((index + 1) % self.used.len(), Ordering::Relaxed);
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    fn release(&self, index: usize) {
+        self.used[index].store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slot_rotation() {
+        let tracker = SlotTracker::new(3);
+        assert_eq!(tracker.try_acquire(), Some(0));
+        assert_eq!(tracker.try_acquire(), Some(1));
+
+        tracker.release(0);
+        assert_eq!(tracker.try_acquire(), Some(2));
+        assert_eq!(tracker.try_acquire(), Some(0));
+        assert_eq!(tracker.try_acquire(), None);
+
+        tracker.release(1);
+        assert_eq!(tracker.try_acquire(), Some(1));
+    }
+}
@@ -0,0 +1,967 @@
+//! Provides a minimal [`EmulatorPipeline`] used if the device does not support the
+//! [`DebugPipeline`](super::debug_pipeline::DebugPipeline).
+//!
+//! The fallback pipeline only records vulkan 1.0 commands. It does not use any descriptors, push
+//! descriptors or synchronization2 barriers, all synchronization is done by the subpass
+//! dependencies of its render pass. Only the formats every vulkan implementation must support are
+//! used for its attachments.
+//!
+//! Draws use one of two fixed vertex layouts, either only the position or the position and color
+//! channel of the vertex format of the shader. The model view and projection matrices and the
+//! chunk offset are the only uniforms used, textures, blending, lights and screen effects are
+//! ignored. Instanced draws only draw their first instance and culled draws draw all sections of
+//! the mesh. The output is plain but shows the scene on devices which could not render at all
+//! otherwise.
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use ash::vk;
+use bumpalo::Bump;
+use bytemuck::{bytes_of, Pod, Zeroable};
+use include_bytes_aligned::include_bytes_aligned;
+
+use crate::allocator::Allocation;
+use crate::device::device::Queue;
+use crate::device::device_utils::create_shader_from_bytes;
+use crate::renderer::emulator::EmulatorRenderer;
+use crate::renderer::emulator::debug_pipeline::{clamp_scissor, intersect_rect, ObjectCreateError};
+use crate::renderer::emulator::mc_shaders::{McUniformData, ShaderId, VertexFormat};
+use crate::renderer::emulator::pipeline::{AttachmentInfo, ClearConfig, CullState, DepthTest, DrawTask, EmulatorPipeline, EmulatorPipelinePass, PassAttachment, PipelineTask, PooledObjectProvider, SubmitRecorder};
+use crate::renderer::emulator::vertex_compaction::VertexChannels;
+use crate::util::vk::make_full_rect;
+
+use crate::prelude::*;
+
+/// The format of the output attachment. Color attachment, blending and sampling support is
+/// required for this format by the vulkan specification.
+const COLOR_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// The depth formats in order of preference. `D16_UNORM` must be supported by every device.
+const DEPTH_FORMATS: [vk::Format; 3] = [vk::Format::D32_SFLOAT, vk::Format::X8_D24_UNORM_PACK32, vk::Format::D16_UNORM];
+
+/// The number of passes which can be in flight at the same time.
+const CONCURRENT_PASSES: usize = 2;
+
+/// A [`EmulatorPipeline`] rendering the vertex colors of all draws using only vulkan 1.0
+/// functionality. See the [module documentation](self).
+pub struct FallbackPipeline {
+    emulator: Arc<EmulatorRenderer>,
+    weak: Weak<Self>,
+
+    framebuffer_size: Vec2u32,
+    depth_format: vk::Format,
+    render_pass: vk::RenderPass,
+
+    /// Owned by the layout cache of the device.
+    pipeline_layout: vk::PipelineLayout,
+    position_module: vk::ShaderModule,
+    color_module: vk::ShaderModule,
+    fragment_module: vk::ShaderModule,
+
+    shaders: Mutex<HashMap<ShaderId, ShaderPipelines>>,
+
+    next_index: AtomicUsize,
+    pass_objects: Box<[PassObjects]>,
+    output_views: Box<[vk::ImageView]>,
+}
+assert_impl_all!(FallbackPipeline: Send, Sync);
+
+impl FallbackPipeline {
+    pub fn new(emulator: Arc<EmulatorRenderer>, framebuffer_size: Vec2u32) -> Result<Arc<Self>, ObjectCreateError> {
+        let device = emulator.get_device().clone();
+        let depth_format = Self::select_depth_format(&device);
+
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: 0,
+            size: std::mem::size_of::<PushConstants>() as u32,
+        };
+        let pipeline_layout = device.get_utils().layout_cache().get_pipeline_layout(&[], std::slice::from_ref(&push_constant_range)).map_err(|err| {
+            log::error!("vkCreatePipelineLayout returned {:?} in FallbackPipeline::new", err);
+            err
+        })?;
+
+        let render_pass = Self::create_render_pass(&device, depth_format)?;
+
+        let mut modules = Vec::with_capacity(3);
+        for (name, data) in [("position_vertex", FALLBACK_POSITION_VERTEX_BIN), ("color_vertex", FALLBACK_COLOR_VERTEX_BIN), ("fragment", FALLBACK_FRAGMENT_BIN)] {
+            match unsafe { create_shader_from_bytes(device.get_functions(), data) } {
+                Ok(module) => modules.push(module),
+                Err(err) => {
+                    log::error!("vkCreateShaderModule returned {:?} when creating fallback module {:?}", err, name);
+                    unsafe {
+                        for module in modules {
+                            device.vk().destroy_shader_module(module, None);
+                        }
+                        device.vk().destroy_render_pass(render_pass, None);
+                    }
+                    return Err(ObjectCreateError::Vulkan(err));
+                }
+            }
+        }
+
+        let mut pass_objects = Vec::with_capacity(CONCURRENT_PASSES);
+        for _ in 0..CONCURRENT_PASSES {
+            match PassObjects::new(&device, framebuffer_size, depth_format, render_pass) {
+                Ok(objects) => pass_objects.push(objects),
+                Err(err) => {
+                    for mut objects in pass_objects {
+                        objects.destroy(&device);
+                    }
+                    unsafe {
+                        for module in modules {
+                            device.vk().destroy_shader_module(module, None);
+                        }
+                        device.vk().destroy_render_pass(render_pass, None);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        let pass_objects = pass_objects.into_boxed_slice();
+        let output_views = pass_objects.iter().map(|objects| objects.color_view).collect();
+
+        log::info!("Created fallback pipeline with size {:?} and depth format {:?}", framebuffer_size, depth_format);
+
+        Ok(Arc::new_cyclic(|weak| {
+            Self {
+                emulator,
+                weak: weak.clone(),
+
+                framebuffer_size,
+                depth_format,
+                render_pass,
+
+                pipeline_layout,
+                position_module: modules[0],
+                color_module: modules[1],
+                fragment_module: modules[2],
+
+                shaders: Mutex::new(HashMap::new()),
+
+                next_index: AtomicUsize::new(0),
+                pass_objects,
+                output_views,
+            }
+        }))
+    }
+
+    /// Returns the depth format used for the depth attachment.
+    pub fn get_depth_format(&self) -> vk::Format {
+        self.depth_format
+    }
+
+    /// Returns the first depth format of [`DEPTH_FORMATS`] supported as a depth attachment.
+    fn select_depth_format(device: &DeviceContext) -> vk::Format {
+        for format in DEPTH_FORMATS {
+            let properties = unsafe {
+                device.get_instance().vk().get_physical_device_format_properties(device.get_functions().physical_device, format)
+            };
+            if properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT) {
+                return format;
+            }
+        }
+        vk::Format::D16_UNORM
+    }
+
+    /// Creates the render pass. Both attachments are cleared and the color attachment can be
+    /// sampled after the pass. The subpass dependencies replace all barriers the other pipelines
+    /// record.
+    fn create_render_pass(device: &DeviceContext, depth_format: vk::Format) -> Result<vk::RenderPass, ObjectCreateError> {
+        let attachments = [
+            vk::AttachmentDescription::builder()
+                .format(depth_format)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                .build(),
+            vk::AttachmentDescription::builder()
+                .format(COLOR_FORMAT)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .build(),
+        ];
+
+        let depth_reference = vk::AttachmentReference {
+            attachment: 0,
+            layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+        };
+        let color_reference = vk::AttachmentReference {
+            attachment: 1,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        };
+
+        let subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(std::slice::from_ref(&color_reference))
+            .depth_stencil_attachment(&depth_reference);
+
+        let dependencies = [
+            // Outputs of the previous pass using the attachments must have finished reading them
+            // and immediate data copied by the worker must be visible to the vertex input
+            vk::SubpassDependency {
+                src_subpass: vk::SUBPASS_EXTERNAL,
+                dst_subpass: 0,
+                src_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER,
+                dst_stage_mask: vk::PipelineStageFlags::VERTEX_INPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags::VERTEX_ATTRIBUTE_READ | vk::AccessFlags::INDEX_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dependency_flags: vk::DependencyFlags::empty(),
+            },
+            vk::SubpassDependency {
+                src_subpass: 0,
+                dst_subpass: vk::SUBPASS_EXTERNAL,
+                src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::TRANSFER,
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::TRANSFER_READ,
+                dependency_flags: vk::DependencyFlags::empty(),
+            },
+        ];
+
+        let info = vk::RenderPassCreateInfo::builder()
+            .attachments(&attachments)
+            .subpasses(std::slice::from_ref(&subpass))
+            .dependencies(&dependencies);
+
+        let render_pass = unsafe {
+            device.vk().create_render_pass(&info, None)
+        }.map_err(|err| {
+            log::error!("vkCreateRenderPass returned {:?} in FallbackPipeline::create_render_pass", err);
+            err
+        })?;
+
+        Ok(render_pass)
+    }
+
+    /// Returns the next index to be used for a pass and increments the internal counter.
+    fn next_index(&self) -> usize {
+        loop {
+            let current = self.next_index.load(Ordering::SeqCst);
+            let next = (current + 1) % self.pass_objects.len();
+            if self.next_index.compare_exchange(current, next, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return current;
+            }
+        }
+    }
+
+    /// Returns the pipeline of the shader for the config creating it if needed. Returns [`None`]
+    /// if the shader has not been marked as used.
+    fn get_pipeline(&self, shader: ShaderId, config: &PipelineConfig) -> Option<vk::Pipeline> {
+        let mut guard = self.shaders.lock().unwrap();
+        let pipelines = guard.get_mut(&shader)?;
+        if let Some(pipeline) = pipelines.pipelines.get(config) {
+            return Some(*pipeline);
+        }
+
+        let pipeline = self.create_pipeline(config, &pipelines.vertex_format)?;
+        pipelines.pipelines.insert(*config, pipeline);
+        Some(pipeline)
+    }
+
+    fn create_pipeline(&self, config: &PipelineConfig, vertex_format: &VertexFormat) -> Option<vk::Pipeline> {
+        let mut attributes = vec![
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vertex_format.position.format,
+                offset: vertex_format.position.offset,
+            }
+        ];
+        let vertex_module = if let Some(color) = &vertex_format.color {
+            attributes.push(vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: color.format,
+                offset: color.offset,
+            });
+            self.color_module
+        } else {
+            self.position_module
+        };
+
+        let binding = vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: vertex_format.stride,
+            input_rate: vk::VertexInputRate::VERTEX,
+        };
+        let input_state = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(std::slice::from_ref(&binding))
+            .vertex_attribute_descriptions(&attributes);
+
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .module(vertex_module)
+                .name(SHADER_ENTRY)
+                .build(),
+            vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .module(self.fragment_module)
+                .name(SHADER_ENTRY)
+                .build(),
+        ];
+
+        let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(config.primitive_topology)
+            .primitive_restart_enable(false);
+
+        // The viewport and scissor are dynamic so that pipelines do not depend on the framebuffer size
+        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
+            .polygon_mode(vk::PolygonMode::FILL)
+            .cull_mode(config.cull_state.cull_mode)
+            .front_face(config.cull_state.front_face)
+            .line_width(1f32);
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
+            .depth_test_enable(true)
+            .depth_write_enable(config.depth_write_enable)
+            .depth_compare_op(match config.depth_test {
+                DepthTest::Default => vk::CompareOp::LESS,
+                DepthTest::Equal => vk::CompareOp::EQUAL,
+            });
+
+        let attachment_blend_state = vk::PipelineColorBlendAttachmentState::builder()
+            .blend_enable(false)
+            .color_write_mask(vk::ColorComponentFlags::RGBA);
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo::builder()
+            .logic_op_enable(false)
+            .attachments(std::slice::from_ref(&attachment_blend_state));
+
+        let dynamic_states = [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&dynamic_states);
+
+        let info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&stages)
+            .vertex_input_state(&input_state)
+            .input_assembly_state(&input_assembly_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .multisample_state(&multisample_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .color_blend_state(&color_blend_state)
+            .dynamic_state(&dynamic_state)
+            .layout(self.pipeline_layout)
+            .render_pass(self.render_pass)
+            .subpass(0);
+
+        match unsafe {
+            self.emulator.get_device().vk().create_graphics_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), None)
+        } {
+            Ok(pipelines) => Some(pipelines[0]),
+            Err((_, err)) => {
+                // Skipping the draws of the config is better than aborting on a driver this old
+                log::error!("vkCreateGraphicsPipelines returned {:?} for fallback pipeline config {:?}", err, config);
+                None
+            }
+        }
+    }
+
+    fn destroy_pipelines(&self, pipelines: ShaderPipelines) {
+        let device = self.emulator.get_device();
+        for pipeline in pipelines.pipelines.into_values() {
+            unsafe {
+                device.vk().destroy_pipeline(pipeline, None);
+            }
+        }
+    }
+}
+
+impl EmulatorPipeline for FallbackPipeline {
+    fn start_pass(&self) -> Box<dyn EmulatorPipelinePass + Send> {
+        let index = self.next_index();
+        self.pass_objects[index].wait_and_take();
+
+        Box::new(FallbackPipelinePass::new(self.weak.upgrade().unwrap(), index))
+    }
+
+    fn get_output(&self) -> (Vec2u32, &[vk::ImageView]) {
+        (self.framebuffer_size, &self.output_views)
+    }
+
+    fn get_attachment(&self, attachment: PassAttachment, index: usize) -> Option<AttachmentInfo> {
+        let objects = self.pass_objects.get(index)?;
+        match attachment {
+            PassAttachment::Output | PassAttachment::Color => Some(AttachmentInfo {
+                image: objects.color_image,
+                format: COLOR_FORMAT,
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                size: self.framebuffer_size,
+            }),
+            _ => None,
+        }
+    }
+
+    fn get_used_vertex_channels(&self) -> VertexChannels {
+        VertexChannels::COLOR
+    }
+
+    fn inc_shader_used(&self, shader: ShaderId) {
+        let mut guard = self.shaders.lock().unwrap();
+        if let Some(pipelines) = guard.get_mut(&shader) {
+            pipelines.used_count += 1;
+        } else {
+            let shader_obj = self.emulator.get_shader(shader).unwrap_or_else(|| {
+                log::error!("Called inc_shader_used for nonexistent shader {:?}", shader);
+                panic!()
+            });
+            guard.insert(shader, ShaderPipelines {
+                vertex_format: *shader_obj.get_vertex_format(),
+                used_count: 1,
+                pipelines: HashMap::new(),
+            });
+        }
+    }
+
+    fn dec_shader_used(&self, shader: ShaderId) {
+        let mut guard = self.shaders.lock().unwrap();
+        let pipelines = guard.get_mut(&shader).unwrap_or_else(|| {
+            log::error!("Called dec_shader_used for shader which is not registered {:?}", shader);
+            panic!();
+        });
+        pipelines.used_count -= 1;
+        if pipelines.used_count == 0 {
+            let pipelines = guard.remove(&shader).unwrap();
+            drop(guard);
+            self.destroy_pipelines(pipelines);
+        }
+    }
+}
+
+impl Drop for FallbackPipeline {
+    fn drop(&mut self) {
+        let shaders: Vec<_> = self.shaders.get_mut().unwrap().drain().map(|(_, pipelines)| pipelines).collect();
+        for pipelines in shaders {
+            self.destroy_pipelines(pipelines);
+        }
+
+        let device = self.emulator.get_device();
+        for objects in self.pass_objects.iter_mut() {
+            objects.destroy(device);
+        }
+        unsafe {
+            device.vk().destroy_shader_module(self.fragment_module, None);
+            device.vk().destroy_shader_module(self.color_module, None);
+            device.vk().destroy_shader_module(self.position_module, None);
+            device.vk().destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
+
+/// The pipelines created for a shader. Destroyed once no pass uses the shader anymore.
+struct ShaderPipelines {
+    vertex_format: VertexFormat,
+    used_count: u32,
+    pipelines: HashMap<PipelineConfig, vk::Pipeline>,
+}
+
+/// The draw state baked into a fallback pipeline. All other draw state is ignored.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+struct PipelineConfig {
+    primitive_topology: vk::PrimitiveTopology,
+    depth_write_enable: bool,
+    depth_test: DepthTest,
+    cull_state: CullState,
+}
+
+impl PipelineConfig {
+    fn from_task(task: &DrawTask) -> Self {
+        Self {
+            primitive_topology: task.primitive_topology,
+            depth_write_enable: task.depth_write_enable,
+            depth_test: task.depth_test,
+            cull_state: task.cull_state,
+        }
+    }
+}
+
+/// The render targets of one pass.
+struct PassObjects {
+    ready: AtomicBool,
+
+    depth_image: vk::Image,
+    depth_view: vk::ImageView,
+    color_image: vk::Image,
+    color_view: vk::ImageView,
+    framebuffer: vk::Framebuffer,
+
+    allocations: Vec<Allocation>,
+}
+
+impl PassObjects {
+    fn new(device: &DeviceContext, framebuffer_size: Vec2u32, depth_format: vk::Format, render_pass: vk::RenderPass) -> Result<Self, ObjectCreateError> {
+        let mut result = Self {
+            ready: AtomicBool::new(true),
+
+            depth_image: vk::Image::null(),
+            depth_view: vk::ImageView::null(),
+            color_image: vk::Image::null(),
+            color_view: vk::ImageView::null(),
+            framebuffer: vk::Framebuffer::null(),
+
+            allocations: Vec::with_capacity(2),
+        };
+
+        let (depth_image, allocation) = Self::create_image(device, framebuffer_size, depth_format, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)?;
+        result.depth_image = depth_image;
+        result.allocations.push(allocation);
+
+        result.depth_view = Self::create_image_view(device, depth_image, depth_format, vk::ImageAspectFlags::DEPTH).map_err(|err| {
+            result.destroy(device);
+            err
+        })?;
+
+        let (color_image, allocation) = Self::create_image(device, framebuffer_size, COLOR_FORMAT, vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_SRC).map_err(|err| {
+            result.destroy(device);
+            err
+        })?;
+        result.color_image = color_image;
+        result.allocations.push(allocation);
+
+        result.color_view = Self::create_image_view(device, color_image, COLOR_FORMAT, vk::ImageAspectFlags::COLOR).map_err(|err| {
+            result.destroy(device);
+            err
+        })?;
+
+        let attachments = [result.depth_view, result.color_view];
+        let info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(framebuffer_size[0])
+            .height(framebuffer_size[1])
+            .layers(1);
+
+        result.framebuffer = unsafe {
+            device.vk().create_framebuffer(&info, None)
+        }.map_err(|err| {
+            log::error!("vkCreateFramebuffer returned {:?} in fallback PassObjects::new", err);
+            result.destroy(device);
+            ObjectCreateError::Vulkan(err)
+        })?;
+
+        Ok(result)
+    }
+
+    fn create_image(device: &DeviceContext, size: Vec2u32, format: vk::Format, usage: vk::ImageUsageFlags) -> Result<(vk::Image, Allocation), ObjectCreateError> {
+        let info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: size[0],
+                height: size[1],
+                depth: 1
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        unsafe {
+            device.get_allocator().create_gpu_image(&info, &format_args!("FallbackPipelineImage"))
+        }.ok_or(ObjectCreateError::Allocation)
+    }
+
+    fn create_image_view(device: &DeviceContext, image: vk::Image, format: vk::Format, aspect_mask: vk::ImageAspectFlags) -> Result<vk::ImageView, ObjectCreateError> {
+        let info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1
+            });
+
+        let image_view = unsafe {
+            device.vk().create_image_view(&info, None)
+        }.map_err(|err| {
+            log::error!("vkCreateImageView returned {:?} in fallback PassObjects::create_image_view", err);
+            err
+        })?;
+
+        Ok(image_view)
+    }
+
+    fn wait_and_take(&self) {
+        let mut start = Instant::now();
+        loop {
+            if self.ready.compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return;
+            }
+            std::thread::yield_now();
+            if start.elapsed().as_millis() > 1000 {
+                log::warn!("Hit 1s timeout waiting for next fallback pipeline object");
+                start = Instant::now();
+            }
+        }
+    }
+
+    fn destroy(&mut self, device: &DeviceContext) {
+        unsafe {
+            if self.framebuffer != vk::Framebuffer::null() {
+                device.vk().destroy_framebuffer(self.framebuffer, None);
+            }
+            if self.color_view != vk::ImageView::null() {
+                device.vk().destroy_image_view(self.color_view, None);
+            }
+            if self.color_image != vk::Image::null() {
+                device.vk().destroy_image(self.color_image, None);
+            }
+            if self.depth_view != vk::ImageView::null() {
+                device.vk().destroy_image_view(self.depth_view, None);
+            }
+            if self.depth_image != vk::Image::null() {
+                device.vk().destroy_image(self.depth_image, None);
+            }
+            device.get_allocator().free_memory_pages(&self.allocations);
+        }
+        self.allocations.clear();
+    }
+}
+
+/// The uniforms of a shader used by the fallback pipeline.
+#[derive(Copy, Clone)]
+struct ShaderUniforms {
+    model_view: Mat4f32,
+    projection: Mat4f32,
+    chunk_offset: Vec3f32,
+}
+
+impl ShaderUniforms {
+    fn new() -> Self {
+        Self {
+            model_view: Mat4f32::identity(),
+            projection: Mat4f32::identity(),
+            chunk_offset: Vec3f32::zeros(),
+        }
+    }
+
+    fn to_push_constants(&self) -> PushConstants {
+        let mut model_view_projection = [0f32; 16];
+        model_view_projection.copy_from_slice((self.projection * self.model_view).as_slice());
+
+        PushConstants {
+            model_view_projection,
+            chunk_offset: [self.chunk_offset[0], self.chunk_offset[1], self.chunk_offset[2], 0f32],
+        }
+    }
+}
+
+struct FallbackPipelinePass {
+    parent: Arc<FallbackPipeline>,
+    index: usize,
+
+    command_buffer: Option<vk::CommandBuffer>,
+    uniforms: HashMap<ShaderId, ShaderUniforms>,
+
+    current_pipeline: Option<vk::Pipeline>,
+    current_push_constants: Option<PushConstants>,
+    current_vertex_buffer: Option<vk::Buffer>,
+    current_index_buffer: Option<(vk::Buffer, vk::IndexType)>,
+    current_viewport: Option<vk::Rect2D>,
+    current_scissor: Option<vk::Rect2D>,
+
+    /// The render pass is only begun by the first task rendering into the attachments so that the
+    /// clear config can still be changed before.
+    clear_config: ClearConfig,
+    render_pass_begun: bool,
+}
+
+impl FallbackPipelinePass {
+    fn new(parent: Arc<FallbackPipeline>, index: usize) -> Self {
+        Self {
+            parent,
+            index,
+
+            command_buffer: None,
+            uniforms: HashMap::new(),
+
+            current_pipeline: None,
+            current_push_constants: None,
+            current_vertex_buffer: None,
+            current_index_buffer: None,
+            current_viewport: None,
+            current_scissor: None,
+
+            clear_config: ClearConfig::DEFAULT,
+            render_pass_begun: false,
+        }
+    }
+
+    fn update_uniform(&mut self, shader: ShaderId, data: &McUniformData) {
+        let uniforms = self.uniforms.entry(shader).or_insert_with(ShaderUniforms::new);
+        match data {
+            McUniformData::ModelViewMatrix(mat) => uniforms.model_view = *mat,
+            McUniformData::ProjectionMatrix(mat) => uniforms.projection = *mat,
+            McUniformData::ChunkOffset(offset) => uniforms.chunk_offset = *offset,
+            _ => {}
+        }
+    }
+
+    /// Begins the render pass using the clear config if it has not been begun yet. The depth
+    /// attachment is always cleared since its content is not stored.
+    fn ensure_render_pass(&mut self) {
+        if !self.render_pass_begun {
+            self.render_pass_begun = true;
+
+            let clear_values = [
+                vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: 1f32,
+                        stencil: 0
+                    }
+                },
+                vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: self.clear_config.color.unwrap_or([0f32, 0f32, 0f32, 0f32]),
+                    }
+                },
+            ];
+            let info = vk::RenderPassBeginInfo::builder()
+                .render_pass(self.parent.render_pass)
+                .framebuffer(self.parent.pass_objects[self.index].framebuffer)
+                .render_area(make_full_rect(self.parent.framebuffer_size))
+                .clear_values(&clear_values);
+
+            unsafe {
+                self.parent.emulator.get_device().vk().cmd_begin_render_pass(*self.command_buffer.as_ref().unwrap(), &info, vk::SubpassContents::INLINE);
+            }
+        }
+    }
+
+    fn clear_depth(&mut self, region: Option<vk::Rect2D>) {
+        let rect = clamp_scissor(region, self.parent.framebuffer_size);
+        if rect.extent.width == 0 || rect.extent.height == 0 {
+            return;
+        }
+
+        let attachment = vk::ClearAttachment {
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            color_attachment: 0,
+            clear_value: vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: 1f32,
+                    stencil: 0
+                }
+            }
+        };
+        let rect = vk::ClearRect {
+            rect,
+            base_array_layer: 0,
+            layer_count: 1
+        };
+
+        unsafe {
+            self.parent.emulator.get_device().vk().cmd_clear_attachments(*self.command_buffer.as_ref().unwrap(), std::slice::from_ref(&attachment), std::slice::from_ref(&rect));
+        }
+    }
+
+    fn draw(&mut self, task: &DrawTask) {
+        let region = task.viewport.unwrap_or_else(|| make_full_rect(self.parent.framebuffer_size));
+        if region.extent.width == 0 || region.extent.height == 0 {
+            // Vulkan does not allow empty viewports
+            return;
+        }
+
+        let pipeline = match self.parent.get_pipeline(task.shader, &PipelineConfig::from_task(task)) {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+
+        let device = self.parent.emulator.get_device();
+        let cmd = *self.command_buffer.as_ref().unwrap();
+
+        if self.current_pipeline != Some(pipeline) {
+            unsafe {
+                device.vk().cmd_bind_pipeline(cmd, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            }
+            self.current_pipeline = Some(pipeline);
+        }
+
+        let push_constants = self.uniforms.get(&task.shader).copied().unwrap_or_else(ShaderUniforms::new).to_push_constants();
+        if self.current_push_constants != Some(push_constants) {
+            unsafe {
+                device.vk().cmd_push_constants(cmd, self.parent.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, bytes_of(&push_constants));
+            }
+            self.current_push_constants = Some(push_constants);
+        }
+
+        if self.current_viewport != Some(region) {
+            let viewport = vk::Viewport {
+                x: region.offset.x as f32,
+                y: region.offset.y as f32,
+                width: region.extent.width as f32,
+                height: region.extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0
+            };
+            unsafe {
+                device.vk().cmd_set_viewport(cmd, 0, std::slice::from_ref(&viewport));
+            }
+            self.current_viewport = Some(region);
+        }
+
+        let scissor = match (task.scissor, task.viewport) {
+            (Some(scissor), Some(viewport)) => Some(intersect_rect(scissor, viewport)),
+            (scissor, viewport) => scissor.or(viewport),
+        };
+        let scissor = clamp_scissor(scissor, self.parent.framebuffer_size);
+        if self.current_scissor != Some(scissor) {
+            unsafe {
+                device.vk().cmd_set_scissor(cmd, 0, std::slice::from_ref(&scissor));
+            }
+            self.current_scissor = Some(scissor);
+        }
+
+        if self.current_vertex_buffer != Some(task.vertex_buffer) {
+            unsafe {
+                device.vk().cmd_bind_vertex_buffers(cmd, 0, std::slice::from_ref(&task.vertex_buffer), std::slice::from_ref(&0));
+            }
+            self.current_vertex_buffer = Some(task.vertex_buffer);
+        }
+
+        if self.current_index_buffer != Some((task.index_buffer, task.index_type)) {
+            unsafe {
+                device.vk().cmd_bind_index_buffer(cmd, task.index_buffer, 0, task.index_type);
+            }
+            self.current_index_buffer = Some((task.index_buffer, task.index_type));
+        }
+
+        // Instance data is not bound so all instances would be drawn at the same position
+        unsafe {
+            device.vk().cmd_draw_indexed(cmd, task.index_count, 1, task.first_index, task.vertex_offset, 0);
+        }
+    }
+}
+
+impl EmulatorPipelinePass for FallbackPipelinePass {
+    fn init(&mut self, _: &Queue, obj: &mut PooledObjectProvider, _: vk::ImageView, _: vk::Sampler) {
+        self.command_buffer = Some(obj.get_begin_command_buffer().unwrap());
+    }
+
+    fn process_task(&mut self, task: &PipelineTask, _: &mut PooledObjectProvider) {
+        match task {
+            PipelineTask::UpdateUniform(shader, data) => {
+                self.update_uniform(*shader, data);
+            }
+            PipelineTask::Draw(task) => {
+                self.ensure_render_pass();
+                self.draw(task);
+            }
+            PipelineTask::DrawCulled(task) => {
+                // The draw task covers all sections of the mesh
+                self.ensure_render_pass();
+                self.draw(&task.draw);
+            }
+            PipelineTask::ClearDepth(region) => {
+                self.ensure_render_pass();
+                self.clear_depth(*region);
+            }
+            PipelineTask::SetClearConfig(config) => {
+                if self.render_pass_begun {
+                    log::warn!("Clear config set after the first draw of a pass. Ignoring!");
+                } else {
+                    self.clear_config = *config;
+                }
+            }
+            PipelineTask::UpdateTexture(_, _, _, _) |
+            PipelineTask::UpdateBoneMatrices(_, _, _, _) |
+            PipelineTask::SetScreenEffects(_) |
+            PipelineTask::SetViewProjections(_) |
+            PipelineTask::SetLights(_) => {}
+        }
+    }
+
+    fn record<'a>(&mut self, _: &mut PooledObjectProvider, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+        self.ensure_render_pass();
+        let device = self.parent.emulator.get_device();
+        let cmd = self.command_buffer.take().unwrap();
+
+        unsafe {
+            device.vk().cmd_end_render_pass(cmd);
+            device.vk().end_command_buffer(cmd).unwrap();
+        }
+
+        let command_buffer_info = alloc.alloc(vk::CommandBufferSubmitInfo::builder()
+            .command_buffer(cmd)
+        );
+
+        submits.push(vk::SubmitInfo2::builder()
+            .command_buffer_infos(std::slice::from_ref(command_buffer_info))
+        );
+    }
+
+    fn get_output_index(&self) -> usize {
+        self.index
+    }
+
+    fn get_internal_fences(&self, _: &mut Vec<vk::Fence>) {
+        // All command buffers are submitted by the worker
+    }
+}
+
+impl Drop for FallbackPipelinePass {
+    fn drop(&mut self) {
+        self.parent.pass_objects[self.index].ready.store(true, Ordering::SeqCst);
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq)]
+struct PushConstants {
+    model_view_projection: [f32; 16],
+
+    /// The w component is unused.
+    chunk_offset: [f32; 4],
+}
+const_assert_eq!(std::mem::size_of::<PushConstants>(), 80);
+
+unsafe impl Zeroable for PushConstants {}
+unsafe impl Pod for PushConstants {}
+
+const SHADER_ENTRY: &CStr = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
+
+static FALLBACK_POSITION_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/fallback/position_vert.spv"));
+static FALLBACK_COLOR_VERTEX_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/fallback/color_vert.spv"));
+static FALLBACK_FRAGMENT_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/fallback/fallback_frag.spv"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_constants_combine_matrices() {
+        let mut uniforms = ShaderUniforms::new();
+        uniforms.model_view = Mat4f32::new_translation(&Vec3f32::new(1f32, 2f32, 3f32));
+        uniforms.projection = Mat4f32::new_scaling(2f32);
+        uniforms.chunk_offset = Vec3f32::new(16f32, 0f32, -16f32);
+
+        let constants = uniforms.to_push_constants();
+        let expected = uniforms.projection * uniforms.model_view;
+        assert_eq!(&constants.model_view_projection[..], expected.as_slice());
+        assert_eq!(constants.chunk_offset, [16f32, 0f32, -16f32, 0f32]);
+    }
+}
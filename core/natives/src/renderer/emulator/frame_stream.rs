@@ -0,0 +1,179 @@
+//! Streaming of rendered frames to host memory for video capture.
+//!
+//! A [`FrameStream`] reads back the output of passes at a fixed target rate and hands the frames
+//! to a user callback, for example to feed a video encoder. At most
+//! [`FrameStream::MAX_PENDING_FRAMES`] readbacks are in flight at the same time. If the readbacks
+//! cannot keep up frames are dropped instead of stalling the render thread.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use ash::vk;
+
+use crate::renderer::emulator::PassRecorder;
+use crate::renderer::emulator::pipeline::PassAttachment;
+use crate::renderer::emulator::readback::{AttachmentData, AttachmentReadback};
+
+use crate::prelude::*;
+
+/// A frame delivered by a [`FrameStream`].
+pub struct StreamedFrame {
+    /// The index of the frame in the stream. Dropped frames are not assigned an index.
+    pub index: u64,
+
+    /// The time at which the frame was scheduled relative to the start of the stream. Frames are
+    /// spaced by exactly the frame interval unless frames have been skipped.
+    pub timestamp: Duration,
+    pub size: Vec2u32,
+    pub format: vk::Format,
+
+    /// The tightly packed texels of the frame.
+    pub data: Box<[u8]>,
+}
+
+pub type FrameStreamCallback = Box<dyn FnMut(StreamedFrame) + Send>;
+
+pub struct FrameStream {
+    callback: FrameStreamCallback,
+    scheduler: FrameScheduler,
+    start: Option<Instant>,
+    next_index: u64,
+    dropped_frames: u64,
+    pending: VecDeque<(u64, Duration, AttachmentReadback)>,
+}
+
+impl FrameStream {
+    /// The number of frames which can be read back at the same time.
+    pub const MAX_PENDING_FRAMES: usize = 2;
+
+    /// Creates a new stream delivering frames at up to `target_rate` frames per second. The
+    /// callback is called on the thread starting frames and should hand the data off to a encoder
+    /// thread as quickly as possible.
+    pub fn new(target_rate: f32, callback: FrameStreamCallback) -> Self {
+        Self {
+            callback,
+            scheduler: FrameScheduler::new(Duration::from_secs_f32(1f32 / target_rate)),
+            start: None,
+            next_index: 0,
+            dropped_frames: 0,
+            pending: VecDeque::with_capacity(Self::MAX_PENDING_FRAMES),
+        }
+    }
+
+    /// Returns the number of frames which have been dropped because too many readbacks were in
+    /// flight.
+    pub fn get_dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    /// Must be called for every pass of the stream before any commands are recorded. Delivers all
+    /// completed frames and requests a readback of the pass output if a frame is due.
+    pub fn on_pass(&mut self, recorder: &mut PassRecorder) {
+        self.poll();
+
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let timestamp = match self.scheduler.next_due(start.elapsed()) {
+            Some(timestamp) => timestamp,
+            None => return,
+        };
+
+        if self.pending.len() >= Self::MAX_PENDING_FRAMES {
+            self.dropped_frames += 1;
+            log::debug!("Dropping streamed frame at {:?}. Too many readbacks in flight", timestamp);
+            return;
+        }
+
+        let readback = recorder.readback_attachment(PassAttachment::Output);
+        self.pending.push_back((self.next_index, timestamp, readback));
+        self.next_index += 1;
+    }
+
+    /// Delivers all frames which have completed. Frames are always delivered in order.
+    pub fn poll(&mut self) {
+        while let Some((index, timestamp, readback)) = self.pending.pop_front() {
+            match readback.try_get() {
+                Ok(data) => self.deliver(index, timestamp, data),
+                Err(readback) => {
+                    self.pending.push_front((index, timestamp, readback));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Blocks until all pending frames have been delivered.
+    pub fn flush(&mut self) {
+        while let Some((index, timestamp, readback)) = self.pending.pop_front() {
+            self.deliver(index, timestamp, readback.wait());
+        }
+    }
+
+    fn deliver(&mut self, index: u64, timestamp: Duration, data: Option<AttachmentData>) {
+        match data {
+            Some(data) => (self.callback)(StreamedFrame {
+                index,
+                timestamp,
+                size: data.size,
+                format: data.format,
+                data: data.data,
+            }),
+            None => log::warn!("Failed to read back streamed frame {}", index),
+        }
+    }
+}
+
+impl Drop for FrameStream {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Decides which frames are captured to reach the target rate.
+struct FrameScheduler {
+    interval: Duration,
+    next: Duration,
+}
+
+impl FrameScheduler {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Duration::ZERO,
+        }
+    }
+
+    /// Returns the timestamp of the frame if a frame is due at `now`. The timestamp is the latest
+    /// frame slot which is not after `now`. Any earlier missed slots are skipped.
+    fn next_due(&mut self, now: Duration) -> Option<Duration> {
+        if now < self.next {
+            return None;
+        }
+
+        let missed = (now - self.next).as_nanos() / self.interval.as_nanos();
+        let timestamp = self.next + self.interval * (missed as u32);
+        self.next = timestamp + self.interval;
+
+        Some(timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scheduler() {
+        let ms = Duration::from_millis;
+        let mut scheduler = FrameScheduler::new(ms(10));
+
+        assert_eq!(scheduler.next_due(ms(0)), Some(ms(0)));
+        assert_eq!(scheduler.next_due(ms(5)), None);
+        assert_eq!(scheduler.next_due(ms(12)), Some(ms(10)));
+        assert_eq!(scheduler.next_due(ms(19)), None);
+
+        // The frames at 20 and 30 are skipped
+        assert_eq!(scheduler.next_due(ms(45)), Some(ms(40)));
+        assert_eq!(scheduler.next_due(ms(49)), None);
+        assert_eq!(scheduler.next_due(ms(50)), Some(ms(50)));
+    }
+}
@@ -0,0 +1,111 @@
+//! The enchantment glint mirroring minecrafts glint render types.
+//!
+//! Enchanted geometry is drawn twice. The first draw uses the regular shader and state of the
+//! geometry. The second draw renders the same mesh again using a glint layer which only passes
+//! fragments at exactly the depth written by the first draw and adds a scrolling glint texture
+//! sampled using the texture coordinates of the mesh transformed by the `TextureMatrix` uniform.
+//!
+//! The glint layer should be created using [`create_glint_layer_info`] and a shader using the same
+//! vertex format as the drawn meshes. Both draws are recorded using
+//! [`DrawRecorder::draw_global_glint`](super::DrawRecorder::draw_global_glint) or
+//! [`DrawRecorder::draw_immediate_glint`](super::DrawRecorder::draw_immediate_glint).
+
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::prelude::*;
+use crate::renderer::emulator::{GlobalImage, SamplerInfo};
+use crate::renderer::emulator::mc_shaders::ShaderId;
+use crate::renderer::emulator::pipeline::{BlendState, DepthTest};
+use crate::renderer::emulator::render_layer::{RenderLayerInfo, RenderLayerTexture};
+
+/// Per frame parameters of the glint.
+#[derive(Copy, Clone, Debug)]
+pub struct GlintParams {
+    /// The scale of the glint texture. See [`GlintParams::ITEM_SCALE`] and
+    /// [`GlintParams::ENTITY_SCALE`].
+    pub scale: f32,
+
+    /// The glint speed option of vanilla. 1 is the default speed.
+    pub speed: f32,
+
+    /// Used to animate the texture.
+    pub time_millis: u64,
+}
+
+impl GlintParams {
+    /// The scale used by vanilla for items and armor.
+    pub const ITEM_SCALE: f32 = 8f32;
+
+    /// The scale used by vanilla for entities.
+    pub const ENTITY_SCALE: f32 = 0.16f32;
+
+    pub fn new(scale: f32, time_millis: u64) -> Self {
+        Self {
+            scale,
+            speed: 1f32,
+            time_millis,
+        }
+    }
+
+    /// Returns the texture matrix matching `RenderStateShard.setupGlintTexturing`.
+    pub fn get_texture_matrix(&self) -> Mat4f32 {
+        let time = ((self.time_millis as f64) * (self.speed as f64) * 8f64) as u64;
+        let offset_x = ((time % 110000) as f32) / 110000f32;
+        let offset_y = ((time % 30000) as f32) / 30000f32;
+
+        let translation = Mat4f32::new_translation(&Vec3f32::new(-offset_x, offset_y, 0f32));
+        let rotation = Mat4f32::from_axis_angle(&Vec3f32::z_axis(), std::f32::consts::PI / 18f32);
+        let scale = Mat4f32::new_scaling(self.scale);
+
+        translation * rotation * scale
+    }
+}
+
+/// Returns the info of a glint layer drawing meshes with the specified topology. The glint texture
+/// is bound to texture index 0 of the shader.
+pub fn create_glint_layer_info(shader: ShaderId, primitive_topology: vk::PrimitiveTopology, texture: Arc<GlobalImage>) -> RenderLayerInfo {
+    let sampler_info = SamplerInfo {
+        mag_filter: vk::Filter::LINEAR,
+        min_filter: vk::Filter::LINEAR,
+        mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+        address_mode_u: vk::SamplerAddressMode::REPEAT,
+        address_mode_v: vk::SamplerAddressMode::REPEAT,
+        anisotropy_enable: false,
+    };
+
+    let mut info = RenderLayerInfo::new(shader, primitive_topology);
+    info.depth_write_enable = false;
+    info.depth_test = DepthTest::Equal;
+    info.blend_state = Some(BlendState::GLINT);
+    info.textures.push(RenderLayerTexture {
+        index: 0,
+        image: texture,
+        sampler_info,
+    });
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point3;
+
+    use super::*;
+
+    #[test]
+    fn texture_matrix_scrolls() {
+        let start = GlintParams::new(GlintParams::ITEM_SCALE, 0).get_texture_matrix();
+        let origin = start.transform_point(&Point3::origin());
+        assert!(origin.coords.norm() < 1e-5);
+
+        // Both offsets wrap once the scaled time is a multiple of 110000 and 30000
+        let wrapped = GlintParams::new(GlintParams::ITEM_SCALE, 110000 * 30000 / 8).get_texture_matrix();
+        assert!((wrapped - start).abs().max() < 1e-5);
+
+        let moved = GlintParams::new(GlintParams::ITEM_SCALE, 1000).get_texture_matrix();
+        let origin = moved.transform_point(&Point3::origin());
+        assert!((origin[0] + 8000f32 / 110000f32).abs() < 1e-5);
+        assert!((origin[1] - 8000f32 / 30000f32).abs() < 1e-5);
+    }
+}
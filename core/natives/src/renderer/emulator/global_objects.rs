@@ -11,8 +11,14 @@ use crate::define_uuid_type;
 use crate::renderer::emulator::{MeshData, PassId};
 
 use crate::prelude::*;
+use crate::renderer::emulator::block_layout::{get_mip_size, BlockLayout, RegionError};
+use crate::renderer::emulator::capture::CapturedMesh;
+use crate::renderer::emulator::mesh_compression::{self, CompressedMeshData, MeshCompression};
 use crate::renderer::emulator::share::Share;
-use crate::renderer::emulator::worker::{GlobalImageClear, GlobalImageWrite, GlobalMeshWrite, WorkerTask};
+use crate::renderer::emulator::quad_indices::QuadIndexBuffer;
+use crate::renderer::emulator::readback::{get_texel_size, GlobalImageReadback};
+use crate::renderer::emulator::staging::StagingAllocation;
+use crate::renderer::emulator::worker::{GlobalImageClear, GlobalImageCopy, GlobalImageRead, GlobalImageWrite, GlobalMeshDecompress, GlobalMeshFill, GlobalMeshUpdate, GlobalMeshWrite, ImageWriteStaging, WorkerTask};
 use crate::util::alloc::next_aligned;
 use crate::util::format::Format;
 
@@ -22,6 +28,10 @@ define_uuid_type!(pub, GlobalMeshId);
 pub enum GlobalObjectCreateError {
     Vulkan(vk::Result),
     Allocation,
+    UnsupportedFormat,
+    /// The compression format requires a device feature which is not supported.
+    UnsupportedCompression,
+    InvalidCompressedData,
 }
 
 impl From<vk::Result> for GlobalObjectCreateError {
@@ -32,6 +42,7 @@ impl From<vk::Result> for GlobalObjectCreateError {
 
 pub struct GlobalMesh {
     share: Arc<Share>,
+    weak: Weak<Self>,
     id: GlobalMeshId,
 
     last_used_pass: AtomicU64,
@@ -41,49 +52,154 @@ pub struct GlobalMesh {
     buffer_size: vk::DeviceSize,
 
     draw_info: GlobalMeshDrawInfo,
+
+    #[allow(unused)] // We just need to keep the shared indices alive
+    quad_indices: Option<Arc<GlobalMesh>>,
+
+    capture_data: Option<Box<CapturedMesh>>,
 }
 
 impl GlobalMesh {
-    pub(super) fn new(share: Arc<Share>, data: &MeshData) -> Result<Arc<Self>, GlobalObjectCreateError> {
-        let index_offset = next_aligned(data.vertex_data.len() as vk::DeviceSize, data.get_index_size() as vk::DeviceSize);
-        let required_size = index_offset + (data.index_data.len() as vk::DeviceSize);
+    pub(super) fn new(share: Arc<Share>, data: &MeshData, quad_index_buffer: &QuadIndexBuffer) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        let mut tasks = Vec::with_capacity(1);
+        let mesh = Self::new_batched(share, data, quad_index_buffer, &mut tasks)?;
+        mesh.share.push_tasks(tasks);
+        Ok(mesh)
+    }
 
-        let (buffer, allocation) = Self::create_buffer(share.get_device(), required_size)?;
+    /// Like [`GlobalMesh::new`] but collects the upload task into `tasks` instead of pushing it.
+    pub(super) fn new_batched(share: Arc<Share>, data: &MeshData, quad_index_buffer: &QuadIndexBuffer, tasks: &mut Vec<WorkerTask>) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        Self::new_staged(share, data, data.vertex_data.len() as vk::DeviceSize, data.index_data.len() as vk::DeviceSize, quad_index_buffer, tasks, |staging, index_offset| {
+            staging.write_bytes(0, data.vertex_data);
+            staging.write_bytes(index_offset as usize, data.index_data);
+            Ok(())
+        })
+    }
 
-        let (staging, staging_allocation) = share.get_staging_pool().lock().unwrap_or_else(|_| {
-            log::error!("Poisoned staging memory mutex in GlobalMesh::new");
-            panic!()
-        }).allocate(required_size, 1);
+    /// Creates a new mesh from compressed data. See [`mesh_compression`](super::mesh_compression).
+    pub(super) fn new_compressed(share: Arc<Share>, data: &CompressedMeshData, quad_index_buffer: &QuadIndexBuffer) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        let mut tasks = Vec::with_capacity(1);
+        let mesh = Self::new_compressed_batched(share, data, quad_index_buffer, &mut tasks)?;
+        mesh.share.push_tasks(tasks);
+        Ok(mesh)
+    }
 
-        unsafe {
-            let dst = std::slice::from_raw_parts_mut(staging.mapped.as_ptr(), required_size as usize);
+    /// Like [`GlobalMesh::new_compressed`] but collects the upload task into `tasks` instead of
+    /// pushing it.
+    pub(super) fn new_compressed_batched(share: Arc<Share>, data: &CompressedMeshData, quad_index_buffer: &QuadIndexBuffer, tasks: &mut Vec<WorkerTask>) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        let info = data.as_empty_mesh_data();
+        let vertex_size = data.vertex_data_size as vk::DeviceSize;
+        let index_size = data.index_data_size as vk::DeviceSize;
+
+        match data.compression {
+            MeshCompression::Deflate => {
+                Self::new_staged(share, &info, vertex_size, index_size, quad_index_buffer, tasks, |staging, index_offset| {
+                    let dst = staging.as_bytes_mut();
+                    let decompressed_size = (vertex_size + index_size) as usize;
+                    if !mesh_compression::inflate_into(data.payload, &mut dst[0..decompressed_size]) {
+                        return Err(GlobalObjectCreateError::InvalidCompressedData);
+                    }
+                    if index_offset != vertex_size {
+                        dst.copy_within((vertex_size as usize)..decompressed_size, index_offset as usize);
+                    }
+                    Ok(())
+                })
+            }
+            MeshCompression::GDeflate => Self::new_device_decompressed(share, data, &info, quad_index_buffer, tasks),
+        }
+    }
 
-            dst[0..data.vertex_data.len()].copy_from_slice(data.vertex_data);
-            dst[(index_offset as usize)..].copy_from_slice(data.index_data);
+    /// Creates a new mesh uploading its data in chunks of at most `chunk_size` bytes. Every chunk
+    /// uses a separate staging allocation and is pushed as soon as it has been written so that the
+    /// staging memory of earlier chunks can be released while later chunks are uploaded.
+    pub(super) fn new_chunked(share: Arc<Share>, data: &MeshData, quad_index_buffer: &QuadIndexBuffer, chunk_size: vk::DeviceSize) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        if chunk_size == 0 {
+            log::error!("Attempted to upload global mesh with a chunk size of 0");
+            panic!()
         }
 
-        let draw_info = GlobalMeshDrawInfo {
-            buffer,
-            first_index: (index_offset / (data.get_index_size() as vk::DeviceSize)) as u32,
-            index_type: data.index_type,
-            index_count: data.index_count,
-            primitive_topology: data.primitive_topology
+        let vertex_size = data.vertex_data.len() as vk::DeviceSize;
+        let index_size = data.index_data.len() as vk::DeviceSize;
+        let index_offset = next_aligned(vertex_size, data.get_index_size() as vk::DeviceSize);
+        let required_size = index_offset + index_size;
+
+        let (buffer, allocation) = Self::create_buffer(share.get_device(), required_size)?;
+
+        let capture_data = if share.retains_capture_data() {
+            Some(Box::new(CapturedMesh::from_mesh_data(data)))
+        } else {
+            None
         };
 
-        let mesh = Arc::new(GlobalMesh {
-            share,
-            id: GlobalMeshId::new(),
+        let mesh = Self::from_parts(share, data, buffer, allocation, required_size, index_offset, index_size, quad_index_buffer, capture_data);
+
+        let mut is_uninit = true;
+        for (base_offset, src) in [(0, data.vertex_data), (index_offset, data.index_data)] {
+            for (index, chunk) in src.chunks(chunk_size as usize).enumerate() {
+                let size = chunk.len() as vk::DeviceSize;
+                let (mut staging, staging_allocation) = mesh.share.allocate_staging(size, 4);
+                staging.write_bytes(0, chunk);
+                staging.flush(mesh.share.get_device());
+                mesh.share.record_upload(size);
+
+                mesh.share.push_task(WorkerTask::WriteGlobalMesh(GlobalMeshWrite {
+                    after_pass: PassId::from_raw(0),
+                    staging_allocation,
+                    staging_range: (staging.offset, size),
+                    staging_buffer: staging.buffer,
+                    dst_mesh: mesh.clone(),
+                    regions: Box::new([vk::BufferCopy {
+                        src_offset: staging.offset,
+                        dst_offset: base_offset + (index as vk::DeviceSize) * chunk_size,
+                        size
+                    }])
+                }, is_uninit));
+                is_uninit = false;
+            }
+        }
 
-            last_used_pass: AtomicU64::new(0),
+        Ok(mesh)
+    }
 
-            buffer,
-            allocation,
-            buffer_size: required_size,
+    /// Creates a new mesh uploading its data through the staging pool. `fill` must write the
+    /// vertex data at offset 0 and the index data at the provided index offset of the staging
+    /// memory. The data of `info` is ignored.
+    pub(super) fn new_staged<F>(share: Arc<Share>, info: &MeshData, vertex_size: vk::DeviceSize, index_size: vk::DeviceSize, quad_index_buffer: &QuadIndexBuffer, tasks: &mut Vec<WorkerTask>, fill: F) -> Result<Arc<Self>, GlobalObjectCreateError>
+        where F: FnOnce(&mut StagingAllocation, vk::DeviceSize) -> Result<(), GlobalObjectCreateError> {
 
-            draw_info
-        });
+        let index_offset = next_aligned(vertex_size, info.get_index_size() as vk::DeviceSize);
+        let required_size = index_offset + index_size;
+
+        let (buffer, allocation) = Self::create_buffer(share.get_device(), required_size)?;
+
+        // Aligned to the index size so that index data can be written as typed values
+        let (mut staging, staging_allocation) = share.allocate_staging_batched(required_size, info.get_index_size() as vk::DeviceSize, tasks);
+
+        if let Err(err) = fill(&mut staging, index_offset) {
+            share.get_staging_pool().lock().unwrap().free(staging_allocation);
+            share.notify_staging_freed();
+            unsafe {
+                share.get_device().get_allocator().destroy_buffer(buffer, allocation);
+            }
+            return Err(err);
+        }
+        staging.flush(share.get_device());
+        share.record_upload(required_size);
+
+        let capture_data = if share.retains_capture_data() {
+            let dst = staging.as_bytes();
+            Some(Box::new(CapturedMesh::from_mesh_data(&MeshData {
+                vertex_data: &dst[0..(vertex_size as usize)],
+                index_data: &dst[(index_offset as usize)..],
+                ..*info
+            })))
+        } else {
+            None
+        };
+
+        let mesh = Self::from_parts(share, info, buffer, allocation, required_size, index_offset, index_size, quad_index_buffer, capture_data);
 
-        mesh.share.push_task(WorkerTask::WriteGlobalMesh(GlobalMeshWrite {
+        tasks.push(WorkerTask::WriteGlobalMesh(GlobalMeshWrite {
             after_pass: PassId::from_raw(0),
             staging_allocation,
             staging_range: (staging.offset, required_size),
@@ -99,6 +215,112 @@ impl GlobalMesh {
         Ok(mesh)
     }
 
+    /// Creates a new mesh from a [`MeshCompression::GDeflate`] payload which is decompressed by
+    /// the device.
+    fn new_device_decompressed(share: Arc<Share>, data: &CompressedMeshData, info: &MeshData, quad_index_buffer: &QuadIndexBuffer, tasks: &mut Vec<WorkerTask>) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        if share.get_device().memory_decompression_nv().is_none() {
+            return Err(GlobalObjectCreateError::UnsupportedCompression);
+        }
+
+        // The payload is decompressed as a whole so the index data must already be aligned
+        let vertex_size = data.vertex_data_size as vk::DeviceSize;
+        if vertex_size % (info.get_index_size() as vk::DeviceSize) != 0 {
+            return Err(GlobalObjectCreateError::InvalidCompressedData);
+        }
+        let required_size = data.get_decompressed_size();
+        let payload_size = data.payload.len() as vk::DeviceSize;
+
+        let (buffer, allocation) = Self::create_buffer(share.get_device(), required_size)?;
+        let (mut staging, staging_allocation) = share.allocate_staging_batched(payload_size, 4, tasks);
+
+        let src_address = share.get_device().get_buffer_device_address(staging.buffer).unwrap() + staging.offset;
+        let dst_address = share.get_device().get_buffer_device_address(buffer).unwrap();
+        let regions = match mesh_compression::gdeflate_regions(src_address, dst_address, payload_size, data.page_sizes, required_size) {
+            Some(regions) => regions,
+            None => {
+                share.get_staging_pool().lock().unwrap().free(staging_allocation);
+                share.notify_staging_freed();
+                unsafe {
+                    share.get_device().get_allocator().destroy_buffer(buffer, allocation);
+                }
+                return Err(GlobalObjectCreateError::InvalidCompressedData);
+            }
+        };
+
+        staging.write_bytes(0, data.payload);
+        staging.flush(share.get_device());
+        share.record_upload(payload_size);
+
+        if share.retains_capture_data() {
+            log::warn!("Device decompressed mesh cannot be retained for frame captures");
+        }
+
+        let mesh = Self::from_parts(share, info, buffer, allocation, required_size, vertex_size, data.index_data_size as vk::DeviceSize, quad_index_buffer, None);
+
+        tasks.push(WorkerTask::DecompressGlobalMesh(GlobalMeshDecompress {
+            after_pass: PassId::from_raw(0),
+            staging_allocation,
+            staging_range: (staging.offset, payload_size),
+            staging_buffer: staging.buffer,
+            dst_mesh: mesh.clone(),
+            regions,
+        }, true));
+
+        Ok(mesh)
+    }
+
+    fn from_parts(share: Arc<Share>, info: &MeshData, buffer: vk::Buffer, allocation: Allocation, buffer_size: vk::DeviceSize, index_offset: vk::DeviceSize, index_size: vk::DeviceSize, quad_index_buffer: &QuadIndexBuffer, capture_data: Option<Box<CapturedMesh>>) -> Arc<Self> {
+        // The info may not contain any data so we cannot use MeshData::uses_quad_indices
+        let quad_indices = if index_size == 0 && info.index_count != 0 {
+            Some(quad_index_buffer.get(&share, info.index_count / 6))
+        } else {
+            None
+        };
+
+        let vertex_address = share.get_device().get_buffer_device_address(buffer);
+
+        let draw_info = if let Some(quad_indices) = &quad_indices {
+            GlobalMeshDrawInfo {
+                buffer,
+                vertex_address,
+                index_buffer: quad_indices.get_buffer_handle(),
+                first_index: 0,
+                index_type: vk::IndexType::UINT32,
+                index_count: info.index_count,
+                primitive_topology: info.primitive_topology
+            }
+        } else {
+            GlobalMeshDrawInfo {
+                buffer,
+                vertex_address,
+                index_buffer: buffer,
+                first_index: (index_offset / (info.get_index_size() as vk::DeviceSize)) as u32,
+                index_type: info.index_type,
+                index_count: info.index_count,
+                primitive_topology: info.primitive_topology
+            }
+        };
+
+        let id = GlobalMeshId::from_uuid(share.create_id());
+        Arc::new_cyclic(|weak| GlobalMesh {
+            share,
+            weak: weak.clone(),
+            id,
+
+            last_used_pass: AtomicU64::new(0),
+
+            buffer,
+            allocation,
+            buffer_size,
+
+            draw_info,
+
+            quad_indices,
+
+            capture_data,
+        })
+    }
+
     pub(super) fn update_used_in(&self, pass: PassId) {
         let pass = pass.get_raw();
         loop {
@@ -112,6 +334,85 @@ impl GlobalMesh {
         }
     }
 
+    pub fn get_id(&self) -> GlobalMeshId {
+        self.id
+    }
+
+    /// The maximum number of bytes which can be written by [`GlobalMesh::update`].
+    pub const MAX_UPDATE_SIZE: usize = 65536;
+
+    /// Fills `size` bytes of the mesh buffer starting at `offset` with the repeated 4 byte `data`
+    /// without requiring staging memory. The buffer contains the vertex data followed by the index
+    /// data. Both `offset` and `size` must be multiples of 4. Like other global object updates
+    /// this is executed after all passes which have used this mesh so far. Data retained for frame
+    /// captures is not updated.
+    pub fn fill(&self, offset: vk::DeviceSize, size: vk::DeviceSize, data: u32) {
+        let mut tasks = Vec::with_capacity(1);
+        self.fill_batched(offset, size, data, &mut tasks);
+        self.share.push_tasks(tasks);
+    }
+
+    /// Writes a small amount of data into the mesh buffer starting at `offset` without requiring
+    /// staging memory. `offset` and the data length must be multiples of 4 and the data must not
+    /// be larger than [`GlobalMesh::MAX_UPDATE_SIZE`]. See [`GlobalMesh::fill`].
+    pub fn update(&self, offset: vk::DeviceSize, data: &[u8]) {
+        let mut tasks = Vec::with_capacity(1);
+        self.update_batched(offset, data, &mut tasks);
+        self.share.push_tasks(tasks);
+    }
+
+    /// Like [`GlobalMesh::fill`] but collects the task into `tasks` instead of pushing it.
+    pub(super) fn fill_batched(&self, offset: vk::DeviceSize, size: vk::DeviceSize, data: u32, tasks: &mut Vec<WorkerTask>) {
+        self.validate_range(offset, size);
+        if size == 0 {
+            return;
+        }
+
+        tasks.push(WorkerTask::FillGlobalMesh(GlobalMeshFill {
+            after_pass: PassId::from_raw(self.last_used_pass.load(std::sync::atomic::Ordering::Acquire)),
+            dst_mesh: self.weak.upgrade().unwrap(),
+            offset,
+            size,
+            data
+        }));
+    }
+
+    /// Like [`GlobalMesh::update`] but collects the task into `tasks` instead of pushing it.
+    pub(super) fn update_batched(&self, offset: vk::DeviceSize, data: &[u8], tasks: &mut Vec<WorkerTask>) {
+        if data.len() > Self::MAX_UPDATE_SIZE {
+            log::error!("Global mesh update of {} bytes exceeds the maximum of {} bytes", data.len(), Self::MAX_UPDATE_SIZE);
+            panic!()
+        }
+        self.validate_range(offset, data.len() as vk::DeviceSize);
+        if data.is_empty() {
+            return;
+        }
+        self.share.record_upload(data.len() as u64);
+
+        tasks.push(WorkerTask::UpdateGlobalMesh(GlobalMeshUpdate {
+            after_pass: PassId::from_raw(self.last_used_pass.load(std::sync::atomic::Ordering::Acquire)),
+            dst_mesh: self.weak.upgrade().unwrap(),
+            offset,
+            data: data.into()
+        }));
+    }
+
+    fn validate_range(&self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        if offset % 4 != 0 || size % 4 != 0 {
+            log::error!("Global mesh write offset {} and size {} must be multiples of 4", offset, size);
+            panic!()
+        }
+        if offset.checked_add(size).map_or(true, |end| end > self.buffer_size) {
+            log::error!("Global mesh write of {} bytes at offset {} exceeds buffer size {}", size, offset, self.buffer_size);
+            panic!()
+        }
+    }
+
+    /// Returns the mesh data if it was retained for frame captures when this mesh was created.
+    pub(super) fn get_capture_data(&self) -> Option<&CapturedMesh> {
+        self.capture_data.as_deref()
+    }
+
     pub(super) fn get_buffer_handle(&self) -> vk::Buffer {
         self.buffer
     }
@@ -121,11 +422,27 @@ impl GlobalMesh {
     }
 
     fn create_buffer(device: &DeviceContext, size: vk::DeviceSize) -> Result<(vk::Buffer, Allocation), GlobalObjectCreateError> {
+        let mut usage = vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER;
+        if device.buffer_device_address_khr().is_some() {
+            usage |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        }
+
         let info = vk::BufferCreateInfo::builder()
             .size(size)
-            .usage(vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER)
+            .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
+        // Initial uploads are copied on the async transfer queues if available
+        let queue_families;
+        let info = match device.get_async_transfer_queue() {
+            Some(transfer_queue) => {
+                queue_families = [device.get_main_queue().get_queue_family_index(), transfer_queue.get_queue_family_index()];
+                info.sharing_mode(vk::SharingMode::CONCURRENT)
+                    .queue_family_indices(&queue_families)
+            }
+            None => info,
+        };
+
         unsafe {
             device.get_allocator().create_gpu_buffer(&info, &format_args!("GlobalBuffer"))
         }.ok_or(GlobalObjectCreateError::Allocation)
@@ -169,12 +486,26 @@ impl Drop for GlobalMesh {
 
 pub(super) struct GlobalMeshDrawInfo {
     pub(super) buffer: vk::Buffer,
+    /// The device address of the vertex data. [`None`] if buffer device addresses are not
+    /// supported by the device.
+    pub(super) vertex_address: Option<vk::DeviceAddress>,
+    /// Either the same as buffer or the shared quad index buffer.
+    pub(super) index_buffer: vk::Buffer,
     pub(super) first_index: u32,
     pub(super) index_count: u32,
     pub(super) index_type: vk::IndexType,
     pub(super) primitive_topology: vk::PrimitiveTopology,
 }
 
+/// A region copied between two global images. See [`GlobalImage::copy_regions_from`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ImageCopyRegion {
+    pub mip_level: u32,
+    pub src_offset: Vec2u32,
+    pub dst_offset: Vec2u32,
+    pub extent: Vec2u32,
+}
+
 pub struct ImageData<'a> {
     /// The image data
     pub data: &'a [u8],
@@ -229,6 +560,16 @@ impl<'a> ImageData<'a> {
 
 define_uuid_type!(pub, GlobalImageId);
 
+/// Configures how the mip levels of a [`GlobalImage`] are generated.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum MipmapMode {
+    /// Every level is a linear blit of the previous level.
+    Blit,
+    /// Every level is generated by a compute pass averaging the previous level with the color
+    /// weighted by alpha. Only supports [`Format::R8G8B8A8_UNORM`] and [`Format::R8G8B8A8_SRGB`].
+    AlphaWeighted,
+}
+
 pub struct GlobalImage {
     weak: Weak<Self>,
     share: Arc<Share>,
@@ -241,18 +582,36 @@ pub struct GlobalImage {
     allocation: Allocation,
     size: Vec2u32,
     mip_levels: u32,
+    mipmap_mode: MipmapMode,
+    format: &'static Format,
+
+    /// Storage views of every mip level. Only used for [`MipmapMode::AlphaWeighted`].
+    mip_storage_views: Box<[vk::ImageView]>,
 
     sampler_database: Mutex<HashMap<SamplerInfo, vk::Sampler>>,
 }
 
 impl GlobalImage {
-    pub(super) fn new(share: Arc<Share>, size: Vec2u32, mip_levels: u32, format: &'static Format) -> Result<Arc<Self>, GlobalObjectCreateError> {
-        let (image, allocation, sampler_view) = Self::create_image(share.get_device(), format.into(), size, mip_levels)?;
+    pub(super) fn new(share: Arc<Share>, size: Vec2u32, mip_levels: u32, mipmap_mode: MipmapMode, format: &'static Format) -> Result<Arc<Self>, GlobalObjectCreateError> {
+        if mipmap_mode == MipmapMode::AlphaWeighted && format != &Format::R8G8B8A8_UNORM && format != &Format::R8G8B8A8_SRGB {
+            return Err(GlobalObjectCreateError::UnsupportedFormat);
+        }
+
+        let (image, allocation, sampler_view, mip_storage_views) = Self::create_image(share.get_device(), format.into(), size, mip_levels, mipmap_mode)?;
 
+        // Images generating their mipmaps on the async compute queue are shared concurrently
+        let device = share.get_device();
+        let queue_family = match device.get_async_compute_queue() {
+            Some(_) if mipmap_mode == MipmapMode::AlphaWeighted => vk::QUEUE_FAMILY_IGNORED,
+            _ => device.get_main_queue().get_queue_family_index(),
+        };
+        device.get_image_layouts().register(image, vk::ImageAspectFlags::COLOR, mip_levels, queue_family);
+
+        let id = GlobalImageId::from_uuid(share.create_id());
         let image = Arc::new_cyclic(|weak| GlobalImage {
             weak: weak.clone(),
             share,
-            id: GlobalImageId::new(),
+            id,
 
             last_used_pass: AtomicU64::new(0),
 
@@ -261,6 +620,10 @@ impl GlobalImage {
             allocation,
             size,
             mip_levels,
+            mipmap_mode,
+            format,
+
+            mip_storage_views,
 
             sampler_database: Mutex::new(HashMap::new())
         });
@@ -269,7 +632,7 @@ impl GlobalImage {
             after_pass: PassId::from_raw(0),
             clear_value: format.get_clear_color_type().unwrap().make_zero_clear(),
             dst_image: image.clone()
-        }, true));
+        }));
 
         Ok(image)
     }
@@ -295,25 +658,75 @@ impl GlobalImage {
         self.size
     }
 
+    pub fn get_format(&self) -> &'static Format {
+        self.format
+    }
+
     pub fn update_regions(&self, regions: &[ImageData]) {
+        let regions: Vec<_> = regions.iter().map(|region| (0, region)).collect();
+        self.update_mip_regions(&regions);
+    }
+
+    /// Writes regions of arbitrary mip levels. Every region is paired with the mip level it is
+    /// written to. All regions share a single staging allocation.
+    pub fn update_mip_regions(&self, regions: &[(u32, &ImageData)]) {
+        let mut tasks = Vec::with_capacity(1);
+        self.update_mip_regions_batched(regions, &mut tasks);
+        self.share.push_tasks(tasks);
+    }
+
+    /// Like [`GlobalImage::update_mip_regions`] but validates every region against the block
+    /// layout of the image format first. Nothing is written if any region is invalid.
+    pub fn try_update_mip_regions(&self, regions: &[(u32, &ImageData)]) -> Result<(), RegionError> {
+        self.validate_regions(regions)?;
+        self.update_mip_regions(regions);
+        Ok(())
+    }
+
+    /// Validates that all regions can be copied into the image. Offsets and extents of block
+    /// compressed formats must be aligned to the block extent unless they reach the edge of the mip
+    /// level.
+    pub fn validate_regions(&self, regions: &[(u32, &ImageData)]) -> Result<(), RegionError> {
+        let layout = BlockLayout::for_format(self.format.get_format()).ok_or(RegionError::UnsupportedFormat(self.format.get_format()))?;
+        for (mip_level, region) in regions {
+            if *mip_level >= self.mip_levels {
+                return Err(RegionError::MipLevelOutOfRange(*mip_level));
+            }
+            layout.validate_region(get_mip_size(self.size, *mip_level), region)?;
+        }
+        Ok(())
+    }
+
+    /// Like [`GlobalImage::update_mip_regions`] but collects the upload task into `tasks` instead
+    /// of pushing it.
+    pub(super) fn update_mip_regions_batched(&self, regions: &[(u32, &ImageData)], tasks: &mut Vec<WorkerTask>) {
         if regions.is_empty() {
             return;
         }
 
-        let required_memory = regions.iter().map(|r| r.data.len()).sum::<usize>() as u64;
+        // Offsets into the staging buffer must be a multiple of the texel block size of the format
+        const REGION_ALIGNMENT: u64 = 16;
+        let aligned_size = |size: u64| (size + REGION_ALIGNMENT - 1) & !(REGION_ALIGNMENT - 1);
 
-        let (staging, allocation) = self.share.get_staging_pool().lock().unwrap().allocate(required_memory as u64, 1);
+        let required_memory = regions.iter().map(|(_, r)| aligned_size(r.data.len() as u64)).sum::<u64>();
+
+        let (mut staging, allocation) = self.share.allocate_staging_batched(required_memory, REGION_ALIGNMENT, tasks);
 
         let mut copies = Vec::with_capacity(regions.len());
         let mut current_offset = 0;
-        for region in regions {
+        for (mip_level, region) in regions {
+            if *mip_level >= self.mip_levels {
+                log::error!("Attempted to write mip level {} of global image with {} mip levels", mip_level, self.mip_levels);
+                panic!()
+            }
+
             copies.push(vk::BufferImageCopy {
                 buffer_offset: staging.offset + current_offset,
                 buffer_row_length: region.row_stride,
                 buffer_image_height: 0,
                 image_subresource: vk::ImageSubresourceLayers {
                     aspect_mask: vk::ImageAspectFlags::COLOR,
-                    mip_level: 0,
+                    mip_level: *mip_level,
                     base_array_layer: 0,
                     layer_count: 1
                 },
@@ -325,24 +738,170 @@ impl GlobalImage {
                 }
             });
 
-            unsafe {
-                let mapped = std::slice::from_raw_parts_mut(staging.mapped.as_ptr().offset(current_offset as isize), region.data.len());
-                mapped.copy_from_slice(region.data);
-            }
+            staging.write_bytes(current_offset as usize, region.data);
 
-            current_offset += region.data.len() as u64;
+            current_offset += aligned_size(region.data.len() as u64);
         }
+        staging.flush(self.share.get_device());
 
-        self.share.push_task(WorkerTask::WriteGlobalImage(GlobalImageWrite {
+        tasks.push(self.create_write_task(ImageWriteStaging::Pool(allocation), staging.buffer, (staging.offset, required_memory), copies.into_boxed_slice()));
+    }
+
+    /// Pushes a write of staging memory which has already been filled into the image.
+    pub(super) fn push_write(&self, staging: ImageWriteStaging, staging_buffer: vk::Buffer, staging_range: (vk::DeviceSize, vk::DeviceSize), regions: Box<[vk::BufferImageCopy]>) {
+        self.share.push_task(self.create_write_task(staging, staging_buffer, staging_range, regions));
+    }
+
+    fn create_write_task(&self, staging: ImageWriteStaging, staging_buffer: vk::Buffer, staging_range: (vk::DeviceSize, vk::DeviceSize), regions: Box<[vk::BufferImageCopy]>) -> WorkerTask {
+        self.share.record_upload(staging_range.1);
+
+        WorkerTask::WriteGlobalImage(GlobalImageWrite {
             after_pass: PassId::from_raw(self.last_used_pass.load(std::sync::atomic::Ordering::Acquire)),
-            staging_allocation: allocation,
-            staging_range: (staging.offset, required_memory),
-            staging_buffer: staging.buffer,
+            staging_allocation: staging,
+            staging_range,
+            staging_buffer,
+            dst_image: self.weak.upgrade().unwrap(),
+            regions
+        })
+    }
+
+    /// Copies a mip level of this image into host memory. The copy is executed after all passes
+    /// which have used this image so far and after all previous writes to this image. Like other
+    /// global object updates the copy is submitted together with the next pass.
+    ///
+    /// If the format of the image does not support reading back the returned handle completes
+    /// without any data.
+    pub fn read_back(&self, mip_level: u32) -> GlobalImageReadback {
+        if mip_level >= self.mip_levels {
+            log::error!("Attempted to read back mip level {} of global image with {} mip levels", mip_level, self.mip_levels);
+            panic!()
+        }
+
+        let (sender, readback) = GlobalImageReadback::new();
+        let texel_size = match get_texel_size(self.format.get_format(), vk::ImageAspectFlags::COLOR) {
+            Some(size) => size,
+            None => {
+                log::warn!("Reading back global images of format {:?} is not supported", self.format.get_format());
+                return readback;
+            }
+        };
+
+        let size = Vec2u32::new(std::cmp::max(self.size[0] >> mip_level, 1), std::cmp::max(self.size[1] >> mip_level, 1));
+        let byte_size = (size[0] as u64) * (size[1] as u64) * (texel_size as u64);
+        let (staging, staging_allocation) = self.share.allocate_staging(byte_size, 16);
+
+        self.share.push_task(WorkerTask::ReadGlobalImage(GlobalImageRead {
+            after_pass: PassId::from_raw(self.last_used_pass.load(std::sync::atomic::Ordering::Acquire)),
+            staging,
+            staging_allocation,
+            staging_size: byte_size,
+            src_image: self.weak.upgrade().unwrap(),
+            mip_level,
+            size,
+            sender,
+        }));
+
+        readback
+    }
+
+    /// Copies regions of another image into this image on the gpu. Both images must have the same
+    /// format and must not be the same image.
+    ///
+    /// The copy is executed after all passes which have used either image so far. Following writes
+    /// to this image are executed after the copy.
+    pub fn copy_regions_from(&self, src: &Arc<GlobalImage>, regions: &[ImageCopyRegion]) {
+        if src.id == self.id {
+            log::error!("Attempted to copy global image {:?} into itself", self.id);
+            panic!()
+        }
+        if src.format != self.format {
+            log::error!("Attempted to copy global image {:?} with format {:?} into global image {:?} with format {:?}", src.id, src.format, self.id, self.format);
+            panic!()
+        }
+        if regions.is_empty() {
+            return;
+        }
+
+        let copies: Box<[_]> = regions.iter().map(|region| {
+            if region.mip_level >= self.mip_levels || region.mip_level >= src.mip_levels {
+                log::error!("Attempted to copy mip level {} between global images with {} and {} mip levels", region.mip_level, src.mip_levels, self.mip_levels);
+                panic!()
+            }
+
+            let subresource = vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: region.mip_level,
+                base_array_layer: 0,
+                layer_count: 1
+            };
+            vk::ImageCopy {
+                src_subresource: subresource,
+                src_offset: vk::Offset3D { x: region.src_offset[0] as i32, y: region.src_offset[1] as i32, z: 0 },
+                dst_subresource: subresource,
+                dst_offset: vk::Offset3D { x: region.dst_offset[0] as i32, y: region.dst_offset[1] as i32, z: 0 },
+                extent: vk::Extent3D { width: region.extent[0], height: region.extent[1], depth: 1 }
+            }
+        }).collect();
+
+        let after_pass = std::cmp::max(
+            src.last_used_pass.load(std::sync::atomic::Ordering::Acquire),
+            self.last_used_pass.load(std::sync::atomic::Ordering::Acquire)
+        );
+        // Writes are ordered by the last used pass so they must not be executed before the copy
+        self.update_used_in(PassId::from_raw(after_pass));
+
+        self.share.push_task(WorkerTask::CopyGlobalImage(GlobalImageCopy {
+            after_pass: PassId::from_raw(after_pass),
+            src_image: src.clone(),
             dst_image: self.weak.upgrade().unwrap(),
-            regions: copies.into_boxed_slice()
+            regions: copies
         }));
     }
 
+    /// Regenerates all mip levels above 0 from the contents of mip level 0.
+    pub fn generate_mipmaps(&self) {
+        self.generate_mip_levels(self.mip_levels);
+    }
+
+    /// Regenerates the mip levels `1..level_count` from the contents of mip level 0. Levels at or
+    /// above `level_count` are left untouched. `level_count` is clamped to the mip levels of the
+    /// image.
+    pub fn generate_mip_levels(&self, level_count: u32) {
+        let mut tasks = Vec::with_capacity(1);
+        self.generate_mipmaps_batched(level_count, &mut tasks);
+        self.share.push_tasks(tasks);
+    }
+
+    /// Writes regions of mip level 0 and regenerates all other mip levels from it. Both operations
+    /// are pushed together so no other update of this image can happen in between.
+    pub fn update_regions_with_mipmaps(&self, regions: &[ImageData]) {
+        let regions: Vec<_> = regions.iter().map(|region| (0, region)).collect();
+
+        let mut tasks = Vec::with_capacity(2);
+        self.update_mip_regions_batched(&regions, &mut tasks);
+        self.generate_mipmaps_batched(self.mip_levels, &mut tasks);
+        self.share.push_tasks(tasks);
+    }
+
+    /// Like [`GlobalImage::generate_mip_levels`] but collects the task into `tasks` instead of
+    /// pushing it.
+    pub(super) fn generate_mipmaps_batched(&self, level_count: u32, tasks: &mut Vec<WorkerTask>) {
+        let level_count = std::cmp::min(level_count, self.mip_levels);
+        if level_count <= 1 {
+            return;
+        }
+        if self.format.is_block_compressed() {
+            log::warn!("Attempted to generate mipmaps of block compressed global image {:?}. Mip levels must be uploaded", self.id);
+            return;
+        }
+
+        tasks.push(WorkerTask::GenerateGlobalImageMipmaps(
+            self.weak.upgrade().unwrap(),
+            level_count,
+            PassId::from_raw(self.last_used_pass.load(std::sync::atomic::Ordering::Acquire))
+        ));
+    }
+
     pub(super) fn get_image_handle(&self) -> vk::Image {
         self.image
     }
@@ -351,7 +910,15 @@ impl GlobalImage {
         self.mip_levels
     }
 
-    pub(super) fn get_sampler_view(&self) -> vk::ImageView {
+    pub fn get_mipmap_mode(&self) -> MipmapMode {
+        self.mipmap_mode
+    }
+
+    pub(super) fn get_mip_storage_view(&self, level: u32) -> vk::ImageView {
+        self.mip_storage_views[level as usize]
+    }
+
+    pub(crate) fn get_sampler_view(&self) -> vk::ImageView {
         self.sampler_view
     }
 
@@ -387,8 +954,19 @@ impl GlobalImage {
         }
     }
 
-    fn create_image(device: &DeviceContext, format: vk::Format, size: Vec2u32, mip_levels: u32) -> Result<(vk::Image, Allocation, vk::ImageView), GlobalObjectCreateError> {
+    fn create_image(device: &DeviceContext, format: vk::Format, size: Vec2u32, mip_levels: u32, mipmap_mode: MipmapMode) -> Result<(vk::Image, Allocation, vk::ImageView, Box<[vk::ImageView]>), GlobalObjectCreateError> {
+        let mut flags = vk::ImageCreateFlags::empty();
+        let mut usage = vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+        if mipmap_mode == MipmapMode::AlphaWeighted {
+            // sRGB formats generally do not support storage so mip levels are written through unorm views
+            usage |= vk::ImageUsageFlags::STORAGE;
+            if format != vk::Format::R8G8B8A8_UNORM {
+                flags |= vk::ImageCreateFlags::MUTABLE_FORMAT | vk::ImageCreateFlags::EXTENDED_USAGE;
+            }
+        }
+
         let info = vk::ImageCreateInfo::builder()
+            .flags(flags)
             .image_type(vk::ImageType::TYPE_2D)
             .format(format)
             .extent(vk::Extent3D {
@@ -400,15 +978,29 @@ impl GlobalImage {
             .array_layers(1)
             .samples(vk::SampleCountFlags::TYPE_1)
             .tiling(vk::ImageTiling::OPTIMAL)
-            .usage(vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED)
+            .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
             .initial_layout(vk::ImageLayout::UNDEFINED);
 
+        // Alpha weighted mipmaps are generated on the async compute queue if available
+        let queue_families;
+        let info = match device.get_async_compute_queue() {
+            Some(compute_queue) if mipmap_mode == MipmapMode::AlphaWeighted => {
+                queue_families = [device.get_main_queue().get_queue_family_index(), compute_queue.get_queue_family_index()];
+                info.sharing_mode(vk::SharingMode::CONCURRENT)
+                    .queue_family_indices(&queue_families)
+            }
+            _ => info,
+        };
+
         let (image, allocation) = unsafe {
             device.get_allocator().create_gpu_image(&info, &format_args!("GlobalImage"))
         }.ok_or(GlobalObjectCreateError::Allocation)?;
 
-        let info = vk::ImageViewCreateInfo::builder()
+        let mut view_usage = vk::ImageViewUsageCreateInfo::builder()
+            .usage(vk::ImageUsageFlags::SAMPLED);
+
+        let mut info = vk::ImageViewCreateInfo::builder()
             .image(image)
             .view_type(vk::ImageViewType::TYPE_2D)
             .format(format)
@@ -425,6 +1017,9 @@ impl GlobalImage {
                 base_array_layer: 0,
                 layer_count: 1
             });
+        if usage.contains(vk::ImageUsageFlags::STORAGE) {
+            info = info.push_next(&mut view_usage);
+        }
 
         let sampler_view = match unsafe {
             device.vk().create_image_view(&info, None)
@@ -437,7 +1032,42 @@ impl GlobalImage {
             }
         };
 
-        Ok((image, allocation, sampler_view))
+        let mut mip_storage_views = Vec::new();
+        if mipmap_mode == MipmapMode::AlphaWeighted {
+            for level in 0..mip_levels {
+                let info = vk::ImageViewCreateInfo::builder()
+                    .image(image)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(vk::Format::R8G8B8A8_UNORM)
+                    .components(vk::ComponentMapping::default())
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: level,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1
+                    });
+
+                match unsafe {
+                    device.vk().create_image_view(&info, None)
+                } {
+                    Ok(view) => mip_storage_views.push(view),
+                    Err(err) => {
+                        log::error!("vkCreateImageView returned {:?} in GlobalImage::create_image when creating mip storage view", err);
+                        unsafe {
+                            for view in mip_storage_views {
+                                device.vk().destroy_image_view(view, None);
+                            }
+                            device.vk().destroy_image_view(sampler_view, None);
+                            device.get_allocator().destroy_image(image, allocation);
+                        }
+                        return Err(GlobalObjectCreateError::Vulkan(err));
+                    }
+                }
+            }
+        }
+
+        Ok((image, allocation, sampler_view, mip_storage_views.into_boxed_slice()))
     }
 }
 
@@ -472,7 +1102,11 @@ impl Drop for GlobalImage {
     fn drop(&mut self) {
         let device = self.share.get_device();
         unsafe {
+            for view in self.mip_storage_views.iter() {
+                device.vk().destroy_image_view(*view, None);
+            }
             device.vk().destroy_image_view(self.sampler_view, None);
+            device.get_image_layouts().unregister(self.image);
             device.get_allocator().destroy_image(self.image, self.allocation);
         }
     }
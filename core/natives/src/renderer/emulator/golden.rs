@@ -0,0 +1,294 @@
+//! Golden image comparison for rendering regression tests.
+//!
+//! Replayed images are compared against stored png references using the CIE76 color difference of
+//! every pixel. Small differences caused by driver or hardware specific rasterization are tolerated
+//! as long as only a small fraction of pixels differ noticeably.
+//!
+//! References are only written if the `B4D_UPDATE_GOLDEN` environment variable is set. This
+//! creates missing references and overwrites all existing ones. Without it a missing reference is
+//! reported as [`GoldenResult::Missing`] so new references are never accepted without review.
+
+use std::path::{Path, PathBuf};
+
+use crate::renderer::emulator::replay::ReplayImage;
+
+use crate::prelude::*;
+
+/// The environment variable which allows references to be created or overwritten.
+pub const UPDATE_GOLDEN_ENV: &str = "B4D_UPDATE_GOLDEN";
+
+/// Returns true if [`UPDATE_GOLDEN_ENV`] is set.
+pub fn is_update_requested() -> bool {
+    std::env::var_os(UPDATE_GOLDEN_ENV).is_some()
+}
+
+#[derive(Debug)]
+pub enum GoldenError {
+    Io(std::io::Error),
+    PngDecoding(png::DecodingError),
+    PngEncoding(png::EncodingError),
+    UnsupportedReference(&'static str),
+    SizeMismatch(Vec2u32, Vec2u32),
+}
+
+impl From<std::io::Error> for GoldenError {
+    fn from(err: std::io::Error) -> Self {
+        GoldenError::Io(err)
+    }
+}
+
+impl From<png::DecodingError> for GoldenError {
+    fn from(err: png::DecodingError) -> Self {
+        GoldenError::PngDecoding(err)
+    }
+}
+
+impl From<png::EncodingError> for GoldenError {
+    fn from(err: png::EncodingError) -> Self {
+        GoldenError::PngEncoding(err)
+    }
+}
+
+/// Configures how much 2 images may differ while still being considered equal.
+#[derive(Copy, Clone, Debug)]
+pub struct GoldenTolerance {
+    /// The maximum color difference (CIE76 delta E) of a single pixel before it is considered
+    /// mismatched. A value of about 2.3 corresponds to a just noticeable difference. Alpha
+    /// differences are scaled to the same range.
+    pub max_delta_e: f32,
+
+    /// The maximum fraction of mismatched pixels.
+    pub max_mismatch_fraction: f32,
+}
+
+impl Default for GoldenTolerance {
+    fn default() -> Self {
+        Self {
+            max_delta_e: 2.3,
+            max_mismatch_fraction: 0.001,
+        }
+    }
+}
+
+/// The result of comparing 2 images.
+pub struct ImageDifference {
+    pub size: Vec2u32,
+    pub mismatched_pixels: usize,
+    pub max_delta_e: f32,
+
+    /// A R8G8B8A8 image with mismatched pixels marked red and all other pixels as a faded
+    /// greyscale version of the reference.
+    pub diff_image: Box<[u8]>,
+}
+
+impl ImageDifference {
+    pub fn get_mismatch_fraction(&self) -> f32 {
+        let total = (self.size[0] as usize) * (self.size[1] as usize);
+        if total == 0 {
+            0.0
+        } else {
+            self.mismatched_pixels as f32 / total as f32
+        }
+    }
+
+    pub fn is_within(&self, tolerance: &GoldenTolerance) -> bool {
+        self.get_mismatch_fraction() <= tolerance.max_mismatch_fraction
+    }
+}
+
+#[derive(Debug)]
+pub enum GoldenResult {
+    /// The image matches the reference.
+    Match,
+    /// The image does not match the reference. The actual and diff images have been written next
+    /// to the reference.
+    Mismatch {
+        mismatch_fraction: f32,
+        max_delta_e: f32,
+        actual_path: PathBuf,
+        diff_path: PathBuf,
+    },
+    /// No reference exists and no update was requested. The actual image has been written next to
+    /// the missing reference.
+    Missing {
+        actual_path: PathBuf,
+    },
+    /// An update was requested. The image has been written as the new reference.
+    Created(PathBuf),
+}
+
+/// Compares 2 tightly packed R8G8B8A8 sRGB images.
+pub fn compare_images(reference: &ReplayImage, actual: &ReplayImage, tolerance: &GoldenTolerance) -> Result<ImageDifference, GoldenError> {
+    if reference.size != actual.size {
+        return Err(GoldenError::SizeMismatch(reference.size, actual.size));
+    }
+
+    let mut mismatched_pixels = 0usize;
+    let mut max_delta_e = 0f32;
+    let mut diff_image = Vec::with_capacity(reference.data.len());
+
+    for (r, a) in reference.data.chunks_exact(4).zip(actual.data.chunks_exact(4)) {
+        let lab_r = srgb_to_lab(r);
+        let lab_a = srgb_to_lab(a);
+        let color_delta = ((lab_r[0] - lab_a[0]).powi(2) + (lab_r[1] - lab_a[1]).powi(2) + (lab_r[2] - lab_a[2]).powi(2)).sqrt();
+        let alpha_delta = ((r[3] as f32) - (a[3] as f32)).abs() * (100.0 / 255.0);
+        let delta_e = color_delta.max(alpha_delta);
+
+        max_delta_e = max_delta_e.max(delta_e);
+        if delta_e > tolerance.max_delta_e {
+            mismatched_pixels += 1;
+            diff_image.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let grey = (lab_r[0] * 2.55 * 0.25) as u8;
+            diff_image.extend_from_slice(&[grey, grey, grey, 255]);
+        }
+    }
+
+    Ok(ImageDifference {
+        size: reference.size,
+        mismatched_pixels,
+        max_delta_e,
+        diff_image: diff_image.into_boxed_slice(),
+    })
+}
+
+/// Compares a image against the reference `name.png` in `dir`.
+///
+/// If [`UPDATE_GOLDEN_ENV`] is set the image is written as the new reference. If the reference does
+/// not exist the actual image is written as `name.actual.png`. On mismatch the actual image and a
+/// diff image are written as `name.actual.png` and `name.diff.png`.
+pub fn check_golden(dir: &Path, name: &str, actual: &ReplayImage, tolerance: &GoldenTolerance) -> Result<GoldenResult, GoldenError> {
+    let reference_path = dir.join(format!("{}.png", name));
+
+    if is_update_requested() {
+        std::fs::create_dir_all(dir)?;
+        write_png(&reference_path, actual.size, &actual.data)?;
+        return Ok(GoldenResult::Created(reference_path));
+    }
+
+    let actual_path = dir.join(format!("{}.actual.png", name));
+    if !reference_path.exists() {
+        std::fs::create_dir_all(dir)?;
+        write_png(&actual_path, actual.size, &actual.data)?;
+        return Ok(GoldenResult::Missing { actual_path });
+    }
+
+    let reference = read_png(&reference_path)?;
+    let difference = compare_images(&reference, actual, tolerance)?;
+    if difference.is_within(tolerance) {
+        return Ok(GoldenResult::Match);
+    }
+
+    let diff_path = dir.join(format!("{}.diff.png", name));
+    write_png(&actual_path, actual.size, &actual.data)?;
+    write_png(&diff_path, difference.size, &difference.diff_image)?;
+
+    Ok(GoldenResult::Mismatch {
+        mismatch_fraction: difference.get_mismatch_fraction(),
+        max_delta_e: difference.max_delta_e,
+        actual_path,
+        diff_path
+    })
+}
+
+/// Reads a 8 bit RGBA or RGB png file.
+pub fn read_png(path: &Path) -> Result<ReplayImage, GoldenError> {
+    let decoder = png::Decoder::new(std::io::BufReader::new(std::fs::File::open(path)?));
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0u8; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+
+    if info.bit_depth != png::BitDepth::Eight {
+        return Err(GoldenError::UnsupportedReference("Reference bit depth must be 8"));
+    }
+
+    let data = &data[0..info.buffer_size()];
+    let data: Box<[u8]> = match info.color_type {
+        png::ColorType::Rgba => data.into(),
+        png::ColorType::Rgb => data.chunks_exact(3).flat_map(|texel| [texel[0], texel[1], texel[2], 255u8]).collect(),
+        _ => return Err(GoldenError::UnsupportedReference("Reference must be a RGB or RGBA image")),
+    };
+
+    Ok(ReplayImage {
+        size: Vec2u32::new(info.width, info.height),
+        data
+    })
+}
+
+/// Writes a tightly packed R8G8B8A8 image as a png file.
+pub fn write_png(path: &Path, size: Vec2u32, data: &[u8]) -> Result<(), GoldenError> {
+    let file = std::fs::File::create(path)?;
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), size[0], size[1]);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(data)?;
+    Ok(())
+}
+
+fn srgb_to_lab(texel: &[u8]) -> [f32; 3] {
+    let linear = |c: u8| {
+        let c = (c as f32) / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(texel[0]), linear(texel[1]), linear(texel[2]));
+
+    // sRGB to XYZ normalized to the D65 white point
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| {
+        if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_image(size: u32, texel: [u8; 4]) -> ReplayImage {
+        ReplayImage {
+            size: Vec2u32::new(size, size),
+            data: std::iter::repeat(texel).take((size * size) as usize).flatten().collect(),
+        }
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = make_image(16, [120, 40, 200, 255]);
+        let difference = compare_images(&image, &image, &GoldenTolerance::default()).unwrap();
+        assert_eq!(difference.mismatched_pixels, 0);
+        assert_eq!(difference.max_delta_e, 0.0);
+    }
+
+    #[test]
+    fn small_differences_are_tolerated() {
+        let reference = make_image(16, [120, 40, 200, 255]);
+        let actual = make_image(16, [121, 40, 200, 255]);
+        let difference = compare_images(&reference, &actual, &GoldenTolerance::default()).unwrap();
+        assert!(difference.is_within(&GoldenTolerance::default()));
+    }
+
+    #[test]
+    fn large_differences_mismatch() {
+        let reference = make_image(16, [120, 40, 200, 255]);
+        let mut actual = make_image(16, [120, 40, 200, 255]);
+        actual.data[0..4].copy_from_slice(&[0, 255, 0, 255]);
+
+        let tolerance = GoldenTolerance::default();
+        let difference = compare_images(&reference, &actual, &tolerance).unwrap();
+        assert_eq!(difference.mismatched_pixels, 1);
+        assert!(!difference.is_within(&tolerance));
+        assert_eq!(&difference.diff_image[0..4], &[255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn size_mismatch() {
+        let reference = make_image(16, [0, 0, 0, 255]);
+        let actual = make_image(8, [0, 0, 0, 255]);
+        assert!(matches!(compare_images(&reference, &actual, &GoldenTolerance::default()), Err(GoldenError::SizeMismatch(_, _))));
+    }
+}
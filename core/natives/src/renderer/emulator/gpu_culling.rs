@@ -0,0 +1,303 @@
+//! Gpu driven frustum culling of the sections of a global mesh.
+//!
+//! A chunk mesh is usually stored in a single [`GlobalMesh`](super::GlobalMesh) made up of many
+//! sections, each with its own bounding box. Using
+//! [`DrawRecorder::draw_global_culled`](super::DrawRecorder::draw_global_culled) the sections are
+//! tested against the view frustum by a compute shader before the render pass starts. The shader
+//! writes one indexed indirect draw command for every visible section which are then drawn without
+//! the cpu ever reading back the result.
+//!
+//! If `VK_KHR_draw_indirect_count` is supported the visible commands are compacted and drawn using
+//! a single draw call. Otherwise every section is drawn individually and culled sections draw zero
+//! instances.
+
+use std::ffi::CStr;
+use std::sync::Arc;
+
+use ash::vk;
+use bytemuck::{bytes_of, cast_slice, Pod, Zeroable};
+use include_bytes_aligned::include_bytes_aligned;
+
+use crate::device::device_utils::create_shader_from_bytes;
+use crate::renderer::emulator::immediate::ImmediateBuffer;
+use crate::renderer::emulator::pipeline::CulledDrawTask;
+use crate::renderer::emulator::share::Share;
+
+use crate::prelude::*;
+
+/// The number of sections culled by each workgroup of the culling shader.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Storage buffer offsets must be aligned to minStorageBufferOffsetAlignment. 256 is the highest
+/// value in the gpuinfo database.
+const SECTION_BUFFER_ALIGNMENT: vk::DeviceSize = 256;
+
+/// The size of a `VkDrawIndexedIndirectCommand` in bytes.
+pub(super) const DRAW_COMMAND_STRIDE: u32 = 20;
+
+/// The offset of the first draw command in the indirect buffer. The commands are preceded by the
+/// number of visible sections.
+pub(super) const DRAW_COMMAND_OFFSET: vk::DeviceSize = 4;
+
+/// A range of the indices of a global mesh which is culled as a whole.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CulledSection {
+    /// The first index of the section relative to the first index of the mesh.
+    pub first_index: u32,
+    pub index_count: u32,
+    pub vertex_offset: i32,
+
+    /// The bounding box of the section in model space.
+    pub bounds_min: Vec3f32,
+    pub bounds_max: Vec3f32,
+}
+
+impl CulledSection {
+    fn to_gpu(&self, mesh_first_index: u32) -> GpuSection {
+        GpuSection {
+            bounds_min: [self.bounds_min[0], self.bounds_min[1], self.bounds_min[2]],
+            first_index: mesh_first_index + self.first_index,
+            bounds_max: [self.bounds_max[0], self.bounds_max[1], self.bounds_max[2]],
+            index_count: self.index_count,
+            vertex_offset: self.vertex_offset,
+            _padding: [0; 3],
+        }
+    }
+}
+
+/// Returns the size in bytes of the indirect buffer written by the culling shader for the number
+/// of sections.
+pub(super) fn get_indirect_buffer_size(section_count: u32) -> vk::DeviceSize {
+    DRAW_COMMAND_OFFSET + (section_count as vk::DeviceSize) * (DRAW_COMMAND_STRIDE as vk::DeviceSize)
+}
+
+/// Uploads the sections into the immediate buffer and reserves the indirect buffer written by the
+/// culling shader. Returns the section buffer and the indirect buffer.
+pub(super) fn upload_sections(immediate: &mut ImmediateBuffer, share: &Share, sections: &[CulledSection], mesh_first_index: u32) -> ((vk::Buffer, vk::DeviceSize), (vk::Buffer, vk::DeviceSize)) {
+    let data: Vec<GpuSection> = sections.iter().map(|section| section.to_gpu(mesh_first_index)).collect();
+    let bytes: &[u8] = cast_slice(&data);
+
+    let section_buffer = immediate.allocate(bytes, SECTION_BUFFER_ALIGNMENT);
+    share.record_upload(bytes.len() as u64);
+
+    let indirect_buffer = immediate.reserve(get_indirect_buffer_size(sections.len() as u32), SECTION_BUFFER_ALIGNMENT);
+
+    (section_buffer, indirect_buffer)
+}
+
+/// Compute pipeline writing the draw commands of the visible sections.
+pub(super) struct SectionCullingPipeline {
+    device: Arc<DeviceContext>,
+
+    /// Owned by the layout cache of the device.
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl SectionCullingPipeline {
+    pub(super) fn new(device: Arc<DeviceContext>) -> Result<Self, vk::Result> {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                p_immutable_samplers: std::ptr::null(),
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                p_immutable_samplers: std::ptr::null(),
+            },
+        ];
+
+        let layout_cache = device.get_utils().layout_cache();
+
+        let set_layout = layout_cache.get_descriptor_set_layout(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR, &bindings).map_err(|err| {
+            log::error!("vkCreateDescriptorSetLayout returned {:?} in SectionCullingPipeline::new", err);
+            err
+        })?;
+
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: std::mem::size_of::<PushConstants>() as u32,
+        };
+
+        let pipeline_layout = layout_cache.get_pipeline_layout(std::slice::from_ref(&set_layout), std::slice::from_ref(&push_constant_range)).map_err(|err| {
+            log::error!("vkCreatePipelineLayout returned {:?} in SectionCullingPipeline::new", err);
+            err
+        })?;
+
+        let module = create_shader_from_bytes(device.get_functions(), SECTION_CULLING_COMPUTE_BIN).map_err(|err| {
+            log::error!("vkCreateShaderModule returned {:?} in SectionCullingPipeline::new", err);
+            err
+        })?;
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(SHADER_ENTRY);
+
+        let info = vk::ComputePipelineCreateInfo::builder()
+            .stage(*stage)
+            .layout(pipeline_layout);
+
+        let pipeline = unsafe {
+            device.vk().create_compute_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), None)
+        };
+        unsafe { device.vk().destroy_shader_module(module, None) };
+
+        let pipeline = pipeline.map_err(|(_, err)| {
+            log::error!("vkCreateComputePipelines returned {:?} in SectionCullingPipeline::new", err);
+            err
+        })?[0];
+
+        Ok(Self {
+            device,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    /// Records the culling of the sections of the task followed by a barrier making the draw
+    /// commands available to indirect draws. Must be recorded outside of a render pass and after
+    /// the copy commands of the immediate buffers. If `compact` is set the visible draw commands
+    /// are written to the start of the indirect buffer and counted, otherwise a command is written
+    /// for every section.
+    pub(super) fn record(&self, cmd: vk::CommandBuffer, task: &CulledDrawTask, compact: bool) {
+        let section_info = vk::DescriptorBufferInfo {
+            buffer: task.section_buffer,
+            offset: task.section_offset,
+            range: (task.section_count as vk::DeviceSize) * (std::mem::size_of::<GpuSection>() as vk::DeviceSize),
+        };
+        let indirect_info = vk::DescriptorBufferInfo {
+            buffer: task.indirect_buffer,
+            offset: task.indirect_offset,
+            range: get_indirect_buffer_size(task.section_count),
+        };
+
+        let writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(std::slice::from_ref(&section_info))
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(std::slice::from_ref(&indirect_info))
+                .build(),
+        ];
+
+        let mut view_projection = [0f32; 16];
+        view_projection.copy_from_slice(task.view_projection.as_slice());
+        let push_constants = PushConstants {
+            view_projection,
+            section_count: task.section_count,
+            compact: compact as u32,
+            _padding: [0; 2],
+        };
+
+        // The staging copies of the immediate buffers and the count reset must complete before the
+        // shader reads the sections and increments the count
+        let pre_barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE);
+        let pre_dependency_info = vk::DependencyInfo::builder()
+            .memory_barriers(std::slice::from_ref(&pre_barrier));
+
+        let post_barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::DRAW_INDIRECT)
+            .dst_access_mask(vk::AccessFlags2::INDIRECT_COMMAND_READ);
+        let post_dependency_info = vk::DependencyInfo::builder()
+            .memory_barriers(std::slice::from_ref(&post_barrier));
+
+        unsafe {
+            if compact {
+                self.device.vk().cmd_fill_buffer(cmd, task.indirect_buffer, task.indirect_offset, DRAW_COMMAND_OFFSET, 0);
+            }
+            self.device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &pre_dependency_info);
+
+            self.device.vk().cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            self.device.push_descriptor_khr().cmd_push_descriptor_set(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline_layout, 0, &writes);
+            self.device.vk().cmd_push_constants(cmd, self.pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, bytes_of(&push_constants));
+            self.device.vk().cmd_dispatch(cmd, (task.section_count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE, 1, 1);
+
+            self.device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &post_dependency_info);
+        }
+    }
+}
+
+impl Drop for SectionCullingPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.vk().destroy_pipeline(self.pipeline, None);
+        }
+    }
+}
+
+/// Matches the std430 layout of the `Section` struct of the culling shader.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GpuSection {
+    bounds_min: [f32; 3],
+    first_index: u32,
+    bounds_max: [f32; 3],
+    index_count: u32,
+    vertex_offset: i32,
+    _padding: [u32; 3],
+}
+unsafe impl Zeroable for GpuSection {}
+unsafe impl Pod for GpuSection {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PushConstants {
+    view_projection: [f32; 16],
+    section_count: u32,
+    compact: u32,
+    _padding: [u32; 2],
+}
+unsafe impl Zeroable for PushConstants {}
+unsafe impl Pod for PushConstants {}
+
+const SHADER_ENTRY: &CStr = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
+static SECTION_CULLING_COMPUTE_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/culling/section_culling_comp.spv"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpu_section_matches_std430() {
+        assert_eq!(std::mem::size_of::<GpuSection>(), 48);
+    }
+
+    #[test]
+    fn section_first_index_is_offset_by_mesh() {
+        let section = CulledSection {
+            first_index: 6,
+            index_count: 12,
+            vertex_offset: 4,
+            bounds_min: Vec3f32::new(0f32, 0f32, 0f32),
+            bounds_max: Vec3f32::new(16f32, 16f32, 16f32),
+        };
+        let gpu = section.to_gpu(100);
+        assert_eq!(gpu.first_index, 106);
+        assert_eq!(gpu.index_count, 12);
+        assert_eq!(gpu.bounds_max, [16f32; 3]);
+    }
+
+    #[test]
+    fn indirect_buffer_size() {
+        assert_eq!(get_indirect_buffer_size(0), 4);
+        assert_eq!(get_indirect_buffer_size(3), 4 + 3 * 20);
+    }
+}
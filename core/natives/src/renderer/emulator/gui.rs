@@ -0,0 +1,165 @@
+//! Gui scaling mirroring the gui scale handling of minecrafts `Window`.
+//!
+//! Gui draws are specified in scaled coordinates where the origin is the top left corner of the
+//! framebuffer. [`DrawRecorder::use_gui_scale`](super::DrawRecorder::use_gui_scale) applies the
+//! matching projection and model view matrix.
+
+use ash::vk;
+
+use crate::prelude::*;
+
+/// The gui scale for a specific framebuffer size.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct GuiScale {
+    framebuffer_size: Vec2u32,
+    scale: u32,
+    scaled_size: Vec2u32,
+}
+
+impl GuiScale {
+    /// The near and far plane used by vanilla for the gui projection.
+    pub const NEAR_PLANE: f32 = 1000f32;
+    pub const FAR_PLANE: f32 = 3000f32;
+
+    /// Creates the gui scale for the framebuffer size. `gui_scale` is the gui scale setting where 0
+    /// selects the largest possible scale. If `force_unicode` is true the scale is rounded up to an
+    /// even number.
+    pub fn new(framebuffer_size: Vec2u32, gui_scale: u32, force_unicode: bool) -> Self {
+        let scale = Self::calculate_scale(framebuffer_size, gui_scale, force_unicode);
+
+        let scaled_size = framebuffer_size.map(|v| (v + scale - 1) / scale);
+
+        Self {
+            framebuffer_size,
+            scale,
+            scaled_size,
+        }
+    }
+
+    /// Returns the largest scale up to the requested scale for which the scaled framebuffer is
+    /// at least 320x240.
+    pub fn calculate_scale(framebuffer_size: Vec2u32, gui_scale: u32, force_unicode: bool) -> u32 {
+        let (width, height) = (framebuffer_size[0], framebuffer_size[1]);
+
+        let mut scale = 1;
+        while scale != gui_scale && scale < width && scale < height && width / (scale + 1) >= 320 && height / (scale + 1) >= 240 {
+            scale += 1;
+        }
+
+        if force_unicode && scale % 2 != 0 {
+            scale += 1;
+        }
+
+        scale
+    }
+
+    pub fn get_framebuffer_size(&self) -> Vec2u32 {
+        self.framebuffer_size
+    }
+
+    pub fn get_scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Returns the size of the framebuffer in scaled coordinates. Rounded up if the framebuffer
+    /// size is not a multiple of the scale.
+    pub fn get_scaled_size(&self) -> Vec2u32 {
+        self.scaled_size
+    }
+
+    /// Returns the orthographic projection mapping scaled coordinates to the framebuffer.
+    pub fn get_projection_matrix(&self) -> Mat4f32 {
+        let width = (self.framebuffer_size[0] as f32) / (self.scale as f32);
+        let height = (self.framebuffer_size[1] as f32) / (self.scale as f32);
+        Mat4f32::new_orthographic(0f32, width, height, 0f32, Self::NEAR_PLANE, Self::FAR_PLANE)
+    }
+
+    /// Returns the model view matrix moving gui elements between the near and far plane.
+    pub fn get_model_view_matrix(&self) -> Mat4f32 {
+        Mat4f32::new_translation(&Vec3f32::new(0f32, 0f32, -2000f32))
+    }
+
+    /// Converts framebuffer coordinates, for example the cursor position, to scaled coordinates.
+    pub fn to_scaled(&self, position: Vec2f32) -> Vec2f32 {
+        position / (self.scale as f32)
+    }
+
+    /// Converts scaled coordinates to framebuffer coordinates.
+    pub fn to_framebuffer(&self, position: Vec2f32) -> Vec2f32 {
+        position * (self.scale as f32)
+    }
+
+    /// Rounds scaled coordinates to the closest framebuffer pixel.
+    pub fn snap(&self, position: Vec2f32) -> Vec2f32 {
+        let scale = self.scale as f32;
+        position.map(|v| (v * scale).round() / scale)
+    }
+
+    /// Returns the scissor rectangle for a region specified in scaled coordinates. The region is
+    /// clamped to the framebuffer.
+    pub fn get_scissor(&self, offset: Vec2i32, size: Vec2u32) -> vk::Rect2D {
+        let scale = self.scale as i64;
+        let clamp = |offset: i32, size: u32, max: u32| {
+            let start = (offset as i64) * scale;
+            let end = ((offset as i64) + (size as i64)) * scale;
+            let start = start.clamp(0, max as i64);
+            let end = end.clamp(start, max as i64);
+            (start as i32, (end - start) as u32)
+        };
+
+        let (x, width) = clamp(offset[0], size[0], self.framebuffer_size[0]);
+        let (y, height) = clamp(offset[1], size[1], self.framebuffer_size[1]);
+
+        vk::Rect2D {
+            offset: vk::Offset2D { x, y },
+            extent: vk::Extent2D { width, height }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point3;
+
+    use super::*;
+
+    #[test]
+    fn auto_scale() {
+        assert_eq!(GuiScale::calculate_scale(Vec2u32::new(1920, 1080), 0, false), 4);
+        assert_eq!(GuiScale::calculate_scale(Vec2u32::new(1920, 1080), 2, false), 2);
+        assert_eq!(GuiScale::calculate_scale(Vec2u32::new(854, 480), 0, false), 2);
+        assert_eq!(GuiScale::calculate_scale(Vec2u32::new(854, 480), 0, true), 2);
+        assert_eq!(GuiScale::calculate_scale(Vec2u32::new(640, 400), 0, true), 2);
+        assert_eq!(GuiScale::calculate_scale(Vec2u32::new(300, 200), 3, false), 1);
+    }
+
+    #[test]
+    fn scaled_size() {
+        let gui = GuiScale::new(Vec2u32::new(1366, 768), 3, false);
+        assert_eq!(gui.get_scale(), 3);
+        assert_eq!(gui.get_scaled_size(), Vec2u32::new(456, 256));
+    }
+
+    #[test]
+    fn projection() {
+        let gui = GuiScale::new(Vec2u32::new(1920, 1080), 0, false);
+        let matrix = gui.get_projection_matrix() * gui.get_model_view_matrix();
+
+        let top_left = matrix.transform_point(&Point3::new(0f32, 0f32, 0f32));
+        assert_eq!((top_left.x, top_left.y), (-1f32, 1f32));
+        assert!(top_left.z > -1f32 && top_left.z < 1f32);
+
+        let bottom_right = matrix.transform_point(&Point3::new(480f32, 270f32, 0f32));
+        assert_eq!((bottom_right.x, bottom_right.y), (1f32, -1f32));
+    }
+
+    #[test]
+    fn scissor() {
+        let gui = GuiScale::new(Vec2u32::new(800, 600), 2, false);
+        let scissor = gui.get_scissor(Vec2i32::new(-10, 10), Vec2u32::new(100, 500));
+        assert_eq!((scissor.offset.x, scissor.offset.y), (0, 20));
+        assert_eq!((scissor.extent.width, scissor.extent.height), (180, 580));
+
+        assert_eq!(gui.snap(Vec2f32::new(1.3f32, 2.75f32)), Vec2f32::new(1.5f32, 3f32));
+    }
+}
@@ -0,0 +1,200 @@
+//! Decoding of common image formats into textures ready to be uploaded.
+//!
+//! Only available with the `image-loader` feature. [`DecodedTexture::decode`] detects the format of
+//! the data, decodes it using the `image` crate, converts it to tightly packed RGBA and computes the
+//! number of mip levels if mipmaps are requested. PNG, JPEG and TGA images are supported. Gpu
+//! compressed textures should be loaded using the [`texture_loader`](super::texture_loader) instead.
+
+use std::sync::Arc;
+
+use image::ImageFormat;
+
+use crate::prelude::*;
+use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, ImageData};
+use crate::util::format::Format;
+
+const TGA_HEADER_SIZE: usize = 18;
+
+#[derive(Debug)]
+pub enum ImageLoadError {
+    /// The image crate failed to decode the data.
+    Image(image::ImageError),
+    /// The data is in a format which cannot be decoded.
+    UnsupportedFormat(&'static str),
+}
+
+impl From<image::ImageError> for ImageLoadError {
+    fn from(err: image::ImageError) -> Self {
+        ImageLoadError::Image(err)
+    }
+}
+
+/// Configures how a image is decoded.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ImageLoadOptions {
+    /// If true the texture uses a srgb format.
+    pub srgb: bool,
+
+    /// If true the texture has a full mip chain which is generated after the upload.
+    pub generate_mipmaps: bool,
+}
+
+impl Default for ImageLoadOptions {
+    fn default() -> Self {
+        Self {
+            srgb: true,
+            generate_mipmaps: false,
+        }
+    }
+}
+
+/// A decoded image in RGBA format.
+pub struct DecodedTexture {
+    pub size: Vec2u32,
+    pub format: &'static Format,
+
+    /// The number of mip levels the texture should be created with. Level 0 is stored in `data`
+    /// and all other levels must be generated.
+    pub mip_levels: u32,
+
+    /// The tightly packed RGBA data of mip level 0.
+    pub data: Box<[u8]>,
+}
+
+impl DecodedTexture {
+    pub fn decode(data: &[u8], options: ImageLoadOptions) -> Result<Self, ImageLoadError> {
+        let image_format = match image::guess_format(data) {
+            Ok(format @ (ImageFormat::Png | ImageFormat::Jpeg)) => format,
+            Ok(_) => return Err(ImageLoadError::UnsupportedFormat("Unsupported image format")),
+            // TGA images have no signature
+            Err(_) if is_tga_header(data) => ImageFormat::Tga,
+            Err(_) => return Err(ImageLoadError::UnsupportedFormat("Unknown image format")),
+        };
+
+        let image = image::load_from_memory_with_format(data, image_format)?.into_rgba8();
+        let size = Vec2u32::new(image.width(), image.height());
+        let data = image.into_raw().into_boxed_slice();
+
+        let format = if options.srgb { &Format::R8G8B8A8_SRGB } else { &Format::R8G8B8A8_UNORM };
+        let mip_levels = if options.generate_mipmaps {
+            32 - std::cmp::max(size[0], size[1]).leading_zeros()
+        } else {
+            1
+        };
+
+        Ok(Self {
+            size,
+            format,
+            mip_levels,
+            data,
+        })
+    }
+
+    pub fn get_image_data(&self) -> ImageData<'_> {
+        ImageData::new_full(&self.data, self.size)
+    }
+
+    /// Creates a global image, uploads the data and generates the mip levels if requested.
+    pub fn create_image(&self, renderer: &EmulatorRenderer) -> Arc<GlobalImage> {
+        let image = renderer.create_global_image_mips(self.size, self.mip_levels, self.format);
+        image.update_regions(std::slice::from_ref(&self.get_image_data()));
+        image.generate_mipmaps();
+        image
+    }
+}
+
+/// Returns true if the data starts with a plausible TGA header.
+fn is_tga_header(data: &[u8]) -> bool {
+    if data.len() < TGA_HEADER_SIZE {
+        return false;
+    }
+
+    let color_map_type = data[1];
+    let image_type = data[2];
+    let width = u16::from_le_bytes([data[12], data[13]]);
+    let height = u16::from_le_bytes([data[14], data[15]]);
+    let bits_per_pixel = data[16];
+
+    color_map_type <= 1
+        && matches!(image_type, 1 | 2 | 3 | 9 | 10 | 11)
+        && matches!(bits_per_pixel, 8 | 15 | 16 | 24 | 32)
+        && width != 0 && height != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tga_header(image_type: u8, width: u16, height: u16, bits_per_pixel: u8, descriptor: u8) -> Vec<u8> {
+        let mut data = vec![0u8; TGA_HEADER_SIZE];
+        data[2] = image_type;
+        data[12..14].copy_from_slice(&width.to_le_bytes());
+        data[14..16].copy_from_slice(&height.to_le_bytes());
+        data[16] = bits_per_pixel;
+        data[17] = descriptor;
+        data
+    }
+
+    #[test]
+    fn decode_tga_bottom_up() {
+        let mut data = tga_header(2, 2, 2, 24, 0);
+        // Bottom row first in BGR order
+        data.extend_from_slice(&[0, 0, 255, 0, 255, 0]);
+        data.extend_from_slice(&[255, 0, 0, 255, 255, 255]);
+
+        let texture = DecodedTexture::decode(&data, ImageLoadOptions::default()).unwrap();
+        assert_eq!(texture.size, Vec2u32::new(2, 2));
+        assert_eq!(texture.mip_levels, 1);
+        assert_eq!(texture.data.as_ref(), &[
+            0, 0, 255, 255, 255, 255, 255, 255,
+            255, 0, 0, 255, 0, 255, 0, 255,
+        ]);
+    }
+
+    #[test]
+    fn decode_tga_rle() {
+        let mut data = tga_header(10, 3, 1, 32, 0x20);
+        // A run of 2 pixels followed by a single raw pixel
+        data.extend_from_slice(&[0x81, 1, 2, 3, 4]);
+        data.extend_from_slice(&[0x00, 5, 6, 7, 8]);
+
+        let texture = DecodedTexture::decode(&data, ImageLoadOptions { srgb: false, generate_mipmaps: true }).unwrap();
+        assert!(texture.format == &Format::R8G8B8A8_UNORM);
+        assert_eq!(texture.mip_levels, 2);
+        assert_eq!(texture.data.as_ref(), &[3, 2, 1, 4, 3, 2, 1, 4, 7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn decode_png_grayscale() {
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut encoded, 2, 1);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[10, 200]).unwrap();
+        }
+
+        let texture = DecodedTexture::decode(&encoded, ImageLoadOptions::default()).unwrap();
+        assert_eq!(texture.size, Vec2u32::new(2, 1));
+        assert_eq!(texture.data.as_ref(), &[10, 10, 10, 255, 200, 200, 200, 255]);
+    }
+
+    #[test]
+    fn decode_jpeg() {
+        let mut encoded = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut encoded, 100).encode(&[128u8; 8 * 8 * 3], 8, 8, image::ColorType::Rgb8).unwrap();
+
+        let texture = DecodedTexture::decode(&encoded, ImageLoadOptions::default()).unwrap();
+        assert_eq!(texture.size, Vec2u32::new(8, 8));
+        assert!(texture.data.chunks_exact(4).all(|texel| texel[3] == 255 && texel[0].abs_diff(128) <= 2));
+    }
+
+    #[test]
+    fn unsupported_formats() {
+        // GIF signature
+        assert!(matches!(DecodedTexture::decode(b"GIF89a\0\0\0\0\0\0\0\0\0\0\0\0", ImageLoadOptions::default()), Err(ImageLoadError::UnsupportedFormat(_))));
+        assert!(matches!(DecodedTexture::decode(&[0u8; 4], ImageLoadOptions::default()), Err(ImageLoadError::UnsupportedFormat(_))));
+        assert!(matches!(DecodedTexture::decode(&tga_header(7, 1, 1, 24, 0), ImageLoadOptions::default()), Err(ImageLoadError::UnsupportedFormat(_))));
+    }
+}
@@ -11,20 +11,31 @@ use crate::util::alloc::next_aligned;
 use crate::prelude::*;
 
 pub(super) struct ImmediatePool {
+    device: Arc<DeviceContext>,
     buffer_queue: Mutex<VecDeque<Box<ImmediateBuffer>>>,
     ready_condvar: Condvar,
+
+    /// Buffers used by sub pass recorders. These are created on demand and do not limit the number
+    /// of passes in flight.
+    sub_buffers: Mutex<Vec<Box<ImmediateBuffer>>>,
 }
 
 impl ImmediatePool {
-    pub(super) fn new(device: Arc<DeviceContext>) -> Self {
-        let mut buffer_queue = VecDeque::with_capacity(2);
-        for _ in 0..2 {
+    /// The number of buffers used if not configured otherwise. Limits the number of passes which
+    /// can be recorded while previous passes are still in flight.
+    pub(super) const DEFAULT_BUFFER_COUNT: usize = 2;
+
+    pub(super) fn new(device: Arc<DeviceContext>, buffer_count: usize) -> Self {
+        let mut buffer_queue = VecDeque::with_capacity(buffer_count);
+        for _ in 0..buffer_count {
             buffer_queue.push_back(Box::new(ImmediateBuffer::new(device.clone())));
         }
 
         Self {
+            device,
             buffer_queue: Mutex::new(buffer_queue),
             ready_condvar: Condvar::new(),
+            sub_buffers: Mutex::new(Vec::new()),
         }
     }
 
@@ -61,6 +72,26 @@ impl ImmediatePool {
         guard.push_back(buffer);
         self.ready_condvar.notify_one();
     }
+
+    pub(super) fn get_sub_buffer(&self) -> Box<ImmediateBuffer> {
+        let mut guard = self.sub_buffers.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned sub buffer mutex in ImmediatePool::get_sub_buffer");
+            panic!()
+        });
+
+        guard.pop().unwrap_or_else(|| Box::new(ImmediateBuffer::new(self.device.clone())))
+    }
+
+    pub(super) fn return_sub_buffer(&self, mut buffer: Box<ImmediateBuffer>) {
+        buffer.reset();
+
+        let mut guard = self.sub_buffers.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned sub buffer mutex in ImmediatePool::return_sub_buffer");
+            panic!()
+        });
+
+        guard.push(buffer);
+    }
 }
 
 impl RefUnwindSafe for ImmediatePool {} // Condvar is not RefUnwindSafe
@@ -69,19 +100,34 @@ pub(super) struct ImmediateBuffer {
     device: Arc<DeviceContext>,
     current_buffer: Buffer,
     old_buffers: Vec<Buffer>,
+
+    /// Persistently mapped host memory used directly as vertex buffer for tiny meshes like gui
+    /// quads and text. Only created if the main buffers require staging copies.
+    pinned_buffer: Option<Buffer>,
 }
 
 impl ImmediateBuffer {
     const MIN_BUFFER_SIZE: vk::DeviceSize = 2u64.pow(24); // 16MB
     const OVER_ALLOCATION: u8 = 77; // 30%
 
+    const PINNED_BUFFER_SIZE: vk::DeviceSize = 2u64.pow(20); // 1MB
+
+    /// The largest allocation placed into the pinned buffer.
+    const PINNED_MAX_ALLOCATION: usize = 4096;
+
     fn new(device: Arc<DeviceContext>) -> Self {
         let current_buffer = Buffer::new(device.clone(), Self::MIN_BUFFER_SIZE);
+        let pinned_buffer = if current_buffer.staging.is_some() {
+            Some(Buffer::new_pinned(device.clone(), Self::PINNED_BUFFER_SIZE))
+        } else {
+            None
+        };
 
         Self {
             device,
             current_buffer,
             old_buffers: Vec::new(),
+            pinned_buffer,
         }
     }
 
@@ -95,24 +141,53 @@ impl ImmediateBuffer {
     pub(super) fn reset(&mut self) {
         self.current_buffer.reset();
         self.old_buffers.clear();
+        if let Some(pinned) = &mut self.pinned_buffer {
+            pinned.reset();
+        }
     }
 
     pub(super) fn allocate(&mut self, data: &[u8], alignment: vk::DeviceSize) -> (vk::Buffer, vk::DeviceSize) {
-        if let Some(info) = self.current_buffer.allocate(data, alignment) {
-            info
+        // Tiny meshes are written directly into device visible memory to avoid the staging copy.
+        // Once the pinned buffer is full the regular path is used for the rest of the pass.
+        if data.len() <= Self::PINNED_MAX_ALLOCATION {
+            if let Some(info) = self.pinned_buffer.as_mut().and_then(|pinned| pinned.allocate(data, alignment)) {
+                return info;
+            }
+        }
+
+        let (buffer, offset) = self.reserve(data.len() as vk::DeviceSize, alignment);
+        self.current_buffer.write(offset, data);
+
+        (buffer, offset)
+    }
+
+    /// Reserves a range which is written by the device, for example by a compute shader. The
+    /// content of the range is undefined until it is written. The range is never placed into the
+    /// pinned buffer so it can be used as a transfer destination.
+    pub(super) fn reserve(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> (vk::Buffer, vk::DeviceSize) {
+        if let Some(offset) = self.current_buffer.reserve(size, alignment) {
+            (self.current_buffer.main_buffer, offset)
         } else {
             let usage = self.get_current_usage();
             let alloc_size = usage + (usage * (Self::OVER_ALLOCATION as u64) / (u8::MAX as u64));
-            let alloc_size = std::cmp::max(alloc_size, data.len() as u64);
+            let alloc_size = std::cmp::max(alloc_size, size);
             let alloc_size = std::cmp::max(alloc_size, Self::MIN_BUFFER_SIZE);
 
             let new_buffer = Buffer::new(self.device.clone(), alloc_size);
             self.old_buffers.push(std::mem::replace(&mut self.current_buffer, new_buffer));
 
-            self.current_buffer.allocate(data, alignment).unwrap()
+            (self.current_buffer.main_buffer, self.current_buffer.reserve(size, alignment).unwrap())
         }
     }
 
+    /// Returns the device address of a buffer returned by [`ImmediateBuffer::allocate`]. Returns
+    /// [`None`] if buffer device addresses are not supported by the device.
+    pub(super) fn get_device_address(&self, buffer: vk::Buffer) -> Option<vk::DeviceAddress> {
+        std::iter::once(&self.current_buffer).chain(self.old_buffers.iter()).chain(self.pinned_buffer.iter())
+            .find(|b| b.main_buffer == buffer)
+            .and_then(|b| b.main_address)
+    }
+
     fn get_current_usage(&self) -> vk::DeviceSize {
         let mut usage = self.current_buffer.get_current_used_bytes();
         for old_buffer in &self.old_buffers {
@@ -127,6 +202,7 @@ struct Buffer {
     device: Arc<DeviceContext>,
 
     main_buffer: vk::Buffer,
+    main_address: Option<vk::DeviceAddress>,
     mapped_memory: NonNull<u8>,
     size: vk::DeviceSize,
     current_offset: vk::DeviceSize,
@@ -138,6 +214,7 @@ struct Buffer {
 impl Buffer {
     fn new(device: Arc<DeviceContext>, size: vk::DeviceSize) -> Self {
         let (main_buffer, main_allocation, main_mapped) = Self::create_main_buffer(&device, size);
+        let main_address = device.get_buffer_device_address(main_buffer);
 
         let (staging, mapped_memory) = if let Some(mapped) = main_mapped {
             log::info!("Immediate buffer uses mapped memory");
@@ -151,6 +228,7 @@ impl Buffer {
         Self {
             device,
             main_buffer,
+            main_address,
             mapped_memory,
             size,
             current_offset: 0,
@@ -159,6 +237,23 @@ impl Buffer {
         }
     }
 
+    /// Creates a buffer which is always host visible and never requires a staging copy.
+    fn new_pinned(device: Arc<DeviceContext>, size: vk::DeviceSize) -> Self {
+        let (main_buffer, main_allocation, mapped_memory) = Self::create_pinned_buffer(&device, size);
+        let main_address = device.get_buffer_device_address(main_buffer);
+
+        Self {
+            device,
+            main_buffer,
+            main_address,
+            mapped_memory,
+            size,
+            current_offset: 0,
+            main_allocation,
+            staging: None
+        }
+    }
+
     fn generate_copy_commands(&self, cmd: vk::CommandBuffer) {
         if let Some((staging_buffer, _)) = &self.staging {
             if self.current_offset != 0 {
@@ -183,20 +278,31 @@ impl Buffer {
     }
 
     fn allocate(&mut self, bytes: &[u8], alignment: vk::DeviceSize) -> Option<(vk::Buffer, vk::DeviceSize)> {
+        let offset = self.reserve(bytes.len() as vk::DeviceSize, alignment)?;
+        self.write(offset, bytes);
+
+        Some((self.main_buffer, offset))
+    }
+
+    /// Reserves a aligned range of the buffer and returns its offset. Returns [`None`] if the
+    /// buffer is full.
+    fn reserve(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<vk::DeviceSize> {
         let aligned = next_aligned(self.current_offset, alignment);
-        if aligned + (bytes.len() as vk::DeviceSize) > self.size {
+        if aligned + size > self.size {
             return None;
         }
 
-        self.current_offset = aligned + (bytes.len() as vk::DeviceSize);
+        self.current_offset = aligned + size;
+        Some(aligned)
+    }
 
-        let start = aligned as usize;
-        let end = self.current_offset as usize;
+    /// Writes data into a range previously returned by [`Buffer::reserve`].
+    fn write(&mut self, offset: vk::DeviceSize, bytes: &[u8]) {
+        let start = offset as usize;
+        let end = start + bytes.len();
         let dst = &mut unsafe { std::slice::from_raw_parts_mut(self.mapped_memory.as_ptr(), self.size as usize) }[start..end];
 
         dst.copy_from_slice(bytes);
-
-        Some((self.main_buffer, aligned))
     }
 
     fn get_current_used_bytes(&self) -> vk::DeviceSize {
@@ -204,9 +310,14 @@ impl Buffer {
     }
 
     fn create_main_buffer(device: &DeviceContext, size: vk::DeviceSize) -> (vk::Buffer, Allocation, Option<NonNull<u8>>) {
+        let mut usage = vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::INDIRECT_BUFFER | vk::BufferUsageFlags::TRANSFER_DST;
+        if device.buffer_device_address_khr().is_some() {
+            usage |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        }
+
         let info = vk::BufferCreateInfo::builder()
             .size(size)
-            .usage(vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
+            .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
         let (buffer, allocation, mapped) = unsafe {
@@ -219,6 +330,27 @@ impl Buffer {
         (buffer, allocation, mapped)
     }
 
+    fn create_pinned_buffer(device: &DeviceContext, size: vk::DeviceSize) -> (vk::Buffer, Allocation, NonNull<u8>) {
+        let mut usage = vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER;
+        if device.buffer_device_address_khr().is_some() {
+            usage |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        }
+
+        let info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let (buffer, allocation, mapped) = unsafe {
+            device.get_allocator().create_buffer(&info, HostAccess::SequentialWrite, &format_args!("ImmediatePinnedBuffer"))
+        }.unwrap_or_else(|| {
+            log::error!("Failed to create pinned buffer.");
+            panic!()
+        });
+
+        (buffer, allocation, mapped.unwrap())
+    }
+
     fn create_staging_buffer(device: &DeviceContext, size: vk::DeviceSize) -> (vk::Buffer, Allocation, NonNull<u8>) {
         let info = vk::BufferCreateInfo::builder()
             .size(size)
@@ -0,0 +1,288 @@
+//! Dynamic point lights using tiled light culling.
+//!
+//! The lights of a pass are set using [`PassRecorder::set_lights`](super::PassRecorder::set_lights).
+//! Before the first draw of the pass a compute shader splits the framebuffer into tiles of
+//! [`LIGHT_TILE_SIZE`] pixels and builds a list of the lights overlapping each tile. Fragment
+//! shaders can then shade all lights of their tile using the `lights.glsl` include without a fixed
+//! per draw light limit. At most [`MAX_LIGHTS_PER_TILE`] lights are stored per tile, any further
+//! lights are dropped.
+
+use std::ffi::CStr;
+use std::sync::Arc;
+
+use ash::vk;
+use bytemuck::{bytes_of, cast_slice, Pod, Zeroable};
+use include_bytes_aligned::include_bytes_aligned;
+
+use crate::device::device_utils::create_shader_from_bytes;
+use crate::renderer::emulator::immediate::ImmediateBuffer;
+use crate::renderer::emulator::pipeline::{LightsInfo, PipelineTask};
+use crate::renderer::emulator::share::Share;
+
+use crate::prelude::*;
+
+/// The width and height of a light culling tile in pixels.
+pub const LIGHT_TILE_SIZE: u32 = 16;
+
+/// The maximum number of lights affecting a single tile.
+pub const MAX_LIGHTS_PER_TILE: u32 = 63;
+
+/// The workgroup size of the culling shader in tiles in each dimension.
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Storage buffer offsets must be aligned to minStorageBufferOffsetAlignment. 256 is the highest
+/// value in the gpuinfo database.
+const LIGHT_BUFFER_ALIGNMENT: vk::DeviceSize = 256;
+
+/// A point light. The position is in view space.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PointLight {
+    pub position: Vec3f32,
+
+    /// The distance at which the light no longer has any effect.
+    pub radius: f32,
+    pub color: Vec3f32,
+    pub intensity: f32,
+}
+
+impl PointLight {
+    fn to_gpu(&self) -> [f32; 8] {
+        [
+            self.position[0], self.position[1], self.position[2], self.radius,
+            self.color[0], self.color[1], self.color[2], self.intensity,
+        ]
+    }
+}
+
+/// Returns the number of light culling tiles covering the framebuffer.
+pub fn get_light_tile_count(framebuffer_size: Vec2u32) -> Vec2u32 {
+    Vec2u32::new(
+        (framebuffer_size[0] + LIGHT_TILE_SIZE - 1) / LIGHT_TILE_SIZE,
+        (framebuffer_size[1] + LIGHT_TILE_SIZE - 1) / LIGHT_TILE_SIZE,
+    )
+}
+
+/// Returns the size in bytes of the tile buffer written by the light culling shader. The buffer
+/// starts with the number of tiles in x direction followed by the light count and light indices of
+/// every tile.
+pub(super) fn get_light_tile_buffer_size(framebuffer_size: Vec2u32) -> vk::DeviceSize {
+    let tile_count = get_light_tile_count(framebuffer_size);
+    let entries = 1 + (tile_count[0] as u64) * (tile_count[1] as u64) * ((MAX_LIGHTS_PER_TILE + 1) as u64);
+    entries * (std::mem::size_of::<u32>() as vk::DeviceSize)
+}
+
+/// Uploads the lights into the immediate buffer. Returns a task without a buffer if there are no
+/// lights.
+pub(super) fn upload_lights(immediate: &mut ImmediateBuffer, share: &Share, lights: &[PointLight], projection: &Mat4f32) -> PipelineTask {
+    if lights.is_empty() {
+        return PipelineTask::SetLights(LightsInfo::none());
+    }
+
+    let data: Vec<f32> = lights.iter().flat_map(PointLight::to_gpu).collect();
+    let bytes: &[u8] = cast_slice(&data);
+
+    let (buffer, offset) = immediate.allocate(bytes, LIGHT_BUFFER_ALIGNMENT);
+    share.record_upload(bytes.len() as u64);
+
+    PipelineTask::SetLights(LightsInfo {
+        buffer,
+        offset,
+        count: lights.len() as u32,
+        projection: *projection,
+    })
+}
+
+/// Returns the descriptor of the light buffer. If there are no lights the tile buffer is used
+/// instead since the descriptor must be valid even if no lights are read.
+pub(super) fn get_light_buffer_info(lights: &LightsInfo, tile_buffer: vk::Buffer) -> vk::DescriptorBufferInfo {
+    if lights.count != 0 {
+        vk::DescriptorBufferInfo {
+            buffer: lights.buffer,
+            offset: lights.offset,
+            range: (lights.count as vk::DeviceSize) * (std::mem::size_of::<[f32; 8]>() as vk::DeviceSize),
+        }
+    } else {
+        vk::DescriptorBufferInfo {
+            buffer: tile_buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        }
+    }
+}
+
+/// Compute pipeline building the per tile light lists.
+pub(super) struct LightCullingPipeline {
+    device: Arc<DeviceContext>,
+
+    /// Owned by the layout cache of the device.
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl LightCullingPipeline {
+    pub(super) fn new(device: Arc<DeviceContext>) -> Result<Self, vk::Result> {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                p_immutable_samplers: std::ptr::null(),
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                p_immutable_samplers: std::ptr::null(),
+            },
+        ];
+
+        let layout_cache = device.get_utils().layout_cache();
+
+        let set_layout = layout_cache.get_descriptor_set_layout(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR, &bindings).map_err(|err| {
+            log::error!("vkCreateDescriptorSetLayout returned {:?} in LightCullingPipeline::new", err);
+            err
+        })?;
+
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: std::mem::size_of::<PushConstants>() as u32,
+        };
+
+        let pipeline_layout = layout_cache.get_pipeline_layout(std::slice::from_ref(&set_layout), std::slice::from_ref(&push_constant_range)).map_err(|err| {
+            log::error!("vkCreatePipelineLayout returned {:?} in LightCullingPipeline::new", err);
+            err
+        })?;
+
+        let module = create_shader_from_bytes(device.get_functions(), LIGHT_CULLING_COMPUTE_BIN).map_err(|err| {
+            log::error!("vkCreateShaderModule returned {:?} in LightCullingPipeline::new", err);
+            err
+        })?;
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(SHADER_ENTRY);
+
+        let info = vk::ComputePipelineCreateInfo::builder()
+            .stage(*stage)
+            .layout(pipeline_layout);
+
+        let pipeline = unsafe {
+            device.vk().create_compute_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), None)
+        };
+        unsafe { device.vk().destroy_shader_module(module, None) };
+
+        let pipeline = pipeline.map_err(|(_, err)| {
+            log::error!("vkCreateComputePipelines returned {:?} in LightCullingPipeline::new", err);
+            err
+        })?[0];
+
+        Ok(Self {
+            device,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    /// Records the culling of the lights into the tile buffer followed by a barrier making the
+    /// tile buffer and lights available to fragment shaders. Must be recorded outside of a render
+    /// pass. If there are no lights all tiles are cleared.
+    pub(super) fn record(&self, cmd: vk::CommandBuffer, lights: &LightsInfo, tile_buffer: vk::Buffer, framebuffer_size: Vec2u32) {
+        let tile_count = get_light_tile_count(framebuffer_size);
+
+        let light_info = get_light_buffer_info(lights, tile_buffer);
+        let tile_info = vk::DescriptorBufferInfo {
+            buffer: tile_buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        };
+
+        let writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(std::slice::from_ref(&light_info))
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .buffer_info(std::slice::from_ref(&tile_info))
+                .build(),
+        ];
+
+        let mut projection = [0f32; 16];
+        projection.copy_from_slice(lights.projection.as_slice());
+        let push_constants = PushConstants {
+            projection,
+            framebuffer_size: [framebuffer_size[0], framebuffer_size[1]],
+            tile_count: [tile_count[0], tile_count[1]],
+            light_count: lights.count,
+            _padding: [0; 3],
+        };
+
+        let barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ);
+        let dependency_info = vk::DependencyInfo::builder()
+            .memory_barriers(std::slice::from_ref(&barrier));
+
+        unsafe {
+            self.device.vk().cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            self.device.push_descriptor_khr().cmd_push_descriptor_set(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline_layout, 0, &writes);
+            self.device.vk().cmd_push_constants(cmd, self.pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, bytes_of(&push_constants));
+            self.device.vk().cmd_dispatch(
+                cmd,
+                (tile_count[0] + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                (tile_count[1] + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                1
+            );
+            self.device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &dependency_info);
+        }
+    }
+}
+
+impl Drop for LightCullingPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.vk().destroy_pipeline(self.pipeline, None);
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PushConstants {
+    projection: [f32; 16],
+    framebuffer_size: [u32; 2],
+    tile_count: [u32; 2],
+    light_count: u32,
+    _padding: [u32; 3],
+}
+unsafe impl Zeroable for PushConstants {}
+unsafe impl Pod for PushConstants {}
+
+const SHADER_ENTRY: &CStr = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
+static LIGHT_CULLING_COMPUTE_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/lights/light_culling_comp.spv"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_count_rounds_up() {
+        assert_eq!(get_light_tile_count(Vec2u32::new(1920, 1080)), Vec2u32::new(120, 68));
+        assert_eq!(get_light_tile_count(Vec2u32::new(16, 16)), Vec2u32::new(1, 1));
+        assert_eq!(get_light_tile_count(Vec2u32::new(17, 1)), Vec2u32::new(2, 1));
+    }
+
+    #[test]
+    fn tile_buffer_size() {
+        let size = get_light_tile_buffer_size(Vec2u32::new(32, 16));
+        assert_eq!(size, (1 + 2 * (MAX_LIGHTS_PER_TILE as u64 + 1)) * 4);
+    }
+}
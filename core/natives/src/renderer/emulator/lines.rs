@@ -0,0 +1,183 @@
+//! Line rendering used for block outlines, hitboxes, chunk borders and other debug gizmos.
+//!
+//! Vulkan only guarantees support for 1 pixel wide lines. Like minecraft every line segment is
+//! instead submitted as a quad made of its duplicated end points. The normal of each vertex
+//! contains the direction of the line which the vertex shader uses to expand the quad to the width
+//! of the `LineWidth` uniform, exactly like vanilla's `rendertype_lines` shader does.
+
+use ash::vk;
+use bytemuck::{cast_slice, Pod, Zeroable};
+
+use crate::prelude::*;
+use crate::renderer::emulator::MeshData;
+use crate::renderer::emulator::mc_shaders::{VertexFormat, VertexFormatEntry};
+use crate::renderer::emulator::pipeline::DepthBias;
+
+/// A list of colored lines which can be drawn using
+/// [`DrawRecorder::draw_lines`](super::DrawRecorder::draw_lines).
+///
+/// The vertices use [`LineBatch::VERTEX_FORMAT`] which the shader used to draw the batch must be
+/// created with.
+pub struct LineBatch {
+    vertices: Vec<LineVertex>,
+}
+
+impl LineBatch {
+    pub const VERTEX_FORMAT: VertexFormat = VertexFormat {
+        stride: std::mem::size_of::<LineVertex>() as u32,
+        position: VertexFormatEntry { offset: 0, format: vk::Format::R32G32B32_SFLOAT },
+        normal: Some(VertexFormatEntry { offset: 16, format: vk::Format::R32G32B32_SFLOAT }),
+        color: Some(VertexFormatEntry { offset: 12, format: vk::Format::R8G8B8A8_UNORM }),
+        uv0: None,
+        uv1: None,
+        uv2: None,
+        joint_indices: None,
+        joint_weights: None,
+    };
+
+    pub fn new() -> Self {
+        Self {
+            vertices: Vec::with_capacity(96),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// Returns the number of lines in the batch.
+    pub fn get_line_count(&self) -> usize {
+        self.vertices.len() / 4
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// Adds a line between the 2 points. Lines of length 0 are ignored.
+    pub fn line(&mut self, start: Vec3f32, end: Vec3f32, color: Vec4f32) -> &mut Self {
+        let direction = end - start;
+        let length = direction.norm();
+        if length == 0f32 {
+            return self;
+        }
+        let normal = direction / length;
+
+        let color = pack_color(&color);
+        let start = LineVertex::new(&start, color, &normal);
+        let end = LineVertex::new(&end, color, &normal);
+        self.vertices.extend_from_slice(&[start, start, end, end]);
+
+        self
+    }
+
+    /// Adds the 12 edges of the axis aligned box. Used for block outlines and hitboxes.
+    pub fn box_outline(&mut self, min: Vec3f32, max: Vec3f32, color: Vec4f32) -> &mut Self {
+        let corner = |x: bool, y: bool, z: bool| {
+            Vec3f32::new(if x { max[0] } else { min[0] }, if y { max[1] } else { min[1] }, if z { max[2] } else { min[2] })
+        };
+
+        for a in [false, true] {
+            for b in [false, true] {
+                self.line(corner(false, a, b), corner(true, a, b), color);
+                self.line(corner(a, false, b), corner(a, true, b), color);
+                self.line(corner(a, b, false), corner(a, b, true), color);
+            }
+        }
+
+        self
+    }
+
+    pub(super) fn as_mesh_data(&self) -> MeshData<'_> {
+        MeshData::new_quads(cast_slice(&self.vertices), Self::VERTEX_FORMAT.stride)
+    }
+}
+
+impl Default for LineBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Configures how a [`LineBatch`] is drawn.
+#[derive(Copy, Clone, Debug)]
+pub struct LineStyle {
+    /// The width of the lines in pixels.
+    pub width: f32,
+    pub depth_write_enable: bool,
+
+    /// Used to move lines in front of the surfaces they outline.
+    pub depth_bias: Option<DepthBias>,
+}
+
+impl LineStyle {
+    pub const fn new(width: f32) -> Self {
+        Self {
+            width,
+            depth_write_enable: true,
+            depth_bias: None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [u8; 4],
+    normal: [f32; 3],
+}
+const_assert_eq!(std::mem::size_of::<LineVertex>(), 28);
+
+unsafe impl Zeroable for LineVertex {}
+unsafe impl Pod for LineVertex {}
+
+impl LineVertex {
+    fn new(position: &Vec3f32, color: [u8; 4], normal: &Vec3f32) -> Self {
+        Self {
+            position: [position[0], position[1], position[2]],
+            color,
+            normal: [normal[0], normal[1], normal[2]],
+        }
+    }
+}
+
+fn pack_color(color: &Vec4f32) -> [u8; 4] {
+    color.map(|v| (v.clamp(0f32, 1f32) * 255f32).round() as u8).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_vertices() {
+        let mut batch = LineBatch::new();
+        batch.line(Vec3f32::new(1f32, 0f32, 0f32), Vec3f32::new(1f32, 2f32, 0f32), Vec4f32::new(1f32, 0f32, 0f32, 1f32));
+        batch.line(Vec3f32::zeros(), Vec3f32::zeros(), Vec4f32::zeros());
+        assert_eq!(batch.get_line_count(), 1);
+
+        let vertices = &batch.vertices;
+        assert_eq!(vertices[1].position, [1f32, 0f32, 0f32]);
+        assert_eq!(vertices[2].position, [1f32, 2f32, 0f32]);
+        assert_eq!(vertices[0].normal, [0f32, 1f32, 0f32]);
+        assert_eq!(vertices[3].color, [255, 0, 0, 255]);
+
+        let data = batch.as_mesh_data();
+        assert_eq!(data.index_count, 6);
+        assert!(data.uses_quad_indices());
+    }
+
+    #[test]
+    fn box_outline_edges() {
+        let mut batch = LineBatch::new();
+        batch.box_outline(Vec3f32::zeros(), Vec3f32::new(1f32, 1f32, 1f32), Vec4f32::new(0f32, 0f32, 0f32, 0.4f32));
+        assert_eq!(batch.get_line_count(), 12);
+
+        for line in batch.vertices.chunks(4) {
+            let start = Vec3f32::from(line[0].position);
+            let end = Vec3f32::from(line[2].position);
+            assert_eq!((end - start).norm(), 1f32);
+        }
+    }
+}
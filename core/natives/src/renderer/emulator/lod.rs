@@ -0,0 +1,86 @@
+//! Distance based level of detail selection for global meshes.
+//!
+//! A [`LodMesh`] bundles multiple versions of the same object with decreasing detail, for example
+//! simplified geometry for distant chunk sections. The level is selected on the host when the mesh
+//! is drawn using [`DrawRecorder::draw_global_lod`](super::DrawRecorder::draw_global_lod) so no
+//! additional gpu work is required.
+
+use std::sync::Arc;
+
+use crate::prelude::*;
+use crate::renderer::emulator::GlobalMesh;
+
+/// A single level of a [`LodMesh`].
+#[derive(Clone)]
+pub struct LodLevel {
+    pub mesh: Arc<GlobalMesh>,
+
+    /// The largest distance from the camera at which this level is used.
+    pub max_distance: f32,
+}
+
+/// A set of global meshes representing the same object at different levels of detail.
+pub struct LodMesh {
+    levels: Box<[LodLevel]>,
+}
+
+impl LodMesh {
+    /// Creates a new lod mesh. The levels are sorted by their max distance so the order they are
+    /// passed in does not matter. At least one level must be provided.
+    pub fn new(mut levels: Vec<LodLevel>) -> Self {
+        if levels.is_empty() {
+            log::error!("Attempted to create lod mesh without any levels");
+            panic!()
+        }
+        levels.sort_by(|a, b| a.max_distance.total_cmp(&b.max_distance));
+
+        Self {
+            levels: levels.into_boxed_slice(),
+        }
+    }
+
+    pub fn get_levels(&self) -> &[LodLevel] {
+        &self.levels
+    }
+
+    /// Returns the mesh used at the specified distance from the camera. The level with the
+    /// smallest max distance greater or equal to the distance is used. If the distance exceeds the
+    /// max distance of all levels the least detailed level is used.
+    pub fn select(&self, distance: f32) -> &Arc<GlobalMesh> {
+        let index = select_level(self.levels.iter().map(|level| level.max_distance), distance);
+        &self.levels[index].mesh
+    }
+
+    /// Returns the mesh used for an object centered at the position when viewed from the camera
+    /// position. Both positions must be in the same space.
+    pub fn select_for_camera(&self, center: &Vec3f32, camera_position: &Vec3f32) -> &Arc<GlobalMesh> {
+        self.select((center - camera_position).norm())
+    }
+}
+
+/// Returns the index of the level used at the distance. The max distances must be sorted in
+/// ascending order.
+fn select_level<I: ExactSizeIterator<Item = f32>>(max_distances: I, distance: f32) -> usize {
+    let count = max_distances.len();
+    max_distances.into_iter().position(|max_distance| distance <= max_distance).unwrap_or(count - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_selection() {
+        let distances = [16f32, 64f32, 256f32];
+        let select = |distance: f32| select_level(distances.iter().copied(), distance);
+
+        assert_eq!(select(0f32), 0);
+        assert_eq!(select(16f32), 0);
+        assert_eq!(select(16.5f32), 1);
+        assert_eq!(select(200f32), 2);
+        assert_eq!(select(1000f32), 2);
+        assert_eq!(select(f32::INFINITY), 2);
+
+        assert_eq!(select_level([8f32].into_iter(), 100f32), 0);
+    }
+}
@@ -0,0 +1,191 @@
+//! Progress markers written by the worker between the recording stages of a pass.
+//!
+//! If the device is lost the last marker reached by each queue is included in the crash report to
+//! state which pass, segment and stage was executing. With VK_NV_device_diagnostic_checkpoints the
+//! markers are written as checkpoints. Otherwise they are written into a host visible buffer which
+//! can still be read after the device has been lost.
+
+use std::ffi::c_void;
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::allocator::{Allocation, HostAccess};
+use crate::device::crash::Checkpoint;
+
+use crate::prelude::*;
+
+/// The stage of a pass which is started once a marker is reached.
+///
+/// Global object updates are submitted before the immediate buffer uploads of a segment. If the
+/// last reached marker is the end of a pass the global object updates of the next pass were
+/// executing.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(super) enum MarkerStage {
+    /// Immediate buffer uploads of a segment.
+    Uploads = 1,
+
+    /// The pipeline commands of a segment. For the last segment this includes the outputs.
+    Draws = 2,
+
+    /// The end of the pass.
+    End = 3,
+
+    /// Async compute work submitted before a segment of the pass.
+    AsyncCompute = 4,
+}
+
+impl MarkerStage {
+    fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            1 => Some(Self::Uploads),
+            2 => Some(Self::Draws),
+            3 => Some(Self::End),
+            4 => Some(Self::AsyncCompute),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(super) struct ProgressMarker {
+    pub pass_id: u64,
+    pub segment: u32,
+    pub stage: MarkerStage,
+}
+
+impl ProgressMarker {
+    pub fn new(pass_id: u64, segment: u32, stage: MarkerStage) -> Self {
+        Self {
+            pass_id,
+            segment,
+            stage,
+        }
+    }
+
+    /// Encodes the marker into a 64 bit value. Markers of the same queue are ordered by execution.
+    /// 0 is never a valid marker.
+    pub fn encode(&self) -> u64 {
+        (self.pass_id << 24) | (((self.segment as u64) & 0xFFFF) << 8) | (self.stage as u64)
+    }
+
+    pub fn decode(marker: u64) -> Option<Self> {
+        Some(Self {
+            pass_id: marker >> 24,
+            segment: ((marker >> 8) & 0xFFFF) as u32,
+            stage: MarkerStage::from_raw((marker & 0xFF) as u8)?,
+        })
+    }
+}
+
+/// The queue a marker is written on. Each queue has its own last reached marker.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(super) enum MarkerQueue {
+    Main = 0,
+    AsyncCompute = 1,
+}
+
+pub(super) struct ProgressMarkers {
+    device: Arc<DeviceContext>,
+
+    /// The fallback buffer containing the last reached marker of each queue. [`None`] if
+    /// checkpoints are used or the buffer could not be created.
+    buffer: Option<(vk::Buffer, Allocation, NonNull<u8>)>,
+}
+
+impl ProgressMarkers {
+    pub fn new(device: Arc<DeviceContext>) -> Self {
+        let buffer = if device.diagnostic_checkpoints_nv().is_none() {
+            Self::create_buffer(&device)
+        } else {
+            None
+        };
+
+        Self {
+            device,
+            buffer,
+        }
+    }
+
+    fn create_buffer(device: &DeviceContext) -> Option<(vk::Buffer, Allocation, NonNull<u8>)> {
+        let info = vk::BufferCreateInfo::builder()
+            .size(16)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let (buffer, allocation, mapped_ptr) = unsafe {
+            device.get_allocator().create_buffer(&info, HostAccess::Random, &format_args!("ProgressMarkerBuffer"))
+        }.or_else(|| {
+            log::warn!("Failed to create progress marker buffer. Crash reports will not contain progress markers");
+            None
+        })?;
+        let mapped_ptr = mapped_ptr.unwrap();
+
+        unsafe {
+            std::ptr::write_bytes(mapped_ptr.as_ptr(), 0, 16);
+        }
+
+        Some((buffer, allocation, mapped_ptr))
+    }
+
+    /// Records a marker into the command buffer. The command buffer must not be inside a render
+    /// pass and must be submitted to the specified queue.
+    pub fn write(&self, cmd: vk::CommandBuffer, queue: MarkerQueue, marker: ProgressMarker) {
+        let encoded = marker.encode();
+        if let Some(checkpoints) = self.device.diagnostic_checkpoints_nv() {
+            unsafe {
+                checkpoints.cmd_set_checkpoint(cmd, encoded as usize as *const c_void);
+            }
+        } else if let Some((buffer, _, _)) = &self.buffer {
+            unsafe {
+                self.device.vk().cmd_update_buffer(cmd, *buffer, (queue as vk::DeviceSize) * 8, &encoded.to_ne_bytes());
+            }
+        }
+    }
+
+    /// Returns the last marker reached by the queue. Must only be called after the device has been
+    /// lost or all work of the queue has completed.
+    pub fn get_last_reached(&self, queue: MarkerQueue) -> Option<ProgressMarker> {
+        if self.device.diagnostic_checkpoints_nv().is_some() {
+            let queue = match queue {
+                MarkerQueue::Main => self.device.get_main_queue(),
+                MarkerQueue::AsyncCompute => self.device.get_async_compute_queue()?,
+            };
+            Checkpoint::query(queue).iter().filter_map(|checkpoint| ProgressMarker::decode(checkpoint.marker)).max_by_key(ProgressMarker::encode)
+        } else {
+            let (_, _, mapped_ptr) = self.buffer.as_ref()?;
+            let encoded = unsafe {
+                std::ptr::read_volatile((mapped_ptr.as_ptr() as *const u64).add(queue as usize))
+            };
+            ProgressMarker::decode(encoded)
+        }
+    }
+}
+
+impl Drop for ProgressMarkers {
+    fn drop(&mut self) {
+        if let Some((buffer, allocation, _)) = self.buffer.take() {
+            unsafe {
+                self.device.get_allocator().destroy_buffer(buffer, allocation);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marker_encoding() {
+        let marker = ProgressMarker::new(1234, 3, MarkerStage::Draws);
+        assert_eq!(ProgressMarker::decode(marker.encode()), Some(marker));
+        assert_eq!(ProgressMarker::decode(0), None);
+
+        // Later stages and passes encode to larger values
+        assert!(ProgressMarker::new(1234, 3, MarkerStage::End).encode() > marker.encode());
+        assert!(ProgressMarker::new(1234, 4, MarkerStage::Uploads).encode() > marker.encode());
+        assert!(ProgressMarker::new(1235, 0, MarkerStage::Uploads).encode() > marker.encode());
+    }
+}
@@ -0,0 +1,179 @@
+//! Model view matrix stack mirroring minecrafts `PoseStack`.
+
+use nalgebra::Unit;
+
+use crate::prelude::*;
+
+/// A stack of model view matrices.
+///
+/// All transformations are applied to the matrix at the top of the stack and are applied before
+/// any previous transformations, matching the behaviour of `PoseStack`. Additionally a chunk
+/// offset can be set which is applied first. This allows chunk meshes to use the same stack as
+/// every other object without having to use the `ChunkOffset` uniform.
+///
+/// Every recorder owns a matrix stack. See [`DrawRecorder::get_matrix_stack`](super::DrawRecorder::get_matrix_stack).
+#[derive(Clone)]
+pub struct MatrixStack {
+    stack: Vec<Mat4f32>,
+    chunk_offset: Vec3f32,
+    version: u64,
+}
+
+impl MatrixStack {
+    pub fn new() -> Self {
+        let mut stack = Vec::with_capacity(16);
+        stack.push(Mat4f32::identity());
+
+        Self {
+            stack,
+            chunk_offset: Vec3f32::zeros(),
+            version: 0,
+        }
+    }
+
+    /// Pushes a copy of the current matrix onto the stack.
+    pub fn push(&mut self) {
+        let top = *self.top();
+        self.stack.push(top);
+    }
+
+    /// Removes the current matrix from the stack. The last matrix is never removed.
+    pub fn pop(&mut self) {
+        if self.stack.len() == 1 {
+            log::warn!("Called MatrixStack::pop with no pushed matrix. Ignoring!");
+            return;
+        }
+        self.stack.pop();
+        self.version += 1;
+    }
+
+    /// Returns the number of matrices pushed onto the stack.
+    pub fn depth(&self) -> usize {
+        self.stack.len() - 1
+    }
+
+    pub fn set_identity(&mut self) {
+        *self.top_mut() = Mat4f32::identity();
+    }
+
+    pub fn translate(&mut self, translation: &Vec3f32) {
+        self.multiply(&Mat4f32::new_translation(translation));
+    }
+
+    pub fn scale(&mut self, scale: &Vec3f32) {
+        self.multiply(&Mat4f32::new_nonuniform_scaling(scale));
+    }
+
+    /// Rotates around the axis by the angle in radians.
+    pub fn rotate(&mut self, axis: &Vec3f32, angle: f32) {
+        self.multiply(&Mat4f32::from_axis_angle(&Unit::new_normalize(*axis), angle));
+    }
+
+    pub fn multiply(&mut self, matrix: &Mat4f32) {
+        let top = self.top_mut();
+        *top = *top * matrix;
+    }
+
+    /// Sets the chunk offset applied before the current matrix. Set it to 0 once all chunks have
+    /// been drawn.
+    pub fn set_chunk_offset(&mut self, chunk_offset: Vec3f32) {
+        if self.chunk_offset != chunk_offset {
+            self.chunk_offset = chunk_offset;
+            self.version += 1;
+        }
+    }
+
+    /// Returns the current matrix without the chunk offset.
+    pub fn get(&self) -> &Mat4f32 {
+        self.top()
+    }
+
+    /// Returns the current matrix composed with the chunk offset.
+    pub fn get_model_view(&self) -> Mat4f32 {
+        if self.chunk_offset == Vec3f32::zeros() {
+            *self.top()
+        } else {
+            self.top() * Mat4f32::new_translation(&self.chunk_offset)
+        }
+    }
+
+    /// Returns a value which changes every time the model view matrix may have changed. Is 0 if
+    /// the stack has never been modified.
+    pub(super) fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    /// Replaces this stack with a previously cloned stack. If the saved stack has been modified
+    /// the version is increased so that the model view matrix is uploaded again.
+    pub(super) fn restore(&mut self, saved: MatrixStack) {
+        let version = self.version.max(saved.version) + 1;
+        let modified = saved.version != 0;
+        *self = saved;
+        if modified {
+            self.version = version;
+        }
+    }
+
+    fn top(&self) -> &Mat4f32 {
+        self.stack.last().unwrap()
+    }
+
+    fn top_mut(&mut self) -> &mut Mat4f32 {
+        self.version += 1;
+        self.stack.last_mut().unwrap()
+    }
+}
+
+impl Default for MatrixStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point3;
+
+    use super::*;
+
+    #[test]
+    fn push_pop() {
+        let mut stack = MatrixStack::new();
+        stack.translate(&Vec3f32::new(1f32, 0f32, 0f32));
+        stack.push();
+        stack.scale(&Vec3f32::new(2f32, 2f32, 2f32));
+        assert_eq!(stack.depth(), 1);
+        assert_eq!(stack.get().transform_point(&Point3::new(1f32, 1f32, 1f32)), Point3::new(3f32, 2f32, 2f32));
+
+        stack.pop();
+        stack.pop();
+        assert_eq!(stack.depth(), 0);
+        assert_eq!(*stack.get(), Mat4f32::new_translation(&Vec3f32::new(1f32, 0f32, 0f32)));
+    }
+
+    #[test]
+    fn chunk_offset() {
+        let mut stack = MatrixStack::new();
+        stack.scale(&Vec3f32::new(2f32, 2f32, 2f32));
+        stack.set_chunk_offset(Vec3f32::new(16f32, 0f32, 0f32));
+
+        let model_view = stack.get_model_view();
+        assert_eq!(model_view.transform_point(&Point3::new(1f32, 0f32, 0f32)), Point3::new(34f32, 0f32, 0f32));
+    }
+
+    #[test]
+    fn version() {
+        let mut stack = MatrixStack::new();
+        assert_eq!(stack.get_version(), 0);
+
+        stack.push();
+        assert_eq!(stack.get_version(), 0);
+
+        stack.translate(&Vec3f32::new(1f32, 0f32, 0f32));
+        let version = stack.get_version();
+        assert_ne!(version, 0);
+
+        stack.set_chunk_offset(Vec3f32::zeros());
+        assert_eq!(stack.get_version(), version);
+    }
+}
@@ -18,17 +18,19 @@ pub trait ShaderDropListener {
 pub struct Shader {
     id: ShaderId,
     vertex_format: VertexFormat,
+    instance_format: Option<InstanceFormat>,
     used_uniforms: McUniform,
     weak: Weak<Self>,
     listeners: Mutex<HashMap<UUID, Weak<dyn ShaderDropListener + Send + Sync>>>,
 }
 
 impl Shader {
-    pub fn new(vertex_format: VertexFormat, used_uniforms: McUniform) -> Arc<Self> {
+    pub fn new(id: ShaderId, vertex_format: VertexFormat, instance_format: Option<InstanceFormat>, used_uniforms: McUniform) -> Arc<Self> {
         Arc::new_cyclic(|weak| {
             Self {
-                id: ShaderId::new(),
+                id,
                 vertex_format,
+                instance_format,
                 used_uniforms,
                 weak: weak.clone(),
                 listeners: Mutex::new(HashMap::new()),
@@ -44,6 +46,12 @@ impl Shader {
         &self.vertex_format
     }
 
+    /// Returns the per instance data read by instanced draws using this shader. [`None`] if the
+    /// shader cannot be used for instanced draws.
+    pub fn get_instance_format(&self) -> Option<&InstanceFormat> {
+        self.instance_format.as_ref()
+    }
+
     pub fn get_used_uniforms(&self) -> McUniform {
         self.used_uniforms
     }
@@ -133,6 +141,7 @@ impl McUniform {
     pub const LINE_WIDTH: Self = Self::from_raw(1u64 << 12);
     pub const GAME_TIME: Self = Self::from_raw(1u64 << 13);
     pub const CHUNK_OFFSET: Self = Self::from_raw(1u64 << 14);
+    pub const BONE_MATRICES: Self = Self::from_raw(1u64 << 15);
 }
 
 impl BitOr for McUniform {
@@ -192,7 +201,7 @@ impl Not for McUniform {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum McUniformData {
     ModelViewMatrix(Mat4f32),
     ProjectionMatrix(Mat4f32),
@@ -243,4 +252,34 @@ pub struct VertexFormat {
     pub uv0: Option<VertexFormatEntry>,
     pub uv1: Option<VertexFormatEntry>,
     pub uv2: Option<VertexFormatEntry>,
+
+    /// The indices of the 4 bones influencing a vertex. Must be a unsigned integer format.
+    pub joint_indices: Option<VertexFormatEntry>,
+
+    /// The weights of the 4 bones influencing a vertex.
+    pub joint_weights: Option<VertexFormatEntry>,
+}
+
+impl VertexFormat {
+    /// Returns true if the format provides both joint channels needed for gpu skinning.
+    pub fn has_joints(&self) -> bool {
+        self.joint_indices.is_some() && self.joint_weights.is_some()
+    }
+}
+
+/// The layout of the per instance data of instanced draws. Every instance reads `stride` bytes
+/// from the instance data of the draw. See
+/// [`DrawRecorder::draw_static_instanced`](super::DrawRecorder::draw_static_instanced).
+#[derive(Copy, Clone, Debug)]
+pub struct InstanceFormat {
+    pub stride: u32,
+
+    /// The offset added to the position of every vertex of the instance before the model view
+    /// matrix is applied.
+    pub offset: VertexFormatEntry,
+}
+
+impl InstanceFormat {
+    /// The largest stride every vulkan implementation supports for vertex input bindings.
+    pub const MAX_STRIDE: u32 = 2048;
 }
\ No newline at end of file
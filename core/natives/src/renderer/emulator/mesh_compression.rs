@@ -0,0 +1,217 @@
+//! Compressed mesh payloads which are decompressed while uploading.
+//!
+//! Allows chunk meshes to be cached on disk in compressed form and uploaded without first
+//! decompressing them into a separate host allocation. Two formats are supported:
+//! - [`MeshCompression::Deflate`] is decompressed on the host directly into staging memory.
+//! - [`MeshCompression::GDeflate`] is copied into staging memory as is and decompressed on the
+//!   device using `VK_NV_memory_decompression`. Only available if
+//!   [`EmulatorRenderer::supports_device_decompression`](super::EmulatorRenderer::supports_device_decompression)
+//!   returns true. There is no host fallback for this format so callers should select the format
+//!   when writing their cache.
+//!
+//! The decompressed payload must contain the vertex data immediately followed by the index data.
+
+use ash::vk;
+
+use crate::renderer::emulator::MeshData;
+
+/// The number of bytes each GDeflate page decompresses to. Only the last page may be smaller.
+pub const GDEFLATE_PAGE_SIZE: u32 = 65536;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum MeshCompression {
+    /// A zlib wrapped deflate stream.
+    Deflate,
+
+    /// A list of independent GDeflate 1.0 streams each decompressing to one page of
+    /// [`GDEFLATE_PAGE_SIZE`] bytes.
+    GDeflate,
+}
+
+/// A compressed version of [`MeshData`].
+pub struct CompressedMeshData<'a> {
+    pub compression: MeshCompression,
+    pub payload: &'a [u8],
+
+    /// The compressed size of every page of a [`MeshCompression::GDeflate`] payload in order.
+    /// Must be empty for other formats.
+    pub page_sizes: &'a [u32],
+
+    /// The size of the decompressed vertex data in bytes.
+    pub vertex_data_size: u32,
+
+    /// The size of the decompressed index data in bytes. See [`MeshData`] for meshes using the
+    /// shared quad list index buffer.
+    pub index_data_size: u32,
+
+    pub vertex_stride: u32,
+    pub index_count: u32,
+    pub index_type: vk::IndexType,
+    pub primitive_topology: vk::PrimitiveTopology,
+}
+
+impl<'a> CompressedMeshData<'a> {
+    /// Returns the size of the decompressed payload in bytes.
+    pub fn get_decompressed_size(&self) -> u64 {
+        (self.vertex_data_size as u64) + (self.index_data_size as u64)
+    }
+
+    pub fn uses_quad_indices(&self) -> bool {
+        self.index_data_size == 0 && self.index_count != 0
+    }
+
+    /// Returns a [`MeshData`] with the same properties but empty data. Note that
+    /// [`MeshData::uses_quad_indices`] is not valid for the returned value.
+    pub(super) fn as_empty_mesh_data(&self) -> MeshData<'static> {
+        MeshData {
+            vertex_data: &[],
+            index_data: &[],
+            vertex_stride: self.vertex_stride,
+            index_count: self.index_count,
+            index_type: self.index_type,
+            primitive_topology: self.primitive_topology,
+        }
+    }
+}
+
+/// A host copy of compressed mesh data. See [`CompressedMeshData`].
+#[derive(Clone)]
+pub struct OwnedCompressedMeshData {
+    pub compression: MeshCompression,
+    pub payload: Box<[u8]>,
+    pub page_sizes: Box<[u32]>,
+    pub vertex_data_size: u32,
+    pub index_data_size: u32,
+    pub vertex_stride: u32,
+    pub index_count: u32,
+    pub index_type: vk::IndexType,
+    pub primitive_topology: vk::PrimitiveTopology,
+}
+
+impl OwnedCompressedMeshData {
+    /// Compresses mesh data using [`MeshCompression::Deflate`]. The level ranges from 0 to 10.
+    pub fn compress_deflate(data: &MeshData<'_>, level: u8) -> Self {
+        let mut decompressed = Vec::with_capacity(data.vertex_data.len() + data.index_data.len());
+        decompressed.extend_from_slice(data.vertex_data);
+        decompressed.extend_from_slice(data.index_data);
+
+        Self {
+            compression: MeshCompression::Deflate,
+            payload: miniz_oxide::deflate::compress_to_vec_zlib(&decompressed, level).into_boxed_slice(),
+            page_sizes: Box::new([]),
+            vertex_data_size: data.vertex_data.len() as u32,
+            index_data_size: data.index_data.len() as u32,
+            vertex_stride: data.vertex_stride,
+            index_count: data.index_count,
+            index_type: data.index_type,
+            primitive_topology: data.primitive_topology,
+        }
+    }
+
+    pub fn as_compressed_mesh_data(&self) -> CompressedMeshData<'_> {
+        CompressedMeshData {
+            compression: self.compression,
+            payload: &self.payload,
+            page_sizes: &self.page_sizes,
+            vertex_data_size: self.vertex_data_size,
+            index_data_size: self.index_data_size,
+            vertex_stride: self.vertex_stride,
+            index_count: self.index_count,
+            index_type: self.index_type,
+            primitive_topology: self.primitive_topology,
+        }
+    }
+
+    pub(super) fn get_decompressed_size(&self) -> u64 {
+        (self.vertex_data_size as u64) + (self.index_data_size as u64)
+    }
+}
+
+/// Decompresses a [`MeshCompression::Deflate`] payload into `dst`. Returns false if the payload
+/// is invalid or does not decompress to exactly the size of `dst`.
+pub(super) fn inflate_into(payload: &[u8], dst: &mut [u8]) -> bool {
+    match miniz_oxide::inflate::decompress_slice_iter_to_slice(dst, std::iter::once(payload), true, false) {
+        Ok(size) => size == dst.len(),
+        Err(_) => false,
+    }
+}
+
+/// Generates the device decompression regions of a [`MeshCompression::GDeflate`] payload stored
+/// at `src_address` which decompresses to `dst_address`. Returns [`None`] if the page sizes do not
+/// match the payload or decompressed size.
+pub(super) fn gdeflate_regions(src_address: vk::DeviceAddress, dst_address: vk::DeviceAddress, payload_size: u64, page_sizes: &[u32], decompressed_size: u64) -> Option<Box<[vk::DecompressMemoryRegionNV]>> {
+    let page_count = (decompressed_size + (GDEFLATE_PAGE_SIZE as u64) - 1) / (GDEFLATE_PAGE_SIZE as u64);
+    if page_sizes.len() as u64 != page_count {
+        return None;
+    }
+    if page_sizes.iter().map(|size| *size as u64).sum::<u64>() != payload_size {
+        return None;
+    }
+
+    let mut src_offset = 0u64;
+    let regions = page_sizes.iter().enumerate().map(|(page, compressed_size)| {
+        let dst_offset = (page as u64) * (GDEFLATE_PAGE_SIZE as u64);
+        let region = vk::DecompressMemoryRegionNV {
+            src_address: src_address + src_offset,
+            dst_address: dst_address + dst_offset,
+            compressed_size: *compressed_size as u64,
+            decompressed_size: std::cmp::min(decompressed_size - dst_offset, GDEFLATE_PAGE_SIZE as u64),
+            decompression_method: vk::MemoryDecompressionMethodFlagsNV::GDEFLATE_1_0,
+        };
+        src_offset += *compressed_size as u64;
+        region
+    }).collect();
+
+    Some(regions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deflate_round_trip() {
+        let vertices: Vec<u8> = (0..1024u32).flat_map(|i| (i % 17).to_le_bytes()).collect();
+        let indices: Vec<u8> = (0..96u16).flat_map(|i| i.to_le_bytes()).collect();
+        let data = MeshData {
+            vertex_data: &vertices,
+            index_data: &indices,
+            vertex_stride: 16,
+            index_count: 96,
+            index_type: vk::IndexType::UINT16,
+            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        };
+
+        let compressed = OwnedCompressedMeshData::compress_deflate(&data, 6);
+        assert!(compressed.payload.len() < vertices.len() + indices.len());
+
+        let mut dst = vec![0u8; compressed.get_decompressed_size() as usize];
+        assert!(inflate_into(&compressed.payload, &mut dst));
+        assert_eq!(&dst[..vertices.len()], vertices.as_slice());
+        assert_eq!(&dst[vertices.len()..], indices.as_slice());
+
+        // Size mismatches are rejected
+        let mut small = vec![0u8; dst.len() - 1];
+        assert!(!inflate_into(&compressed.payload, &mut small));
+        let mut large = vec![0u8; dst.len() + 1];
+        assert!(!inflate_into(&compressed.payload, &mut large));
+        assert!(!inflate_into(&compressed.payload[..compressed.payload.len() / 2], &mut dst));
+    }
+
+    #[test]
+    fn gdeflate_pages() {
+        let size = (GDEFLATE_PAGE_SIZE as u64) * 2 + 100;
+        let regions = gdeflate_regions(1000, 5000, 60, &[20, 30, 10], size).unwrap();
+
+        assert_eq!(regions.len(), 3);
+        assert_eq!(regions[1].src_address, 1020);
+        assert_eq!(regions[1].dst_address, 5000 + GDEFLATE_PAGE_SIZE as u64);
+        assert_eq!(regions[1].decompressed_size, GDEFLATE_PAGE_SIZE as u64);
+        assert_eq!(regions[2].src_address, 1050);
+        assert_eq!(regions[2].compressed_size, 10);
+        assert_eq!(regions[2].decompressed_size, 100);
+
+        assert!(gdeflate_regions(1000, 5000, 60, &[20, 40], size).is_none());
+        assert!(gdeflate_regions(1000, 5000, 61, &[20, 30, 10], size).is_none());
+    }
+}
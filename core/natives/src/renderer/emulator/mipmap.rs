@@ -0,0 +1,172 @@
+//! Compute based mipmap generation for images using [`MipmapMode::AlphaWeighted`].
+//!
+//! Every texel of a mip level is the average of the corresponding 2x2 texels of the previous level
+//! with the color weighted by alpha. This matches the behaviour of the minecraft mipmap generator
+//! and prevents cutout textures like leaves from darkening at a distance.
+//!
+//! [`MipmapMode::AlphaWeighted`]: crate::renderer::emulator::MipmapMode::AlphaWeighted
+
+use std::ffi::CStr;
+use std::sync::Arc;
+
+use ash::vk;
+use bytemuck::{bytes_of, Pod, Zeroable};
+use include_bytes_aligned::include_bytes_aligned;
+
+use crate::device::device_utils::create_shader_from_bytes;
+
+use crate::prelude::*;
+
+/// The workgroup size of the compute shader in each dimension.
+const WORKGROUP_SIZE: u32 = 8;
+
+pub(super) struct AlphaMipmapPipeline {
+    device: Arc<DeviceContext>,
+
+    /// Owned by the layout cache of the device.
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl AlphaMipmapPipeline {
+    pub(super) fn new(device: Arc<DeviceContext>) -> Result<Self, vk::Result> {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                p_immutable_samplers: std::ptr::null(),
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                p_immutable_samplers: std::ptr::null(),
+            },
+        ];
+
+        let layout_cache = device.get_utils().layout_cache();
+
+        let set_layout = layout_cache.get_descriptor_set_layout(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR, &bindings).map_err(|err| {
+            log::error!("vkCreateDescriptorSetLayout returned {:?} in AlphaMipmapPipeline::new", err);
+            err
+        })?;
+
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: std::mem::size_of::<PushConstants>() as u32,
+        };
+
+        let pipeline_layout = layout_cache.get_pipeline_layout(std::slice::from_ref(&set_layout), std::slice::from_ref(&push_constant_range)).map_err(|err| {
+            log::error!("vkCreatePipelineLayout returned {:?} in AlphaMipmapPipeline::new", err);
+            err
+        })?;
+
+        let module = create_shader_from_bytes(device.get_functions(), ALPHA_MIPMAP_COMPUTE_BIN).map_err(|err| {
+            log::error!("vkCreateShaderModule returned {:?} in AlphaMipmapPipeline::new", err);
+            err
+        })?;
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(module)
+            .name(SHADER_ENTRY);
+
+        let info = vk::ComputePipelineCreateInfo::builder()
+            .stage(*stage)
+            .layout(pipeline_layout);
+
+        let pipeline = unsafe {
+            device.vk().create_compute_pipelines(vk::PipelineCache::null(), std::slice::from_ref(&info), None)
+        };
+        unsafe { device.vk().destroy_shader_module(module, None) };
+
+        let pipeline = pipeline.map_err(|(_, err)| {
+            log::error!("vkCreateComputePipelines returned {:?} in AlphaMipmapPipeline::new", err);
+            err
+        })?[0];
+
+        Ok(Self {
+            device,
+            pipeline_layout,
+            pipeline,
+        })
+    }
+
+    pub(super) fn bind(&self, cmd: vk::CommandBuffer) {
+        unsafe {
+            self.device.vk().cmd_bind_pipeline(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+        }
+    }
+
+    /// Records the generation of a single mip level. The pipeline must be bound and both views
+    /// must be in the [`vk::ImageLayout::GENERAL`] layout.
+    pub(super) fn record_level(&self, cmd: vk::CommandBuffer, src_view: vk::ImageView, src_size: Vec2u32, dst_view: vk::ImageView, dst_size: Vec2u32, srgb: bool) {
+        let image_infos = [
+            vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: src_view,
+                image_layout: vk::ImageLayout::GENERAL,
+            },
+            vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: dst_view,
+                image_layout: vk::ImageLayout::GENERAL,
+            },
+        ];
+
+        let writes = [
+            vk::WriteDescriptorSet::builder()
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(&image_infos[0..1])
+                .build(),
+            vk::WriteDescriptorSet::builder()
+                .dst_binding(1)
+                .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+                .image_info(&image_infos[1..2])
+                .build(),
+        ];
+
+        let push_constants = PushConstants {
+            src_size: [src_size[0] as i32, src_size[1] as i32],
+            dst_size: [dst_size[0] as i32, dst_size[1] as i32],
+            srgb: if srgb { 1 } else { 0 },
+        };
+
+        unsafe {
+            self.device.push_descriptor_khr().cmd_push_descriptor_set(cmd, vk::PipelineBindPoint::COMPUTE, self.pipeline_layout, 0, &writes);
+            self.device.vk().cmd_push_constants(cmd, self.pipeline_layout, vk::ShaderStageFlags::COMPUTE, 0, bytes_of(&push_constants));
+            self.device.vk().cmd_dispatch(
+                cmd,
+                (dst_size[0] + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                (dst_size[1] + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE,
+                1
+            );
+        }
+    }
+}
+
+impl Drop for AlphaMipmapPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.vk().destroy_pipeline(self.pipeline, None);
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PushConstants {
+    src_size: [i32; 2],
+    dst_size: [i32; 2],
+    srgb: u32,
+}
+unsafe impl Zeroable for PushConstants {}
+unsafe impl Pod for PushConstants {}
+
+const SHADER_ENTRY: &CStr = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
+static ALPHA_MIPMAP_COMPUTE_BIN: &'static [u8] = include_bytes_aligned!(4, concat!(env!("B4D_RESOURCE_DIR"), "emulator/atlas/alpha_mipmap_comp.spv"));
@@ -19,52 +19,131 @@ mod pass;
 
 pub mod pipeline;
 pub mod debug_pipeline;
+pub mod fallback_pipeline;
 pub mod mc_shaders;
+pub mod capture;
+pub mod replay;
+pub mod golden;
+pub mod atlas;
+pub mod tessellator;
+pub mod render_layer;
+pub mod matrix_stack;
+pub mod lines;
+pub mod weather;
+pub mod world_border;
+pub mod panorama;
+pub mod readback;
+pub mod glint;
+pub mod gui;
+pub mod dynamic_texture;
+pub mod gpu_culling;
+pub mod frame_stream;
+pub mod render_graph;
+pub mod split_screen;
+pub mod projection;
+pub mod vertex_compaction;
+pub mod lod;
+pub mod texture_loader;
+pub mod streaming;
+pub mod probe;
+pub mod resource_set;
+pub mod bundle;
+pub mod lights;
+pub mod visibility;
+pub mod uniform_interpolation;
+pub mod compositor;
+pub mod mesh_compression;
+pub mod completion;
+pub mod upload_batch;
+pub mod block_layout;
+#[cfg(feature = "image-loader")]
+pub mod image_loader;
 mod descriptors;
+mod barrier_batch;
+mod markers;
 mod share;
 mod staging;
+mod watchdog;
+mod mipmap;
+mod quad_indices;
+mod pipeline_compiler;
+mod recorder_state;
 
 use std::fmt::{Debug, Formatter};
 use std::panic::RefUnwindSafe;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use ash::vk;
 use bytemuck::cast_slice;
 
+use crate::renderer::emulator::watchdog::run_watchdog;
 use crate::renderer::emulator::worker::run_worker;
 use crate::renderer::emulator::pipeline::EmulatorPipeline;
 
 use crate::prelude::*;
 
-pub use global_objects::{GlobalMesh, GlobalImage, ImageData, SamplerInfo};
+pub use global_objects::{GlobalMesh, GlobalObjectCreateError, GlobalImage, ImageCopyRegion, ImageData, MipmapMode, SamplerInfo};
 
 pub use pass::PassId;
+pub use pass::DrawRecorder;
 pub use pass::PassRecorder;
+pub use pass::SubPassRecorder;
 pub use pass::ImmediateMeshId;
+pub use descriptors::UniformStatistics;
 
 use share::Share;
-use crate::renderer::emulator::mc_shaders::{McUniform, Shader, ShaderId, VertexFormat};
+use quad_indices::QuadIndexBuffer;
+use crate::renderer::emulator::mc_shaders::{InstanceFormat, McUniform, Shader, ShaderId, VertexFormat};
+use crate::renderer::emulator::mesh_compression::CompressedMeshData;
+use crate::renderer::emulator::render_layer::{RenderLayer, RenderLayerId, RenderLayerInfo};
 use crate::util::format::Format;
 
 pub struct EmulatorRenderer {
     share: Arc<Share>,
+    quad_indices: Arc<QuadIndexBuffer>,
     placeholder_image: Arc<GlobalImage>,
     placeholder_sampler: SamplerInfo,
     worker: std::thread::JoinHandle<()>,
 }
 
+/// Set while a deterministic renderer is alive. See [`EmulatorRenderer::new_deterministic`].
+static DETERMINISTIC_RENDERER_ALIVE: AtomicBool = AtomicBool::new(false);
+
 impl EmulatorRenderer {
     pub(crate) fn new(device: Arc<DeviceContext>) -> Self {
-        let share = Arc::new(Share::new(device.clone()));
+        Self::new_with_config(device, None)
+    }
+
+    /// Creates a renderer processing all work in a fully deterministic order. See
+    /// [`DeterministicConfig`].
+    ///
+    /// Returns [`None`] if another deterministic renderer is still alive.
+    pub(crate) fn new_deterministic(device: Arc<DeviceContext>, config: &DeterministicConfig) -> Option<Self> {
+        if DETERMINISTIC_RENDERER_ALIVE.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return None;
+        }
+
+        Some(Self::new_with_config(device, Some(config)))
+    }
+
+    fn new_with_config(device: Arc<DeviceContext>, deterministic: Option<&DeterministicConfig>) -> Self {
+        let share = Arc::new(Share::new(device.clone(), deterministic));
 
         let share2 = share.clone();
-        let worker = std::thread::spawn(move || {
+        let worker = std::thread::Builder::new().name("B4D Emulator Worker".to_string()).spawn(move || {
             std::panic::catch_unwind(|| {
                 run_worker(device,share2);
             }).unwrap_or_else(|_| {
                 log::error!("Emulator worker panicked!");
                 std::process::exit(1);
             })
-        });
+        }).unwrap();
+
+        let share3 = Arc::downgrade(&share);
+        std::thread::Builder::new().name("B4D Emulator Watchdog".to_string()).spawn(move || {
+            run_watchdog(share3);
+        }).unwrap();
 
         let placeholder_image = Self::create_placeholder_image(share.clone());
         let placeholder_sampler = SamplerInfo {
@@ -78,6 +157,7 @@ impl EmulatorRenderer {
 
         Self {
             share,
+            quad_indices: Arc::new(QuadIndexBuffer::new()),
             placeholder_image,
             placeholder_sampler,
             worker,
@@ -88,20 +168,62 @@ impl EmulatorRenderer {
         self.share.get_device()
     }
 
+    /// Configures if the data of global meshes should be retained on the host so that they can be
+    /// included in frame captures. Only affects meshes created after this call.
+    pub fn set_retain_capture_data(&self, retain: bool) {
+        self.share.set_retain_capture_data(retain);
+    }
+
+    /// Sets the maximum amount of host visible memory in bytes used to stage uploads of global
+    /// objects. Once the budget is exhausted creating or updating global objects blocks until
+    /// previous uploads have completed. Defaults to 256MB.
+    pub fn set_staging_budget(&self, budget: u64) {
+        self.share.set_staging_budget(budget);
+    }
+
     pub fn create_global_mesh(&self, data: &MeshData) -> Arc<GlobalMesh> {
-        GlobalMesh::new(self.share.clone(), data).unwrap()
+        GlobalMesh::new(self.share.clone(), data, &self.quad_indices).unwrap()
+    }
+
+    /// Creates a global mesh uploading the data in chunks of at most `chunk_size` bytes. Use this
+    /// for very large meshes which would otherwise require a single staging allocation larger
+    /// than the staging budget. See [`EmulatorRenderer::set_staging_budget`].
+    pub fn create_global_mesh_chunked(&self, data: &MeshData, chunk_size: u64) -> Arc<GlobalMesh> {
+        GlobalMesh::new_chunked(self.share.clone(), data, &self.quad_indices, chunk_size).unwrap()
+    }
+
+    /// Creates a global mesh from compressed data. Fails if the payload is invalid or the
+    /// compression format is not supported by the device.
+    pub fn create_compressed_global_mesh(&self, data: &CompressedMeshData) -> Result<Arc<GlobalMesh>, GlobalObjectCreateError> {
+        GlobalMesh::new_compressed(self.share.clone(), data, &self.quad_indices)
+    }
+
+    /// Returns true if meshes compressed using [`MeshCompression::GDeflate`](mesh_compression::MeshCompression::GDeflate)
+    /// can be decompressed by the device.
+    pub fn supports_device_decompression(&self) -> bool {
+        self.share.get_device().memory_decompression_nv().is_some()
     }
 
     pub fn create_global_image(&self, size: Vec2u32, format: &'static Format) -> Arc<GlobalImage> {
-        GlobalImage::new(self.share.clone(), size, 1, format).unwrap()
+        GlobalImage::new(self.share.clone(), size, 1, MipmapMode::Blit, format).unwrap()
     }
 
     pub fn create_global_image_mips(&self, size: Vec2u32, mip_levels: u32, format: &'static Format) -> Arc<GlobalImage> {
-        GlobalImage::new(self.share.clone(), size, mip_levels, format).unwrap()
+        GlobalImage::new(self.share.clone(), size, mip_levels, MipmapMode::Blit, format).unwrap()
+    }
+
+    pub fn create_global_image_mips_with_mode(&self, size: Vec2u32, mip_levels: u32, mipmap_mode: MipmapMode, format: &'static Format) -> Arc<GlobalImage> {
+        GlobalImage::new(self.share.clone(), size, mip_levels, mipmap_mode, format).unwrap()
     }
 
     pub fn create_shader(&self, vertex_format: &VertexFormat, used_uniforms: McUniform) -> ShaderId {
-        self.share.create_shader(vertex_format, used_uniforms)
+        self.share.create_shader(vertex_format, None, used_uniforms)
+    }
+
+    /// Creates a shader which can be used for instanced draws. See
+    /// [`DrawRecorder::draw_static_instanced`].
+    pub fn create_instanced_shader(&self, vertex_format: &VertexFormat, instance_format: &InstanceFormat, used_uniforms: McUniform) -> ShaderId {
+        self.share.create_shader(vertex_format, Some(instance_format), used_uniforms)
     }
 
     pub fn drop_shader(&self, id: ShaderId) {
@@ -112,8 +234,85 @@ impl EmulatorRenderer {
         self.share.get_shader(id)
     }
 
+    /// Registers a new render layer which can be used by all following passes.
+    pub fn create_render_layer(&self, info: RenderLayerInfo) -> RenderLayerId {
+        self.share.create_render_layer(info)
+    }
+
+    /// Removes a render layer. Passes which have already used the layer are not affected.
+    pub fn drop_render_layer(&self, id: RenderLayerId) {
+        self.share.drop_render_layer(id)
+    }
+
+    pub fn get_render_layer(&self, id: RenderLayerId) -> Option<Arc<RenderLayer>> {
+        self.share.get_render_layer(id)
+    }
+
+    /// Returns statistics about the work performed by this renderer.
+    pub fn get_statistics(&self) -> EmulatorStatistics {
+        self.share.get_statistics()
+    }
+
+    /// Returns the number of passes which have been started but have not yet completed execution
+    /// on the gpu. Includes the currently recording pass.
+    pub fn get_pending_pass_count(&self) -> u64 {
+        self.share.get_pending_pass_count()
+    }
+
+    /// Calls the callback once the pass has completed execution on the gpu. The callback is called
+    /// on the emulator worker thread or immediately if the pass has already completed. Callbacks
+    /// of passes which never complete, for example because the renderer is destroyed, are dropped
+    /// without being called.
+    pub fn on_pass_complete<F: FnOnce() + Send + 'static>(&self, pass: PassId, callback: F) {
+        self.share.add_completion_callback(pass.get_raw(), Box::new(callback));
+    }
+
+    /// Returns true if the pass has completed execution on the gpu. Does not block.
+    pub fn is_pass_complete(&self, pass: PassId) -> bool {
+        self.share.is_pass_completed(pass.get_raw())
+    }
+
+    /// Returns a future which completes once the pass has completed execution on the gpu. See
+    /// [`completion::PassCompletion`].
+    pub fn pass_completion(&self, pass: PassId) -> completion::PassCompletion {
+        completion::PassCompletion::new(self.share.clone(), pass)
+    }
+
+    /// Returns a timeline semaphore and value which is signaled once the pass has completed
+    /// execution on the gpu, including all global object updates submitted with it. Any number of
+    /// submits on any queue of the device may wait on it. The semaphore is owned by the renderer
+    /// and must not be used after the renderer has been destroyed.
+    pub fn get_pass_sync_point(&self, pass: PassId) -> (vk::Semaphore, u64) {
+        (self.share.get_pass_timeline(), pass.get_raw())
+    }
+
+    /// Blocks until less than `max_pending` passes are pending or the timeout elapsed. Returns
+    /// false if the timeout elapsed.
+    pub fn wait_pending_passes(&self, max_pending: u64, timeout: Duration) -> bool {
+        self.share.wait_pending_passes(max_pending, timeout)
+    }
+
+    /// Creates a batch collecting global object updates which are submitted to the worker at
+    /// once. See [`upload_batch`].
+    pub fn create_upload_batch(&self) -> upload_batch::UploadBatch {
+        upload_batch::UploadBatch::new(self.share.clone(), self.quad_indices.clone())
+    }
+
+    /// Creates a recorder for a reusable [`DrawBundle`](bundle::DrawBundle).
+    pub fn create_bundle_recorder(&self) -> bundle::DrawBundleRecorder {
+        bundle::DrawBundleRecorder::new(self.share.clone(), self.quad_indices.clone())
+    }
+
+    fn get_light_culling_pipeline(&self) -> &lights::LightCullingPipeline {
+        self.share.get_light_culling_pipeline()
+    }
+
+    fn get_section_culling_pipeline(&self) -> &gpu_culling::SectionCullingPipeline {
+        self.share.get_section_culling_pipeline()
+    }
+
     pub fn start_pass(&self, pipeline: Arc<dyn EmulatorPipeline>) -> PassRecorder {
-        PassRecorder::new(self.share.clone(), pipeline, self.placeholder_image.clone(), &self.placeholder_sampler)
+        PassRecorder::new(self.share.clone(), self.quad_indices.clone(), pipeline, self.placeholder_image.clone(), &self.placeholder_sampler)
     }
 
     fn create_placeholder_image(share: Arc<Share>) -> Arc<GlobalImage> {
@@ -137,7 +336,7 @@ impl EmulatorRenderer {
             extent: size
         };
 
-        let image = GlobalImage::new(share, size, 1, &Format::R8G8B8A8_SRGB).unwrap();
+        let image = GlobalImage::new(share, size, 1, MipmapMode::Blit, &Format::R8G8B8A8_SRGB).unwrap();
         image.update_regions(std::slice::from_ref(&info));
         image
     }
@@ -155,6 +354,65 @@ impl Eq for EmulatorRenderer {
 impl RefUnwindSafe for EmulatorRenderer { // Join handle is making issues
 }
 
+impl Drop for EmulatorRenderer {
+    fn drop(&mut self) {
+        if self.share.is_deterministic() {
+            DETERMINISTIC_RENDERER_ALIVE.store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Statistics about the work performed by a [`EmulatorRenderer`].
+#[derive(Copy, Clone, Debug)]
+pub struct EmulatorStatistics {
+    /// The number of draw calls recorded in the last ended pass.
+    pub draw_count: u32,
+    /// The total number of bytes uploaded to the gpu since the renderer was created.
+    pub uploaded_bytes: u64,
+    /// The gpu execution time of the last completed pass. [`None`] if no pass has completed yet or
+    /// the queue does not support timestamps.
+    pub gpu_pass_time: Option<Duration>,
+    /// The usage of the uniform buffer memory of passes.
+    pub uniforms: UniformStatistics,
+}
+
+/// Configuration of the deterministic mode of a [`EmulatorRenderer`].
+///
+/// In deterministic mode all ids of the renderer are generated from a single seeded sequence, pools are created with
+/// a fixed size up front and the worker waits for every pass to complete on the gpu before
+/// processing the next task. Global object updates, completion callbacks and resource reuse thus
+/// always happen at the same point of the task stream which allows intermittent synchronization
+/// bugs to be reproduced reliably. This serializes the cpu and gpu and should only be used for
+/// testing. Only one deterministic renderer may be alive at a time as the ids of renderers using
+/// the same seed would collide.
+#[derive(Copy, Clone, Debug)]
+pub struct DeterministicConfig {
+    /// The seed of the id generator.
+    pub seed: u64,
+
+    /// The number of immediate buffers and therefore the number of passes which can be recorded
+    /// ahead of the worker.
+    pub immediate_buffer_count: usize,
+
+    /// The number of uniform blocks created up front.
+    pub uniform_block_count: u64,
+}
+
+impl DeterministicConfig {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            immediate_buffer_count: 2,
+            uniform_block_count: 4,
+        }
+    }
+}
+
+/// The data of a mesh to be uploaded.
+///
+/// If the index data is empty while the index count is not 0 the mesh is drawn using the shared
+/// quad list index buffer of the emulator. In that case the vertices must form a list of quads and
+/// the index count must be 6 times the number of quads. See [`MeshData::new_quads`].
 pub struct MeshData<'a> {
     pub vertex_data: &'a [u8],
     pub index_data: &'a [u8],
@@ -165,6 +423,25 @@ pub struct MeshData<'a> {
 }
 
 impl<'a> MeshData<'a> {
+    /// Creates mesh data for a list of quads using the shared quad list index buffer.
+    pub fn new_quads(vertex_data: &'a [u8], vertex_stride: u32) -> Self {
+        let quad_count = (vertex_data.len() as u32) / (vertex_stride * 4);
+
+        Self {
+            vertex_data,
+            index_data: &[],
+            vertex_stride,
+            index_count: quad_count * 6,
+            index_type: vk::IndexType::UINT32,
+            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        }
+    }
+
+    /// Returns true if the mesh uses the shared quad list index buffer instead of its own indices.
+    pub fn uses_quad_indices(&self) -> bool {
+        self.index_data.is_empty() && self.index_count != 0
+    }
+
     pub fn get_index_size(&self) -> u32 {
         match self.index_type {
             vk::IndexType::UINT8_EXT => 1u32,
@@ -0,0 +1,181 @@
+//! Title screen panorama mirroring minecrafts `PanoramaRenderer` and `CubeMap`.
+//!
+//! The panorama creates its own shader and render layers so it can be used before any world or
+//! resource pack shaders have been registered.
+
+use std::sync::Arc;
+
+use ash::vk;
+use bytemuck::{cast_slice, Pod, Zeroable};
+
+use crate::prelude::*;
+use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, MeshData, PassRecorder, SamplerInfo};
+use crate::renderer::emulator::mc_shaders::{McUniform, McUniformData, ShaderId, VertexFormat, VertexFormatEntry};
+use crate::renderer::emulator::pipeline::{BlendState, CullState};
+use crate::renderer::emulator::render_layer::{RenderLayerId, RenderLayerInfo, RenderLayerTexture};
+
+/// A slowly rotating cube map drawn behind the title screen.
+///
+/// The faces are ordered like the `panorama_<n>.png` textures of vanilla resource packs.
+pub struct Panorama {
+    emulator: Arc<EmulatorRenderer>,
+    shader: ShaderId,
+    layers: [RenderLayerId; 6],
+    time: f32,
+}
+
+impl Panorama {
+    pub const VERTEX_FORMAT: VertexFormat = VertexFormat {
+        stride: std::mem::size_of::<PanoramaVertex>() as u32,
+        position: VertexFormatEntry { offset: 0, format: vk::Format::R32G32B32_SFLOAT },
+        normal: None,
+        color: Some(VertexFormatEntry { offset: 20, format: vk::Format::R8G8B8A8_UNORM }),
+        uv0: Some(VertexFormatEntry { offset: 12, format: vk::Format::R32G32_SFLOAT }),
+        uv1: None,
+        uv2: None,
+        joint_indices: None,
+        joint_weights: None,
+    };
+
+    /// The number of times the cube is drawn with small offsets to blur the faces.
+    const BLUR_PASSES: u32 = 4;
+
+    pub fn new(emulator: Arc<EmulatorRenderer>, faces: [Arc<GlobalImage>; 6]) -> Self {
+        let shader = emulator.create_shader(&Self::VERTEX_FORMAT, McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+
+        let sampler_info = SamplerInfo {
+            mag_filter: vk::Filter::LINEAR,
+            min_filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            anisotropy_enable: false
+        };
+        let layers = faces.map(|image| {
+            let mut info = RenderLayerInfo::new(shader, vk::PrimitiveTopology::TRIANGLE_LIST);
+            info.depth_write_enable = false;
+            info.cull_state = CullState::NONE;
+            info.blend_state = Some(BlendState::TRANSLUCENT);
+            info.textures.push(RenderLayerTexture {
+                index: 0,
+                image,
+                sampler_info,
+            });
+            emulator.create_render_layer(info)
+        });
+
+        Self {
+            emulator,
+            shader,
+            layers,
+            time: 0f32,
+        }
+    }
+
+    /// Advances the rotation. Should be called once per frame with the partial tick.
+    pub fn advance(&mut self, partial_tick: f32) {
+        self.time += partial_tick;
+    }
+
+    /// Returns the x and y rotation of the camera in degrees.
+    pub fn get_rotation(&self) -> (f32, f32) {
+        ((self.time * 0.001f32).sin() * 5f32 + 25f32, -self.time * 0.1f32)
+    }
+
+    /// Draws the panorama. See [`PassRecorder::draw_panorama`].
+    pub(super) fn record(&self, recorder: &mut PassRecorder, aspect_ratio: f32, alpha: f32) {
+        let projection = Mat4f32::new_perspective(aspect_ratio, 85f32.to_radians(), 0.05f32, 10f32);
+        recorder.update_uniform(&McUniformData::ProjectionMatrix(projection), self.shader);
+
+        let (x_rotation, y_rotation) = self.get_rotation();
+        let x_axis = Vec3f32::new(1f32, 0f32, 0f32);
+        let y_axis = Vec3f32::new(0f32, 1f32, 0f32);
+
+        recorder.push_matrix();
+        let stack = recorder.get_matrix_stack();
+        stack.set_identity();
+        stack.rotate(&x_axis, 180f32.to_radians());
+
+        for pass in 0..Self::BLUR_PASSES {
+            recorder.push_matrix();
+            let stack = recorder.get_matrix_stack();
+            let offset_x = (((pass % 2) as f32) / 2f32 - 0.5f32) / 256f32;
+            let offset_y = (((pass / 2) as f32) / 2f32 - 0.5f32) / 256f32;
+            stack.translate(&Vec3f32::new(offset_x, offset_y, 0f32));
+            stack.rotate(&x_axis, x_rotation.to_radians());
+            stack.rotate(&y_axis, y_rotation.to_radians());
+
+            let alpha = (((255f32 * alpha).round() as u32) / (pass + 1)).min(255) as u8;
+            for (face, layer) in self.layers.iter().enumerate() {
+                let vertices = make_face(face, alpha);
+                let id = recorder.upload_immediate(&MeshData::new_quads(cast_slice(&vertices), Self::VERTEX_FORMAT.stride));
+                recorder.draw_immediate_layer(id, *layer);
+            }
+
+            recorder.pop_matrix();
+        }
+
+        recorder.pop_matrix();
+    }
+}
+
+impl Drop for Panorama {
+    fn drop(&mut self) {
+        for layer in &self.layers {
+            self.emulator.drop_render_layer(*layer);
+        }
+        self.emulator.drop_shader(self.shader);
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct PanoramaVertex {
+    position: [f32; 3],
+    uv0: [f32; 2],
+    color: [u8; 4],
+}
+const_assert_eq!(std::mem::size_of::<PanoramaVertex>(), 24);
+
+unsafe impl Zeroable for PanoramaVertex {}
+unsafe impl Pod for PanoramaVertex {}
+
+/// The corners of each face of the cube in the same order as used by vanilla.
+const FACE_CORNERS: [[[f32; 3]; 4]; 6] = [
+    [[-1f32, -1f32, 1f32], [-1f32, 1f32, 1f32], [1f32, 1f32, 1f32], [1f32, -1f32, 1f32]],
+    [[1f32, -1f32, 1f32], [1f32, 1f32, 1f32], [1f32, 1f32, -1f32], [1f32, -1f32, -1f32]],
+    [[1f32, -1f32, -1f32], [1f32, 1f32, -1f32], [-1f32, 1f32, -1f32], [-1f32, -1f32, -1f32]],
+    [[-1f32, -1f32, -1f32], [-1f32, 1f32, -1f32], [-1f32, 1f32, 1f32], [-1f32, -1f32, 1f32]],
+    [[-1f32, -1f32, -1f32], [-1f32, -1f32, 1f32], [1f32, -1f32, 1f32], [1f32, -1f32, -1f32]],
+    [[-1f32, 1f32, 1f32], [-1f32, 1f32, -1f32], [1f32, 1f32, -1f32], [1f32, 1f32, 1f32]],
+];
+
+const FACE_UVS: [[f32; 2]; 4] = [[0f32, 0f32], [0f32, 1f32], [1f32, 1f32], [1f32, 0f32]];
+
+fn make_face(face: usize, alpha: u8) -> [PanoramaVertex; 4] {
+    let corners = &FACE_CORNERS[face];
+    [0, 1, 2, 3].map(|i| PanoramaVertex {
+        position: corners[i],
+        uv0: FACE_UVS[i],
+        color: [255, 255, 255, alpha],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faces_cover_cube() {
+        // Every face must lie in a plane of the cube and all 6 planes must be covered
+        let mut planes = Vec::new();
+        for face in 0..6 {
+            let vertices = make_face(face, 255);
+            let plane = (0..3).find(|axis| vertices.iter().all(|v| v.position[*axis] == vertices[0].position[*axis])).unwrap();
+            planes.push((plane, vertices[0].position[plane] as i32));
+        }
+        planes.sort();
+        planes.dedup();
+        assert_eq!(planes.len(), 6);
+    }
+}
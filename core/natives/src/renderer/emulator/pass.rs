@@ -1,16 +1,40 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use ash::vk;
+use bytemuck::cast_slice;
 
+use crate::renderer::emulator::bundle::DrawBundle;
+use crate::renderer::emulator::capture::CaptureRecorder;
+use crate::renderer::emulator::glint::GlintParams;
+use crate::renderer::emulator::gpu_culling::{CulledSection, upload_sections};
+use crate::renderer::emulator::gui::GuiScale;
 use crate::renderer::emulator::immediate::ImmediateBuffer;
 use crate::renderer::emulator::{GlobalImage, GlobalMesh, MeshData};
 use crate::renderer::emulator::global_objects::{GlobalImageId, SamplerInfo};
 use crate::renderer::emulator::worker::WorkerTask;
 
-use crate::renderer::emulator::mc_shaders::{McUniformData, ShaderId};
-use crate::renderer::emulator::pipeline::{DrawTask, EmulatorOutput, EmulatorPipeline, PipelineTask};
+use crate::renderer::emulator::lights::{PointLight, upload_lights};
+use crate::renderer::emulator::lines::{LineBatch, LineStyle};
+use crate::renderer::emulator::lod::LodMesh;
+use crate::renderer::emulator::mc_shaders::{McUniform, McUniformData, ShaderId, VertexFormat};
+use crate::renderer::emulator::matrix_stack::MatrixStack;
+use crate::renderer::emulator::panorama::Panorama;
+use crate::renderer::emulator::probe::ProbeFaceOutput;
+use crate::renderer::emulator::pipeline::{BlendMode, BlendState, ClearConfig, CulledDrawTask, CullState, DepthBias, DepthTest, DrawTask, EmulatorOutput, EmulatorPipeline, PassAttachment, PipelineTask, ScreenEffects};
 use crate::renderer::emulator::share::Share;
+use crate::renderer::emulator::quad_indices::QuadIndexBuffer;
+use crate::renderer::emulator::readback::{AttachmentReadback, AttachmentReadbackOutput, PickReadback};
+use crate::renderer::emulator::recorder_state::{PendingUniforms, TrackedUniforms};
+use crate::renderer::emulator::render_layer::{RenderLayer, RenderLayerId, RenderLayerInfo};
+use crate::renderer::emulator::tessellator::{Tessellator, TessellatorMode};
+use crate::renderer::emulator::weather::Weather;
+use crate::renderer::emulator::world_border::WorldBorder;
+
+use crate::prelude::*;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
 pub struct PassId(u64);
@@ -39,21 +63,11 @@ impl ImmediateMeshId {
 }
 
 pub struct PassRecorder {
-    id: PassId,
-    share: Arc<Share>,
-
-    used_shaders: HashSet<ShaderId>,
-    used_global_image: HashSet<GlobalImageId>,
-    immediate_meshes: Vec<ImmediateMeshInfo>,
-
-    immediate_buffer: Option<Box<ImmediateBuffer>>,
-
-    #[allow(unused)] // We just need to keep the pipeline alive
-    pipeline: Arc<dyn EmulatorPipeline>,
+    recorder: DrawRecorder,
 }
 
 impl PassRecorder {
-    pub(super) fn new(share: Arc<Share>, pipeline: Arc<dyn EmulatorPipeline>, placeholder_image: Arc<GlobalImage>, placeholder_sampler: &SamplerInfo) -> Self {
+    pub(super) fn new(share: Arc<Share>, quad_indices: Arc<QuadIndexBuffer>, pipeline: Arc<dyn EmulatorPipeline>, placeholder_image: Arc<GlobalImage>, placeholder_sampler: &SamplerInfo) -> Self {
         let id = share.try_start_pass_id().unwrap_or_else(|| {
             log::error!("Attempted to start pass with an already running pass!");
             panic!();
@@ -65,125 +79,1168 @@ impl PassRecorder {
         let placeholder_sampler = placeholder_image.get_sampler(placeholder_sampler);
         share.push_task(WorkerTask::StartPass(id, pipeline.clone(), pipeline.start_pass(), placeholder_image, placeholder_sampler));
 
+        Self {
+            recorder: DrawRecorder::new(id, share, quad_indices, TaskTarget::Worker(pipeline), immediate_buffer),
+        }
+    }
+
+    /// Submits all commands recorded so far so that the gpu can start executing them while the rest
+    /// of the pass is still being recorded. For example after all opaque geometry has been drawn.
+    ///
+    /// Flushing does not change the result of the pass. Pipelines which cannot split their passes
+    /// submit everything at the end of the pass. Flushes are not part of frame captures.
+    pub fn flush(&mut self) {
+        let recorder = &mut self.recorder;
+        let immediate_buffer = std::mem::replace(recorder.immediate_buffer.as_mut().unwrap(), recorder.share.get_sub_immediate_buffer());
+        recorder.share.push_task(WorkerTask::FlushPass(immediate_buffer));
+    }
+
+    /// Creates a new sub recorder for this pass which can be used to record draws on a different
+    /// thread.
+    ///
+    /// Commands recorded into the sub recorder are only executed after it is merged back using
+    /// [`PassRecorder::merge_sub_recorder`]. All sub recorders must be merged before the pass ends.
+    pub fn create_sub_recorder(&self) -> SubPassRecorder {
+        SubPassRecorder::new(self.recorder.id, self.recorder.share.clone(), self.recorder.quad_indices.clone())
+    }
+
+    /// Merges the commands of a sub recorder into this pass. The commands are executed after all
+    /// commands previously recorded into this pass. Sub recorders are not included in frame
+    /// captures.
+    pub fn merge_sub_recorder(&mut self, mut sub_recorder: SubPassRecorder) {
+        let recorder = &mut self.recorder;
+        let sub = &mut sub_recorder.recorder;
+        if sub.id != recorder.id {
+            log::error!("Attempted to merge sub recorder of pass {:?} into pass {:?}", sub.id, recorder.id);
+            panic!();
+        }
+        if recorder.capture.is_some() && sub.draw_count != 0 {
+            log::warn!("Merged sub recorder into a captured pass. Its commands will not be part of the capture");
+        }
+
+        if let Some(immediate_buffer) = sub.immediate_buffer.take() {
+            recorder.share.push_task(WorkerTask::UseSubImmediateBuffer(immediate_buffer));
+        }
+        for shader in std::mem::take(&mut sub.used_shaders) {
+            // The sub recorder may have applied its own matrix stack
+            recorder.model_view_versions.remove(&shader);
+            recorder.use_shader(shader);
+        }
+        // The sub recorder already submits the images it uses as part of its tasks
+        recorder.used_global_images.extend(std::mem::take(&mut sub.used_global_images));
+
+        // The sub recorder may have bound different textures
+        recorder.current_layer = None;
+
+        // Uniforms and textures set by the sub recorder must be restored by a later pop_state
+        recorder.uniforms.merge(std::mem::replace(&mut sub.uniforms, TrackedUniforms::new()));
+
+        // Draws of the sub recorder may depend on uniforms set through this recorder and uniforms
+        // set through the sub recorder remain set after it has been merged
+        for (shader, data) in recorder.pending_uniforms.take_all() {
+            recorder.share.push_task(WorkerTask::PipelineTask(PipelineTask::UpdateUniform(shader, data)));
+        }
+        for (shader, data) in sub.pending_uniforms.take_all() {
+            sub.push_task(WorkerTask::PipelineTask(PipelineTask::UpdateUniform(shader, data)));
+        }
+
+        recorder.draw_count += sub.draw_count;
+        for task in sub.take_tasks() {
+            recorder.share.push_task(task);
+        }
+    }
+
+    /// Draws a bundle recorded by a [`DrawBundleRecorder`](super::bundle::DrawBundleRecorder). The
+    /// draws of the bundle use the fixed function state recorded into the bundle. Model view
+    /// matrices set by the bundle are multiplied with the current matrix of the matrix stack of
+    /// this recorder if the stack has been used. Bundles are not included in frame captures.
+    pub fn draw_bundle(&mut self, bundle: &DrawBundle) {
+        if bundle.is_empty() {
+            return;
+        }
+
+        let recorder = &mut self.recorder;
+        if recorder.capture.is_some() {
+            log::warn!("Drew bundle in a captured pass. Its commands will not be part of the capture");
+        }
+
+        for shader in bundle.get_used_shaders() {
+            recorder.use_shader(*shader);
+            recorder.apply_matrix_stack(*shader);
+        }
+        for image in bundle.get_used_global_images() {
+            recorder.use_global_image(image);
+        }
+        for mesh in bundle.get_meshes() {
+            mesh.update_used_in(recorder.id);
+            recorder.share.push_task(WorkerTask::UseGlobalMesh(mesh.clone()));
+        }
+        for (shader, index, image, sampler_info) in bundle.get_textures() {
+            recorder.uniforms.set_texture(*shader, *index, image, sampler_info);
+        }
+
+        // The bundle may have bound different textures
+        recorder.current_layer = None;
+
+        // Draws of the bundle may depend on uniforms set through this recorder
+        for (shader, data) in recorder.pending_uniforms.take_all() {
+            recorder.share.push_task(WorkerTask::PipelineTask(PipelineTask::UpdateUniform(shader, data)));
+        }
+
+        let model_view = (recorder.matrix_stack.get_version() != 0).then(|| recorder.matrix_stack.get_model_view());
+        for task in bundle.get_tasks() {
+            let task = match (task, &model_view) {
+                (PipelineTask::UpdateUniform(shader, McUniformData::ModelViewMatrix(matrix)), Some(model_view)) => {
+                    recorder.model_view_versions.remove(shader);
+                    PipelineTask::UpdateUniform(*shader, McUniformData::ModelViewMatrix(model_view * matrix))
+                }
+                (PipelineTask::UpdateUniform(shader, McUniformData::ModelViewMatrix(_)), None) => {
+                    recorder.model_view_versions.remove(shader);
+                    *task
+                }
+                _ => *task,
+            };
+            if let PipelineTask::UpdateUniform(shader, data) = &task {
+                recorder.uniforms.set_uniform(*shader, data);
+            }
+            recorder.share.push_task(WorkerTask::PipelineTask(task));
+        }
+
+        recorder.draw_count += bundle.get_draw_count();
+    }
+
+    pub fn use_output(&mut self, output: Box<dyn EmulatorOutput + Send>) {
+        self.recorder.share.push_task(WorkerTask::UseOutput(output));
+    }
+
+    /// Reads back a attachment of this pass once it has been executed. The readback completes
+    /// after the pass has finished execution on the gpu.
+    pub fn readback_attachment(&mut self, attachment: PassAttachment) -> AttachmentReadback {
+        let (output, readback) = AttachmentReadbackOutput::new(self.recorder.share.get_device().clone(), self.get_pipeline().clone(), attachment, None);
+        self.use_output(Box::new(output));
+        readback
+    }
+
+    /// Blits the output of this pass into a face of a environment probe atlas once the pass has
+    /// been executed. See [`EnvironmentProbe::capture_face`](super::probe::EnvironmentProbe::capture_face).
+    pub(super) fn capture_probe_face(&mut self, atlas: Arc<GlobalImage>, offset: Vec2u32, face_size: u32) {
+        let output = ProbeFaceOutput::new(self.recorder.share.get_device().clone(), self.get_pipeline().clone(), atlas, offset, face_size);
+        self.use_output(Box::new(output));
+    }
+
+    /// Reads back the object id of the pixel at the specified framebuffer position once this pass
+    /// has been executed. See [`DrawRecorder::set_object_id`].
+    ///
+    /// The pipeline must support the [`PassAttachment::ObjectId`] attachment. Otherwise the pick
+    /// completes with [`None`].
+    pub fn pick(&mut self, x: u32, y: u32) -> PickReadback {
+        let region = vk::Rect2D {
+            offset: vk::Offset2D { x: x as i32, y: y as i32 },
+            extent: vk::Extent2D { width: 1, height: 1 },
+        };
+        let (output, readback) = AttachmentReadbackOutput::new(self.recorder.share.get_device().clone(), self.get_pipeline().clone(), PassAttachment::ObjectId, Some(region));
+        self.use_output(Box::new(output));
+        PickReadback::new(readback)
+    }
+
+    /// Starts capturing all commands recorded into this pass. When the pass is ended the capture
+    /// is written to the specified file on a background thread.
+    ///
+    /// Must be called before any commands are recorded otherwise the capture will be incomplete.
+    pub fn start_capture(&mut self, path: PathBuf) {
+        let (output_size, _) = self.get_pipeline().get_output();
+        self.recorder.capture = Some((CaptureRecorder::new(output_size), path));
+    }
+
+    /// Sets how the attachments of the pass are initialized. Must be called before the first draw
+    /// or clear of the pass, later calls are ignored by the pipeline. If not called
+    /// [`ClearConfig::DEFAULT`] is used.
+    pub fn set_clear_config(&mut self, config: ClearConfig) {
+        if let Some((capture, _)) = &mut self.recorder.capture {
+            capture.set_clear_config(&config);
+        }
+        self.recorder.share.push_task(WorkerTask::PipelineTask(PipelineTask::SetClearConfig(config)));
+    }
+
+    /// Sets the point lights of the pass. The lights are culled using the projection matrix, see
+    /// [`lights`](super::lights). Must be called before the first draw or clear of the pass, later
+    /// calls are ignored by the pipeline. Lights are not included in frame captures.
+    pub fn set_lights(&mut self, lights: &[PointLight], projection: &Mat4f32) {
+        let recorder = &mut self.recorder;
+        let task = upload_lights(recorder.immediate_buffer.as_mut().unwrap(), &recorder.share, lights, projection);
+        recorder.share.push_task(WorkerTask::PipelineTask(task));
+    }
+
+    /// Sets the fullscreen overlay effects applied to the output of this pass. The last value set
+    /// before the pass is submitted is used. Initially all effects are disabled.
+    pub fn set_screen_effects(&mut self, effects: &ScreenEffects) {
+        if let Some((capture, _)) = &mut self.recorder.capture {
+            capture.set_screen_effects(effects);
+        }
+        self.recorder.share.push_task(WorkerTask::PipelineTask(PipelineTask::SetScreenEffects(*effects)));
+    }
+
+    /// Sets the view projection matrices of the left and right eye used if the pipeline renders a
+    /// stereo pass. The matrices replace the projection matrix of all shaders and should transform
+    /// from the view space of the model view matrix into the clip space of each eye. Ignored by
+    /// pipelines rendering a single view.
+    pub fn set_view_projections(&mut self, left: &Mat4f32, right: &Mat4f32) {
+        if let Some((capture, _)) = &mut self.recorder.capture {
+            capture.set_view_projections(left, right);
+        }
+        self.recorder.share.push_task(WorkerTask::PipelineTask(PipelineTask::SetViewProjections([*left, *right])));
+    }
+
+    /// Draws the panorama filling the entire output. Should be drawn before any other geometry of
+    /// the pass since depth writes are disabled.
+    pub fn draw_panorama(&mut self, panorama: &Panorama, aspect_ratio: f32, alpha: f32) {
+        panorama.record(self, aspect_ratio, alpha);
+    }
+
+    fn get_pipeline(&self) -> &Arc<dyn EmulatorPipeline> {
+        match &self.recorder.target {
+            TaskTarget::Worker(pipeline) => pipeline,
+            TaskTarget::Buffered(_) => unreachable!(),
+        }
+    }
+}
+
+impl Deref for PassRecorder {
+    type Target = DrawRecorder;
+
+    fn deref(&self) -> &Self::Target {
+        &self.recorder
+    }
+}
+
+impl DerefMut for PassRecorder {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.recorder
+    }
+}
+
+impl Drop for PassRecorder {
+    fn drop(&mut self) {
+        let recorder = &mut self.recorder;
+        recorder.share.record_pass_draw_count(recorder.draw_count);
+        recorder.share.push_task(WorkerTask::EndPass(recorder.immediate_buffer.take().unwrap()));
+        recorder.share.end_pass_id();
+
+        // Writing large captures can take a while so it must not delay the end of the pass
+        if let Some((capture, path)) = recorder.capture.take() {
+            let capture = capture.finish();
+            let result = std::thread::Builder::new().name("B4D Capture Writer".to_string()).spawn(move || {
+                match capture.write_to_file(&path) {
+                    Ok(_) => log::info!("Wrote frame capture to {:?}", path),
+                    Err(err) => log::error!("Failed to write frame capture to {:?}: {:?}", path, err),
+                }
+            });
+            if let Err(err) = result {
+                log::error!("Failed to spawn frame capture writer thread: {:?}", err);
+            }
+        }
+    }
+}
+
+/// Records draws of a pass on a different thread than the [`PassRecorder`] of the pass.
+///
+/// Created by [`PassRecorder::create_sub_recorder`]. Commands are buffered and only submitted to
+/// the worker once the sub recorder is merged. Immediate mesh ids are only valid inside the sub
+/// recorder that created them. Dropping a sub recorder without merging discards all its commands.
+///
+/// The fixed function state, matrix stack and state stack of the pass recorder are not inherited,
+/// a sub recorder starts with the initial state.
+pub struct SubPassRecorder {
+    recorder: DrawRecorder,
+}
+
+impl SubPassRecorder {
+    fn new(id: PassId, share: Arc<Share>, quad_indices: Arc<QuadIndexBuffer>) -> Self {
+        Self {
+            recorder: DrawRecorder::new(id, share, quad_indices, TaskTarget::Buffered(Vec::with_capacity(256)), None),
+        }
+    }
+}
+
+impl Deref for SubPassRecorder {
+    type Target = DrawRecorder;
+
+    fn deref(&self) -> &Self::Target {
+        &self.recorder
+    }
+}
+
+impl DerefMut for SubPassRecorder {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.recorder
+    }
+}
+
+impl Drop for SubPassRecorder {
+    fn drop(&mut self) {
+        let recorder = &mut self.recorder;
+        let discarded = recorder.take_tasks().len();
+        if discarded != 0 {
+            log::warn!("Dropped sub recorder of pass {:?} without merging it. {} commands have been discarded", recorder.id, discarded);
+        }
+        if let Some(immediate_buffer) = recorder.immediate_buffer.take() {
+            recorder.share.return_sub_immediate_buffer(immediate_buffer);
+        }
+    }
+}
+
+/// Where a [`DrawRecorder`] submits its worker tasks.
+enum TaskTarget {
+    /// Tasks are pushed to the worker immediately. Used by the [`PassRecorder`].
+    Worker(Arc<dyn EmulatorPipeline>),
+
+    /// Tasks are buffered until the [`SubPassRecorder`] is merged.
+    Buffered(Vec<WorkerTask>),
+}
+
+// Buffered tasks are only ever accessed through the recorder owning them. A panic while recording
+// aborts the process in the c api so a partially recorded task list is never observed.
+impl UnwindSafe for TaskTarget {
+}
+
+impl RefUnwindSafe for TaskTarget {
+}
+
+/// Records draws and their state into a pass. Shared by the [`PassRecorder`] and
+/// [`SubPassRecorder`] which both dereference to it.
+pub struct DrawRecorder {
+    id: PassId,
+    share: Arc<Share>,
+    quad_indices: Arc<QuadIndexBuffer>,
+    target: TaskTarget,
+
+    used_shaders: HashSet<ShaderId>,
+    used_global_images: HashSet<GlobalImageId>,
+    used_quad_indices: Option<Arc<GlobalMesh>>,
+    immediate_meshes: Vec<ImmediateMeshInfo>,
+    draw_count: u32,
+    draw_state: DrawState,
+    current_layer: Option<RenderLayerId>,
+    matrix_stack: MatrixStack,
+    model_view_versions: HashMap<ShaderId, u64>,
+    uniforms: TrackedUniforms,
+    pending_uniforms: PendingUniforms,
+    state_stack: Vec<RecorderState>,
+
+    /// Always set for the pass recorder. Sub recorders only request a buffer once they upload
+    /// data.
+    immediate_buffer: Option<Box<ImmediateBuffer>>,
+
+    /// Only used by the pass recorder.
+    capture: Option<(CaptureRecorder, PathBuf)>,
+}
+
+impl DrawRecorder {
+    fn new(id: PassId, share: Arc<Share>, quad_indices: Arc<QuadIndexBuffer>, target: TaskTarget, immediate_buffer: Option<Box<ImmediateBuffer>>) -> Self {
         Self {
             id,
             share,
+            quad_indices,
+            target,
 
             used_shaders: HashSet::new(),
-            used_global_image: HashSet::new(),
+            used_global_images: HashSet::new(),
+            used_quad_indices: None,
             immediate_meshes: Vec::with_capacity(128),
+            draw_count: 0,
+            draw_state: DrawState::new(),
+            current_layer: None,
+            matrix_stack: MatrixStack::new(),
+            model_view_versions: HashMap::new(),
+            uniforms: TrackedUniforms::new(),
+            pending_uniforms: PendingUniforms::new(),
+            state_stack: Vec::new(),
 
             immediate_buffer,
 
-            pipeline,
+            capture: None,
         }
     }
 
-    pub fn use_output(&mut self, output: Box<dyn EmulatorOutput + Send>) {
-        self.share.push_task(WorkerTask::UseOutput(output));
+    pub fn get_pass_id(&self) -> PassId {
+        self.id
     }
 
     pub fn update_uniform(&mut self, data: &McUniformData, shader: ShaderId) {
         self.use_shader(shader);
-        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::UpdateUniform(shader, *data)))
+        if let McUniformData::ModelViewMatrix(_) = data {
+            self.model_view_versions.remove(&shader);
+        }
+        self.uniforms.set_uniform(shader, data);
+        self.pending_uniforms.set_uniform(shader, data);
+        if let Some((capture, _)) = &mut self.capture {
+            capture.update_uniform(&self.share.get_shader(shader).unwrap(), data);
+        }
     }
 
     pub fn update_texture(&mut self, index: u32, image: &Arc<GlobalImage>, sampler_info: &SamplerInfo, shader: ShaderId) {
         self.use_shader(shader);
         let view = image.get_sampler_view();
         let sampler = image.get_sampler(sampler_info);
+        if let Some((capture, _)) = &mut self.capture {
+            capture.update_texture(&self.share.get_shader(shader).unwrap(), index, image, sampler_info);
+        }
+        self.uniforms.set_texture(shader, index, image, sampler_info);
 
-        if self.used_global_image.insert(image.get_id()) {
-            self.share.push_task(WorkerTask::UseGlobalImage(image.clone()));
+        self.use_global_image(image);
+        self.current_layer = None;
+
+        self.push_task(WorkerTask::PipelineTask(PipelineTask::UpdateTexture(shader, index, view, sampler)));
+    }
+
+    /// Sets the bone matrices used to skin all following draws using the shader. The joint indices
+    /// of the vertex format index into the matrices.
+    pub fn update_bone_matrices(&mut self, matrices: &[Mat4f32], shader: ShaderId) {
+        if matrices.is_empty() {
+            log::warn!("Called update_bone_matrices with no matrices. Ignoring!");
+            return;
+        }
+
+        self.use_shader(shader);
+        if let Some((capture, _)) = &mut self.capture {
+            capture.update_bone_matrices(&self.share.get_shader(shader).unwrap(), matrices);
         }
 
-        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::UpdateTexture(shader, index, view, sampler)));
+        let task = {
+            let (immediate_buffer, share) = self.get_immediate_buffer();
+            upload_bone_matrices(immediate_buffer, share, matrices, shader)
+        };
+        self.push_task(WorkerTask::PipelineTask(task));
     }
 
-    pub fn upload_immediate(&mut self, data: &MeshData) -> ImmediateMeshId {
-        let index_size = data.get_index_size();
+    /// Returns the model view matrix stack of this recorder.
+    ///
+    /// Once the stack has been modified its matrix is automatically uploaded as the model view
+    /// matrix of all following draws, overriding any model view matrix set using
+    /// [`DrawRecorder::update_uniform`].
+    pub fn get_matrix_stack(&mut self) -> &mut MatrixStack {
+        &mut self.matrix_stack
+    }
 
-        let immediate = self.immediate_buffer.as_mut().unwrap();
-        let (vertex_buffer, vertex_offset) = immediate.allocate(data.vertex_data, data.vertex_stride as vk::DeviceSize);
-        let (index_buffer, index_offset) = immediate.allocate(data.index_data, index_size as vk::DeviceSize);
+    /// Shorthand for [`MatrixStack::push`] on the stack of this recorder.
+    pub fn push_matrix(&mut self) {
+        self.matrix_stack.push();
+    }
 
-        let id = self.immediate_meshes.len() as u32;
-        self.immediate_meshes.push(ImmediateMeshInfo {
-            vertex_buffer,
-            index_buffer,
-            vertex_offset: (vertex_offset / (data.vertex_stride as vk::DeviceSize)) as i32,
-            first_index: (index_offset / (index_size as vk::DeviceSize)) as u32,
-            index_type: data.index_type,
-            index_count: data.index_count,
-            primitive_topology: data.primitive_topology
+    /// Shorthand for [`MatrixStack::pop`] on the stack of this recorder.
+    pub fn pop_matrix(&mut self) {
+        self.matrix_stack.pop();
+    }
+
+    /// Saves the current shader uniforms and textures, fixed function state, active render layer
+    /// and matrix stack. The state can be restored using [`DrawRecorder::pop_state`].
+    ///
+    /// Used to isolate nested rendering code, for example gui drawing inside world rendering
+    /// hooks, from the draws recorded after it.
+    pub fn push_state(&mut self) {
+        self.state_stack.push(RecorderState {
+            draw_state: self.draw_state,
+            current_layer: self.current_layer,
+            matrix_stack: self.matrix_stack.clone(),
+            uniforms: self.uniforms.clone(),
         });
+    }
+
+    /// Restores the state saved by the matching [`DrawRecorder::push_state`]. Only uniforms and
+    /// textures which had been set before the push are restored, any other uniforms keep the
+    /// value set after the push.
+    pub fn pop_state(&mut self) {
+        let state = match self.state_stack.pop() {
+            Some(state) => state,
+            None => {
+                log::warn!("Called pop_state with no pushed state. Ignoring!");
+                return;
+            }
+        };
+
+        for (shader, data) in self.uniforms.get_changed_uniforms(&state.uniforms) {
+            self.update_uniform(&data, shader);
+        }
+        for (shader, index, image, sampler_info) in self.uniforms.get_changed_textures(&state.uniforms) {
+            self.update_texture(index, &image, &sampler_info, shader);
+        }
+
+        self.set_scissor(state.draw_state.scissor);
+        self.set_viewport(state.draw_state.viewport);
+        self.set_blend_state(state.draw_state.blend_state);
+        self.set_logic_op(state.draw_state.logic_op);
+        self.set_color_write_mask(state.draw_state.color_write_mask);
+        self.set_depth_bias(state.draw_state.depth_bias);
+        self.set_depth_test(state.draw_state.depth_test);
+        self.set_cull_state(state.draw_state.cull_state);
+        self.set_object_id(state.draw_state.object_id);
+        self.current_layer = state.current_layer;
+        self.matrix_stack.restore(state.matrix_stack);
+    }
+
+    /// Sets the scissor rectangle used by all following draws of this recorder. If [`None`] draws
+    /// are not clipped.
+    pub fn set_scissor(&mut self, scissor: Option<vk::Rect2D>) {
+        if let Some((capture, _)) = &mut self.capture {
+            capture.set_scissor(scissor);
+        }
+        self.draw_state.scissor = scissor;
+    }
+
+    /// Sets the region of the framebuffer all following draws of this recorder are rendered into.
+    /// Draws are clipped to the region. If [`None`] the full framebuffer is used which is the
+    /// initial state.
+    ///
+    /// Used to render the world multiple times per frame, for example for split screen or
+    /// picture in picture views. The projection matrix should use the aspect ratio of the region.
+    /// See [`split_screen_regions`](super::split_screen::split_screen_regions).
+    pub fn set_viewport(&mut self, viewport: Option<vk::Rect2D>) {
+        if let Some((capture, _)) = &mut self.capture {
+            capture.set_viewport(viewport);
+        }
+        self.draw_state.viewport = viewport;
+    }
+
+    /// Clears the depth buffer inside the region. If [`None`] the full depth buffer is cleared.
+    ///
+    /// Used before rendering a view into a region which overlaps previously rendered geometry,
+    /// for example a picture in picture view.
+    pub fn clear_depth(&mut self, region: Option<vk::Rect2D>) {
+        if let Some((capture, _)) = &mut self.capture {
+            capture.clear_depth(region);
+        }
+        self.push_task(WorkerTask::PipelineTask(PipelineTask::ClearDepth(region)));
+    }
+
+    /// Sets the blend state used by all following draws of this recorder. If [`None`] blending is
+    /// disabled which is the initial state.
+    pub fn set_blend_state(&mut self, blend_state: Option<BlendState>) {
+        if let Some((capture, _)) = &mut self.capture {
+            capture.set_blend_state(blend_state);
+        }
+        self.draw_state.blend_state = blend_state;
+    }
+
+    /// Like [`DrawRecorder::set_blend_state`] but takes a [`BlendMode`].
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.set_blend_state(blend_mode.to_blend_state());
+    }
+
+    /// Sets the logic op used by all following draws of this recorder. If set it replaces the
+    /// blend state. If [`None`] no logic op is used which is the initial state.
+    pub fn set_logic_op(&mut self, logic_op: Option<vk::LogicOp>) {
+        if let Some((capture, _)) = &mut self.capture {
+            capture.set_logic_op(logic_op);
+        }
+        self.draw_state.logic_op = logic_op;
+    }
+
+    /// Sets the components of the color attachment written by all following draws of this
+    /// recorder. Initially all components are written.
+    pub fn set_color_write_mask(&mut self, color_write_mask: vk::ColorComponentFlags) {
+        if let Some((capture, _)) = &mut self.capture {
+            capture.set_color_write_mask(color_write_mask);
+        }
+        self.draw_state.color_write_mask = color_write_mask;
+    }
+
+    /// Sets the depth bias used by all following draws of this recorder. If [`None`] no bias is
+    /// applied which is the initial state.
+    pub fn set_depth_bias(&mut self, depth_bias: Option<DepthBias>) {
+        if let Some((capture, _)) = &mut self.capture {
+            capture.set_depth_bias(depth_bias);
+        }
+        self.draw_state.depth_bias = depth_bias;
+    }
+
+    /// Sets the depth comparison used by all following draws of this recorder. Initially
+    /// [`DepthTest::Default`] is used.
+    pub fn set_depth_test(&mut self, depth_test: DepthTest) {
+        if let Some((capture, _)) = &mut self.capture {
+            capture.set_depth_test(depth_test);
+        }
+        self.draw_state.depth_test = depth_test;
+    }
+
+    /// Sets the faces culled by all following draws of this recorder. Initially back faces are
+    /// culled using counter clockwise front faces.
+    pub fn set_cull_state(&mut self, cull_state: CullState) {
+        if let Some((capture, _)) = &mut self.capture {
+            capture.set_cull_state(cull_state);
+        }
+        self.draw_state.cull_state = cull_state;
+    }
+
+    /// Enables or disables back face culling for all following draws of this recorder. Equivalent
+    /// to calling [`DrawRecorder::set_cull_state`] with [`CullState::from_enable`].
+    pub fn set_cull_enable(&mut self, cull_enable: bool) {
+        self.set_cull_state(CullState::from_enable(cull_enable));
+    }
+
+    /// Sets the object id written into the object id attachment by all following draws of this
+    /// recorder. The initial id is 0 which is also used for pixels not covered by any draw. See
+    /// [`PassRecorder::pick`].
+    pub fn set_object_id(&mut self, object_id: u32) {
+        if let Some((capture, _)) = &mut self.capture {
+            capture.set_object_id(object_id);
+        }
+        self.draw_state.object_id = object_id;
+    }
+
+    pub fn upload_immediate(&mut self, data: &MeshData<'_>) -> ImmediateMeshId {
+        if let Some((capture, _)) = &mut self.capture {
+            capture.upload_immediate(data);
+        }
+
+        let quad_indices = self.use_quad_indices(data);
+
+        let id = self.immediate_meshes.len() as u32;
+        let mesh = {
+            let (immediate_buffer, share) = self.get_immediate_buffer();
+            ImmediateMeshInfo::upload(immediate_buffer, share, data, quad_indices.as_deref())
+        };
+        self.immediate_meshes.push(mesh);
 
         ImmediateMeshId::form_raw(id)
     }
 
+    /// Starts building a immediate mesh one vertex at a time. See [`Tessellator`].
+    pub fn begin(&mut self, format: &VertexFormat, mode: TessellatorMode) -> Tessellator<'_, Self> {
+        Tessellator::new(self, format, mode)
+    }
+
     pub fn draw_immediate(&mut self, id: ImmediateMeshId, shader: ShaderId, depth_write_enable: bool) {
         self.use_shader(shader);
+        self.apply_matrix_stack(shader);
 
         let mesh_data = self.immediate_meshes.get(id.get_raw() as usize).unwrap();
+        if let Some((capture, _)) = &mut self.capture {
+            capture.draw_immediate(id.get_raw(), &self.share.get_shader(shader).unwrap(), depth_write_enable);
+        }
 
-        let draw_task = DrawTask {
-            vertex_buffer: mesh_data.vertex_buffer,
-            index_buffer: mesh_data.index_buffer,
-            vertex_offset: mesh_data.vertex_offset,
-            first_index: mesh_data.first_index,
-            index_type: mesh_data.index_type,
-            index_count: mesh_data.index_count,
-            shader,
-            primitive_topology: mesh_data.primitive_topology,
-            depth_write_enable,
-        };
-        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::Draw(draw_task)));
+        let draw_task = mesh_data.make_draw_task(shader, depth_write_enable, &self.draw_state);
+        self.draw_count += 1;
+        self.push_draw(draw_task);
     }
 
     pub fn draw_global(&mut self, mesh: Arc<GlobalMesh>, shader: ShaderId, depth_write_enable: bool) {
         mesh.update_used_in(self.id);
 
         self.use_shader(shader);
+        self.apply_matrix_stack(shader);
+        if let Some((capture, _)) = &mut self.capture {
+            capture.draw_global(&mesh, &self.share.get_shader(shader).unwrap(), depth_write_enable);
+        }
 
-        let draw_info = mesh.get_draw_info();
+        let draw_task = make_global_draw_task(&mesh, shader, depth_write_enable, &self.draw_state);
 
-        let draw_task = DrawTask {
-            vertex_buffer: draw_info.buffer,
-            index_buffer: draw_info.buffer,
-            vertex_offset: 0,
-            first_index: draw_info.first_index,
-            index_type: draw_info.index_type,
-            index_count: draw_info.index_count,
-            shader,
-            primitive_topology: draw_info.primitive_topology,
-            depth_write_enable,
+        self.draw_count += 1;
+        self.push_task(WorkerTask::UseGlobalMesh(mesh));
+        self.push_draw(draw_task);
+    }
+
+    /// Draws the level of the lod mesh used at the specified distance from the camera. See
+    /// [`LodMesh::select`].
+    pub fn draw_global_lod(&mut self, mesh: &LodMesh, distance: f32, shader: ShaderId, depth_write_enable: bool) {
+        self.draw_global(mesh.select(distance).clone(), shader, depth_write_enable);
+    }
+
+    /// Draws the sections of a global mesh which are inside the view frustum. The sections are
+    /// culled on the gpu using `view_projection` which must transform the section bounds into clip
+    /// space, usually the projection matrix multiplied by the current model view matrix. See
+    /// [`gpu_culling`](super::gpu_culling).
+    pub fn draw_global_culled(&mut self, mesh: Arc<GlobalMesh>, sections: &[CulledSection], view_projection: &Mat4f32, shader: ShaderId, depth_write_enable: bool) {
+        if sections.is_empty() {
+            return;
+        }
+
+        mesh.update_used_in(self.id);
+
+        self.use_shader(shader);
+        self.apply_matrix_stack(shader);
+        if let Some((capture, _)) = &mut self.capture {
+            capture.draw_global_culled(&mesh, &self.share.get_shader(shader).unwrap(), sections, view_projection, depth_write_enable);
+        }
+
+        let draw = make_global_draw_task(&mesh, shader, depth_write_enable, &self.draw_state);
+        let ((section_buffer, section_offset), (indirect_buffer, indirect_offset)) = {
+            let (immediate_buffer, share) = self.get_immediate_buffer();
+            upload_sections(immediate_buffer, share, sections, draw.first_index)
         };
 
-        self.share.push_task(WorkerTask::UseGlobalMesh(mesh));
-        self.share.push_task(WorkerTask::PipelineTask(PipelineTask::Draw(draw_task)));
+        self.draw_count += 1;
+        self.push_task(WorkerTask::UseGlobalMesh(mesh));
+        self.push_pending_uniforms(shader);
+        self.push_task(WorkerTask::PipelineTask(PipelineTask::DrawCulled(CulledDrawTask {
+            draw,
+            section_buffer,
+            section_offset,
+            section_count: sections.len() as u32,
+            indirect_buffer,
+            indirect_offset,
+            view_projection: *view_projection,
+        })));
+    }
+
+    /// Draws `instance_count` instances of a global mesh starting at `first_instance` using a
+    /// single draw call. The shader must have been created with a
+    /// [`InstanceFormat`](super::mc_shaders::InstanceFormat) which describes the layout of
+    /// `instance_data`. The data must contain at least `first_instance + instance_count` instances.
+    pub fn draw_static_instanced(&mut self, mesh: Arc<GlobalMesh>, instance_data: &[u8], instance_count: u32, first_instance: u32, shader: ShaderId, depth_write_enable: bool) {
+        let shader_obj = self.share.get_shader(shader).unwrap();
+        let instance_format = shader_obj.get_instance_format().unwrap_or_else(|| {
+            log::error!("Called draw_static_instanced with shader {:?} which has no instance format", shader);
+            panic!()
+        });
+        let required_len = (first_instance as u64 + instance_count as u64) * instance_format.stride as u64;
+        if (instance_data.len() as u64) < required_len {
+            log::error!("Instance data of size {} is too small for {} instances starting at {}", instance_data.len(), instance_count, first_instance);
+            panic!()
+        }
+        if instance_count == 0 {
+            return;
+        }
+
+        mesh.update_used_in(self.id);
+
+        self.use_shader(shader);
+        self.apply_matrix_stack(shader);
+        if let Some((capture, _)) = &mut self.capture {
+            capture.draw_global_instanced(&mesh, &shader_obj, instance_data, instance_count, first_instance, depth_write_enable);
+        }
+
+        let mut draw_task = make_global_draw_task(&mesh, shader, depth_write_enable, &self.draw_state);
+        draw_task.instance_count = instance_count;
+        draw_task.first_instance = first_instance;
+        let (immediate_buffer, share) = self.get_immediate_buffer();
+        draw_task.instance_buffer = Some(upload_instance_data(immediate_buffer, share, instance_data));
+
+        self.draw_count += 1;
+        self.push_task(WorkerTask::UseGlobalMesh(mesh));
+        self.push_draw(draw_task);
+    }
+
+    /// Draws a immediate mesh using a render layer. The textures and state of the layer replace the
+    /// state set on this recorder for this draw only. The scissor of the recorder is still applied.
+    pub fn draw_immediate_layer(&mut self, id: ImmediateMeshId, layer: RenderLayerId) {
+        let layer = self.use_render_layer(layer);
+        let info = layer.get_info();
+        self.use_shader(info.shader);
+        self.apply_matrix_stack(info.shader);
+
+        let mesh_data = self.immediate_meshes.get(id.get_raw() as usize).unwrap();
+        layer.validate_topology(mesh_data.primitive_topology);
+
+        let state = self.draw_state.with_layer(info);
+        if let Some((capture, _)) = &mut self.capture {
+            capture_draw_state(capture, &state);
+            capture.draw_immediate(id.get_raw(), &self.share.get_shader(info.shader).unwrap(), info.depth_write_enable);
+            capture_draw_state(capture, &self.draw_state);
+        }
+
+        let draw_task = mesh_data.make_draw_task(info.shader, info.depth_write_enable, &state);
+        self.draw_count += 1;
+        self.push_draw(draw_task);
+    }
+
+    /// Draws a global mesh using a render layer. See [`DrawRecorder::draw_immediate_layer`].
+    pub fn draw_global_layer(&mut self, mesh: Arc<GlobalMesh>, layer: RenderLayerId) {
+        mesh.update_used_in(self.id);
+
+        let layer = self.use_render_layer(layer);
+        let info = layer.get_info();
+        self.use_shader(info.shader);
+        self.apply_matrix_stack(info.shader);
+        layer.validate_topology(mesh.get_draw_info().primitive_topology);
+
+        let state = self.draw_state.with_layer(info);
+        if let Some((capture, _)) = &mut self.capture {
+            capture_draw_state(capture, &state);
+            capture.draw_global(&mesh, &self.share.get_shader(info.shader).unwrap(), info.depth_write_enable);
+            capture_draw_state(capture, &self.draw_state);
+        }
+
+        let draw_task = make_global_draw_task(&mesh, info.shader, info.depth_write_enable, &state);
+
+        self.draw_count += 1;
+        self.push_task(WorkerTask::UseGlobalMesh(mesh));
+        self.push_draw(draw_task);
+    }
+
+    /// Draws the lines of the batch. The shader must use [`LineBatch::VERTEX_FORMAT`] and expand
+    /// the line quads using the `LineWidth` uniform. Back face culling is always disabled for lines.
+    pub fn draw_lines(&mut self, lines: &LineBatch, shader: ShaderId, style: &LineStyle) {
+        if lines.is_empty() {
+            return;
+        }
+
+        self.update_uniform(&McUniformData::LineWidth(style.width), shader);
+        let id = self.upload_immediate(&lines.as_mesh_data());
+        self.use_shader(shader);
+        self.apply_matrix_stack(shader);
+
+        let mesh_data = self.immediate_meshes.get(id.get_raw() as usize).unwrap();
+        let state = self.draw_state.with_line_style(style);
+        if let Some((capture, _)) = &mut self.capture {
+            capture_draw_state(capture, &state);
+            capture.draw_immediate(id.get_raw(), &self.share.get_shader(shader).unwrap(), style.depth_write_enable);
+            capture_draw_state(capture, &self.draw_state);
+        }
+
+        let draw_task = mesh_data.make_draw_task(shader, style.depth_write_enable, &state);
+        self.draw_count += 1;
+        self.push_draw(draw_task);
+    }
+
+    /// Draws the precipitation quads of the weather. The layers must use shaders created with
+    /// [`Weather::VERTEX_FORMAT`] and bind the rain and snow textures respectively.
+    pub fn draw_weather(&mut self, weather: &Weather, rain_layer: RenderLayerId, snow_layer: RenderLayerId) {
+        if let Some(data) = weather.get_rain_mesh_data() {
+            let id = self.upload_immediate(&data);
+            self.draw_immediate_layer(id, rain_layer);
+        }
+        if let Some(data) = weather.get_snow_mesh_data() {
+            let id = self.upload_immediate(&data);
+            self.draw_immediate_layer(id, snow_layer);
+        }
+    }
+
+    /// Draws the walls of the world border using the layer. See [`WorldBorder`].
+    pub fn draw_world_border(&mut self, border: &WorldBorder, layer: RenderLayerId) {
+        if !border.is_empty() {
+            let id = self.upload_immediate(&border.as_mesh_data());
+            self.draw_immediate_layer(id, layer);
+        }
+    }
+
+    /// Draws a immediate mesh using the shader and then draws it again using the glint layer. See
+    /// [`crate::renderer::emulator::glint`].
+    pub fn draw_immediate_glint(&mut self, id: ImmediateMeshId, shader: ShaderId, depth_write_enable: bool, glint_layer: RenderLayerId, params: &GlintParams) {
+        self.draw_immediate(id, shader, depth_write_enable);
+
+        let glint_shader = get_render_layer(&self.share, glint_layer).get_info().shader;
+        self.update_uniform(&McUniformData::TextureMatrix(params.get_texture_matrix()), glint_shader);
+        self.draw_immediate_layer(id, glint_layer);
     }
 
+    /// Draws a global mesh using the shader and then draws it again using the glint layer. See
+    /// [`DrawRecorder::draw_immediate_glint`].
+    pub fn draw_global_glint(&mut self, mesh: Arc<GlobalMesh>, shader: ShaderId, depth_write_enable: bool, glint_layer: RenderLayerId, params: &GlintParams) {
+        self.draw_global(mesh.clone(), shader, depth_write_enable);
+
+        let glint_shader = get_render_layer(&self.share, glint_layer).get_info().shader;
+        self.update_uniform(&McUniformData::TextureMatrix(params.get_texture_matrix()), glint_shader);
+        self.draw_global_layer(mesh, glint_layer);
+    }
+
+    /// Prepares the shader for drawing gui elements in scaled coordinates. Uploads the gui
+    /// projection and screen size to the shader and replaces the current matrix of the matrix
+    /// stack with the gui model view matrix. Should be called for every shader used to draw the
+    /// gui, push the matrix stack first if the current matrix is needed afterwards.
+    pub fn use_gui_scale(&mut self, gui: &GuiScale, shader: ShaderId) {
+        let framebuffer_size = gui.get_framebuffer_size();
+        self.update_uniform(&McUniformData::ProjectionMatrix(gui.get_projection_matrix()), shader);
+        self.update_uniform(&McUniformData::ScreenSize(Vec2f32::new(framebuffer_size[0] as f32, framebuffer_size[1] as f32)), shader);
+
+        let stack = self.get_matrix_stack();
+        stack.set_identity();
+        stack.multiply(&gui.get_model_view_matrix());
+    }
+
+    /// Returns the render layer and binds its textures if the layer differs from the last used
+    /// layer.
+    fn use_render_layer(&mut self, id: RenderLayerId) -> Arc<RenderLayer> {
+        let layer = get_render_layer(&self.share, id);
+        if self.current_layer != Some(id) {
+            let info = layer.get_info();
+            for texture in &info.textures {
+                self.update_texture(texture.index, &texture.image, &texture.sampler_info, info.shader);
+            }
+            self.current_layer = Some(id);
+        }
+        layer
+    }
+
+    /// Uploads the model view matrix of the matrix stack if the stack has been used and the shader
+    /// has not received the current matrix yet.
+    fn apply_matrix_stack(&mut self, shader: ShaderId) {
+        let version = self.matrix_stack.get_version();
+        if version == 0 || self.model_view_versions.get(&shader) == Some(&version) {
+            return;
+        }
+
+        if uses_model_view(&self.share, shader) {
+            let model_view = self.matrix_stack.get_model_view();
+            self.update_uniform(&McUniformData::ModelViewMatrix(model_view), shader);
+        }
+        self.model_view_versions.insert(shader, version);
+    }
+
+    /// Submits the pending uniform updates of the shader of the draw followed by the draw itself.
+    /// Uniforms are only consumed by draws so intermediate updates never have to reach the worker.
+    fn push_draw(&mut self, draw_task: DrawTask) {
+        self.push_pending_uniforms(draw_task.shader);
+        self.push_task(WorkerTask::PipelineTask(PipelineTask::Draw(draw_task)));
+    }
+
+    fn push_pending_uniforms(&mut self, shader: ShaderId) {
+        for data in self.pending_uniforms.take_shader(shader) {
+            self.push_task(WorkerTask::PipelineTask(PipelineTask::UpdateUniform(shader, data)));
+        }
+    }
+
+    fn push_task(&mut self, task: WorkerTask) {
+        match &mut self.target {
+            TaskTarget::Worker(_) => self.share.push_task(task),
+            TaskTarget::Buffered(tasks) => tasks.push(task),
+        }
+    }
+
+    /// Returns all buffered tasks. Always empty for the pass recorder.
+    fn take_tasks(&mut self) -> Vec<WorkerTask> {
+        match &mut self.target {
+            TaskTarget::Worker(_) => Vec::new(),
+            TaskTarget::Buffered(tasks) => std::mem::take(tasks),
+        }
+    }
+
+    /// Marks the shader as used by the pass. Sub recorders only collect the shaders, they are
+    /// marked as used once the sub recorder is merged.
     fn use_shader(&mut self, shader: ShaderId) {
         if self.used_shaders.insert(shader) {
-            self.pipeline.inc_shader_used(shader);
-            self.share.push_task(WorkerTask::UseShader(shader));
+            if let TaskTarget::Worker(pipeline) = &self.target {
+                pipeline.inc_shader_used(shader);
+                self.share.push_task(WorkerTask::UseShader(shader));
+            }
+        }
+    }
+
+    fn use_global_image(&mut self, image: &Arc<GlobalImage>) {
+        if self.used_global_images.insert(image.get_id()) {
+            self.push_task(WorkerTask::UseGlobalImage(image.clone()));
+        }
+    }
+
+    /// Returns the shared quad indices if the mesh uses them and makes sure they are kept alive
+    /// until the pass completes.
+    fn use_quad_indices(&mut self, data: &MeshData<'_>) -> Option<Arc<GlobalMesh>> {
+        if !data.uses_quad_indices() {
+            return None;
+        }
+
+        let mesh = self.quad_indices.get(&self.share, data.index_count / 6);
+        if self.used_quad_indices.as_ref() != Some(&mesh) {
+            mesh.update_used_in(self.id);
+            self.push_task(WorkerTask::UseGlobalMesh(mesh.clone()));
+            self.used_quad_indices = Some(mesh.clone());
         }
+        Some(mesh)
+    }
+
+    /// Returns the immediate buffer of this recorder, requesting one if the recorder does not have
+    /// one yet.
+    fn get_immediate_buffer(&mut self) -> (&mut ImmediateBuffer, &Arc<Share>) {
+        let share = &self.share;
+        (self.immediate_buffer.get_or_insert_with(|| share.get_sub_immediate_buffer()), share)
     }
 }
 
-impl Drop for PassRecorder {
-    fn drop(&mut self) {
-        self.share.push_task(WorkerTask::EndPass(self.immediate_buffer.take().unwrap()));
-        self.share.end_pass_id();
+/// Fixed function state applied to all draws recorded after it has been set.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct DrawState {
+    pub(super) scissor: Option<vk::Rect2D>,
+    pub(super) viewport: Option<vk::Rect2D>,
+    pub(super) blend_state: Option<BlendState>,
+    pub(super) logic_op: Option<vk::LogicOp>,
+    pub(super) color_write_mask: vk::ColorComponentFlags,
+    pub(super) depth_bias: Option<DepthBias>,
+    pub(super) depth_test: DepthTest,
+    pub(super) cull_state: CullState,
+    pub(super) object_id: u32,
+}
+
+impl DrawState {
+    pub(super) fn new() -> Self {
+        Self {
+            scissor: None,
+            viewport: None,
+            blend_state: None,
+            logic_op: None,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+            depth_bias: None,
+            depth_test: DepthTest::Default,
+            cull_state: CullState::BACK,
+            object_id: 0,
+        }
     }
+
+    /// Returns the state used to draw with the render layer. Only the scissor, viewport and object
+    /// id are kept.
+    pub(super) fn with_layer(&self, layer: &RenderLayerInfo) -> Self {
+        Self {
+            scissor: self.scissor,
+            viewport: self.viewport,
+            blend_state: layer.blend_state,
+            logic_op: layer.logic_op,
+            color_write_mask: layer.color_write_mask,
+            depth_bias: layer.depth_bias,
+            depth_test: layer.depth_test,
+            cull_state: layer.cull_state,
+            object_id: self.object_id,
+        }
+    }
+
+    /// Returns the state used to draw lines. Culling is disabled since the line quads may face
+    /// away from the camera.
+    fn with_line_style(&self, style: &LineStyle) -> Self {
+        Self {
+            depth_bias: style.depth_bias,
+            cull_state: CullState::NONE,
+            ..*self
+        }
+    }
+}
+
+/// A snapshot of the state of a recorder created by [`DrawRecorder::push_state`].
+struct RecorderState {
+    draw_state: DrawState,
+    current_layer: Option<RenderLayerId>,
+    matrix_stack: MatrixStack,
+    uniforms: TrackedUniforms,
+}
+
+/// Records the state into the capture. Used to replay render layer draws as individual state
+/// changes.
+fn capture_draw_state(capture: &mut CaptureRecorder, state: &DrawState) {
+    capture.set_blend_state(state.blend_state);
+    capture.set_logic_op(state.logic_op);
+    capture.set_color_write_mask(state.color_write_mask);
+    capture.set_depth_bias(state.depth_bias);
+    capture.set_depth_test(state.depth_test);
+    capture.set_cull_state(state.cull_state);
+}
+
+pub(super) fn uses_model_view(share: &Share, shader: ShaderId) -> bool {
+    share.get_shader(shader).map_or(false, |shader| shader.get_used_uniforms().contains(&McUniform::MODEL_VIEW_MATRIX))
+}
+
+pub(super) fn get_render_layer(share: &Share, id: RenderLayerId) -> Arc<RenderLayer> {
+    share.get_render_layer(id).unwrap_or_else(|| {
+        log::error!("Attempted to draw using unknown render layer {:?}", id);
+        panic!()
+    })
 }
 
 struct ImmediateMeshInfo {
     vertex_buffer: vk::Buffer,
+    vertex_address: Option<vk::DeviceAddress>,
     index_buffer: vk::Buffer,
     vertex_offset: i32,
     first_index: u32,
     index_type: vk::IndexType,
     index_count: u32,
     primitive_topology: vk::PrimitiveTopology,
+}
+
+impl ImmediateMeshInfo {
+    fn upload(immediate: &mut ImmediateBuffer, share: &Share, data: &MeshData<'_>, quad_indices: Option<&GlobalMesh>) -> Self {
+        let (vertex_buffer, vertex_offset) = immediate.allocate(data.vertex_data, data.vertex_stride as vk::DeviceSize);
+        let vertex_address = immediate.get_device_address(vertex_buffer);
+        let (index_buffer, first_index, index_type) = if let Some(quad_indices) = quad_indices {
+            (quad_indices.get_buffer_handle(), 0, vk::IndexType::UINT32)
+        } else {
+            let index_size = data.get_index_size();
+            let (index_buffer, index_offset) = immediate.allocate(data.index_data, index_size as vk::DeviceSize);
+            (index_buffer, (index_offset / (index_size as vk::DeviceSize)) as u32, data.index_type)
+        };
+        share.record_upload((data.vertex_data.len() + data.index_data.len()) as u64);
+
+        Self {
+            vertex_buffer,
+            vertex_address,
+            index_buffer,
+            vertex_offset: (vertex_offset / (data.vertex_stride as vk::DeviceSize)) as i32,
+            first_index,
+            index_type,
+            index_count: data.index_count,
+            primitive_topology: data.primitive_topology
+        }
+    }
+
+    fn make_draw_task(&self, shader: ShaderId, depth_write_enable: bool, state: &DrawState) -> DrawTask {
+        DrawTask {
+            vertex_buffer: self.vertex_buffer,
+            vertex_address: self.vertex_address,
+            index_buffer: self.index_buffer,
+            vertex_offset: self.vertex_offset,
+            first_index: self.first_index,
+            index_type: self.index_type,
+            index_count: self.index_count,
+            instance_count: 1,
+            first_instance: 0,
+            instance_buffer: None,
+            shader,
+            primitive_topology: self.primitive_topology,
+            depth_write_enable,
+            cull_state: state.cull_state,
+            scissor: state.scissor,
+            viewport: state.viewport,
+            blend_state: state.blend_state,
+            logic_op: state.logic_op,
+            color_write_mask: state.color_write_mask,
+            depth_bias: state.depth_bias,
+            depth_test: state.depth_test,
+            object_id: state.object_id,
+        }
+    }
+}
+
+/// Storage buffer offsets must be aligned to minStorageBufferOffsetAlignment. 256 is the highest
+/// value in the gpuinfo database.
+const BONE_MATRIX_ALIGNMENT: vk::DeviceSize = 256;
+
+fn upload_bone_matrices(immediate: &mut ImmediateBuffer, share: &Share, matrices: &[Mat4f32], shader: ShaderId) -> PipelineTask {
+    let data: Vec<f32> = matrices.iter().flat_map(|matrix| matrix.as_slice().iter().copied()).collect();
+    let bytes: &[u8] = cast_slice(&data);
+
+    let (buffer, offset) = immediate.allocate(bytes, BONE_MATRIX_ALIGNMENT);
+    share.record_upload(bytes.len() as u64);
+
+    PipelineTask::UpdateBoneMatrices(shader, buffer, offset, matrices.len() as u32)
+}
+
+fn upload_instance_data(immediate: &mut ImmediateBuffer, share: &Share, data: &[u8]) -> (vk::Buffer, vk::DeviceSize) {
+    let allocation = immediate.allocate(data, 4);
+    share.record_upload(data.len() as u64);
+
+    allocation
+}
+
+pub(super) fn make_global_draw_task(mesh: &GlobalMesh, shader: ShaderId, depth_write_enable: bool, state: &DrawState) -> DrawTask {
+    let draw_info = mesh.get_draw_info();
+
+    DrawTask {
+        vertex_buffer: draw_info.buffer,
+        vertex_address: draw_info.vertex_address,
+        index_buffer: draw_info.index_buffer,
+        vertex_offset: 0,
+        first_index: draw_info.first_index,
+        index_type: draw_info.index_type,
+        index_count: draw_info.index_count,
+        instance_count: 1,
+        first_instance: 0,
+        instance_buffer: None,
+        shader,
+        primitive_topology: draw_info.primitive_topology,
+        depth_write_enable,
+        cull_state: state.cull_state,
+        scissor: state.scissor,
+        viewport: state.viewport,
+        blend_state: state.blend_state,
+        logic_op: state.logic_op,
+        color_write_mask: state.color_write_mask,
+        depth_bias: state.depth_bias,
+        depth_test: state.depth_test,
+        object_id: state.object_id,
+    }
 }
\ No newline at end of file
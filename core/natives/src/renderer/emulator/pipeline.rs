@@ -1,16 +1,20 @@
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
 use std::panic::{RefUnwindSafe, UnwindSafe};
 use std::sync::{Arc, Weak};
 use ash::prelude::VkResult;
 
 use ash::vk;
 use bumpalo::Bump;
+use bytemuck::{Pod, Zeroable};
 use crate::device::device::Queue;
-use crate::device::device_utils::BlitPass;
+use crate::device::device_utils::{BlitPass, BlitSampling};
 use crate::device::surface::{AcquiredImageInfo, SurfaceSwapchain};
 
 use crate::prelude::*;
+use crate::renderer::debug::overlay::{DebugOverlay, OverlayDraw, OverlayRenderer};
+use crate::renderer::emulator::compositor::{self, CompositorLayer};
 use crate::renderer::emulator::mc_shaders::{McUniformData, ShaderId};
+use crate::renderer::emulator::vertex_compaction::VertexChannels;
 
 pub use super::worker::SubmitRecorder;
 pub use super::worker::PooledObjectProvider;
@@ -36,6 +40,12 @@ pub trait EmulatorPipeline: Send + Sync + UnwindSafe + RefUnwindSafe {
     /// **This is a temporary api and needs a rework to improve flexibility and elegance**
     fn get_output(&self) -> (Vec2u32, &[vk::ImageView]);
 
+    /// Returns a name identifying the pipeline in diagnostics like crash reports. Defaults to the
+    /// type name.
+    fn get_debug_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
     /// Called internally by the emulator renderer when pass uses a shader for the first time.
     /// A corresponding call to [`dec_shader_used`] will be performed after the corresponding pass
     /// has been dropped.
@@ -51,6 +61,112 @@ pub trait EmulatorPipeline: Send + Sync + UnwindSafe + RefUnwindSafe {
     ///
     /// This can be used to keep track of used shaders globally to manage vulkan pipelines.
     fn dec_shader_used(&self, shader: ShaderId);
+
+    /// Returns the image of a attachment of the pass with the specified output index (see
+    /// [`EmulatorPipelinePass::get_output_index`]) so that it can be read back by outputs. Returns
+    /// [`None`] if the pipeline does not support reading back the attachment.
+    ///
+    /// The image must have been created with [`vk::ImageUsageFlags::TRANSFER_SRC`].
+    fn get_attachment(&self, _attachment: PassAttachment, _index: usize) -> Option<AttachmentInfo> {
+        None
+    }
+
+    /// Returns image views of a attachment which can be sampled by outputs, one for every output
+    /// index, together with the layout of the attachment after the pass has been executed. Returns
+    /// [`None`] if the attachment cannot be sampled.
+    ///
+    /// By default only [`PassAttachment::Output`] is supported which returns the views of
+    /// [`EmulatorPipeline::get_output`].
+    fn get_sampled_attachment(&self, attachment: PassAttachment) -> Option<(Vec<vk::ImageView>, vk::ImageLayout)> {
+        match attachment {
+            PassAttachment::Output => Some((self.get_output().1.to_vec(), vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)),
+            _ => None,
+        }
+    }
+
+    /// Returns the vertex channels read by the pipeline. Meshes only drawn by this pipeline may be
+    /// compacted to these channels using
+    /// [`compact_vertex_data`](super::vertex_compaction::compact_vertex_data).
+    fn get_used_vertex_channels(&self) -> VertexChannels {
+        VertexChannels::all()
+    }
+
+    /// Requests the pipelines a shader is likely to be drawn with to be created in the background
+    /// so that the first draw using them does not stall the pass. The shader must be registered
+    /// with the emulator renderer.
+    ///
+    /// Pipelines which do not create pipelines per draw state may ignore this.
+    fn prewarm_shader(&self, _shader: ShaderId, _hints: &[PipelineConfigHint]) {
+    }
+
+    /// Returns true if the pipelines of the shader for all hints have been created. Used to delay
+    /// switching to new shaders until they can be used without stalling the frame.
+    ///
+    /// Pipelines which do not create pipelines per draw state should always return true.
+    fn is_shader_prewarmed(&self, _shader: ShaderId, _hints: &[PipelineConfigHint]) -> bool {
+        true
+    }
+}
+
+/// The draw state of a likely pipeline permutation of a shader. See
+/// [`EmulatorPipeline::prewarm_shader`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct PipelineConfigHint {
+    pub primitive_topology: vk::PrimitiveTopology,
+    pub depth_write_enable: bool,
+    pub depth_bias_enable: bool,
+    pub depth_test: DepthTest,
+    pub cull_state: CullState,
+    pub blend_state: Option<BlendState>,
+    pub logic_op: Option<vk::LogicOp>,
+    pub color_write_mask: vk::ColorComponentFlags,
+}
+
+impl PipelineConfigHint {
+    /// Creates a hint for opaque draws with depth writes and back face culling enabled.
+    pub const fn new(primitive_topology: vk::PrimitiveTopology) -> Self {
+        Self {
+            primitive_topology,
+            depth_write_enable: true,
+            depth_bias_enable: false,
+            depth_test: DepthTest::Default,
+            cull_state: CullState::BACK,
+            blend_state: None,
+            logic_op: None,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+        }
+    }
+}
+
+/// A attachment of a pass which can be read back.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum PassAttachment {
+    /// The final output of the pass. See [`EmulatorPipeline::get_output`].
+    Output,
+
+    /// The color attachment draws are rendered into before any post processing. For pipelines
+    /// visualizing other data, like the uv or normal modes of the debug pipeline, this contains
+    /// the visualized data.
+    Color,
+
+    Depth,
+
+    /// A [`vk::Format::R32_UINT`] attachment containing the object id of the draw which rendered
+    /// the closest fragment of every pixel. See [`DrawTask::object_id`].
+    ObjectId,
+}
+
+/// The image of a [`PassAttachment`].
+#[derive(Copy, Clone, Debug)]
+pub struct AttachmentInfo {
+    pub image: vk::Image,
+    pub format: vk::Format,
+    pub aspect_mask: vk::ImageAspectFlags,
+
+    /// The layout of the image after the pass has been executed. Outputs accessing the image must
+    /// transition it back to this layout.
+    pub layout: vk::ImageLayout,
+    pub size: Vec2u32,
 }
 
 /// Represents one execution of a [`EmulatorPipeline`].
@@ -81,6 +197,17 @@ pub trait EmulatorPipelinePass {
     /// Must only be called while the pass is in the recording state.
     fn process_task(&mut self, task: &PipelineTask, obj: &mut PooledObjectProvider);
 
+    /// Called to record the tasks processed so far as a segment which is submitted before the pass
+    /// ends, so that the gpu can start executing them while later tasks are still being recorded.
+    /// The recorded submits will be submitted by the calling code. The pass stays in the recording
+    /// state and later tasks must produce the same result as if no segment had been recorded.
+    ///
+    /// Returns false if the pass does not support segments. In that case nothing must be recorded
+    /// and all tasks are submitted by [`EmulatorPipelinePass::record`].
+    fn record_segment<'a>(&mut self, _obj: &mut PooledObjectProvider, _submits: &mut SubmitRecorder<'a>, _alloc: &'a Bump) -> bool {
+        false
+    }
+
     /// Called to record any necessary command buffer submissions for the execution of the pass.
     /// The recorded submits will be submitted by the calling code.
     ///
@@ -110,20 +237,483 @@ pub trait EmulatorPipelinePass {
 pub enum PipelineTask {
     UpdateUniform(ShaderId, McUniformData),
     UpdateTexture(ShaderId, u32, vk::ImageView, vk::Sampler),
+
+    /// Sets the bone matrices used by the shader. The buffer contains the specified number of
+    /// column major 4x4 float matrices starting at the offset and can be bound as a storage buffer.
+    UpdateBoneMatrices(ShaderId, vk::Buffer, vk::DeviceSize, u32),
     Draw(DrawTask),
+
+    /// Draws the sections of a global mesh which pass gpu frustum culling. See
+    /// [`gpu_culling`](super::gpu_culling).
+    DrawCulled(CulledDrawTask),
+
+    /// Sets the overlay effects applied by the post processing stage of the pipeline.
+    SetScreenEffects(ScreenEffects),
+
+    /// Clears the depth attachment inside the region. If [`None`] the full attachment is cleared.
+    /// May extend outside of the framebuffer.
+    ClearDepth(Option<vk::Rect2D>),
+
+    /// Sets the view projection matrices of the left and right view. Only used by pipelines
+    /// rendering stereo passes where they replace the projection matrix of all shaders.
+    SetViewProjections([Mat4f32; 2]),
+
+    /// Sets how the attachments are initialized at the start of the pass. Only has an effect if
+    /// it is processed before any draw or clear task of the pass.
+    SetClearConfig(ClearConfig),
+
+    /// Sets the point lights of the pass. Only has an effect if it is processed before any draw or
+    /// clear task of the pass.
+    SetLights(LightsInfo),
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
 pub struct DrawTask {
     pub vertex_buffer: vk::Buffer,
+
+    /// The device address of the start of the vertex buffer. Allows pipelines to fetch vertex data
+    /// in shaders using buffer references instead of binding the vertex buffer. [`None`] if the
+    /// `bufferDeviceAddress` feature is not supported by the device.
+    pub vertex_address: Option<vk::DeviceAddress>,
+
     pub index_buffer: vk::Buffer,
     pub vertex_offset: i32,
     pub first_index: u32,
     pub index_type: vk::IndexType,
     pub index_count: u32,
+
+    /// The number of instances drawn. Usually 1.
+    pub instance_count: u32,
+    pub first_instance: u32,
+
+    /// The buffer and offset of the per instance data of the draw. The data is laid out according
+    /// to the [`InstanceFormat`](super::mc_shaders::InstanceFormat) of the shader. If [`None`] the
+    /// draw must only use the first instance which has an offset of 0.
+    pub instance_buffer: Option<(vk::Buffer, vk::DeviceSize)>,
+
     pub shader: ShaderId,
     pub primitive_topology: vk::PrimitiveTopology,
     pub depth_write_enable: bool,
+
+    /// The faces culled by the draw.
+    pub cull_state: CullState,
+
+    /// The region of the framebuffer the draw is clipped to. If [`None`] the full framebuffer is
+    /// used. May extend outside of the framebuffer.
+    pub scissor: Option<vk::Rect2D>,
+
+    /// The region of the framebuffer normalized device coordinates are mapped to. Draws are also
+    /// clipped to the region. If [`None`] the full framebuffer is used. May extend outside of the
+    /// framebuffer.
+    pub viewport: Option<vk::Rect2D>,
+
+    /// The blend function used for the color attachment. If [`None`] blending is disabled.
+    ///
+    /// Blend states using the second fragment shader output require the `dualSrcBlend` device
+    /// feature, pipelines without support should use [`BlendState::approximate_dual_source`].
+    pub blend_state: Option<BlendState>,
+
+    /// The logic op applied to the color attachment. If set it replaces the blend state. Requires
+    /// the `logicOp` device feature, pipelines without support should use
+    /// [`BlendState::approximate_logic_op`].
+    pub logic_op: Option<vk::LogicOp>,
+
+    /// The components of the color attachment written by the draw. Matches `glColorMask`. Other
+    /// attachments like the object id attachment are not affected.
+    pub color_write_mask: vk::ColorComponentFlags,
+
+    /// The depth bias applied to the fragments of the draw. If [`None`] no bias is applied.
+    pub depth_bias: Option<DepthBias>,
+
+    /// The depth comparison used by the draw.
+    pub depth_test: DepthTest,
+
+    /// The id written into the object id attachment by the draw. 0 is used for fragments not
+    /// covered by any object. See [`PassAttachment::ObjectId`].
+    pub object_id: u32,
+}
+
+/// Depth bias factors matching the parameters of `glPolygonOffset`.
+#[derive(Copy, Clone, Debug)]
+pub struct DepthBias {
+    pub constant_factor: f32,
+    pub slope_factor: f32,
+}
+
+impl DepthBias {
+    /// The bias used by vanilla for the block breaking overlay.
+    pub const CRUMBLING: Self = Self::new(-10f32, -1f32);
+
+    /// The bias used by vanilla for the world border.
+    pub const WORLD_BORDER: Self = Self::new(-3f32, -3f32);
+
+    pub const fn new(constant_factor: f32, slope_factor: f32) -> Self {
+        Self {
+            constant_factor,
+            slope_factor,
+        }
+    }
+}
+
+// Compare the bits so that DrawTask can be used as a key
+impl PartialEq for DepthBias {
+    fn eq(&self, other: &Self) -> bool {
+        self.constant_factor.to_bits() == other.constant_factor.to_bits() && self.slope_factor.to_bits() == other.slope_factor.to_bits()
+    }
+}
+
+impl Eq for DepthBias {
+}
+
+impl Hash for DepthBias {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.constant_factor.to_bits().hash(state);
+        self.slope_factor.to_bits().hash(state);
+    }
+}
+
+/// The depth comparison of a draw.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum DepthTest {
+    /// The comparison used by the pipeline for regular geometry. Depends on the depth mode of the
+    /// pipeline.
+    Default,
+
+    /// Only fragments with exactly the depth already stored pass. Used to draw the same geometry
+    /// a second time, for example by the enchantment glint.
+    Equal,
+}
+
+impl Default for DepthTest {
+    fn default() -> Self {
+        Self::Default
+    }
+}
+
+/// Face culling state of a draw.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct CullState {
+    pub cull_mode: vk::CullModeFlags,
+
+    /// The winding of front facing triangles.
+    pub front_face: vk::FrontFace,
+}
+
+impl CullState {
+    /// No faces are culled.
+    pub const NONE: Self = Self::new(vk::CullModeFlags::NONE);
+
+    /// Back faces are culled. Used by vanilla for all culled render types.
+    pub const BACK: Self = Self::new(vk::CullModeFlags::BACK);
+
+    /// Front faces are culled.
+    pub const FRONT: Self = Self::new(vk::CullModeFlags::FRONT);
+
+    /// Creates a cull state using counter clockwise front faces which matches minecraft.
+    pub const fn new(cull_mode: vk::CullModeFlags) -> Self {
+        Self {
+            cull_mode,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+        }
+    }
+
+    pub const fn with_front_face(self, front_face: vk::FrontFace) -> Self {
+        Self {
+            cull_mode: self.cull_mode,
+            front_face,
+        }
+    }
+
+    /// Returns [`CullState::BACK`] if enabled and [`CullState::NONE`] otherwise.
+    pub const fn from_enable(cull_enable: bool) -> Self {
+        if cull_enable {
+            Self::BACK
+        } else {
+            Self::NONE
+        }
+    }
+}
+
+/// Blend function and equation of a draw.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct BlendState {
+    pub color_op: vk::BlendOp,
+    pub color_src_factor: vk::BlendFactor,
+    pub color_dst_factor: vk::BlendFactor,
+    pub alpha_op: vk::BlendOp,
+    pub alpha_src_factor: vk::BlendFactor,
+    pub alpha_dst_factor: vk::BlendFactor,
+}
+
+impl BlendState {
+    /// Regular alpha blending used by translucent render types.
+    pub const TRANSLUCENT: Self = Self::new_separate(vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE_MINUS_SRC_ALPHA, vk::BlendFactor::ONE, vk::BlendFactor::ONE_MINUS_SRC_ALPHA);
+
+    pub const ADDITIVE: Self = Self::new(vk::BlendFactor::ONE, vk::BlendFactor::ONE);
+
+    pub const MULTIPLY: Self = Self::new(vk::BlendFactor::DST_COLOR, vk::BlendFactor::ZERO);
+
+    /// Used by lightning and other glowing effects.
+    pub const LIGHTNING: Self = Self::new(vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE);
+
+    /// Used by the enchantment glint. Keeps the alpha of the destination.
+    pub const GLINT: Self = Self::new_separate(vk::BlendFactor::SRC_COLOR, vk::BlendFactor::ONE, vk::BlendFactor::ZERO, vk::BlendFactor::ONE);
+
+    /// Used by the block breaking overlay.
+    pub const CRUMBLING: Self = Self::new_separate(vk::BlendFactor::DST_COLOR, vk::BlendFactor::SRC_COLOR, vk::BlendFactor::ONE, vk::BlendFactor::ZERO);
+
+    /// Used by the world border.
+    pub const WORLD_BORDER: Self = Self::new_separate(vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ONE, vk::BlendFactor::ONE, vk::BlendFactor::ZERO);
+
+    /// Returns a blend state approximating a logic op. The result is exact for colors where every
+    /// channel is either 0 or 1. [`vk::LogicOp::COPY`] and logic ops which cannot be approximated
+    /// return [`None`] which disables blending.
+    pub fn approximate_logic_op(op: vk::LogicOp) -> Option<Self> {
+        match op {
+            vk::LogicOp::COPY => None,
+            vk::LogicOp::CLEAR => Some(Self::new(vk::BlendFactor::ZERO, vk::BlendFactor::ZERO)),
+            vk::LogicOp::NO_OP => Some(Self::new(vk::BlendFactor::ZERO, vk::BlendFactor::ONE)),
+            vk::LogicOp::AND => Some(Self::new(vk::BlendFactor::DST_COLOR, vk::BlendFactor::ZERO)),
+            vk::LogicOp::OR => Some(Self::new(vk::BlendFactor::ONE, vk::BlendFactor::ONE_MINUS_SRC_COLOR)),
+            // Vanilla only uses these with white source colors in which case all of them invert
+            vk::LogicOp::XOR |
+            vk::LogicOp::INVERT |
+            vk::LogicOp::OR_REVERSE => Some(Self::new(vk::BlendFactor::ONE_MINUS_DST_COLOR, vk::BlendFactor::ONE_MINUS_SRC_COLOR)),
+            _ => {
+                log::warn!("Logic op {:?} cannot be approximated using blending. Ignoring!", op);
+                None
+            }
+        }
+    }
+
+    /// Returns true if any factor uses the second fragment shader output. Used by vanilla for text
+    /// rendered with an outline.
+    pub fn uses_dual_source(&self) -> bool {
+        [self.color_src_factor, self.color_dst_factor, self.alpha_src_factor, self.alpha_dst_factor].iter().any(|factor| is_dual_source_factor(*factor))
+    }
+
+    /// Returns a blend state replacing all factors using the second fragment shader output with
+    /// the matching factors of the first output. The result is exact if the shader writes the
+    /// same value to both outputs.
+    pub fn approximate_dual_source(&self) -> Self {
+        Self {
+            color_op: self.color_op,
+            color_src_factor: to_single_source_factor(self.color_src_factor),
+            color_dst_factor: to_single_source_factor(self.color_dst_factor),
+            alpha_op: self.alpha_op,
+            alpha_src_factor: to_single_source_factor(self.alpha_src_factor),
+            alpha_dst_factor: to_single_source_factor(self.alpha_dst_factor),
+        }
+    }
+
+    /// Creates a blend state using the add operation and the same factors for color and alpha.
+    pub const fn new(src_factor: vk::BlendFactor, dst_factor: vk::BlendFactor) -> Self {
+        Self::new_separate(src_factor, dst_factor, src_factor, dst_factor)
+    }
+
+    /// Creates a blend state using the add operation.
+    pub const fn new_separate(color_src_factor: vk::BlendFactor, color_dst_factor: vk::BlendFactor, alpha_src_factor: vk::BlendFactor, alpha_dst_factor: vk::BlendFactor) -> Self {
+        Self {
+            color_op: vk::BlendOp::ADD,
+            color_src_factor,
+            color_dst_factor,
+            alpha_op: vk::BlendOp::ADD,
+            alpha_src_factor,
+            alpha_dst_factor,
+        }
+    }
+}
+
+/// Canonical form of the blend state of a draw. Different blend states producing the same result
+/// map to the same mode so that pipelines only need to be created once per mode.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum BlendMode {
+    /// Blending is disabled and the color is written as is.
+    Opaque,
+    /// See [`BlendState::TRANSLUCENT`].
+    Alpha,
+    /// See [`BlendState::ADDITIVE`].
+    Additive,
+    /// See [`BlendState::MULTIPLY`].
+    Multiply,
+    Custom(BlendState),
+}
+
+impl BlendMode {
+    /// Returns the canonical mode of a blend state. Blend states which always output the source
+    /// color are treated as opaque.
+    pub fn from_blend_state(blend_state: Option<BlendState>) -> Self {
+        match blend_state {
+            None => Self::Opaque,
+            Some(BlendState::TRANSLUCENT) => Self::Alpha,
+            Some(BlendState::ADDITIVE) => Self::Additive,
+            Some(BlendState::MULTIPLY) => Self::Multiply,
+            Some(blend_state) => {
+                if blend_state == BlendState::new(vk::BlendFactor::ONE, vk::BlendFactor::ZERO) {
+                    Self::Opaque
+                } else {
+                    Self::Custom(blend_state)
+                }
+            }
+        }
+    }
+
+    /// Returns the blend state of this mode. If [`None`] blending is disabled.
+    pub fn to_blend_state(&self) -> Option<BlendState> {
+        match self {
+            Self::Opaque => None,
+            Self::Alpha => Some(BlendState::TRANSLUCENT),
+            Self::Additive => Some(BlendState::ADDITIVE),
+            Self::Multiply => Some(BlendState::MULTIPLY),
+            Self::Custom(blend_state) => Some(*blend_state),
+        }
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        *self == Self::Opaque
+    }
+}
+
+fn is_dual_source_factor(factor: vk::BlendFactor) -> bool {
+    matches!(factor, vk::BlendFactor::SRC1_COLOR | vk::BlendFactor::ONE_MINUS_SRC1_COLOR | vk::BlendFactor::SRC1_ALPHA | vk::BlendFactor::ONE_MINUS_SRC1_ALPHA)
+}
+
+fn to_single_source_factor(factor: vk::BlendFactor) -> vk::BlendFactor {
+    match factor {
+        vk::BlendFactor::SRC1_COLOR => vk::BlendFactor::SRC_COLOR,
+        vk::BlendFactor::ONE_MINUS_SRC1_COLOR => vk::BlendFactor::ONE_MINUS_SRC_COLOR,
+        vk::BlendFactor::SRC1_ALPHA => vk::BlendFactor::SRC_ALPHA,
+        vk::BlendFactor::ONE_MINUS_SRC1_ALPHA => vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        factor => factor,
+    }
+}
+
+/// Parameters of the vanilla fullscreen overlay effects. Instead of drawing fullscreen quads these
+/// are applied by pipelines in their post processing stage. All intensities are in the range 0 to 1
+/// where 0 disables the effect.
+///
+/// The layout matches the `_ScreenEffects` block in `screen_effects.glsl`.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ScreenEffects {
+    /// The nether portal wobble and tint. Vanilla uses the portal time of the player.
+    pub portal: f32,
+
+    /// The underwater distortion and tint.
+    pub underwater: f32,
+
+    /// The powder snow frost overlay. Vanilla uses the percentage frozen of the player.
+    pub powder_snow: f32,
+
+    /// The pumpkin blur vignette.
+    pub pumpkin_blur: f32,
+
+    /// The time in seconds used to animate the effects.
+    pub time: f32,
+}
+const_assert_eq!(std::mem::size_of::<ScreenEffects>(), 20);
+
+unsafe impl Zeroable for ScreenEffects {}
+unsafe impl Pod for ScreenEffects {}
+
+impl ScreenEffects {
+    pub const NONE: Self = Self {
+        portal: 0f32,
+        underwater: 0f32,
+        powder_snow: 0f32,
+        pumpkin_blur: 0f32,
+        time: 0f32,
+    };
+
+    /// Returns true if none of the effects are enabled.
+    pub fn is_none(&self) -> bool {
+        self.portal <= 0f32 && self.underwater <= 0f32 && self.powder_snow <= 0f32 && self.pumpkin_blur <= 0f32
+    }
+}
+
+impl Default for ScreenEffects {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Controls how the attachments of a pass are initialized before the first draw.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ClearConfig {
+    /// The value the color attachment is cleared to. If [`None`] the color attachment is not
+    /// cleared and its content is undefined until it is overwritten. Useful if the first draws of
+    /// the pass cover the full framebuffer, for example the sky.
+    pub color: Option<[f32; 4]>,
+
+    /// If false the depth attachment is not cleared and its content is undefined until it is
+    /// overwritten. Otherwise it is cleared to the far plane.
+    pub clear_depth: bool,
+}
+
+impl ClearConfig {
+    /// Clears the color attachment to transparent black and the depth attachment to the far plane.
+    pub const DEFAULT: Self = Self {
+        color: Some([0f32, 0f32, 0f32, 0f32]),
+        clear_depth: true,
+    };
+
+    pub const fn new(color: Option<[f32; 4]>, clear_depth: bool) -> Self {
+        Self {
+            color,
+            clear_depth,
+        }
+    }
+}
+
+impl Default for ClearConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The point lights of a pass. See [`lights`](super::lights).
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LightsInfo {
+    /// A storage buffer containing the lights starting at the offset. Each light is stored as a
+    /// view space position and radius followed by a color and intensity. Null if there are no
+    /// lights.
+    pub buffer: vk::Buffer,
+    pub offset: vk::DeviceSize,
+    pub count: u32,
+
+    /// The projection matrix used to determine the framebuffer tiles affected by each light.
+    pub projection: Mat4f32,
+}
+
+impl LightsInfo {
+    pub fn none() -> Self {
+        Self {
+            buffer: vk::Buffer::null(),
+            offset: 0,
+            count: 0,
+            projection: Mat4f32::identity(),
+        }
+    }
+}
+
+/// A draw whose draw commands are generated on the gpu. The index range and instances of the
+/// draw task are replaced by the commands of the visible sections.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CulledDrawTask {
+    pub draw: DrawTask,
+
+    /// A storage buffer containing the sections starting at the offset.
+    pub section_buffer: vk::Buffer,
+    pub section_offset: vk::DeviceSize,
+    pub section_count: u32,
+
+    /// The buffer the culling shader writes the draw count followed by the draw commands into.
+    /// Large enough to hold a command for every section.
+    pub indirect_buffer: vk::Buffer,
+    pub indirect_offset: vk::DeviceSize,
+
+    /// The model view projection matrix the section bounds are tested against.
+    pub view_projection: Mat4f32,
 }
 
 /// Used to process the output of a [`EmulatorPipelinePass`].
@@ -146,6 +736,46 @@ pub trait EmulatorOutput {
     fn on_post_submit(&mut self, queue: &Queue);
 }
 
+/// How the source image of a [`OutputUtil`] is fit into the output image.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PresentScaling {
+    /// The source image is stretched to cover the full output image.
+    Stretch,
+
+    /// The source image is scaled uniformly to fit into the output image. Uncovered areas are
+    /// cleared to black.
+    Letterbox,
+}
+
+/// Configures which attachment of a pass is presented by a [`OutputUtil`] and how.
+///
+/// Presenting attachments other than [`PassAttachment::Output`] is intended for debugging.
+/// [`PassAttachment::Depth`] is visualized as a grayscale image.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PresentConfig {
+    /// The attachment which is presented. If the pipeline cannot sample the attachment
+    /// [`PassAttachment::Output`] is used instead.
+    pub source: PassAttachment,
+
+    /// The filter used to sample the source image.
+    pub filter: vk::Filter,
+    pub scaling: PresentScaling,
+}
+
+impl PresentConfig {
+    pub const DEFAULT: Self = Self {
+        source: PassAttachment::Output,
+        filter: vk::Filter::LINEAR,
+        scaling: PresentScaling::Stretch,
+    };
+}
+
+impl Default for PresentConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
 /// A utility struct providing a [`BlitPass`] for the output of a [`EmulatorPipeline`].
 pub struct OutputUtil {
     #[allow(unused)] // We just need to keep the pipeline alive
@@ -153,22 +783,49 @@ pub struct OutputUtil {
     descriptor_pool: vk::DescriptorPool,
     descriptor_sets: Box<[vk::DescriptorSet]>,
     blit_pass: BlitPass,
+    source: PassAttachment,
+    source_size: Vec2u32,
+    scaling: PresentScaling,
 }
 
 impl OutputUtil {
-    pub fn new(device: &DeviceContext, pipeline: Arc<dyn EmulatorPipeline>, format: vk::Format, final_layout: vk::ImageLayout) -> Self {
-        let (_, sampler_views) = pipeline.get_output();
+    pub fn new(device: &DeviceContext, pipeline: Arc<dyn EmulatorPipeline>, format: vk::Format, color_space: vk::ColorSpaceKHR, premultiply_alpha: bool, final_layout: vk::ImageLayout) -> Self {
+        Self::new_with_config(device, pipeline, format, color_space, premultiply_alpha, final_layout, &PresentConfig::DEFAULT)
+    }
+
+    /// Creates a output util presenting the source selected by the config.
+    pub fn new_with_config(device: &DeviceContext, pipeline: Arc<dyn EmulatorPipeline>, format: vk::Format, color_space: vk::ColorSpaceKHR, premultiply_alpha: bool, final_layout: vk::ImageLayout, config: &PresentConfig) -> Self {
+        let (source, (sampler_views, sampler_layout)) = match pipeline.get_sampled_attachment(config.source) {
+            Some(views) => (config.source, views),
+            None => {
+                log::warn!("Pipeline cannot present attachment {:?}. Presenting the output instead", config.source);
+                (PassAttachment::Output, pipeline.get_sampled_attachment(PassAttachment::Output).unwrap())
+            }
+        };
+        let (source_size, _) = pipeline.get_output();
 
-        let blit_pass = device.get_utils().blit_utils().create_blit_pass(format, vk::AttachmentLoadOp::DONT_CARE, vk::ImageLayout::UNDEFINED, final_layout);
+        let sampling = BlitSampling {
+            nearest: config.filter == vk::Filter::NEAREST,
+            grayscale: source == PassAttachment::Depth,
+        };
+        // Letterboxing does not cover the full output so the rest must be cleared
+        let load_op = match config.scaling {
+            PresentScaling::Stretch => vk::AttachmentLoadOp::DONT_CARE,
+            PresentScaling::Letterbox => vk::AttachmentLoadOp::CLEAR,
+        };
+        let blit_pass = device.get_utils().blit_utils().create_blit_pass_with_sampling(format, color_space, premultiply_alpha, sampling, load_op, vk::ImageLayout::UNDEFINED, final_layout);
 
         let descriptor_pool = Self::create_descriptor_pool(device, sampler_views.len());
-        let descriptor_sets = blit_pass.create_descriptor_sets(descriptor_pool, sampler_views).unwrap().into_boxed_slice();
+        let descriptor_sets = blit_pass.create_descriptor_sets_with_layout(descriptor_pool, &sampler_views, sampler_layout).unwrap().into_boxed_slice();
 
         Self {
             pipeline,
             descriptor_pool,
             descriptor_sets,
-            blit_pass
+            blit_pass,
+            source,
+            source_size,
+            scaling: config.scaling,
         }
     }
 
@@ -184,12 +841,39 @@ impl OutputUtil {
     ///
     /// The pipeline index is the index returned by [`EmulatorPipelinePass::get_output_index`].
     pub fn record(&self, command_buffer: vk::CommandBuffer, output_framebuffer: vk::Framebuffer, output_size: Vec2u32, pipeline_index: usize) {
+        if self.source != PassAttachment::Output {
+            // Pipelines only guarantee that the output is visible to outputs
+            let barrier = vk::MemoryBarrier::builder()
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                .dst_access_mask(vk::AccessFlags::SHADER_READ);
+
+            unsafe {
+                self.blit_pass.get_device().vk.cmd_pipeline_barrier(
+                    command_buffer,
+                    vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    std::slice::from_ref(&barrier),
+                    &[],
+                    &[]
+                );
+            }
+        }
+
+        let (region, clear_value) = match self.scaling {
+            PresentScaling::Stretch => (None, None),
+            PresentScaling::Letterbox => (Some(letterbox_region(self.source_size, output_size)), Some(vk::ClearValue {
+                color: vk::ClearColorValue { float32: [0f32, 0f32, 0f32, 1f32] }
+            })),
+        };
+
         self.blit_pass.record_blit(
             command_buffer,
             self.descriptor_sets[pipeline_index],
             output_framebuffer,
             output_size,
-            None
+            region,
+            clear_value.as_ref()
         )
     }
 
@@ -219,27 +903,61 @@ impl Drop for OutputUtil {
     }
 }
 
+/// Returns the largest region centered in the output which has the aspect ratio of the source.
+fn letterbox_region(source_size: Vec2u32, output_size: Vec2u32) -> vk::Rect2D {
+    let (source_width, source_height) = (source_size[0] as u64, source_size[1] as u64);
+    let (output_width, output_height) = (output_size[0] as u64, output_size[1] as u64);
+
+    let (width, height) = if source_width == 0 || source_height == 0 {
+        (output_width, output_height)
+    } else if output_width * source_height > output_height * source_width {
+        (source_width * output_height / source_height, output_height)
+    } else {
+        (output_width, source_height * output_width / source_width)
+    };
+
+    vk::Rect2D {
+        offset: vk::Offset2D { x: ((output_width - width) / 2) as i32, y: ((output_height - height) / 2) as i32 },
+        extent: vk::Extent2D { width: width as u32, height: height as u32 }
+    }
+}
+
 /// A [`EmulatorOutput`] implementation which copes the output image to a swapchain image and
 /// presents it.
 pub struct SwapchainOutput {
     weak: Weak<Self>,
     swapchain: Arc<SurfaceSwapchain>,
     util: OutputUtil,
+    overlay: Option<OverlayRenderer>,
+    compositor: BlitPass,
     framebuffers: Box<[vk::Framebuffer]>,
 }
 
 impl SwapchainOutput {
     pub fn new(device: &DeviceContext, pipeline: Arc<dyn EmulatorPipeline>, swapchain: Arc<SurfaceSwapchain>) -> Arc<Self> {
-        let util = OutputUtil::new(device, pipeline, swapchain.get_image_format().format, vk::ImageLayout::PRESENT_SRC_KHR);
+        Self::new_with_overlay(device, pipeline, swapchain, None, &PresentConfig::DEFAULT)
+    }
+
+    /// Creates a new swapchain output which draws a [`DebugOverlay`] on top of the presented
+    /// source selected by the config.
+    pub fn new_with_overlay(device: &DeviceContext, pipeline: Arc<dyn EmulatorPipeline>, swapchain: Arc<SurfaceSwapchain>, overlay: Option<&Arc<DebugOverlay>>, present_config: &PresentConfig) -> Arc<Self> {
+        let format = swapchain.get_image_format();
+        let premultiply_alpha = swapchain.get_composite_alpha() == vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED;
+        let util = OutputUtil::new_with_config(device, pipeline, format.format, format.color_space, premultiply_alpha, vk::ImageLayout::PRESENT_SRC_KHR, present_config);
 
         let framebuffers = swapchain.get_images().iter().map(|image| {
             util.create_framebuffer(image.get_framebuffer_view(), swapchain.get_image_size()).unwrap()
         }).collect();
 
+        let overlay = overlay.map(|overlay| overlay.create_renderer(format.format, swapchain.get_image_size()));
+        let compositor = device.get_utils().blit_utils().create_blend_blit_pass(format.format, format.color_space, premultiply_alpha, vk::ImageLayout::PRESENT_SRC_KHR);
+
         Arc::new_cyclic(|weak| Self {
             weak: weak.clone(),
             swapchain,
             util,
+            overlay,
+            compositor,
             framebuffers
         })
     }
@@ -251,11 +969,17 @@ impl SwapchainOutput {
     /// If it successfully acquires a image returns a [`EmulatorOutput`] instance for the image as
     /// well as a boolean flag set to true if the swapchain is suboptimal.
     pub fn next_image(&self) -> Option<(Box<dyn EmulatorOutput + Send>, bool)> {
+        self.next_image_with_layers(Vec::new())
+    }
+
+    /// Like [`SwapchainOutput::next_image`] but blends the latest output of each layer over the
+    /// presented image in order. The debug overlay is drawn on top of all layers.
+    pub fn next_image_with_layers(&self, layers: Vec<CompositorLayer>) -> Option<(Box<dyn EmulatorOutput + Send>, bool)> {
         loop {
             let arc = self.weak.upgrade().unwrap();
             match self.swapchain.acquire_next_image(1000000000, None) {
                 Ok((info, suboptimal)) =>
-                    return Some((Box::new(SwapchainOutputInstance::new(arc, info)), suboptimal)),
+                    return Some((Box::new(SwapchainOutputInstance::new(arc, info, layers)), suboptimal)),
                 Err(vk::Result::TIMEOUT) =>
                     log::warn!("1s timeout reached while waiting for next swapchain image in SwapchainOutput::next_image"),
                 Err(err) => {
@@ -282,14 +1006,20 @@ struct SwapchainOutputInstance {
     output: Arc<SwapchainOutput>,
     image_info: AcquiredImageInfo,
     pipeline_index: Option<usize>,
+    overlay_draw: Option<OverlayDraw>,
+    layers: Vec<CompositorLayer>,
 }
 
 impl SwapchainOutputInstance {
-    fn new(output: Arc<SwapchainOutput>, image_info: AcquiredImageInfo) -> Self {
+    fn new(output: Arc<SwapchainOutput>, image_info: AcquiredImageInfo, layers: Vec<CompositorLayer>) -> Self {
+        let overlay_draw = output.overlay.as_ref().map(OverlayRenderer::prepare);
+
         Self {
             output,
             image_info,
             pipeline_index: None,
+            overlay_draw,
+            layers,
         }
     }
 }
@@ -302,7 +1032,12 @@ impl EmulatorOutput for SwapchainOutputInstance {
     fn record<'a>(&mut self, obj: &mut PooledObjectProvider, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
         let cmd = obj.get_begin_command_buffer().unwrap();
 
-        self.output.util.record(cmd, self.output.framebuffers[self.image_info.image_index as usize], self.output.swapchain.get_image_size(), self.pipeline_index.unwrap());
+        let framebuffer = self.output.framebuffers[self.image_info.image_index as usize];
+        self.output.util.record(cmd, framebuffer, self.output.swapchain.get_image_size(), self.pipeline_index.unwrap());
+        compositor::record_layers(cmd, &self.output.compositor, framebuffer, self.output.swapchain.get_image_size(), &self.layers);
+        if let (Some(overlay), Some(draw)) = (&self.output.overlay, &self.overlay_draw) {
+            overlay.record(cmd, framebuffer, draw);
+        }
 
         unsafe {
             self.output.swapchain.get_device().vk.end_command_buffer(cmd)
@@ -341,15 +1076,83 @@ impl EmulatorOutput for SwapchainOutputInstance {
     fn on_post_submit(&mut self, queue: &Queue) {
         let present_semaphore = self.output.swapchain.get_images()[self.image_info.image_index as usize].get_present_semaphore().get_handle();
 
+        let present_time = self.output.swapchain.next_present_id().map(|present_id| {
+            vk::PresentTimeGOOGLE {
+                present_id,
+                desired_present_time: 0,
+            }
+        });
+        let mut present_times = present_time.as_ref().map(|present_time| {
+            vk::PresentTimesInfoGOOGLE::builder()
+                .times(std::slice::from_ref(present_time))
+        });
+
         let guard = self.output.swapchain.get_swapchain().lock().unwrap();
 
-        let present_info = vk::PresentInfoKHR::builder()
+        let mut present_info = vk::PresentInfoKHR::builder()
             .wait_semaphores(std::slice::from_ref(&present_semaphore))
             .swapchains(std::slice::from_ref(&*guard))
             .image_indices(std::slice::from_ref(&self.image_info.image_index));
+        if let Some(present_times) = present_times.as_mut() {
+            present_info = present_info.push_next(present_times);
+        }
 
         unsafe {
             queue.present(&present_info)
         }.unwrap();
+        drop(guard);
+
+        self.output.swapchain.update_present_timing();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> vk::Rect2D {
+        vk::Rect2D {
+            offset: vk::Offset2D { x, y },
+            extent: vk::Extent2D { width, height }
+        }
+    }
+
+    #[test]
+    fn letterbox() {
+        assert_eq!(letterbox_region(Vec2u32::new(1920, 1080), Vec2u32::new(1920, 1080)), rect(0, 0, 1920, 1080));
+        assert_eq!(letterbox_region(Vec2u32::new(960, 540), Vec2u32::new(1920, 1080)), rect(0, 0, 1920, 1080));
+
+        // Pillarbox
+        assert_eq!(letterbox_region(Vec2u32::new(800, 600), Vec2u32::new(1920, 1080)), rect(240, 0, 1440, 1080));
+
+        assert_eq!(letterbox_region(Vec2u32::new(1920, 800), Vec2u32::new(1920, 1080)), rect(0, 140, 1920, 800));
+        assert_eq!(letterbox_region(Vec2u32::new(0, 0), Vec2u32::new(100, 100)), rect(0, 0, 100, 100));
+    }
+
+    #[test]
+    fn dual_source_approximation() {
+        let blend_state = BlendState::new_separate(vk::BlendFactor::ONE, vk::BlendFactor::ONE_MINUS_SRC1_COLOR, vk::BlendFactor::SRC1_ALPHA, vk::BlendFactor::ZERO);
+        assert!(blend_state.uses_dual_source());
+
+        let approximated = blend_state.approximate_dual_source();
+        assert!(!approximated.uses_dual_source());
+        assert_eq!(approximated, BlendState::new_separate(vk::BlendFactor::ONE, vk::BlendFactor::ONE_MINUS_SRC_COLOR, vk::BlendFactor::SRC_ALPHA, vk::BlendFactor::ZERO));
+
+        assert!(!BlendState::TRANSLUCENT.uses_dual_source());
+        assert_eq!(BlendState::TRANSLUCENT.approximate_dual_source(), BlendState::TRANSLUCENT);
+    }
+
+    #[test]
+    fn blend_mode_canonicalization() {
+        assert_eq!(BlendMode::from_blend_state(None), BlendMode::Opaque);
+        assert_eq!(BlendMode::from_blend_state(Some(BlendState::new(vk::BlendFactor::ONE, vk::BlendFactor::ZERO))), BlendMode::Opaque);
+        assert_eq!(BlendMode::from_blend_state(Some(BlendState::TRANSLUCENT)), BlendMode::Alpha);
+        assert_eq!(BlendMode::from_blend_state(Some(BlendState::ADDITIVE)), BlendMode::Additive);
+        assert_eq!(BlendMode::from_blend_state(Some(BlendState::MULTIPLY)), BlendMode::Multiply);
+        assert_eq!(BlendMode::from_blend_state(Some(BlendState::LIGHTNING)), BlendMode::Custom(BlendState::LIGHTNING));
+
+        for mode in [BlendMode::Opaque, BlendMode::Alpha, BlendMode::Additive, BlendMode::Multiply, BlendMode::Custom(BlendState::GLINT)] {
+            assert_eq!(BlendMode::from_blend_state(mode.to_blend_state()), mode);
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,62 @@
+//! Background threads used to create pipelines without blocking the threads recording passes.
+//!
+//! Pipeline creation can take tens of milliseconds for new shaders which causes visible hitches if
+//! it happens while recording a frame. Jobs submitted here are executed on a small shared pool of
+//! threads which is started on first use.
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use lazy_static::lazy_static;
+
+pub(super) type CompileJob = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    static ref COMPILER: PipelineCompiler = PipelineCompiler::new();
+}
+
+/// Queues a job to be executed on one of the compiler threads. Jobs are started in submission
+/// order but may complete in any order.
+pub(super) fn submit(job: CompileJob) {
+    COMPILER.sender.lock().unwrap().send(job).unwrap_or_else(|_| {
+        log::error!("Pipeline compiler threads are no longer running");
+        panic!()
+    });
+}
+
+struct PipelineCompiler {
+    sender: Mutex<Sender<CompileJob>>,
+}
+
+impl PipelineCompiler {
+    /// The maximum number of compiler threads. Leaves enough cores for the render and worker
+    /// threads.
+    const MAX_THREAD_COUNT: usize = 4;
+
+    fn new() -> Self {
+        let (sender, receiver) = channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let thread_count = std::thread::available_parallelism().map(|count| count.get() / 2).unwrap_or(1).clamp(1, Self::MAX_THREAD_COUNT);
+        for index in 0..thread_count {
+            let receiver = receiver.clone();
+            std::thread::Builder::new().name(format!("B4D Pipeline Compiler {}", index)).spawn(move || {
+                Self::run(receiver);
+            }).unwrap();
+        }
+
+        Self {
+            sender: Mutex::new(sender),
+        }
+    }
+
+    fn run(receiver: Arc<Mutex<Receiver<CompileJob>>>) {
+        loop {
+            let job = receiver.lock().unwrap().recv();
+            match job {
+                Ok(job) => job(),
+                Err(_) => return,
+            }
+        }
+    }
+}
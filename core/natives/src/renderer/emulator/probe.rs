@@ -0,0 +1,412 @@
+//! Environment probes capturing the scene around a point for reflections.
+//!
+//! A probe renders the 6 faces of a cube map using regular emulator passes. Since shaders can only
+//! bind a small number of 2d textures the faces are stored in a single [`GlobalImage`] laid out as
+//! a 3 by 2 atlas. Face `n` is stored in column `n % 3` and row `n / 3`, the faces are ordered
+//! +X, -X, +Y, -Y, +Z, -Z and use the same orientation as vulkan cube maps. The
+//! `environment_probe.glsl` include provides the matching lookup for shaders.
+//!
+//! Each face is rendered into its own pass using a pipeline with a square output. The output of
+//! the pass is blitted into the atlas once the pass has been executed so the pipeline resolution
+//! does not have to match the probe resolution.
+
+use std::sync::Arc;
+
+use ash::vk;
+use bumpalo::Bump;
+
+use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, PassRecorder, SamplerInfo};
+use crate::renderer::emulator::mc_shaders::ShaderId;
+use crate::renderer::emulator::pipeline::{AttachmentInfo, EmulatorOutput, EmulatorPipeline, EmulatorPipelinePass, PassAttachment, PooledObjectProvider, SubmitRecorder};
+use crate::renderer::emulator::projection::{ClipPlanes, PerspectiveCamera};
+use crate::device::device::Queue;
+use crate::util::format::Format;
+
+use crate::prelude::*;
+
+/// A cube map of the scene around a point which is updated over multiple frames.
+pub struct EnvironmentProbe {
+    image: Arc<GlobalImage>,
+    face_size: u32,
+
+    faces_per_update: u32,
+    update_interval: u32,
+    frames_until_update: u32,
+    next_face: u32,
+    full_update_requested: bool,
+}
+
+impl EnvironmentProbe {
+    pub const FACE_COUNT: u32 = 6;
+
+    /// The sampler which should be used to sample the probe. Mipmaps are not generated and
+    /// the faces must not be repeated.
+    pub const SAMPLER_INFO: SamplerInfo = SamplerInfo {
+        mag_filter: vk::Filter::LINEAR,
+        min_filter: vk::Filter::LINEAR,
+        mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+        address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        anisotropy_enable: false
+    };
+
+    /// Creates a new probe with faces of `face_size` by `face_size` texels. By default one face is
+    /// updated every frame. The faces are cleared until they are first rendered.
+    pub fn new(emulator: &EmulatorRenderer, face_size: u32, format: &'static Format) -> Self {
+        if face_size == 0 {
+            log::error!("Environment probe face size must not be 0");
+            panic!()
+        }
+
+        let image = emulator.create_global_image(Vec2u32::new(face_size * 3, face_size * 2), format);
+
+        Self {
+            image,
+            face_size,
+            faces_per_update: 1,
+            update_interval: 1,
+            frames_until_update: 0,
+            next_face: 0,
+            full_update_requested: true,
+        }
+    }
+
+    /// Returns the atlas containing all faces.
+    pub fn get_image(&self) -> &Arc<GlobalImage> {
+        &self.image
+    }
+
+    pub fn get_face_size(&self) -> u32 {
+        self.face_size
+    }
+
+    /// Sets how often the probe is updated. Every `interval` frames the next `faces` faces are
+    /// updated. A interval of 1 updates the probe every frame.
+    pub fn set_update_rate(&mut self, faces: u32, interval: u32) {
+        if faces == 0 || interval == 0 {
+            log::error!("Invalid environment probe update rate of {} faces every {} frames", faces, interval);
+            panic!()
+        }
+        self.faces_per_update = faces.min(Self::FACE_COUNT);
+        self.update_interval = interval;
+        self.frames_until_update = self.frames_until_update.min(interval - 1);
+    }
+
+    /// Requests all faces to be updated the next time [`EnvironmentProbe::begin_frame`] is called.
+    /// Should be used when the probe is moved or the scene changed significantly.
+    pub fn request_full_update(&mut self) {
+        self.full_update_requested = true;
+    }
+
+    /// Advances the update schedule by one frame and returns the faces which should be rendered
+    /// this frame. Each returned face should be rendered into its own pass followed by a call to
+    /// [`EnvironmentProbe::capture_face`].
+    pub fn begin_frame(&mut self) -> Vec<u32> {
+        if self.full_update_requested {
+            self.full_update_requested = false;
+            self.frames_until_update = self.update_interval - 1;
+            return (0..Self::FACE_COUNT).collect();
+        }
+
+        if self.frames_until_update > 0 {
+            self.frames_until_update -= 1;
+            return Vec::new();
+        }
+        self.frames_until_update = self.update_interval - 1;
+
+        (0..self.faces_per_update).map(|_| {
+            let face = self.next_face;
+            self.next_face = (self.next_face + 1) % Self::FACE_COUNT;
+            face
+        }).collect()
+    }
+
+    /// Returns the model view matrix used to render a face. The matrix only contains the rotation
+    /// of the face. Like vanilla the geometry must be positioned relative to the probe.
+    pub fn get_face_view_matrix(face: u32) -> Mat4f32 {
+        let rows = FACE_ROTATIONS[face as usize];
+        let mut matrix = Mat4f32::identity();
+        for row in 0..3 {
+            for column in 0..3 {
+                matrix[(row, column)] = rows[row][column];
+            }
+        }
+        matrix
+    }
+
+    /// Returns the projection matrix used to render every face.
+    pub fn get_projection_matrix(clip_planes: ClipPlanes) -> Mat4f32 {
+        PerspectiveCamera::new(90f32.to_radians(), 1f32, clip_planes).get_projection_matrix()
+    }
+
+    /// Copies the output of the pass into a face of the probe once the pass has been executed.
+    /// The pass must have been rendered using the matrices returned by
+    /// [`EnvironmentProbe::get_face_view_matrix`] and [`EnvironmentProbe::get_projection_matrix`].
+    pub fn capture_face(&self, pass: &mut PassRecorder, face: u32) {
+        if face >= Self::FACE_COUNT {
+            log::error!("Environment probe face index {} is out of bounds", face);
+            panic!()
+        }
+        pass.capture_probe_face(self.image.clone(), get_face_offset(face, self.face_size), self.face_size);
+    }
+
+    /// Binds the probe atlas to a texture slot of a shader.
+    pub fn bind(&self, pass: &mut PassRecorder, index: u32, shader: ShaderId) {
+        pass.update_texture(index, &self.image, &Self::SAMPLER_INFO, shader);
+    }
+}
+
+/// The rotation of every face. Vulkan cube maps are left handed so each face is rendered mirrored
+/// horizontally which is undone when blitting into the atlas. Otherwise the rotations would have
+/// to include a reflection which would invert the winding order of all triangles.
+const FACE_ROTATIONS: [[[f32; 3]; 3]; 6] = [
+    [[0f32, 0f32, 1f32], [0f32, 1f32, 0f32], [-1f32, 0f32, 0f32]],
+    [[0f32, 0f32, -1f32], [0f32, 1f32, 0f32], [1f32, 0f32, 0f32]],
+    [[-1f32, 0f32, 0f32], [0f32, 0f32, -1f32], [0f32, -1f32, 0f32]],
+    [[-1f32, 0f32, 0f32], [0f32, 0f32, 1f32], [0f32, 1f32, 0f32]],
+    [[-1f32, 0f32, 0f32], [0f32, 1f32, 0f32], [0f32, 0f32, -1f32]],
+    [[1f32, 0f32, 0f32], [0f32, 1f32, 0f32], [0f32, 0f32, 1f32]],
+];
+
+/// Returns the texel offset of a face in the atlas.
+fn get_face_offset(face: u32, face_size: u32) -> Vec2u32 {
+    Vec2u32::new((face % 3) * face_size, (face / 3) * face_size)
+}
+
+/// Returns the face and uv coordinates within the face sampled for a direction. Matches
+/// `environment_probe_face_uv` in `environment_probe.glsl`.
+#[cfg(test)]
+fn get_face_uv(direction: Vec3f32) -> (u32, Vec2f32) {
+    let abs = direction.abs();
+    let (face, sc, tc, ma) = if abs.x >= abs.y && abs.x >= abs.z {
+        if direction.x >= 0f32 {
+            (0, -direction.z, -direction.y, abs.x)
+        } else {
+            (1, direction.z, -direction.y, abs.x)
+        }
+    } else if abs.y >= abs.z {
+        if direction.y >= 0f32 {
+            (2, direction.x, direction.z, abs.y)
+        } else {
+            (3, direction.x, -direction.z, abs.y)
+        }
+    } else if direction.z >= 0f32 {
+        (4, direction.x, -direction.y, abs.z)
+    } else {
+        (5, -direction.x, -direction.y, abs.z)
+    };
+
+    (face, Vec2f32::new((sc / ma + 1f32) / 2f32, (tc / ma + 1f32) / 2f32))
+}
+
+/// Blits the output of a pass into a face of a probe atlas.
+pub(super) struct ProbeFaceOutput {
+    device: Arc<DeviceContext>,
+    pipeline: Arc<dyn EmulatorPipeline>,
+    atlas: Arc<GlobalImage>,
+    offset: Vec2u32,
+    face_size: u32,
+    source: Option<AttachmentInfo>,
+}
+
+impl ProbeFaceOutput {
+    pub(super) fn new(device: Arc<DeviceContext>, pipeline: Arc<dyn EmulatorPipeline>, atlas: Arc<GlobalImage>, offset: Vec2u32, face_size: u32) -> Self {
+        Self {
+            device,
+            pipeline,
+            atlas,
+            offset,
+            face_size,
+            source: None,
+        }
+    }
+
+    fn record_blit(&self, command_buffer: vk::CommandBuffer, source: &AttachmentInfo) {
+        let color_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1
+        };
+        let color_layers = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1
+        };
+
+        let pre_barriers = [
+            vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+                .old_layout(source.layout)
+                .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .image(source.image)
+                .subresource_range(color_range)
+                .build(),
+            // Global images are in the shader read only layout between global object recorders
+            vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .src_access_mask(vk::AccessFlags2::NONE)
+                .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .image(self.atlas.get_image_handle())
+                .subresource_range(color_range)
+                .build(),
+        ];
+
+        // The destination offsets are swapped to undo the mirroring of the face rotations
+        let x = self.offset[0] as i32;
+        let y = self.offset[1] as i32;
+        let size = self.face_size as i32;
+        let blit = vk::ImageBlit {
+            src_subresource: color_layers,
+            src_offsets: [
+                vk::Offset3D { x: 0, y: 0, z: 0 },
+                vk::Offset3D { x: source.size[0] as i32, y: source.size[1] as i32, z: 1 },
+            ],
+            dst_subresource: color_layers,
+            dst_offsets: [
+                vk::Offset3D { x: x + size, y, z: 0 },
+                vk::Offset3D { x, y: y + size, z: 1 },
+            ],
+        };
+
+        let post_barriers = [
+            vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .src_access_mask(vk::AccessFlags2::NONE)
+                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .new_layout(source.layout)
+                .image(source.image)
+                .subresource_range(color_range)
+                .build(),
+            vk::ImageMemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image(self.atlas.get_image_handle())
+                .subresource_range(color_range)
+                .build(),
+        ];
+
+        unsafe {
+            let info = vk::DependencyInfo::builder()
+                .image_memory_barriers(&pre_barriers);
+            self.device.synchronization_2_khr().cmd_pipeline_barrier2(command_buffer, &info);
+
+            self.device.vk().cmd_blit_image(
+                command_buffer,
+                source.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.atlas.get_image_handle(),
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                std::slice::from_ref(&blit),
+                vk::Filter::LINEAR
+            );
+
+            let info = vk::DependencyInfo::builder()
+                .image_memory_barriers(&post_barriers);
+            self.device.synchronization_2_khr().cmd_pipeline_barrier2(command_buffer, &info);
+        }
+    }
+}
+
+impl EmulatorOutput for ProbeFaceOutput {
+    fn init(&mut self, pass: &dyn EmulatorPipelinePass, _: &mut PooledObjectProvider) {
+        let info = match self.pipeline.get_attachment(PassAttachment::Output, pass.get_output_index()) {
+            Some(info) => info,
+            None => {
+                log::warn!("Pipeline does not support capturing environment probe faces");
+                return;
+            }
+        };
+
+        if info.aspect_mask != vk::ImageAspectFlags::COLOR {
+            log::warn!("Environment probe faces can not be captured from outputs with aspect {:?}", info.aspect_mask);
+            return;
+        }
+        if info.size[0] != info.size[1] {
+            log::warn!("Environment probe face captured from a non square output of size {:?}", info.size);
+        }
+
+        self.source = Some(info);
+    }
+
+    fn record<'a>(&mut self, obj: &mut PooledObjectProvider, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+        let source = match &self.source {
+            Some(source) => source,
+            None => return,
+        };
+
+        let cmd = obj.get_begin_command_buffer().unwrap();
+
+        self.record_blit(cmd, source);
+
+        unsafe {
+            self.device.vk().end_command_buffer(cmd)
+        }.unwrap();
+
+        let commands = alloc.alloc([
+            vk::CommandBufferSubmitInfo::builder()
+                .command_buffer(cmd)
+                .build()
+        ]);
+
+        submits.push(vk::SubmitInfo2::builder()
+            .command_buffer_infos(commands)
+        );
+    }
+
+    fn on_post_submit(&mut self, _: &Queue) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn face_rotations_are_proper() {
+        for face in 0..EnvironmentProbe::FACE_COUNT {
+            let matrix = EnvironmentProbe::get_face_view_matrix(face);
+            let rotation = matrix.fixed_slice::<3, 3>(0, 0).into_owned();
+            assert!((rotation.determinant() - 1f32).abs() < 1e-6, "Face {} rotation includes a reflection", face);
+        }
+    }
+
+    #[test]
+    fn rendered_faces_match_lookup() {
+        let projection = EnvironmentProbe::get_projection_matrix(ClipPlanes { near: 0.05f32, far: 100f32 });
+        let directions = [
+            Vec3f32::new(1f32, 0.2f32, -0.3f32),
+            Vec3f32::new(-1f32, -0.4f32, 0.1f32),
+            Vec3f32::new(0.3f32, 1f32, 0.5f32),
+            Vec3f32::new(-0.2f32, -1f32, 0.6f32),
+            Vec3f32::new(0.7f32, -0.1f32, 1f32),
+            Vec3f32::new(-0.5f32, 0.3f32, -1f32),
+        ];
+
+        for direction in directions {
+            let (face, uv) = get_face_uv(direction);
+            let view = EnvironmentProbe::get_face_view_matrix(face);
+            let clip = projection * view * Vec4f32::new(direction.x, direction.y, direction.z, 1f32);
+
+            // Framebuffer coordinates with the y flip applied by mc_transform_position and the
+            // horizontal mirroring of the blit into the atlas
+            let ndc = Vec2f32::new(clip.x / clip.w, -clip.y / clip.w);
+            let rendered = Vec2f32::new(1f32 - (ndc.x + 1f32) / 2f32, (ndc.y + 1f32) / 2f32);
+            assert!((rendered - uv).norm() < 1e-5, "Direction {:?} was rendered at {:?} but is sampled at {:?}", direction, rendered, uv);
+        }
+    }
+}
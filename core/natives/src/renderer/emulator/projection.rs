@@ -0,0 +1,179 @@
+//! Perspective projections and the depth conventions used by pipelines.
+//!
+//! Projection matrices passed to shaders always use the OpenGL clip space convention of minecraft
+//! where depth ranges from -1 at the near plane to 1 at the far plane. Pipelines using
+//! [`DepthMode::Reversed`] convert them with [`to_reverse_z`] so that the near plane is mapped to
+//! depth 1 and the far plane to depth 0. Combined with a floating point depth buffer this spreads
+//! the precision evenly over the full view distance.
+
+use ash::vk;
+
+use crate::prelude::*;
+
+/// The mapping of view distance to depth values used by a pipeline.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum DepthMode {
+    /// The near plane is mapped to depth 0 and the far plane to depth 1.
+    Standard,
+
+    /// The near plane is mapped to depth 1 and the far plane to depth 0.
+    Reversed,
+}
+
+impl DepthMode {
+    /// Returns the compare op used to keep fragments closer to the camera.
+    pub fn get_compare_op(&self) -> vk::CompareOp {
+        match self {
+            DepthMode::Standard => vk::CompareOp::LESS,
+            DepthMode::Reversed => vk::CompareOp::GREATER,
+        }
+    }
+
+    /// Returns the depth value of the far plane which the depth buffer is cleared to.
+    pub fn get_clear_depth(&self) -> f32 {
+        match self {
+            DepthMode::Standard => 1f32,
+            DepthMode::Reversed => 0f32,
+        }
+    }
+
+    /// Returns the factor applied to depth bias values so that they move fragments in the same
+    /// direction relative to the camera.
+    pub fn get_depth_bias_factor(&self) -> f32 {
+        match self {
+            DepthMode::Standard => 1f32,
+            DepthMode::Reversed => -1f32,
+        }
+    }
+}
+
+/// The distance of the near and far plane from the camera.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ClipPlanes {
+    pub near: f32,
+
+    /// May be [`f32::INFINITY`] in which case nothing is clipped by the far plane.
+    pub far: f32,
+}
+
+impl ClipPlanes {
+    /// The planes used by vanilla for a render distance in chunks.
+    pub fn for_render_distance(render_distance: u32) -> Self {
+        Self {
+            near: 0.05f32,
+            far: ((render_distance * 16) as f32) * 4f32,
+        }
+    }
+}
+
+/// A perspective camera projection.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PerspectiveCamera {
+    /// The vertical field of view in radians.
+    pub fov_y: f32,
+    pub aspect_ratio: f32,
+    pub clip_planes: ClipPlanes,
+}
+
+impl PerspectiveCamera {
+    pub fn new(fov_y: f32, aspect_ratio: f32, clip_planes: ClipPlanes) -> Self {
+        Self {
+            fov_y,
+            aspect_ratio,
+            clip_planes,
+        }
+    }
+
+    pub fn set_clip_planes(&mut self, clip_planes: ClipPlanes) {
+        self.clip_planes = clip_planes;
+    }
+
+    /// Returns the projection matrix in the OpenGL convention expected by the projection matrix
+    /// uniform.
+    pub fn get_projection_matrix(&self) -> Mat4f32 {
+        let ClipPlanes { near, far } = self.clip_planes;
+        let (m22, m23) = if far.is_infinite() {
+            (-1f32, -2f32 * near)
+        } else {
+            ((far + near) / (near - far), (2f32 * far * near) / (near - far))
+        };
+
+        self.make_matrix(m22, m23)
+    }
+
+    /// Returns the projection matrix mapping directly to reversed depth values. Avoids the
+    /// rounding errors of converting the OpenGL matrix with [`to_reverse_z`].
+    pub fn get_reverse_z_projection_matrix(&self) -> Mat4f32 {
+        let ClipPlanes { near, far } = self.clip_planes;
+        let (m22, m23) = if far.is_infinite() {
+            (0f32, near)
+        } else {
+            (near / (far - near), (far * near) / (far - near))
+        };
+
+        self.make_matrix(m22, m23)
+    }
+
+    fn make_matrix(&self, m22: f32, m23: f32) -> Mat4f32 {
+        let f = 1f32 / (self.fov_y / 2f32).tan();
+
+        let mut matrix = Mat4f32::zeros();
+        matrix[(0, 0)] = f / self.aspect_ratio;
+        matrix[(1, 1)] = f;
+        matrix[(2, 2)] = m22;
+        matrix[(2, 3)] = m23;
+        matrix[(3, 2)] = -1f32;
+        matrix
+    }
+}
+
+/// Converts a projection matrix in the OpenGL convention to one mapping directly to reversed
+/// depth values in the range 0 to 1.
+pub fn to_reverse_z(projection: &Mat4f32) -> Mat4f32 {
+    let mut result = *projection;
+    let z = (projection.row(3) - projection.row(2)) / 2f32;
+    result.set_row(2, &z);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the depth value of a point in front of the camera after the remapping applied by
+    /// the shaders.
+    fn depth(projection: &Mat4f32, distance: f32, mode: DepthMode) -> f32 {
+        let clip = projection * Vec4f32::new(0f32, 0f32, -distance, 1f32);
+        match mode {
+            DepthMode::Standard => ((clip[2] + clip[3]) / 2f32) / clip[3],
+            DepthMode::Reversed => clip[2] / clip[3],
+        }
+    }
+
+    #[test]
+    fn clip_planes() {
+        let camera = PerspectiveCamera::new(70f32.to_radians(), 16f32 / 9f32, ClipPlanes { near: 0.05f32, far: 512f32 });
+
+        let standard = camera.get_projection_matrix();
+        assert!((depth(&standard, 0.05f32, DepthMode::Standard) - 0f32).abs() < 1e-5);
+        assert!((depth(&standard, 512f32, DepthMode::Standard) - 1f32).abs() < 1e-5);
+        assert!((standard - Mat4f32::new_perspective(16f32 / 9f32, 70f32.to_radians(), 0.05f32, 512f32)).abs().max() < 1e-5);
+
+        let reversed = camera.get_reverse_z_projection_matrix();
+        assert!((depth(&reversed, 0.05f32, DepthMode::Reversed) - 1f32).abs() < 1e-5);
+        assert!((depth(&reversed, 512f32, DepthMode::Reversed) - 0f32).abs() < 1e-5);
+        assert!((to_reverse_z(&standard) - reversed).abs().max() < 1e-5);
+    }
+
+    #[test]
+    fn infinite_far_plane() {
+        let camera = PerspectiveCamera::new(70f32.to_radians(), 1f32, ClipPlanes { near: 0.05f32, far: f32::INFINITY });
+
+        let reversed = camera.get_reverse_z_projection_matrix();
+        assert_eq!(to_reverse_z(&camera.get_projection_matrix()), reversed);
+        assert!((depth(&reversed, 0.05f32, DepthMode::Reversed) - 1f32).abs() < 1e-6);
+
+        // Distant surfaces can still be told apart
+        assert!(depth(&reversed, 100000f32, DepthMode::Reversed) > depth(&reversed, 100001f32, DepthMode::Reversed));
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::{Arc, Mutex};
+
+use ash::vk;
+
+use crate::renderer::emulator::{GlobalMesh, MeshData};
+use crate::renderer::emulator::share::Share;
+
+/// Shared index data for quad lists used by all meshes which do not provide their own indices.
+///
+/// The indices are stored in a global mesh without vertex data. If more quads are requested than
+/// the current mesh contains a new larger mesh is created. Meshes and passes referencing the old
+/// mesh keep it alive until they are done with it.
+pub(super) struct QuadIndexBuffer {
+    current: Mutex<Option<Arc<GlobalMesh>>>,
+}
+
+impl QuadIndexBuffer {
+    const MIN_QUAD_COUNT: u32 = 4096;
+
+    pub(super) fn new() -> Self {
+        Self {
+            current: Mutex::new(None),
+        }
+    }
+
+    /// Returns a mesh containing [`vk::IndexType::UINT32`] quad list indices for at least the
+    /// specified number of quads starting at index 0.
+    pub(super) fn get(&self, share: &Arc<Share>, quad_count: u32) -> Arc<GlobalMesh> {
+        let mut current = self.current.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned quad index buffer mutex");
+            panic!()
+        });
+
+        if let Some(mesh) = current.as_ref() {
+            if mesh.get_draw_info().index_count / 6 >= quad_count {
+                return mesh.clone();
+            }
+        }
+
+        let quad_count = std::cmp::max(quad_count.next_power_of_two(), Self::MIN_QUAD_COUNT);
+        let index_count = quad_count * 6;
+
+        let info = MeshData {
+            vertex_data: &[],
+            index_data: &[],
+            vertex_stride: 0,
+            index_count,
+            index_type: vk::IndexType::UINT32,
+            primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+        };
+        // The indices are generated directly into staging memory
+        let mut tasks = Vec::with_capacity(1);
+        let mesh = GlobalMesh::new_staged(share.clone(), &info, 0, (index_count as vk::DeviceSize) * 4, self, &mut tasks, |staging, index_offset| {
+            staging.write_iter(index_offset as usize / 4, generate_quad_indices(quad_count));
+            Ok(())
+        }).unwrap_or_else(|err| {
+            log::error!("Failed to create quad index buffer for {} quads {:?}", quad_count, err);
+            panic!()
+        });
+        share.push_tasks(tasks);
+        log::debug!("Resized quad index buffer to {} quads", quad_count);
+
+        *current = Some(mesh.clone());
+        mesh
+    }
+}
+
+/// Generates the 0, 1, 2, 2, 3, 0 index pattern for the specified number of quads.
+fn generate_quad_indices(quad_count: u32) -> impl Iterator<Item=u32> {
+    (0..quad_count).flat_map(|quad| {
+        let base = quad * 4;
+        [base, base + 1, base + 2, base + 2, base + 3, base]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quad_pattern() {
+        assert_eq!(generate_quad_indices(2).collect::<Vec<_>>(), vec![0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4]);
+    }
+}
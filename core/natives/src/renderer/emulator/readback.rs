@@ -0,0 +1,466 @@
+//! Reading back individual attachments of a pass and global images.
+//!
+//! Used by tools inspecting intermediate render targets and for gpu picking. A readback is
+//! requested using [`PassRecorder::readback_attachment`](super::PassRecorder::readback_attachment)
+//! and copies the attachment into a host visible buffer after the pass has been executed.
+//!
+//! Global images are read back using [`GlobalImage::read_back`](super::GlobalImage::read_back)
+//! which copies a mip level into staging memory together with the other global object updates.
+
+use std::ptr::NonNull;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+
+use ash::vk;
+use bumpalo::Bump;
+
+use crate::allocator::{Allocation, HostAccess};
+use crate::device::device::Queue;
+use crate::renderer::emulator::pipeline::{AttachmentInfo, EmulatorOutput, EmulatorPipeline, EmulatorPipelinePass, PassAttachment, PooledObjectProvider, SubmitRecorder};
+
+use crate::prelude::*;
+
+/// The data of a attachment which has been read back. The texels are tightly packed.
+pub struct AttachmentData {
+    pub attachment: PassAttachment,
+
+    /// The size of the read back region. This is the size of the attachment unless only a region
+    /// has been requested.
+    pub size: Vec2u32,
+    pub format: vk::Format,
+    pub data: Box<[u8]>,
+}
+
+/// Handle to a requested readback.
+pub struct AttachmentReadback {
+    receiver: Receiver<AttachmentData>,
+}
+
+impl AttachmentReadback {
+    /// Blocks until the pass has finished execution and returns the data. Returns [`None`] if the
+    /// pipeline does not support reading back the attachment or the pass has been aborted.
+    pub fn wait(self) -> Option<AttachmentData> {
+        self.receiver.recv().ok()
+    }
+
+    /// Returns the data if the readback has completed. Returns [`Err`] with the handle if the
+    /// readback has not completed yet.
+    pub fn try_get(self) -> Result<Option<AttachmentData>, Self> {
+        match self.receiver.try_recv() {
+            Ok(data) => Ok(Some(data)),
+            Err(TryRecvError::Disconnected) => Ok(None),
+            Err(TryRecvError::Empty) => Err(self),
+        }
+    }
+}
+
+/// The data of a mip level of a global image which has been read back. The texels are tightly
+/// packed.
+pub struct GlobalImageData {
+    pub mip_level: u32,
+    pub size: Vec2u32,
+    pub format: vk::Format,
+    pub data: Box<[u8]>,
+}
+
+/// Handle to a requested global image readback.
+pub struct GlobalImageReadback {
+    receiver: Receiver<GlobalImageData>,
+}
+
+impl GlobalImageReadback {
+    pub(super) fn new() -> (Sender<GlobalImageData>, Self) {
+        let (sender, receiver) = channel();
+        (sender, Self {
+            receiver,
+        })
+    }
+
+    /// Blocks until the copy has finished execution and returns the data. Returns [`None`] if
+    /// the format of the image does not support reading back.
+    pub fn wait(self) -> Option<GlobalImageData> {
+        self.receiver.recv().ok()
+    }
+
+    /// Returns the data if the readback has completed. Returns [`Err`] with the handle if the
+    /// readback has not completed yet.
+    pub fn try_get(self) -> Result<Option<GlobalImageData>, Self> {
+        match self.receiver.try_recv() {
+            Ok(data) => Ok(Some(data)),
+            Err(TryRecvError::Disconnected) => Ok(None),
+            Err(TryRecvError::Empty) => Err(self),
+        }
+    }
+}
+
+/// Handle to a requested pick. See [`PassRecorder::pick`](super::PassRecorder::pick).
+pub struct PickReadback {
+    readback: AttachmentReadback,
+}
+
+impl PickReadback {
+    pub(super) fn new(readback: AttachmentReadback) -> Self {
+        Self {
+            readback,
+        }
+    }
+
+    /// Blocks until the pass has finished execution and returns the object id. Returns [`None`]
+    /// if the pipeline does not support object ids, the position is outside of the framebuffer
+    /// or the pass has been aborted.
+    pub fn wait(self) -> Option<u32> {
+        self.readback.wait().and_then(|data| read_object_id(&data))
+    }
+
+    /// Returns the object id if the pick has completed. Returns [`Err`] with the handle if the
+    /// pick has not completed yet.
+    pub fn try_get(self) -> Result<Option<u32>, Self> {
+        match self.readback.try_get() {
+            Ok(data) => Ok(data.and_then(|data| read_object_id(&data))),
+            Err(readback) => Err(Self { readback }),
+        }
+    }
+}
+
+fn read_object_id(data: &AttachmentData) -> Option<u32> {
+    let bytes = data.data.get(0..4)?;
+    Some(u32::from_ne_bytes(bytes.try_into().unwrap()))
+}
+
+/// A [`EmulatorOutput`] copying a attachment of the pass into a host visible buffer.
+pub(super) struct AttachmentReadbackOutput {
+    device: Arc<DeviceContext>,
+    pipeline: Arc<dyn EmulatorPipeline>,
+    attachment: PassAttachment,
+    region: Option<vk::Rect2D>,
+    sender: Sender<AttachmentData>,
+    target: Option<ReadbackBuffer>,
+}
+
+impl AttachmentReadbackOutput {
+    /// Creates a new readback of the attachment. If `region` is [`None`] the full attachment is
+    /// read back.
+    pub(super) fn new(device: Arc<DeviceContext>, pipeline: Arc<dyn EmulatorPipeline>, attachment: PassAttachment, region: Option<vk::Rect2D>) -> (Self, AttachmentReadback) {
+        let (sender, receiver) = channel();
+
+        (Self {
+            device,
+            pipeline,
+            attachment,
+            region,
+            sender,
+            target: None,
+        }, AttachmentReadback {
+            receiver,
+        })
+    }
+
+    fn record_copy(&self, command_buffer: vk::CommandBuffer, target: &ReadbackBuffer) {
+        let info = &target.info;
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: info.aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1
+        };
+
+        let pre_barrier = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+            .old_layout(info.layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .image(info.image)
+            .subresource_range(subresource_range);
+
+        let copy = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: info.aspect_mask,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1
+            },
+            image_offset: vk::Offset3D { x: target.region.offset.x, y: target.region.offset.y, z: 0 },
+            image_extent: vk::Extent3D { width: target.region.extent.width, height: target.region.extent.height, depth: 1 }
+        };
+
+        let post_image_barrier = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::NONE)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(info.layout)
+            .image(info.image)
+            .subresource_range(subresource_range);
+
+        let post_buffer_barrier = vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+            .dst_access_mask(vk::AccessFlags2::HOST_READ)
+            .buffer(target.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE);
+
+        unsafe {
+            let info = vk::DependencyInfo::builder()
+                .image_memory_barriers(std::slice::from_ref(&pre_barrier));
+            self.device.synchronization_2_khr().cmd_pipeline_barrier2(command_buffer, &info);
+
+            self.device.vk().cmd_copy_image_to_buffer(command_buffer, target.info.image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, target.buffer, std::slice::from_ref(&copy));
+
+            let info = vk::DependencyInfo::builder()
+                .image_memory_barriers(std::slice::from_ref(&post_image_barrier))
+                .buffer_memory_barriers(std::slice::from_ref(&post_buffer_barrier));
+            self.device.synchronization_2_khr().cmd_pipeline_barrier2(command_buffer, &info);
+        }
+    }
+}
+
+impl EmulatorOutput for AttachmentReadbackOutput {
+    fn init(&mut self, pass: &dyn EmulatorPipelinePass, _: &mut PooledObjectProvider) {
+        let info = match self.pipeline.get_attachment(self.attachment, pass.get_output_index()) {
+            Some(info) => info,
+            None => {
+                log::warn!("Pipeline does not support reading back {:?} attachment", self.attachment);
+                return;
+            }
+        };
+
+        let texel_size = match get_texel_size(info.format, info.aspect_mask) {
+            Some(size) => size,
+            None => {
+                log::warn!("Reading back attachments of format {:?} with aspect {:?} is not supported", info.format, info.aspect_mask);
+                return;
+            }
+        };
+
+        let region = match self.region {
+            Some(region) => match clamp_region(region, info.size) {
+                Some(region) => region,
+                None => {
+                    log::warn!("Readback region {:?} is outside of the {:?} attachment", region, self.attachment);
+                    return;
+                }
+            },
+            None => vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D { width: info.size[0], height: info.size[1] },
+            },
+        };
+
+        self.target = Some(ReadbackBuffer::new(self.device.clone(), info, region, texel_size));
+    }
+
+    fn record<'a>(&mut self, obj: &mut PooledObjectProvider, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+        let target = match &self.target {
+            Some(target) => target,
+            None => return,
+        };
+
+        let cmd = obj.get_begin_command_buffer().unwrap();
+
+        self.record_copy(cmd, target);
+
+        unsafe {
+            self.device.vk().end_command_buffer(cmd)
+        }.unwrap();
+
+        let commands = alloc.alloc([
+            vk::CommandBufferSubmitInfo::builder()
+                .command_buffer(cmd)
+                .build()
+        ]);
+
+        submits.push(vk::SubmitInfo2::builder()
+            .command_buffer_infos(commands)
+        );
+    }
+
+    fn on_post_submit(&mut self, _: &Queue) {
+    }
+}
+
+impl Drop for AttachmentReadbackOutput {
+    fn drop(&mut self) {
+        // We are only dropped after all submitted commands have finished execution
+        if let Some(target) = self.target.take() {
+            let _ = self.sender.send(AttachmentData {
+                attachment: self.attachment,
+                size: Vec2u32::new(target.region.extent.width, target.region.extent.height),
+                format: target.info.format,
+                data: target.read(),
+            });
+        }
+    }
+}
+
+struct ReadbackBuffer {
+    device: Arc<DeviceContext>,
+    info: AttachmentInfo,
+    region: vk::Rect2D,
+    buffer: vk::Buffer,
+    allocation: Allocation,
+    mapped_ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl ReadbackBuffer {
+    fn new(device: Arc<DeviceContext>, info: AttachmentInfo, region: vk::Rect2D, texel_size: u32) -> Self {
+        let len = (region.extent.width as usize) * (region.extent.height as usize) * (texel_size as usize);
+
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(len as vk::DeviceSize)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let (buffer, allocation, mapped_ptr) = unsafe {
+            device.get_allocator().create_buffer(&buffer_info, HostAccess::Random, &format_args!("AttachmentReadbackBuffer"))
+        }.unwrap();
+
+        Self {
+            device,
+            info,
+            region,
+            buffer,
+            allocation,
+            mapped_ptr: mapped_ptr.unwrap(),
+            len,
+        }
+    }
+
+    /// Returns a copy of the buffer. Must only be called after the recorded commands have finished
+    /// execution.
+    fn read(&self) -> Box<[u8]> {
+        unsafe {
+            std::slice::from_raw_parts(self.mapped_ptr.as_ptr(), self.len)
+        }.into()
+    }
+}
+
+impl Drop for ReadbackBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.get_allocator().destroy_buffer(self.buffer, self.allocation);
+        }
+    }
+}
+
+// The mapped pointer is only read after the gpu has finished writing to it
+unsafe impl Send for ReadbackBuffer {
+}
+
+/// Clamps a region to the size of a attachment. Returns [`None`] if the region does not overlap
+/// the attachment.
+fn clamp_region(region: vk::Rect2D, size: Vec2u32) -> Option<vk::Rect2D> {
+    let start_x = region.offset.x.max(0) as i64;
+    let start_y = region.offset.y.max(0) as i64;
+    let end_x = (region.offset.x as i64 + region.extent.width as i64).min(size[0] as i64);
+    let end_y = (region.offset.y as i64 + region.extent.height as i64).min(size[1] as i64);
+    if end_x <= start_x || end_y <= start_y {
+        return None;
+    }
+
+    Some(vk::Rect2D {
+        offset: vk::Offset2D { x: start_x as i32, y: start_y as i32 },
+        extent: vk::Extent2D { width: (end_x - start_x) as u32, height: (end_y - start_y) as u32 },
+    })
+}
+
+/// Returns the size of a texel when copying the aspect of a image with the format into a buffer.
+pub(super) fn get_texel_size(format: vk::Format, aspect_mask: vk::ImageAspectFlags) -> Option<u32> {
+    if aspect_mask == vk::ImageAspectFlags::DEPTH {
+        match format {
+            vk::Format::D16_UNORM |
+            vk::Format::D16_UNORM_S8_UINT => Some(2),
+            vk::Format::X8_D24_UNORM_PACK32 |
+            vk::Format::D24_UNORM_S8_UINT |
+            vk::Format::D32_SFLOAT |
+            vk::Format::D32_SFLOAT_S8_UINT => Some(4),
+            _ => None,
+        }
+    } else if aspect_mask == vk::ImageAspectFlags::STENCIL {
+        Some(1)
+    } else {
+        match format {
+            vk::Format::R8_UNORM |
+            vk::Format::R8_SRGB => Some(1),
+            vk::Format::R8G8_UNORM |
+            vk::Format::R16_SFLOAT => Some(2),
+            vk::Format::R8G8B8A8_UNORM |
+            vk::Format::R8G8B8A8_SRGB |
+            vk::Format::B8G8R8A8_UNORM |
+            vk::Format::B8G8R8A8_SRGB |
+            vk::Format::A2B10G10R10_UNORM_PACK32 |
+            vk::Format::R16G16_SFLOAT |
+            vk::Format::R32_SFLOAT |
+            vk::Format::R32_UINT => Some(4),
+            vk::Format::R16G16B16A16_SFLOAT |
+            vk::Format::R32G32_SFLOAT => Some(8),
+            vk::Format::R32G32B32A32_SFLOAT => Some(16),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::renderer::emulator::{EmulatorRenderer, ImageData};
+    use crate::renderer::emulator::debug_pipeline::{DebugPipeline, DebugPipelineMode};
+    use crate::util::format::Format;
+
+    use super::*;
+
+    #[test]
+    fn texel_sizes() {
+        assert_eq!(get_texel_size(vk::Format::D32_SFLOAT, vk::ImageAspectFlags::DEPTH), Some(4));
+        assert_eq!(get_texel_size(vk::Format::D24_UNORM_S8_UINT, vk::ImageAspectFlags::DEPTH), Some(4));
+        assert_eq!(get_texel_size(vk::Format::D24_UNORM_S8_UINT, vk::ImageAspectFlags::STENCIL), Some(1));
+        assert_eq!(get_texel_size(vk::Format::R8G8B8A8_SRGB, vk::ImageAspectFlags::COLOR), Some(4));
+        assert_eq!(get_texel_size(vk::Format::BC1_RGB_UNORM_BLOCK, vk::ImageAspectFlags::COLOR), None);
+    }
+
+    #[test]
+    fn region_clamping() {
+        let size = Vec2u32::new(100, 50);
+        let rect = |x: i32, y: i32, width: u32, height: u32| vk::Rect2D {
+            offset: vk::Offset2D { x, y },
+            extent: vk::Extent2D { width, height },
+        };
+
+        assert_eq!(clamp_region(rect(10, 20, 1, 1), size), Some(rect(10, 20, 1, 1)));
+        assert_eq!(clamp_region(rect(-5, 40, 20, 20), size), Some(rect(0, 40, 15, 10)));
+        assert_eq!(clamp_region(rect(100, 0, 1, 1), size), None);
+        assert_eq!(clamp_region(rect(0, 50, 1, 1), size), None);
+    }
+
+    /// Writes a global image and reads it back again. This transitions the image from undefined to
+    /// transfer dst, transfer src and finally back into the ready layout.
+    #[test]
+    #[ignore]
+    fn validated_global_image_readback() {
+        crate::vk::test::run_validated(|device| {
+            let size = Vec2u32::new(16, 16);
+            let emulator = Arc::new(EmulatorRenderer::new(device.clone()));
+            let pipeline = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, size).unwrap();
+
+            let image = emulator.create_global_image(size, &Format::R8G8B8A8_UNORM);
+            let data: Vec<u8> = (0..(size[0] * size[1] * 4)).map(|index| index as u8).collect();
+            image.update_regions(&[ImageData::new_full(&data, size)]);
+            let readback = image.read_back(0);
+
+            // Global object updates are submitted together with the next pass
+            drop(emulator.start_pass(pipeline));
+
+            let result = readback.wait().unwrap();
+            assert_eq!(result.size, size);
+            assert_eq!(result.data.as_ref(), data.as_slice());
+        });
+    }
+}
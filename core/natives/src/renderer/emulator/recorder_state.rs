@@ -0,0 +1,167 @@
+//! Host side tracking of the uniforms and textures set through a recorder.
+//!
+//! Uniform updates are sent to the worker so the recorders do not know the current values.
+//! [`TrackedUniforms`] keeps a copy of the last value of every uniform and texture so that
+//! [`DrawRecorder::pop_state`](super::DrawRecorder::pop_state) can restore the values which were
+//! changed after the matching push.
+//!
+//! Hosts often update the same uniform many times between two draws, for example the model view
+//! matrix for every block. [`PendingUniforms`] collects the updates and only the last value of each
+//! uniform is sent to the worker once a draw uses the shader.
+
+use std::collections::HashMap;
+use std::mem::Discriminant;
+use std::sync::Arc;
+
+use crate::renderer::emulator::GlobalImage;
+use crate::renderer::emulator::global_objects::SamplerInfo;
+use crate::renderer::emulator::mc_shaders::{McUniformData, ShaderId};
+
+#[derive(Clone)]
+pub(super) struct TrackedUniforms {
+    uniforms: HashMap<(ShaderId, Discriminant<McUniformData>), McUniformData>,
+    textures: HashMap<(ShaderId, u32), (Arc<GlobalImage>, SamplerInfo)>,
+}
+
+impl TrackedUniforms {
+    pub(super) fn new() -> Self {
+        Self {
+            uniforms: HashMap::new(),
+            textures: HashMap::new(),
+        }
+    }
+
+    pub(super) fn set_uniform(&mut self, shader: ShaderId, data: &McUniformData) {
+        self.uniforms.insert((shader, std::mem::discriminant(data)), *data);
+    }
+
+    pub(super) fn set_texture(&mut self, shader: ShaderId, index: u32, image: &Arc<GlobalImage>, sampler_info: &SamplerInfo) {
+        self.textures.insert((shader, index), (image.clone(), *sampler_info));
+    }
+
+    /// Applies all uniforms and textures set in `other` on top of this tracker. Used when the
+    /// commands recorded with `other` are appended to the commands recorded with this tracker.
+    pub(super) fn merge(&mut self, other: TrackedUniforms) {
+        self.uniforms.extend(other.uniforms);
+        self.textures.extend(other.textures);
+    }
+
+    /// Returns all uniforms of `saved` which have a different value in this tracker.
+    ///
+    /// Uniforms which have only been set after `saved` was created are not returned since their
+    /// previous value is unknown.
+    pub(super) fn get_changed_uniforms(&self, saved: &TrackedUniforms) -> Vec<(ShaderId, McUniformData)> {
+        saved.uniforms.iter().filter(|(key, data)| {
+            self.uniforms.get(key) != Some(data)
+        }).map(|((shader, _), data)| (*shader, *data)).collect()
+    }
+
+    /// Returns all textures of `saved` which have a different image or sampler in this tracker.
+    /// See [`TrackedUniforms::get_changed_uniforms`].
+    pub(super) fn get_changed_textures(&self, saved: &TrackedUniforms) -> Vec<(ShaderId, u32, Arc<GlobalImage>, SamplerInfo)> {
+        saved.textures.iter().filter(|(key, (image, sampler_info))| {
+            match self.textures.get(key) {
+                Some((current_image, current_sampler_info)) => current_image != image || current_sampler_info != sampler_info,
+                None => true,
+            }
+        }).map(|((shader, index), (image, sampler_info))| (*shader, *index, image.clone(), *sampler_info)).collect()
+    }
+}
+
+/// Uniform updates which have not been sent to the worker yet.
+pub(super) struct PendingUniforms {
+    uniforms: HashMap<ShaderId, Vec<McUniformData>>,
+}
+
+impl PendingUniforms {
+    pub(super) fn new() -> Self {
+        Self {
+            uniforms: HashMap::new(),
+        }
+    }
+
+    /// Records a update replacing any pending update of the same uniform.
+    pub(super) fn set_uniform(&mut self, shader: ShaderId, data: &McUniformData) {
+        let pending = self.uniforms.entry(shader).or_default();
+        match pending.iter_mut().find(|pending| std::mem::discriminant(*pending) == std::mem::discriminant(data)) {
+            Some(pending) => *pending = *data,
+            None => pending.push(*data),
+        }
+    }
+
+    /// Removes and returns all pending updates of the shader.
+    pub(super) fn take_shader(&mut self, shader: ShaderId) -> Vec<McUniformData> {
+        self.uniforms.remove(&shader).unwrap_or_default()
+    }
+
+    /// Removes and returns all pending updates.
+    pub(super) fn take_all(&mut self) -> Vec<(ShaderId, McUniformData)> {
+        self.uniforms.drain().flat_map(|(shader, uniforms)| {
+            uniforms.into_iter().map(move |data| (shader, data))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn changed_uniforms() {
+        let shader0 = ShaderId::new();
+        let shader1 = ShaderId::new();
+
+        let mut tracker = TrackedUniforms::new();
+        tracker.set_uniform(shader0, &McUniformData::FogStart(1f32));
+        tracker.set_uniform(shader0, &McUniformData::FogEnd(2f32));
+        tracker.set_uniform(shader1, &McUniformData::FogStart(3f32));
+
+        let saved = tracker.clone();
+        tracker.set_uniform(shader0, &McUniformData::FogStart(1f32));
+        tracker.set_uniform(shader0, &McUniformData::FogEnd(5f32));
+        tracker.set_uniform(shader1, &McUniformData::ColorModulator(Vec4f32::zeros()));
+
+        let changed = tracker.get_changed_uniforms(&saved);
+        assert_eq!(changed, vec![(shader0, McUniformData::FogEnd(2f32))]);
+        assert!(saved.get_changed_uniforms(&saved).is_empty());
+    }
+
+    #[test]
+    fn merged_uniforms() {
+        let shader0 = ShaderId::new();
+        let shader1 = ShaderId::new();
+
+        let mut tracker = TrackedUniforms::new();
+        tracker.set_uniform(shader0, &McUniformData::FogStart(1f32));
+        tracker.set_uniform(shader1, &McUniformData::FogStart(2f32));
+
+        // Push the state, merge a sub recorder and pop the state again
+        let saved = tracker.clone();
+        let mut sub = TrackedUniforms::new();
+        sub.set_uniform(shader0, &McUniformData::FogStart(3f32));
+        sub.set_uniform(shader0, &McUniformData::FogEnd(4f32));
+        tracker.merge(sub);
+
+        assert_eq!(tracker.get_changed_uniforms(&saved), vec![(shader0, McUniformData::FogStart(1f32))]);
+    }
+
+    #[test]
+    fn pending_uniforms() {
+        let shader0 = ShaderId::new();
+        let shader1 = ShaderId::new();
+
+        let mut pending = PendingUniforms::new();
+        pending.set_uniform(shader0, &McUniformData::FogStart(1f32));
+        pending.set_uniform(shader0, &McUniformData::FogEnd(2f32));
+        pending.set_uniform(shader0, &McUniformData::FogStart(3f32));
+        pending.set_uniform(shader1, &McUniformData::FogStart(4f32));
+
+        assert_eq!(pending.take_shader(shader0), vec![McUniformData::FogStart(3f32), McUniformData::FogEnd(2f32)]);
+        assert!(pending.take_shader(shader0).is_empty());
+
+        assert_eq!(pending.take_all(), vec![(shader1, McUniformData::FogStart(4f32))]);
+        assert!(pending.take_all().is_empty());
+    }
+}
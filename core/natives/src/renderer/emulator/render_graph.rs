@@ -0,0 +1,301 @@
+//! A minimal render graph used to derive the barriers between passes.
+//!
+//! Passes declare how they use each image and are added in execution order. The graph tracks the
+//! last usage of every image and derives the layout transitions and barriers needed before each
+//! pass. Images are only transitioned between passes, transitions inside a render pass are
+//! expected to be performed by the render pass itself.
+
+use ash::vk;
+
+use crate::prelude::*;
+
+/// How a pass uses a image.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ImageUsage {
+    pub stage_mask: vk::PipelineStageFlags2,
+    pub access_mask: vk::AccessFlags2,
+
+    /// The layout the image must be in when the pass starts. If [`vk::ImageLayout::UNDEFINED`]
+    /// the previous contents are discarded.
+    pub layout: vk::ImageLayout,
+
+    /// The layout of the image after the pass. Differs from `layout` if the image is transitioned
+    /// by a render pass.
+    pub final_layout: vk::ImageLayout,
+}
+
+impl ImageUsage {
+    /// The image has not been used yet.
+    pub const UNDEFINED: Self = Self {
+        stage_mask: vk::PipelineStageFlags2::NONE,
+        access_mask: vk::AccessFlags2::NONE,
+        layout: vk::ImageLayout::UNDEFINED,
+        final_layout: vk::ImageLayout::UNDEFINED,
+    };
+
+    /// The image is read by [`EmulatorOutput`](super::pipeline::EmulatorOutput)s, for example the
+    /// final blit to the swapchain or readbacks. The outputs are not known to the pipeline so any
+    /// read access in any stage must be allowed.
+    pub const OUTPUT_READ: Self = Self {
+        stage_mask: vk::PipelineStageFlags2::ALL_COMMANDS,
+        access_mask: vk::AccessFlags2::MEMORY_READ,
+        layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    };
+
+    /// The image is sampled in fragment shaders.
+    pub const FRAGMENT_SAMPLED: Self = Self {
+        stage_mask: vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        access_mask: vk::AccessFlags2::SHADER_SAMPLED_READ,
+        layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    };
+
+    /// The image is the source of a transfer operation.
+    pub const TRANSFER_SRC: Self = Self {
+        stage_mask: vk::PipelineStageFlags2::TRANSFER,
+        access_mask: vk::AccessFlags2::TRANSFER_READ,
+        layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        final_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+    };
+
+    /// The image is cleared and written as color attachment by a render pass which transitions
+    /// the image to `final_layout`.
+    pub const fn color_attachment(final_layout: vk::ImageLayout) -> Self {
+        Self {
+            stage_mask: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            access_mask: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            layout: vk::ImageLayout::UNDEFINED,
+            final_layout,
+        }
+    }
+
+    /// The image is cleared and written as depth attachment by a render pass which transitions
+    /// the image to `final_layout`.
+    pub const fn depth_attachment(final_layout: vk::ImageLayout) -> Self {
+        Self {
+            stage_mask: vk::PipelineStageFlags2::from_raw(vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS.as_raw() | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS.as_raw()),
+            access_mask: vk::AccessFlags2::from_raw(vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ.as_raw() | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()),
+            layout: vk::ImageLayout::UNDEFINED,
+            final_layout,
+        }
+    }
+
+    fn is_write(&self) -> bool {
+        self.access_mask.intersects(
+            vk::AccessFlags2::MEMORY_WRITE |
+            vk::AccessFlags2::SHADER_WRITE |
+            vk::AccessFlags2::SHADER_STORAGE_WRITE |
+            vk::AccessFlags2::COLOR_ATTACHMENT_WRITE |
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE |
+            vk::AccessFlags2::TRANSFER_WRITE |
+            vk::AccessFlags2::HOST_WRITE
+        )
+    }
+}
+
+/// A image added to a [`RenderGraph`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct GraphImage(usize);
+
+/// A pass added to a [`RenderGraph`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct GraphPass(usize);
+
+pub struct RenderGraph {
+    images: Vec<TrackedImage>,
+    passes: Vec<Box<[Barrier]>>,
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        Self {
+            images: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    /// Adds a image to the graph. `initial_usage` is the last usage of the image before any pass
+    /// of the graph.
+    pub fn add_image(&mut self, image: vk::Image, subresource_range: vk::ImageSubresourceRange, initial_usage: ImageUsage) -> GraphImage {
+        self.images.push(TrackedImage {
+            image,
+            subresource_range,
+            last_usage: initial_usage,
+        });
+        GraphImage(self.images.len() - 1)
+    }
+
+    /// Adds a pass using the images. Passes are executed in the order they are added.
+    pub fn add_pass(&mut self, usages: &[(GraphImage, ImageUsage)]) -> GraphPass {
+        let mut barriers = Vec::with_capacity(usages.len());
+        for (image, usage) in usages {
+            let tracked = &mut self.images[image.0];
+            if let Some(barrier) = Barrier::derive(tracked, usage) {
+                barriers.push(barrier);
+            }
+            tracked.last_usage = *usage;
+        }
+
+        self.passes.push(barriers.into_boxed_slice());
+        GraphPass(self.passes.len() - 1)
+    }
+
+    /// Records the barriers needed before the pass into the command buffer. Does nothing if no
+    /// barriers are needed.
+    pub fn record_barriers(&self, device: &DeviceContext, command_buffer: vk::CommandBuffer, pass: GraphPass) {
+        let barriers = &self.passes[pass.0];
+        if barriers.is_empty() {
+            return;
+        }
+
+        let mut memory_barriers = Vec::new();
+        let mut image_barriers = Vec::new();
+        for barrier in barriers.iter() {
+            match barrier.layouts {
+                Some((old_layout, new_layout)) => image_barriers.push(vk::ImageMemoryBarrier2::builder()
+                    .src_stage_mask(barrier.src_stage_mask)
+                    .src_access_mask(barrier.src_access_mask)
+                    .dst_stage_mask(barrier.dst_stage_mask)
+                    .dst_access_mask(barrier.dst_access_mask)
+                    .old_layout(old_layout)
+                    .new_layout(new_layout)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .image(barrier.image)
+                    .subresource_range(barrier.subresource_range)
+                    .build()
+                ),
+                None => memory_barriers.push(vk::MemoryBarrier2::builder()
+                    .src_stage_mask(barrier.src_stage_mask)
+                    .src_access_mask(barrier.src_access_mask)
+                    .dst_stage_mask(barrier.dst_stage_mask)
+                    .dst_access_mask(barrier.dst_access_mask)
+                    .build()
+                ),
+            }
+        }
+
+        let info = vk::DependencyInfo::builder()
+            .memory_barriers(&memory_barriers)
+            .image_memory_barriers(&image_barriers);
+
+        unsafe {
+            device.synchronization_2_khr().cmd_pipeline_barrier2(command_buffer, &info);
+        }
+    }
+}
+
+impl Default for RenderGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct TrackedImage {
+    image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+    last_usage: ImageUsage,
+}
+
+/// A barrier derived by the graph. Stored without the vulkan structs so the graph is [`Send`].
+#[derive(Copy, Clone, Debug)]
+struct Barrier {
+    image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+    src_stage_mask: vk::PipelineStageFlags2,
+    src_access_mask: vk::AccessFlags2,
+    dst_stage_mask: vk::PipelineStageFlags2,
+    dst_access_mask: vk::AccessFlags2,
+
+    /// The old and new layout if a layout transition is needed. If [`None`] a global memory
+    /// barrier is used.
+    layouts: Option<(vk::ImageLayout, vk::ImageLayout)>,
+}
+
+impl Barrier {
+    /// Returns the barrier needed between the last usage of the image and the new usage. Returns
+    /// [`None`] if no barrier is needed.
+    fn derive(image: &TrackedImage, usage: &ImageUsage) -> Option<Self> {
+        let last = &image.last_usage;
+
+        // Discarding the contents never needs a layout transition
+        let transition = usage.layout != vk::ImageLayout::UNDEFINED && usage.layout != last.final_layout;
+        let hazard = last.is_write() || usage.is_write();
+        if !transition && (!hazard || last.stage_mask == vk::PipelineStageFlags2::NONE) {
+            return None;
+        }
+
+        Some(Self {
+            image: image.image,
+            subresource_range: image.subresource_range,
+            src_stage_mask: last.stage_mask,
+            // Only writes need to be made available
+            src_access_mask: if last.is_write() { last.access_mask } else { vk::AccessFlags2::NONE },
+            dst_stage_mask: usage.stage_mask,
+            dst_access_mask: usage.access_mask,
+            layouts: if usage.layout != vk::ImageLayout::UNDEFINED {
+                Some((last.final_layout, usage.layout))
+            } else {
+                None
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLOR_RANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1
+    };
+
+    #[test]
+    fn attachment_to_output() {
+        let mut graph = RenderGraph::new();
+        let image = graph.add_image(vk::Image::null(), COLOR_RANGE, ImageUsage::UNDEFINED);
+
+        let pass = graph.add_pass(&[(image, ImageUsage::color_attachment(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL))]);
+        assert!(graph.passes[pass.0].is_empty());
+
+        let output = graph.add_pass(&[(image, ImageUsage::OUTPUT_READ)]);
+        let barrier = &graph.passes[output.0][0];
+        assert_eq!(barrier.src_stage_mask, vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT);
+        assert_eq!(barrier.src_access_mask, vk::AccessFlags2::COLOR_ATTACHMENT_WRITE);
+        assert_eq!(barrier.dst_access_mask, vk::AccessFlags2::MEMORY_READ);
+        assert_eq!(barrier.layouts, Some((vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)));
+    }
+
+    #[test]
+    fn read_after_read() {
+        let mut graph = RenderGraph::new();
+        let image = graph.add_image(vk::Image::null(), COLOR_RANGE, ImageUsage::OUTPUT_READ);
+
+        // Same layout and both read only
+        let sampled = graph.add_pass(&[(image, ImageUsage::FRAGMENT_SAMPLED)]);
+        assert!(graph.passes[sampled.0].is_empty());
+
+        let transfer = graph.add_pass(&[(image, ImageUsage::TRANSFER_SRC)]);
+        let barrier = &graph.passes[transfer.0][0];
+        assert_eq!(barrier.src_access_mask, vk::AccessFlags2::NONE);
+        assert_eq!(barrier.layouts, Some((vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL)));
+    }
+
+    #[test]
+    fn write_after_read() {
+        let mut graph = RenderGraph::new();
+        let image = graph.add_image(vk::Image::null(), COLOR_RANGE, ImageUsage::OUTPUT_READ);
+
+        // The contents are discarded so only a execution dependency is needed
+        let pass = graph.add_pass(&[(image, ImageUsage::color_attachment(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL))]);
+        let barrier = &graph.passes[pass.0][0];
+        assert_eq!(barrier.src_stage_mask, vk::PipelineStageFlags2::ALL_COMMANDS);
+        assert_eq!(barrier.src_access_mask, vk::AccessFlags2::NONE);
+        assert_eq!(barrier.layouts, None);
+    }
+}
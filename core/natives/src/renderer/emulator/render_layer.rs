@@ -0,0 +1,110 @@
+//! Render layers bundle the state used to draw a group of objects.
+//!
+//! A [`RenderLayer`] mirrors minecrafts `RenderType`. Instead of configuring the textures, blend,
+//! depth and cull state of a recorder before every draw a layer is created once using
+//! [`EmulatorRenderer::create_render_layer`](super::EmulatorRenderer::create_render_layer) and draws
+//! are submitted against its id using [`DrawRecorder::draw_immediate_layer`](super::DrawRecorder::draw_immediate_layer)
+//! or [`DrawRecorder::draw_global_layer`](super::DrawRecorder::draw_global_layer).
+
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::define_uuid_type;
+use crate::prelude::*;
+use crate::renderer::emulator::{GlobalImage, SamplerInfo};
+use crate::renderer::emulator::mc_shaders::ShaderId;
+use crate::renderer::emulator::pipeline::{BlendState, CullState, DepthBias, DepthTest, PipelineConfigHint};
+
+define_uuid_type!(pub, RenderLayerId);
+
+/// A texture bound to the shader of a layer whenever the layer is used.
+#[derive(Clone)]
+pub struct RenderLayerTexture {
+    pub index: u32,
+    pub image: Arc<GlobalImage>,
+    pub sampler_info: SamplerInfo,
+}
+
+/// The description of a render layer.
+#[derive(Clone)]
+pub struct RenderLayerInfo {
+    pub shader: ShaderId,
+
+    /// The topology all meshes drawn with this layer must use.
+    pub primitive_topology: vk::PrimitiveTopology,
+    pub depth_write_enable: bool,
+
+    /// The faces culled by all draws of this layer.
+    pub cull_state: CullState,
+    pub blend_state: Option<BlendState>,
+    pub logic_op: Option<vk::LogicOp>,
+
+    /// The components of the color attachment written by all draws of this layer.
+    pub color_write_mask: vk::ColorComponentFlags,
+    pub depth_bias: Option<DepthBias>,
+    pub depth_test: DepthTest,
+    pub textures: Vec<RenderLayerTexture>,
+}
+
+impl RenderLayerInfo {
+    /// Creates a opaque layer with depth writes and back face culling enabled.
+    pub fn new(shader: ShaderId, primitive_topology: vk::PrimitiveTopology) -> Self {
+        Self {
+            shader,
+            primitive_topology,
+            depth_write_enable: true,
+            cull_state: CullState::BACK,
+            blend_state: None,
+            logic_op: None,
+            color_write_mask: vk::ColorComponentFlags::RGBA,
+            depth_bias: None,
+            depth_test: DepthTest::Default,
+            textures: Vec::new(),
+        }
+    }
+
+    /// Returns the pipeline state used by draws of this layer. Can be used to prewarm the pipeline
+    /// of the layer using [`EmulatorPipeline::prewarm_shader`](super::pipeline::EmulatorPipeline::prewarm_shader).
+    pub fn get_pipeline_hint(&self) -> PipelineConfigHint {
+        PipelineConfigHint {
+            primitive_topology: self.primitive_topology,
+            depth_write_enable: self.depth_write_enable,
+            depth_bias_enable: self.depth_bias.is_some(),
+            depth_test: self.depth_test,
+            cull_state: self.cull_state,
+            blend_state: self.blend_state,
+            logic_op: self.logic_op,
+            color_write_mask: self.color_write_mask,
+        }
+    }
+}
+
+pub struct RenderLayer {
+    id: RenderLayerId,
+    info: RenderLayerInfo,
+}
+
+impl RenderLayer {
+    pub fn new(id: RenderLayerId, info: RenderLayerInfo) -> Arc<Self> {
+        Arc::new(Self {
+            id,
+            info,
+        })
+    }
+
+    pub fn get_id(&self) -> RenderLayerId {
+        self.id
+    }
+
+    pub fn get_info(&self) -> &RenderLayerInfo {
+        &self.info
+    }
+
+    /// Logs a warning if a mesh with the specified topology is drawn using this layer.
+    pub(super) fn validate_topology(&self, primitive_topology: vk::PrimitiveTopology) {
+        if primitive_topology != self.info.primitive_topology {
+            log::warn!("Drew mesh with topology {:?} using render layer {:?} expecting {:?}", primitive_topology, self.id, self.info.primitive_topology);
+        }
+    }
+}
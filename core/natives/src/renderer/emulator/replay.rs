@@ -0,0 +1,489 @@
+//! Replaying of [`FrameCapture`]s into host readable images.
+//!
+//! Used to reproduce captured rendering bugs without the original application and to turn them
+//! into regression tests.
+
+use std::ffi::CString;
+use std::ptr::NonNull;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender};
+
+use ash::vk;
+use bumpalo::Bump;
+
+use crate::allocator::{Allocation, HostAccess};
+use crate::device::init::{create_device, DeviceCreateConfig};
+use crate::instance::init::{create_instance, InstanceCreateConfig};
+use crate::renderer::emulator::capture::{CaptureCommand, FrameCapture};
+use crate::renderer::emulator::debug_pipeline::{DebugPipeline, DebugPipelineMode};
+use crate::renderer::emulator::mc_shaders::ShaderId;
+use crate::renderer::emulator::pipeline::{EmulatorOutput, EmulatorPipeline, EmulatorPipelinePass, OutputUtil, PooledObjectProvider, SubmitRecorder};
+use crate::renderer::emulator::{DeterministicConfig, EmulatorRenderer, GlobalImage, GlobalMesh};
+use crate::util::format::Format;
+
+use crate::prelude::*;
+
+/// The format of replayed images.
+const REPLAY_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The capture references a object which does not exist in the capture.
+    InvalidCapture(&'static str),
+    /// The emulator pipeline could not be created.
+    PipelineCreation,
+    /// The output size of the provided pipeline does not match the output size of the capture.
+    OutputSizeMismatch,
+}
+
+/// The result of a replay.
+pub struct ReplayImage {
+    pub size: Vec2u32,
+
+    /// Tightly packed R8G8B8A8 sRGB texels.
+    pub data: Box<[u8]>,
+}
+
+/// Replays [`FrameCapture`]s through a [`EmulatorRenderer`].
+pub struct FrameReplayer {
+    #[allow(unused)] // We just need to keep the instance alive
+    instance: Option<Arc<InstanceContext>>,
+    emulator: Arc<EmulatorRenderer>,
+}
+
+impl FrameReplayer {
+    pub fn new(emulator: Arc<EmulatorRenderer>) -> Self {
+        Self {
+            instance: None,
+            emulator,
+        }
+    }
+
+    /// Creates a replayer using a new headless instance and device.
+    pub fn new_headless(enable_validation: bool) -> Self {
+        let (instance, device) = Self::create_headless_device(enable_validation);
+
+        Self {
+            instance: Some(instance),
+            emulator: Arc::new(EmulatorRenderer::new(device)),
+        }
+    }
+
+    /// Creates a replayer using a new headless instance and device with the emulator running in
+    /// deterministic mode. See [`DeterministicConfig`].
+    ///
+    /// Returns [`None`] if another deterministic renderer is still alive.
+    pub fn new_headless_deterministic(enable_validation: bool, config: &DeterministicConfig) -> Option<Self> {
+        let (instance, device) = Self::create_headless_device(enable_validation);
+
+        Some(Self {
+            instance: Some(instance),
+            emulator: Arc::new(EmulatorRenderer::new_deterministic(device, config)?),
+        })
+    }
+
+    fn create_headless_device(enable_validation: bool) -> (Arc<InstanceContext>, Arc<DeviceContext>) {
+        let mut instance_config = InstanceCreateConfig::new(
+            CString::new("B4D Replay").unwrap(),
+            vk::make_api_version(0, 0, 1, 0)
+        );
+        if enable_validation {
+            instance_config.enable_validation();
+        }
+
+        // The LunarG desktop profile requires the swapchain extension which in turn requires the surface extensions
+        instance_config.require_surface_khr();
+
+        let instance = create_instance(instance_config).unwrap_or_else(|err| {
+            log::error!("Failed to create instance in FrameReplayer::new_headless(): {:?}", err);
+            panic!()
+        });
+
+        let mut device_config = DeviceCreateConfig::new();
+        device_config.disable_robustness();
+        let device = create_device(device_config, instance.clone()).unwrap_or_else(|err| {
+            log::error!("Failed to create device in FrameReplayer::new_headless(): {:?}", err);
+            panic!()
+        });
+
+        (instance, device)
+    }
+
+    pub fn get_emulator(&self) -> &Arc<EmulatorRenderer> {
+        &self.emulator
+    }
+
+    /// Replays a capture using a [`DebugPipeline`] of the specified mode and blocks until the
+    /// result is available.
+    ///
+    /// Global images are recreated with their captured size and format but are cleared to zero
+    /// since their contents are not captured. Draws of global meshes without retained data are
+    /// skipped.
+    pub fn replay(&self, capture: &FrameCapture, mode: DebugPipelineMode) -> Result<ReplayImage, ReplayError> {
+        let pipeline = DebugPipeline::new(self.emulator.clone(), mode, capture.output_size)
+            .map_err(|_| ReplayError::PipelineCreation)?;
+
+        self.replay_with_pipeline(capture, pipeline)
+    }
+
+    /// Replays a capture using the provided pipeline and blocks until the result is available. The
+    /// output size of the pipeline must match the output size of the capture. See
+    /// [`FrameReplayer::replay`].
+    pub fn replay_with_pipeline(&self, capture: &FrameCapture, pipeline: Arc<dyn EmulatorPipeline>) -> Result<ReplayImage, ReplayError> {
+        if pipeline.get_output().0 != capture.output_size {
+            return Err(ReplayError::OutputSizeMismatch);
+        }
+
+        let images: Vec<Arc<GlobalImage>> = capture.images.iter().map(|image| {
+            let format = Format::try_format_for(image.format).ok_or(ReplayError::InvalidCapture("Unknown image format"))?;
+            Ok(self.emulator.create_global_image(image.size, format))
+        }).collect::<Result<_, ReplayError>>()?;
+
+        let shaders: Vec<_> = capture.shaders.iter().map(|shader| {
+            match &shader.instance_format {
+                Some(instance_format) => self.emulator.create_instanced_shader(&shader.vertex_format, instance_format, shader.used_uniforms),
+                None => self.emulator.create_shader(&shader.vertex_format, shader.used_uniforms),
+            }
+        }).collect();
+
+        let meshes: Vec<Option<Arc<GlobalMesh>>> = capture.global_meshes.iter().map(|mesh| {
+            mesh.as_ref().map(|mesh| self.emulator.create_global_mesh(&mesh.as_mesh_data()))
+        }).collect();
+
+        let result = self.replay_commands(capture, pipeline, &shaders, &images, &meshes);
+
+        for shader in shaders {
+            self.emulator.drop_shader(shader);
+        }
+
+        result
+    }
+
+    fn replay_commands(&self, capture: &FrameCapture, pipeline: Arc<dyn EmulatorPipeline>, shaders: &[ShaderId], images: &[Arc<GlobalImage>], meshes: &[Option<Arc<GlobalMesh>>]) -> Result<ReplayImage, ReplayError> {
+        let get_shader = |index: u32| shaders.get(index as usize).copied().ok_or(ReplayError::InvalidCapture("Invalid shader index"));
+
+        let target = Arc::new(ReadbackTarget::new(self.emulator.get_device().clone(), pipeline.clone()));
+        let (sender, receiver) = channel();
+
+        let mut recorder = self.emulator.start_pass(pipeline);
+        recorder.use_output(Box::new(ReadbackOutput {
+            target: target.clone(),
+            sender,
+            pipeline_index: None,
+        }));
+
+        let mut immediate_meshes = Vec::new();
+        for command in &capture.commands {
+            match command {
+                CaptureCommand::UpdateUniform { shader, data } => {
+                    recorder.update_uniform(data, get_shader(*shader)?);
+                }
+                CaptureCommand::UpdateTexture { shader, index, image, sampler } => {
+                    let image = images.get(*image as usize).ok_or(ReplayError::InvalidCapture("Invalid image index"))?;
+                    recorder.update_texture(*index, image, sampler, get_shader(*shader)?);
+                }
+                CaptureCommand::UpdateBoneMatrices { shader, matrices } => {
+                    recorder.update_bone_matrices(matrices, get_shader(*shader)?);
+                }
+                CaptureCommand::SetScissor(scissor) => {
+                    recorder.set_scissor(*scissor);
+                }
+                CaptureCommand::SetBlendState(blend_state) => {
+                    recorder.set_blend_state(*blend_state);
+                }
+                CaptureCommand::SetLogicOp(logic_op) => {
+                    recorder.set_logic_op(*logic_op);
+                }
+                CaptureCommand::SetDepthBias(depth_bias) => {
+                    recorder.set_depth_bias(*depth_bias);
+                }
+                CaptureCommand::SetCullState(cull_state) => {
+                    recorder.set_cull_state(*cull_state);
+                }
+                CaptureCommand::SetScreenEffects(effects) => {
+                    recorder.set_screen_effects(effects);
+                }
+                CaptureCommand::SetViewport(viewport) => {
+                    recorder.set_viewport(*viewport);
+                }
+                CaptureCommand::ClearDepth(region) => {
+                    recorder.clear_depth(*region);
+                }
+                CaptureCommand::SetViewProjections([left, right]) => {
+                    recorder.set_view_projections(left, right);
+                }
+                CaptureCommand::SetObjectId(object_id) => {
+                    recorder.set_object_id(*object_id);
+                }
+                CaptureCommand::SetClearConfig(config) => {
+                    recorder.set_clear_config(*config);
+                }
+                CaptureCommand::SetColorWriteMask(color_write_mask) => {
+                    recorder.set_color_write_mask(*color_write_mask);
+                }
+                CaptureCommand::SetDepthTest(depth_test) => {
+                    recorder.set_depth_test(*depth_test);
+                }
+                CaptureCommand::UploadImmediate(mesh) => {
+                    immediate_meshes.push(recorder.upload_immediate(&mesh.as_mesh_data()));
+                }
+                CaptureCommand::DrawImmediate { mesh, shader, depth_write_enable } => {
+                    let mesh = *immediate_meshes.get(*mesh as usize).ok_or(ReplayError::InvalidCapture("Invalid immediate mesh index"))?;
+                    recorder.draw_immediate(mesh, get_shader(*shader)?, *depth_write_enable);
+                }
+                CaptureCommand::DrawGlobal { mesh, shader, depth_write_enable } => {
+                    match meshes.get(*mesh as usize).ok_or(ReplayError::InvalidCapture("Invalid global mesh index"))? {
+                        Some(mesh) => recorder.draw_global(mesh.clone(), get_shader(*shader)?, *depth_write_enable),
+                        None => log::warn!("Skipping draw of global mesh without data"),
+                    }
+                }
+                CaptureCommand::DrawGlobalInstanced { mesh, shader, instance_data, instance_count, first_instance, depth_write_enable } => {
+                    match meshes.get(*mesh as usize).ok_or(ReplayError::InvalidCapture("Invalid global mesh index"))? {
+                        Some(mesh) => recorder.draw_static_instanced(mesh.clone(), instance_data, *instance_count, *first_instance, get_shader(*shader)?, *depth_write_enable),
+                        None => log::warn!("Skipping draw of global mesh without data"),
+                    }
+                }
+                CaptureCommand::DrawGlobalCulled { mesh, shader, sections, view_projection, depth_write_enable } => {
+                    match meshes.get(*mesh as usize).ok_or(ReplayError::InvalidCapture("Invalid global mesh index"))? {
+                        Some(mesh) => recorder.draw_global_culled(mesh.clone(), sections, view_projection, get_shader(*shader)?, *depth_write_enable),
+                        None => log::warn!("Skipping draw of global mesh without data"),
+                    }
+                }
+            }
+        }
+        drop(recorder);
+
+        let data = receiver.recv().unwrap_or_else(|_| {
+            log::error!("Replay output was dropped without producing a result");
+            panic!()
+        });
+
+        Ok(ReplayImage {
+            size: capture.output_size,
+            data
+        })
+    }
+}
+
+/// The image and buffer used to read back the output of a replayed pass.
+struct ReadbackTarget {
+    device: Arc<DeviceContext>,
+    util: OutputUtil,
+    size: Vec2u32,
+    image: vk::Image,
+    image_allocation: Allocation,
+    image_view: vk::ImageView,
+    framebuffer: vk::Framebuffer,
+    buffer: vk::Buffer,
+    buffer_allocation: Allocation,
+    mapped_ptr: NonNull<u8>,
+}
+
+impl ReadbackTarget {
+    fn new(device: Arc<DeviceContext>, pipeline: Arc<dyn EmulatorPipeline>) -> Self {
+        let (size, _) = pipeline.get_output();
+        let util = OutputUtil::new(&device, pipeline, REPLAY_FORMAT, vk::ColorSpaceKHR::SRGB_NONLINEAR, false, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+        let info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(REPLAY_FORMAT)
+            .extent(vk::Extent3D {
+                width: size[0],
+                height: size[1],
+                depth: 1
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        let (image, image_allocation) = unsafe {
+            device.get_allocator().create_gpu_image(&info, &format_args!("ReplayReadbackImage"))
+        }.unwrap();
+
+        let info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(REPLAY_FORMAT)
+            .components(vk::ComponentMapping {
+                r: vk::ComponentSwizzle::IDENTITY,
+                g: vk::ComponentSwizzle::IDENTITY,
+                b: vk::ComponentSwizzle::IDENTITY,
+                a: vk::ComponentSwizzle::IDENTITY
+            })
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1
+            });
+
+        let image_view = unsafe {
+            device.vk().create_image_view(&info, None)
+        }.unwrap();
+
+        let framebuffer = util.create_framebuffer(image_view, size).unwrap();
+
+        let info = vk::BufferCreateInfo::builder()
+            .size((size[0] as vk::DeviceSize) * (size[1] as vk::DeviceSize) * 4)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let (buffer, buffer_allocation, mapped_ptr) = unsafe {
+            device.get_allocator().create_buffer(&info, HostAccess::Random, &format_args!("ReplayReadbackBuffer"))
+        }.unwrap();
+
+        Self {
+            device,
+            util,
+            size,
+            image,
+            image_allocation,
+            image_view,
+            framebuffer,
+            buffer,
+            buffer_allocation,
+            mapped_ptr: mapped_ptr.unwrap(),
+        }
+    }
+
+    fn record(&self, command_buffer: vk::CommandBuffer, pipeline_index: usize) {
+        self.util.record(command_buffer, self.framebuffer, self.size, pipeline_index);
+
+        let image_barrier = vk::ImageMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .image(self.image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1
+            });
+
+        let copy = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D { width: self.size[0], height: self.size[1], depth: 1 }
+        };
+
+        let buffer_barrier = vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+            .dst_access_mask(vk::AccessFlags2::HOST_READ)
+            .buffer(self.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE);
+
+        unsafe {
+            let info = vk::DependencyInfo::builder()
+                .image_memory_barriers(std::slice::from_ref(&image_barrier));
+            self.device.synchronization_2_khr().cmd_pipeline_barrier2(command_buffer, &info);
+
+            self.device.vk().cmd_copy_image_to_buffer(command_buffer, self.image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, self.buffer, std::slice::from_ref(&copy));
+
+            let info = vk::DependencyInfo::builder()
+                .buffer_memory_barriers(std::slice::from_ref(&buffer_barrier));
+            self.device.synchronization_2_khr().cmd_pipeline_barrier2(command_buffer, &info);
+        }
+    }
+
+    /// Returns a copy of the readback buffer. Must only be called after the recorded commands
+    /// have finished execution.
+    fn read(&self) -> Box<[u8]> {
+        let len = (self.size[0] as usize) * (self.size[1] as usize) * 4;
+
+        // The buffer may be allocated from non coherent memory
+        unsafe {
+            self.device.get_allocator().invalidate_allocation(self.buffer_allocation, 0, len as vk::DeviceSize)
+        }.unwrap_or_else(|err| {
+            log::error!("Failed to invalidate replay readback memory {:?}", err);
+            panic!()
+        });
+
+        unsafe {
+            std::slice::from_raw_parts(self.mapped_ptr.as_ptr(), len)
+        }.into()
+    }
+}
+
+impl Drop for ReadbackTarget {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.vk().destroy_framebuffer(self.framebuffer, None);
+            self.device.vk().destroy_image_view(self.image_view, None);
+            self.device.get_allocator().destroy_image(self.image, self.image_allocation);
+            self.device.get_allocator().destroy_buffer(self.buffer, self.buffer_allocation);
+        }
+    }
+}
+
+// The mapped pointer is only read after the gpu has finished writing to it
+unsafe impl Send for ReadbackTarget {
+}
+unsafe impl Sync for ReadbackTarget {
+}
+
+/// A [`EmulatorOutput`] copying the pipeline output into a [`ReadbackTarget`] and sending the
+/// result once execution has finished.
+struct ReadbackOutput {
+    target: Arc<ReadbackTarget>,
+    sender: Sender<Box<[u8]>>,
+    pipeline_index: Option<usize>,
+}
+
+impl EmulatorOutput for ReadbackOutput {
+    fn init(&mut self, pass: &dyn EmulatorPipelinePass, _: &mut PooledObjectProvider) {
+        self.pipeline_index = Some(pass.get_output_index());
+    }
+
+    fn record<'a>(&mut self, obj: &mut PooledObjectProvider, submits: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+        let cmd = obj.get_begin_command_buffer().unwrap();
+
+        self.target.record(cmd, self.pipeline_index.unwrap());
+
+        unsafe {
+            self.target.device.vk().end_command_buffer(cmd)
+        }.unwrap();
+
+        let commands = alloc.alloc([
+            vk::CommandBufferSubmitInfo::builder()
+                .command_buffer(cmd)
+                .build()
+        ]);
+
+        submits.push(vk::SubmitInfo2::builder()
+            .command_buffer_infos(commands)
+        );
+    }
+
+    fn on_post_submit(&mut self, _: &Queue) {
+    }
+}
+
+impl Drop for ReadbackOutput {
+    fn drop(&mut self) {
+        // We are only dropped after all submitted commands have finished execution
+        if self.pipeline_index.is_some() {
+            let _ = self.sender.send(self.target.read());
+        }
+    }
+}
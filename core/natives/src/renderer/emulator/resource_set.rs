@@ -0,0 +1,110 @@
+//! Named resources which are replaced as a whole when resource packs are reloaded.
+//!
+//! A [`ResourceSet`] is immutable once created. Frames should fetch the current set once when they
+//! start and use it for all of their draws so that they never observe a partially reloaded state.
+//! Resources which are no longer part of any set are released once the last set referencing them
+//! has been dropped.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, SamplerInfo};
+use crate::renderer::emulator::mc_shaders::ShaderId;
+
+/// A immutable collection of shaders, images and samplers identified by name.
+#[derive(Clone)]
+pub struct ResourceSet {
+    generation: u64,
+    shaders: HashMap<String, Arc<OwnedShader>>,
+    images: HashMap<String, Arc<GlobalImage>>,
+    samplers: HashMap<String, SamplerInfo>,
+}
+
+impl ResourceSet {
+    pub(crate) fn new() -> Self {
+        Self {
+            generation: 0,
+            shaders: HashMap::new(),
+            images: HashMap::new(),
+            samplers: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of reloads which have been committed before this set was created.
+    pub fn get_generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn get_shader(&self, name: &str) -> Option<ShaderId> {
+        self.shaders.get(name).map(|shader| shader.id)
+    }
+
+    pub fn get_image(&self, name: &str) -> Option<&Arc<GlobalImage>> {
+        self.images.get(name)
+    }
+
+    pub fn get_sampler(&self, name: &str) -> Option<&SamplerInfo> {
+        self.samplers.get(name)
+    }
+
+    /// Returns the ids of all shaders in this set.
+    pub fn get_shader_ids(&self) -> impl Iterator<Item=ShaderId> + '_ {
+        self.shaders.values().map(|shader| shader.id)
+    }
+
+    pub(crate) fn with_generation(mut self, generation: u64) -> Self {
+        self.generation = generation;
+        self
+    }
+
+    pub(crate) fn insert_shader(&mut self, name: String, shader: Arc<OwnedShader>) {
+        self.shaders.insert(name, shader);
+    }
+
+    pub(crate) fn insert_image(&mut self, name: String, image: Arc<GlobalImage>) {
+        self.images.insert(name, image);
+    }
+
+    pub(crate) fn insert_sampler(&mut self, name: String, sampler: SamplerInfo) {
+        self.samplers.insert(name, sampler);
+    }
+
+    pub(crate) fn remove_shader(&mut self, name: &str) {
+        self.shaders.remove(name);
+    }
+
+    pub(crate) fn remove_image(&mut self, name: &str) {
+        self.images.remove(name);
+    }
+
+    pub(crate) fn remove_sampler(&mut self, name: &str) {
+        self.samplers.remove(name);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.shaders.clear();
+        self.images.clear();
+        self.samplers.clear();
+    }
+}
+
+/// A shader which is dropped once it is no longer referenced by any [`ResourceSet`].
+pub(crate) struct OwnedShader {
+    emulator: Arc<EmulatorRenderer>,
+    id: ShaderId,
+}
+
+impl OwnedShader {
+    pub(crate) fn new(emulator: Arc<EmulatorRenderer>, id: ShaderId) -> Self {
+        Self {
+            emulator,
+            id,
+        }
+    }
+}
+
+impl Drop for OwnedShader {
+    fn drop(&mut self) {
+        self.emulator.drop_shader(self.id);
+    }
+}
@@ -2,16 +2,23 @@ use std::sync::{Arc, Condvar, Mutex};
 use std::time::{Duration, Instant};
 use std::panic::RefUnwindSafe;
 use std::collections::{HashMap, VecDeque};
-use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64};
 use ash::vk;
 
-use crate::renderer::emulator::descriptors::DescriptorPool;
+use crate::renderer::emulator::descriptors::{DescriptorPool, UniformBlock};
 use crate::renderer::emulator::worker::WorkerTask;
-use crate::renderer::emulator::mc_shaders::{McUniform, Shader, ShaderId, VertexFormat};
+use crate::renderer::emulator::mc_shaders::{InstanceFormat, McUniform, Shader, ShaderId, VertexFormat};
+use crate::renderer::emulator::render_layer::{RenderLayer, RenderLayerId, RenderLayerInfo};
 
 use crate::prelude::*;
+use crate::util::id::SeededUUIDs;
 use crate::renderer::emulator::immediate::{ImmediateBuffer, ImmediatePool};
-use crate::renderer::emulator::staging::StagingMemoryPool;
+use crate::renderer::emulator::staging::{StagingAllocation, StagingAllocationId, StagingMemoryPool};
+use crate::renderer::emulator::{DeterministicConfig, EmulatorStatistics};
+use crate::renderer::emulator::watchdog::WorkerProgress;
+use crate::renderer::emulator::mipmap::AlphaMipmapPipeline;
+use crate::renderer::emulator::lights::LightCullingPipeline;
+use crate::renderer::emulator::gpu_culling::SectionCullingPipeline;
 
 pub(super) struct Share {
     id: UUID,
@@ -19,34 +26,115 @@ pub(super) struct Share {
     current_pass: AtomicU64,
 
     staging_memory: Mutex<StagingMemoryPool>,
+    /// Signaled whenever staging memory is freed.
+    staging_freed: Condvar,
     immediate_buffers: ImmediatePool,
     shader_database: Mutex<HashMap<ShaderId, Arc<Shader>>>,
+    render_layers: Mutex<HashMap<RenderLayerId, Arc<RenderLayer>>>,
     descriptors: Mutex<DescriptorPool>,
+    alpha_mipmap_pipeline: AlphaMipmapPipeline,
+    light_culling_pipeline: LightCullingPipeline,
+    section_culling_pipeline: SectionCullingPipeline,
     channel: Mutex<Channel>,
     signal: Condvar,
+    progress: WorkerProgress,
+
+    /// A timeline semaphore which is signaled with the id of each pass once it has completed
+    /// execution on the gpu.
+    pass_timeline: vk::Semaphore,
+
+    /// The id of the last pass which has completed execution on the gpu.
+    completed_pass: Mutex<u64>,
+    completed_signal: Condvar,
+    /// Callbacks waiting for a pass to complete. Only accessed while `completed_pass` is locked.
+    completion_callbacks: Mutex<Vec<(u64, Box<dyn FnOnce() + Send>)>>,
+
+    last_draw_count: AtomicU32,
+    uploaded_bytes: AtomicU64,
+    /// The gpu time of the last completed pass in nanoseconds or [`u64::MAX`] if unavailable.
+    last_gpu_pass_time: AtomicU64,
+
+    retain_capture_data: AtomicBool,
+
+    /// If set the worker processes passes in a fully deterministic order and all ids of this
+    /// renderer are generated from this generator. See [`DeterministicConfig`].
+    deterministic_ids: Option<SeededUUIDs>,
 }
 
 impl Share {
     const PASS_ID_ACTIVE_BIT: u64 = 1u64 << 63;
+    const STAGING_WAIT_TIMEOUT: Duration = Duration::from_secs(2);
 
-    pub(super) fn new(device: Arc<DeviceContext>) -> Self {
+    pub(super) fn new(device: Arc<DeviceContext>, deterministic: Option<&DeterministicConfig>) -> Self {
         let queue = device.get_main_queue();
 
+        // Must be created before any id of this renderer is created
+        let deterministic_ids = deterministic.map(|config| SeededUUIDs::new(config.seed));
+        let id = deterministic_ids.as_ref().map_or_else(UUID::new, SeededUUIDs::next);
+
         let staging_memory = StagingMemoryPool::new(device.clone());
-        let immediate_buffers = ImmediatePool::new(device.clone());
-        let descriptors = Mutex::new(DescriptorPool::new(device.clone()));
+        let immediate_buffers = ImmediatePool::new(device.clone(), deterministic.map_or(ImmediatePool::DEFAULT_BUFFER_COUNT, |config| config.immediate_buffer_count));
+        let mut descriptors = DescriptorPool::new(device.clone());
+        if let Some(config) = deterministic {
+            descriptors.preallocate_uniform_blocks(config.uniform_block_count);
+        }
+        let descriptors = Mutex::new(descriptors);
+        let alpha_mipmap_pipeline = AlphaMipmapPipeline::new(device.clone()).unwrap_or_else(|err| {
+            log::error!("Failed to create alpha mipmap pipeline {:?}", err);
+            panic!()
+        });
+        let light_culling_pipeline = LightCullingPipeline::new(device.clone()).unwrap_or_else(|err| {
+            log::error!("Failed to create light culling pipeline {:?}", err);
+            panic!()
+        });
+        let section_culling_pipeline = SectionCullingPipeline::new(device.clone()).unwrap_or_else(|err| {
+            log::error!("Failed to create section culling pipeline {:?}", err);
+            panic!()
+        });
+
+        let mut timeline = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let info = vk::SemaphoreCreateInfo::builder()
+            .push_next(&mut timeline);
+        let pass_timeline = unsafe {
+            device.vk().create_semaphore(&info, None)
+        }.unwrap_or_else(|err| {
+            log::error!("Failed to create pass timeline semaphore {:?}", err);
+            panic!()
+        });
 
         Self {
-            id: UUID::new(),
+            id,
             device,
             current_pass: AtomicU64::new(0),
 
             staging_memory: Mutex::new(staging_memory),
+            staging_freed: Condvar::new(),
             immediate_buffers,
             shader_database: Mutex::new(HashMap::new()),
+            render_layers: Mutex::new(HashMap::new()),
             descriptors,
+            alpha_mipmap_pipeline,
+            light_culling_pipeline,
+            section_culling_pipeline,
             channel: Mutex::new(Channel::new()),
             signal: Condvar::new(),
+            progress: WorkerProgress::new(),
+
+            pass_timeline,
+
+            completed_pass: Mutex::new(0),
+            completed_signal: Condvar::new(),
+            completion_callbacks: Mutex::new(Vec::new()),
+
+            last_draw_count: AtomicU32::new(0),
+            uploaded_bytes: AtomicU64::new(0),
+            last_gpu_pass_time: AtomicU64::new(u64::MAX),
+
+            retain_capture_data: AtomicBool::new(false),
+
+            deterministic_ids,
         }
     }
 
@@ -54,12 +142,95 @@ impl Share {
         &self.device
     }
 
+    pub(super) fn get_alpha_mipmap_pipeline(&self) -> &AlphaMipmapPipeline {
+        &self.alpha_mipmap_pipeline
+    }
+
+    pub(super) fn get_light_culling_pipeline(&self) -> &LightCullingPipeline {
+        &self.light_culling_pipeline
+    }
+
+    pub(super) fn get_section_culling_pipeline(&self) -> &SectionCullingPipeline {
+        &self.section_culling_pipeline
+    }
+
+    pub(super) fn get_pass_timeline(&self) -> vk::Semaphore {
+        self.pass_timeline
+    }
+
     pub(super) fn get_staging_pool(&self) -> &Mutex<StagingMemoryPool> {
         &self.staging_memory
     }
 
-    pub(super) fn create_shader(&self, vertex_format: &VertexFormat, used_uniforms: McUniform) -> ShaderId {
-        let shader = Shader::new(*vertex_format, used_uniforms);
+    /// Allocates staging memory within the staging budget. If the budget is exhausted this blocks
+    /// until previous uploads have completed and freed their memory. Since the memory of pending
+    /// uploads is only freed once their pass has completed this gives up after
+    /// [`Share::STAGING_WAIT_TIMEOUT`] and exceeds the budget instead of deadlocking.
+    pub(super) fn allocate_staging(&self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> (StagingAllocation, StagingAllocationId) {
+        let mut guard = self.staging_memory.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned staging memory mutex in Share::allocate_staging");
+            panic!()
+        });
+
+        let deadline = Instant::now() + Self::STAGING_WAIT_TIMEOUT;
+        loop {
+            if let Some(result) = guard.try_allocate(size, alignment) {
+                return result;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                log::warn!("Timed out waiting for {} bytes of staging memory. Exceeding staging budget", size);
+                return guard.allocate(size, alignment);
+            }
+
+            guard = self.staging_freed.wait_timeout(guard, deadline - now).unwrap_or_else(|_| {
+                log::error!("Poisoned staging memory mutex in Share::allocate_staging");
+                panic!()
+            }).0;
+        }
+    }
+
+    /// Like [`Share::allocate_staging`] for updates collected into a batch before being pushed. The
+    /// staging memory of the `pending` tasks of the batch is only freed after they have been pushed
+    /// and executed so waiting could only time out. If any task is pending the budget is exceeded
+    /// instead of waiting.
+    pub(super) fn allocate_staging_batched(&self, size: vk::DeviceSize, alignment: vk::DeviceSize, pending: &[WorkerTask]) -> (StagingAllocation, StagingAllocationId) {
+        if pending.is_empty() {
+            return self.allocate_staging(size, alignment);
+        }
+
+        let mut guard = self.staging_memory.lock().unwrap_or_else(|_| {
+            log::error!("Poisoned staging memory mutex in Share::allocate_staging_batched");
+            panic!()
+        });
+        match guard.try_allocate(size, alignment) {
+            Some(result) => result,
+            None => guard.allocate(size, alignment),
+        }
+    }
+
+    /// Must be called after staging memory has been freed to wake threads blocked in
+    /// [`Share::allocate_staging`].
+    pub(super) fn notify_staging_freed(&self) {
+        self.staging_freed.notify_all();
+    }
+
+    pub(super) fn set_staging_budget(&self, budget: vk::DeviceSize) {
+        self.staging_memory.lock().unwrap().set_budget(budget);
+        // A larger budget may allow waiting allocations to proceed
+        self.staging_freed.notify_all();
+    }
+
+    pub(super) fn create_shader(&self, vertex_format: &VertexFormat, instance_format: Option<&InstanceFormat>, used_uniforms: McUniform) -> ShaderId {
+        if let Some(instance_format) = instance_format {
+            if instance_format.stride == 0 || instance_format.stride > InstanceFormat::MAX_STRIDE || instance_format.offset.offset >= instance_format.stride {
+                log::error!("Invalid instance format {:?}", instance_format);
+                panic!();
+            }
+        }
+
+        let shader = Shader::new(ShaderId::from_uuid(self.create_id()), *vertex_format, instance_format.copied(), used_uniforms);
         let id = shader.get_id();
 
         let mut guard = self.shader_database.lock().unwrap();
@@ -78,6 +249,26 @@ impl Share {
         guard.get(&id).cloned()
     }
 
+    pub(super) fn create_render_layer(&self, info: RenderLayerInfo) -> RenderLayerId {
+        let layer = RenderLayer::new(RenderLayerId::from_uuid(self.create_id()), info);
+        let id = layer.get_id();
+
+        let mut guard = self.render_layers.lock().unwrap();
+        guard.insert(id, layer);
+
+        id
+    }
+
+    pub(super) fn drop_render_layer(&self, id: RenderLayerId) {
+        let mut guard = self.render_layers.lock().unwrap();
+        guard.remove(&id);
+    }
+
+    pub(super) fn get_render_layer(&self, id: RenderLayerId) -> Option<Arc<RenderLayer>> {
+        let guard = self.render_layers.lock().unwrap();
+        guard.get(&id).cloned()
+    }
+
     pub(super) fn get_current_pass_id(&self) -> Option<u64> {
         let id = self.current_pass.load(std::sync::atomic::Ordering::Acquire);
         if (id & Self::PASS_ID_ACTIVE_BIT) == Self::PASS_ID_ACTIVE_BIT {
@@ -120,6 +311,60 @@ impl Share {
         });
     }
 
+    /// Called by the worker when a pass has completed execution on the gpu.
+    pub(super) fn on_pass_completed(&self, pass_id: u64) {
+        let mut guard = self.completed_pass.lock().unwrap();
+        *guard = std::cmp::max(*guard, pass_id);
+        self.completed_signal.notify_all();
+
+        let completed = *guard;
+        let ready: Vec<_> = {
+            let mut callbacks = self.completion_callbacks.lock().unwrap();
+            let (ready, pending) = std::mem::take(&mut *callbacks).into_iter().partition(|(id, _)| *id <= completed);
+            *callbacks = pending;
+            ready
+        };
+        drop(guard);
+
+        // Callbacks may register new callbacks so no locks must be held
+        for (_, callback) in ready {
+            callback();
+        }
+    }
+
+    /// Calls the callback on the worker thread once the pass has completed execution on the gpu.
+    /// If the pass has already completed the callback is called immediately on the calling thread.
+    pub(super) fn add_completion_callback(&self, pass_id: u64, callback: Box<dyn FnOnce() + Send>) {
+        let guard = self.completed_pass.lock().unwrap();
+        if *guard >= pass_id {
+            drop(guard);
+            callback();
+        } else {
+            self.completion_callbacks.lock().unwrap().push((pass_id, callback));
+        }
+    }
+
+    pub(super) fn is_pass_completed(&self, pass_id: u64) -> bool {
+        *self.completed_pass.lock().unwrap() >= pass_id
+    }
+
+    /// Returns the number of passes which have been started but not yet completed on the gpu.
+    pub(super) fn get_pending_pass_count(&self) -> u64 {
+        let started = self.current_pass.load(std::sync::atomic::Ordering::Acquire) & !Self::PASS_ID_ACTIVE_BIT;
+        started.saturating_sub(*self.completed_pass.lock().unwrap())
+    }
+
+    /// Blocks until less than `max_pending` passes are pending or the timeout elapsed. Returns
+    /// false if the timeout elapsed.
+    pub(super) fn wait_pending_passes(&self, max_pending: u64, timeout: Duration) -> bool {
+        let started = self.current_pass.load(std::sync::atomic::Ordering::Acquire) & !Self::PASS_ID_ACTIVE_BIT;
+        let guard = self.completed_pass.lock().unwrap();
+        let (_guard, result) = self.completed_signal.wait_timeout_while(guard, timeout, |completed| {
+            started.saturating_sub(*completed) >= max_pending
+        }).unwrap();
+        !result.timed_out()
+    }
+
     pub(super) fn get_next_immediate_buffer(&self) -> Box<ImmediateBuffer> {
         self.immediate_buffers.get_next_buffer()
     }
@@ -128,8 +373,74 @@ impl Share {
         self.immediate_buffers.return_buffer(buffer);
     }
 
-    pub(super) fn allocate_uniform(&self, data: &[u8]) -> (vk::Buffer, vk::DeviceSize) {
-        self.descriptors.lock().unwrap().allocate_uniform(data)
+    pub(super) fn get_sub_immediate_buffer(&self) -> Box<ImmediateBuffer> {
+        self.immediate_buffers.get_sub_buffer()
+    }
+
+    pub(super) fn return_sub_immediate_buffer(&self, buffer: Box<ImmediateBuffer>) {
+        self.immediate_buffers.return_sub_buffer(buffer);
+    }
+
+    pub(super) fn get_uniform_block(&self) -> UniformBlock {
+        self.descriptors.lock().unwrap().get_uniform_block()
+    }
+
+    pub(super) fn return_uniform_blocks(&self, blocks: Vec<UniformBlock>, used_bytes: u64) {
+        self.descriptors.lock().unwrap().return_uniform_blocks(blocks, used_bytes);
+    }
+
+    pub(super) fn get_uniform_alignment(&self) -> vk::DeviceSize {
+        self.descriptors.lock().unwrap().get_uniform_alignment()
+    }
+
+    pub(super) fn set_retain_capture_data(&self, retain: bool) {
+        self.retain_capture_data.store(retain, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub(super) fn retains_capture_data(&self) -> bool {
+        self.retain_capture_data.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    pub(super) fn record_pass_draw_count(&self, draw_count: u32) {
+        self.last_draw_count.store(draw_count, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub(super) fn record_upload(&self, bytes: u64) {
+        self.uploaded_bytes.fetch_add(bytes, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub(super) fn record_gpu_pass_time(&self, time: Duration) {
+        self.last_gpu_pass_time.store(time.as_nanos() as u64, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub(super) fn get_statistics(&self) -> EmulatorStatistics {
+        let gpu_pass_time = self.last_gpu_pass_time.load(std::sync::atomic::Ordering::Relaxed);
+
+        EmulatorStatistics {
+            draw_count: self.last_draw_count.load(std::sync::atomic::Ordering::Relaxed),
+            uploaded_bytes: self.uploaded_bytes.load(std::sync::atomic::Ordering::Relaxed),
+            gpu_pass_time: if gpu_pass_time == u64::MAX { None } else { Some(Duration::from_nanos(gpu_pass_time)) },
+            uniforms: self.descriptors.lock().unwrap().get_uniform_statistics(),
+        }
+    }
+
+    pub(super) fn is_deterministic(&self) -> bool {
+        self.deterministic_ids.is_some()
+    }
+
+    /// Creates a new id for a object of this renderer. In deterministic mode the id is taken from
+    /// the seeded generator of this renderer.
+    pub(super) fn create_id(&self) -> UUID {
+        self.deterministic_ids.as_ref().map_or_else(UUID::new, SeededUUIDs::next)
+    }
+
+    pub(super) fn get_progress(&self) -> &WorkerProgress {
+        &self.progress
+    }
+
+    /// Returns the number of tasks waiting to be processed by the worker.
+    pub(super) fn get_queue_depth(&self) -> usize {
+        self.channel.lock().unwrap().queue.len()
     }
 
     pub(super) fn push_task(&self, task: WorkerTask) {
@@ -137,6 +448,16 @@ impl Share {
         self.signal.notify_one();
     }
 
+    /// Pushes multiple tasks at once waking the worker only once. No other task will be pushed in
+    /// between the tasks.
+    pub(super) fn push_tasks(&self, tasks: Vec<WorkerTask>) {
+        if tasks.is_empty() {
+            return;
+        }
+        self.channel.lock().unwrap().queue.extend(tasks);
+        self.signal.notify_one();
+    }
+
     pub(super) fn try_get_next_task_timeout(&self, timeout: Duration) -> NextTaskResult {
         let start = Instant::now();
 
@@ -168,6 +489,15 @@ impl Share {
     }
 }
 
+impl Drop for Share {
+    fn drop(&mut self) {
+        // The worker holds a reference to the share until all passes have completed
+        unsafe {
+            self.device.vk().destroy_semaphore(self.pass_timeline, None);
+        }
+    }
+}
+
 impl PartialEq for Share {
     fn eq(&self, other: &Self) -> bool {
         self.id.eq(&other.id)
@@ -0,0 +1,82 @@
+//! Layout of split screen views.
+//!
+//! Each view is rendered into its own region of the framebuffer using
+//! [`DrawRecorder::set_viewport`](super::DrawRecorder::set_viewport). Since the regions do not
+//! overlap they can share the depth buffer without clearing it.
+
+use ash::vk;
+
+use crate::prelude::*;
+
+/// Returns the regions of the framebuffer used for `count` split screen views.
+///
+/// Two views are stacked vertically. With three views the first view uses the top half and the
+/// other views share the bottom half. Any larger number of views is laid out in a grid filling
+/// rows first. The regions exactly cover the framebuffer if the grid is full.
+pub fn split_screen_regions(framebuffer_size: Vec2u32, count: u32) -> Vec<vk::Rect2D> {
+    match count {
+        0 => Vec::new(),
+        1 => vec![make_region(framebuffer_size, (0, 1), (0, 1))],
+        2 => vec![
+            make_region(framebuffer_size, (0, 1), (0, 2)),
+            make_region(framebuffer_size, (0, 1), (1, 2)),
+        ],
+        3 => vec![
+            make_region(framebuffer_size, (0, 1), (0, 2)),
+            make_region(framebuffer_size, (0, 2), (1, 2)),
+            make_region(framebuffer_size, (1, 2), (1, 2)),
+        ],
+        _ => {
+            let columns = (count as f32).sqrt().ceil() as u32;
+            let rows = (count + columns - 1) / columns;
+            (0..count).map(|index| {
+                make_region(framebuffer_size, (index % columns, columns), (index / columns, rows))
+            }).collect()
+        }
+    }
+}
+
+/// Returns the region of the cell in a grid. Cells are specified as (index, count) pairs for each
+/// axis. Cell boundaries are rounded down so neighbouring cells never overlap.
+fn make_region(framebuffer_size: Vec2u32, x: (u32, u32), y: (u32, u32)) -> vk::Rect2D {
+    let boundary = |size: u32, index: u32, count: u32| ((size as u64) * (index as u64) / (count as u64)) as u32;
+
+    let min_x = boundary(framebuffer_size[0], x.0, x.1);
+    let max_x = boundary(framebuffer_size[0], x.0 + 1, x.1);
+    let min_y = boundary(framebuffer_size[1], y.0, y.1);
+    let max_y = boundary(framebuffer_size[1], y.0 + 1, y.1);
+
+    vk::Rect2D {
+        offset: vk::Offset2D { x: min_x as i32, y: min_y as i32 },
+        extent: vk::Extent2D { width: max_x - min_x, height: max_y - min_y }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> vk::Rect2D {
+        vk::Rect2D {
+            offset: vk::Offset2D { x, y },
+            extent: vk::Extent2D { width, height }
+        }
+    }
+
+    #[test]
+    fn regions() {
+        let size = Vec2u32::new(1921, 1081);
+        assert_eq!(split_screen_regions(size, 1), vec![rect(0, 0, 1921, 1081)]);
+        assert_eq!(split_screen_regions(size, 2), vec![rect(0, 0, 1921, 540), rect(0, 540, 1921, 541)]);
+        assert_eq!(split_screen_regions(size, 3), vec![rect(0, 0, 1921, 540), rect(0, 540, 960, 541), rect(960, 540, 961, 541)]);
+
+        let regions = split_screen_regions(size, 4);
+        assert_eq!(regions[0], rect(0, 0, 960, 540));
+        assert_eq!(regions[3], rect(960, 540, 961, 541));
+
+        // 3x2 grid with one empty cell
+        let regions = split_screen_regions(size, 5);
+        assert_eq!(regions.len(), 5);
+        assert_eq!(regions[4], rect(640, 540, 640, 541));
+    }
+}
@@ -2,6 +2,7 @@ use std::ptr::NonNull;
 use std::sync::Arc;
 
 use ash::vk;
+use bytemuck::Pod;
 use crate::allocator::{Allocation, HostAccess};
 
 use crate::prelude::DeviceContext;
@@ -27,10 +28,15 @@ pub struct StagingMemoryPool {
     /// `0` defines a threshold of `0%` i.e. never reduce and [`u8::MAX`] a threshold of `100%` i.e.
     /// always reduce.
     reduce_threshold: u8,
+
+    /// The maximum number of bytes of all backing buffers combined. New backing buffers are only
+    /// created by [`StagingMemoryPool::try_allocate`] if they fit into the budget.
+    budget: vk::DeviceSize,
 }
 
 impl StagingMemoryPool {
     const MIN_BUFFER_SIZE: vk::DeviceSize = 2u64.pow(24); // 16MB
+    pub(super) const DEFAULT_BUDGET: vk::DeviceSize = 2u64.pow(28); // 256MB
 
     pub(super) fn new(device: Arc<DeviceContext>) -> Self {
         let current_buffer = StagingBuffer::new(device.clone(), Self::MIN_BUFFER_SIZE);
@@ -42,20 +48,52 @@ impl StagingMemoryPool {
             current_buffer,
             old_buffers: Vec::new(),
             over_allocation: 76,
-            reduce_threshold: 127
+            reduce_threshold: 127,
+            budget: Self::DEFAULT_BUDGET,
         }
     }
 
+    pub(super) fn set_budget(&mut self, budget: vk::DeviceSize) {
+        self.budget = budget;
+    }
+
+    /// Allocates staging memory creating a new backing buffer if necessary. The budget is ignored.
     pub(super) fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> (StagingAllocation, StagingAllocationId) {
         if let Some((alloc, slot_id)) = self.current_buffer.try_allocate(size, alignment) {
             (alloc, StagingAllocationId{ buffer_id: self.current_buffer_id, slot_id })
         } else {
-            self.create_new_buffer(size);
+            let new_size = get_new_buffer_size(self.used_byte_count(), size, self.over_allocation, vk::DeviceSize::MAX).unwrap();
+            self.create_new_buffer(new_size);
             let (alloc, slot_id) = self.current_buffer.try_allocate(size, alignment).unwrap();
-            (alloc, StagingAllocationId{ buffer_id: 0, slot_id })
+            (alloc, StagingAllocationId{ buffer_id: self.current_buffer_id, slot_id })
         }
     }
 
+    /// Allocates staging memory without exceeding the budget. Returns [`None`] if the current
+    /// backing buffer is full and no new backing buffer fits into the budget. Memory becomes
+    /// available again once previous allocations are freed.
+    ///
+    /// An allocation larger than the budget succeeds if no other allocation is alive since it could
+    /// never succeed otherwise.
+    pub(super) fn try_allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<(StagingAllocation, StagingAllocationId)> {
+        if let Some((alloc, slot_id)) = self.current_buffer.try_allocate(size, alignment) {
+            return Some((alloc, StagingAllocationId{ buffer_id: self.current_buffer_id, slot_id }));
+        }
+
+        let used = self.used_byte_count();
+        let new_size = if used == 0 {
+            get_new_buffer_size(0, size, self.over_allocation, std::cmp::max(self.budget, size))?
+        } else {
+            // The current buffer stays alive until its allocations are freed
+            let remaining = self.budget.checked_sub(self.live_buffer_byte_count())?;
+            get_new_buffer_size(used, size, self.over_allocation, remaining)?
+        };
+
+        self.create_new_buffer(new_size);
+        let (alloc, slot_id) = self.current_buffer.try_allocate(size, alignment).unwrap();
+        Some((alloc, StagingAllocationId{ buffer_id: self.current_buffer_id, slot_id }))
+    }
+
     pub(super) fn free(&mut self, allocation: StagingAllocationId) {
         if allocation.buffer_id == self.current_buffer_id {
             self.current_buffer.free(allocation.slot_id);
@@ -76,18 +114,27 @@ impl StagingMemoryPool {
         }
     }
 
-    fn create_new_buffer(&mut self, additional_size: vk::DeviceSize) {
+    /// The number of bytes currently allocated from all backing buffers.
+    fn used_byte_count(&self) -> vk::DeviceSize {
         let mut usage_sum = self.current_buffer.used_byte_count();
         for (_, old) in &self.old_buffers {
             usage_sum += old.used_byte_count();
         }
-        usage_sum += additional_size;
+        usage_sum
+    }
 
-        let new_size = usage_sum + ((usage_sum * (self.over_allocation as u64)) / (u8::MAX as u64));
-        let new_size = std::cmp::max(new_size, Self::MIN_BUFFER_SIZE);
+    /// The size of all backing buffers which contain live allocations.
+    fn live_buffer_byte_count(&self) -> vk::DeviceSize {
+        let mut size_sum = if self.current_buffer.is_empty() { 0 } else { self.current_buffer.size };
+        for (_, old) in &self.old_buffers {
+            size_sum += old.size;
+        }
+        size_sum
+    }
 
+    fn create_new_buffer(&mut self, new_size: vk::DeviceSize) {
         // Yes this is slow but it shouldn't matter since we never have many buffers
-        while self.is_id_unused(self.next_buffer_id) {
+        while !self.is_id_unused(self.next_buffer_id) {
             // Technically there is a potential infinite loop here but at that point we would have
             // allocated at least 1TB of memory so i will accept this risk
             self.next_buffer_id = self.next_buffer_id.wrapping_add(1);
@@ -98,7 +145,10 @@ impl StagingMemoryPool {
         let buffer = StagingBuffer::new(self.device.clone(), new_size);
 
         let old = std::mem::replace(&mut self.current_buffer, buffer);
-        self.old_buffers.push((self.current_buffer_id, old));
+        if !old.is_empty() {
+            // Destroyed once all of its allocations are freed
+            self.old_buffers.push((self.current_buffer_id, old));
+        }
         self.current_buffer_id = id;
     }
 
@@ -115,8 +165,24 @@ impl StagingMemoryPool {
     }
 }
 
+/// Calculates the size of a new backing buffer if `used` bytes are currently allocated and
+/// `additional` bytes are requested. Returns [`None`] if the buffer would not fit into `remaining`
+/// bytes of the budget. The buffer is shrunk down to the remaining budget if possible.
+fn get_new_buffer_size(used: vk::DeviceSize, additional: vk::DeviceSize, over_allocation: u8, remaining: vk::DeviceSize) -> Option<vk::DeviceSize> {
+    if additional > remaining {
+        return None;
+    }
+
+    let usage_sum = used.saturating_add(additional);
+    let new_size = usage_sum.saturating_add(usage_sum.saturating_mul(over_allocation as u64) / (u8::MAX as u64));
+    let new_size = std::cmp::max(new_size, StagingMemoryPool::MIN_BUFFER_SIZE);
+
+    Some(std::cmp::min(new_size, remaining))
+}
+
 struct StagingBuffer {
     device: Arc<DeviceContext>,
+    size: vk::DeviceSize,
     buffer: vk::Buffer,
     mapped_ptr: NonNull<u8>,
     allocation: Allocation,
@@ -125,17 +191,35 @@ struct StagingBuffer {
 
 impl StagingBuffer {
     fn new(device: Arc<DeviceContext>, size: vk::DeviceSize) -> Self {
+        // Device memory decompression reads its source through a device address
+        let mut usage = vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST;
+        if device.memory_decompression_nv().is_some() {
+            usage |= vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS;
+        }
+
         let info = vk::BufferCreateInfo::builder()
             .size(size)
-            .usage(vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
+            .usage(usage)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
+        // Initial global mesh uploads are copied on the async transfer queues if available
+        let queue_families;
+        let info = match device.get_async_transfer_queue() {
+            Some(transfer_queue) => {
+                queue_families = [device.get_main_queue().get_queue_family_index(), transfer_queue.get_queue_family_index()];
+                info.sharing_mode(vk::SharingMode::CONCURRENT)
+                    .queue_family_indices(&queue_families)
+            }
+            None => info,
+        };
+
         let (buffer, allocation, mapped_ptr) = unsafe {
             device.get_allocator().create_buffer(&info, HostAccess::Random, &format_args!("StagingBuffer"))
         }.unwrap();
 
         Self {
             device,
+            size,
             buffer,
             mapped_ptr: mapped_ptr.unwrap(),
             allocation,
@@ -148,6 +232,8 @@ impl StagingBuffer {
             let alloc = StagingAllocation {
                 buffer: self.buffer,
                 offset,
+                size,
+                memory: self.allocation,
                 mapped: unsafe { NonNull::new_unchecked(self.mapped_ptr.as_ptr().offset(offset as isize)) }
             };
             (alloc, slot)
@@ -183,13 +269,145 @@ unsafe impl Send for StagingBuffer { // Needed because of NonNull<u8>
 unsafe impl Sync for StagingBuffer { // Needed because of NonNull<u8>
 }
 
+/// A range of mapped staging memory. The memory must only be accessed until the allocation has
+/// been passed to the worker.
 pub(super) struct StagingAllocation {
     pub(super) buffer: vk::Buffer,
     pub(super) offset: vk::DeviceSize,
-    pub(super) mapped: NonNull<u8>,
+    pub(super) size: vk::DeviceSize,
+    /// The memory of the backing buffer. The buffer is bound at offset 0 of the allocation.
+    memory: Allocation,
+    mapped: NonNull<u8>,
+}
+
+impl StagingAllocation {
+    pub(super) fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(self.mapped.as_ptr(), self.size as usize)
+        }
+    }
+
+    pub(super) fn as_bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            std::slice::from_raw_parts_mut(self.mapped.as_ptr(), self.size as usize)
+        }
+    }
+
+    /// Makes host writes available to the device. Must be called after writing and before the
+    /// allocation is passed to the worker. Does nothing for host coherent memory.
+    pub(super) fn flush(&self, device: &DeviceContext) {
+        unsafe {
+            device.get_allocator().flush_allocation(self.memory, self.offset, self.size)
+        }.unwrap_or_else(|err| {
+            log::error!("Failed to flush staging memory {:?}", err);
+            panic!()
+        });
+    }
+
+    /// Makes device writes visible to the host. Must be called after the writes have completed and
+    /// before reading. Does nothing for host coherent memory.
+    pub(super) fn invalidate(&self, device: &DeviceContext) {
+        unsafe {
+            device.get_allocator().invalidate_allocation(self.memory, self.offset, self.size)
+        }.unwrap_or_else(|err| {
+            log::error!("Failed to invalidate staging memory {:?}", err);
+            panic!()
+        });
+    }
+
+    /// Copies `data` into the allocation starting at the byte `offset`. Panics if the data does not
+    /// fit into the allocation.
+    pub(super) fn write_bytes(&mut self, offset: usize, data: &[u8]) {
+        let end = offset.checked_add(data.len()).filter(|end| *end <= self.size as usize).unwrap_or_else(|| {
+            log::error!("Staging write of {} bytes at offset {} exceeds allocation of {} bytes", data.len(), offset, self.size);
+            panic!()
+        });
+        self.as_bytes_mut()[offset..end].copy_from_slice(data);
+    }
+
+    /// Returns the allocation as a slice of `T`. Trailing bytes which do not form a full `T` are
+    /// excluded. Panics if the allocation is not aligned to `T` which cannot happen if the
+    /// allocation was made with an alignment of at least the alignment of `T`.
+    pub(super) fn as_slice_of<T: Pod>(&mut self) -> &mut [T] {
+        let bytes = self.as_bytes_mut();
+        let len = bytes.len() - (bytes.len() % std::mem::size_of::<T>());
+        bytemuck::cast_slice_mut(&mut bytes[..len])
+    }
+
+    /// Writes `value` to the `index`th `T` of the allocation. See [`StagingAllocation::as_slice_of`].
+    pub(super) fn write_at<T: Pod>(&mut self, index: usize, value: T) {
+        self.as_slice_of::<T>()[index] = value;
+    }
+
+    /// Writes all values of `iter` starting at the `first_index`th `T` of the allocation. Returns
+    /// the number of written values. Panics if the values do not fit into the allocation.
+    pub(super) fn write_iter<T: Pod, I: IntoIterator<Item=T>>(&mut self, first_index: usize, iter: I) -> usize {
+        let mut count = 0;
+        for value in iter {
+            self.write_at(first_index + count, value);
+            count += 1;
+        }
+        count
+    }
 }
 
 unsafe impl Send for StagingAllocation { // Needed because of NonNull<u8>
 }
 unsafe impl Sync for StagingAllocation { // Needed because of NonNull<u8>
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_allocation(memory: &mut [u32]) -> StagingAllocation {
+        StagingAllocation {
+            buffer: vk::Buffer::null(),
+            offset: 0,
+            size: (memory.len() * 4) as vk::DeviceSize,
+            memory: Allocation::default(),
+            mapped: NonNull::new(memory.as_mut_ptr() as *mut u8).unwrap(),
+        }
+    }
+
+    #[test]
+    fn typed_writes() {
+        let mut memory = [0u32; 4];
+        let mut alloc = make_allocation(&mut memory);
+
+        assert_eq!(alloc.write_iter(1, [1u32, 2u32]), 2);
+        alloc.write_at(3, 3u32);
+        alloc.write_bytes(0, &[4, 0]);
+        assert_eq!(alloc.as_slice_of::<u32>(), &[4, 1, 2, 3]);
+        assert_eq!(alloc.as_slice_of::<u16>().len(), 8);
+        assert_eq!(&alloc.as_bytes()[4..8], &1u32.to_ne_bytes());
+    }
+
+    #[test]
+    #[should_panic]
+    fn write_iter_out_of_bounds() {
+        let mut memory = [0u32; 2];
+        let mut alloc = make_allocation(&mut memory);
+        alloc.write_iter(1, [1u32, 2u32]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn write_bytes_out_of_bounds() {
+        let mut memory = [0u32; 2];
+        let mut alloc = make_allocation(&mut memory);
+        alloc.write_bytes(6, &[0u8; 4]);
+    }
+
+    #[test]
+    fn new_buffer_size() {
+        let min = StagingMemoryPool::MIN_BUFFER_SIZE;
+        assert_eq!(get_new_buffer_size(0, 1024, 0, vk::DeviceSize::MAX), Some(min));
+        assert_eq!(get_new_buffer_size(min * 2, min, 0, vk::DeviceSize::MAX), Some(min * 3));
+        assert_eq!(get_new_buffer_size(min * 2, min, u8::MAX, vk::DeviceSize::MAX), Some(min * 6));
+
+        // Limited by the remaining budget
+        assert_eq!(get_new_buffer_size(0, 1024, 0, 4096), Some(4096));
+        assert_eq!(get_new_buffer_size(min * 2, min, u8::MAX, min * 4), Some(min * 4));
+        assert_eq!(get_new_buffer_size(0, 8192, 0, 4096), None);
+    }
+}
@@ -0,0 +1,437 @@
+//! Residency management of global meshes and images under a memory budget.
+//!
+//! Large render distances can require more mesh and texture memory than the device provides. A
+//! [`StreamingManager`] keeps the data of every registered resource on the host, either as a
+//! retained copy or through a callback which reloads it, and only keeps the recently drawn
+//! resources uploaded. If uploading a resource would exceed the budget the least recently used
+//! resources are evicted first.
+//!
+//! Meshes may also be registered in compressed form using [`StreamingManager::add_compressed_mesh`]
+//! in which case only the compressed data is kept on the host and decompressed during the upload.
+//! The budget always accounts for the decompressed size.
+//!
+//! Resources are uploaded on demand when they are requested using [`StreamingManager::get_mesh`]
+//! or [`StreamingManager::get_image`]. Resources requested during the current frame (see
+//! [`StreamingManager::begin_frame`]) are never evicted so the budget may be exceeded temporarily
+//! if a single frame uses more memory than available. Evicted resources are released once all
+//! passes using them have completed.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use ash::vk;
+
+use crate::define_uuid_type;
+use crate::prelude::*;
+use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, GlobalMesh, ImageData, MeshData};
+use crate::renderer::emulator::mesh_compression::OwnedCompressedMeshData;
+use crate::util::format::Format;
+
+define_uuid_type!(pub, StreamedMeshId);
+define_uuid_type!(pub, StreamedImageId);
+
+/// A host copy of mesh data. See [`MeshData`].
+#[derive(Clone)]
+pub struct OwnedMeshData {
+    pub vertex_data: Box<[u8]>,
+    pub index_data: Box<[u8]>,
+    pub vertex_stride: u32,
+    pub index_count: u32,
+    pub index_type: vk::IndexType,
+    pub primitive_topology: vk::PrimitiveTopology,
+}
+
+impl OwnedMeshData {
+    pub fn from_mesh_data(data: &MeshData<'_>) -> Self {
+        Self {
+            vertex_data: data.vertex_data.into(),
+            index_data: data.index_data.into(),
+            vertex_stride: data.vertex_stride,
+            index_count: data.index_count,
+            index_type: data.index_type,
+            primitive_topology: data.primitive_topology,
+        }
+    }
+
+    pub fn as_mesh_data(&self) -> MeshData<'_> {
+        MeshData {
+            vertex_data: &self.vertex_data,
+            index_data: &self.index_data,
+            vertex_stride: self.vertex_stride,
+            index_count: self.index_count,
+            index_type: self.index_type,
+            primitive_topology: self.primitive_topology,
+        }
+    }
+
+    fn get_byte_size(&self) -> u64 {
+        (self.vertex_data.len() + self.index_data.len()) as u64
+    }
+}
+
+/// A host copy of image data.
+#[derive(Clone)]
+pub struct OwnedImageData {
+    pub size: Vec2u32,
+    pub format: &'static Format,
+    pub mip_levels: u32,
+
+    /// The tightly packed data of the first mip levels. If fewer levels than `mip_levels` are
+    /// provided the remaining levels are generated after the upload.
+    pub levels: Box<[Box<[u8]>]>,
+}
+
+impl OwnedImageData {
+    fn get_byte_size(&self) -> u64 {
+        self.levels.iter().map(|level| level.len() as u64).sum()
+    }
+}
+
+/// The source used to upload a resource whenever it becomes resident.
+pub enum StreamSource<T> {
+    /// A copy of the data retained in host memory.
+    Retained(T),
+
+    /// A callback which loads the data. Called every time the resource is uploaded.
+    Callback(Box<dyn Fn() -> T + Send + Sync>),
+}
+
+impl<T> StreamSource<T> {
+    fn with_data<R, F: FnOnce(&T) -> R>(&self, f: F) -> R {
+        match self {
+            StreamSource::Retained(data) => f(data),
+            StreamSource::Callback(callback) => f(&callback()),
+        }
+    }
+}
+
+/// Statistics about the resources of a [`StreamingManager`].
+#[derive(Copy, Clone, Debug)]
+pub struct StreamingStatistics {
+    pub budget: u64,
+    /// The number of bytes of all currently uploaded resources.
+    pub resident_bytes: u64,
+    pub resident_count: usize,
+    pub registered_count: usize,
+    /// The total number of uploads since the manager was created.
+    pub uploads: u64,
+    /// The total number of evictions since the manager was created.
+    pub evictions: u64,
+}
+
+pub struct StreamingManager {
+    renderer: Arc<EmulatorRenderer>,
+    state: Mutex<StreamingState>,
+}
+
+impl StreamingManager {
+    /// Creates a new manager which attempts to keep the uploaded resources below `budget` bytes.
+    pub fn new(renderer: Arc<EmulatorRenderer>, budget: u64) -> Self {
+        Self {
+            renderer,
+            state: Mutex::new(StreamingState {
+                meshes: HashMap::new(),
+                images: HashMap::new(),
+                residency: ResidencyTracker::new(budget),
+                uploads: 0,
+                evictions: 0,
+            }),
+        }
+    }
+
+    /// Changes the budget. If the new budget is smaller resources not used in the current frame
+    /// are evicted immediately.
+    pub fn set_budget(&self, budget: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.residency.budget = budget;
+        let evicted = state.residency.evict_for(0);
+        state.release(&evicted);
+    }
+
+    /// Starts a new frame. Resources requested after this call are kept resident until the next
+    /// call.
+    pub fn begin_frame(&self) {
+        self.state.lock().unwrap().residency.current_frame += 1;
+    }
+
+    /// Registers a new mesh. The mesh is only uploaded once it is first requested.
+    pub fn add_mesh(&self, source: StreamSource<OwnedMeshData>) -> StreamedMeshId {
+        let id = StreamedMeshId::from_uuid(self.renderer.share.create_id());
+        self.state.lock().unwrap().meshes.insert(id, StreamedResource::new(MeshSource::Raw(source)));
+        id
+    }
+
+    /// Registers a new compressed mesh. The mesh is only uploaded once it is first requested. The
+    /// compression format must be supported by the device.
+    pub fn add_compressed_mesh(&self, source: StreamSource<OwnedCompressedMeshData>) -> StreamedMeshId {
+        let id = StreamedMeshId::from_uuid(self.renderer.share.create_id());
+        self.state.lock().unwrap().meshes.insert(id, StreamedResource::new(MeshSource::Compressed(source)));
+        id
+    }
+
+    /// Removes a mesh. The uploaded mesh is released once all passes using it have completed.
+    pub fn remove_mesh(&self, id: StreamedMeshId) {
+        let mut state = self.state.lock().unwrap();
+        if state.meshes.remove(&id).is_some() {
+            state.residency.remove(ResourceKey::Mesh(id));
+        }
+    }
+
+    /// Returns the uploaded mesh uploading it first if it is not resident.
+    pub fn get_mesh(&self, id: StreamedMeshId) -> Arc<GlobalMesh> {
+        let mut state = self.state.lock().unwrap();
+        let key = ResourceKey::Mesh(id);
+
+        let entry = state.meshes.get(&id).unwrap_or_else(|| {
+            log::error!("Requested unknown streamed mesh {:?}", id);
+            panic!()
+        });
+        if let Some(mesh) = entry.resident.clone() {
+            state.residency.touch(key);
+            return mesh;
+        }
+
+        let (mesh, size) = match &entry.source {
+            MeshSource::Raw(source) => source.with_data(|data| {
+                (self.renderer.create_global_mesh(&data.as_mesh_data()), data.get_byte_size())
+            }),
+            MeshSource::Compressed(source) => source.with_data(|data| {
+                let mesh = self.renderer.create_compressed_global_mesh(&data.as_compressed_mesh_data()).unwrap_or_else(|err| {
+                    log::error!("Failed to upload compressed streamed mesh {:?}: {:?}", id, err);
+                    panic!()
+                });
+                (mesh, data.get_decompressed_size())
+            }),
+        };
+
+        let evicted = state.residency.evict_for(size);
+        state.release(&evicted);
+        state.residency.insert(key, size);
+        state.uploads += 1;
+        state.meshes.get_mut(&id).unwrap().resident = Some(mesh.clone());
+
+        mesh
+    }
+
+    /// Registers a new image. The image is only uploaded once it is first requested.
+    pub fn add_image(&self, source: StreamSource<OwnedImageData>) -> StreamedImageId {
+        let id = StreamedImageId::from_uuid(self.renderer.share.create_id());
+        self.state.lock().unwrap().images.insert(id, StreamedResource::new(source));
+        id
+    }
+
+    /// Removes a image. The uploaded image is released once all passes using it have completed.
+    pub fn remove_image(&self, id: StreamedImageId) {
+        let mut state = self.state.lock().unwrap();
+        if state.images.remove(&id).is_some() {
+            state.residency.remove(ResourceKey::Image(id));
+        }
+    }
+
+    /// Returns the uploaded image uploading it first if it is not resident.
+    pub fn get_image(&self, id: StreamedImageId) -> Arc<GlobalImage> {
+        let mut state = self.state.lock().unwrap();
+        let key = ResourceKey::Image(id);
+
+        let entry = state.images.get(&id).unwrap_or_else(|| {
+            log::error!("Requested unknown streamed image {:?}", id);
+            panic!()
+        });
+        if let Some(image) = entry.resident.clone() {
+            state.residency.touch(key);
+            return image;
+        }
+
+        let (image, size) = entry.source.with_data(|data| {
+            (self.upload_image(data), data.get_byte_size())
+        });
+
+        let evicted = state.residency.evict_for(size);
+        state.release(&evicted);
+        state.residency.insert(key, size);
+        state.uploads += 1;
+        state.images.get_mut(&id).unwrap().resident = Some(image.clone());
+
+        image
+    }
+
+    pub fn get_statistics(&self) -> StreamingStatistics {
+        let state = self.state.lock().unwrap();
+        StreamingStatistics {
+            budget: state.residency.budget,
+            resident_bytes: state.residency.resident_bytes,
+            resident_count: state.residency.lru.len(),
+            registered_count: state.meshes.len() + state.images.len(),
+            uploads: state.uploads,
+            evictions: state.evictions,
+        }
+    }
+
+    fn upload_image(&self, data: &OwnedImageData) -> Arc<GlobalImage> {
+        let image = self.renderer.create_global_image_mips(data.size, data.mip_levels, data.format);
+
+        let regions: Box<[_]> = data.levels.iter().enumerate().map(|(level, level_data)| {
+            let level_size = Vec2u32::new(std::cmp::max(data.size[0] >> level, 1), std::cmp::max(data.size[1] >> level, 1));
+            ImageData::new_full(level_data, level_size)
+        }).collect();
+        let regions: Box<[_]> = regions.iter().enumerate().map(|(level, region)| (level as u32, region)).collect();
+        image.update_mip_regions(&regions);
+
+        if (data.levels.len() as u32) < data.mip_levels {
+            image.generate_mipmaps();
+        }
+
+        image
+    }
+}
+
+enum MeshSource {
+    Raw(StreamSource<OwnedMeshData>),
+    Compressed(StreamSource<OwnedCompressedMeshData>),
+}
+
+struct StreamedResource<S, R> {
+    source: S,
+    resident: Option<Arc<R>>,
+}
+
+impl<S, R> StreamedResource<S, R> {
+    fn new(source: S) -> Self {
+        Self {
+            source,
+            resident: None,
+        }
+    }
+}
+
+struct StreamingState {
+    meshes: HashMap<StreamedMeshId, StreamedResource<MeshSource, GlobalMesh>>,
+    images: HashMap<StreamedImageId, StreamedResource<StreamSource<OwnedImageData>, GlobalImage>>,
+    residency: ResidencyTracker<ResourceKey>,
+    uploads: u64,
+    evictions: u64,
+}
+
+impl StreamingState {
+    /// Drops the uploaded objects of evicted resources.
+    fn release(&mut self, evicted: &[ResourceKey]) {
+        for key in evicted {
+            match key {
+                ResourceKey::Mesh(id) => self.meshes.get_mut(id).map(|entry| entry.resident = None),
+                ResourceKey::Image(id) => self.images.get_mut(id).map(|entry| entry.resident = None),
+            };
+        }
+        self.evictions += evicted.len() as u64;
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+enum ResourceKey {
+    Mesh(StreamedMeshId),
+    Image(StreamedImageId),
+}
+
+/// Tracks the size and last use of resident resources in least recently used order.
+struct ResidencyTracker<K: Copy + Ord + std::hash::Hash> {
+    budget: u64,
+    resident_bytes: u64,
+    current_frame: u64,
+
+    /// The last used frame and size of every resident resource.
+    resident: HashMap<K, (u64, u64)>,
+
+    /// All resident resources ordered by the frame they were last used in.
+    lru: BTreeSet<(u64, K)>,
+}
+
+impl<K: Copy + Ord + std::hash::Hash> ResidencyTracker<K> {
+    fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            resident_bytes: 0,
+            current_frame: 0,
+            resident: HashMap::new(),
+            lru: BTreeSet::new(),
+        }
+    }
+
+    fn insert(&mut self, key: K, size: u64) {
+        self.remove(key);
+        self.resident.insert(key, (self.current_frame, size));
+        self.lru.insert((self.current_frame, key));
+        self.resident_bytes += size;
+    }
+
+    fn remove(&mut self, key: K) {
+        if let Some((frame, size)) = self.resident.remove(&key) {
+            self.lru.remove(&(frame, key));
+            self.resident_bytes -= size;
+        }
+    }
+
+    /// Marks a resident resource as used in the current frame.
+    fn touch(&mut self, key: K) {
+        if let Some((frame, _)) = self.resident.get_mut(&key) {
+            if *frame != self.current_frame {
+                self.lru.remove(&(*frame, key));
+                *frame = self.current_frame;
+                self.lru.insert((self.current_frame, key));
+            }
+        }
+    }
+
+    /// Removes the least recently used resources until `size` additional bytes fit into the
+    /// budget. Resources used in the current frame are never evicted. Returns the evicted
+    /// resources.
+    fn evict_for(&mut self, size: u64) -> Vec<K> {
+        let mut evicted = Vec::new();
+        while self.resident_bytes + size > self.budget {
+            match self.lru.iter().next() {
+                Some((frame, key)) if *frame != self.current_frame => {
+                    let key = *key;
+                    self.remove(key);
+                    evicted.push(key);
+                }
+                _ => {
+                    log::debug!("Streaming budget of {} bytes exceeded by resources used in the current frame", self.budget);
+                    break;
+                }
+            }
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_eviction() {
+        let mut tracker = ResidencyTracker::new(100);
+        tracker.insert(0u32, 40);
+        tracker.insert(1u32, 40);
+
+        tracker.current_frame += 1;
+        tracker.touch(0);
+        tracker.insert(2, 20);
+        assert_eq!(tracker.resident_bytes, 100);
+
+        // 1 is the least recently used resource
+        assert_eq!(tracker.evict_for(10), vec![1]);
+        tracker.insert(3, 10);
+        assert_eq!(tracker.resident_bytes, 70);
+
+        // Resources used in the current frame are not evicted even if the budget is exceeded
+        assert!(tracker.evict_for(50).is_empty());
+
+        tracker.current_frame += 1;
+        tracker.touch(2);
+        assert_eq!(tracker.evict_for(50), vec![0]);
+        assert_eq!(tracker.resident_bytes, 30);
+
+        tracker.remove(3);
+        tracker.remove(3);
+        assert_eq!(tracker.resident_bytes, 20);
+    }
+}
@@ -0,0 +1,286 @@
+//! Immediate mode vertex building similar to minecrafts `BufferBuilder`.
+//!
+//! A [`Tessellator`] is started on a recorder using [`DrawRecorder::begin`] or
+//! [`DrawBundleRecorder::begin`]. Vertices are built one attribute at a time and accumulated on the
+//! host. When the tessellator is ended the vertices are uploaded into the immediate buffer of the
+//! pass together with generated index data. Primitive modes which cannot be rendered directly by
+//! vulkan are converted to triangle lists. Quads use the shared quad index buffer of the emulator.
+
+use ash::vk;
+use bytemuck::cast_slice;
+
+use crate::renderer::emulator::{DrawRecorder, ImmediateMeshId, MeshData};
+use crate::renderer::emulator::bundle::DrawBundleRecorder;
+use crate::renderer::emulator::mc_shaders::{ShaderId, VertexFormat, VertexFormatEntry};
+
+use crate::prelude::*;
+
+/// The primitive modes supported by the [`Tessellator`]. These match the modes of minecrafts
+/// `VertexFormat.Mode`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum TessellatorMode {
+    Lines,
+    LineStrip,
+    Triangles,
+    TriangleStrip,
+    TriangleFan,
+    Quads,
+}
+
+impl TessellatorMode {
+    pub fn get_primitive_topology(&self) -> vk::PrimitiveTopology {
+        match self {
+            TessellatorMode::Lines => vk::PrimitiveTopology::LINE_LIST,
+            TessellatorMode::LineStrip => vk::PrimitiveTopology::LINE_STRIP,
+            TessellatorMode::Triangles |
+            TessellatorMode::TriangleFan |
+            TessellatorMode::Quads => vk::PrimitiveTopology::TRIANGLE_LIST,
+            TessellatorMode::TriangleStrip => vk::PrimitiveTopology::TRIANGLE_STRIP,
+        }
+    }
+
+    /// Returns the number of vertices which can be used to form complete primitives. Any
+    /// additional vertices are discarded.
+    fn get_usable_vertex_count(&self, vertex_count: u32) -> u32 {
+        match self {
+            TessellatorMode::Lines => vertex_count - (vertex_count % 2),
+            TessellatorMode::Triangles => vertex_count - (vertex_count % 3),
+            TessellatorMode::Quads => vertex_count - (vertex_count % 4),
+            TessellatorMode::LineStrip => if vertex_count < 2 { 0 } else { vertex_count },
+            TessellatorMode::TriangleStrip |
+            TessellatorMode::TriangleFan => if vertex_count < 3 { 0 } else { vertex_count },
+        }
+    }
+
+    /// Generates the indices needed to draw the specified number of vertices. The vertex count
+    /// must be a usable vertex count. Quads use the shared quad indices instead.
+    fn generate_indices(&self, vertex_count: u32) -> Vec<u32> {
+        match self {
+            TessellatorMode::TriangleFan => {
+                (1..vertex_count.saturating_sub(1)).flat_map(|index| [0, index, index + 1]).collect()
+            }
+            _ => (0..vertex_count).collect(),
+        }
+    }
+}
+
+/// Recorders which a [`Tessellator`] can upload its vertices to.
+pub trait ImmediateRecorder {
+    fn upload_immediate(&mut self, data: &MeshData<'_>) -> ImmediateMeshId;
+
+    fn draw_immediate(&mut self, id: ImmediateMeshId, shader: ShaderId, depth_write_enable: bool);
+}
+
+impl ImmediateRecorder for DrawRecorder {
+    fn upload_immediate(&mut self, data: &MeshData<'_>) -> ImmediateMeshId {
+        DrawRecorder::upload_immediate(self, data)
+    }
+
+    fn draw_immediate(&mut self, id: ImmediateMeshId, shader: ShaderId, depth_write_enable: bool) {
+        DrawRecorder::draw_immediate(self, id, shader, depth_write_enable)
+    }
+}
+
+impl ImmediateRecorder for DrawBundleRecorder {
+    fn upload_immediate(&mut self, data: &MeshData<'_>) -> ImmediateMeshId {
+        DrawBundleRecorder::upload_immediate(self, data)
+    }
+
+    fn draw_immediate(&mut self, id: ImmediateMeshId, shader: ShaderId, depth_write_enable: bool) {
+        DrawBundleRecorder::draw_immediate(self, id, shader, depth_write_enable)
+    }
+}
+
+/// Builds immediate meshes one vertex at a time.
+///
+/// Every vertex is started by calling [`Tessellator::vertex`] followed by any number of attribute
+/// setters. Attributes which are not part of the vertex format are ignored and attributes which
+/// are not set are zero. Attributes are converted to the format specified in the vertex format.
+pub struct Tessellator<'a, R: ImmediateRecorder> {
+    recorder: &'a mut R,
+    format: VertexFormat,
+    mode: TessellatorMode,
+    vertex_data: Vec<u8>,
+    vertex_count: u32,
+}
+
+impl<'a, R: ImmediateRecorder> Tessellator<'a, R> {
+    pub(super) fn new(recorder: &'a mut R, format: &VertexFormat, mode: TessellatorMode) -> Self {
+        Self {
+            recorder,
+            format: *format,
+            mode,
+            vertex_data: Vec::with_capacity((format.stride as usize) * 64),
+            vertex_count: 0,
+        }
+    }
+
+    pub fn get_mode(&self) -> TessellatorMode {
+        self.mode
+    }
+
+    pub fn get_vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    /// Starts a new vertex at the specified position.
+    pub fn vertex(&mut self, position: Vec3f32) -> &mut Self {
+        self.vertex_data.resize(self.vertex_data.len() + (self.format.stride as usize), 0u8);
+        self.vertex_count += 1;
+
+        let entry = self.format.position;
+        self.write_entry(Some(entry), position.as_slice())
+    }
+
+    /// Adds a vertex from already packed data. The data must match the vertex format.
+    pub fn raw_vertex(&mut self, data: &[u8]) -> &mut Self {
+        if data.len() != self.format.stride as usize {
+            log::error!("Raw vertex size {:?} does not match vertex stride {:?}", data.len(), self.format.stride);
+            panic!();
+        }
+        self.vertex_data.extend_from_slice(data);
+        self.vertex_count += 1;
+        self
+    }
+
+    pub fn normal(&mut self, normal: Vec3f32) -> &mut Self {
+        self.write_entry(self.format.normal, normal.as_slice())
+    }
+
+    pub fn color(&mut self, color: Vec4f32) -> &mut Self {
+        self.write_entry(self.format.color, color.as_slice())
+    }
+
+    pub fn uv0(&mut self, uv: Vec2f32) -> &mut Self {
+        self.write_entry(self.format.uv0, uv.as_slice())
+    }
+
+    pub fn uv1(&mut self, uv: Vec2i32) -> &mut Self {
+        self.write_entry(self.format.uv1, &[uv[0] as f32, uv[1] as f32])
+    }
+
+    pub fn uv2(&mut self, uv: Vec2i32) -> &mut Self {
+        self.write_entry(self.format.uv2, &[uv[0] as f32, uv[1] as f32])
+    }
+
+    /// Uploads all complete primitives into the immediate buffer. Returns [`None`] if no complete
+    /// primitive has been built.
+    pub fn end(mut self) -> Option<ImmediateMeshId> {
+        self.upload()
+    }
+
+    /// Ends the tessellator and draws the uploaded mesh.
+    pub fn draw(mut self, shader: ShaderId, depth_write_enable: bool) {
+        if let Some(id) = self.upload() {
+            self.recorder.draw_immediate(id, shader, depth_write_enable);
+        }
+    }
+
+    fn upload(&mut self) -> Option<ImmediateMeshId> {
+        let usable_count = self.mode.get_usable_vertex_count(self.vertex_count);
+        if usable_count != self.vertex_count {
+            log::warn!("Tessellator in mode {:?} ended with incomplete primitive. Discarding {} vertices", self.mode, self.vertex_count - usable_count);
+        }
+        if usable_count == 0 {
+            return None;
+        }
+
+        let vertex_data = &self.vertex_data[..(usable_count as usize) * (self.format.stride as usize)];
+        if self.mode == TessellatorMode::Quads {
+            return Some(self.recorder.upload_immediate(&MeshData::new_quads(vertex_data, self.format.stride)));
+        }
+
+        let indices = self.mode.generate_indices(usable_count);
+
+        let small_indices: Vec<u16>;
+        let (index_data, index_type) = if usable_count <= (u16::MAX as u32) + 1 {
+            small_indices = indices.iter().map(|index| *index as u16).collect();
+            (cast_slice::<u16, u8>(&small_indices), vk::IndexType::UINT16)
+        } else {
+            (cast_slice::<u32, u8>(&indices), vk::IndexType::UINT32)
+        };
+
+        let data = MeshData {
+            vertex_data,
+            index_data,
+            vertex_stride: self.format.stride,
+            index_count: indices.len() as u32,
+            index_type,
+            primitive_topology: self.mode.get_primitive_topology(),
+        };
+
+        Some(self.recorder.upload_immediate(&data))
+    }
+
+    fn write_entry(&mut self, entry: Option<VertexFormatEntry>, values: &[f32]) -> &mut Self {
+        let entry = match entry {
+            Some(entry) => entry,
+            None => return self,
+        };
+        if self.vertex_count == 0 {
+            log::error!("Attempted to set vertex attribute before starting a vertex");
+            panic!();
+        }
+
+        let vertex_start = ((self.vertex_count - 1) as usize) * (self.format.stride as usize);
+        let vertex = &mut self.vertex_data[vertex_start..(vertex_start + (self.format.stride as usize))];
+        write_attribute(&mut vertex[(entry.offset as usize)..], entry.format, values);
+
+        self
+    }
+}
+
+fn write_attribute(dst: &mut [u8], format: vk::Format, values: &[f32]) {
+    match format {
+        vk::Format::R32_SFLOAT => write_components(dst, values, 1, |v| v.to_ne_bytes()),
+        vk::Format::R32G32_SFLOAT => write_components(dst, values, 2, |v| v.to_ne_bytes()),
+        vk::Format::R32G32B32_SFLOAT => write_components(dst, values, 3, |v| v.to_ne_bytes()),
+        vk::Format::R32G32B32A32_SFLOAT => write_components(dst, values, 4, |v| v.to_ne_bytes()),
+        vk::Format::R8G8B8A8_UNORM => write_components(dst, values, 4, |v| [(v.clamp(0f32, 1f32) * 255f32).round() as u8]),
+        vk::Format::R8G8B8_SNORM => write_components(dst, values, 3, |v| [((v.clamp(-1f32, 1f32) * 127f32).round() as i8) as u8]),
+        vk::Format::R8G8B8A8_SNORM => write_components(dst, values, 4, |v| [((v.clamp(-1f32, 1f32) * 127f32).round() as i8) as u8]),
+        vk::Format::R16G16_SINT |
+        vk::Format::R16G16_SSCALED => write_components(dst, values, 2, |v| (v as i16).to_ne_bytes()),
+        vk::Format::R16G16_UINT |
+        vk::Format::R16G16_USCALED => write_components(dst, values, 2, |v| (v as u16).to_ne_bytes()),
+        vk::Format::R32G32_SINT => write_components(dst, values, 2, |v| (v as i32).to_ne_bytes()),
+        _ => log::warn!("Tessellator does not support vertex attribute format {:?}. Ignoring!", format),
+    }
+}
+
+fn write_components<const N: usize, F: Fn(f32) -> [u8; N]>(dst: &mut [u8], values: &[f32], components: usize, convert: F) {
+    for (index, value) in values.iter().take(components).enumerate() {
+        dst[(index * N)..((index + 1) * N)].copy_from_slice(&convert(*value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triangle_fan_indices() {
+        let indices = TessellatorMode::TriangleFan.generate_indices(5);
+        assert_eq!(indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn incomplete_primitives_are_discarded() {
+        assert_eq!(TessellatorMode::Quads.get_usable_vertex_count(7), 4);
+        assert_eq!(TessellatorMode::Triangles.get_usable_vertex_count(2), 0);
+        assert_eq!(TessellatorMode::LineStrip.get_usable_vertex_count(1), 0);
+        assert_eq!(TessellatorMode::TriangleStrip.get_usable_vertex_count(5), 5);
+    }
+
+    #[test]
+    fn attribute_conversion() {
+        let mut dst = [0u8; 4];
+        write_attribute(&mut dst, vk::Format::R8G8B8A8_UNORM, &[1f32, 0f32, 0.5f32, 2f32]);
+        assert_eq!(dst, [255, 0, 128, 255]);
+
+        let mut dst = [0u8; 4];
+        write_attribute(&mut dst, vk::Format::R16G16_SINT, &[240f32, -3f32]);
+        assert_eq!(dst[0..2], 240i16.to_ne_bytes());
+        assert_eq!(dst[2..4], (-3i16).to_ne_bytes());
+    }
+}
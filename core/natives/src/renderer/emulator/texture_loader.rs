@@ -0,0 +1,419 @@
+//! Loading of gpu compressed textures stored in KTX2 containers.
+//!
+//! Resource packs can ship textures which are already compressed for the gpu to reduce load times
+//! and memory usage. [`Ktx2Texture`] parses the container and validates the data of every mip
+//! level. [`Ktx2Texture::create_image`] then uploads all levels into a new [`GlobalImage`] using the
+//! stored format if the device supports it. BC1, BC2 and BC3 data is decoded to RGBA on the host
+//! if the device does not support the format.
+//!
+//! BasisU payloads (ETC1S or UASTC) and zstd or zlib supercompression require a transcoder or
+//! decompressor which is not available. Such containers are rejected with a descriptive error so
+//! that the host can fall back to the png version of the texture.
+
+use std::sync::Arc;
+
+use ash::vk;
+
+use crate::device::device::DeviceContext;
+use crate::prelude::*;
+use crate::renderer::emulator::block_layout::{get_mip_size, BlockLayout};
+use crate::renderer::emulator::{EmulatorRenderer, GlobalImage, ImageData};
+use crate::util::format::Format;
+
+const KTX2_IDENTIFIER: &[u8; 12] = &[0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+const KTX2_HEADER_SIZE: usize = 80;
+const KTX2_LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+const SUPERCOMPRESSION_NONE: u32 = 0;
+const SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+
+#[derive(Debug)]
+pub enum TextureLoadError {
+    /// The data is not a valid KTX2 container.
+    InvalidContainer(&'static str),
+    /// The container uses a supercompression scheme which is not supported.
+    UnsupportedSupercompression(u32),
+    /// The container stores a BasisU payload which requires transcoding.
+    RequiresBasisTranscoder,
+    /// The container is not a single 2D image. Arrays, cube maps and 3D textures are not supported.
+    UnsupportedImageType,
+    /// The stored format is not supported by the device and cannot be decoded on the host.
+    UnsupportedFormat(vk::Format),
+}
+
+/// A parsed KTX2 container. The level data references the original data.
+pub struct Ktx2Texture<'a> {
+    format: vk::Format,
+    size: Vec2u32,
+
+    /// The tightly packed data of every mip level starting with the base level.
+    levels: Box<[&'a [u8]]>,
+}
+
+impl<'a> Ktx2Texture<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, TextureLoadError> {
+        if data.len() < KTX2_HEADER_SIZE || &data[0..12] != KTX2_IDENTIFIER {
+            return Err(TextureLoadError::InvalidContainer("Missing KTX2 identifier"));
+        }
+
+        let format = vk::Format::from_raw(read_u32(data, 12) as i32);
+        let width = read_u32(data, 20);
+        let height = read_u32(data, 24);
+        let depth = read_u32(data, 28);
+        let layer_count = read_u32(data, 32);
+        let face_count = read_u32(data, 36);
+        let level_count = read_u32(data, 40);
+        let supercompression = read_u32(data, 44);
+
+        if supercompression == SUPERCOMPRESSION_BASIS_LZ || format == vk::Format::UNDEFINED {
+            return Err(TextureLoadError::RequiresBasisTranscoder);
+        }
+        if supercompression != SUPERCOMPRESSION_NONE {
+            return Err(TextureLoadError::UnsupportedSupercompression(supercompression));
+        }
+        if width == 0 || height == 0 {
+            return Err(TextureLoadError::InvalidContainer("Image has a size of 0"));
+        }
+        if depth != 0 || layer_count > 1 || face_count != 1 {
+            return Err(TextureLoadError::UnsupportedImageType);
+        }
+
+        let block_info = BlockLayout::for_format(format).ok_or(TextureLoadError::UnsupportedFormat(format))?;
+
+        // A level count of 0 requests mipmap generation which we do not support. Only the base level is used.
+        let level_count = std::cmp::max(level_count, 1);
+        if level_count > get_max_level_count(width, height) {
+            return Err(TextureLoadError::InvalidContainer("Level count exceeds the number of possible mip levels"));
+        }
+
+        let index_end = KTX2_HEADER_SIZE + (level_count as usize) * KTX2_LEVEL_INDEX_ENTRY_SIZE;
+        if data.len() < index_end {
+            return Err(TextureLoadError::InvalidContainer("Truncated level index"));
+        }
+
+        let mut levels = Vec::with_capacity(level_count as usize);
+        for level in 0..level_count {
+            let entry = KTX2_HEADER_SIZE + (level as usize) * KTX2_LEVEL_INDEX_ENTRY_SIZE;
+            let byte_offset = read_u64(data, entry);
+            let byte_length = read_u64(data, entry + 8);
+
+            let expected_length = block_info.get_level_byte_size(get_mip_size(Vec2u32::new(width, height), level));
+            if byte_length < expected_length {
+                return Err(TextureLoadError::InvalidContainer("Level data is smaller than the level size"));
+            }
+
+            let start = usize::try_from(byte_offset).map_err(|_| TextureLoadError::InvalidContainer("Level data out of bounds"))?;
+            let end = start.checked_add(expected_length as usize).filter(|end| *end <= data.len())
+                .ok_or(TextureLoadError::InvalidContainer("Level data out of bounds"))?;
+
+            levels.push(&data[start..end]);
+        }
+
+        Ok(Self {
+            format,
+            size: Vec2u32::new(width, height),
+            levels: levels.into_boxed_slice(),
+        })
+    }
+
+    /// Returns the format the texture data is stored in.
+    pub fn get_format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn get_size(&self) -> Vec2u32 {
+        self.size
+    }
+
+    pub fn get_level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    /// Returns the tightly packed data of a mip level.
+    pub fn get_level_data(&self, level: u32) -> &'a [u8] {
+        self.levels[level as usize]
+    }
+
+    /// Creates a global image containing all mip levels of the texture.
+    ///
+    /// The stored format is used if the device supports sampling from it. Otherwise BC1, BC2 and
+    /// BC3 textures are decoded into a RGBA image.
+    pub fn create_image(&self, renderer: &EmulatorRenderer) -> Result<Arc<GlobalImage>, TextureLoadError> {
+        let level_sizes: Box<[_]> = (0..self.get_level_count()).map(|level| get_mip_size(self.size, level)).collect();
+
+        if is_format_supported(renderer.get_device(), self.format) {
+            let image = renderer.create_global_image_mips(self.size, self.get_level_count(), Format::format_for(self.format));
+
+            let regions: Box<[_]> = self.levels.iter().zip(level_sizes.iter()).map(|(data, size)| ImageData::new_full(data, *size)).collect();
+            let regions: Box<[_]> = regions.iter().enumerate().map(|(level, region)| (level as u32, region)).collect();
+            image.update_mip_regions(&regions);
+
+            return Ok(image);
+        }
+
+        let (decode_format, srgb) = get_decode_info(self.format).ok_or(TextureLoadError::UnsupportedFormat(self.format))?;
+        log::debug!("Device does not support {:?}. Decoding texture of size {:?} on the host", self.format, self.size);
+
+        let format = if srgb { &Format::R8G8B8A8_SRGB } else { &Format::R8G8B8A8_UNORM };
+        let image = renderer.create_global_image_mips(self.size, self.get_level_count(), format);
+
+        let decoded: Box<[_]> = self.levels.iter().zip(level_sizes.iter()).map(|(data, size)| decode_bc(decode_format, data, *size)).collect();
+        let regions: Box<[_]> = decoded.iter().zip(level_sizes.iter()).map(|(data, size)| ImageData::new_full(data, *size)).collect();
+        let regions: Box<[_]> = regions.iter().enumerate().map(|(level, region)| (level as u32, region)).collect();
+        image.update_mip_regions(&regions);
+
+        Ok(image)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum DecodeFormat {
+    BC1,
+    BC2,
+    BC3,
+}
+
+/// Returns the decoder and whether the decoded data is srgb for formats which can be decoded on the host.
+fn get_decode_info(format: vk::Format) -> Option<(DecodeFormat, bool)> {
+    match format {
+        vk::Format::BC1_RGB_UNORM_BLOCK | vk::Format::BC1_RGBA_UNORM_BLOCK => Some((DecodeFormat::BC1, false)),
+        vk::Format::BC1_RGB_SRGB_BLOCK | vk::Format::BC1_RGBA_SRGB_BLOCK => Some((DecodeFormat::BC1, true)),
+        vk::Format::BC2_UNORM_BLOCK => Some((DecodeFormat::BC2, false)),
+        vk::Format::BC2_SRGB_BLOCK => Some((DecodeFormat::BC2, true)),
+        vk::Format::BC3_UNORM_BLOCK => Some((DecodeFormat::BC3, false)),
+        vk::Format::BC3_SRGB_BLOCK => Some((DecodeFormat::BC3, true)),
+        _ => None,
+    }
+}
+
+fn is_format_supported(device: &DeviceContext, format: vk::Format) -> bool {
+    let properties = unsafe {
+        device.get_instance().vk().get_physical_device_format_properties(device.get_functions().physical_device, format)
+    };
+    properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE | vk::FormatFeatureFlags::TRANSFER_DST)
+}
+
+fn get_max_level_count(width: u32, height: u32) -> u32 {
+    32 - std::cmp::max(width, height).leading_zeros()
+}
+
+/// Decodes BC1, BC2 or BC3 data into tightly packed RGBA8 data.
+fn decode_bc(format: DecodeFormat, data: &[u8], size: Vec2u32) -> Box<[u8]> {
+    let width = size[0] as usize;
+    let height = size[1] as usize;
+    let blocks_x = (width + 3) / 4;
+    let blocks_y = (height + 3) / 4;
+    let block_bytes = if format == DecodeFormat::BC1 { 8 } else { 16 };
+
+    let mut result = vec![0u8; width * height * 4].into_boxed_slice();
+    for block_y in 0..blocks_y {
+        for block_x in 0..blocks_x {
+            let offset = (block_y * blocks_x + block_x) * block_bytes;
+            let block = &data[offset..(offset + block_bytes)];
+
+            let texels = match format {
+                DecodeFormat::BC1 => decode_bc1_colors(block, true),
+                DecodeFormat::BC2 => {
+                    let mut texels = decode_bc1_colors(&block[8..16], false);
+                    let alpha = read_u64(block, 0);
+                    for (index, texel) in texels.iter_mut().enumerate() {
+                        let value = ((alpha >> (index * 4)) & 0xF) as u8;
+                        texel[3] = (value << 4) | value;
+                    }
+                    texels
+                }
+                DecodeFormat::BC3 => {
+                    let mut texels = decode_bc1_colors(&block[8..16], false);
+                    let alpha = decode_bc3_alpha(&block[0..8]);
+                    for (texel, alpha) in texels.iter_mut().zip(alpha.iter()) {
+                        texel[3] = *alpha;
+                    }
+                    texels
+                }
+            };
+
+            for (index, texel) in texels.iter().enumerate() {
+                let x = block_x * 4 + (index % 4);
+                let y = block_y * 4 + (index / 4);
+                if x < width && y < height {
+                    let dst = (y * width + x) * 4;
+                    result[dst..(dst + 4)].copy_from_slice(texel);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Decodes the 16 texels of a BC1 color block. If `allow_transparent` is false the block is
+/// always decoded in 4 color mode as required by BC2 and BC3.
+fn decode_bc1_colors(block: &[u8], allow_transparent: bool) -> [[u8; 4]; 16] {
+    let color0 = u16::from_le_bytes([block[0], block[1]]);
+    let color1 = u16::from_le_bytes([block[2], block[3]]);
+    let c0 = expand_rgb565(color0);
+    let c1 = expand_rgb565(color1);
+
+    let mix = |a: u8, b: u8, wa: u32, wb: u32| (((a as u32) * wa + (b as u32) * wb) / (wa + wb)) as u8;
+
+    let mut palette = [[0u8; 4]; 4];
+    palette[0] = [c0[0], c0[1], c0[2], 255];
+    palette[1] = [c1[0], c1[1], c1[2], 255];
+    if color0 > color1 || !allow_transparent {
+        palette[2] = [mix(c0[0], c1[0], 2, 1), mix(c0[1], c1[1], 2, 1), mix(c0[2], c1[2], 2, 1), 255];
+        palette[3] = [mix(c0[0], c1[0], 1, 2), mix(c0[1], c1[1], 1, 2), mix(c0[2], c1[2], 1, 2), 255];
+    } else {
+        palette[2] = [mix(c0[0], c1[0], 1, 1), mix(c0[1], c1[1], 1, 1), mix(c0[2], c1[2], 1, 1), 255];
+        palette[3] = [0, 0, 0, 0];
+    }
+
+    let indices = read_u32(block, 4);
+    let mut texels = [[0u8; 4]; 16];
+    for (index, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[((indices >> (index * 2)) & 0x3) as usize];
+    }
+    texels
+}
+
+/// Decodes the 16 alpha values of a BC3 alpha block.
+fn decode_bc3_alpha(block: &[u8]) -> [u8; 16] {
+    let a0 = block[0] as u32;
+    let a1 = block[1] as u32;
+
+    let mut palette = [0u8; 8];
+    palette[0] = a0 as u8;
+    palette[1] = a1 as u8;
+    if a0 > a1 {
+        for i in 2..8u32 {
+            palette[i as usize] = (((8 - i) * a0 + (i - 1) * a1) / 7) as u8;
+        }
+    } else {
+        for i in 2..6u32 {
+            palette[i as usize] = (((6 - i) * a0 + (i - 1) * a1) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    let mut index_bytes = [0u8; 8];
+    index_bytes[0..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(index_bytes);
+
+    let mut alpha = [0u8; 16];
+    for (index, value) in alpha.iter_mut().enumerate() {
+        *value = palette[((indices >> (index * 3)) & 0x7) as usize];
+    }
+    alpha
+}
+
+fn expand_rgb565(color: u16) -> [u8; 3] {
+    let r = ((color >> 11) & 0x1F) as u8;
+    let g = ((color >> 5) & 0x3F) as u8;
+    let b = (color & 0x1F) as u8;
+    [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..(offset + 4)].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..(offset + 8)].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a KTX2 container without data format descriptor or key value data.
+    fn build_ktx2(format: vk::Format, size: Vec2u32, supercompression: u32, levels: &[&[u8]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(KTX2_IDENTIFIER);
+        for value in [format.as_raw() as u32, 1, size[0], size[1], 0, 0, 1, levels.len() as u32, supercompression] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&[0u8; 32]);
+
+        let mut offset = (KTX2_HEADER_SIZE + levels.len() * KTX2_LEVEL_INDEX_ENTRY_SIZE) as u64;
+        for level in levels {
+            for value in [offset, level.len() as u64, level.len() as u64] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            offset += level.len() as u64;
+        }
+        for level in levels {
+            data.extend_from_slice(level);
+        }
+        data
+    }
+
+    #[test]
+    fn parse_levels() {
+        let level0 = [1u8; 16 * 4];
+        let level1 = [2u8; 16];
+        let level2 = [3u8; 8];
+        let data = build_ktx2(vk::Format::BC1_RGBA_UNORM_BLOCK, Vec2u32::new(16, 4), SUPERCOMPRESSION_NONE, &[&level0[0..64], &level1, &level2]);
+
+        let texture = Ktx2Texture::parse(&data).unwrap();
+        assert_eq!(texture.get_format(), vk::Format::BC1_RGBA_UNORM_BLOCK);
+        assert_eq!(texture.get_size(), Vec2u32::new(16, 4));
+        assert_eq!(texture.get_level_count(), 3);
+        assert_eq!(texture.get_level_data(0), &level0[0..32]);
+        assert_eq!(texture.get_level_data(1), &level1);
+        assert_eq!(texture.get_level_data(2), &level2);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(matches!(Ktx2Texture::parse(&[0u8; 100]), Err(TextureLoadError::InvalidContainer(_))));
+
+        let basis = build_ktx2(vk::Format::UNDEFINED, Vec2u32::new(4, 4), SUPERCOMPRESSION_BASIS_LZ, &[&[0u8; 16]]);
+        assert!(matches!(Ktx2Texture::parse(&basis), Err(TextureLoadError::RequiresBasisTranscoder)));
+
+        let zstd = build_ktx2(vk::Format::R8G8B8A8_UNORM, Vec2u32::new(4, 4), 2, &[&[0u8; 64]]);
+        assert!(matches!(Ktx2Texture::parse(&zstd), Err(TextureLoadError::UnsupportedSupercompression(2))));
+
+        let truncated = build_ktx2(vk::Format::R8G8B8A8_UNORM, Vec2u32::new(4, 4), SUPERCOMPRESSION_NONE, &[&[0u8; 60]]);
+        assert!(matches!(Ktx2Texture::parse(&truncated), Err(TextureLoadError::InvalidContainer(_))));
+
+        let too_many_levels = build_ktx2(vk::Format::R8G8B8A8_UNORM, Vec2u32::new(2, 2), SUPERCOMPRESSION_NONE, &[&[0u8; 16], &[0u8; 4], &[0u8; 4]]);
+        assert!(matches!(Ktx2Texture::parse(&too_many_levels), Err(TextureLoadError::InvalidContainer(_))));
+    }
+
+    #[test]
+    fn decode_bc1() {
+        // Red and blue endpoints with the texels cycling through all 4 palette entries
+        let mut block = [0u8; 8];
+        block[0..2].copy_from_slice(&0xF800u16.to_le_bytes());
+        block[2..4].copy_from_slice(&0x001Fu16.to_le_bytes());
+        block[4..8].copy_from_slice(&0xE4E4E4E4u32.to_le_bytes());
+
+        let decoded = decode_bc(DecodeFormat::BC1, &block, Vec2u32::new(2, 2));
+        assert_eq!(&decoded[0..4], &[255, 0, 0, 255]);
+        assert_eq!(&decoded[4..8], &[0, 0, 255, 255]);
+        assert_eq!(&decoded[8..12], &[255, 0, 0, 255]);
+        assert_eq!(&decoded[12..16], &[0, 0, 255, 255]);
+
+        // Swapped endpoints select the 3 color mode with transparent black
+        block[0..2].copy_from_slice(&0x001Fu16.to_le_bytes());
+        block[2..4].copy_from_slice(&0xF800u16.to_le_bytes());
+        block[4..8].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+        let decoded = decode_bc(DecodeFormat::BC1, &block, Vec2u32::new(1, 1));
+        assert_eq!(decoded.as_ref(), &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn decode_bc3() {
+        let mut block = [0u8; 16];
+        block[0] = 255;
+        block[1] = 0;
+        // Texel 0 uses a0, texel 1 uses a1, texel 2 uses the first interpolated value
+        block[2] = 0b10_001_000;
+        block[8..10].copy_from_slice(&0xFFFFu16.to_le_bytes());
+
+        let decoded = decode_bc(DecodeFormat::BC3, &block, Vec2u32::new(4, 4));
+        assert_eq!(&decoded[0..4], &[255, 255, 255, 255]);
+        assert_eq!(&decoded[4..8], &[255, 255, 255, 0]);
+        assert_eq!(decoded[11], 218);
+    }
+}
@@ -0,0 +1,187 @@
+//! Interpolation of time continuous uniforms between game ticks.
+//!
+//! Minecraft only updates values like the game time, the fog range or the sky color 20 times per
+//! second. When rendering at a higher frame rate this causes visible stepping. A
+//! [`UniformInterpolator`] records the values of the last two ticks and blends between them based on
+//! the time passed since the last tick, similar to the partial tick used by vanilla for entities.
+//!
+//! Only uniforms which change continuously are interpolated. All other uniforms are passed through
+//! using the value of the last tick.
+
+use std::mem::Discriminant;
+use std::time::{Duration, Instant};
+
+use crate::renderer::emulator::mc_shaders::{McUniformData, ShaderId};
+use crate::renderer::emulator::PassRecorder;
+
+use crate::prelude::*;
+
+pub struct UniformInterpolator {
+    tick_duration: Duration,
+    last_tick: Option<Instant>,
+    values: Vec<(Discriminant<McUniformData>, InterpolatedValue)>,
+}
+
+impl UniformInterpolator {
+    /// The duration of a game tick at the default tick rate of 20 ticks per second.
+    pub const DEFAULT_TICK_DURATION: Duration = Duration::from_millis(50);
+
+    pub fn new(tick_duration: Duration) -> Self {
+        Self {
+            tick_duration,
+            last_tick: None,
+            values: Vec::new(),
+        }
+    }
+
+    /// Records the uniform values of a new tick. Values which are not part of this tick keep
+    /// their last value and stop changing.
+    pub fn on_tick(&mut self, values: &[McUniformData]) {
+        self.on_tick_at(values, Instant::now());
+    }
+
+    fn on_tick_at(&mut self, values: &[McUniformData], now: Instant) {
+        for (_, value) in &mut self.values {
+            value.previous = value.current;
+        }
+
+        for data in values {
+            let key = std::mem::discriminant(data);
+            if let Some((_, value)) = self.values.iter_mut().find(|(k, _)| *k == key) {
+                value.current = *data;
+            } else {
+                self.values.push((key, InterpolatedValue { previous: *data, current: *data }));
+            }
+        }
+
+        self.last_tick = Some(now);
+    }
+
+    /// Returns the fraction of the current tick which has passed. Clamped to 1 if the next tick is
+    /// late so values never overshoot.
+    pub fn get_partial_tick(&self) -> f32 {
+        self.get_partial_tick_at(Instant::now())
+    }
+
+    fn get_partial_tick_at(&self, now: Instant) -> f32 {
+        match self.last_tick {
+            Some(last_tick) => {
+                let passed = now.saturating_duration_since(last_tick).as_secs_f32();
+                (passed / self.tick_duration.as_secs_f32()).clamp(0f32, 1f32)
+            }
+            None => 1f32,
+        }
+    }
+
+    /// Returns all recorded values interpolated at the partial tick.
+    pub fn get_values(&self, partial_tick: f32) -> impl Iterator<Item=McUniformData> + '_ {
+        self.values.iter().map(move |(_, value)| interpolate(&value.previous, &value.current, partial_tick))
+    }
+
+    /// Updates all recorded uniforms of the shader with the values interpolated at the current
+    /// time. Should be called once per frame before the first draw using the shader.
+    pub fn update_uniforms(&self, pass: &mut PassRecorder, shader: ShaderId) {
+        for data in self.get_values(self.get_partial_tick()) {
+            pass.update_uniform(&data, shader);
+        }
+    }
+}
+
+impl Default for UniformInterpolator {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_TICK_DURATION)
+    }
+}
+
+struct InterpolatedValue {
+    previous: McUniformData,
+    current: McUniformData,
+}
+
+fn interpolate(previous: &McUniformData, current: &McUniformData, t: f32) -> McUniformData {
+    match (previous, current) {
+        (McUniformData::GameTime(a), McUniformData::GameTime(b)) => {
+            // The game time wraps around to 0 at the end of every day
+            let b = if b < a { b + 1f32 } else { *b };
+            McUniformData::GameTime((a + (b - a) * t).fract())
+        }
+        (McUniformData::FogStart(a), McUniformData::FogStart(b)) => McUniformData::FogStart(a + (b - a) * t),
+        (McUniformData::FogEnd(a), McUniformData::FogEnd(b)) => McUniformData::FogEnd(a + (b - a) * t),
+        (McUniformData::FogColor(a), McUniformData::FogColor(b)) => McUniformData::FogColor(a.lerp(b, t)),
+        (McUniformData::ColorModulator(a), McUniformData::ColorModulator(b)) => McUniformData::ColorModulator(a.lerp(b, t)),
+        (McUniformData::Light0Direction(a), McUniformData::Light0Direction(b)) => McUniformData::Light0Direction(lerp_direction(a, b, t)),
+        (McUniformData::Light1Direction(a), McUniformData::Light1Direction(b)) => McUniformData::Light1Direction(lerp_direction(a, b, t)),
+        _ => *current,
+    }
+}
+
+fn lerp_direction(a: &Vec3f32, b: &Vec3f32, t: f32) -> Vec3f32 {
+    let result = a.lerp(b, t);
+    result.try_normalize(f32::EPSILON).unwrap_or(*b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_between_ticks() {
+        let mut interpolator = UniformInterpolator::default();
+        let start = Instant::now();
+
+        interpolator.on_tick_at(&[McUniformData::FogStart(0f32), McUniformData::FogShape(0)], start);
+        interpolator.on_tick_at(&[McUniformData::FogStart(10f32), McUniformData::FogShape(1)], start);
+
+        let half = interpolator.get_partial_tick_at(start + Duration::from_millis(25));
+        assert_eq!(half, 0.5f32);
+        let values: Vec<_> = interpolator.get_values(half).collect();
+        assert_eq!(values, vec![McUniformData::FogStart(5f32), McUniformData::FogShape(1)]);
+
+        // Late ticks do not overshoot
+        assert_eq!(interpolator.get_partial_tick_at(start + Duration::from_millis(80)), 1f32);
+    }
+
+    #[test]
+    fn missing_values_stop_changing() {
+        let mut interpolator = UniformInterpolator::default();
+        let start = Instant::now();
+
+        interpolator.on_tick_at(&[McUniformData::FogEnd(0f32)], start);
+        interpolator.on_tick_at(&[McUniformData::FogEnd(8f32)], start);
+        interpolator.on_tick_at(&[], start);
+
+        let values: Vec<_> = interpolator.get_values(0f32).collect();
+        assert_eq!(values, vec![McUniformData::FogEnd(8f32)]);
+    }
+
+    #[test]
+    fn game_time_wraps() {
+        let result = interpolate(&McUniformData::GameTime(0.9f32), &McUniformData::GameTime(0.1f32), 0.5f32);
+        if let McUniformData::GameTime(time) = result {
+            assert!(time.abs() < 1e-5 || (1f32 - time).abs() < 1e-5);
+        } else {
+            panic!()
+        }
+
+        let result = interpolate(&McUniformData::GameTime(0.2f32), &McUniformData::GameTime(0.4f32), 0.5f32);
+        if let McUniformData::GameTime(time) = result {
+            assert!((time - 0.3f32).abs() < 1e-5);
+        } else {
+            panic!()
+        }
+    }
+
+    #[test]
+    fn directions_stay_normalized() {
+        let result = interpolate(
+            &McUniformData::Light0Direction(Vec3f32::new(1f32, 0f32, 0f32)),
+            &McUniformData::Light0Direction(Vec3f32::new(0f32, 1f32, 0f32)),
+            0.5f32
+        );
+        if let McUniformData::Light0Direction(direction) = result {
+            assert!((direction.norm() - 1f32).abs() < 1e-5);
+        } else {
+            panic!()
+        }
+    }
+}
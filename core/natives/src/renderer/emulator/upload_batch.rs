@@ -0,0 +1,101 @@
+//! Batched submission of global object updates.
+//!
+//! Every global object update is a separate worker task. Pushing many small tasks, for example
+//! while loading a world, wakes the worker once per task. An [`UploadBatch`] collects updates and
+//! pushes all of them at once when it is submitted or dropped. The updates are executed in the
+//! order they were recorded and no task of another thread is executed in between.
+//!
+//! Staging memory is allocated while recording and only released once the batch has been
+//! submitted and executed. Waiting for the budget while the batch holds staging memory itself could
+//! only time out, so once a batch contains updates further allocations exceed the staging budget
+//! instead of blocking. Very large batches should therefore be split into multiple batches.
+
+use std::sync::Arc;
+
+use crate::renderer::emulator::{GlobalImage, GlobalMesh, MeshData};
+use crate::renderer::emulator::global_objects::{GlobalObjectCreateError, ImageData};
+use crate::renderer::emulator::mesh_compression::CompressedMeshData;
+use crate::renderer::emulator::quad_indices::QuadIndexBuffer;
+use crate::renderer::emulator::share::Share;
+use crate::renderer::emulator::worker::WorkerTask;
+
+/// Collects global object updates and submits them as a single batch.
+///
+/// Created by [`EmulatorRenderer::create_upload_batch`](super::EmulatorRenderer::create_upload_batch).
+pub struct UploadBatch {
+    share: Arc<Share>,
+    quad_indices: Arc<QuadIndexBuffer>,
+    tasks: Vec<WorkerTask>,
+}
+
+impl UploadBatch {
+    pub(super) fn new(share: Arc<Share>, quad_indices: Arc<QuadIndexBuffer>) -> Self {
+        Self {
+            share,
+            quad_indices,
+            tasks: Vec::new(),
+        }
+    }
+
+    /// See [`EmulatorRenderer::create_global_mesh`](super::EmulatorRenderer::create_global_mesh).
+    /// The mesh must not be drawn before the batch has been submitted.
+    pub fn create_global_mesh(&mut self, data: &MeshData<'_>) -> Arc<GlobalMesh> {
+        GlobalMesh::new_batched(self.share.clone(), data, &self.quad_indices, &mut self.tasks).unwrap()
+    }
+
+    /// See [`EmulatorRenderer::create_compressed_global_mesh`](super::EmulatorRenderer::create_compressed_global_mesh).
+    /// The mesh must not be drawn before the batch has been submitted.
+    pub fn create_compressed_global_mesh(&mut self, data: &CompressedMeshData<'_>) -> Result<Arc<GlobalMesh>, GlobalObjectCreateError> {
+        GlobalMesh::new_compressed_batched(self.share.clone(), data, &self.quad_indices, &mut self.tasks)
+    }
+
+    /// See [`GlobalMesh::fill`].
+    pub fn fill_mesh(&mut self, mesh: &GlobalMesh, offset: u64, size: u64, data: u32) {
+        mesh.fill_batched(offset, size, data, &mut self.tasks);
+    }
+
+    /// See [`GlobalMesh::update`].
+    pub fn update_mesh(&mut self, mesh: &GlobalMesh, offset: u64, data: &[u8]) {
+        mesh.update_batched(offset, data, &mut self.tasks);
+    }
+
+    /// See [`GlobalImage::update_regions`].
+    pub fn update_image_regions(&mut self, image: &GlobalImage, regions: &[ImageData<'_>]) {
+        let regions: Vec<_> = regions.iter().map(|region| (0, region)).collect();
+        self.update_image_mip_regions(image, &regions);
+    }
+
+    /// See [`GlobalImage::update_mip_regions`].
+    pub fn update_image_mip_regions(&mut self, image: &GlobalImage, regions: &[(u32, &ImageData<'_>)]) {
+        image.update_mip_regions_batched(regions, &mut self.tasks);
+    }
+
+    /// See [`GlobalImage::generate_mipmaps`].
+    pub fn generate_image_mipmaps(&mut self, image: &GlobalImage) {
+        image.generate_mipmaps_batched(u32::MAX, &mut self.tasks);
+    }
+
+    /// See [`GlobalImage::generate_mip_levels`].
+    pub fn generate_image_mip_levels(&mut self, image: &GlobalImage, level_count: u32) {
+        image.generate_mipmaps_batched(level_count, &mut self.tasks);
+    }
+
+    /// Returns the number of recorded tasks.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Submits all recorded updates. Equivalent to dropping the batch.
+    pub fn submit(self) {
+    }
+}
+
+impl Drop for UploadBatch {
+    fn drop(&mut self) {
+        self.share.push_tasks(std::mem::take(&mut self.tasks));
+    }
+}
@@ -0,0 +1,371 @@
+//! Re-encoding of vertex data into the smallest format a pipeline actually reads.
+//!
+//! Minecraft vertex formats frequently contain channels which are never read by the shader used to
+//! draw them and store normals and texture coordinates as full 32bit floats. The
+//! [`compact_vertex_data`] function drops all channels not contained in a [`VertexChannels`] set and
+//! packs the remaining ones into smaller formats where this is possible without visible precision
+//! loss:
+//! - Float normals are packed into [`vk::Format::R8G8B8A8_SNORM`].
+//! - Float colors are packed into [`vk::Format::R8G8B8A8_UNORM`].
+//! - Float texture coordinates are packed into [`vk::Format::R16G16_UNORM`] if all of them are
+//!   inside the `[0, 1]` range.
+//!
+//! All other channels are copied unmodified. Pipelines report the channels they read using
+//! [`EmulatorPipeline::get_used_vertex_channels`](super::pipeline::EmulatorPipeline::get_used_vertex_channels).
+//! Since the compacted data uses a different vertex format it must be drawn with a shader created
+//! using [`CompactedVertexData::vertex_format`].
+
+use std::ops::{BitOr, BitOrAssign};
+
+use ash::vk;
+
+use crate::renderer::emulator::MeshData;
+use crate::renderer::emulator::mc_shaders::{VertexFormat, VertexFormatEntry};
+
+/// A set of vertex channels of a [`VertexFormat`]. The position is always required and thus not
+/// part of the set.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct VertexChannels(u32);
+
+impl VertexChannels {
+    #[inline]
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    #[inline]
+    pub const fn all() -> Self {
+        Self(0b1111111)
+    }
+
+    #[inline]
+    pub const fn from_raw(raw: u32) -> Self {
+        Self(raw)
+    }
+
+    #[inline]
+    pub const fn as_raw(&self) -> u32 {
+        self.0
+    }
+
+    #[inline]
+    pub const fn contains(&self, other: &Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+
+    pub const NORMAL: Self = Self::from_raw(1u32);
+    pub const COLOR: Self = Self::from_raw(1u32 << 1);
+    pub const UV0: Self = Self::from_raw(1u32 << 2);
+    pub const UV1: Self = Self::from_raw(1u32 << 3);
+    pub const UV2: Self = Self::from_raw(1u32 << 4);
+    pub const JOINT_INDICES: Self = Self::from_raw(1u32 << 5);
+    pub const JOINT_WEIGHTS: Self = Self::from_raw(1u32 << 6);
+}
+
+impl BitOr for VertexChannels {
+    type Output = VertexChannels;
+
+    #[inline]
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for VertexChannels {
+    #[inline]
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = *self | rhs
+    }
+}
+
+/// Vertex data re-encoded by [`compact_vertex_data`].
+pub struct CompactedVertexData {
+    pub vertex_format: VertexFormat,
+    pub vertex_data: Vec<u8>,
+}
+
+impl CompactedVertexData {
+    /// Returns mesh data using the compacted vertices and the index data of the source mesh.
+    pub fn get_mesh_data<'a>(&'a self, source: &MeshData<'a>) -> MeshData<'a> {
+        MeshData {
+            vertex_data: &self.vertex_data,
+            index_data: source.index_data,
+            vertex_stride: self.vertex_format.stride,
+            index_count: source.index_count,
+            index_type: source.index_type,
+            primitive_topology: source.primitive_topology,
+        }
+    }
+}
+
+/// Re-encodes the vertices of a mesh such that only the specified channels remain and packs them
+/// into smaller formats where possible.
+///
+/// Returns [`None`] if the mesh cannot be compacted. This is the case if the compacted vertices
+/// would not be smaller than the source vertices or if a used channel has a format which is not
+/// supported.
+pub fn compact_vertex_data(data: &MeshData<'_>, format: &VertexFormat, channels: VertexChannels) -> Option<CompactedVertexData> {
+    if data.vertex_stride != format.stride || format.stride == 0 {
+        log::warn!("Mesh vertex stride {} does not match vertex format stride {}. Skipping compaction", data.vertex_stride, format.stride);
+        return None;
+    }
+    let vertex_count = data.vertex_data.len() / (format.stride as usize);
+
+    let mut plans = Vec::with_capacity(8);
+    plans.push(ChannelPlan::copy(format.position)?);
+    let optional = [
+        (format.normal, VertexChannels::NORMAL),
+        (format.color, VertexChannels::COLOR),
+        (format.uv0, VertexChannels::UV0),
+        (format.uv1, VertexChannels::UV1),
+        (format.uv2, VertexChannels::UV2),
+        (format.joint_indices, VertexChannels::JOINT_INDICES),
+        (format.joint_weights, VertexChannels::JOINT_WEIGHTS),
+    ];
+    for (entry, channel) in optional {
+        match entry {
+            Some(entry) if channels.contains(&channel) => {
+                plans.push(ChannelPlan::new(entry, channel, data.vertex_data, format.stride as usize, vertex_count)?);
+            }
+            _ => plans.push(ChannelPlan::dropped()),
+        }
+    }
+
+    // Keep every attribute 4 byte aligned
+    let mut stride = 0u32;
+    for plan in plans.iter_mut() {
+        if let Some(dst_format) = plan.dst_format {
+            plan.dst_offset = stride;
+            stride += align_4(get_attribute_size(dst_format)?);
+        }
+    }
+    if stride >= format.stride {
+        return None;
+    }
+
+    let mut vertex_data = vec![0u8; vertex_count * (stride as usize)];
+    for (src, dst) in data.vertex_data.chunks_exact(format.stride as usize).zip(vertex_data.chunks_exact_mut(stride as usize)) {
+        for plan in plans.iter() {
+            plan.encode(src, dst);
+        }
+    }
+
+    let entries: Vec<_> = plans.iter().map(ChannelPlan::get_entry).collect();
+    let vertex_format = VertexFormat {
+        stride,
+        position: entries[0].unwrap(),
+        normal: entries[1],
+        color: entries[2],
+        uv0: entries[3],
+        uv1: entries[4],
+        uv2: entries[5],
+        joint_indices: entries[6],
+        joint_weights: entries[7],
+    };
+
+    Some(CompactedVertexData {
+        vertex_format,
+        vertex_data,
+    })
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ChannelPlan {
+    src: Option<VertexFormatEntry>,
+    dst_format: Option<vk::Format>,
+    dst_offset: u32,
+}
+
+impl ChannelPlan {
+    fn dropped() -> Self {
+        Self {
+            src: None,
+            dst_format: None,
+            dst_offset: 0,
+        }
+    }
+
+    fn copy(src: VertexFormatEntry) -> Option<Self> {
+        if get_attribute_size(src.format).is_none() {
+            log::warn!("Vertex compaction does not support attribute format {:?}. Skipping compaction", src.format);
+            return None;
+        }
+
+        Some(Self {
+            src: Some(src),
+            dst_format: Some(src.format),
+            dst_offset: 0,
+        })
+    }
+
+    fn new(src: VertexFormatEntry, channel: VertexChannels, vertex_data: &[u8], stride: usize, vertex_count: usize) -> Option<Self> {
+        let dst_format = match (channel, src.format) {
+            (VertexChannels::NORMAL, vk::Format::R32G32B32_SFLOAT) |
+            (VertexChannels::NORMAL, vk::Format::R32G32B32A32_SFLOAT) => vk::Format::R8G8B8A8_SNORM,
+            (VertexChannels::COLOR, vk::Format::R32G32B32A32_SFLOAT) => vk::Format::R8G8B8A8_UNORM,
+            (VertexChannels::UV0, vk::Format::R32G32_SFLOAT) if is_normalized(src, vertex_data, stride, vertex_count) => vk::Format::R16G16_UNORM,
+            _ => return Self::copy(src),
+        };
+
+        Some(Self {
+            src: Some(src),
+            dst_format: Some(dst_format),
+            dst_offset: 0,
+        })
+    }
+
+    fn get_entry(&self) -> Option<VertexFormatEntry> {
+        self.dst_format.map(|format| VertexFormatEntry {
+            offset: self.dst_offset,
+            format,
+        })
+    }
+
+    fn encode(&self, src: &[u8], dst: &mut [u8]) {
+        let (src_entry, dst_format) = match (self.src, self.dst_format) {
+            (Some(src), Some(dst)) => (src, dst),
+            _ => return,
+        };
+        let src = &src[(src_entry.offset as usize)..];
+        let dst = &mut dst[(self.dst_offset as usize)..];
+
+        if src_entry.format == dst_format {
+            let size = get_attribute_size(dst_format).unwrap() as usize;
+            dst[..size].copy_from_slice(&src[..size]);
+            return;
+        }
+
+        let components = match src_entry.format {
+            vk::Format::R32G32_SFLOAT => 2,
+            vk::Format::R32G32B32_SFLOAT => 3,
+            _ => 4,
+        };
+        let mut values = [0f32; 4];
+        for (index, value) in values.iter_mut().take(components).enumerate() {
+            *value = read_f32(src, index);
+        }
+
+        match dst_format {
+            vk::Format::R8G8B8A8_SNORM => {
+                for (index, value) in values.iter().enumerate() {
+                    dst[index] = ((value.clamp(-1f32, 1f32) * 127f32).round() as i8) as u8;
+                }
+            }
+            vk::Format::R8G8B8A8_UNORM => {
+                for (index, value) in values.iter().enumerate() {
+                    dst[index] = (value.clamp(0f32, 1f32) * 255f32).round() as u8;
+                }
+            }
+            vk::Format::R16G16_UNORM => {
+                for (index, value) in values.iter().take(2).enumerate() {
+                    let packed = (value.clamp(0f32, 1f32) * 65535f32).round() as u16;
+                    dst[(index * 2)..((index + 1) * 2)].copy_from_slice(&packed.to_ne_bytes());
+                }
+            }
+            _ => {
+                log::error!("Invalid compacted vertex format {:?}", dst_format);
+                panic!()
+            }
+        }
+    }
+}
+
+/// Returns true if all values of a 2 component float attribute are inside the `[0, 1]` range.
+fn is_normalized(entry: VertexFormatEntry, vertex_data: &[u8], stride: usize, vertex_count: usize) -> bool {
+    (0..vertex_count).all(|vertex| {
+        let src = &vertex_data[(vertex * stride + (entry.offset as usize))..];
+        (0..2).all(|index| (0f32..=1f32).contains(&read_f32(src, index)))
+    })
+}
+
+fn read_f32(src: &[u8], index: usize) -> f32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&src[(index * 4)..((index + 1) * 4)]);
+    f32::from_ne_bytes(bytes)
+}
+
+fn align_4(size: u32) -> u32 {
+    (size + 3) & !3
+}
+
+/// Returns the size in bytes of a vertex attribute format or [`None`] if the format is not
+/// supported by the compaction.
+fn get_attribute_size(format: vk::Format) -> Option<u32> {
+    match format {
+        vk::Format::R8G8B8_SNORM |
+        vk::Format::R8G8B8_UNORM => Some(3),
+        vk::Format::R8G8B8A8_UNORM |
+        vk::Format::R8G8B8A8_SNORM |
+        vk::Format::R8G8B8A8_UINT |
+        vk::Format::R16G16_SINT |
+        vk::Format::R16G16_UINT |
+        vk::Format::R16G16_SSCALED |
+        vk::Format::R16G16_USCALED |
+        vk::Format::R16G16_UNORM |
+        vk::Format::R16G16_SFLOAT |
+        vk::Format::R32_SFLOAT => Some(4),
+        vk::Format::R16G16B16A16_UINT |
+        vk::Format::R16G16B16A16_UNORM |
+        vk::Format::R32G32_SFLOAT |
+        vk::Format::R32G32_SINT => Some(8),
+        vk::Format::R32G32B32_SFLOAT => Some(12),
+        vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytemuck::cast_slice;
+
+    use super::*;
+
+    const FORMAT: VertexFormat = VertexFormat {
+        stride: 36,
+        position: VertexFormatEntry { offset: 0, format: vk::Format::R32G32B32_SFLOAT },
+        normal: Some(VertexFormatEntry { offset: 12, format: vk::Format::R32G32B32_SFLOAT }),
+        color: None,
+        uv0: Some(VertexFormatEntry { offset: 24, format: vk::Format::R32G32_SFLOAT }),
+        uv1: Some(VertexFormatEntry { offset: 32, format: vk::Format::R16G16_SINT }),
+        uv2: None,
+        joint_indices: None,
+        joint_weights: None,
+    };
+
+    fn vertex(uv: [f32; 2]) -> [f32; 9] {
+        [1f32, 2f32, 3f32, 0f32, 1f32, 0f32, uv[0], uv[1], 0f32]
+    }
+
+    #[test]
+    fn drops_and_packs_channels() {
+        let vertices: Vec<f32> = [vertex([0f32, 1f32]), vertex([0.5f32, 0.25f32])].concat();
+        let data = MeshData::new_quads(cast_slice(&vertices), FORMAT.stride);
+
+        let compacted = compact_vertex_data(&data, &FORMAT, VertexChannels::NORMAL | VertexChannels::UV0).unwrap();
+        let format = &compacted.vertex_format;
+        assert_eq!(format.stride, 20);
+        assert_eq!(format.normal.unwrap().format, vk::Format::R8G8B8A8_SNORM);
+        assert_eq!(format.uv0.unwrap().format, vk::Format::R16G16_UNORM);
+        assert!(format.uv1.is_none());
+        assert_eq!(compacted.vertex_data.len(), 40);
+
+        let second = &compacted.vertex_data[20..];
+        assert_eq!(second[0..4], 1f32.to_ne_bytes());
+        assert_eq!(second[12..16], [0, 127, 0, 0]);
+        assert_eq!(second[16..18], 32768u16.to_ne_bytes());
+    }
+
+    #[test]
+    fn keeps_out_of_range_uvs() {
+        let vertices: Vec<f32> = [vertex([0f32, 1f32]), vertex([2f32, 0f32])].concat();
+        let data = MeshData::new_quads(cast_slice(&vertices), FORMAT.stride);
+
+        let compacted = compact_vertex_data(&data, &FORMAT, VertexChannels::UV0).unwrap();
+        assert_eq!(compacted.vertex_format.uv0.unwrap().format, vk::Format::R32G32_SFLOAT);
+        assert_eq!(compacted.vertex_format.stride, 20);
+
+        let compacted = compact_vertex_data(&data, &FORMAT, VertexChannels::all()).unwrap();
+        assert_eq!(compacted.vertex_format.stride, 28);
+        assert_eq!(compacted.vertex_format.uv1.unwrap().offset, 24);
+    }
+}
@@ -0,0 +1,383 @@
+//! Cave culling of chunk sections using the visibility graph provided by the host.
+//!
+//! The host supplies the face connectivity of every loaded 16x16x16 chunk section (the
+//! `VisibilitySet` computed by vanilla when a section is meshed) to a [`SectionVisibilityGraph`].
+//! [`SectionVisibilityGraph::traverse`] then performs the same breadth first search as vanilla
+//! starting from the section containing the camera and returns the visible sections ordered front
+//! to back, so the host does not need to walk the graph every frame.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::prelude::*;
+
+/// The width, height and depth of a chunk section in blocks.
+pub const SECTION_SIZE: i32 = 16;
+
+/// A face of a chunk section. The order matches the ordinal of vanilla's `Direction`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum SectionFace {
+    Down,
+    Up,
+    North,
+    South,
+    West,
+    East,
+}
+
+impl SectionFace {
+    pub const ALL: [SectionFace; 6] = [Self::Down, Self::Up, Self::North, Self::South, Self::West, Self::East];
+
+    pub const fn get_index(&self) -> u32 {
+        *self as u32
+    }
+
+    pub const fn get_opposite(&self) -> Self {
+        match self {
+            Self::Down => Self::Up,
+            Self::Up => Self::Down,
+            Self::North => Self::South,
+            Self::South => Self::North,
+            Self::West => Self::East,
+            Self::East => Self::West,
+        }
+    }
+
+    /// Returns the offset to the neighbouring section across this face.
+    pub fn get_offset(&self) -> Vec3i32 {
+        match self {
+            Self::Down => Vec3i32::new(0, -1, 0),
+            Self::Up => Vec3i32::new(0, 1, 0),
+            Self::North => Vec3i32::new(0, 0, -1),
+            Self::South => Vec3i32::new(0, 0, 1),
+            Self::West => Vec3i32::new(-1, 0, 0),
+            Self::East => Vec3i32::new(1, 0, 0),
+        }
+    }
+
+    const fn get_bit(&self) -> u8 {
+        1u8 << (*self as u8)
+    }
+}
+
+/// The pairs of faces of a section which are connected through non opaque blocks.
+///
+/// Uses the same bit layout as vanilla's `VisibilitySet` so the host can pass it unmodified. The
+/// connection from face `a` to face `b` is stored in bit `a + b * 6`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct FaceConnectivity(u64);
+
+impl FaceConnectivity {
+    /// No faces are connected. Used for fully opaque sections.
+    pub const NONE: Self = Self(0);
+
+    /// All faces are connected. Used for empty sections.
+    pub const ALL: Self = Self((1u64 << 36) - 1);
+
+    pub const fn from_raw(raw: u64) -> Self {
+        Self(raw & Self::ALL.0)
+    }
+
+    pub const fn get_raw(&self) -> u64 {
+        self.0
+    }
+
+    /// Connects the two faces in both directions.
+    pub fn set_connected(&mut self, a: SectionFace, b: SectionFace) {
+        self.0 |= 1u64 << (a.get_index() + b.get_index() * 6);
+        self.0 |= 1u64 << (b.get_index() + a.get_index() * 6);
+    }
+
+    pub const fn is_connected(&self, a: SectionFace, b: SectionFace) -> bool {
+        (self.0 & (1u64 << (a.get_index() + b.get_index() * 6))) != 0
+    }
+}
+
+/// The face connectivity of all loaded chunk sections. Sections are identified by their section
+/// coordinates, i.e. the block position divided by [`SECTION_SIZE`].
+pub struct SectionVisibilityGraph {
+    sections: HashMap<Vec3i32, FaceConnectivity>,
+}
+
+impl SectionVisibilityGraph {
+    pub fn new() -> Self {
+        Self {
+            sections: HashMap::new(),
+        }
+    }
+
+    /// Adds a section or replaces the connectivity of an existing one.
+    pub fn set_section(&mut self, position: Vec3i32, connectivity: FaceConnectivity) {
+        self.sections.insert(position, connectivity);
+    }
+
+    /// Removes a section, for example because its chunk has been unloaded. Unknown sections are
+    /// never traversed.
+    pub fn remove_section(&mut self, position: &Vec3i32) {
+        self.sections.remove(position);
+    }
+
+    pub fn clear(&mut self) {
+        self.sections.clear();
+    }
+
+    pub fn get_section(&self, position: &Vec3i32) -> Option<FaceConnectivity> {
+        self.sections.get(position).copied()
+    }
+
+    pub fn get_section_count(&self) -> usize {
+        self.sections.len()
+    }
+
+    /// Returns the sections visible from the camera ordered front to back.
+    ///
+    /// The `view_projection` matrix must transform positions relative to the camera position
+    /// into clip space and is used to skip sections outside of the view frustum. Sections further
+    /// than `max_distance` sections away from the camera horizontally are not traversed.
+    ///
+    /// If the camera is above or below all sections of the graph the traversal starts from all
+    /// sections of the top or bottom layer like vanilla does.
+    pub fn traverse(&self, camera_position: &Vec3f32, view_projection: &Mat4f32, max_distance: u32) -> Vec<Vec3i32> {
+        let camera_section = get_section_position(camera_position);
+        self.traverse_with(camera_section, max_distance, |section| {
+            is_section_in_frustum(section, camera_position, view_projection)
+        })
+    }
+
+    fn traverse_with<F: FnMut(Vec3i32) -> bool>(&self, camera_section: Vec3i32, max_distance: u32, mut is_visible: F) -> Vec<Vec3i32> {
+        let mut result = Vec::new();
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+
+        let is_in_range = |section: &Vec3i32| {
+            (section[0] - camera_section[0]).unsigned_abs() <= max_distance &&
+                (section[2] - camera_section[2]).unsigned_abs() <= max_distance
+        };
+
+        let (min_y, max_y) = self.sections.keys().fold((i32::MAX, i32::MIN), |(min, max), section| {
+            (min.min(section[1]), max.max(section[1]))
+        });
+
+        if !self.sections.is_empty() && (camera_section[1] > max_y || camera_section[1] < min_y) {
+            let layer = if camera_section[1] > max_y { max_y } else { min_y };
+
+            let mut seeds: Vec<_> = self.sections.keys()
+                .filter(|section| section[1] == layer && is_in_range(section))
+                .copied()
+                .collect();
+            seeds.sort_by_key(|section| {
+                let diff = section - camera_section;
+                (diff[0] * diff[0] + diff[2] * diff[2], section[0], section[2])
+            });
+
+            for section in seeds {
+                if is_visible(section) {
+                    visited.insert(section);
+                    result.push(section);
+                    queue.push_back(Node { position: section, entered: None, sources: 0 });
+                }
+            }
+        } else {
+            // Sections which are not loaded yet are treated as empty so the camera can still see
+            // its surroundings.
+            visited.insert(camera_section);
+            if self.sections.contains_key(&camera_section) {
+                result.push(camera_section);
+            }
+            queue.push_back(Node { position: camera_section, entered: None, sources: 0 });
+        }
+
+        while let Some(node) = queue.pop_front() {
+            let connectivity = self.sections.get(&node.position).copied().unwrap_or(FaceConnectivity::ALL);
+
+            for face in SectionFace::ALL {
+                // Never walk back towards the camera
+                if (node.sources & face.get_opposite().get_bit()) != 0 {
+                    continue;
+                }
+                if let Some(entered) = node.entered {
+                    if !connectivity.is_connected(entered.get_opposite(), face) {
+                        continue;
+                    }
+                }
+
+                let neighbour = node.position + face.get_offset();
+                if !self.sections.contains_key(&neighbour) || !is_in_range(&neighbour) || visited.contains(&neighbour) {
+                    continue;
+                }
+                if !is_visible(neighbour) {
+                    continue;
+                }
+
+                visited.insert(neighbour);
+                result.push(neighbour);
+                queue.push_back(Node {
+                    position: neighbour,
+                    entered: Some(face),
+                    sources: node.sources | face.get_bit(),
+                });
+            }
+        }
+
+        result
+    }
+}
+
+impl Default for SectionVisibilityGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Node {
+    position: Vec3i32,
+
+    /// The direction of travel used to enter this section.
+    entered: Option<SectionFace>,
+
+    /// All directions of travel used on the path from the camera.
+    sources: u8,
+}
+
+/// Returns the section containing a block position.
+pub fn get_section_position(position: &Vec3f32) -> Vec3i32 {
+    Vec3i32::new(
+        (position[0] / SECTION_SIZE as f32).floor() as i32,
+        (position[1] / SECTION_SIZE as f32).floor() as i32,
+        (position[2] / SECTION_SIZE as f32).floor() as i32,
+    )
+}
+
+/// Tests the bounding box of a section against the view frustum. The depth range is tested
+/// against `-w..w` so both OpenGL and vulkan style projections are accepted.
+fn is_section_in_frustum(section: Vec3i32, camera_position: &Vec3f32, view_projection: &Mat4f32) -> bool {
+    let min = section.cast::<f32>() * (SECTION_SIZE as f32) - camera_position;
+    let size = SECTION_SIZE as f32;
+
+    let mut outside = [true; 6];
+    for corner in 0..8 {
+        let offset = Vec3f32::new(
+            if (corner & 1) != 0 { size } else { 0f32 },
+            if (corner & 2) != 0 { size } else { 0f32 },
+            if (corner & 4) != 0 { size } else { 0f32 },
+        );
+        let position = min + offset;
+        let clip = view_projection * Vec4f32::new(position[0], position[1], position[2], 1f32);
+
+        outside[0] &= clip[0] < -clip[3];
+        outside[1] &= clip[0] > clip[3];
+        outside[2] &= clip[1] < -clip[3];
+        outside[3] &= clip[1] > clip[3];
+        outside[4] &= clip[2] < -clip[3];
+        outside[5] &= clip[2] > clip[3];
+    }
+
+    !outside.iter().any(|outside| *outside)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(graph: &mut SectionVisibilityGraph, length: i32, connectivity: FaceConnectivity) {
+        for x in 0..length {
+            graph.set_section(Vec3i32::new(x, 0, 0), connectivity);
+        }
+    }
+
+    #[test]
+    fn connectivity_bits() {
+        let mut connectivity = FaceConnectivity::NONE;
+        connectivity.set_connected(SectionFace::West, SectionFace::Up);
+
+        assert!(connectivity.is_connected(SectionFace::West, SectionFace::Up));
+        assert!(connectivity.is_connected(SectionFace::Up, SectionFace::West));
+        assert!(!connectivity.is_connected(SectionFace::West, SectionFace::East));
+        assert_eq!(connectivity.get_raw(), (1u64 << (4 + 6)) | (1u64 << (1 + 4 * 6)));
+
+        for a in SectionFace::ALL {
+            for b in SectionFace::ALL {
+                assert!(FaceConnectivity::ALL.is_connected(a, b));
+            }
+        }
+    }
+
+    #[test]
+    fn open_sections_are_visible() {
+        let mut graph = SectionVisibilityGraph::new();
+        line(&mut graph, 4, FaceConnectivity::ALL);
+
+        let visible = graph.traverse_with(Vec3i32::new(0, 0, 0), 16, |_| true);
+        assert_eq!(visible, (0..4).map(|x| Vec3i32::new(x, 0, 0)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn opaque_section_blocks_traversal() {
+        let mut graph = SectionVisibilityGraph::new();
+        line(&mut graph, 4, FaceConnectivity::ALL);
+        graph.set_section(Vec3i32::new(1, 0, 0), FaceConnectivity::NONE);
+
+        // The opaque section itself is visible but nothing behind it
+        let visible = graph.traverse_with(Vec3i32::new(0, 0, 0), 16, |_| true);
+        assert_eq!(visible, vec![Vec3i32::new(0, 0, 0), Vec3i32::new(1, 0, 0)]);
+    }
+
+    #[test]
+    fn traversal_does_not_turn_back() {
+        // A U shaped tunnel starting at the camera. Sections on the way back towards the camera
+        // are not visited.
+        let mut graph = SectionVisibilityGraph::new();
+        line(&mut graph, 3, FaceConnectivity::ALL);
+        graph.set_section(Vec3i32::new(2, 0, 1), FaceConnectivity::ALL);
+        graph.set_section(Vec3i32::new(2, 0, 2), FaceConnectivity::ALL);
+        graph.set_section(Vec3i32::new(1, 0, 2), FaceConnectivity::ALL);
+        graph.set_section(Vec3i32::new(0, 0, 2), FaceConnectivity::ALL);
+
+        let visible = graph.traverse_with(Vec3i32::new(0, 0, 0), 16, |_| true);
+        assert!(visible.contains(&Vec3i32::new(2, 0, 2)));
+        assert!(!visible.contains(&Vec3i32::new(1, 0, 2)));
+        assert!(!visible.contains(&Vec3i32::new(0, 0, 2)));
+    }
+
+    #[test]
+    fn distance_and_frustum_limit() {
+        let mut graph = SectionVisibilityGraph::new();
+        line(&mut graph, 8, FaceConnectivity::ALL);
+
+        let visible = graph.traverse_with(Vec3i32::new(0, 0, 0), 2, |_| true);
+        assert_eq!(visible.len(), 3);
+
+        let visible = graph.traverse_with(Vec3i32::new(0, 0, 0), 16, |section| section[0] < 5);
+        assert_eq!(visible.len(), 5);
+    }
+
+    #[test]
+    fn camera_above_graph() {
+        let mut graph = SectionVisibilityGraph::new();
+        for x in -1..=1 {
+            graph.set_section(Vec3i32::new(x, 0, 0), FaceConnectivity::NONE);
+            graph.set_section(Vec3i32::new(x, -1, 0), FaceConnectivity::ALL);
+        }
+
+        // The traversal starts from the top layer sorted by distance and continues downwards
+        let visible = graph.traverse_with(Vec3i32::new(0, 10, 0), 16, |_| true);
+        assert_eq!(&visible[0..3], &[Vec3i32::new(0, 0, 0), Vec3i32::new(-1, 0, 0), Vec3i32::new(1, 0, 0)]);
+        assert_eq!(visible.len(), 6);
+    }
+
+    #[test]
+    fn section_position() {
+        assert_eq!(get_section_position(&Vec3f32::new(15.9, 16.0, -0.1)), Vec3i32::new(0, 1, -1));
+    }
+
+    #[test]
+    fn frustum_culling() {
+        // Orthographic projection covering -16..16 around the camera
+        let projection = Mat4f32::new_nonuniform_scaling(&Vec3f32::new(1f32 / 16f32, 1f32 / 16f32, 1f32 / 16f32));
+        let camera = Vec3f32::new(8f32, 8f32, 8f32);
+
+        assert!(is_section_in_frustum(Vec3i32::new(0, 0, 0), &camera, &projection));
+        assert!(is_section_in_frustum(Vec3i32::new(1, 0, 0), &camera, &projection));
+        assert!(!is_section_in_frustum(Vec3i32::new(2, 0, 0), &camera, &projection));
+        assert!(!is_section_in_frustum(Vec3i32::new(0, -3, 0), &camera, &projection));
+    }
+}
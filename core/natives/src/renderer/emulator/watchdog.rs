@@ -0,0 +1,123 @@
+//! Detection of stalls of the emulator worker.
+//!
+//! The worker reports its progress to a [`WorkerProgress`] instance. A separate watchdog thread
+//! periodically checks if the worker has pending work but has not made any progress for
+//! [`STALL_TIMEOUT`] and if so logs the state of the worker to help diagnose the stall.
+
+use std::sync::{Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use ash::vk;
+use ash::vk::Handle;
+
+use crate::renderer::emulator::pass::PassId;
+use crate::renderer::emulator::share::Share;
+
+/// The time without progress after which the worker is considered stalled.
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The interval in which the watchdog checks the worker progress.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(super) struct WorkerProgress {
+    state: Mutex<ProgressState>,
+}
+
+struct ProgressState {
+    last_progress: Instant,
+    current_task: Option<&'static str>,
+    last_submitted_pass: Option<PassId>,
+    last_submitted_fence: vk::Fence,
+    last_completed_pass: Option<PassId>,
+    in_flight_passes: usize,
+    stall_reported: bool,
+}
+
+impl WorkerProgress {
+    pub(super) fn new() -> Self {
+        Self {
+            state: Mutex::new(ProgressState {
+                last_progress: Instant::now(),
+                current_task: None,
+                last_submitted_pass: None,
+                last_submitted_fence: vk::Fence::null(),
+                last_completed_pass: None,
+                in_flight_passes: 0,
+                stall_reported: false,
+            })
+        }
+    }
+
+    /// Called by the worker before it starts processing a task.
+    pub(super) fn on_task_started(&self, task: &'static str) {
+        let mut state = self.state.lock().unwrap();
+        state.current_task = Some(task);
+        state.mark_progress();
+    }
+
+    /// Called by the worker after it finished processing a task.
+    pub(super) fn on_task_finished(&self) {
+        self.state.lock().unwrap().current_task = None;
+    }
+
+    pub(super) fn on_pass_submitted(&self, pass_id: PassId, fence: vk::Fence, in_flight_passes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.last_submitted_pass = Some(pass_id);
+        state.last_submitted_fence = fence;
+        state.in_flight_passes = in_flight_passes;
+        state.mark_progress();
+    }
+
+    pub(super) fn on_passes_completed(&self, last_completed_pass: Option<PassId>, in_flight_passes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.last_completed_pass = last_completed_pass;
+        state.in_flight_passes = in_flight_passes;
+        state.mark_progress();
+    }
+}
+
+impl ProgressState {
+    fn mark_progress(&mut self) {
+        if self.stall_reported {
+            log::info!("Emulator worker resumed after a stall of {:?}", self.last_progress.elapsed());
+            self.stall_reported = false;
+        }
+        self.last_progress = Instant::now();
+    }
+}
+
+/// Runs the watchdog until the share is dropped.
+pub(super) fn run_watchdog(share: Weak<Share>) {
+    loop {
+        std::thread::sleep(CHECK_INTERVAL);
+
+        let share = match share.upgrade() {
+            Some(share) => share,
+            None => return,
+        };
+
+        let queue_depth = share.get_queue_depth();
+        let mut state = share.get_progress().state.lock().unwrap();
+
+        let has_work = queue_depth > 0 || state.in_flight_passes > 0 || state.current_task.is_some();
+        let elapsed = state.last_progress.elapsed();
+        if !has_work || state.stall_reported || elapsed < STALL_TIMEOUT {
+            continue;
+        }
+        state.stall_reported = true;
+
+        log::error!(
+            "Emulator worker has not made progress for {:?}. \
+            Current task: {:?}, queued tasks: {}, recording pass: {:?}, \
+            last submitted pass: {:?} (fence {:#x}), last completed pass: {:?}, in flight passes: {}",
+            elapsed,
+            state.current_task,
+            queue_depth,
+            share.get_current_pass_id(),
+            state.last_submitted_pass.map(|id| id.get_raw()),
+            state.last_submitted_fence.as_raw(),
+            state.last_completed_pass.map(|id| id.get_raw()),
+            state.in_flight_passes
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Rain and snow rendering mirroring minecrafts `LevelRenderer::renderSnowAndRain`.
+//!
+//! The host describes which columns around the camera receive precipitation and how intense it is.
+//! A [`Weather`] then generates one camera facing quad per column with the same texture scrolling
+//! and distance based fading used by vanilla. The quads are drawn using render layers which should
+//! enable depth testing, translucent blending and disable culling.
+
+use ash::vk;
+use bytemuck::{cast_slice, Pod, Zeroable};
+
+use crate::prelude::*;
+use crate::renderer::emulator::MeshData;
+use crate::renderer::emulator::mc_shaders::{VertexFormat, VertexFormatEntry};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Precipitation {
+    Rain,
+    Snow,
+}
+
+/// A column around the camera which receives precipitation.
+#[derive(Copy, Clone, Debug)]
+pub struct WeatherColumn {
+    pub precipitation: Precipitation,
+
+    /// The y coordinate of the highest motion blocking block in the column. Precipitation is not
+    /// rendered below it.
+    pub height: i32,
+
+    /// The packed block and sky light used for the column.
+    pub light: [i16; 2],
+}
+
+/// Per frame parameters provided by the host.
+#[derive(Copy, Clone, Debug)]
+pub struct WeatherParams {
+    /// The camera position in world space. The generated vertices are relative to it.
+    pub camera_position: Vec3f32,
+
+    /// The number of columns rendered in each direction around the camera. Vanilla uses 10 with
+    /// fancy graphics and 5 otherwise.
+    pub radius: u32,
+
+    /// The rain level in the range 0 to 1.
+    pub intensity: f32,
+
+    /// The number of ticks passed since the level was loaded.
+    pub ticks: u32,
+    pub partial_tick: f32,
+}
+
+/// The precipitation quads for a single frame.
+pub struct Weather {
+    rain: Vec<WeatherVertex>,
+    snow: Vec<WeatherVertex>,
+}
+
+impl Weather {
+    pub const VERTEX_FORMAT: VertexFormat = VertexFormat {
+        stride: std::mem::size_of::<WeatherVertex>() as u32,
+        position: VertexFormatEntry { offset: 0, format: vk::Format::R32G32B32_SFLOAT },
+        normal: None,
+        color: Some(VertexFormatEntry { offset: 20, format: vk::Format::R8G8B8A8_UNORM }),
+        uv0: Some(VertexFormatEntry { offset: 12, format: vk::Format::R32G32_SFLOAT }),
+        uv1: None,
+        uv2: Some(VertexFormatEntry { offset: 24, format: vk::Format::R16G16_SINT }),
+        joint_indices: None,
+        joint_weights: None,
+    };
+
+    /// Generates the quads for all columns around the camera. The function is called with the x
+    /// and z coordinates of every column and returns [`None`] if the column does not receive any
+    /// precipitation.
+    pub fn build<F>(params: &WeatherParams, mut column_fn: F) -> Self where F: FnMut(i32, i32) -> Option<WeatherColumn> {
+        let mut weather = Self {
+            rain: Vec::new(),
+            snow: Vec::new(),
+        };
+        if params.intensity <= 0f32 {
+            return weather;
+        }
+
+        let radius = params.radius as i32;
+        let camera = &params.camera_position;
+        let camera_x = camera[0].floor() as i32;
+        let camera_y = camera[1].floor() as i32;
+        let camera_z = camera[2].floor() as i32;
+
+        for z in (camera_z - radius)..=(camera_z + radius) {
+            for x in (camera_x - radius)..=(camera_x + radius) {
+                if let Some(column) = column_fn(x, z) {
+                    weather.add_column(params, x, z, camera_y, &column);
+                }
+            }
+        }
+
+        weather
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rain.is_empty() && self.snow.is_empty()
+    }
+
+    pub(super) fn get_rain_mesh_data(&self) -> Option<MeshData<'_>> {
+        Self::make_mesh_data(&self.rain)
+    }
+
+    pub(super) fn get_snow_mesh_data(&self) -> Option<MeshData<'_>> {
+        Self::make_mesh_data(&self.snow)
+    }
+
+    fn make_mesh_data(vertices: &[WeatherVertex]) -> Option<MeshData<'_>> {
+        if vertices.is_empty() {
+            None
+        } else {
+            Some(MeshData::new_quads(cast_slice(vertices), Self::VERTEX_FORMAT.stride))
+        }
+    }
+
+    fn add_column(&mut self, params: &WeatherParams, x: i32, z: i32, camera_y: i32, column: &WeatherColumn) {
+        let radius = params.radius as i32;
+        let min_y = column.height.max(camera_y - radius);
+        let max_y = column.height.max(camera_y + radius);
+        if min_y == max_y {
+            return;
+        }
+
+        let camera = &params.camera_position;
+        let center_x = (x as f32) + 0.5f32 - camera[0];
+        let center_z = (z as f32) + 0.5f32 - camera[2];
+
+        // The quads always face the column the camera is in
+        let offset_x = (x - camera[0].floor() as i32) as f32;
+        let offset_z = (z - camera[2].floor() as i32) as f32;
+        let offset_length = (offset_x * offset_x + offset_z * offset_z).sqrt();
+        let (half_x, half_z) = if offset_length == 0f32 {
+            (0.5f32, 0f32)
+        } else {
+            (-offset_z / offset_length * 0.5f32, offset_x / offset_length * 0.5f32)
+        };
+
+        let distance = (center_x * center_x + center_z * center_z).sqrt() / (params.radius.max(1) as f32);
+        let seed = column_seed(x, z);
+        let (alpha, u_offset, v_offset, vertices) = match column.precipitation {
+            Precipitation::Rain => {
+                let phase = (params.ticks.wrapping_add(seed as u32) & 31) as f32;
+                let v_offset = -(phase + params.partial_tick) / 32f32 * (3f32 + column_random(seed, 0));
+                (((1f32 - distance * distance) * 0.5f32 + 0.5f32) * params.intensity, 0f32, v_offset, &mut self.rain)
+            }
+            Precipitation::Snow => {
+                let time = (params.ticks as f32) + params.partial_tick;
+                let v_offset = -(((params.ticks & 511) as f32) + params.partial_tick) / 512f32;
+                let u_offset = column_random(seed, 0) + time * 0.01f32 * column_gaussian(seed, 1);
+                let v_drift = column_random(seed, 3) + time * 0.001f32 * column_gaussian(seed, 4);
+                (((1f32 - distance * distance) * 0.3f32 + 0.5f32) * params.intensity, u_offset, v_offset + v_drift, &mut self.snow)
+            }
+        };
+
+        let color = [255, 255, 255, (alpha.clamp(0f32, 1f32) * 255f32).round() as u8];
+        let top = (max_y as f32) - camera[1];
+        let bottom = (min_y as f32) - camera[1];
+        let v_top = (min_y as f32) * 0.25f32 + v_offset;
+        let v_bottom = (max_y as f32) * 0.25f32 + v_offset;
+
+        let vertex = |dir: f32, y: f32, u: f32, v: f32| WeatherVertex {
+            position: [center_x + half_x * dir, y, center_z + half_z * dir],
+            uv0: [u + u_offset, v],
+            color,
+            uv2: column.light,
+        };
+        vertices.extend_from_slice(&[
+            vertex(-1f32, top, 0f32, v_top),
+            vertex(1f32, top, 1f32, v_top),
+            vertex(1f32, bottom, 1f32, v_bottom),
+            vertex(-1f32, bottom, 0f32, v_bottom),
+        ]);
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct WeatherVertex {
+    position: [f32; 3],
+    uv0: [f32; 2],
+    color: [u8; 4],
+    uv2: [i16; 2],
+}
+const_assert_eq!(std::mem::size_of::<WeatherVertex>(), 28);
+
+unsafe impl Zeroable for WeatherVertex {}
+unsafe impl Pod for WeatherVertex {}
+
+/// Same seed as used by vanilla so that the columns keep their animation speed across frames.
+fn column_seed(x: i32, z: i32) -> i32 {
+    x.wrapping_mul(x).wrapping_mul(3121)
+        .wrapping_add(x.wrapping_mul(45238971))
+        .wrapping_add(z.wrapping_mul(z).wrapping_mul(418711))
+        .wrapping_add(z.wrapping_mul(13761))
+}
+
+/// Returns a stable pseudo random value in the range 0 to 1 for the column.
+fn column_random(seed: i32, index: u32) -> f32 {
+    let mut bytes = [0u8; 8];
+    bytes[0..4].copy_from_slice(&seed.to_le_bytes());
+    bytes[4..8].copy_from_slice(&index.to_le_bytes());
+    let hash = xxhash_rust::xxh3::xxh3_64(&bytes);
+    ((hash >> 40) as f32) / ((1u64 << 24) as f32)
+}
+
+/// Returns a stable pseudo random normally distributed value for the column. Uses the values at
+/// index and index + 1.
+fn column_gaussian(seed: i32, index: u32) -> f32 {
+    let u1 = column_random(seed, index).max(f32::EPSILON);
+    let u2 = column_random(seed, index + 1);
+    (-2f32 * u1.ln()).sqrt() * (2f32 * std::f32::consts::PI * u2).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(intensity: f32) -> WeatherParams {
+        WeatherParams {
+            camera_position: Vec3f32::new(0.5f32, 64f32, 0.5f32),
+            radius: 2,
+            intensity,
+            ticks: 100,
+            partial_tick: 0.5f32,
+        }
+    }
+
+    #[test]
+    fn columns() {
+        let column = |x: i32, _: i32| Some(WeatherColumn {
+            precipitation: if x < 0 { Precipitation::Snow } else { Precipitation::Rain },
+            height: 60,
+            light: [240, 240],
+        });
+
+        let weather = Weather::build(&params(1f32), column);
+        assert_eq!(weather.snow.len(), 2 * 5 * 4);
+        assert_eq!(weather.rain.len(), 3 * 5 * 4);
+        assert_eq!(weather.get_rain_mesh_data().unwrap().index_count, 3 * 5 * 6);
+
+        for vertex in weather.rain.iter().chain(weather.snow.iter()) {
+            assert!(vertex.position[1] == 2f32 || vertex.position[1] == -2f32);
+            if vertex.position[0].abs() <= 1f32 && vertex.position[2].abs() <= 1f32 {
+                assert!(vertex.color[3] >= 127);
+            }
+        }
+
+        assert!(Weather::build(&params(0f32), column).is_empty());
+    }
+
+    #[test]
+    fn covered_columns() {
+        let weather = Weather::build(&params(1f32), |_, _| Some(WeatherColumn {
+            precipitation: Precipitation::Rain,
+            height: 80,
+            light: [0, 0],
+        }));
+        assert!(weather.is_empty());
+        assert!(weather.get_snow_mesh_data().is_none());
+    }
+
+    #[test]
+    fn stable_random() {
+        let seed = column_seed(-5, 12);
+        assert_eq!(column_random(seed, 0), column_random(seed, 0));
+        for index in 0..64 {
+            let value = column_random(seed, index);
+            assert!((0f32..1f32).contains(&value));
+        }
+    }
+}
@@ -1,38 +1,87 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
+use std::path::Path;
 use std::rc::Rc;
 use std::sync::Arc;
+use std::sync::mpsc::Sender;
 use std::time::Duration;
 
 use ash::prelude::VkResult;
 use ash::vk;
 use bumpalo::Bump;
 
+use crate::device::crash::CrashReport;
 use crate::device::device::Queue;
+use crate::device::transfer::TransferScheduler;
+use crate::objects::image_layout::ImageLayoutScope;
 
+use crate::renderer::emulator::barrier_batch::BarrierBatcher;
+use crate::renderer::emulator::descriptors::UniformArena;
+use crate::renderer::emulator::dynamic_texture::DynamicTextureSlot;
 use crate::renderer::emulator::pass::PassId;
 use crate::renderer::emulator::immediate::ImmediateBuffer;
+use crate::renderer::emulator::markers::{MarkerQueue, MarkerStage, ProgressMarker, ProgressMarkers};
 use crate::renderer::emulator::pipeline::{EmulatorOutput, EmulatorPipeline, EmulatorPipelinePass, PipelineTask};
 
 use crate::prelude::*;
-use crate::renderer::emulator::global_objects::{GlobalImage, GlobalMesh};
+use crate::renderer::emulator::global_objects::{GlobalImage, GlobalMesh, MipmapMode};
 use crate::renderer::emulator::mc_shaders::ShaderId;
 use crate::renderer::emulator::share::{NextTaskResult, Share};
-use crate::renderer::emulator::staging::StagingAllocationId;
+use crate::renderer::emulator::readback::GlobalImageData;
+use crate::renderer::emulator::staging::{StagingAllocation, StagingAllocationId};
+use crate::util::format::Format;
+use crate::util::trace::b4d_span;
 
 pub(super) enum WorkerTask {
     StartPass(PassId, Arc<dyn EmulatorPipeline>, Box<dyn EmulatorPipelinePass + Send>, Arc<GlobalImage>, vk::Sampler),
     EndPass(Box<ImmediateBuffer>),
+
+    /// Submits the tasks of the current pass processed so far. Contains the immediate buffer used
+    /// by the pass recorder up to this point.
+    FlushPass(Box<ImmediateBuffer>),
+    UseSubImmediateBuffer(Box<ImmediateBuffer>),
     UseGlobalMesh(Arc<GlobalMesh>),
     UseGlobalImage(Arc<GlobalImage>),
     UseShader(ShaderId),
     UseOutput(Box<dyn EmulatorOutput + Send>),
     PipelineTask(PipelineTask),
     WriteGlobalMesh(GlobalMeshWrite, bool),
-    ClearGlobalImage(GlobalImageClear, bool),
+    DecompressGlobalMesh(GlobalMeshDecompress, bool),
+    FillGlobalMesh(GlobalMeshFill),
+    UpdateGlobalMesh(GlobalMeshUpdate),
+    ClearGlobalImage(GlobalImageClear),
     WriteGlobalImage(GlobalImageWrite),
-    GenerateGlobalImageMipmaps(Arc<GlobalImage>, PassId),
+    CopyGlobalImage(GlobalImageCopy),
+    ReadGlobalImage(GlobalImageRead),
+    /// Generates the mip levels `1..level_count` of the image from mip level 0.
+    GenerateGlobalImageMipmaps(Arc<GlobalImage>, u32, PassId),
+}
+
+impl WorkerTask {
+    /// Returns the name of the task used for diagnostics.
+    fn get_name(&self) -> &'static str {
+        match self {
+            WorkerTask::StartPass(..) => "StartPass",
+            WorkerTask::EndPass(..) => "EndPass",
+            WorkerTask::FlushPass(..) => "FlushPass",
+            WorkerTask::UseSubImmediateBuffer(..) => "UseSubImmediateBuffer",
+            WorkerTask::UseGlobalMesh(..) => "UseGlobalMesh",
+            WorkerTask::UseGlobalImage(..) => "UseGlobalImage",
+            WorkerTask::UseShader(..) => "UseShader",
+            WorkerTask::UseOutput(..) => "UseOutput",
+            WorkerTask::PipelineTask(..) => "PipelineTask",
+            WorkerTask::WriteGlobalMesh(..) => "WriteGlobalMesh",
+            WorkerTask::DecompressGlobalMesh(..) => "DecompressGlobalMesh",
+            WorkerTask::FillGlobalMesh(..) => "FillGlobalMesh",
+            WorkerTask::UpdateGlobalMesh(..) => "UpdateGlobalMesh",
+            WorkerTask::ClearGlobalImage(..) => "ClearGlobalImage",
+            WorkerTask::WriteGlobalImage(..) => "WriteGlobalImage",
+            WorkerTask::CopyGlobalImage(..) => "CopyGlobalImage",
+            WorkerTask::ReadGlobalImage(..) => "ReadGlobalImage",
+            WorkerTask::GenerateGlobalImageMipmaps(..) => "GenerateGlobalImageMipmaps",
+        }
+    }
 }
 
 pub(super) struct GlobalMeshWrite {
@@ -44,15 +93,72 @@ pub(super) struct GlobalMeshWrite {
     pub(super) regions: Box<[vk::BufferCopy]>,
 }
 
-pub(super) struct GlobalImageWrite {
+/// Decompresses a GDeflate payload from staging memory into a global mesh using
+/// `VK_NV_memory_decompression`.
+pub(super) struct GlobalMeshDecompress {
     pub(super) after_pass: PassId,
     pub(super) staging_allocation: StagingAllocationId,
     pub(super) staging_range: (vk::DeviceSize, vk::DeviceSize),
     pub(super) staging_buffer: vk::Buffer,
+    pub(super) dst_mesh: Arc<GlobalMesh>,
+    pub(super) regions: Box<[vk::DecompressMemoryRegionNV]>,
+}
+
+/// Fills a range of a global mesh with a repeated 4 byte value using `vkCmdFillBuffer`.
+pub(super) struct GlobalMeshFill {
+    pub(super) after_pass: PassId,
+    pub(super) dst_mesh: Arc<GlobalMesh>,
+    pub(super) offset: vk::DeviceSize,
+    pub(super) size: vk::DeviceSize,
+    pub(super) data: u32,
+}
+
+/// Writes a small amount of data into a global mesh using `vkCmdUpdateBuffer`. The data is
+/// stored in the command buffer so no staging memory is needed.
+pub(super) struct GlobalMeshUpdate {
+    pub(super) after_pass: PassId,
+    pub(super) dst_mesh: Arc<GlobalMesh>,
+    pub(super) offset: vk::DeviceSize,
+    pub(super) data: Box<[u8]>,
+}
+
+/// Owner of the staging memory used by a [`GlobalImageWrite`]. Released once the write has
+/// finished execution.
+pub(super) enum ImageWriteStaging {
+    Pool(StagingAllocationId),
+    DynamicTexture(DynamicTextureSlot),
+}
+
+pub(super) struct GlobalImageWrite {
+    pub(super) after_pass: PassId,
+    pub(super) staging_allocation: ImageWriteStaging,
+    pub(super) staging_range: (vk::DeviceSize, vk::DeviceSize),
+    pub(super) staging_buffer: vk::Buffer,
     pub(super) dst_image: Arc<GlobalImage>,
     pub(super) regions: Box<[vk::BufferImageCopy]>,
 }
 
+/// Copies regions of one global image into another. The images must be different.
+pub(super) struct GlobalImageCopy {
+    pub(super) after_pass: PassId,
+    pub(super) src_image: Arc<GlobalImage>,
+    pub(super) dst_image: Arc<GlobalImage>,
+    pub(super) regions: Box<[vk::ImageCopy]>,
+}
+
+/// Copies a mip level of a global image into staging memory. The data is sent to the readback
+/// handle once the copy has finished execution.
+pub(super) struct GlobalImageRead {
+    pub(super) after_pass: PassId,
+    pub(super) staging: StagingAllocation,
+    pub(super) staging_allocation: StagingAllocationId,
+    pub(super) staging_size: vk::DeviceSize,
+    pub(super) src_image: Arc<GlobalImage>,
+    pub(super) mip_level: u32,
+    pub(super) size: Vec2u32,
+    pub(super) sender: Sender<GlobalImageData>,
+}
+
 pub(super) struct GlobalImageClear {
     pub(super) after_pass: PassId,
     pub(super) clear_value: vk::ClearColorValue,
@@ -63,6 +169,14 @@ pub(super) fn run_worker(device: Arc<DeviceContext>, share: Arc<Share>) {
     let queue = device.get_main_queue();
 
     let pool = Rc::new(RefCell::new(WorkerObjectPool::new(device.clone(), queue.get_queue_family_index())));
+    let compute_pool = device.get_async_compute_queue().map(|compute_queue| {
+        Rc::new(RefCell::new(WorkerObjectPool::new(device.clone(), compute_queue.get_queue_family_index())))
+    });
+    let transfer = TransferScheduler::new(&device).map(|scheduler| {
+        let pool = Rc::new(RefCell::new(WorkerObjectPool::new(device.clone(), scheduler.get_queue_family_index())));
+        Rc::new(AsyncTransfer { scheduler, pool })
+    });
+    let markers = Rc::new(ProgressMarkers::new(device.clone()));
     let mut current_pass: Option<PassState> = None;
     let mut old_frames = Vec::new();
 
@@ -75,38 +189,90 @@ pub(super) fn run_worker(device: Arc<DeviceContext>, share: Arc<Share>) {
 
     let queue = device.get_main_queue();
 
+    let mut last_completed_pass: Option<PassId> = None;
+
     loop {
+        let mut fence_error = None;
+        let in_flight_passes = old_frames.len();
         old_frames.retain(|old: &PassState| {
-            !old.is_complete()
+            match old.is_complete() {
+                Ok(true) => {
+                    last_completed_pass = last_completed_pass.max(Some(old.pass_id));
+                    false
+                },
+                Ok(false) => true,
+                Err(err) => {
+                    fence_error = Some(err);
+                    true
+                }
+            }
         });
+        if let Some(err) = fence_error {
+            handle_fatal_error(&device, &queue, &markers, err, last_completed_pass, &old_frames, current_pass.as_ref());
+        }
+        if old_frames.len() != in_flight_passes {
+            share.get_progress().on_passes_completed(last_completed_pass, old_frames.len());
+            if let Some(pass_id) = last_completed_pass {
+                share.on_pass_completed(pass_id.get_raw());
+            }
+        }
 
         let task = match share.try_get_next_task_timeout(Duration::from_micros(500)) {
             NextTaskResult::Ok(task) => task,
             NextTaskResult::Timeout => continue,
         };
+        share.get_progress().on_task_started(task.get_name());
+        let _submissions = device.lock_submissions();
 
         match task {
             WorkerTask::StartPass(id, pipeline, pass, placeholder_image, placeholder_sampler) => {
+                let _span = b4d_span!("start_pass", pass_id = id.get_raw());
                 if current_pass.is_some() {
                     log::error!("Worker received WorkerTask::StartPass when a pass is already running");
                     panic!()
                 }
-                let state = PassState::new(id, pipeline, pass, device.clone(), &queue, share.clone(), pool.clone(), placeholder_image, placeholder_sampler);
+                let state = PassState::new(id, pipeline, pass, device.clone(), &queue, share.clone(), pool.clone(), markers.clone(), placeholder_image, placeholder_sampler);
                 current_pass = Some(state);
                 current_global_recorder = next_global_recorder.take();
             }
 
             WorkerTask::EndPass(immediate_buffer) => {
                 if let Some(mut pass) = current_pass.take() {
+                    let _span = b4d_span!("end_pass", pass_id = pass.pass_id.get_raw());
                     pass.use_immediate_buffer(immediate_buffer);
-                    pass.submit(&queue, current_global_recorder.take());
+                    let result = pass.submit(&queue, current_global_recorder.take());
+                    let pass_id = pass.pass_id;
+                    let end_fence = pass.end_fence.unwrap();
                     old_frames.push(pass);
+                    if let Err(err) = result {
+                        handle_fatal_error(&device, &queue, &markers, err, last_completed_pass, &old_frames, None);
+                    }
+                    share.get_progress().on_pass_submitted(pass_id, end_fence, old_frames.len());
+
+                    if share.is_deterministic() {
+                        // The completion is processed at the start of the next iteration
+                        if let Err(err) = old_frames.last().unwrap().wait_complete() {
+                            handle_fatal_error(&device, &queue, &markers, err, last_completed_pass, &old_frames, None);
+                        }
+                    }
                 } else {
                     log::error!("Worker received WorkerTask::EndPass when no active pass exists");
                     panic!()
                 }
             }
 
+            WorkerTask::FlushPass(immediate_buffer) => {
+                if let Some(pass) = &mut current_pass {
+                    pass.use_immediate_buffer(immediate_buffer);
+                    if let Err(err) = pass.flush(&queue, &mut current_global_recorder) {
+                        handle_fatal_error(&device, &queue, &markers, err, last_completed_pass, &old_frames, current_pass.as_ref());
+                    }
+                } else {
+                    log::error!("Worker received WorkerTask::FlushPass when no active pass exists");
+                    panic!()
+                }
+            }
+
             WorkerTask::UseGlobalMesh(mesh) => {
                 if let Some(pass) = &mut current_pass {
                     pass.global_meshes.push(mesh)
@@ -116,6 +282,15 @@ pub(super) fn run_worker(device: Arc<DeviceContext>, share: Arc<Share>) {
                 }
             }
 
+            WorkerTask::UseSubImmediateBuffer(immediate_buffer) => {
+                if let Some(pass) = &mut current_pass {
+                    pass.use_sub_immediate_buffer(immediate_buffer);
+                } else {
+                    log::error!("Worker received WorkerTask::UseSubImmediateBuffer when no active pass exists");
+                    panic!()
+                }
+            }
+
             WorkerTask::UseGlobalImage(image) => {
                 if let Some(pass) = &mut current_pass {
                     pass.global_images.push(image);
@@ -145,6 +320,7 @@ pub(super) fn run_worker(device: Arc<DeviceContext>, share: Arc<Share>) {
 
             WorkerTask::PipelineTask(task) => {
                 if let Some(pass) = &mut current_pass {
+                    let _span = b4d_span!("pipeline_task", pass_id = pass.pass_id.get_raw());
                     pass.process_task(&task)
                 } else {
                     log::error!("Worker received WorkerTask::PipelineTask when no active pass exists");
@@ -153,70 +329,151 @@ pub(super) fn run_worker(device: Arc<DeviceContext>, share: Arc<Share>) {
             }
 
             WorkerTask::WriteGlobalMesh(write, uninit) => {
+                let _span = b4d_span!("global_object_update");
                 if let Some(current_pass) = &current_pass {
                     if current_pass.pass_id > write.after_pass {
-                        get_or_create_recorder(&mut current_global_recorder, &share, &pool).record_global_buffer_write(write, uninit);
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_buffer_write(write, uninit);
+                    } else {
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_buffer_write(write, uninit);
+                    }
+                } else {
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_buffer_write(write, uninit);
+                }
+            }
+
+            WorkerTask::DecompressGlobalMesh(decompress, uninit) => {
+                let _span = b4d_span!("global_object_update");
+                if let Some(current_pass) = &current_pass {
+                    if current_pass.pass_id > decompress.after_pass {
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_buffer_decompress(decompress, uninit);
+                    } else {
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_buffer_decompress(decompress, uninit);
+                    }
+                } else {
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_buffer_decompress(decompress, uninit);
+                }
+            }
+
+            WorkerTask::FillGlobalMesh(fill) => {
+                let _span = b4d_span!("global_object_update");
+                if let Some(current_pass) = &current_pass {
+                    if current_pass.pass_id > fill.after_pass {
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_buffer_fill(fill);
+                    } else {
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_buffer_fill(fill);
+                    }
+                } else {
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_buffer_fill(fill);
+                }
+            }
+
+            WorkerTask::UpdateGlobalMesh(update) => {
+                let _span = b4d_span!("global_object_update");
+                if let Some(current_pass) = &current_pass {
+                    if current_pass.pass_id > update.after_pass {
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_buffer_update(update);
                     } else {
-                        get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_buffer_write(write, uninit);
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_buffer_update(update);
                     }
                 } else {
-                    get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_buffer_write(write, uninit);
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_buffer_update(update);
                 }
             }
 
-            WorkerTask::ClearGlobalImage(clear, uninit) => {
+            WorkerTask::ClearGlobalImage(clear) => {
+                let _span = b4d_span!("global_object_update");
                 if let Some(current_pass) = &current_pass {
                     if current_pass.pass_id > clear.after_pass {
-                        get_or_create_recorder(&mut current_global_recorder, &share, &pool).record_global_image_clear(clear, uninit);
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_clear(clear);
                     } else {
-                        get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_clear(clear, uninit);
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_clear(clear);
                     }
                 } else {
-                    get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_clear(clear, uninit);
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_clear(clear);
                 }
             }
 
             WorkerTask::WriteGlobalImage(write) => {
+                let _span = b4d_span!("global_object_update");
                 if let Some(current_pass) = &current_pass {
                     if current_pass.pass_id > write.after_pass {
-                        get_or_create_recorder(&mut current_global_recorder, &share, &pool).record_global_image_write(write, false);
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_write(write);
+                    } else {
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_write(write);
+                    }
+                } else {
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_write(write);
+                }
+            }
+
+            WorkerTask::CopyGlobalImage(copy) => {
+                let _span = b4d_span!("global_object_update");
+                if let Some(current_pass) = &current_pass {
+                    if current_pass.pass_id > copy.after_pass {
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_copy(copy);
+                    } else {
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_copy(copy);
+                    }
+                } else {
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_copy(copy);
+                }
+            }
+
+            WorkerTask::ReadGlobalImage(read) => {
+                let _span = b4d_span!("global_object_update");
+                if let Some(current_pass) = &current_pass {
+                    if current_pass.pass_id > read.after_pass {
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_read(read);
                     } else {
-                        get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_write(write, false);
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_read(read);
                     }
                 } else {
-                    get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_write(write, false);
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_read(read);
                 }
             }
 
-            WorkerTask::GenerateGlobalImageMipmaps(image, after_pass) => {
+            WorkerTask::GenerateGlobalImageMipmaps(image, level_count, after_pass) => {
+                let _span = b4d_span!("global_object_update");
                 if let Some(current_pass) = &current_pass {
                     if current_pass.pass_id > after_pass {
-                        get_or_create_recorder(&mut current_global_recorder, &share, &pool).record_global_image_generate_mipmaps(image);
+                        get_or_create_recorder(&mut current_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_generate_mipmaps(image, level_count);
                     } else {
-                        get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_generate_mipmaps(image);
+                        get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_generate_mipmaps(image, level_count);
                     }
                 } else {
-                    get_or_create_recorder(&mut next_global_recorder, &share, &pool).record_global_image_generate_mipmaps(image);
+                    get_or_create_recorder(&mut next_global_recorder, &share, &pool, &compute_pool, &transfer).record_global_image_generate_mipmaps(image, level_count);
                 }
             }
         }
+
+        share.get_progress().on_task_finished();
     }
 }
 
-fn get_or_create_recorder<'a>(recorder: &'a mut Option<GlobalObjectsRecorder>, share: &Arc<Share>, object_pool: &Rc<RefCell<WorkerObjectPool>>) -> &'a mut GlobalObjectsRecorder {
+fn get_or_create_recorder<'a>(recorder: &'a mut Option<GlobalObjectsRecorder>, share: &Arc<Share>, object_pool: &Rc<RefCell<WorkerObjectPool>>, compute_pool: &Option<Rc<RefCell<WorkerObjectPool>>>, transfer: &Option<Rc<AsyncTransfer>>) -> &'a mut GlobalObjectsRecorder {
     if let Some(recorder) = recorder {
         recorder
     } else {
-        *recorder = Some(GlobalObjectsRecorder::new(share.clone(), object_pool.clone()));
+        *recorder = Some(GlobalObjectsRecorder::new(share.clone(), object_pool.clone(), compute_pool.clone(), transfer.clone()));
         recorder.as_mut().unwrap()
     }
 }
 
+/// The async transfer queues together with the object pool of their queue family.
+struct AsyncTransfer {
+    scheduler: TransferScheduler,
+    pool: Rc<RefCell<WorkerObjectPool>>,
+}
+
 struct WorkerObjectPool {
     device: Arc<DeviceContext>,
     command_pool: vk::CommandPool,
     command_buffers: Vec<vk::CommandBuffer>,
     fences: Vec<vk::Fence>,
+    semaphores: Vec<vk::Semaphore>,
+    timestamp_pools: Vec<vk::QueryPool>,
+    /// The number of nanoseconds per timestamp tick or [`None`] if the queue does not support timestamps.
+    timestamp_period: Option<f32>,
 }
 
 impl WorkerObjectPool {
@@ -229,11 +486,25 @@ impl WorkerObjectPool {
             device.vk().create_command_pool(&info, None)
         }.unwrap();
 
+        let instance = device.get_instance().vk();
+        let physical_device = device.get_functions().physical_device;
+        let timestamp_valid_bits = unsafe {
+            instance.get_physical_device_queue_family_properties(physical_device)
+        }[queue_family as usize].timestamp_valid_bits;
+        let timestamp_period = if timestamp_valid_bits != 0 {
+            Some(unsafe { instance.get_physical_device_properties(physical_device) }.limits.timestamp_period)
+        } else {
+            None
+        };
+
         Self {
             device,
             command_pool,
             command_buffers: Vec::new(),
             fences: Vec::new(),
+            semaphores: Vec::new(),
+            timestamp_pools: Vec::new(),
+            timestamp_period,
         }
     }
 
@@ -279,6 +550,44 @@ impl WorkerObjectPool {
     fn return_fence(&mut self, fence: vk::Fence) {
         self.fences.push(fence);
     }
+
+    fn get_semaphore(&mut self) -> vk::Semaphore {
+        if let Some(semaphore) = self.semaphores.pop() {
+            return semaphore;
+        }
+
+        let info = vk::SemaphoreCreateInfo::builder();
+
+        unsafe {
+            self.device.vk().create_semaphore(&info, None)
+        }.unwrap()
+    }
+
+    fn return_semaphore(&mut self, semaphore: vk::Semaphore) {
+        self.semaphores.push(semaphore);
+    }
+
+    /// Returns a query pool containing 2 timestamp queries or [`None`] if timestamps are not
+    /// supported. The queries must be reset before use.
+    fn get_timestamp_pool(&mut self) -> Option<vk::QueryPool> {
+        self.timestamp_period?;
+
+        if let Some(pool) = self.timestamp_pools.pop() {
+            return Some(pool);
+        }
+
+        let info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(2);
+
+        Some(unsafe {
+            self.device.vk().create_query_pool(&info, None)
+        }.unwrap())
+    }
+
+    fn return_timestamp_pool(&mut self, pool: vk::QueryPool) {
+        self.timestamp_pools.push(pool);
+    }
 }
 
 pub struct PooledObjectProvider {
@@ -286,15 +595,27 @@ pub struct PooledObjectProvider {
     pool: Rc<RefCell<WorkerObjectPool>>,
     used_buffers: Vec<vk::CommandBuffer>,
     used_fences: Vec<vk::Fence>,
+    used_semaphores: Vec<vk::Semaphore>,
+    used_timestamp_pools: Vec<vk::QueryPool>,
+
+    /// Uniform data written by the submissions of this provider. The blocks are returned when the
+    /// provider is dropped which only happens after the submissions have completed.
+    uniform_arena: UniformArena,
+    uniform_alignment: vk::DeviceSize,
 }
 
 impl PooledObjectProvider {
     fn new(share: Arc<Share>, pool: Rc<RefCell<WorkerObjectPool>>) -> Self {
+        let uniform_alignment = share.get_uniform_alignment();
         Self {
             share,
             pool,
             used_buffers: Vec::with_capacity(8),
             used_fences: Vec::with_capacity(4),
+            used_semaphores: Vec::new(),
+            used_timestamp_pools: Vec::new(),
+            uniform_arena: UniformArena::new(),
+            uniform_alignment,
         }
     }
 
@@ -325,14 +646,50 @@ impl PooledObjectProvider {
         fence
     }
 
+    /// Returns a binary semaphore. The semaphore must be unsignaled when this provider is dropped.
+    pub fn get_semaphore(&mut self) -> vk::Semaphore {
+        let semaphore = self.pool.borrow_mut().get_semaphore();
+        self.used_semaphores.push(semaphore);
+
+        semaphore
+    }
+
+    /// Returns a query pool containing 2 timestamp queries or [`None`] if timestamps are not
+    /// supported. The queries must be reset before use.
+    pub fn get_timestamp_pool(&mut self) -> Option<vk::QueryPool> {
+        let pool = self.pool.borrow_mut().get_timestamp_pool()?;
+        self.used_timestamp_pools.push(pool);
+
+        Some(pool)
+    }
+
+    /// Returns the number of nanoseconds per timestamp tick.
+    pub fn get_timestamp_period(&self) -> Option<f32> {
+        self.pool.borrow().timestamp_period
+    }
+
+    /// Writes uniform data into a host visible buffer and returns the buffer and offset of the
+    /// data. The data stays valid until all submissions made with this provider have completed.
     pub fn allocate_uniform(&mut self, data: &[u8]) -> (vk::Buffer, vk::DeviceSize) {
-        self.share.allocate_uniform(data)
+        let share = &self.share;
+        self.uniform_arena.allocate_write(data, self.uniform_alignment, || share.get_uniform_block())
     }
 }
 
 impl Drop for PooledObjectProvider {
     fn drop(&mut self) {
-        self.pool.borrow_mut().return_buffers(self.used_buffers.as_slice());
+        let mut pool = self.pool.borrow_mut();
+        pool.return_buffers(self.used_buffers.as_slice());
+        for semaphore in self.used_semaphores.drain(..) {
+            pool.return_semaphore(semaphore);
+        }
+        for timestamp_pool in self.used_timestamp_pools.drain(..) {
+            pool.return_timestamp_pool(timestamp_pool);
+        }
+        let (blocks, used_bytes) = self.uniform_arena.take_blocks();
+        if !blocks.is_empty() {
+            self.share.return_uniform_blocks(blocks, used_bytes);
+        }
     }
 }
 
@@ -356,6 +713,10 @@ impl<'a> SubmitRecorder<'a> {
     fn as_slice(&self) -> &[vk::SubmitInfo2] {
         self.submits.as_slice()
     }
+
+    fn append(&mut self, other: SubmitRecorder<'a>) {
+        self.submits.extend(other.submits);
+    }
 }
 
 struct PassState {
@@ -370,16 +731,27 @@ struct PassState {
     outputs: Vec<Box<dyn EmulatorOutput>>,
 
     immediate_buffer: Option<Box<ImmediateBuffer>>,
+    sub_immediate_buffers: Vec<Box<ImmediateBuffer>>,
     global_meshes: Vec<Arc<GlobalMesh>>,
     global_images: Vec<Arc<GlobalImage>>,
     shaders: Vec<ShaderId>,
 
     pre_cmd: vk::CommandBuffer,
     post_cmd: vk::CommandBuffer,
+    timestamp_pool: Option<vk::QueryPool>,
+
+    /// The number of draw tasks processed by this pass. Only used for crash reports.
+    draw_count: u32,
+
+    /// The number of draw tasks processed before the end of each submitted segment. Used to find
+    /// the draws of a segment for crash reports.
+    segment_draw_counts: Vec<u32>,
+    markers: Rc<ProgressMarkers>,
 
     end_fence: Option<vk::Fence>,
 
-    gob: Option<GlobalObjectsRecorder>,
+    /// The global object recorders submitted before each segment of the pass.
+    gobs: Vec<GlobalObjectsRecorder>,
 }
 
 impl PassState {
@@ -391,6 +763,7 @@ impl PassState {
         queue: &Queue,
         share: Arc<Share>,
         pool: Rc<RefCell<WorkerObjectPool>>,
+        markers: Rc<ProgressMarkers>,
         placeholder_image: Arc<GlobalImage>,
         placeholder_sampler: vk::Sampler
     ) -> Self {
@@ -399,6 +772,16 @@ impl PassState {
         let pre_cmd = object_pool.get_begin_command_buffer().unwrap();
         let post_cmd = object_pool.get_begin_command_buffer().unwrap();
 
+        let timestamp_pool = object_pool.get_timestamp_pool();
+        if let Some(timestamp_pool) = timestamp_pool {
+            unsafe {
+                device.vk().cmd_reset_query_pool(pre_cmd, timestamp_pool, 0, 2);
+                device.vk().cmd_write_timestamp(pre_cmd, vk::PipelineStageFlags::TOP_OF_PIPE, timestamp_pool, 0);
+            }
+        }
+
+        markers.write(pre_cmd, MarkerQueue::Main, ProgressMarker::new(pass_id.get_raw(), 0, MarkerStage::Uploads));
+
         pass.init(queue, &mut object_pool, placeholder_image.get_sampler_view(), placeholder_sampler);
 
         Self {
@@ -413,59 +796,76 @@ impl PassState {
             outputs: Vec::with_capacity(8),
 
             immediate_buffer: None,
+            sub_immediate_buffers: Vec::new(),
             global_meshes: Vec::new(),
             global_images: vec![placeholder_image],
             shaders: Vec::new(),
 
             pre_cmd,
             post_cmd,
+            timestamp_pool,
+
+            draw_count: 0,
+            segment_draw_counts: Vec::new(),
+            markers,
 
             end_fence: None,
-            gob: None
+            gobs: Vec::new()
         }
     }
 
     fn use_immediate_buffer(&mut self, immediate_buffer: Box<ImmediateBuffer>) {
         if self.immediate_buffer.is_some() {
-            log::error!("Called PassState::use_immediate_buffer when a immediate buffer already exists");
-            panic!()
+            // After the pass has been flushed the recorder continues with a sub buffer
+            self.use_sub_immediate_buffer(immediate_buffer);
+            return;
         }
 
         immediate_buffer.generate_copy_commands(self.pre_cmd);
         self.immediate_buffer = Some(immediate_buffer);
     }
 
+    fn use_sub_immediate_buffer(&mut self, immediate_buffer: Box<ImmediateBuffer>) {
+        immediate_buffer.generate_copy_commands(self.pre_cmd);
+        self.sub_immediate_buffers.push(immediate_buffer);
+    }
+
     fn use_output(&mut self, mut output: Box<dyn EmulatorOutput>) {
         output.init(self.pass.as_ref(), &mut self.object_pool);
         self.outputs.push(output);
     }
 
     fn process_task(&mut self, task: &PipelineTask) {
+        if let PipelineTask::Draw(_) | PipelineTask::DrawCulled(_) = task {
+            self.draw_count += 1;
+        }
         self.pass.process_task(task, &mut self.object_pool);
     }
 
-    fn submit(&mut self, queue: &Queue, gob: Option<GlobalObjectsRecorder>) {
+    fn submit(&mut self, queue: &Queue, gob: Option<GlobalObjectsRecorder>) -> VkResult<()> {
+        let _span = b4d_span!("submit_pass", pass_id = self.pass_id.get_raw());
         assert!(self.end_fence.is_none());
         let end_fence = self.object_pool.get_fence();
         self.end_fence = Some(end_fence);
 
-        unsafe {
-            self.device.vk().end_command_buffer(self.pre_cmd)
-        }.unwrap();
-
-        unsafe {
-            self.device.vk().end_command_buffer(self.post_cmd)
-        }.unwrap();
-
         let submit_alloc = Bump::new();
         let mut submit_recorder = SubmitRecorder::new(32);
 
-        if let Some(mut gob) = gob {
-            gob.record(&mut submit_recorder, &submit_alloc);
-            self.gob = Some(gob);
+        self.record_segment_start(queue, gob, &mut submit_recorder, &submit_alloc)?;
+
+        if let Some(timestamp_pool) = self.timestamp_pool {
+            unsafe {
+                self.device.vk().cmd_write_timestamp(self.post_cmd, vk::PipelineStageFlags::BOTTOM_OF_PIPE, timestamp_pool, 1);
+            }
         }
 
-        self.record_pre_submits(&mut submit_recorder, &submit_alloc);
+        let last_segment = (self.segment_draw_counts.len() - 1) as u32;
+        self.markers.write(self.post_cmd, MarkerQueue::Main, ProgressMarker::new(self.pass_id.get_raw(), last_segment, MarkerStage::End));
+
+        unsafe {
+            self.device.vk().end_command_buffer(self.post_cmd)
+        }.unwrap();
+
         self.pass.record(&mut self.object_pool, &mut submit_recorder, &submit_alloc);
         for output in &mut self.outputs {
             output.record(&mut self.object_pool, &mut submit_recorder, &submit_alloc);
@@ -474,24 +874,102 @@ impl PassState {
 
         unsafe {
             queue.submit_2(submit_recorder.as_slice(), Some(end_fence))
-        }.unwrap();
+        }?;
 
         for output in &mut self.outputs {
             output.on_post_submit(&queue);
         }
+
+        Ok(())
     }
 
-    fn is_complete(&self) -> bool {
+    fn is_complete(&self) -> VkResult<bool> {
         if let Some(fence) = self.end_fence {
             unsafe {
                 self.device.vk().get_fence_status(fence)
-            }.unwrap()
+            }
         } else {
             panic!("Illegal state");
         }
     }
 
-    fn record_pre_submits<'a>(&self, recorder: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+    /// Blocks until the pass has completed execution on the gpu.
+    fn wait_complete(&self) -> VkResult<()> {
+        if let Some(fence) = self.end_fence {
+            unsafe {
+                self.device.vk().wait_for_fences(std::slice::from_ref(&fence), true, u64::MAX)
+            }
+        } else {
+            panic!("Illegal state");
+        }
+    }
+
+    /// Submits all tasks processed so far as a segment of the pass if the pass supports it. See
+    /// [`EmulatorPipelinePass::record_segment`]. The global objects recorder is only taken if the
+    /// segment is submitted.
+    fn flush(&mut self, queue: &Queue, gob: &mut Option<GlobalObjectsRecorder>) -> VkResult<()> {
+        let _span = b4d_span!("flush_pass", pass_id = self.pass_id.get_raw());
+
+        let submit_alloc = Bump::new();
+        let mut segment_recorder = SubmitRecorder::new(4);
+        if !self.pass.record_segment(&mut self.object_pool, &mut segment_recorder, &submit_alloc) {
+            return Ok(());
+        }
+
+        let mut submit_recorder = SubmitRecorder::new(8);
+        self.record_segment_start(queue, gob.take(), &mut submit_recorder, &submit_alloc)?;
+        submit_recorder.append(segment_recorder);
+
+        unsafe {
+            queue.submit_2(submit_recorder.as_slice(), None)
+        }?;
+
+        // Immediate buffers and global objects of the next segment are copied in a new pre command
+        // buffer
+        self.pre_cmd = self.object_pool.get_begin_command_buffer()?;
+        self.markers.write(self.pre_cmd, MarkerQueue::Main, ProgressMarker::new(self.pass_id.get_raw(), self.segment_draw_counts.len() as u32, MarkerStage::Uploads));
+
+        Ok(())
+    }
+
+    /// Records the global objects and the pre submit which have to be executed before the pass
+    /// commands of a segment. Ends the pre command buffer.
+    fn record_segment_start<'a>(&mut self, queue: &Queue, gob: Option<GlobalObjectsRecorder>, recorder: &mut SubmitRecorder<'a>, alloc: &'a Bump) -> VkResult<()> {
+        let segment = self.segment_draw_counts.len() as u32;
+        self.segment_draw_counts.push(self.draw_count);
+
+        let mut async_compute_semaphore = None;
+        if let Some(mut gob) = gob {
+            gob.submit_async_transfers(alloc)?;
+            if gob.has_async_compute() {
+                // The async compute work waits on the global objects so they have to be submitted first
+                let mut gob_submits = SubmitRecorder::new(1);
+                gob.record(&mut gob_submits, alloc);
+                unsafe {
+                    queue.submit_2(gob_submits.as_slice(), None)
+                }?;
+
+                let marker = ProgressMarker::new(self.pass_id.get_raw(), segment, MarkerStage::AsyncCompute);
+                async_compute_semaphore = Some(gob.submit_async_compute(alloc, &self.markers, marker)?);
+                self.record_async_compute_barrier();
+            } else {
+                gob.record(recorder, alloc);
+            }
+            self.gobs.push(gob);
+        }
+
+        self.markers.write(self.pre_cmd, MarkerQueue::Main, ProgressMarker::new(self.pass_id.get_raw(), segment, MarkerStage::Draws));
+        unsafe {
+            self.device.vk().end_command_buffer(self.pre_cmd)
+        }.unwrap();
+
+        self.record_pre_submits(recorder, alloc, async_compute_semaphore);
+
+        Ok(())
+    }
+
+    /// Records the pre submit. If `wait_semaphore` is set the submit waits on it.
+    fn record_pre_submits<'a>(&self, recorder: &mut SubmitRecorder<'a>, alloc: &'a Bump, wait_semaphore: Option<vk::Semaphore>) {
         let cmd_infos = alloc.alloc([
             vk::CommandBufferSubmitInfo::builder()
                 .command_buffer(self.pre_cmd)
@@ -501,36 +979,202 @@ impl PassState {
         let submit_info = vk::SubmitInfo2::builder()
             .command_buffer_infos(cmd_infos);
 
+        let submit_info = if let Some(semaphore) = wait_semaphore {
+            let wait_infos = alloc.alloc([
+                vk::SemaphoreSubmitInfo::builder()
+                    .semaphore(semaphore)
+                    .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                    .build()
+            ]);
+            submit_info.wait_semaphore_infos(wait_infos)
+        } else {
+            submit_info
+        };
+
+        recorder.push(submit_info);
+    }
+
+    /// Records a barrier into the pre command buffer extending the async compute semaphore wait to
+    /// all later submits of the pass. Semaphore waits only apply to their own batch.
+    fn record_async_compute_barrier(&self) {
+        let barrier = vk::MemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::NONE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ);
+
+        let info = vk::DependencyInfo::builder()
+            .memory_barriers(std::slice::from_ref(&barrier));
+
+        unsafe {
+            self.device.synchronization_2_khr().cmd_pipeline_barrier2(self.pre_cmd, &info);
+        }
+    }
+
+    fn record_post_submits<'a>(&self, recorder: &mut SubmitRecorder<'a>, alloc: &'a Bump) {
+        let cmd_infos = alloc.alloc([
+            vk::CommandBufferSubmitInfo::builder()
+                .command_buffer(self.post_cmd)
+                .build()
+        ]);
+
+        // This is the last submit of the pass so all previous work is included in the signal
+        let signal_infos = alloc.alloc([
+            vk::SemaphoreSubmitInfo::builder()
+                .semaphore(self.share.get_pass_timeline())
+                .value(self.pass_id.get_raw())
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .build()
+        ]);
+
+        let submit_info = vk::SubmitInfo2::builder()
+            .command_buffer_infos(cmd_infos)
+            .signal_semaphore_infos(signal_infos);
+
         recorder.push(submit_info);
     }
 
-    fn record_post_submits<'a>(&self, _: &mut SubmitRecorder<'a>, _: &'a Bump) {
+    /// Reads the gpu execution time of the pass. Must only be called after the pass has completed.
+    fn read_gpu_time(&self) -> Option<Duration> {
+        let timestamp_pool = self.timestamp_pool?;
+        let period = self.object_pool.get_timestamp_period()?;
+
+        let mut timestamps = [0u64; 2];
+        unsafe {
+            self.device.vk().get_query_pool_results(timestamp_pool, 0, 2, &mut timestamps, vk::QueryResultFlags::TYPE_64)
+        }.ok()?;
+
+        let ticks = timestamps[1].saturating_sub(timestamps[0]);
+        Some(Duration::from_nanos(((ticks as f64) * (period as f64)) as u64))
     }
 }
 
 impl Drop for PassState {
     fn drop(&mut self) {
+        if self.end_fence.is_some() {
+            if let Some(time) = self.read_gpu_time() {
+                self.share.record_gpu_pass_time(time);
+            }
+        }
         if let Some(immediate_buffer) = self.immediate_buffer.take() {
             self.share.return_immediate_buffer(immediate_buffer);
         }
+        for immediate_buffer in std::mem::take(&mut self.sub_immediate_buffers) {
+            self.share.return_sub_immediate_buffer(immediate_buffer);
+        }
         for shader in &self.shaders {
             self.pipeline.dec_shader_used(*shader);
         }
     }
 }
 
+/// Called when a queue operation of the worker fails. Writes a crash report if the device was lost
+/// and then panics.
+fn handle_fatal_error(device: &DeviceContext, queue: &Queue, markers: &ProgressMarkers, err: vk::Result, last_completed_pass: Option<PassId>, in_flight: &[PassState], current_pass: Option<&PassState>) -> ! {
+    if err != vk::Result::ERROR_DEVICE_LOST {
+        log::error!("Emulator worker queue operation failed {:?}", err);
+        panic!()
+    }
+
+    let mut report = CrashReport::collect(device, queue, format!("{:?}", err));
+
+    let describe_pass = |pass: &PassState| json::object! {
+        "pass_id": pass.pass_id.get_raw(),
+        "pipeline": pass.pipeline.get_debug_name(),
+        "draw_count": pass.draw_count,
+        "segment_draw_counts": pass.segment_draw_counts.clone(),
+    };
+    report.add_context("last_completed_pass", last_completed_pass.map(|id| id.get_raw()));
+    report.add_context("in_flight_passes", in_flight.iter().map(describe_pass).collect::<Vec<_>>());
+    report.add_context("recording_pass", current_pass.map(describe_pass));
+
+    let describe_marker = |marker: ProgressMarker| {
+        let pass = in_flight.iter().chain(current_pass).find(|pass| pass.pass_id.get_raw() == marker.pass_id);
+        let draws = pass.and_then(|pass| {
+            let first = if marker.segment == 0 { 0 } else { *pass.segment_draw_counts.get((marker.segment - 1) as usize)? };
+            let end = *pass.segment_draw_counts.get(marker.segment as usize)?;
+            Some(json::array![first, end])
+        });
+        json::object! {
+            "pass_id": marker.pass_id,
+            "pipeline": pass.map(|pass| pass.pipeline.get_debug_name()),
+            "segment": marker.segment,
+            "stage": format!("{:?}", marker.stage),
+            "draws": draws,
+        }
+    };
+    report.add_context("last_marker", markers.get_last_reached(MarkerQueue::Main).map(describe_marker));
+    if device.get_async_compute_queue().is_some() {
+        report.add_context("last_async_compute_marker", markers.get_last_reached(MarkerQueue::AsyncCompute).map(describe_marker));
+    }
+
+    match report.write_to_dir(Path::new(".")) {
+        Ok(path) => log::error!("Device lost. Last completed pass: {:?}. Crash report written to {:?}", last_completed_pass.map(|id| id.get_raw()), path),
+        Err(io_err) => log::error!("Device lost. Last completed pass: {:?}. Failed to write crash report {:?}: {}", last_completed_pass.map(|id| id.get_raw()), io_err, report.to_json().pretty(4)),
+    }
+    panic!()
+}
+
 struct GlobalObjectsRecorder {
     share: Arc<Share>,
-    _object_pool: PooledObjectProvider,
+    object_pool: PooledObjectProvider,
+
+    /// The object pool of the async compute queue family. [`None`] if no async compute queue
+    /// exists.
+    compute_pool: Option<Rc<RefCell<WorkerObjectPool>>>,
+
+    /// Images with alpha weighted mipmaps which are generated on the async compute queue after the
+    /// commands of this recorder.
+    /// Contains the number of levels to generate.
+    async_compute_mipmaps: Vec<(Arc<GlobalImage>, u32)>,
+
+    /// Signaled by the global object commands and waited on by the async compute commands.
+    async_compute_wait: Option<vk::Semaphore>,
+
+    /// Objects used by the async compute commands. Must be kept alive until the pass completes.
+    async_compute_objects: Option<PooledObjectProvider>,
+
+    /// The async transfer queues. [`None`] if no async transfer queue exists.
+    transfer: Option<Rc<AsyncTransfer>>,
+
+    /// Initial uploads of global meshes which are copied on the async transfer queues before the
+    /// commands of this recorder. Indexed by the queue selected by the [`TransferScheduler`].
+    async_transfer_writes: Box<[Vec<GlobalMeshWrite>]>,
+
+    /// Meshes written by [`GlobalObjectsRecorder::async_transfer_writes`] and the index of the
+    /// queue they are written on.
+    async_transfer_meshes: HashMap<Arc<GlobalMesh>, usize>,
+
+    /// Signaled by the async transfer commands and waited on by the global object commands.
+    async_transfer_semaphores: Vec<vk::Semaphore>,
+
+    /// Objects used by the async transfer commands. Must be kept alive until the pass completes.
+    async_transfer_objects: Option<PooledObjectProvider>,
 
     cmd: vk::CommandBuffer,
 
     staging_allocations: Vec<StagingAllocationId>,
 
+    /// Dynamic texture slots used by this recorder. Released when the recorder is dropped.
+    dynamic_texture_slots: Vec<DynamicTextureSlot>,
+
+    /// Barriers releasing the staging memory back to the host. These are only needed once the
+    /// recorder has finished so they are emitted together with the post barriers.
     staging_barriers: Vec<vk::BufferMemoryBarrier2>,
 
+    /// Global image readbacks whose data is sent once the recorder has finished execution.
+    global_image_reads: Vec<GlobalImageRead>,
+
+    /// Barriers which have been generated but not yet recorded. Must be flushed before recording
+    /// any command depending on them.
+    barriers: BarrierBatcher,
+
     used_global_meshes: HashMap<Arc<GlobalMesh>, gob::MeshState>,
-    used_global_images: HashMap<Arc<GlobalImage>, gob::ImageState>,
+    used_global_images: HashSet<Arc<GlobalImage>>,
+
+    /// The layouts of all global images used by this recorder. Images are returned into the ready
+    /// state at the end of the recorder.
+    image_layouts: ImageLayoutScope,
 
     /// A [`vk::ImageMemoryBarrier2`] Vec which can be used locally inside functions to avoid new
     /// allocations. It should always be cleared before use.
@@ -542,8 +1186,9 @@ struct GlobalObjectsRecorder {
 }
 
 impl GlobalObjectsRecorder {
-    fn new(share: Arc<Share>, object_pool: Rc<RefCell<WorkerObjectPool>>) -> Self {
+    fn new(share: Arc<Share>, object_pool: Rc<RefCell<WorkerObjectPool>>, compute_pool: Option<Rc<RefCell<WorkerObjectPool>>>, transfer: Option<Rc<AsyncTransfer>>) -> Self {
         let mut object_pool = PooledObjectProvider::new(share.clone(), object_pool);
+        let queue_family = share.get_device().get_main_queue().get_queue_family_index();
 
         let cmd = object_pool.get_begin_command_buffer().unwrap_or_else(|err| {
             log::error!("Failed to begin global object command buffer {:?}", err);
@@ -552,15 +1197,30 @@ impl GlobalObjectsRecorder {
 
         Self {
             share,
-            _object_pool: object_pool,
+            object_pool,
+
+            compute_pool,
+            async_compute_mipmaps: Vec::new(),
+            async_compute_wait: None,
+            async_compute_objects: None,
+
+            async_transfer_writes: (0..transfer.as_ref().map_or(0, |transfer| transfer.scheduler.get_queue_count())).map(|_| Vec::new()).collect(),
+            async_transfer_meshes: HashMap::new(),
+            async_transfer_semaphores: Vec::new(),
+            async_transfer_objects: None,
+            transfer,
 
             cmd,
 
             staging_allocations: Vec::new(),
+            dynamic_texture_slots: Vec::new(),
             staging_barriers: Vec::new(),
+            global_image_reads: Vec::new(),
+            barriers: BarrierBatcher::new(),
 
             used_global_meshes: HashMap::new(),
-            used_global_images: HashMap::new(),
+            used_global_images: HashSet::new(),
+            image_layouts: ImageLayoutScope::new(gob::IMAGE_READY_ACCESS, queue_family),
 
             tmp_image_barriers: Vec::new(),
             tmp_buffer_barriers: Vec::new(),
@@ -568,10 +1228,16 @@ impl GlobalObjectsRecorder {
     }
 
     fn record_global_buffer_write(&mut self, write: GlobalMeshWrite, is_uninit: bool) {
+        let write = match self.try_push_async_transfer_write(write, is_uninit) {
+            Some(write) => write,
+            None => return,
+        };
+
         let dst_buffer = write.dst_mesh.get_buffer_handle();
 
         if !write.regions.is_empty() {
             self.transition_mesh(write.dst_mesh, gob::MeshState::TransferWrite, is_uninit);
+            self.barriers.flush(self.share.get_device(), self.cmd);
 
             unsafe {
                 self.share.get_device().vk().cmd_copy_buffer(
@@ -586,10 +1252,95 @@ impl GlobalObjectsRecorder {
         self.push_staging(write.staging_allocation, write.staging_buffer, write.staging_range.0, write.staging_range.1);
     }
 
-    fn record_global_image_clear(&mut self, clear: GlobalImageClear, is_uninit: bool) {
-        let dst_image = clear.dst_image.get_image_handle();
-
-        self.transition_image(clear.dst_image, gob::ImageState::TransferWrite, is_uninit);
+    /// Moves the initial upload of a mesh to the async transfer queues. Only meshes which have not
+    /// been used on the main queue by this recorder are eligible since the copies are executed
+    /// before all commands of this recorder. Returns the write if it has to be recorded on the main
+    /// queue.
+    ///
+    /// Meshes are uploaded in chunks writing disjoint regions. All chunks after the first one are
+    /// copied on the same queue as the first one without any barriers.
+    fn try_push_async_transfer_write(&mut self, write: GlobalMeshWrite, is_uninit: bool) -> Option<GlobalMeshWrite> {
+        let transfer = match &self.transfer {
+            Some(transfer) => transfer,
+            None => return Some(write),
+        };
+        if write.regions.is_empty() || self.used_global_meshes.contains_key(&write.dst_mesh) {
+            return Some(write);
+        }
+
+        let queue_index = match self.async_transfer_meshes.get(&write.dst_mesh) {
+            Some(queue_index) => *queue_index,
+            None if is_uninit => {
+                let size = write.regions.iter().map(|region| region.size).sum();
+                let queue_index = transfer.scheduler.select_queue_index(size);
+                self.async_transfer_meshes.insert(write.dst_mesh.clone(), queue_index);
+                queue_index
+            }
+            None => return Some(write),
+        };
+
+        // The staging memory is only released once the pass has completed which also includes
+        // the async transfer commands so no host barrier is needed
+        self.staging_allocations.push(write.staging_allocation);
+        self.async_transfer_writes[queue_index].push(write);
+
+        None
+    }
+
+    fn record_global_buffer_fill(&mut self, fill: GlobalMeshFill) {
+        let dst_buffer = fill.dst_mesh.get_buffer_handle();
+
+        self.transition_mesh(fill.dst_mesh, gob::MeshState::TransferWrite, false);
+        self.barriers.flush(self.share.get_device(), self.cmd);
+
+        unsafe {
+            self.share.get_device().vk().cmd_fill_buffer(self.cmd, dst_buffer, fill.offset, fill.size, fill.data);
+        }
+    }
+
+    fn record_global_buffer_update(&mut self, update: GlobalMeshUpdate) {
+        let dst_buffer = update.dst_mesh.get_buffer_handle();
+
+        self.transition_mesh(update.dst_mesh, gob::MeshState::TransferWrite, false);
+        self.barriers.flush(self.share.get_device(), self.cmd);
+
+        unsafe {
+            self.share.get_device().vk().cmd_update_buffer(self.cmd, dst_buffer, update.offset, &update.data);
+        }
+    }
+
+    fn record_global_buffer_decompress(&mut self, decompress: GlobalMeshDecompress, is_uninit: bool) {
+        let cmd_decompress_memory = self.share.get_device().memory_decompression_nv().unwrap_or_else(|| {
+            log::error!("Recorded global mesh decompression without VK_NV_memory_decompression");
+            panic!()
+        }).cmd_decompress_memory_nv;
+
+        self.transition_mesh(decompress.dst_mesh, gob::MeshState::DecompressWrite, is_uninit);
+        self.barriers.flush(self.share.get_device(), self.cmd);
+
+        unsafe {
+            cmd_decompress_memory(
+                self.cmd,
+                decompress.regions.len() as u32,
+                decompress.regions.as_ptr()
+            );
+        }
+
+        self.push_staging_with_access(decompress.staging_allocation, decompress.staging_buffer, decompress.staging_range.0, decompress.staging_range.1, gob::MESH_DECOMPRESS_SRC_STAGE, gob::MESH_DECOMPRESS_SRC_ACCESS);
+    }
+
+    fn record_global_image_clear(&mut self, clear: GlobalImageClear) {
+        let dst_image = clear.dst_image.get_image_handle();
+
+        let is_block_compressed = clear.dst_image.get_format().is_block_compressed();
+        self.transition_image(clear.dst_image, gob::ImageState::TransferWrite);
+
+        // Block compressed images cannot be cleared. Their contents stay undefined until written.
+        if is_block_compressed {
+            return;
+        }
+
+        self.barriers.flush(self.share.get_device(), self.cmd);
 
         unsafe {
             self.share.get_device().vk().cmd_clear_color_image(
@@ -608,12 +1359,13 @@ impl GlobalObjectsRecorder {
         }
     }
 
-    fn record_global_image_write(&mut self, write: GlobalImageWrite, is_uninit: bool) {
+    fn record_global_image_write(&mut self, write: GlobalImageWrite) {
         let dst_image = write.dst_image.get_image_handle();
 
-        self.transition_image(write.dst_image, gob::ImageState::TransferWrite, is_uninit);
+        self.transition_image(write.dst_image, gob::ImageState::TransferWrite);
 
         if !write.regions.is_empty() {
+            self.barriers.flush(self.share.get_device(), self.cmd);
             unsafe {
                 self.share.get_device().vk().cmd_copy_buffer_to_image(
                     self.cmd,
@@ -625,44 +1377,118 @@ impl GlobalObjectsRecorder {
             }
         }
 
-        self.push_staging(write.staging_allocation, write.staging_buffer, write.staging_range.0, write.staging_range.1);
+        match write.staging_allocation {
+            ImageWriteStaging::Pool(allocation) => {
+                self.push_staging(allocation, write.staging_buffer, write.staging_range.0, write.staging_range.1);
+            }
+            ImageWriteStaging::DynamicTexture(slot) => {
+                // The slot is rewritten by the host once released which happens after the
+                // recorder has finished execution so no barrier is needed here
+                self.dynamic_texture_slots.push(slot);
+            }
+        }
     }
 
-    fn record_global_image_generate_mipmaps(&mut self, image: Arc<GlobalImage>) {
-        let mip_levels = image.get_mip_levels();
+    fn record_global_image_copy(&mut self, copy: GlobalImageCopy) {
+        let src_image = copy.src_image.get_image_handle();
+        let dst_image = copy.dst_image.get_image_handle();
+
+        self.transition_image(copy.src_image, gob::ImageState::TransferRead);
+        self.transition_image(copy.dst_image, gob::ImageState::TransferWrite);
+
+        if !copy.regions.is_empty() {
+            self.barriers.flush(self.share.get_device(), self.cmd);
+            unsafe {
+                self.share.get_device().vk().cmd_copy_image(
+                    self.cmd,
+                    src_image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    dst_image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    copy.regions.as_ref()
+                );
+            }
+        }
+    }
+
+    fn record_global_image_read(&mut self, read: GlobalImageRead) {
+        let src_image = read.src_image.get_image_handle();
+
+        self.transition_image(read.src_image.clone(), gob::ImageState::TransferRead);
+        self.barriers.flush(self.share.get_device(), self.cmd);
+
+        let copy = vk::BufferImageCopy {
+            buffer_offset: read.staging.offset,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: read.mip_level,
+                base_array_layer: 0,
+                layer_count: 1
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D {
+                width: read.size[0],
+                height: read.size[1],
+                depth: 1
+            }
+        };
+
+        unsafe {
+            self.share.get_device().vk().cmd_copy_image_to_buffer(
+                self.cmd,
+                src_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                read.staging.buffer,
+                std::slice::from_ref(&copy)
+            );
+        }
+
+        self.staging_barriers.push(vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+            .dst_access_mask(vk::AccessFlags2::HOST_READ)
+            .buffer(read.staging.buffer)
+            .offset(read.staging.offset)
+            .size(read.staging_size)
+            .build()
+        );
+        self.global_image_reads.push(read);
+    }
+
+    fn record_global_image_generate_mipmaps(&mut self, image: Arc<GlobalImage>, level_count: u32) {
+        if image.get_mipmap_mode() == MipmapMode::AlphaWeighted {
+            if self.compute_pool.is_some() {
+                if let Some((_, pending_count)) = self.async_compute_mipmaps.iter_mut().find(|(pending, _)| pending == &image) {
+                    *pending_count = std::cmp::max(*pending_count, level_count);
+                } else {
+                    self.async_compute_mipmaps.push((image, level_count));
+                }
+            } else {
+                self.record_global_image_compute_mipmaps(image, level_count);
+            }
+            return;
+        }
+
+        let mip_levels = std::cmp::min(image.get_mip_levels(), level_count);
         if mip_levels > 1 {
             let handle = image.get_image_handle();
             let src_size = image.get_size();
             let mut src_size = Vec2i32::new(src_size[0] as i32, src_size[1] as i32);
 
-            self.transition_image(image, gob::ImageState::GenerateMipmaps, false);
+            self.transition_image(image.clone(), gob::ImageState::GenerateMipmaps);
 
             let device = self.share.get_device();
             for level in 1..mip_levels {
-                if level > 1 {
-                    let barrier = vk::ImageMemoryBarrier2::builder()
-                        .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
-                        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
-                        .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
-                        .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
-                        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
-                        .image(handle)
-                        .subresource_range(vk::ImageSubresourceRange {
-                            aspect_mask: vk::ImageAspectFlags::COLOR,
-                            base_mip_level: level - 1,
-                            level_count: 1,
-                            base_array_layer: 0,
-                            layer_count: 0
-                        });
-
-                    let info = vk::DependencyInfo::builder()
-                        .image_memory_barriers(std::slice::from_ref(&barrier));
-
-                    unsafe {
-                        device.synchronization_2_khr().cmd_pipeline_barrier2(self.cmd, &info);
-                    }
+                // The source level has just been written to unless it is the first level
+                self.tmp_image_barriers.clear();
+                self.image_layouts.transition(device.get_image_layouts(), handle, level - 1, 1, gob::IMAGE_TRANSFER_READ_ACCESS, &mut self.tmp_image_barriers);
+                for barrier in self.tmp_image_barriers.drain(..) {
+                    self.barriers.push_image_barrier(barrier);
                 }
+                self.barriers.flush(device, self.cmd);
 
                 let dst_size = Vec2i32::new(
                     if src_size[0] > 1 { src_size[0] / 2 } else { 1 },
@@ -701,35 +1527,232 @@ impl GlobalObjectsRecorder {
         }
     }
 
+    fn record_global_image_compute_mipmaps(&mut self, image: Arc<GlobalImage>, level_count: u32) {
+        if std::cmp::min(image.get_mip_levels(), level_count) <= 1 {
+            return;
+        }
+
+        self.transition_image(image.clone(), gob::ImageState::ComputeMipmaps);
+        self.barriers.flush(self.share.get_device(), self.cmd);
+
+        Self::record_compute_mipmap_levels(&self.share, self.cmd, &image, level_count);
+    }
+
+    /// Records the compute dispatches generating the mip levels `1..level_count`. The image must be
+    /// in the [`gob::ImageState::ComputeMipmaps`] state.
+    fn record_compute_mipmap_levels(share: &Share, cmd: vk::CommandBuffer, image: &GlobalImage, level_count: u32) {
+        let mip_levels = std::cmp::min(image.get_mip_levels(), level_count);
+        let handle = image.get_image_handle();
+        let srgb = image.get_format() == &Format::R8G8B8A8_SRGB;
+        let mut src_size = image.get_size();
+
+        let device = share.get_device();
+        let pipeline = share.get_alpha_mipmap_pipeline();
+        pipeline.bind(cmd);
+
+        for level in 1..mip_levels {
+            if level > 1 {
+                let barrier = vk::ImageMemoryBarrier2::builder()
+                    .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                    .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                    .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ)
+                    .old_layout(vk::ImageLayout::GENERAL)
+                    .new_layout(vk::ImageLayout::GENERAL)
+                    .image(handle)
+                    .subresource_range(vk::ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: level - 1,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1
+                    });
+
+                let info = vk::DependencyInfo::builder()
+                    .image_memory_barriers(std::slice::from_ref(&barrier));
+
+                unsafe {
+                    device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &info);
+                }
+            }
+
+            let dst_size = Vec2u32::new(std::cmp::max(src_size[0] / 2, 1), std::cmp::max(src_size[1] / 2, 1));
+            pipeline.record_level(
+                cmd,
+                image.get_mip_storage_view(level - 1),
+                src_size,
+                image.get_mip_storage_view(level),
+                dst_size,
+                srgb
+            );
+
+            src_size = dst_size;
+        }
+    }
+
+    /// Records and submits the copies of [`GlobalObjectsRecorder::async_transfer_writes`] to their
+    /// async transfer queues. Must be called before [`GlobalObjectsRecorder::record`] whose submit
+    /// waits on them.
+    fn submit_async_transfers(&mut self, bump: &Bump) -> VkResult<()> {
+        let transfer = match &self.transfer {
+            Some(transfer) => transfer.clone(),
+            None => return Ok(()),
+        };
+        if self.async_transfer_meshes.is_empty() {
+            return Ok(());
+        }
+        let _span = b4d_span!("submit_async_transfers");
+
+        let device = self.share.get_device().clone();
+        let mut objects = PooledObjectProvider::new(self.share.clone(), transfer.pool.clone());
+
+        for (queue_index, writes) in self.async_transfer_writes.iter().enumerate() {
+            if writes.is_empty() {
+                continue;
+            }
+
+            let cmd = objects.get_begin_command_buffer()?;
+            for write in writes {
+                unsafe {
+                    device.vk().cmd_copy_buffer(cmd, write.staging_buffer, write.dst_mesh.get_buffer_handle(), write.regions.as_ref());
+                }
+            }
+            unsafe {
+                device.vk().end_command_buffer(cmd)
+            }?;
+
+            let signal_semaphore = self.object_pool.get_semaphore();
+            let cmd_info = bump.alloc(vk::CommandBufferSubmitInfo::builder()
+                .command_buffer(cmd)
+                .build()
+            );
+            let signal_info = bump.alloc(vk::SemaphoreSubmitInfo::builder()
+                .semaphore(signal_semaphore)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .build()
+            );
+
+            let submit = vk::SubmitInfo2::builder()
+                .command_buffer_infos(std::slice::from_ref(cmd_info))
+                .signal_semaphore_infos(std::slice::from_ref(signal_info))
+                .build();
+
+            unsafe {
+                transfer.scheduler.get_queue(queue_index).submit_2(std::slice::from_ref(&submit), None)
+            }?;
+            self.async_transfer_semaphores.push(signal_semaphore);
+        }
+
+        self.async_transfer_objects = Some(objects);
+
+        Ok(())
+    }
+
+    /// Returns true if this recorder has work for the async compute queue. If so the submits
+    /// recorded by [`GlobalObjectsRecorder::record`] must be submitted before calling
+    /// [`GlobalObjectsRecorder::submit_async_compute`].
+    fn has_async_compute(&self) -> bool {
+        !self.async_compute_mipmaps.is_empty()
+    }
+
+    /// Records and submits the async compute work. Returns a semaphore which is signaled once the
+    /// work has completed.
+    fn submit_async_compute(&mut self, bump: &Bump, markers: &ProgressMarkers, marker: ProgressMarker) -> VkResult<vk::Semaphore> {
+        let _span = b4d_span!("submit_async_compute");
+
+        let device = self.share.get_device().clone();
+        let queue = device.get_async_compute_queue().unwrap();
+        let wait_semaphore = self.async_compute_wait.unwrap();
+        let signal_semaphore = self.object_pool.get_semaphore();
+
+        let mut objects = PooledObjectProvider::new(self.share.clone(), self.compute_pool.clone().unwrap());
+        let cmd = objects.get_begin_command_buffer()?;
+        markers.write(cmd, MarkerQueue::AsyncCompute, marker);
+
+        // The layout transitions have been performed on the main queue and the semaphores provide
+        // the execution and memory dependencies so only the layout transitions are needed here.
+        self.tmp_image_barriers.clear();
+        for (image, _) in &self.async_compute_mipmaps {
+            self.tmp_image_barriers.push(gob::make_async_compute_barrier(image.get_image_handle(), true));
+        }
+        let info = vk::DependencyInfo::builder()
+            .image_memory_barriers(self.tmp_image_barriers.as_slice());
+        unsafe {
+            device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &info);
+        }
+
+        for (image, level_count) in &self.async_compute_mipmaps {
+            Self::record_compute_mipmap_levels(&self.share, cmd, image, *level_count);
+        }
+
+        self.tmp_image_barriers.clear();
+        for (image, _) in &self.async_compute_mipmaps {
+            self.tmp_image_barriers.push(gob::make_async_compute_barrier(image.get_image_handle(), false));
+        }
+        let info = vk::DependencyInfo::builder()
+            .image_memory_barriers(self.tmp_image_barriers.as_slice());
+        unsafe {
+            device.synchronization_2_khr().cmd_pipeline_barrier2(cmd, &info);
+            device.vk().end_command_buffer(cmd)?;
+        }
+
+        let cmd_info = bump.alloc(vk::CommandBufferSubmitInfo::builder()
+            .command_buffer(cmd)
+            .build()
+        );
+        let wait_info = bump.alloc(vk::SemaphoreSubmitInfo::builder()
+            .semaphore(wait_semaphore)
+            .stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .build()
+        );
+        let signal_info = bump.alloc(vk::SemaphoreSubmitInfo::builder()
+            .semaphore(signal_semaphore)
+            .stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+            .build()
+        );
+
+        let submit = vk::SubmitInfo2::builder()
+            .command_buffer_infos(std::slice::from_ref(cmd_info))
+            .wait_semaphore_infos(std::slice::from_ref(wait_info))
+            .signal_semaphore_infos(std::slice::from_ref(signal_info));
+
+        unsafe {
+            queue.submit_2(std::slice::from_ref(&submit), None)
+        }?;
+
+        self.async_compute_objects = Some(objects);
+
+        Ok(signal_semaphore)
+    }
+
     fn record<'a>(&mut self, recorder: &mut SubmitRecorder<'a>, bump: &'a Bump) {
         let buffer_post_barriers = self.generate_buffer_post_barriers();
         let image_post_barriers = self.generate_image_post_barriers();
 
         let device = self.share.get_device();
 
-        if !buffer_post_barriers.is_empty() || !image_post_barriers.is_empty() {
-            let buffer_post_barriers = buffer_post_barriers.as_slice();
-            let image_post_barriers = image_post_barriers.as_slice();
-
-            // If we have too many barriers in a single command the driver may fail to record (Yes this limit has been hit at 4000 barriers during testing in minecraft)
-            const CHUNK_SIZE: usize = 256;
-            let chunk_count = std::cmp::max((buffer_post_barriers.len() / CHUNK_SIZE) + 1, (image_post_barriers.len() / CHUNK_SIZE) + 1);
-            for chunk in 0..chunk_count {
-                let min = chunk * CHUNK_SIZE;
-                let max = min + CHUNK_SIZE;
-                let mut info = vk::DependencyInfo::builder();
-                if min < buffer_post_barriers.len() {
-                    let max = std::cmp::min(max, buffer_post_barriers.len());
-                    info = info.buffer_memory_barriers(&buffer_post_barriers[min..max]);
-                }
-                if min < image_post_barriers.len() {
-                    let max = std::cmp::min(max, image_post_barriers.len());
-                    info = info.image_memory_barriers(&image_post_barriers[min..max]);
-                }
+        for barrier in buffer_post_barriers {
+            self.barriers.push_buffer_barrier(barrier);
+        }
+        for barrier in image_post_barriers {
+            self.barriers.push_image_barrier(barrier);
+        }
+        self.barriers.flush(device, self.cmd);
 
-                unsafe {
-                    device.synchronization_2_khr().cmd_pipeline_barrier2(self.cmd, &info);
-                }
+        if !self.async_transfer_semaphores.is_empty() {
+            // Semaphore waits only apply to their own batch. Extend the async transfer wait to all
+            // later submits.
+            let barrier = vk::MemoryBarrier2::builder()
+                .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .src_access_mask(vk::AccessFlags2::NONE)
+                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE);
+
+            let info = vk::DependencyInfo::builder()
+                .memory_barriers(std::slice::from_ref(&barrier));
+
+            unsafe {
+                device.synchronization_2_khr().cmd_pipeline_barrier2(self.cmd, &info);
             }
         }
 
@@ -745,9 +1768,30 @@ impl GlobalObjectsRecorder {
             .build()
         );
 
-        recorder.push(vk::SubmitInfo2::builder()
+        let wait_infos = bump.alloc_slice_fill_iter(self.async_transfer_semaphores.iter().map(|semaphore| {
+            vk::SemaphoreSubmitInfo::builder()
+                .semaphore(*semaphore)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .build()
+        }));
+
+        let submit = vk::SubmitInfo2::builder()
             .command_buffer_infos(std::slice::from_ref(cmd_info))
-        );
+            .wait_semaphore_infos(wait_infos);
+
+        if self.has_async_compute() {
+            let semaphore = self.object_pool.get_semaphore();
+            self.async_compute_wait = Some(semaphore);
+
+            let signal_info = bump.alloc(vk::SemaphoreSubmitInfo::builder()
+                .semaphore(semaphore)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .build()
+            );
+            recorder.push(submit.signal_semaphore_infos(std::slice::from_ref(signal_info)));
+        } else {
+            recorder.push(submit);
+        }
     }
 
     fn generate_buffer_post_barriers(&mut self) -> Vec<vk::BufferMemoryBarrier2> {
@@ -764,34 +1808,29 @@ impl GlobalObjectsRecorder {
 
     fn generate_image_post_barriers(&mut self) -> Vec<vk::ImageMemoryBarrier2> {
         let mut barriers: Vec<vk::ImageMemoryBarrier2> = Vec::new();
-
-        for (image, old_state) in &self.used_global_images {
-            let handle = image.get_image_handle();
-            let mip_levels = image.get_mip_levels();
-
-            gob::generate_image_barriers(*old_state, gob::ImageState::Ready, handle, mip_levels, &mut barriers);
-        }
+        self.image_layouts.finish(self.share.get_device().get_image_layouts(), &mut barriers);
 
         barriers
     }
 
     fn push_staging(&mut self, alloc: StagingAllocationId, buffer: vk::Buffer, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        self.push_staging_with_access(alloc, buffer, offset, size, vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ);
+    }
+
+    /// Like [`GlobalObjectsRecorder::push_staging`] for staging memory read by something other
+    /// than a transfer operation.
+    fn push_staging_with_access(&mut self, alloc: StagingAllocationId, buffer: vk::Buffer, offset: vk::DeviceSize, size: vk::DeviceSize, src_stage: vk::PipelineStageFlags2, src_access: vk::AccessFlags2) {
         self.staging_allocations.push(alloc);
-        let barrier = vk::BufferMemoryBarrier2::builder()
-            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
-            .src_access_mask(vk::AccessFlags2::TRANSFER_READ)
+        self.staging_barriers.push(vk::BufferMemoryBarrier2::builder()
+            .src_stage_mask(src_stage)
+            .src_access_mask(src_access)
             .dst_stage_mask(vk::PipelineStageFlags2::HOST)
             .dst_access_mask(vk::AccessFlags2::HOST_WRITE)
             .buffer(buffer)
             .offset(offset)
-            .size(size);
-
-        let info = vk::DependencyInfo::builder()
-            .buffer_memory_barriers(std::slice::from_ref(&barrier));
-
-        unsafe {
-            self.share.get_device().synchronization_2_khr().cmd_pipeline_barrier2(self.cmd, &info)
-        };
+            .size(size)
+            .build()
+        );
     }
 
     /// Transitions a mesh to a new state and adds it to the used mesh list.
@@ -813,43 +1852,27 @@ impl GlobalObjectsRecorder {
         self.tmp_buffer_barriers.clear();
         gob::generate_mesh_barriers(old_state, new_state, handle, &mut self.tmp_buffer_barriers);
 
-        if !self.tmp_buffer_barriers.is_empty() {
-            let info = vk::DependencyInfo::builder()
-                .buffer_memory_barriers(self.tmp_buffer_barriers.as_slice());
-
-            unsafe {
-                self.share.get_device().synchronization_2_khr().cmd_pipeline_barrier2(self.cmd, &info);
-            }
+        for barrier in self.tmp_buffer_barriers.drain(..) {
+            self.barriers.push_buffer_barrier(barrier);
         }
     }
 
-    /// Transitions a image to a new state and adds it to the used image list.
-    ///
-    /// If the image is not in the used image list the image is currently either uninitialized or
-    /// ready. In that case if maybe_uninit is set the image is assumed to be uninitialized otherwise
-    /// it is assumed to be in the ready state.
-    fn transition_image(&mut self, image: Arc<GlobalImage>, new_state: gob::ImageState, maybe_uninit: bool) {
-        let handle = image.get_image_handle();
-        let mip_levels = image.get_mip_levels();
-
-        let old_state = self.used_global_images.insert(image, new_state).unwrap_or_else(|| {
-            if maybe_uninit {
-                gob::ImageState::Uninitialized
-            } else {
-                gob::ImageState::Ready
-            }
-        });
+    /// Transitions a image to a new state and adds it to the used image list. The previous layout
+    /// of the image is provided by the layout tracker of the device.
+    fn transition_image(&mut self, image: Arc<GlobalImage>, new_state: gob::ImageState) {
+        if let Some(index) = self.async_compute_mipmaps.iter().position(|(pending, _)| pending == &image) {
+            // The image is used again after its mipmaps have been requested. Generate them on this
+            // queue to preserve the order of operations.
+            let (pending, level_count) = self.async_compute_mipmaps.swap_remove(index);
+            self.record_global_image_compute_mipmaps(pending, level_count);
+        }
 
         self.tmp_image_barriers.clear();
-        gob::generate_image_barriers(old_state, new_state, handle, mip_levels, &mut self.tmp_image_barriers);
-
-        if !self.tmp_image_barriers.is_empty() {
-            let info = vk::DependencyInfo::builder()
-                .image_memory_barriers(self.tmp_image_barriers.as_slice());
+        gob::transition_image(&mut self.image_layouts, self.share.get_device().get_image_layouts(), image.get_image_handle(), image.get_mip_levels(), new_state, &mut self.tmp_image_barriers);
+        self.used_global_images.insert(image);
 
-            unsafe {
-                self.share.get_device().synchronization_2_khr().cmd_pipeline_barrier2(self.cmd, &info);
-            }
+        for barrier in self.tmp_image_barriers.drain(..) {
+            self.barriers.push_image_barrier(barrier);
         }
     }
 }
@@ -864,6 +1887,22 @@ impl Drop for GlobalObjectsRecorder {
         for allocation in std::mem::replace(&mut self.staging_allocations, Vec::new()) {
             guard.free(allocation);
         }
+
+        // We are only dropped after all submitted commands have finished execution
+        for read in std::mem::replace(&mut self.global_image_reads, Vec::new()) {
+            read.staging.invalidate(self.share.get_device());
+            let data = &read.staging.as_bytes()[..(read.staging_size as usize)];
+            let _ = read.sender.send(GlobalImageData {
+                mip_level: read.mip_level,
+                size: read.size,
+                format: read.src_image.get_format().get_format(),
+                data: data.into(),
+            });
+            guard.free(read.staging_allocation);
+        }
+
+        drop(guard);
+        self.share.notify_staging_freed();
     }
 }
 
@@ -872,6 +1911,8 @@ mod gob {
 
     use ash::vk;
 
+    use crate::objects::image_layout::{ImageAccess, ImageLayoutScope, ImageLayoutTracker};
+
     #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
     pub(super) enum MeshState {
         /// Mesh has not been initialized yet
@@ -880,6 +1921,8 @@ mod gob {
         Ready,
         /// Mesh was previously written to
         TransferWrite,
+        /// Mesh was previously written to by a device decompression
+        DecompressWrite,
     }
 
     pub(super) fn generate_mesh_barriers(old_state: MeshState, new_state: MeshState, buffer: vk::Buffer, barriers: &mut Vec<vk::BufferMemoryBarrier2>) {
@@ -901,12 +1944,14 @@ mod gob {
                 barrier = match old {
                     MeshState::Uninitialized => panic!(), // Impossible
                     MeshState::Ready => MESH_READY_INFO().write_src(barrier),
-                    MeshState::TransferWrite => MESH_TRANSFER_WRITE_INFO.write_src(barrier)
+                    MeshState::TransferWrite => MESH_TRANSFER_WRITE_INFO.write_src(barrier),
+                    MeshState::DecompressWrite => MESH_DECOMPRESS_WRITE_INFO.write_src(barrier),
                 };
                 barrier = match new {
                     MeshState::Uninitialized => panic!(), // Impossible
                     MeshState::Ready => MESH_READY_INFO().write_dst(barrier),
-                    MeshState::TransferWrite => MESH_TRANSFER_WRITE_INFO.write_dst(barrier)
+                    MeshState::TransferWrite => MESH_TRANSFER_WRITE_INFO.write_dst(barrier),
+                    MeshState::DecompressWrite => MESH_DECOMPRESS_WRITE_INFO.write_dst(barrier),
                 };
 
                 barriers.push(barrier.build());
@@ -921,6 +1966,11 @@ mod gob {
     }
     const MESH_TRANSFER_WRITE_INFO: BufferAccessInfo = BufferAccessInfo::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE);
 
+    // VK_NV_memory_decompression executes as compute work accessing memory as storage buffers
+    const MESH_DECOMPRESS_WRITE_INFO: BufferAccessInfo = BufferAccessInfo::new(vk::PipelineStageFlags2::COMPUTE_SHADER, vk::AccessFlags2::SHADER_STORAGE_WRITE);
+    pub(super) const MESH_DECOMPRESS_SRC_STAGE: vk::PipelineStageFlags2 = vk::PipelineStageFlags2::COMPUTE_SHADER;
+    pub(super) const MESH_DECOMPRESS_SRC_ACCESS: vk::AccessFlags2 = vk::AccessFlags2::SHADER_STORAGE_READ;
+
     struct BufferAccessInfo {
         stage_mask: vk::PipelineStageFlags2,
         access_mask: vk::AccessFlags2,
@@ -950,233 +2000,84 @@ mod gob {
         }
     }
 
+    /// The states global images are transitioned into while recording. At the end of a recorder
+    /// all images are returned into [`IMAGE_READY_ACCESS`].
     #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
     pub(super) enum ImageState {
-        /// Image has not been initialized yet
-        Uninitialized,
-        /// Image is ready to be used for rendering
-        Ready,
-        /// Image was previously written to
+        /// Image is written to
         TransferWrite,
-        /// Image had previously generated its mipmaps
+        /// Image is copied from
+        TransferRead,
+        /// Image generates its mipmaps. The first mip level is read and all other levels are
+        /// written to.
         GenerateMipmaps,
+        /// Image generates its mipmaps using a compute pass
+        ComputeMipmaps,
     }
 
-    pub(super) fn generate_image_barriers(old_state: ImageState, new_state: ImageState, image: vk::Image, mip_levels: u32, barriers: &mut Vec<vk::ImageMemoryBarrier2>) {
-        match (old_state, new_state) {
-            (ImageState::Uninitialized, ImageState::TransferWrite) => {
-                let mut barrier = vk::ImageMemoryBarrier2::builder()
-                    .image(image)
-                    .subresource_range(make_full_subresource_range(vk::ImageAspectFlags::COLOR));
-                barrier = IMAGE_UNINITIALIZED_INFO.write_src(barrier);
-                barrier = IMAGE_TRANSFER_WRITE_INFO.write_dst(barrier);
-
-                barriers.push(barrier.build());
-            }
-            (ImageState::Ready, ImageState::TransferWrite) => {
-                let mut barrier = vk::ImageMemoryBarrier2::builder()
-                    .image(image)
-                    .subresource_range(make_full_subresource_range(vk::ImageAspectFlags::COLOR));
-                barrier = IMAGE_READY_INFO.write_src(barrier);
-                barrier = IMAGE_TRANSFER_WRITE_INFO.write_dst(barrier);
-
-                barriers.push(barrier.build());
-            }
-            (ImageState::Ready, ImageState::GenerateMipmaps) => {
-                let mut barrier0 = vk::ImageMemoryBarrier2::builder()
-                    .image(image)
-                    .subresource_range(make_first_mip_subresource_range(vk::ImageAspectFlags::COLOR));
-                barrier0 = IMAGE_READY_INFO.write_src(barrier0);
-                barrier0 = IMAGE_GENERATE_MIPMAPS_0_INFO.write_dst(barrier0);
-
-                barriers.push(barrier0.build());
-
-                let mut barrier1 = vk::ImageMemoryBarrier2::builder()
-                    .image(image)
-                    .subresource_range(make_exclude_first_mips_subresource_range(vk::ImageAspectFlags::COLOR));
-                barrier1 = IMAGE_READY_INFO.write_src(barrier1);
-                barrier1 = IMAGE_GENERATE_MIPMAPS_1_INFO.write_dst(barrier1);
-
-                barriers.push(barrier1.build());
-            }
-            (ImageState::TransferWrite, ImageState::Ready) => {
-                let mut barrier = vk::ImageMemoryBarrier2::builder()
-                    .image(image)
-                    .subresource_range(make_full_subresource_range(vk::ImageAspectFlags::COLOR));
-                barrier = IMAGE_TRANSFER_WRITE_INFO.write_src(barrier);
-                barrier = IMAGE_READY_INFO.write_dst(barrier);
-
-                barriers.push(barrier.build());
-            }
-            (ImageState::TransferWrite, ImageState::TransferWrite) => {
-                let mut barrier = vk::ImageMemoryBarrier2::builder()
-                    .image(image)
-                    .subresource_range(make_full_subresource_range(vk::ImageAspectFlags::COLOR));
-                barrier = IMAGE_TRANSFER_WRITE_INFO.write_src(barrier);
-                barrier = IMAGE_TRANSFER_WRITE_INFO.write_dst(barrier);
-
-                barriers.push(barrier.build());
-            }
-            (ImageState::TransferWrite, ImageState::GenerateMipmaps) => {
-                let mut barrier0 = vk::ImageMemoryBarrier2::builder()
-                    .image(image)
-                    .subresource_range(make_first_mip_subresource_range(vk::ImageAspectFlags::COLOR));
-                barrier0 = IMAGE_TRANSFER_WRITE_INFO.write_src(barrier0);
-                barrier0 = IMAGE_GENERATE_MIPMAPS_0_INFO.write_dst(barrier0);
-
-                barriers.push(barrier0.build());
-
-                let mut barrier1 = vk::ImageMemoryBarrier2::builder()
-                    .image(image)
-                    .subresource_range(make_exclude_first_mips_subresource_range(vk::ImageAspectFlags::COLOR));
-                barrier1 = IMAGE_TRANSFER_WRITE_INFO.write_src(barrier1);
-                barrier1 = IMAGE_GENERATE_MIPMAPS_1_INFO.write_dst(barrier1);
-
-                barriers.push(barrier1.build());
-            }
-            (ImageState::GenerateMipmaps, ImageState::Ready) => {
-                let mut barrier0 = vk::ImageMemoryBarrier2::builder()
-                    .image(image)
-                    .subresource_range(make_exclude_last_mips_subresource_range(vk::ImageAspectFlags::COLOR, mip_levels));
-                barrier0 = IMAGE_GENERATE_MIPMAPS_0_INFO.write_src(barrier0);
-                barrier0 = IMAGE_READY_INFO.write_dst(barrier0);
-
-                barriers.push(barrier0.build());
-
-                let mut barrier1 = vk::ImageMemoryBarrier2::builder()
-                    .image(image)
-                    .subresource_range(make_last_mip_subresource_range(vk::ImageAspectFlags::COLOR, mip_levels));
-                barrier1 = IMAGE_GENERATE_MIPMAPS_1_INFO.write_src(barrier1);
-                barrier1 = IMAGE_READY_INFO.write_dst(barrier1);
-
-                barriers.push(barrier1.build());
-            }
-            (ImageState::GenerateMipmaps, ImageState::TransferWrite) => {
-                let mut barrier0 = vk::ImageMemoryBarrier2::builder()
-                    .image(image)
-                    .subresource_range(make_exclude_last_mips_subresource_range(vk::ImageAspectFlags::COLOR, mip_levels));
-                barrier0 = IMAGE_GENERATE_MIPMAPS_0_INFO.write_src(barrier0);
-                barrier0 = IMAGE_TRANSFER_WRITE_INFO.write_dst(barrier0);
-
-                barriers.push(barrier0.build());
-
-                let mut barrier1 = vk::ImageMemoryBarrier2::builder()
-                    .image(image)
-                    .subresource_range(make_last_mip_subresource_range(vk::ImageAspectFlags::COLOR, mip_levels));
-                barrier1 = IMAGE_GENERATE_MIPMAPS_1_INFO.write_src(barrier1);
-                barrier1 = IMAGE_TRANSFER_WRITE_INFO.write_dst(barrier1);
-
-                barriers.push(barrier1.build());
-            }
-            (ImageState::Ready, ImageState::Ready) => {
-                log::warn!("Transitioned image from ready to ready. Why?");
-            }
-            (ImageState::Uninitialized, new) => {
-                log::error!("Image cannot be transitioned from uninitialized to {:?}", new);
-                panic!();
-            }
-            (old, ImageState::Uninitialized) => {
-                log::error!("Image cannot be transitioned into uninitialized (was {:?})", old);
-                panic!();
-            }
-            (ImageState::GenerateMipmaps, ImageState::GenerateMipmaps) => {
-                log::error!("Image cannot be transitioned from generate mipmaps to generate mipmaps");
-                panic!();
+    /// Pushes the barriers transitioning all mip levels of the image into the state.
+    pub(super) fn transition_image(scope: &mut ImageLayoutScope, tracker: &ImageLayoutTracker, image: vk::Image, mip_levels: u32, state: ImageState, barriers: &mut Vec<vk::ImageMemoryBarrier2>) {
+        match state {
+            ImageState::TransferWrite => scope.transition(tracker, image, 0, mip_levels, IMAGE_TRANSFER_WRITE_ACCESS, barriers),
+            ImageState::TransferRead => scope.transition(tracker, image, 0, mip_levels, IMAGE_TRANSFER_READ_ACCESS, barriers),
+            ImageState::GenerateMipmaps => {
+                scope.transition(tracker, image, 0, 1, IMAGE_TRANSFER_READ_ACCESS, barriers);
+                if mip_levels > 1 {
+                    scope.transition(tracker, image, 1, mip_levels - 1, IMAGE_TRANSFER_WRITE_ACCESS, barriers);
+                }
             }
+            ImageState::ComputeMipmaps => scope.transition(tracker, image, 0, mip_levels, IMAGE_COMPUTE_MIPMAPS_ACCESS(), barriers),
         }
     }
 
-    #[inline]
-    fn make_full_subresource_range(aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
-        vk::ImageSubresourceRange {
-            aspect_mask,
-            base_mip_level: 0,
-            level_count: vk::REMAINING_MIP_LEVELS,
-            base_array_layer: 0,
-            layer_count: vk::REMAINING_ARRAY_LAYERS
-        }
-    }
-
-    #[inline]
-    fn make_exclude_last_mips_subresource_range(aspect_mask: vk::ImageAspectFlags, mip_levels: u32) -> vk::ImageSubresourceRange {
-        vk::ImageSubresourceRange {
-            aspect_mask,
-            base_mip_level: 0,
-            level_count: mip_levels - 1,
-            base_array_layer: 0,
-            layer_count: vk::REMAINING_ARRAY_LAYERS
-        }
-    }
-
-    #[inline]
-    fn make_last_mip_subresource_range(aspect_mask: vk::ImageAspectFlags, mip_levels: u32) -> vk::ImageSubresourceRange {
-        vk::ImageSubresourceRange {
-            aspect_mask,
-            base_mip_level: mip_levels - 1,
-            level_count: 1,
-            base_array_layer: 0,
-            layer_count: vk::REMAINING_ARRAY_LAYERS
-        }
-    }
-
-    #[inline]
-    fn make_exclude_first_mips_subresource_range(aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
-        vk::ImageSubresourceRange {
-            aspect_mask,
-            base_mip_level: 1,
-            level_count: vk::REMAINING_MIP_LEVELS,
-            base_array_layer: 0,
-            layer_count: vk::REMAINING_ARRAY_LAYERS,
-        }
-    }
-
-    #[inline]
-    fn make_first_mip_subresource_range(aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
-        vk::ImageSubresourceRange {
-            aspect_mask,
-            base_mip_level: 0,
-            level_count: 1,
-            base_array_layer: 0,
-            layer_count: vk::REMAINING_ARRAY_LAYERS,
+    /// Returns the layout transition between the ready state and the compute mipmaps state
+    /// performed on the async compute queue. If `acquire` is true the image is transitioned into
+    /// the compute mipmaps state otherwise back into the ready state.
+    ///
+    /// The stages of the ready state are not supported on compute queues. The required
+    /// dependencies are instead provided by the semaphores around the async compute submit. Since
+    /// the image is returned into the ready state the layout tracker does not need to be updated.
+    pub(super) fn make_async_compute_barrier(image: vk::Image, acquire: bool) -> vk::ImageMemoryBarrier2 {
+        let compute = IMAGE_COMPUTE_MIPMAPS_ACCESS();
+        let barrier = vk::ImageMemoryBarrier2::builder()
+            .image(image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: vk::REMAINING_MIP_LEVELS,
+                base_array_layer: 0,
+                layer_count: vk::REMAINING_ARRAY_LAYERS
+            });
+
+        if acquire {
+            barrier
+                .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                .src_access_mask(vk::AccessFlags2::NONE)
+                .old_layout(IMAGE_READY_ACCESS.layout)
+                .dst_stage_mask(compute.stage_mask)
+                .dst_access_mask(compute.access_mask)
+                .new_layout(compute.layout)
+                .build()
+        } else {
+            barrier
+                .src_stage_mask(compute.stage_mask)
+                .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                .old_layout(compute.layout)
+                .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                .dst_access_mask(vk::AccessFlags2::NONE)
+                .new_layout(IMAGE_READY_ACCESS.layout)
+                .build()
         }
     }
 
-    const IMAGE_UNINITIALIZED_INFO: ImageAccessInfo = ImageAccessInfo::new(vk::PipelineStageFlags2::NONE, vk::AccessFlags2::NONE, vk::ImageLayout::UNDEFINED);
-    const IMAGE_READY_INFO: ImageAccessInfo = ImageAccessInfo::new(vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
-    const IMAGE_TRANSFER_WRITE_INFO: ImageAccessInfo = ImageAccessInfo::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
-    const IMAGE_GENERATE_MIPMAPS_0_INFO: ImageAccessInfo = ImageAccessInfo::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
-    const IMAGE_GENERATE_MIPMAPS_1_INFO: ImageAccessInfo = ImageAccessInfo::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
-
-    struct ImageAccessInfo {
-        stage_mask: vk::PipelineStageFlags2,
-        access_mask: vk::AccessFlags2,
-        layout: vk::ImageLayout,
-    }
+    /// The state global images are in between recorders.
+    pub(super) const IMAGE_READY_ACCESS: ImageAccess = ImageAccess::new(vk::PipelineStageFlags2::FRAGMENT_SHADER, vk::AccessFlags2::SHADER_SAMPLED_READ, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    pub(super) const IMAGE_TRANSFER_WRITE_ACCESS: ImageAccess = ImageAccess::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_WRITE, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+    pub(super) const IMAGE_TRANSFER_READ_ACCESS: ImageAccess = ImageAccess::new(vk::PipelineStageFlags2::TRANSFER, vk::AccessFlags2::TRANSFER_READ, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
 
-    impl ImageAccessInfo {
-        #[inline]
-        const fn new(stage_mask: vk::PipelineStageFlags2, access_mask: vk::AccessFlags2, layout: vk::ImageLayout) -> Self {
-            Self {
-                stage_mask,
-                access_mask,
-                layout
-            }
-        }
-
-        #[inline]
-        fn write_src<'a>(&self, barrier: vk::ImageMemoryBarrier2Builder<'a>) -> vk::ImageMemoryBarrier2Builder<'a> {
-            barrier
-                .src_stage_mask(self.stage_mask)
-                .src_access_mask(self.access_mask)
-                .old_layout(self.layout)
-        }
-
-        #[inline]
-        fn write_dst<'a>(&self, barrier: vk::ImageMemoryBarrier2Builder<'a>) -> vk::ImageMemoryBarrier2Builder<'a> {
-            barrier
-                .dst_stage_mask(self.stage_mask)
-                .dst_access_mask(self.access_mask)
-                .new_layout(self.layout)
-        }
+    // This needs to be a function because of the bitor. Waiting for const impl
+    #[allow(non_snake_case)]
+    fn IMAGE_COMPUTE_MIPMAPS_ACCESS() -> ImageAccess {
+        ImageAccess::new(vk::PipelineStageFlags2::COMPUTE_SHADER, vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE, vk::ImageLayout::GENERAL)
     }
 }
\ No newline at end of file
@@ -0,0 +1,225 @@
+//! World border rendering mirroring minecrafts `LevelRenderer::renderWorldBorder`.
+//!
+//! The border is made of up to 4 walls close to the camera built from 1 block wide quads with a
+//! scrolling texture. The walls fade in as the camera approaches the border. They should be drawn
+//! using a render layer with [`BlendState::WORLD_BORDER`](super::pipeline::BlendState::WORLD_BORDER),
+//! [`DepthBias::WORLD_BORDER`](super::pipeline::DepthBias::WORLD_BORDER), culling disabled and a
+//! shader created with [`WorldBorder::VERTEX_FORMAT`] binding the forcefield texture.
+
+use ash::vk;
+use bytemuck::{cast_slice, Pod, Zeroable};
+
+use crate::prelude::*;
+use crate::renderer::emulator::MeshData;
+use crate::renderer::emulator::mc_shaders::{VertexFormat, VertexFormatEntry};
+
+/// Determines the color of the border.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum WorldBorderStatus {
+    Growing,
+    Shrinking,
+    Stationary,
+}
+
+impl WorldBorderStatus {
+    pub fn get_color(&self) -> [u8; 3] {
+        match self {
+            WorldBorderStatus::Growing => [0x40, 0xFF, 0x80],
+            WorldBorderStatus::Shrinking => [0xFF, 0x30, 0x30],
+            WorldBorderStatus::Stationary => [0x20, 0xA0, 0xFF],
+        }
+    }
+}
+
+/// Per frame parameters provided by the host.
+#[derive(Copy, Clone, Debug)]
+pub struct WorldBorderParams {
+    /// The x and z coordinates of the center of the border.
+    pub center: Vec2f32,
+
+    /// The side length of the border.
+    pub size: f32,
+
+    /// The distance from the border at which the walls start to fade in. Vanilla uses the render
+    /// distance in blocks.
+    pub warning_distance: f32,
+    pub status: WorldBorderStatus,
+
+    /// The camera position in world space. The generated vertices are relative to it.
+    pub camera_position: Vec3f32,
+
+    /// The distance from the camera up to which the walls are generated. Vanilla uses the far
+    /// plane distance.
+    pub far_distance: f32,
+
+    /// Used to animate the texture. The animation repeats every 3 seconds.
+    pub time_millis: u64,
+}
+
+impl WorldBorderParams {
+    /// Returns the distance from the camera to the closest side of the border. Is negative if the
+    /// camera is outside of the border.
+    pub fn get_distance_to_border(&self) -> f32 {
+        let camera = &self.camera_position;
+        let half_size = self.size * 0.5f32;
+        let dx = half_size - (camera[0] - self.center[0]).abs();
+        let dz = half_size - (camera[2] - self.center[1]).abs();
+        dx.min(dz)
+    }
+}
+
+/// The world border quads for a single frame.
+pub struct WorldBorder {
+    vertices: Vec<WorldBorderVertex>,
+}
+
+impl WorldBorder {
+    pub const VERTEX_FORMAT: VertexFormat = VertexFormat {
+        stride: std::mem::size_of::<WorldBorderVertex>() as u32,
+        position: VertexFormatEntry { offset: 0, format: vk::Format::R32G32B32_SFLOAT },
+        normal: None,
+        color: Some(VertexFormatEntry { offset: 20, format: vk::Format::R8G8B8A8_UNORM }),
+        uv0: Some(VertexFormatEntry { offset: 12, format: vk::Format::R32G32_SFLOAT }),
+        uv1: None,
+        uv2: None,
+        joint_indices: None,
+        joint_weights: None,
+    };
+
+    pub fn build(params: &WorldBorderParams) -> Self {
+        let mut border = Self {
+            vertices: Vec::new(),
+        };
+
+        let visible_distance = params.warning_distance;
+        if visible_distance <= 0f32 || params.get_distance_to_border() >= visible_distance {
+            return border;
+        }
+
+        let fade = (1f32 - params.get_distance_to_border() / visible_distance).clamp(0f32, 1f32).powi(4);
+        let [r, g, b] = params.status.get_color();
+        let color = [r, g, b, (fade * 255f32).round() as u8];
+
+        let camera = &params.camera_position;
+        let half_size = params.size * 0.5f32;
+        let min = Vec2f32::new(params.center[0] - half_size, params.center[1] - half_size);
+        let max = Vec2f32::new(params.center[0] + half_size, params.center[1] + half_size);
+
+        let far = params.far_distance;
+        let scroll = ((params.time_millis % 3000) as f32) / 3000f32;
+        let v_top = scroll - (camera[1] * 0.5f32).fract();
+        let v_bottom = v_top + far;
+
+        let z_range = (min[1].max((camera[2] - far).floor()), max[1].min((camera[2] + far).ceil()));
+        let x_range = (min[0].max((camera[0] - far).floor()), max[0].min((camera[0] + far).ceil()));
+
+        let mut wall = |fixed: f32, range: (f32, f32), along_z: bool, u_sign: f32| {
+            let mut u_offset = (((range.0.floor() as i64) & 1) as f32) * 0.5f32;
+            let mut start = range.0;
+            while start < range.1 {
+                let length = (range.1 - start).min(1f32);
+                let u_start = scroll + u_sign * u_offset;
+                let u_end = scroll + u_sign * (u_offset + length * 0.5f32);
+
+                let position = |along: f32, y: f32| if along_z {
+                    [fixed - camera[0], y, along - camera[2]]
+                } else {
+                    [along - camera[0], y, fixed - camera[2]]
+                };
+                border.vertices.extend_from_slice(&[
+                    WorldBorderVertex { position: position(start, -far), uv0: [u_start, v_bottom], color },
+                    WorldBorderVertex { position: position(start + length, -far), uv0: [u_end, v_bottom], color },
+                    WorldBorderVertex { position: position(start + length, far), uv0: [u_end, v_top], color },
+                    WorldBorderVertex { position: position(start, far), uv0: [u_start, v_top], color },
+                ]);
+
+                start += 1f32;
+                u_offset += 0.5f32;
+            }
+        };
+
+        if camera[0] > max[0] - visible_distance {
+            wall(max[0], z_range, true, -1f32);
+        }
+        if camera[0] < min[0] + visible_distance {
+            wall(min[0], z_range, true, 1f32);
+        }
+        if camera[2] > max[1] - visible_distance {
+            wall(max[1], x_range, false, 1f32);
+        }
+        if camera[2] < min[1] + visible_distance {
+            wall(min[1], x_range, false, -1f32);
+        }
+
+        border
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub(super) fn as_mesh_data(&self) -> MeshData<'_> {
+        MeshData::new_quads(cast_slice(&self.vertices), Self::VERTEX_FORMAT.stride)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct WorldBorderVertex {
+    position: [f32; 3],
+    uv0: [f32; 2],
+    color: [u8; 4],
+}
+const_assert_eq!(std::mem::size_of::<WorldBorderVertex>(), 24);
+
+unsafe impl Zeroable for WorldBorderVertex {}
+unsafe impl Pod for WorldBorderVertex {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(camera_position: Vec3f32) -> WorldBorderParams {
+        WorldBorderParams {
+            center: Vec2f32::new(0f32, 0f32),
+            size: 100f32,
+            warning_distance: 16f32,
+            status: WorldBorderStatus::Shrinking,
+            camera_position,
+            far_distance: 8f32,
+            time_millis: 1500,
+        }
+    }
+
+    #[test]
+    fn hidden_far_from_border() {
+        let border = WorldBorder::build(&params(Vec3f32::new(0f32, 64f32, 0f32)));
+        assert!(border.is_empty());
+    }
+
+    #[test]
+    fn single_wall() {
+        let params = params(Vec3f32::new(45.5f32, 64f32, 0.5f32));
+        assert_eq!(params.get_distance_to_border(), 4.5f32);
+
+        let border = WorldBorder::build(&params);
+        // Wall along the z axis from -8 to 9
+        assert_eq!(border.vertices.len(), 17 * 4);
+        for vertex in &border.vertices {
+            assert_eq!(vertex.position[0], 4.5f32);
+            assert_eq!(&vertex.color[0..3], &[0xFF, 0x30, 0x30]);
+        }
+
+        let alpha = border.vertices[0].color[3];
+        let closer = WorldBorder::build(&WorldBorderParams { camera_position: Vec3f32::new(49f32, 64f32, 0.5f32), ..params });
+        assert!(closer.vertices[0].color[3] > alpha);
+    }
+
+    #[test]
+    fn corner() {
+        let border = WorldBorder::build(&params(Vec3f32::new(-47f32, 64f32, 47f32)));
+        // Both walls are clipped to the border
+        assert_eq!(border.vertices.len(), 2 * 11 * 4);
+        assert_eq!(border.as_mesh_data().index_count, 2 * 11 * 6);
+    }
+}
@@ -153,6 +153,16 @@ macro_rules! define_formats {
             }
         }
 
+        /// Returns [`None`] instead of panicking if the format is unknown.
+        pub const fn try_format_for(format: vk::Format) -> Option<&'static Format> {
+            match format {
+                $(
+                ash::vk::Format::$name => Some(&Self::$name),
+                )+
+                _ => None
+            }
+        }
+
         $(pub const $name : Format = Format::new(ash::vk::Format::$name, $compatibility_class, $channel_count, $clear_color_type);)+
     }
 }
@@ -178,6 +188,12 @@ impl Format {
         self.compatibility_class == other.compatibility_class
     }
 
+    /// Returns true if the format stores blocks of texels instead of individual texels.
+    pub fn is_block_compressed(&self) -> bool {
+        let name = self.compatibility_class.get_name();
+        name.starts_with("BC") || name.starts_with("ETC2") || name.starts_with("EAC") || name.starts_with("ASTC")
+    }
+
     define_formats!(
     R4G4_UNORM_PACK8, CompatibilityClass::BIT8, 2, Some(ClearColorType::Float);
     R4G4B4A4_UNORM_PACK16, CompatibilityClass::BIT16, 4, Some(ClearColorType::Float);
@@ -46,6 +46,29 @@ impl UUID {
     }
 }
 
+/// Generates ids from a single sequence seeded with a fixed seed independent of the thread creating
+/// them. As long as ids are created in the same order the same ids are returned on every run.
+///
+/// Only ids created through this generator are affected, [`UUID::new`] keeps using the per thread
+/// generators. Ids of generators using the same seed collide so they must not be mixed. This is
+/// intended for reproducing bugs only.
+pub struct SeededUUIDs {
+    seeder: Mutex<Xoshiro256PlusPlus>,
+}
+
+impl SeededUUIDs {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seeder: Mutex::new(Xoshiro256PlusPlus::from_seed([seed, !seed, 1u64, 1u64])),
+        }
+    }
+
+    pub fn next(&self) -> UUID {
+        let id = self.seeder.lock().unwrap().find(|id| *id != 0u64).unwrap();
+        UUID(NonZeroU64::new(id).unwrap())
+    }
+}
+
 impl Debug for UUID {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("UUID({:#016X})", self.get_raw()))
@@ -238,4 +261,23 @@ macro_rules! define_uuid_type {
     }
 }
 
-pub use define_uuid_type;
\ No newline at end of file
+pub use define_uuid_type;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_sequence() {
+        let first = SeededUUIDs::new(5);
+        let second = SeededUUIDs::new(5);
+        let other = SeededUUIDs::new(6);
+
+        let first_ids: Vec<_> = (0..4).map(|_| first.next()).collect();
+        let second_ids: Vec<_> = (0..4).map(|_| second.next()).collect();
+        let other_ids: Vec<_> = (0..4).map(|_| other.next()).collect();
+
+        assert_eq!(first_ids, second_ids);
+        assert_ne!(first_ids, other_ids);
+    }
+}
@@ -0,0 +1,39 @@
+//! Tracing instrumentation utilities.
+//!
+//! If the `tracing` feature is enabled spans are emitted using the `tracing` crate so that profilers
+//! like Tracy or chrome-trace can show where frame time is spent across threads. Otherwise all
+//! spans compile to nothing.
+
+/// Creates and enters a span. The span is exited when the returned guard is dropped.
+///
+/// Accepts the same arguments as `tracing::info_span!`.
+#[cfg(feature = "tracing")]
+macro_rules! b4d_span {
+    ($($arg:tt)*) => {
+        ::tracing::info_span!($($arg)*).entered()
+    };
+}
+
+/// Creates and enters a span. The span is exited when the returned guard is dropped.
+///
+/// The `tracing` feature is disabled so this does nothing.
+#[cfg(not(feature = "tracing"))]
+macro_rules! b4d_span {
+    ($($arg:tt)*) => {
+        $crate::util::trace::NoopSpanGuard
+    };
+}
+
+pub(crate) use b4d_span;
+
+/// Placeholder span guard used if the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+pub struct NoopSpanGuard;
+
+/// Sets the global default subscriber receiving all spans emitted by Blaze4D.
+///
+/// Returns false if a global default subscriber has already been set.
+#[cfg(feature = "tracing")]
+pub fn set_global_subscriber<S: tracing::Subscriber + Send + Sync + 'static>(subscriber: S) -> bool {
+    tracing::subscriber::set_global_default(subscriber).is_ok()
+}
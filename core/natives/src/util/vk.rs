@@ -20,4 +20,15 @@ pub fn make_full_rect(size: Vec2u32) -> vk::Rect2D {
         offset: vk::Offset2D{ x: 0, y: 0 },
         extent: vk::Extent2D{ width: size[0], height: size[1] }
     }
+}
+
+#[inline]
+pub fn make_full_subresource_range(aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask,
+        base_mip_level: 0,
+        level_count: vk::REMAINING_MIP_LEVELS,
+        base_array_layer: 0,
+        layer_count: vk::REMAINING_ARRAY_LAYERS,
+    }
 }
\ No newline at end of file
@@ -1,34 +1,113 @@
-use std::ffi::{CString};
-use std::sync::Arc;
+use std::ffi::{CStr, CString};
+use std::sync::{Arc, Mutex};
 
 use ash::vk;
 
-use crate::{B4D_CORE_VERSION_MAJOR, B4D_CORE_VERSION_MINOR, B4D_CORE_VERSION_PATCH};
+use crate::BUILD_INFO;
 
 use crate::device::init::{create_device, DeviceCreateConfig};
+use crate::instance::debug_messenger::{DebugMessengerCallback, RustLogDebugMessenger};
 use crate::instance::init::{create_instance, InstanceCreateConfig};
 
 use crate::prelude::*;
 
-pub fn make_headless_instance() -> Arc<InstanceContext> {
+fn make_instance_config() -> InstanceCreateConfig {
     let mut config = InstanceCreateConfig::new(
         CString::new("B4D Tests").unwrap(),
-        vk::make_api_version(0, B4D_CORE_VERSION_MAJOR, B4D_CORE_VERSION_MINOR, B4D_CORE_VERSION_PATCH)
+        vk::make_api_version(0, BUILD_INFO.version_major, BUILD_INFO.version_minor, BUILD_INFO.version_patch)
     );
     config.enable_validation();
 
     // The LunarG desktop profile requires the swapchain extension which in turn requires the surface extensions
     config.require_surface_khr();
 
-    create_instance(config).unwrap()
+    config
 }
 
-pub fn make_headless_instance_device() -> (Arc<InstanceContext>, Arc<DeviceContext>) {
-    let instance = make_headless_instance();
-
+fn make_device(instance: Arc<InstanceContext>) -> Arc<DeviceContext> {
     let mut config = DeviceCreateConfig::new();
     config.disable_robustness(); // We do this in b4d so we should use it for our tests as well
-    let device = create_device(config, instance.clone()).unwrap();
+    create_device(config, instance).unwrap()
+}
+
+pub fn make_headless_instance() -> Arc<InstanceContext> {
+    create_instance(make_instance_config()).unwrap()
+}
+
+pub fn make_headless_instance_device() -> (Arc<InstanceContext>, Arc<DeviceContext>) {
+    let instance = make_headless_instance();
+    let device = make_device(instance.clone());
 
     (instance, device)
+}
+
+/// Creates a headless instance and device which collect all validation warnings and errors.
+///
+/// The returned [`ValidationMessages`] can be used to fail a test if any message was reported.
+pub fn make_validated_instance_device() -> (Arc<InstanceContext>, Arc<DeviceContext>, Arc<ValidationMessages>) {
+    let messages = Arc::new(ValidationMessages::new());
+
+    let mut config = make_instance_config();
+    config.add_debug_messenger(Box::new(RustLogDebugMessenger::new()));
+    config.add_debug_messenger(Box::new(ValidationCollector(messages.clone())));
+    let instance = create_instance(config).unwrap();
+    let device = make_device(instance.clone());
+
+    (instance, device, messages)
+}
+
+/// Runs a test with a validated headless device.
+///
+/// After the test function returns the device is waited on to idle. If any validation warnings or
+/// errors were reported during the test it fails with the list of messages.
+pub fn run_validated<F: FnOnce(&Arc<DeviceContext>)>(test: F) {
+    let (_instance, device, messages) = make_validated_instance_device();
+
+    test(&device);
+
+    unsafe {
+        device.vk().device_wait_idle()
+    }.unwrap();
+
+    messages.assert_empty();
+}
+
+/// A list of validation messages collected during a test.
+#[derive(Debug)]
+pub struct ValidationMessages {
+    messages: Mutex<Vec<String>>,
+}
+
+impl ValidationMessages {
+    fn new() -> Self {
+        Self {
+            messages: Mutex::new(Vec::new())
+        }
+    }
+
+    /// Removes and returns all collected messages.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.messages.lock().unwrap())
+    }
+
+    /// Panics with the list of collected messages if any message was collected.
+    pub fn assert_empty(&self) {
+        let messages = self.take();
+        if !messages.is_empty() {
+            panic!("{} validation message(s) were reported:\n{}", messages.len(), messages.join("\n"));
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ValidationCollector(Arc<ValidationMessages>);
+
+impl DebugMessengerCallback for ValidationCollector {
+    fn on_message(&self, message_severity: vk::DebugUtilsMessageSeverityFlagsEXT, message_types: vk::DebugUtilsMessageTypeFlagsEXT, message: &CStr, _: &vk::DebugUtilsMessengerCallbackDataEXT) {
+        let severity = vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR;
+        if message_severity.intersects(severity) && message_types.contains(vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+            let message = format!("[{:?}] {}", message_severity, message.to_string_lossy());
+            self.0.messages.lock().unwrap().push(message);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,178 @@
+use std::ffi::{CStr, CString};
+use ash::{Entry, Instance, vk};
+use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::event::WindowEvent;
+use winit::event_loop::EventLoop;
+use winit::window::{WindowBuilder, WindowId};
+
+use crate::prelude::*;
+use crate::vk::objects::surface::{SurfaceInitError, SurfaceProvider};
+
+pub mod r#virtual;
+
+pub struct WinitWindow {
+    handle: winit::window::Window,
+    ash_surface: Option<ash::extensions::khr::Surface>,
+    khr_surface: Option<vk::SurfaceKHR>,
+}
+
+impl WinitWindow {
+    pub fn new<E>(title: &str, width: f64, height: f64, event_loop: &EventLoop<E>) -> Self {
+        Self::new_with_transparency(title, width, height, false, event_loop)
+    }
+
+    /// Creates a new window. If `transparent` is true the window background will be transparent
+    /// allowing the output to be composited with transparency.
+    pub fn new_with_transparency<E>(title: &str, width: f64, height: f64, transparent: bool, event_loop: &EventLoop<E>) -> Self {
+        let window = WindowBuilder::new()
+            .with_title(title)
+            .with_inner_size(LogicalSize::new(width, height))
+            .with_transparent(transparent)
+            .build(&event_loop)
+            .unwrap();
+        window.set_visible(true);
+
+        Self {
+            handle: window,
+            ash_surface: None,
+            khr_surface: None,
+        }
+    }
+
+    pub fn get_id(&self) -> WindowId {
+        self.handle.id()
+    }
+
+    /// Returns the size of the window in logical pixels.
+    pub fn get_logical_size(&self) -> LogicalSize<f64> {
+        self.handle.inner_size().to_logical(self.handle.scale_factor())
+    }
+
+    /// Returns the size of the window framebuffer in physical pixels. This is the size that should
+    /// be passed when starting a frame.
+    pub fn get_framebuffer_size(&self) -> Vec2u32 {
+        let size = self.handle.inner_size();
+        Vec2u32::new(size.width, size.height)
+    }
+
+    pub fn get_scale_factor(&self) -> f64 {
+        self.handle.scale_factor()
+    }
+
+    /// Creates a [`FramebufferTracker`] initialized to the current state of this window.
+    ///
+    /// Since the window is usually moved into [`crate::b4d::Blaze4D`] the tracker should be created
+    /// before that.
+    pub fn create_framebuffer_tracker(&self) -> FramebufferTracker {
+        FramebufferTracker {
+            window_id: self.get_id(),
+            framebuffer_size: self.get_framebuffer_size(),
+            scale_factor: self.get_scale_factor(),
+        }
+    }
+}
+
+impl SurfaceProvider for WinitWindow {
+    fn get_required_instance_extensions(&self) -> Vec<CString> {
+        ash_window::enumerate_required_extensions(&self.handle).unwrap().into_iter().map(|str| {
+            CString::from(unsafe { CStr::from_ptr(*str) })
+        }).collect()
+    }
+
+    fn init(&mut self, entry: &Entry, instance: &Instance) -> Result<vk::SurfaceKHR, SurfaceInitError> {
+        let surface = unsafe { ash_window::create_surface(entry, instance, &self.handle, None)? };
+
+        self.khr_surface = Some(surface);
+        self.ash_surface = Some(ash::extensions::khr::Surface::new(entry, instance));
+
+        Ok(surface)
+    }
+
+    fn get_handle(&self) -> Option<vk::SurfaceKHR> {
+        self.khr_surface
+    }
+}
+
+impl Drop for WinitWindow {
+    fn drop(&mut self) {
+        if let Some(surface) = self.khr_surface.take() {
+            let khr = self.ash_surface.take().unwrap();
+            unsafe { khr.destroy_surface(surface, None) };
+        }
+    }
+}
+
+/// Events emitted by a [`FramebufferTracker`] when the framebuffer of a window changes.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FramebufferEvent {
+    /// The physical size of the framebuffer changed.
+    Resized(Vec2u32),
+    /// The scale factor of the window changed. This usually happens if the window is moved to a
+    /// different monitor or the monitor configuration changes. The framebuffer size may also have
+    /// changed as a result.
+    ScaleFactorChanged {
+        scale_factor: f64,
+        framebuffer_size: Vec2u32,
+    },
+}
+
+/// Keeps track of the physical framebuffer size and scale factor of a [`WinitWindow`] by processing
+/// its window events.
+///
+/// On HiDPI setups (for example macOS or Wayland) the logical window size and the physical
+/// framebuffer size differ. Frames must always be started with the physical framebuffer size.
+pub struct FramebufferTracker {
+    window_id: WindowId,
+    framebuffer_size: Vec2u32,
+    scale_factor: f64,
+}
+
+impl FramebufferTracker {
+    /// Processes a window event. Events for other windows are ignored.
+    ///
+    /// Returns a [`FramebufferEvent`] if the event changed the framebuffer of the window.
+    pub fn handle_event(&mut self, window_id: WindowId, event: &WindowEvent) -> Option<FramebufferEvent> {
+        if window_id != self.window_id {
+            return None;
+        }
+
+        match event {
+            WindowEvent::Resized(size) => {
+                let size = Self::to_vec(size);
+                if size != self.framebuffer_size {
+                    self.framebuffer_size = size;
+                    Some(FramebufferEvent::Resized(size))
+                } else {
+                    None
+                }
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+                self.scale_factor = *scale_factor;
+                self.framebuffer_size = Self::to_vec(new_inner_size);
+                Some(FramebufferEvent::ScaleFactorChanged {
+                    scale_factor: self.scale_factor,
+                    framebuffer_size: self.framebuffer_size
+                })
+            }
+            _ => None
+        }
+    }
+
+    /// Returns the size of the framebuffer in physical pixels.
+    pub fn get_framebuffer_size(&self) -> Vec2u32 {
+        self.framebuffer_size
+    }
+
+    /// Returns the size of the window in logical pixels.
+    pub fn get_logical_size(&self) -> LogicalSize<f64> {
+        PhysicalSize::new(self.framebuffer_size[0], self.framebuffer_size[1]).to_logical(self.scale_factor)
+    }
+
+    pub fn get_scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    fn to_vec(size: &PhysicalSize<u32>) -> Vec2u32 {
+        Vec2u32::new(size.width, size.height)
+    }
+}
@@ -0,0 +1,66 @@
+//! Offscreen window backend.
+//!
+//! Provides a [`SurfaceProvider`] backed by `VK_EXT_headless_surface`. No os window is created and
+//! the swapchain images are never displayed which allows the swapchain and frame code paths to run
+//! under CI or inside the headless test harness.
+
+use std::ffi::CString;
+
+use ash::{Entry, Instance, vk};
+
+use crate::prelude::*;
+use crate::vk::objects::surface::{SurfaceInitError, SurfaceProvider};
+
+pub struct VirtualWindow {
+    size: Vec2u32,
+    surface: Option<(vk::SurfaceKHR, ash::extensions::khr::Surface)>,
+}
+
+impl VirtualWindow {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            size: Vec2u32::new(width, height),
+            surface: None,
+        }
+    }
+
+    /// Returns the size of the virtual window.
+    ///
+    /// Headless surfaces do not report a current extent so this should be used as the swapchain
+    /// extent instead.
+    pub fn get_size(&self) -> Vec2u32 {
+        self.size
+    }
+}
+
+impl SurfaceProvider for VirtualWindow {
+    fn get_required_instance_extensions(&self) -> Vec<CString> {
+        vec![
+            CString::from(ash::extensions::khr::Surface::name()),
+            CString::from(ash::extensions::ext::HeadlessSurface::name()),
+        ]
+    }
+
+    fn init(&mut self, entry: &Entry, instance: &Instance) -> Result<vk::SurfaceKHR, SurfaceInitError> {
+        let headless_surface = ash::extensions::ext::HeadlessSurface::new(entry, instance);
+        let surface_khr = ash::extensions::khr::Surface::new(entry, instance);
+
+        let info = vk::HeadlessSurfaceCreateInfoEXT::builder();
+        let surface = unsafe { headless_surface.create_headless_surface(&info, None)? };
+        self.surface = Some((surface, surface_khr));
+
+        Ok(surface)
+    }
+
+    fn get_handle(&self) -> Option<vk::SurfaceKHR> {
+        self.surface.as_ref().map(|s| s.0)
+    }
+}
+
+impl Drop for VirtualWindow {
+    fn drop(&mut self) {
+        if let Some((surface, khr)) = self.surface.take() {
+            unsafe { khr.destroy_surface(surface, None) };
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Tests the deterministic mode of the emulator renderer.
+//!
+//! These tests require a vulkan capable device and are therefore ignored by default. Run them with
+//! `cargo test -- --ignored`.
+
+mod test_common;
+
+use b4d_core::renderer::emulator::DeterministicConfig;
+use b4d_core::renderer::emulator::mc_shaders::McUniform;
+use b4d_core::renderer::emulator::replay::FrameReplayer;
+
+#[test]
+#[ignore]
+fn two_deterministic_renderers() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let config = DeterministicConfig::new(42);
+    let format = test_common::Vertex::make_b4d_vertex_format();
+
+    let first = FrameReplayer::new_headless_deterministic(true, &config).unwrap();
+    let first_shader = first.get_emulator().create_shader(&format, McUniform::MODEL_VIEW_MATRIX);
+
+    // Only one deterministic renderer may be alive at a time
+    assert!(FrameReplayer::new_headless_deterministic(true, &config).is_none());
+
+    // Ids of other renderers must not be taken from the seeded sequence
+    let concurrent = FrameReplayer::new_headless(true);
+    let concurrent_shader = concurrent.get_emulator().create_shader(&format, McUniform::MODEL_VIEW_MATRIX);
+    let second_shader = first.get_emulator().create_shader(&format, McUniform::MODEL_VIEW_MATRIX);
+    drop(concurrent);
+
+    drop(first);
+
+    // The same seed must generate the same ids again once the first renderer is gone
+    let third = FrameReplayer::new_headless_deterministic(true, &config).unwrap();
+    let third_shader = third.get_emulator().create_shader(&format, McUniform::MODEL_VIEW_MATRIX);
+    assert_eq!(first_shader, third_shader);
+    assert_eq!(second_shader, third.get_emulator().create_shader(&format, McUniform::MODEL_VIEW_MATRIX));
+    assert_ne!(concurrent_shader, second_shader);
+    drop(third);
+
+    // Regular renderers always use the per thread generators
+    let regular = FrameReplayer::new_headless(true);
+    let regular_shader = regular.get_emulator().create_shader(&format, McUniform::MODEL_VIEW_MATRIX);
+    assert_ne!(regular_shader, first_shader);
+}
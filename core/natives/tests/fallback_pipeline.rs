@@ -0,0 +1,48 @@
+//! Tests the fallback pipeline used if the debug pipeline cannot be created.
+//!
+//! These tests require a vulkan capable device and are therefore ignored by default. Run them with
+//! `cargo test -- --ignored`.
+
+mod test_common;
+
+use b4d_core::prelude::*;
+use b4d_core::renderer::emulator::fallback_pipeline::FallbackPipeline;
+use b4d_core::renderer::emulator::replay::FrameReplayer;
+
+const OUTPUT_SIZE: (u32, u32) = (128, 128);
+
+fn get_texel(data: &[u8], x: u32, y: u32) -> &[u8] {
+    let index = ((y * OUTPUT_SIZE.0 + x) * 4) as usize;
+    &data[index..(index + 4)]
+}
+
+#[test]
+#[ignore]
+fn fallback_triangle() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let size = Vec2u32::new(OUTPUT_SIZE.0, OUTPUT_SIZE.1);
+    let replayer = FrameReplayer::new_headless(true);
+    let pipeline = FallbackPipeline::new(replayer.get_emulator().clone(), size).unwrap();
+
+    let image = replayer.replay_with_pipeline(&test_common::make_triangle_scene(size), pipeline).unwrap();
+    assert_eq!(image.size, size);
+
+    // The triangle covers the center of the output but not its corners
+    let background = get_texel(&image.data, 0, 0);
+    let center = get_texel(&image.data, OUTPUT_SIZE.0 / 2, OUTPUT_SIZE.1 / 2);
+    assert_ne!(center, background);
+    assert_eq!(center[3], 255);
+}
+
+#[test]
+#[ignore]
+fn fallback_output_size_mismatch() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let replayer = FrameReplayer::new_headless(true);
+    let pipeline = FallbackPipeline::new(replayer.get_emulator().clone(), Vec2u32::new(64, 64)).unwrap();
+
+    let capture = test_common::make_triangle_scene(Vec2u32::new(OUTPUT_SIZE.0, OUTPUT_SIZE.1));
+    assert!(replayer.replay_with_pipeline(&capture, pipeline).is_err());
+}
@@ -0,0 +1,72 @@
+//! Golden image regression tests.
+//!
+//! These tests require a vulkan capable device and are therefore ignored by default. Run them with
+//! `cargo test -- --ignored`. References are stored in `tests/golden`. A missing reference fails the
+//! test. Run the tests with `B4D_UPDATE_GOLDEN=1` to create or update the references and review
+//! them before committing them.
+
+mod test_common;
+
+use std::path::PathBuf;
+
+use b4d_core::prelude::*;
+use b4d_core::renderer::emulator::capture::FrameCapture;
+use b4d_core::renderer::emulator::debug_pipeline::DebugPipelineMode;
+use b4d_core::renderer::emulator::golden::{check_golden, is_update_requested, GoldenResult, GoldenTolerance};
+use b4d_core::renderer::emulator::replay::FrameReplayer;
+
+const OUTPUT_SIZE: (u32, u32) = (256, 256);
+
+const TESTED_MODES: &[(DebugPipelineMode, &str)] = &[
+    (DebugPipelineMode::Depth, "depth"),
+    (DebugPipelineMode::Position, "position"),
+    (DebugPipelineMode::Color, "color"),
+    (DebugPipelineMode::UV0, "uv0"),
+];
+
+fn get_golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn run_golden_test(scene_name: &str, capture: &FrameCapture) {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let replayer = FrameReplayer::new_headless(true);
+    let tolerance = GoldenTolerance::default();
+
+    let mut failures = Vec::new();
+    for (mode, mode_name) in TESTED_MODES {
+        let image = replayer.replay(capture, *mode).unwrap();
+
+        let name = format!("{}_{}", scene_name, mode_name);
+        match check_golden(&get_golden_dir(), &name, &image, &tolerance).unwrap() {
+            GoldenResult::Match => {},
+            GoldenResult::Created(path) if is_update_requested() => log::warn!("Updated golden reference {:?}", path),
+            GoldenResult::Created(path) => {
+                failures.push(format!("{}: unexpectedly created reference {:?}", name, path));
+            }
+            GoldenResult::Missing { actual_path } => {
+                failures.push(format!("{}: no reference exists, see {:?}. Run with B4D_UPDATE_GOLDEN=1 to create it", name, actual_path));
+            }
+            GoldenResult::Mismatch { mismatch_fraction, max_delta_e, diff_path, .. } => {
+                failures.push(format!("{}: {:.3}% of pixels differ (max delta E {:.1}), see {:?}", name, mismatch_fraction * 100.0, max_delta_e, diff_path));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!("Golden image mismatch:\n{}", failures.join("\n"));
+    }
+}
+
+#[test]
+#[ignore]
+fn golden_triangle() {
+    run_golden_test("triangle", &test_common::make_triangle_scene(Vec2u32::new(OUTPUT_SIZE.0, OUTPUT_SIZE.1)));
+}
+
+#[test]
+#[ignore]
+fn golden_cubes() {
+    run_golden_test("cubes", &test_common::make_cube_scene(Vec2u32::new(OUTPUT_SIZE.0, OUTPUT_SIZE.1)));
+}
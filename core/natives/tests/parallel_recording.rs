@@ -0,0 +1,67 @@
+//! Tests recording a pass from multiple threads using sub recorders.
+//!
+//! These tests require a vulkan capable device and are therefore ignored by default. Run them with
+//! `cargo test -- --ignored`.
+
+mod test_common;
+
+use std::sync::Arc;
+
+use b4d_core::prelude::*;
+use b4d_core::renderer::emulator::debug_pipeline::{DebugPipeline, DebugPipelineMode};
+use b4d_core::renderer::emulator::mc_shaders::{McUniform, McUniformData};
+use b4d_core::renderer::emulator::pipeline::EmulatorPipeline;
+use b4d_core::renderer::emulator::replay::FrameReplayer;
+
+const THREAD_COUNT: u32 = 4;
+const DRAWS_PER_THREAD: u32 = 64;
+
+#[test]
+#[ignore]
+fn parallel_sub_recorders() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let replayer = FrameReplayer::new_headless(true);
+    let emulator = replayer.get_emulator();
+    let size = Vec2u32::new(64, 64);
+    let pipeline: Arc<dyn EmulatorPipeline> = DebugPipeline::new(emulator.clone(), DebugPipelineMode::Color, size).unwrap();
+    let shader = emulator.create_shader(&test_common::Vertex::make_b4d_vertex_format(), McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX);
+
+    let mesh = test_common::make_cube_mesh();
+    let global_mesh = emulator.create_global_mesh(&mesh.as_mesh_data());
+
+    let mut recorder = emulator.start_pass(pipeline);
+    recorder.update_uniform(&McUniformData::ProjectionMatrix(test_common::make_projection_matrix(size, 90f32)), shader);
+
+    let threads: Vec<_> = (0..THREAD_COUNT).map(|thread| {
+        let mut sub_recorder = recorder.create_sub_recorder();
+        let mesh = mesh.clone();
+        let global_mesh = global_mesh.clone();
+        std::thread::spawn(move || {
+            let id = sub_recorder.upload_immediate(&mesh.as_mesh_data());
+            for index in 0..DRAWS_PER_THREAD {
+                let translation = Mat4f32::new_translation(&Vec3f32::new(thread as f32, 0f32, 5f32 + index as f32));
+                sub_recorder.update_uniform(&McUniformData::ModelViewMatrix(translation), shader);
+                if index % 2 == 0 {
+                    sub_recorder.draw_immediate(id, shader, true);
+                } else {
+                    sub_recorder.draw_global(global_mesh.clone(), shader, true);
+                }
+            }
+            sub_recorder
+        })
+    }).collect();
+
+    for thread in threads {
+        recorder.merge_sub_recorder(thread.join().unwrap());
+    }
+    drop(recorder);
+
+    assert_eq!(emulator.get_statistics().draw_count, THREAD_COUNT * DRAWS_PER_THREAD);
+
+    // Waits for the pass to complete
+    replayer.replay(&test_common::make_empty_scene(Vec2u32::new(16, 16)), DebugPipelineMode::Color).unwrap();
+
+    drop(global_mesh);
+    emulator.drop_shader(shader);
+}
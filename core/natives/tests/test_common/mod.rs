@@ -0,0 +1,150 @@
+//! Scenes shared by the integration tests and benchmarks.
+
+#![allow(dead_code)]
+
+use ash::vk;
+use bytemuck::{cast_slice, Pod, Zeroable};
+
+use b4d_core::prelude::*;
+use b4d_core::renderer::emulator::capture::{CaptureCommand, CapturedMesh, CapturedShader, FrameCapture};
+use b4d_core::renderer::emulator::mc_shaders::{McUniform, McUniformData, VertexFormat, VertexFormatEntry};
+use b4d_core::renderer::emulator::MeshData;
+
+#[derive(Copy, Clone)]
+pub struct Vertex {
+    #[allow(unused)]
+    pub position: Vec3f32,
+    #[allow(unused)]
+    pub color: Vec4f32,
+    #[allow(unused)]
+    pub uv: Vec2f32,
+}
+
+unsafe impl Zeroable for Vertex {}
+unsafe impl Pod for Vertex {}
+
+impl Vertex {
+    pub fn make_b4d_vertex_format() -> VertexFormat {
+        VertexFormat {
+            stride: std::mem::size_of::<Vertex>() as u32,
+            position: VertexFormatEntry { offset: 0, format: vk::Format::R32G32B32_SFLOAT },
+            normal: None,
+            color: Some(VertexFormatEntry { offset: std::mem::size_of::<Vec3f32>() as u32, format: vk::Format::R32G32B32A32_SFLOAT }),
+            uv0: Some(VertexFormatEntry { offset: std::mem::size_of::<Vec3f32>() as u32 + std::mem::size_of::<Vec4f32>() as u32, format: vk::Format::R32G32_SFLOAT }),
+            uv1: None,
+            uv2: None,
+            joint_indices: None,
+            joint_weights: None,
+        }
+    }
+}
+
+pub fn make_projection_matrix(size: Vec2u32, fov: f32) -> Mat4f32 {
+    let t = (fov / 2f32).tan();
+    let a1 = (size[1] as f32) / (size[0] as f32);
+
+    let f = 15f32;
+    let n = 0.5f32;
+
+    Mat4f32::new(
+        a1 / t, 0f32, 0f32, 0f32,
+        0f32, 1f32 / t, 0f32, 0f32,
+        0f32, 0f32, f / (f - n), -n * (f - n),
+        0f32, 0f32, 1f32, 0f32
+    )
+}
+
+fn make_mesh(vertices: &[Vertex], indices: &[u32]) -> CapturedMesh {
+    CapturedMesh::from_mesh_data(&MeshData {
+        vertex_data: cast_slice(vertices),
+        index_data: cast_slice(indices),
+        vertex_stride: std::mem::size_of::<Vertex>() as u32,
+        index_count: indices.len() as u32,
+        index_type: vk::IndexType::UINT32,
+        primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+    })
+}
+
+fn make_capture(size: Vec2u32, mesh: CapturedMesh, model_views: &[Mat4f32]) -> FrameCapture {
+    let mut commands = vec![
+        CaptureCommand::UpdateUniform { shader: 0, data: McUniformData::ProjectionMatrix(make_projection_matrix(size, 90f32)) },
+    ];
+    for (index, model_view) in model_views.iter().enumerate() {
+        commands.push(CaptureCommand::UpdateUniform { shader: 0, data: McUniformData::ModelViewMatrix(*model_view) });
+        commands.push(CaptureCommand::UploadImmediate(mesh.clone()));
+        commands.push(CaptureCommand::DrawImmediate { mesh: index as u32, shader: 0, depth_write_enable: true });
+    }
+
+    FrameCapture {
+        output_size: size,
+        shaders: vec![CapturedShader {
+            vertex_format: Vertex::make_b4d_vertex_format(),
+            instance_format: None,
+            used_uniforms: McUniform::MODEL_VIEW_MATRIX | McUniform::PROJECTION_MATRIX,
+        }],
+        images: Vec::new(),
+        global_meshes: Vec::new(),
+        commands,
+    }
+}
+
+/// A single colored triangle covering the center of the output.
+pub fn make_triangle_scene(size: Vec2u32) -> FrameCapture {
+    let vertices = [
+        Vertex { position: Vec3f32::new(-1f32, -1f32, 0f32), color: Vec4f32::new(1f32, 0f32, 0f32, 1f32), uv: Vec2f32::new(0f32, 0f32) },
+        Vertex { position: Vec3f32::new(1f32, -1f32, 0f32), color: Vec4f32::new(0f32, 1f32, 0f32, 1f32), uv: Vec2f32::new(1f32, 0f32) },
+        Vertex { position: Vec3f32::new(0f32, 1f32, 0f32), color: Vec4f32::new(0f32, 0f32, 1f32, 1f32), uv: Vec2f32::new(0.5f32, 1f32) },
+    ];
+
+    make_capture(size, make_mesh(&vertices, &[0, 1, 2]), &[Mat4f32::new_translation(&Vec3f32::new(0f32, 0f32, 3f32))])
+}
+
+/// A cube with per vertex colors centered at the origin with a side length of 2.
+pub fn make_cube_mesh() -> CapturedMesh {
+    let mut vertices = Vec::with_capacity(8);
+    for index in 0..8u32 {
+        let x = (index & 1) as f32;
+        let y = ((index >> 1) & 1) as f32;
+        let z = ((index >> 2) & 1) as f32;
+        vertices.push(Vertex {
+            position: Vec3f32::new(x * 2f32 - 1f32, y * 2f32 - 1f32, z * 2f32 - 1f32),
+            color: Vec4f32::new(x, y, z, 1f32),
+            uv: Vec2f32::new(x, y),
+        });
+    }
+    let indices = [
+        4, 6, 7, 7, 5, 4, // Front
+        3, 2, 0, 0, 1, 3, // Back
+        6, 2, 3, 3, 7, 6, // Top
+        0, 4, 5, 5, 1, 0, // Bottom
+        0, 2, 6, 6, 4, 0, // Left
+        5, 7, 3, 3, 1, 5, // Right
+    ];
+
+    make_mesh(&vertices, &indices)
+}
+
+/// A grid of overlapping rotated cubes to test depth testing.
+pub fn make_cube_scene(size: Vec2u32) -> FrameCapture {
+    let rotation = Mat4f32::new_rotation(Vec3f32::new(0.6f32, 0.8f32, 0.3f32));
+    let mut model_views = Vec::new();
+    for x in -1i32..=1i32 {
+        for y in -1i32..=1i32 {
+            let translation = Mat4f32::new_translation(&Vec3f32::new(x as f32 * 1.5f32, y as f32 * 1.5f32, 6f32 + (x + y) as f32));
+            model_views.push(translation * rotation);
+        }
+    }
+
+    make_capture(size, make_cube_mesh(), &model_views)
+}
+
+/// A capture without any commands. Replaying it waits for all previously submitted work.
+pub fn make_empty_scene(size: Vec2u32) -> FrameCapture {
+    FrameCapture {
+        output_size: size,
+        shaders: Vec::new(),
+        images: Vec::new(),
+        global_meshes: Vec::new(),
+        commands: Vec::new(),
+    }
+}
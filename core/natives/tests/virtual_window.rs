@@ -0,0 +1,52 @@
+//! Tests the swapchain and frame code paths using a [`VirtualWindow`].
+//!
+//! These tests require a vulkan capable device supporting `VK_EXT_headless_surface` and are
+//! therefore ignored by default. Run them with `cargo test -- --ignored`.
+
+use b4d_core::b4d::Blaze4D;
+use b4d_core::prelude::*;
+use b4d_core::window::r#virtual::VirtualWindow;
+
+const FRAME_COUNT: u32 = 4;
+
+/// Starts a number of empty frames and returns how many of them could be started.
+fn run_frames(b4d: &Blaze4D, size: Vec2u32) -> u32 {
+    let mut started = 0;
+    for _ in 0..FRAME_COUNT {
+        if let Some(frame) = b4d.try_start_frame(size) {
+            drop(frame);
+            started += 1;
+        }
+    }
+    started
+}
+
+#[test]
+#[ignore]
+fn virtual_window_frames() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let window = VirtualWindow::new(128, 96);
+    let size = window.get_size();
+    let b4d = Blaze4D::new(Box::new(window), true, false);
+
+    assert!(run_frames(&b4d, size) > 0);
+    b4d.wait_idle();
+}
+
+#[test]
+#[ignore]
+fn virtual_window_resize() {
+    let _ = env_logger::builder().is_test(true).try_init();
+
+    let window = VirtualWindow::new(128, 96);
+    let size = window.get_size();
+    let b4d = Blaze4D::new(Box::new(window), true, false);
+
+    assert!(run_frames(&b4d, size) > 0);
+
+    // Headless surfaces have no current extent so a different size recreates the swapchain
+    assert!(run_frames(&b4d, Vec2u32::new(64, 160)) > 0);
+    assert!(run_frames(&b4d, size) > 0);
+    b4d.wait_idle();
+}
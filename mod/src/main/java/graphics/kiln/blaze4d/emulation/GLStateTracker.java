@@ -12,6 +12,12 @@ import graphics.kiln.blaze4d.core.types.PipelineConfiguration;
 public class GLStateTracker {
     public static GLStateTracker INSTANCE = new GLStateTracker();
 
+    // Values of VkColorComponentFlagBits
+    private static final int COLOR_MASK_R = 1;
+    private static final int COLOR_MASK_G = 2;
+    private static final int COLOR_MASK_B = 4;
+    private static final int COLOR_MASK_A = 8;
+
     private final PipelineConfiguration pipelineConfiguration;
 
     public GLStateTracker() {
@@ -26,6 +32,7 @@ public class GLStateTracker {
         this.pipelineConfiguration.setBlendAlphaOp(BlendOp.ADD);
         this.pipelineConfiguration.setBlendAlphaSrcFactor(BlendFactor.ONE);
         this.pipelineConfiguration.setBlendAlphaDstFactor(BlendFactor.ZERO);
+        this.pipelineConfiguration.setColorWriteMask(COLOR_MASK_R | COLOR_MASK_G | COLOR_MASK_B | COLOR_MASK_A);
     }
 
     public PipelineConfiguration getPipelineConfiguration() {
@@ -75,4 +82,13 @@ public class GLStateTracker {
         this.pipelineConfiguration.setBlendColorOp(op);
         this.pipelineConfiguration.setBlendAlphaOp(op);
     }
+
+    public void setColorMask(boolean red, boolean green, boolean blue, boolean alpha) {
+        int mask = 0;
+        if (red) mask |= COLOR_MASK_R;
+        if (green) mask |= COLOR_MASK_G;
+        if (blue) mask |= COLOR_MASK_B;
+        if (alpha) mask |= COLOR_MASK_A;
+        this.pipelineConfiguration.setColorWriteMask(mask);
+    }
 }